@@ -0,0 +1,4078 @@
+//! Instruction types
+
+#![allow(clippy::too_many_arguments)]
+
+use crate::amm_stats::{HostFee, PoolStatus, ProtocolFee};
+use crate::curve::{
+    base::SwapCurve,
+    fees::Fees,
+    multi::{MultiSwapCurve, MAX_TOKENS},
+};
+use crate::dex::OrderSide;
+use crate::error::AmmError;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+};
+use std::convert::{TryFrom, TryInto};
+use std::mem::size_of;
+
+#[cfg(feature = "fuzz")]
+use arbitrary::Arbitrary;
+
+/// Initialize instruction data
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct InitializeInstruction {
+    /// nonce used to create valid program address
+    pub nonce: u8,
+    /// all swap fees, the owner withdraw fee trails the swap curve and is
+    /// left out when zero
+    pub fees: Fees,
+    /// swap curve info for pool, including CurveType and anything
+    /// else that may be required
+    pub swap_curve: SwapCurve,
+}
+
+/// Time after which an instruction fails with `AmmError::Expired`.  Packed
+/// as a kind byte then the 8 bytes of the value.  An unset deadline is left
+/// out, or packed as kind 0 when more data follows it.
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Deadline {
+    /// Unix timestamp of the clock, kind 1
+    UnixTimestamp(i64),
+    /// Slot of the clock, kind 2
+    Slot(u64),
+}
+
+/// Swap instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapInstruction {
+    /// SOURCE amount to transfer, output to DESTINATION is based on the exchange rate
+    pub amount_in: u64,
+    /// Minimum amount of DESTINATION token to output, prevents excessive slippage
+    pub minimum_amount_out: u64,
+    /// Time after which the instruction fails, never expires if not set
+    pub deadline: Option<Deadline>,
+}
+
+/// SwapExactAmountOut instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapExactAmountOutInstruction {
+    /// DESTINATION amount to receive, input from SOURCE is based on the exchange rate
+    pub amount_out: u64,
+    /// Maximum amount of SOURCE token to pay, fees included, prevents excessive slippage
+    pub maximum_amount_in: u64,
+    /// Time after which the instruction fails, never expires if not set
+    pub deadline: Option<Deadline>,
+}
+
+/// DepositAllTokenTypes instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepositInstruction {
+    /// Pool token amount to transfer. token_a and token_b amount are set by
+    /// the current exchange rate and size of the pool
+    pub pool_token_amount: u64,
+    /// Maximum token A amount to deposit, prevents excessive slippage
+    pub maximum_token_a_amount: u64,
+    /// Maximum token B amount to deposit, prevents excessive slippage
+    pub maximum_token_b_amount: u64,
+    /// Time after which the instruction fails, never expires if not set
+    pub deadline: Option<Deadline>,
+}
+
+/// WithdrawAllTokenTypes instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct WithdrawInstruction {
+    /// Amount of pool tokens to burn. User receives an output of token a
+    /// and b based on the percentage of the pool tokens that are returned.
+    pub pool_token_amount: u64,
+    /// Minimum amount of token A to receive, prevents excessive slippage
+    pub minimum_token_a_amount: u64,
+    /// Minimum amount of token B to receive, prevents excessive slippage
+    pub minimum_token_b_amount: u64,
+    /// Time after which the instruction fails, never expires if not set
+    pub deadline: Option<Deadline>,
+}
+
+/// Deposit one type of tokens into the pool.  The output is a "pool" token
+/// representing ownership into the pool. Input token is converted as if
+/// a swap and deposit all token types were performed.
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepositSingleTokenTypeExactAmountIn {
+    /// Token amount to deposit
+    pub source_token_amount: u64,
+    /// Pool token amount to receive in exchange. The amount is set by
+    /// the current exchange rate and size of the pool
+    pub minimum_pool_token_amount: u64,
+    /// Time after which the instruction fails, never expires if not set
+    pub deadline: Option<Deadline>,
+}
+
+/// WithdrawSingleTokenTypeExactAmountOut instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct WithdrawSingleTokenTypeExactAmountOut {
+    /// Amount of token A or B to receive
+    pub destination_token_amount: u64,
+    /// Maximum amount of pool tokens to burn. User receives an output of token A
+    /// or B based on the percentage of the pool tokens that are returned.
+    pub maximum_pool_token_amount: u64,
+    /// Time after which the instruction fails, never expires if not set
+    pub deadline: Option<Deadline>,
+}
+
+/// InitializeConfig instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct InitializeConfigInstruction {
+    /// nonce used to create the program config address, its canonical bump
+    pub nonce: u8,
+}
+
+/// SetFees instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetFeesInstruction {
+    /// new swap fees for the pool, the owner withdraw fee trails the other
+    /// fees and is left out when zero
+    pub fees: Fees,
+}
+
+/// SetPoolStatus instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetPoolStatusInstruction {
+    /// new trading status for the pool
+    pub status: PoolStatus,
+}
+
+/// RampAmp instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RampAmpInstruction {
+    /// Amplification coefficient reached at the end of the ramp
+    pub target_amp: u64,
+    /// Unix timestamp the ramp ends at
+    pub stop_ramp_ts: i64,
+}
+
+/// RouteSwap instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteSwapInstruction {
+    /// SOURCE amount to transfer into the first pool
+    pub amount_in: u64,
+    /// Minimum amount of DESTINATION token to receive from the last pool,
+    /// prevents excessive slippage over the whole route
+    pub minimum_amount_out: u64,
+    /// Time after which the instruction fails, never expires if not set
+    pub deadline: Option<Deadline>,
+}
+
+/// Number of accounts given for each pool of a `RouteSwap`
+pub const ROUTE_SWAP_LEG_ACCOUNTS: usize = 8;
+
+/// Accounts of one pool in a `RouteSwap`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteSwapLeg {
+    /// Token-swap
+    pub swap_pubkey: Pubkey,
+    /// swap authority
+    pub authority_pubkey: Pubkey,
+    /// Base Account to swap INTO, must be the SOURCE token of this leg
+    pub swap_source_pubkey: Pubkey,
+    /// Base Account to swap FROM, must be the DESTINATION token of this leg
+    pub swap_destination_pubkey: Pubkey,
+    /// User account receiving this leg's DESTINATION token, and the SOURCE
+    /// of the next leg
+    pub destination_pubkey: Pubkey,
+    /// Pool token mint
+    pub pool_mint_pubkey: Pubkey,
+    /// Fixed fee account for this leg's SOURCE token
+    pub fixed_fee_pubkey: Pubkey,
+    /// Serum open orders of the pool, `Pubkey::default()` if it has none
+    pub open_orders_pubkey: Pubkey,
+}
+
+/// PlaceOrder instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlaceOrderInstruction {
+    /// Side of the book to quote on
+    pub side: OrderSide,
+    /// Size of the order, in lots of token A
+    pub coin_lots: u64,
+    /// Distance from the curve price, in basis points
+    pub spread_bps: u64,
+    /// Id to cancel the order with
+    pub client_order_id: u64,
+}
+
+/// CancelOrder instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CancelOrderInstruction {
+    /// Id the order was placed with
+    pub client_order_id: u64,
+}
+
+/// InitializeMulti instruction data
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct InitializeMultiInstruction {
+    /// nonce used to create valid program address
+    pub nonce: u8,
+    /// all swap fees
+    pub fees: Fees,
+    /// swap curve info for pool, a stable or weighted curve
+    pub swap_curve: MultiSwapCurve,
+}
+
+/// MultiDepositAllTokenTypes instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultiDepositInstruction {
+    /// Pool token amount to transfer. Token amounts are set by the current
+    /// exchange rate and size of the pool
+    pub pool_token_amount: u64,
+    /// Maximum amount to deposit of each token, in pool order, prevents
+    /// excessive slippage
+    pub maximum_token_amounts: Vec<u64>,
+    /// Time after which the instruction fails, never expires if not set
+    pub deadline: Option<Deadline>,
+}
+
+/// MultiWithdrawAllTokenTypes instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultiWithdrawInstruction {
+    /// Amount of pool tokens to burn. User receives an output of every token
+    /// based on the percentage of the pool tokens that are returned.
+    pub pool_token_amount: u64,
+    /// Minimum amount to receive of each token, in pool order, prevents
+    /// excessive slippage
+    pub minimum_token_amounts: Vec<u64>,
+    /// Time after which the instruction fails, never expires if not set
+    pub deadline: Option<Deadline>,
+}
+
+/// InitializeConcentrated instruction data
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct InitializeConcentratedInstruction {
+    /// nonce used to create valid program address
+    pub nonce: u8,
+    /// all swap fees
+    pub fees: Fees,
+    /// Positions are bounded by multiples of the tick spacing
+    pub tick_spacing: u16,
+    /// Initial square root of the price of token A in token B, Q64.64
+    pub sqrt_price: u128,
+}
+
+/// OpenPosition instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpenPositionInstruction {
+    /// Lower bound of the price range
+    pub tick_lower: i32,
+    /// Upper bound of the price range
+    pub tick_upper: i32,
+    /// Liquidity to provide over the range
+    pub liquidity: u128,
+    /// Maximum token A amount to deposit, prevents excessive slippage
+    pub maximum_token_a_amount: u64,
+    /// Maximum token B amount to deposit, prevents excessive slippage
+    pub maximum_token_b_amount: u64,
+    /// Time after which the instruction fails, never expires if not set
+    pub deadline: Option<Deadline>,
+}
+
+/// CollectFees instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CollectFeesInstruction {
+    /// Time after which the instruction fails, never expires if not set
+    pub deadline: Option<Deadline>,
+}
+
+/// ClosePosition instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClosePositionInstruction {
+    /// Minimum token A amount to receive, fees excluded, prevents excessive
+    /// slippage
+    pub minimum_token_a_amount: u64,
+    /// Minimum token B amount to receive, fees excluded, prevents excessive
+    /// slippage
+    pub minimum_token_b_amount: u64,
+    /// Time after which the instruction fails, never expires if not set
+    pub deadline: Option<Deadline>,
+}
+
+/// FlashLoan instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlashLoanInstruction {
+    /// Amount of the token to borrow from the pool
+    pub amount: u64,
+    /// Time after which the instruction fails, never expires if not set.
+    /// Packed before the receiver data, with kind 0 when not set.
+    pub deadline: Option<Deadline>,
+    /// Instruction data given as is to the receiver program
+    pub data: Vec<u8>,
+}
+
+/// FlashSwap instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlashSwapInstruction {
+    /// Amount of DESTINATION token sent before the SOURCE token is paid
+    pub amount_out: u64,
+    /// Time after which the instruction fails, never expires if not set.
+    /// Packed before the receiver data, with kind 0 when not set.
+    pub deadline: Option<Deadline>,
+    /// Instruction data given as is to the receiver program
+    pub data: Vec<u8>,
+}
+
+/// SetHostFee instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetHostFeeInstruction {
+    /// new share of the fixed fee paid to the host of a swap
+    pub host_fee: HostFee,
+}
+
+/// SetProtocolFee instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetProtocolFeeInstruction {
+    /// new numerator of the share of the growth of `sqrt(k)` minted to the
+    /// protocol, zero to pay fixed fees again
+    pub numerator: u64,
+    /// new denominator of the share of the growth of `sqrt(k)` minted to the
+    /// protocol
+    pub denominator: u64,
+}
+
+/// Number of accounts given for each token of a multi token pool by
+/// `InitializeMulti`, `MultiDepositAllTokenTypes` and
+/// `MultiWithdrawAllTokenTypes`
+pub const MULTI_TOKEN_ACCOUNTS: usize = 2;
+
+/// Serum accounts used to trade the pool reserves on its market
+#[derive(Clone, Debug, PartialEq)]
+pub struct SerumMarketAccounts {
+    /// Serum dex program id
+    pub dex_program_id: Pubkey,
+    /// Serum market
+    pub market: Pubkey,
+    /// Open orders account of the pool authority
+    pub open_orders: Pubkey,
+    /// Market request queue
+    pub request_queue: Pubkey,
+    /// Market event queue
+    pub event_queue: Pubkey,
+    /// Market bids
+    pub bids: Pubkey,
+    /// Market asks
+    pub asks: Pubkey,
+    /// Market vault of token A
+    pub coin_vault: Pubkey,
+    /// Market vault of token B
+    pub pc_vault: Pubkey,
+    /// Market vault signer
+    pub vault_signer: Pubkey,
+}
+
+/// Instructions supported by the AMM program.
+///
+/// The first byte of the instruction data is the tag of the variant. Tags are
+/// part of the on-chain interface: new instructions are appended with a new
+/// tag and existing tags are never renumbered or reused.
+///
+/// Either token of a two token pool may be on Token-2022, while the pool mint
+/// stays on the "Token program id" account.  `Initialize`, `Swap`,
+/// `SwapExactAmountOut`, `DepositAllTokenTypes`, `WithdrawAllTokenTypes`,
+/// `DepositSingleTokenTypeExactAmountIn` and
+/// `WithdrawSingleTokenTypeExactAmountOut` then take the Token-2022 program and
+/// the Token-2022 mints after their listed accounts, see `with_token_2022`, and
+/// apply the transfer fees of the mints to their amounts and slippage checks.
+/// Other instructions moving the tokens of a pool, including `RouteSwap`, do
+/// not support Token-2022 tokens.
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub enum AmmInstruction {
+    ///   Initializes a new swap
+    ///
+    ///   0. `[writable, signer]` New Token-swap to create.
+    ///   1. `[]` swap authority derived from `create_program_address(&[Token-swap account])`
+    ///   2. `[]` AMM id.
+    ///   3. `[]` token_a Account. Must be non zero, owned by swap authority.
+    ///   4. `[]` token_b Account. Must be non zero, owned by swap authority.
+    ///   5. `[writable]` Pool Token Mint. Must be empty, owned by swap authority.
+    ///   6. `[]` Fixed fee account for token_a, or the fee wallet itself.
+    ///   7. `[]` Fixed fee account for token_b, or the fee wallet itself.
+    ///   8. `[writable]` Pool Token Account to deposit the initial pool token
+    ///      supply.  Must be empty, not owned by swap authority.
+    ///   9. `[]` Token program id
+    ///   10. `[]` Serum dex program id, `serum_dex_program::id()`
+    ///   11. `[]` Serum market
+    ///   12. `[writable]` Serum open orders of the market owned by the swap
+    ///       authority, or the system program id if the pool does not trade
+    ///       on the market.  Open orders the dex has not initialized yet are
+    ///       initialized for the swap authority.
+    ///   13. `[]` Program config
+    ///   14. `[]` Rent sysvar, optional if the open orders are initialized
+    Initialize(InitializeInstruction),
+
+    ///   Swap the tokens in the pool.  Pools taking a protocol fee keep the
+    ///   fixed fee in the pool instead of paying it to the fee accounts.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[writable, signer]` user transfer authority, pays the fixed fee in SOL for WSOL swaps
+    ///   3. `[writable]` token_(A|B) SOURCE Account, amount is transferable by user transfer authority,
+    ///   4. `[writable]` token_(A|B) Base Account to swap INTO.  Must be the SOURCE token.
+    ///   5. `[writable]` token_(A|B) Base Account to swap FROM.  Must be the DESTINATION token.
+    ///   6. `[writable]` token_(A|B) DESTINATION Account assigned to USER as the owner.
+    ///   7. `[]` Pool token mint
+    ///   8. `[writable]` Fixed fee account for the SOURCE token
+    ///   9. `[writable]` Fee wallet
+    ///   10. `[]` Token program id
+    ///   11. `[]` System program id
+    ///   12. `[]` Program config
+    ///   13. `[]` Serum open orders of the pool, optional if the pool has none and no host fee account follows
+    ///   14. `[writable]` Host fee account for the SOURCE token, optional, receives the host share of the fixed fee
+    Swap(SwapInstruction),
+
+    ///   Deposit both types of tokens into the pool.  The output is a "pool"
+    ///   token representing ownership in the pool. Inputs are converted to
+    ///   the current ratio.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` user transfer authority
+    ///   3. `[writable]` token_a user transfer authority can transfer amount,
+    ///   4. `[writable]` token_b user transfer authority can transfer amount,
+    ///   5. `[writable]` token_a Base Account to deposit into.
+    ///   6. `[writable]` token_b Base Account to deposit into.
+    ///   7. `[writable]` Pool MINT account, swap authority is the owner.
+    ///   8. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   9. `[]` Token program id
+    ///   10. `[]` Serum open orders of the pool, optional if the pool has none and no protocol fee account follows
+    ///   11. `[writable]` Protocol fee account, optional if the pool takes no protocol fee
+    DepositAllTokenTypes(DepositInstruction),
+
+    ///   Withdraw both types of tokens from the pool at the current ratio, given
+    ///   pool tokens.  The pool tokens are burned in exchange for an equivalent
+    ///   amount of token A and B.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` user transfer authority
+    ///   3. `[writable]` Pool mint account, swap authority is the owner
+    ///   4. `[writable]` SOURCE Pool account, amount is transferable by user transfer authority.
+    ///   5. `[writable]` token_a Swap Account to withdraw FROM.
+    ///   6. `[writable]` token_b Swap Account to withdraw FROM.
+    ///   7. `[writable]` token_a user Account to credit.
+    ///   8. `[writable]` token_b user Account to credit.
+    ///   9. `[]` Token program id
+    ///   10. `[]` Program config
+    ///   11. `[writable]` Pool fee account, a pool token account of the fee wallet, checked when the pool takes an owner withdraw fee
+    ///   12. `[]` Serum open orders of the pool, optional if the pool has none and no protocol fee account follows
+    ///   13. `[writable]` Protocol fee account, optional if the pool takes no protocol fee
+    WithdrawAllTokenTypes(WithdrawInstruction),
+
+    ///   Deposit one type of tokens into the pool.  The output is a "pool" token
+    ///   representing ownership into the pool. Input token is converted as if
+    ///   a swap and deposit all token types were performed.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` user transfer authority
+    ///   3. `[writable]` token_(A|B) SOURCE Account, amount is transferable by user transfer authority,
+    ///   4. `[writable]` token_a Swap Account, may deposit INTO.
+    ///   5. `[writable]` token_b Swap Account, may deposit INTO.
+    ///   6. `[writable]` Pool MINT account, swap authority is the owner.
+    ///   7. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   8. `[]` Token program id
+    ///   9. `[]` Serum open orders of the pool, optional if the pool has none and no protocol fee account follows
+    ///   10. `[writable]` Protocol fee account, optional if the pool takes no protocol fee
+    DepositSingleTokenTypeExactAmountIn(DepositSingleTokenTypeExactAmountIn),
+
+    ///   Withdraw one token type from the pool at the current ratio given the
+    ///   exact amount out expected.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` user transfer authority
+    ///   3. `[writable]` Pool mint account, swap authority is the owner
+    ///   4. `[writable]` SOURCE Pool account, amount is transferable by user transfer authority.
+    ///   5. `[writable]` token_a Swap Account to potentially withdraw from.
+    ///   6. `[writable]` token_b Swap Account to potentially withdraw from.
+    ///   7. `[writable]` token_(A|B) User Account to credit
+    ///   8. `[]` Fixed fee account for token_a
+    ///   9. `[]` Fixed fee account for token_b
+    ///   10. `[]` Token program id
+    ///   11. `[]` Program config
+    ///   12. `[writable]` Pool fee account, a pool token account of the fee wallet, checked when the pool takes an owner withdraw fee
+    ///   13. `[]` Serum open orders of the pool, optional if the pool has none and no protocol fee account follows
+    ///   14. `[writable]` Protocol fee account, optional if the pool takes no protocol fee
+    WithdrawSingleTokenTypeExactAmountOut(WithdrawSingleTokenTypeExactAmountOut),
+
+    ///   Creates the program config account, which records the admin and the
+    ///   fee wallet.
+    ///
+    ///   0. `[writable]` Program config, derived from `find_program_address(&[CONFIG_SEED])`
+    ///   1. `[writable, signer]` Admin, pays for the config account
+    ///   2. `[]` Fee wallet
+    ///   3. `[]` System program id
+    InitializeConfig(InitializeConfigInstruction),
+
+    ///   Replaces the fee wallet stored in the program config.
+    ///
+    ///   0. `[writable]` Program config
+    ///   1. `[signer]` Admin
+    ///   2. `[]` New fee wallet
+    SetFeeWallet,
+
+    ///   Replaces the fees of an initialized swap.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` Program config
+    ///   2. `[signer]` Admin
+    SetFees(SetFeesInstruction),
+
+    ///   Sets the trading status of a swap, e.g. to halt it during an incident.
+    ///   Swaps created before SwapV2 have no room for the status, they are
+    ///   upgraded to the latest version first, the admin paying for the room.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` Program config
+    ///   2. `[writable, signer]` Admin
+    ///   3. `[]` System program id, optional unless the swap predates SwapV2
+    SetPoolStatus(SetPoolStatusInstruction),
+
+    ///   Swap the tokens in the pool, receiving an exact DESTINATION amount.
+    ///
+    ///   Accounts are the same as for `Swap`.
+    SwapExactAmountOut(SwapExactAmountOutInstruction),
+
+    ///   Swap through several pools in order, A -> B -> C.  The tokens received
+    ///   from each pool are swapped into the next one and slippage is only
+    ///   checked on the amount received from the last pool.
+    ///
+    ///   0. `[writable, signer]` user transfer authority, must be able to move
+    ///      the SOURCE and every intermediate account
+    ///   1. `[writable]` SOURCE Account of the first pool
+    ///   2. `[writable]` Fee wallet
+    ///   3. `[]` Token program id
+    ///   4. `[]` System program id
+    ///   5. `[]` Program config
+    ///
+    ///   Then, for each pool, `ROUTE_SWAP_LEG_ACCOUNTS` accounts:
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[writable]` Base Account to swap INTO.  Must be the SOURCE token of this pool.
+    ///   3. `[writable]` Base Account to swap FROM.  Must be the DESTINATION token of this pool.
+    ///   4. `[writable]` DESTINATION Account of this pool, the SOURCE of the next one
+    ///   5. `[]` Pool token mint
+    ///   6. `[writable]` Fixed fee account for the SOURCE token of this pool
+    ///   7. `[]` Serum open orders of the pool, any account if it has none
+    RouteSwap(RouteSwapInstruction),
+
+    ///   Places a post-only order for the pool on its Serum market, priced
+    ///   around the curve price.  Bids are paid from token_b and asks from
+    ///   token_a.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority, owner of the open orders
+    ///   2. `[]` Program config
+    ///   3. `[signer]` Admin
+    ///   4. `[writable]` token_a Base Account
+    ///   5. `[writable]` token_b Base Account
+    ///   6. `[]` Serum dex program id
+    ///   7. `[writable]` Serum market
+    ///   8. `[writable]` Serum open orders of the pool
+    ///   9. `[writable]` Market request queue
+    ///   10. `[writable]` Market event queue
+    ///   11. `[writable]` Market bids
+    ///   12. `[writable]` Market asks
+    ///   13. `[writable]` Market vault of token A
+    ///   14. `[writable]` Market vault of token B
+    ///   15. `[]` Token program id
+    ///   16. `[]` Rent sysvar
+    PlaceOrder(PlaceOrderInstruction),
+
+    ///   Cancels an order of the pool on its Serum market.  The funds stay in
+    ///   the open orders account until `SettleFunds`.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority, owner of the open orders
+    ///   2. `[]` Program config
+    ///   3. `[signer]` Admin
+    ///   4. `[]` Serum dex program id
+    ///   5. `[writable]` Serum market
+    ///   6. `[writable]` Serum open orders of the pool
+    ///   7. `[writable]` Market bids
+    ///   8. `[writable]` Market asks
+    ///   9. `[writable]` Market event queue
+    CancelOrder(CancelOrderInstruction),
+
+    ///   Moves the free funds of the pool's open orders back into token_a and
+    ///   token_b.  Anyone may settle.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority, owner of the open orders
+    ///   2. `[writable]` token_a Base Account
+    ///   3. `[writable]` token_b Base Account
+    ///   4. `[]` Serum dex program id
+    ///   5. `[writable]` Serum market
+    ///   6. `[writable]` Serum open orders of the pool
+    ///   7. `[writable]` Market vault of token A
+    ///   8. `[writable]` Market vault of token B
+    ///   9. `[]` Market vault signer
+    ///   10. `[]` Token program id
+    SettleFunds,
+
+    ///   Moves the amplification of a stable curve linearly from its current
+    ///   value to the target, starting now.  A ramp in progress must be
+    ///   stopped first.  Swaps created before SwapV2 have no room for the
+    ///   ramp and are rejected.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` Program config
+    ///   2. `[signer]` Admin
+    RampAmp(RampAmpInstruction),
+
+    ///   Stops the ramp in progress, keeping the amplification it reached.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` Program config
+    ///   2. `[signer]` Admin
+    StopRampAmp,
+
+    ///   Initializes a new pool of `MIN_TOKENS` to `MAX_TOKENS` tokens on a
+    ///   stable or weighted curve.  The order of the tokens is the pool
+    ///   order used by the other multi token instructions.
+    ///
+    ///   0. `[writable, signer]` New multi token swap to create.
+    ///   1. `[]` swap authority derived from `create_program_address(&[multi token swap account])`
+    ///   2. `[writable]` Pool Token Mint. Must be empty, owned by swap authority.
+    ///   3. `[writable]` Pool Token Account to deposit the initial pool token
+    ///      supply.  Must be empty, not owned by swap authority.
+    ///   4. `[]` Token program id
+    ///   5. `[]` Program config
+    ///
+    ///   Then, for each token, `MULTI_TOKEN_ACCOUNTS` accounts:
+    ///
+    ///   0. `[]` Token Account. Must be non zero, owned by swap authority.
+    ///   1. `[]` Fixed fee account for the token, or the fee wallet itself.
+    InitializeMulti(InitializeMultiInstruction),
+
+    ///   Swap between any two tokens of a multi token pool, found from the
+    ///   mints of the SOURCE and DESTINATION accounts.
+    ///
+    ///   0. `[]` Multi token swap
+    ///   1. `[]` swap authority
+    ///   2. `[writable, signer]` user transfer authority
+    ///   3. `[writable]` token_(SOURCE) SOURCE Account, amount is transferable by user transfer authority,
+    ///   4. `[writable]` token_(DESTINATION) DESTINATION Account assigned to USER as the owner.
+    ///   5. `[writable]` Fixed fee account for the SOURCE token
+    ///   6. `[writable]` Fee wallet of the program config
+    ///   7. `[]` Token program id
+    ///   8. `[]` System program id
+    ///   9. `[]` Program config
+    ///   10. ..10+N `[writable]` Token accounts of the pool, in pool order
+    MultiSwap(SwapInstruction),
+
+    ///   Deposit every token of a multi token pool in exchange for pool
+    ///   tokens, in proportion to the reserves
+    ///
+    ///   0. `[]` Multi token swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` user transfer authority
+    ///   3. `[writable]` Pool MINT account, swap authority is the owner.
+    ///   4. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   5. `[]` Token program id
+    ///
+    ///   Then, for each token in pool order, `MULTI_TOKEN_ACCOUNTS` accounts:
+    ///
+    ///   0. `[writable]` token SOURCE Account, amount is transferable by user transfer authority,
+    ///   1. `[writable]` token Base Account to deposit into.
+    MultiDepositAllTokenTypes(MultiDepositInstruction),
+
+    ///   Withdraw every token of a multi token pool in exchange for pool
+    ///   tokens, in proportion to the reserves
+    ///
+    ///   0. `[]` Multi token swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` user transfer authority
+    ///   3. `[writable]` Pool mint account, swap authority is the owner
+    ///   4. `[writable]` SOURCE Pool account, amount is transferable by user transfer authority.
+    ///   5. `[]` Token program id
+    ///
+    ///   Then, for each token in pool order, `MULTI_TOKEN_ACCOUNTS` accounts:
+    ///
+    ///   0. `[writable]` token Base Account to withdraw FROM.
+    ///   1. `[writable]` token user Account to credit.
+    MultiWithdrawAllTokenTypes(MultiWithdrawInstruction),
+
+    ///   Deposit one token of a multi token pool, found from the mint of the
+    ///   SOURCE account, in exchange for pool tokens
+    ///
+    ///   0. `[]` Multi token swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` user transfer authority
+    ///   3. `[writable]` token SOURCE Account, amount is transferable by user transfer authority,
+    ///   4. `[writable]` Pool MINT account, swap authority is the owner.
+    ///   5. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   6. `[]` Token program id
+    ///   7. ..7+N `[writable]` Token accounts of the pool, in pool order
+    MultiDepositSingleTokenTypeExactAmountIn(DepositSingleTokenTypeExactAmountIn),
+
+    ///   Withdraw one token of a multi token pool, found from the mint of the
+    ///   DESTINATION account, in exchange for pool tokens
+    ///
+    ///   0. `[]` Multi token swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` user transfer authority
+    ///   3. `[writable]` Pool mint account, swap authority is the owner
+    ///   4. `[writable]` SOURCE Pool account, amount is transferable by user transfer authority.
+    ///   5. `[writable]` token user Account to credit
+    ///   6. `[]` Token program id
+    ///   7. ..7+N `[writable]` Token accounts of the pool, in pool order
+    MultiWithdrawSingleTokenTypeExactAmountOut(WithdrawSingleTokenTypeExactAmountOut),
+
+    ///   Initializes a new concentrated liquidity pool, where liquidity
+    ///   providers open positions over a price range instead of holding pool
+    ///   tokens.  The pool starts empty at the given price.
+    ///
+    ///   0. `[writable, signer]` New concentrated liquidity pool to create.
+    ///   1. `[]` swap authority derived from `create_program_address(&[pool account])`
+    ///   2. `[]` token_a Account. Must be owned by swap authority.
+    ///   3. `[]` token_b Account. Must be owned by swap authority.
+    ///   4. `[]` Fixed fee account for token_a, or the fee wallet itself.
+    ///   5. `[]` Fixed fee account for token_b, or the fee wallet itself.
+    ///   6. `[]` Token program id
+    ///   7. `[]` Program config
+    InitializeConcentrated(InitializeConcentratedInstruction),
+
+    ///   Opens a position in a concentrated liquidity pool, depositing the
+    ///   tokens backing its liquidity at the current price
+    ///
+    ///   0. `[writable]` Concentrated liquidity pool
+    ///   1. `[]` swap authority
+    ///   2. `[writable]` New position account, owned by the program
+    ///   3. `[signer]` Position owner
+    ///   4. `[signer]` user transfer authority
+    ///   5. `[writable]` token_a user transfer authority can transfer amount,
+    ///   6. `[writable]` token_b user transfer authority can transfer amount,
+    ///   7. `[writable]` token_a Base Account to deposit into.
+    ///   8. `[writable]` token_b Base Account to deposit into.
+    ///   9. `[]` Token program id
+    OpenPosition(OpenPositionInstruction),
+
+    ///   Swap the tokens of a concentrated liquidity pool, crossing the ticks
+    ///   of the positions on the way
+    ///
+    ///   0. `[writable]` Concentrated liquidity pool
+    ///   1. `[]` swap authority
+    ///   2. `[writable, signer]` user transfer authority, pays the fixed fee in SOL for WSOL swaps
+    ///   3. `[writable]` token_(A|B) SOURCE Account, amount is transferable by user transfer authority,
+    ///   4. `[writable]` token_(A|B) Base Account to swap INTO.  Must be the SOURCE token.
+    ///   5. `[writable]` token_(A|B) Base Account to swap FROM.  Must be the DESTINATION token.
+    ///   6. `[writable]` token_(A|B) DESTINATION Account assigned to USER as the owner.
+    ///   7. `[writable]` Fixed fee account for the SOURCE token
+    ///   8. `[writable]` Fee wallet
+    ///   9. `[]` Token program id
+    ///   10. `[]` System program id
+    ///   11. `[]` Program config
+    ConcentratedSwap(SwapInstruction),
+
+    ///   Pays out the trade fees earned by a position
+    ///
+    ///   0. `[writable]` Concentrated liquidity pool
+    ///   1. `[]` swap authority
+    ///   2. `[writable]` Position account
+    ///   3. `[signer]` Position owner
+    ///   4. `[writable]` token_a Base Account to withdraw FROM.
+    ///   5. `[writable]` token_b Base Account to withdraw FROM.
+    ///   6. `[writable]` token_a user Account to credit.
+    ///   7. `[writable]` token_b user Account to credit.
+    ///   8. `[]` Token program id
+    CollectFees(CollectFeesInstruction),
+
+    ///   Withdraws the liquidity and the fees of a position and closes its
+    ///   account, returning the lamports to the owner
+    ///
+    ///   0. `[writable]` Concentrated liquidity pool
+    ///   1. `[]` swap authority
+    ///   2. `[writable]` Position account
+    ///   3. `[writable, signer]` Position owner
+    ///   4. `[writable]` token_a Base Account to withdraw FROM.
+    ///   5. `[writable]` token_b Base Account to withdraw FROM.
+    ///   6. `[writable]` token_a user Account to credit.
+    ///   7. `[writable]` token_b user Account to credit.
+    ///   8. `[]` Token program id
+    ClosePosition(ClosePositionInstruction),
+
+    ///   Lends tokens of a pool within one instruction.  The tokens are sent
+    ///   to the destination, then the receiver program is invoked with the
+    ///   base account to repay INTO, the destination, the token program, and
+    ///   accounts 10.. after them, with the `data` of the loan.  The reserves
+    ///   of the lent token, open orders included, must then have grown by the
+    ///   return and fixed fees on the amount lent, and the reserves of the
+    ///   other token must not have shrunk: the return fee stays in the pool
+    ///   and the fixed fee is paid to the fixed fee account of the token.  The
+    ///   pool is locked until the loan is repaid.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[writable]` token_(A|B) Base Account to lend FROM and repay INTO.
+    ///   3. `[]` token_(B|A) Base Account of the other token.
+    ///   4. `[writable]` token_(A|B) DESTINATION Account receiving the loan.
+    ///   5. `[writable]` Fixed fee account for the lent token
+    ///   6. `[]` Program config
+    ///   7. `[]` Serum open orders of the pool, any account if it has none
+    ///   8. `[]` Token program id
+    ///   9. `[]` Receiver program id
+    ///   10. ..10+N `[]` N accounts given to the receiver program
+    FlashLoan(FlashLoanInstruction),
+
+    ///   Swaps without holding the SOURCE token: `amount_out` is sent to the
+    ///   destination first, then the receiver program is invoked with the
+    ///   base account to pay INTO, the destination, the token program, and
+    ///   accounts 10.. after them, with the `data` of the swap.  The growth of
+    ///   the SOURCE reserves, open orders included, must then buy at least
+    ///   `amount_out` on the swap curve, fees included, the DESTINATION
+    ///   reserves must not have lost more than `amount_out`, and the owner
+    ///   fee is paid out of the base account to the fixed fee account.  The
+    ///   pool is locked until the swap is paid for.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[writable]` token_(A|B) Base Account to swap INTO.  Must be the SOURCE token.
+    ///   3. `[writable]` token_(A|B) Base Account to swap FROM.  Must be the DESTINATION token.
+    ///   4. `[writable]` token_(A|B) DESTINATION Account receiving `amount_out`.
+    ///   5. `[writable]` Fixed fee account for the SOURCE token
+    ///   6. `[]` Program config
+    ///   7. `[]` Serum open orders of the pool, any account if it has none
+    ///   8. `[]` Token program id
+    ///   9. `[]` Receiver program id
+    ///   10. ..10+N `[]` N accounts given to the receiver program
+    FlashSwap(FlashSwapInstruction),
+
+    ///   Sets the share of the fixed fee of swaps paid to the host fee
+    ///   account given with them.  Swaps created before SwapV3 have no room
+    ///   for the share and are rejected.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` Program config
+    ///   2. `[signer]` Admin
+    SetHostFee(SetHostFeeInstruction),
+
+    ///   Switches a constant product pool between paying the fixed fee of
+    ///   swaps to the fee accounts and a Uniswap v2 style protocol fee, which
+    ///   leaves the fixed fee in the pool and mints the given share of the
+    ///   growth of `sqrt(k)` between liquidity events to the protocol fee
+    ///   account.  Tracking of the growth restarts from the next deposit or
+    ///   withdrawal.  Swaps created before SwapV3 are rejected.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` Program config
+    ///   2. `[signer]` Admin
+    ///   3. `[]` Protocol fee account, a token account of the pool mint
+    SetProtocolFee(SetProtocolFeeInstruction),
+
+    ///   Swap with native SOL as its source or destination.  The program
+    ///   wraps the SOL into the temporary WSOL account of the user wallet,
+    ///   swaps, then closes the account back into the user wallet.  The
+    ///   accounts of the swap are those of [Swap](enum.AmmInstruction.html#variant.Swap),
+    ///   with the user wallet as user transfer authority and the temporary
+    ///   WSOL account of `find_native_sol_address` as the SOURCE or
+    ///   DESTINATION account.  Lamports already sent to the temporary account
+    ///   are unwrapped into the user wallet with the rest.
+    ///
+    ///   0. `[]` WSOL mint
+    ///   1. `[]` Rent sysvar
+    ///   2. `[]` System program id
+    ///   3. ..3+N `[]` N accounts of the swap
+    SwapNativeSol(SwapInstruction),
+
+    ///   Deposit of both tokens with native SOL as one of them.  The program
+    ///   wraps the maximum amount of SOL into the temporary WSOL account of
+    ///   the user wallet, deposits, then closes the account back into the
+    ///   user wallet.  The accounts of the deposit are those of
+    ///   [DepositAllTokenTypes](enum.AmmInstruction.html#variant.DepositAllTokenTypes),
+    ///   with the user wallet as user transfer authority and the temporary
+    ///   WSOL account of `find_native_sol_address` as the token_a or token_b
+    ///   account.
+    ///
+    ///   0. `[]` WSOL mint
+    ///   1. `[]` Rent sysvar
+    ///   2. `[]` System program id
+    ///   3. ..3+N `[]` N accounts of the deposit
+    DepositAllTokenTypesNativeSol(DepositInstruction),
+
+    ///   Withdrawal of both tokens with native SOL as one of them.  The
+    ///   program withdraws into the temporary WSOL account of the user
+    ///   wallet, then closes the account into the user wallet.  The accounts
+    ///   of the withdrawal are those of
+    ///   [WithdrawAllTokenTypes](enum.AmmInstruction.html#variant.WithdrawAllTokenTypes),
+    ///   with the user wallet as user transfer authority and the temporary
+    ///   WSOL account of `find_native_sol_address` as the token_a or token_b
+    ///   account to credit.
+    ///
+    ///   0. `[]` WSOL mint
+    ///   1. `[]` Rent sysvar
+    ///   2. `[]` System program id
+    ///   3. ..3+N `[]` N accounts of the withdrawal
+    WithdrawAllTokenTypesNativeSol(WithdrawInstruction),
+
+    ///   Replaces the fixed fee accounts of a pool, once the fee wallet of
+    ///   the program config changed.  Each account must be the fee wallet
+    ///   itself or a token account of the fee wallet for the token of the
+    ///   pool at the same index.
+    ///
+    ///   0. `[writable]` Token-swap, multi token pool or concentrated pool
+    ///   1. `[]` Program config
+    ///   2. `[signer]` Admin
+    ///   3. ..3+N `[]` Fixed fee account of each of the N tokens of the pool
+    SetFixedFeeAccounts,
+
+    ///   Deposit of one token with native SOL as the token.  The program
+    ///   wraps the SOL into the temporary WSOL account of the user wallet,
+    ///   deposits, then closes the account back into the user wallet.  The
+    ///   accounts of the deposit are those of
+    ///   [DepositSingleTokenTypeExactAmountIn](enum.AmmInstruction.html#variant.DepositSingleTokenTypeExactAmountIn),
+    ///   with the user wallet as user transfer authority and the temporary
+    ///   WSOL account of `find_native_sol_address` as the SOURCE account.
+    ///
+    ///   0. `[]` WSOL mint
+    ///   1. `[]` Rent sysvar
+    ///   2. `[]` System program id
+    ///   3. ..3+N `[]` N accounts of the deposit
+    DepositSingleTokenTypeExactAmountInNativeSol(DepositSingleTokenTypeExactAmountIn),
+
+    ///   Withdrawal of one token with native SOL as the token.  The program
+    ///   withdraws into the temporary WSOL account of the user wallet, then
+    ///   closes the account into the user wallet.  The accounts of the
+    ///   withdrawal are those of
+    ///   [WithdrawSingleTokenTypeExactAmountOut](enum.AmmInstruction.html#variant.WithdrawSingleTokenTypeExactAmountOut),
+    ///   with the user wallet as user transfer authority and the temporary
+    ///   WSOL account of `find_native_sol_address` as the account to credit.
+    ///
+    ///   0. `[]` WSOL mint
+    ///   1. `[]` Rent sysvar
+    ///   2. `[]` System program id
+    ///   3. ..3+N `[]` N accounts of the withdrawal
+    WithdrawSingleTokenTypeExactAmountOutNativeSol(WithdrawSingleTokenTypeExactAmountOut),
+
+    ///   Swap receiving an exact DESTINATION amount, with native SOL as its
+    ///   source or destination.  The program wraps the maximum amount in of
+    ///   SOL when it is the source, swaps, then closes the temporary WSOL
+    ///   account back into the user wallet, unwrapping what the swap did not
+    ///   take.  The accounts of the swap are those of
+    ///   [SwapExactAmountOut](enum.AmmInstruction.html#variant.SwapExactAmountOut),
+    ///   with the user wallet as user transfer authority and the temporary
+    ///   WSOL account of `find_native_sol_address` as the SOURCE or
+    ///   DESTINATION account.
+    ///
+    ///   0. `[]` WSOL mint
+    ///   1. `[]` Rent sysvar
+    ///   2. `[]` System program id
+    ///   3. ..3+N `[]` N accounts of the swap
+    SwapExactAmountOutNativeSol(SwapExactAmountOutInstruction),
+}
+
+impl AmmInstruction {
+    /// Unpacks a byte buffer into a [AmmInstruction](enum.AmmInstruction.html).
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let (&tag, rest) = input.split_first().ok_or(AmmError::InvalidInstruction)?;
+        Ok(match tag {
+            0 => {
+                if let Some((nonce, rest)) = rest.split_first() {
+                    if rest.len() >= Fees::LEN + SwapCurve::LEN {
+                        let (fees, rest) = rest.split_at(Fees::LEN);
+                        let mut fees = Fees::unpack_unchecked(fees)?;
+                        let (swap_curve, rest) = rest.split_at(SwapCurve::LEN);
+                        let swap_curve = SwapCurve::unpack_unchecked(swap_curve)?;
+                        Self::unpack_owner_withdraw_fee(rest, &mut fees)?;
+                        Self::Initialize(InitializeInstruction {
+                            nonce: *nonce,
+                            fees,
+                            swap_curve,
+                        })
+                    } else {
+                        return Err(AmmError::InvalidInstruction.into());
+                    }
+                } else {
+                    return Err(AmmError::InvalidInstruction.into());
+                }
+            }
+            1 => {
+                let (amount_in, rest) = Self::unpack_u64(rest)?;
+                let (minimum_amount_out, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::Swap(SwapInstruction {
+                    amount_in,
+                    minimum_amount_out,
+                    deadline,
+                })
+            }
+            2 => {
+                let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_token_b_amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::DepositAllTokenTypes(DepositInstruction {
+                    pool_token_amount,
+                    maximum_token_a_amount,
+                    maximum_token_b_amount,
+                    deadline,
+                })
+            }
+            3 => {
+                let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_token_b_amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::WithdrawAllTokenTypes(WithdrawInstruction {
+                    pool_token_amount,
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                    deadline,
+                })
+            }
+            4 => {
+                let (source_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::DepositSingleTokenTypeExactAmountIn(DepositSingleTokenTypeExactAmountIn {
+                    source_token_amount,
+                    minimum_pool_token_amount,
+                    deadline,
+                })
+            }
+            5 => {
+                let (destination_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::WithdrawSingleTokenTypeExactAmountOut(WithdrawSingleTokenTypeExactAmountOut {
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    deadline,
+                })
+            }
+            6 => {
+                let (&nonce, _rest) = rest.split_first().ok_or(AmmError::InvalidInstruction)?;
+                Self::InitializeConfig(InitializeConfigInstruction { nonce })
+            }
+            7 => Self::SetFeeWallet,
+            8 if rest.len() >= Fees::LEN => {
+                let (fees, rest) = rest.split_at(Fees::LEN);
+                let mut fees = Fees::unpack_unchecked(fees)?;
+                Self::unpack_owner_withdraw_fee(rest, &mut fees)?;
+                Self::SetFees(SetFeesInstruction { fees })
+            }
+            9 => {
+                let (&status, _rest) = rest.split_first().ok_or(AmmError::InvalidInstruction)?;
+                let status =
+                    PoolStatus::try_from(status).map_err(|_| AmmError::InvalidInstruction)?;
+                Self::SetPoolStatus(SetPoolStatusInstruction { status })
+            }
+            10 => {
+                let (amount_out, rest) = Self::unpack_u64(rest)?;
+                let (maximum_amount_in, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::SwapExactAmountOut(SwapExactAmountOutInstruction {
+                    amount_out,
+                    maximum_amount_in,
+                    deadline,
+                })
+            }
+            11 => {
+                let (amount_in, rest) = Self::unpack_u64(rest)?;
+                let (minimum_amount_out, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::RouteSwap(RouteSwapInstruction {
+                    amount_in,
+                    minimum_amount_out,
+                    deadline,
+                })
+            }
+            12 => {
+                let (&side, rest) = rest.split_first().ok_or(AmmError::InvalidInstruction)?;
+                let side = OrderSide::try_from(side).map_err(|_| AmmError::InvalidInstruction)?;
+                let (coin_lots, rest) = Self::unpack_u64(rest)?;
+                let (spread_bps, rest) = Self::unpack_u64(rest)?;
+                let (client_order_id, _rest) = Self::unpack_u64(rest)?;
+                Self::PlaceOrder(PlaceOrderInstruction {
+                    side,
+                    coin_lots,
+                    spread_bps,
+                    client_order_id,
+                })
+            }
+            13 => {
+                let (client_order_id, _rest) = Self::unpack_u64(rest)?;
+                Self::CancelOrder(CancelOrderInstruction { client_order_id })
+            }
+            14 => Self::SettleFunds,
+            15 => {
+                let (target_amp, rest) = Self::unpack_u64(rest)?;
+                let (stop_ramp_ts, _rest) = Self::unpack_u64(rest)?;
+                Self::RampAmp(RampAmpInstruction {
+                    target_amp,
+                    stop_ramp_ts: stop_ramp_ts as i64,
+                })
+            }
+            16 => Self::StopRampAmp,
+            17 => {
+                let (&nonce, rest) = rest.split_first().ok_or(AmmError::InvalidInstruction)?;
+                if rest.len() < Fees::LEN + MultiSwapCurve::LEN {
+                    return Err(AmmError::InvalidInstruction.into());
+                }
+                let (fees, rest) = rest.split_at(Fees::LEN);
+                let fees = Fees::unpack_unchecked(fees)?;
+                let swap_curve = MultiSwapCurve::unpack_unchecked(&rest[..MultiSwapCurve::LEN])?;
+                Self::InitializeMulti(InitializeMultiInstruction {
+                    nonce,
+                    fees,
+                    swap_curve,
+                })
+            }
+            18 => {
+                let (amount_in, rest) = Self::unpack_u64(rest)?;
+                let (minimum_amount_out, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::MultiSwap(SwapInstruction {
+                    amount_in,
+                    minimum_amount_out,
+                    deadline,
+                })
+            }
+            19 => {
+                let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_token_amounts, rest) = Self::unpack_token_amounts(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::MultiDepositAllTokenTypes(MultiDepositInstruction {
+                    pool_token_amount,
+                    maximum_token_amounts,
+                    deadline,
+                })
+            }
+            20 => {
+                let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_token_amounts, rest) = Self::unpack_token_amounts(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::MultiWithdrawAllTokenTypes(MultiWithdrawInstruction {
+                    pool_token_amount,
+                    minimum_token_amounts,
+                    deadline,
+                })
+            }
+            21 => {
+                let (source_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::MultiDepositSingleTokenTypeExactAmountIn(
+                    DepositSingleTokenTypeExactAmountIn {
+                        source_token_amount,
+                        minimum_pool_token_amount,
+                        deadline,
+                    },
+                )
+            }
+            22 => {
+                let (destination_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::MultiWithdrawSingleTokenTypeExactAmountOut(
+                    WithdrawSingleTokenTypeExactAmountOut {
+                        destination_token_amount,
+                        maximum_pool_token_amount,
+                        deadline,
+                    },
+                )
+            }
+            23 => {
+                let (&nonce, rest) = rest.split_first().ok_or(AmmError::InvalidInstruction)?;
+                if rest.len() < Fees::LEN {
+                    return Err(AmmError::InvalidInstruction.into());
+                }
+                let (fees, rest) = rest.split_at(Fees::LEN);
+                let fees = Fees::unpack_unchecked(fees)?;
+                let tick_spacing = rest
+                    .get(..2)
+                    .and_then(|slice| slice.try_into().ok())
+                    .map(u16::from_le_bytes)
+                    .ok_or(AmmError::InvalidInstruction)?;
+                let (sqrt_price, _rest) = Self::unpack_u128(&rest[2..])?;
+                Self::InitializeConcentrated(InitializeConcentratedInstruction {
+                    nonce,
+                    fees,
+                    tick_spacing,
+                    sqrt_price,
+                })
+            }
+            24 => {
+                let (tick_lower, rest) = Self::unpack_i32(rest)?;
+                let (tick_upper, rest) = Self::unpack_i32(rest)?;
+                let (liquidity, rest) = Self::unpack_u128(rest)?;
+                let (maximum_token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_token_b_amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::OpenPosition(OpenPositionInstruction {
+                    tick_lower,
+                    tick_upper,
+                    liquidity,
+                    maximum_token_a_amount,
+                    maximum_token_b_amount,
+                    deadline,
+                })
+            }
+            25 => {
+                let (amount_in, rest) = Self::unpack_u64(rest)?;
+                let (minimum_amount_out, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::ConcentratedSwap(SwapInstruction {
+                    amount_in,
+                    minimum_amount_out,
+                    deadline,
+                })
+            }
+            26 => {
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::CollectFees(CollectFeesInstruction { deadline })
+            }
+            27 => {
+                let (minimum_token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_token_b_amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::ClosePosition(ClosePositionInstruction {
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                    deadline,
+                })
+            }
+            28 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, rest) = Self::unpack_deadline(rest)?;
+                Self::FlashLoan(FlashLoanInstruction {
+                    amount,
+                    deadline,
+                    data: rest.to_vec(),
+                })
+            }
+            29 => {
+                let (amount_out, rest) = Self::unpack_u64(rest)?;
+                let (deadline, rest) = Self::unpack_deadline(rest)?;
+                Self::FlashSwap(FlashSwapInstruction {
+                    amount_out,
+                    deadline,
+                    data: rest.to_vec(),
+                })
+            }
+            30 => {
+                let (numerator, rest) = Self::unpack_u64(rest)?;
+                let (denominator, _rest) = Self::unpack_u64(rest)?;
+                Self::SetHostFee(SetHostFeeInstruction {
+                    host_fee: HostFee {
+                        numerator,
+                        denominator,
+                    },
+                })
+            }
+            32 => {
+                let (numerator, rest) = Self::unpack_u64(rest)?;
+                let (denominator, _rest) = Self::unpack_u64(rest)?;
+                Self::SetProtocolFee(SetProtocolFeeInstruction {
+                    numerator,
+                    denominator,
+                })
+            }
+            33 => {
+                let (amount_in, rest) = Self::unpack_u64(rest)?;
+                let (minimum_amount_out, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::SwapNativeSol(SwapInstruction {
+                    amount_in,
+                    minimum_amount_out,
+                    deadline,
+                })
+            }
+            34 => {
+                let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_token_b_amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::DepositAllTokenTypesNativeSol(DepositInstruction {
+                    pool_token_amount,
+                    maximum_token_a_amount,
+                    maximum_token_b_amount,
+                    deadline,
+                })
+            }
+            35 => {
+                let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_token_b_amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::WithdrawAllTokenTypesNativeSol(WithdrawInstruction {
+                    pool_token_amount,
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                    deadline,
+                })
+            }
+            36 => Self::SetFixedFeeAccounts,
+            37 => {
+                let (source_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::DepositSingleTokenTypeExactAmountInNativeSol(
+                    DepositSingleTokenTypeExactAmountIn {
+                        source_token_amount,
+                        minimum_pool_token_amount,
+                        deadline,
+                    },
+                )
+            }
+            38 => {
+                let (destination_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::WithdrawSingleTokenTypeExactAmountOutNativeSol(
+                    WithdrawSingleTokenTypeExactAmountOut {
+                        destination_token_amount,
+                        maximum_pool_token_amount,
+                        deadline,
+                    },
+                )
+            }
+            39 => {
+                let (amount_out, rest) = Self::unpack_u64(rest)?;
+                let (maximum_amount_in, rest) = Self::unpack_u64(rest)?;
+                let (deadline, _rest) = Self::unpack_deadline(rest)?;
+                Self::SwapExactAmountOutNativeSol(SwapExactAmountOutInstruction {
+                    amount_out,
+                    maximum_amount_in,
+                    deadline,
+                })
+            }
+            _ => return Err(AmmError::InvalidInstruction.into()),
+        })
+    }
+
+    fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
+        if input.len() >= 8 {
+            let (amount, rest) = input.split_at(8);
+            let amount = amount
+                .get(..8)
+                .and_then(|slice| slice.try_into().ok())
+                .map(u64::from_le_bytes)
+                .ok_or(AmmError::InvalidInstruction)?;
+            Ok((amount, rest))
+        } else {
+            Err(AmmError::InvalidInstruction.into())
+        }
+    }
+
+    fn unpack_u128(input: &[u8]) -> Result<(u128, &[u8]), ProgramError> {
+        if input.len() >= 16 {
+            let (amount, rest) = input.split_at(16);
+            let amount = amount
+                .try_into()
+                .map(u128::from_le_bytes)
+                .map_err(|_| AmmError::InvalidInstruction)?;
+            Ok((amount, rest))
+        } else {
+            Err(AmmError::InvalidInstruction.into())
+        }
+    }
+
+    fn unpack_i32(input: &[u8]) -> Result<(i32, &[u8]), ProgramError> {
+        if input.len() >= 4 {
+            let (value, rest) = input.split_at(4);
+            let value = value
+                .try_into()
+                .map(i32::from_le_bytes)
+                .map_err(|_| AmmError::InvalidInstruction)?;
+            Ok((value, rest))
+        } else {
+            Err(AmmError::InvalidInstruction.into())
+        }
+    }
+
+    /// Unpacks one amount per token of a multi token pool, preceded by the
+    /// number of tokens
+    fn unpack_token_amounts(input: &[u8]) -> Result<(Vec<u64>, &[u8]), ProgramError> {
+        let (&token_count, mut rest) = input.split_first().ok_or(AmmError::InvalidInstruction)?;
+        if usize::from(token_count) > MAX_TOKENS {
+            return Err(AmmError::InvalidInstruction.into());
+        }
+        let mut token_amounts = Vec::with_capacity(token_count.into());
+        for _ in 0..token_count {
+            let (token_amount, next) = Self::unpack_u64(rest)?;
+            token_amounts.push(token_amount);
+            rest = next;
+        }
+        Ok((token_amounts, rest))
+    }
+
+    fn pack_token_amounts(token_amounts: &[u64], buf: &mut Vec<u8>) {
+        buf.push(token_amounts.len() as u8);
+        for token_amount in token_amounts {
+            buf.extend_from_slice(&token_amount.to_le_bytes());
+        }
+    }
+
+    /// Unpacks an optional deadline, older clients leave it out of the end
+    /// of an instruction.  Unix timestamps beyond the range of an i64 are
+    /// rejected.
+    fn unpack_deadline(input: &[u8]) -> Result<(Option<Deadline>, &[u8]), ProgramError> {
+        let (&kind, rest) = match input.split_first() {
+            Some(split) => split,
+            None => return Ok((None, input)),
+        };
+        match kind {
+            0 => Ok((None, rest)),
+            1 => {
+                let (unix_timestamp, rest) = Self::unpack_u64(rest)?;
+                let unix_timestamp =
+                    i64::try_from(unix_timestamp).map_err(|_| AmmError::InvalidInstruction)?;
+                Ok((Some(Deadline::UnixTimestamp(unix_timestamp)), rest))
+            }
+            2 => {
+                let (slot, rest) = Self::unpack_u64(rest)?;
+                Ok((Some(Deadline::Slot(slot)), rest))
+            }
+            _ => Err(AmmError::InvalidInstruction.into()),
+        }
+    }
+
+    fn pack_deadline(deadline: &Option<Deadline>, buf: &mut Vec<u8>) {
+        match deadline {
+            None => {}
+            Some(Deadline::UnixTimestamp(unix_timestamp)) => {
+                buf.push(1);
+                buf.extend_from_slice(&unix_timestamp.to_le_bytes());
+            }
+            Some(Deadline::Slot(slot)) => {
+                buf.push(2);
+                buf.extend_from_slice(&slot.to_le_bytes());
+            }
+        }
+    }
+
+    /// Unpacks the optional owner withdraw fee trailing the fees of an
+    /// instruction into them, older clients leave it out
+    fn unpack_owner_withdraw_fee(input: &[u8], fees: &mut Fees) -> Result<(), ProgramError> {
+        if input.is_empty() {
+            return Ok(());
+        }
+        let owner_withdraw_fee = input
+            .get(..Fees::OWNER_WITHDRAW_FEE_LEN)
+            .ok_or(AmmError::InvalidInstruction)?;
+        fees.unpack_owner_withdraw_fee(owner_withdraw_fee);
+        Ok(())
+    }
+
+    fn pack_owner_withdraw_fee(fees: &Fees, buf: &mut Vec<u8>) {
+        if fees.has_owner_withdraw_fee() {
+            let mut owner_withdraw_fee = [0u8; Fees::OWNER_WITHDRAW_FEE_LEN];
+            fees.pack_owner_withdraw_fee(&mut owner_withdraw_fee);
+            buf.extend_from_slice(&owner_withdraw_fee);
+        }
+    }
+
+    /// Packs a [AmmInstruction](enum.AmmInstruction.html) into a byte buffer.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(size_of::<Self>());
+        match self {
+            Self::Initialize(InitializeInstruction {
+                nonce,
+                fees,
+                swap_curve,
+            }) => {
+                buf.push(0);
+                buf.push(*nonce);
+                let mut fees_slice = [0u8; Fees::LEN];
+                Pack::pack_into_slice(fees, &mut fees_slice[..]);
+                buf.extend_from_slice(&fees_slice);
+                let mut swap_curve_slice = [0u8; SwapCurve::LEN];
+                Pack::pack_into_slice(swap_curve, &mut swap_curve_slice[..]);
+                buf.extend_from_slice(&swap_curve_slice);
+                Self::pack_owner_withdraw_fee(fees, &mut buf);
+            }
+            Self::Swap(SwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            }) => {
+                buf.push(1);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::DepositAllTokenTypes(DepositInstruction {
+                pool_token_amount,
+                maximum_token_a_amount,
+                maximum_token_b_amount,
+                deadline,
+            }) => {
+                buf.push(2);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                buf.extend_from_slice(&maximum_token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&maximum_token_b_amount.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::WithdrawAllTokenTypes(WithdrawInstruction {
+                pool_token_amount,
+                minimum_token_a_amount,
+                minimum_token_b_amount,
+                deadline,
+            }) => {
+                buf.push(3);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_token_b_amount.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::DepositSingleTokenTypeExactAmountIn(DepositSingleTokenTypeExactAmountIn {
+                source_token_amount,
+                minimum_pool_token_amount,
+                deadline,
+            }) => {
+                buf.push(4);
+                buf.extend_from_slice(&source_token_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_pool_token_amount.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::WithdrawSingleTokenTypeExactAmountOut(
+                WithdrawSingleTokenTypeExactAmountOut {
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    deadline,
+                },
+            ) => {
+                buf.push(5);
+                buf.extend_from_slice(&destination_token_amount.to_le_bytes());
+                buf.extend_from_slice(&maximum_pool_token_amount.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::InitializeConfig(InitializeConfigInstruction { nonce }) => {
+                buf.push(6);
+                buf.push(*nonce);
+            }
+            Self::SetFeeWallet => {
+                buf.push(7);
+            }
+            Self::SetFees(SetFeesInstruction { fees }) => {
+                buf.push(8);
+                let mut fees_slice = [0u8; Fees::LEN];
+                Pack::pack_into_slice(fees, &mut fees_slice[..]);
+                buf.extend_from_slice(&fees_slice);
+                Self::pack_owner_withdraw_fee(fees, &mut buf);
+            }
+            Self::SetPoolStatus(SetPoolStatusInstruction { status }) => {
+                buf.push(9);
+                buf.push(*status as u8);
+            }
+            Self::SwapExactAmountOut(SwapExactAmountOutInstruction {
+                amount_out,
+                maximum_amount_in,
+                deadline,
+            }) => {
+                buf.push(10);
+                buf.extend_from_slice(&amount_out.to_le_bytes());
+                buf.extend_from_slice(&maximum_amount_in.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::RouteSwap(RouteSwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            }) => {
+                buf.push(11);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::PlaceOrder(PlaceOrderInstruction {
+                side,
+                coin_lots,
+                spread_bps,
+                client_order_id,
+            }) => {
+                buf.push(12);
+                buf.push(*side as u8);
+                buf.extend_from_slice(&coin_lots.to_le_bytes());
+                buf.extend_from_slice(&spread_bps.to_le_bytes());
+                buf.extend_from_slice(&client_order_id.to_le_bytes());
+            }
+            Self::CancelOrder(CancelOrderInstruction { client_order_id }) => {
+                buf.push(13);
+                buf.extend_from_slice(&client_order_id.to_le_bytes());
+            }
+            Self::SettleFunds => buf.push(14),
+            Self::RampAmp(RampAmpInstruction {
+                target_amp,
+                stop_ramp_ts,
+            }) => {
+                buf.push(15);
+                buf.extend_from_slice(&target_amp.to_le_bytes());
+                buf.extend_from_slice(&stop_ramp_ts.to_le_bytes());
+            }
+            Self::StopRampAmp => buf.push(16),
+            Self::InitializeMulti(InitializeMultiInstruction {
+                nonce,
+                fees,
+                swap_curve,
+            }) => {
+                buf.push(17);
+                buf.push(*nonce);
+                let mut fees_slice = [0u8; Fees::LEN];
+                Pack::pack_into_slice(fees, &mut fees_slice[..]);
+                buf.extend_from_slice(&fees_slice);
+                let mut swap_curve_slice = [0u8; MultiSwapCurve::LEN];
+                Pack::pack_into_slice(swap_curve, &mut swap_curve_slice[..]);
+                buf.extend_from_slice(&swap_curve_slice);
+            }
+            Self::MultiSwap(SwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            }) => {
+                buf.push(18);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::MultiDepositAllTokenTypes(MultiDepositInstruction {
+                pool_token_amount,
+                maximum_token_amounts,
+                deadline,
+            }) => {
+                buf.push(19);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                Self::pack_token_amounts(maximum_token_amounts, &mut buf);
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::MultiWithdrawAllTokenTypes(MultiWithdrawInstruction {
+                pool_token_amount,
+                minimum_token_amounts,
+                deadline,
+            }) => {
+                buf.push(20);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                Self::pack_token_amounts(minimum_token_amounts, &mut buf);
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::MultiDepositSingleTokenTypeExactAmountIn(
+                DepositSingleTokenTypeExactAmountIn {
+                    source_token_amount,
+                    minimum_pool_token_amount,
+                    deadline,
+                },
+            ) => {
+                buf.push(21);
+                buf.extend_from_slice(&source_token_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_pool_token_amount.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::MultiWithdrawSingleTokenTypeExactAmountOut(
+                WithdrawSingleTokenTypeExactAmountOut {
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    deadline,
+                },
+            ) => {
+                buf.push(22);
+                buf.extend_from_slice(&destination_token_amount.to_le_bytes());
+                buf.extend_from_slice(&maximum_pool_token_amount.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::InitializeConcentrated(InitializeConcentratedInstruction {
+                nonce,
+                fees,
+                tick_spacing,
+                sqrt_price,
+            }) => {
+                buf.push(23);
+                buf.push(*nonce);
+                let mut fees_slice = [0u8; Fees::LEN];
+                Pack::pack_into_slice(fees, &mut fees_slice[..]);
+                buf.extend_from_slice(&fees_slice);
+                buf.extend_from_slice(&tick_spacing.to_le_bytes());
+                buf.extend_from_slice(&sqrt_price.to_le_bytes());
+            }
+            Self::OpenPosition(OpenPositionInstruction {
+                tick_lower,
+                tick_upper,
+                liquidity,
+                maximum_token_a_amount,
+                maximum_token_b_amount,
+                deadline,
+            }) => {
+                buf.push(24);
+                buf.extend_from_slice(&tick_lower.to_le_bytes());
+                buf.extend_from_slice(&tick_upper.to_le_bytes());
+                buf.extend_from_slice(&liquidity.to_le_bytes());
+                buf.extend_from_slice(&maximum_token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&maximum_token_b_amount.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::ConcentratedSwap(SwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            }) => {
+                buf.push(25);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::CollectFees(CollectFeesInstruction { deadline }) => {
+                buf.push(26);
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::ClosePosition(ClosePositionInstruction {
+                minimum_token_a_amount,
+                minimum_token_b_amount,
+                deadline,
+            }) => {
+                buf.push(27);
+                buf.extend_from_slice(&minimum_token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_token_b_amount.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::FlashLoan(FlashLoanInstruction {
+                amount,
+                deadline,
+                data,
+            }) => {
+                buf.push(28);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                match deadline {
+                    None => buf.push(0),
+                    Some(_) => Self::pack_deadline(deadline, &mut buf),
+                }
+                buf.extend_from_slice(data);
+            }
+            Self::FlashSwap(FlashSwapInstruction {
+                amount_out,
+                deadline,
+                data,
+            }) => {
+                buf.push(29);
+                buf.extend_from_slice(&amount_out.to_le_bytes());
+                match deadline {
+                    None => buf.push(0),
+                    Some(_) => Self::pack_deadline(deadline, &mut buf),
+                }
+                buf.extend_from_slice(data);
+            }
+            Self::SetHostFee(SetHostFeeInstruction { host_fee }) => {
+                buf.push(30);
+                buf.extend_from_slice(&host_fee.numerator.to_le_bytes());
+                buf.extend_from_slice(&host_fee.denominator.to_le_bytes());
+            }
+            Self::SetProtocolFee(SetProtocolFeeInstruction {
+                numerator,
+                denominator,
+            }) => {
+                buf.push(32);
+                buf.extend_from_slice(&numerator.to_le_bytes());
+                buf.extend_from_slice(&denominator.to_le_bytes());
+            }
+            Self::SwapNativeSol(SwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            }) => {
+                buf.push(33);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::DepositAllTokenTypesNativeSol(DepositInstruction {
+                pool_token_amount,
+                maximum_token_a_amount,
+                maximum_token_b_amount,
+                deadline,
+            }) => {
+                buf.push(34);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                buf.extend_from_slice(&maximum_token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&maximum_token_b_amount.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::WithdrawAllTokenTypesNativeSol(WithdrawInstruction {
+                pool_token_amount,
+                minimum_token_a_amount,
+                minimum_token_b_amount,
+                deadline,
+            }) => {
+                buf.push(35);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_token_b_amount.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::SetFixedFeeAccounts => {
+                buf.push(36);
+            }
+            Self::DepositSingleTokenTypeExactAmountInNativeSol(
+                DepositSingleTokenTypeExactAmountIn {
+                    source_token_amount,
+                    minimum_pool_token_amount,
+                    deadline,
+                },
+            ) => {
+                buf.push(37);
+                buf.extend_from_slice(&source_token_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_pool_token_amount.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::WithdrawSingleTokenTypeExactAmountOutNativeSol(
+                WithdrawSingleTokenTypeExactAmountOut {
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    deadline,
+                },
+            ) => {
+                buf.push(38);
+                buf.extend_from_slice(&destination_token_amount.to_le_bytes());
+                buf.extend_from_slice(&maximum_pool_token_amount.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+            Self::SwapExactAmountOutNativeSol(SwapExactAmountOutInstruction {
+                amount_out,
+                maximum_amount_in,
+                deadline,
+            }) => {
+                buf.push(39);
+                buf.extend_from_slice(&amount_out.to_le_bytes());
+                buf.extend_from_slice(&maximum_amount_in.to_le_bytes());
+                Self::pack_deadline(deadline, &mut buf);
+            }
+        }
+        buf
+    }
+}
+
+/// Creates an 'initialize' instruction.
+pub fn initialize(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    amm_id_pubkey: &Pubkey,
+    token_a_pubkey: &Pubkey,
+    token_b_pubkey: &Pubkey,
+    pool_pubkey: &Pubkey,
+    fixed_fee_a_pubkey: &Pubkey,
+    fixed_fee_b_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    dex_program_id: &Pubkey,
+    market_pubkey: &Pubkey,
+    open_orders_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    nonce: u8,
+    fees: Fees,
+    swap_curve: SwapCurve,
+) -> Result<Instruction, ProgramError> {
+    let init_data = AmmInstruction::Initialize(InitializeInstruction {
+        nonce,
+        fees,
+        swap_curve,
+    });
+    let data = init_data.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, true),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*amm_id_pubkey, false),
+        AccountMeta::new_readonly(*token_a_pubkey, false),
+        AccountMeta::new_readonly(*token_b_pubkey, false),
+        AccountMeta::new(*pool_pubkey, false),
+        AccountMeta::new_readonly(*fixed_fee_a_pubkey, false),
+        AccountMeta::new_readonly(*fixed_fee_b_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*dex_program_id, false),
+        AccountMeta::new_readonly(*market_pubkey, false),
+        AccountMeta::new(*open_orders_pubkey, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'swap' instruction.
+pub fn swap(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_source_pubkey: &Pubkey,
+    swap_destination_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    fixed_fee_pubkey: &Pubkey,
+    fee_wallet_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    instruction: SwapInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::Swap(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_source_pubkey, false),
+        AccountMeta::new(*swap_destination_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*pool_mint_pubkey, false),
+        AccountMeta::new(*fixed_fee_pubkey, false),
+        AccountMeta::new(*fee_wallet_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'swap_exact_amount_out' instruction.
+pub fn swap_exact_amount_out(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_source_pubkey: &Pubkey,
+    swap_destination_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    fixed_fee_pubkey: &Pubkey,
+    fee_wallet_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    instruction: SwapExactAmountOutInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SwapExactAmountOut(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_source_pubkey, false),
+        AccountMeta::new(*swap_destination_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*pool_mint_pubkey, false),
+        AccountMeta::new(*fixed_fee_pubkey, false),
+        AccountMeta::new(*fee_wallet_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'route_swap' instruction.
+pub fn route_swap(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    fee_wallet_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    legs: &[RouteSwapLeg],
+    instruction: RouteSwapInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::RouteSwap(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*fee_wallet_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+    ];
+    for leg in legs {
+        accounts.extend_from_slice(&[
+            AccountMeta::new_readonly(leg.swap_pubkey, false),
+            AccountMeta::new_readonly(leg.authority_pubkey, false),
+            AccountMeta::new(leg.swap_source_pubkey, false),
+            AccountMeta::new(leg.swap_destination_pubkey, false),
+            AccountMeta::new(leg.destination_pubkey, false),
+            AccountMeta::new_readonly(leg.pool_mint_pubkey, false),
+            AccountMeta::new(leg.fixed_fee_pubkey, false),
+            AccountMeta::new_readonly(leg.open_orders_pubkey, false),
+        ]);
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'place_order' instruction.
+pub fn place_order(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    token_a_pubkey: &Pubkey,
+    token_b_pubkey: &Pubkey,
+    market: &SerumMarketAccounts,
+    instruction: PlaceOrderInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::PlaceOrder(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new(*token_a_pubkey, false),
+        AccountMeta::new(*token_b_pubkey, false),
+        AccountMeta::new_readonly(market.dex_program_id, false),
+        AccountMeta::new(market.market, false),
+        AccountMeta::new(market.open_orders, false),
+        AccountMeta::new(market.request_queue, false),
+        AccountMeta::new(market.event_queue, false),
+        AccountMeta::new(market.bids, false),
+        AccountMeta::new(market.asks, false),
+        AccountMeta::new(market.coin_vault, false),
+        AccountMeta::new(market.pc_vault, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'cancel_order' instruction.
+pub fn cancel_order(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    market: &SerumMarketAccounts,
+    instruction: CancelOrderInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::CancelOrder(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new_readonly(market.dex_program_id, false),
+        AccountMeta::new(market.market, false),
+        AccountMeta::new(market.open_orders, false),
+        AccountMeta::new(market.bids, false),
+        AccountMeta::new(market.asks, false),
+        AccountMeta::new(market.event_queue, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'settle_funds' instruction.
+pub fn settle_funds(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    token_a_pubkey: &Pubkey,
+    token_b_pubkey: &Pubkey,
+    market: &SerumMarketAccounts,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SettleFunds.pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*token_a_pubkey, false),
+        AccountMeta::new(*token_b_pubkey, false),
+        AccountMeta::new_readonly(market.dex_program_id, false),
+        AccountMeta::new(market.market, false),
+        AccountMeta::new(market.open_orders, false),
+        AccountMeta::new(market.coin_vault, false),
+        AccountMeta::new(market.pc_vault, false),
+        AccountMeta::new_readonly(market.vault_signer, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'deposit_all_token_types' instruction.
+pub fn deposit_all_token_types(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    deposit_token_a_pubkey: &Pubkey,
+    deposit_token_b_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    instruction: DepositInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::DepositAllTokenTypes(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*deposit_token_a_pubkey, false),
+        AccountMeta::new(*deposit_token_b_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'withdraw_all_token_types' instruction.
+pub fn withdraw_all_token_types(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    destination_token_a_pubkey: &Pubkey,
+    destination_token_b_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    instruction: WithdrawInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::WithdrawAllTokenTypes(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*destination_token_a_pubkey, false),
+        AccountMeta::new(*destination_token_b_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new(*pool_fee_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'deposit_single_token_type_exact_amount_in' instruction.
+pub fn deposit_single_token_type_exact_amount_in(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_token_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    instruction: DepositSingleTokenTypeExactAmountIn,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::DepositSingleTokenTypeExactAmountIn(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_token_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'withdraw_single_token_type_exact_amount_out' instruction.
+pub fn withdraw_single_token_type_exact_amount_out(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    fixed_fee_a_pubkey: &Pubkey,
+    fixed_fee_b_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    instruction: WithdrawSingleTokenTypeExactAmountOut,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::WithdrawSingleTokenTypeExactAmountOut(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*fixed_fee_a_pubkey, false),
+        AccountMeta::new_readonly(*fixed_fee_b_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new(*pool_fee_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'initialize_config' instruction.
+pub fn initialize_config(
+    program_id: &Pubkey,
+    config_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    fee_wallet_pubkey: &Pubkey,
+    nonce: u8,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::InitializeConfig(InitializeConfigInstruction { nonce }).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*config_pubkey, false),
+        AccountMeta::new(*admin_pubkey, true),
+        AccountMeta::new_readonly(*fee_wallet_pubkey, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_fee_wallet' instruction.
+pub fn set_fee_wallet(
+    program_id: &Pubkey,
+    config_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    fee_wallet_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetFeeWallet.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*config_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new_readonly(*fee_wallet_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_fixed_fee_accounts' instruction.
+pub fn set_fixed_fee_accounts(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    fixed_fee_pubkeys: &[Pubkey],
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetFixedFeeAccounts.pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+    ];
+    accounts.extend(
+        fixed_fee_pubkeys
+            .iter()
+            .map(|pubkey| AccountMeta::new_readonly(*pubkey, false)),
+    );
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_fees' instruction.
+pub fn set_fees(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    fees: Fees,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetFees(SetFeesInstruction { fees }).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_pool_status' instruction.
+pub fn set_pool_status(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    status: PoolStatus,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetPoolStatus(SetPoolStatusInstruction { status }).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new(*admin_pubkey, true),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'ramp_amp' instruction.
+pub fn ramp_amp(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    target_amp: u64,
+    stop_ramp_ts: i64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::RampAmp(RampAmpInstruction {
+        target_amp,
+        stop_ramp_ts,
+    })
+    .pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'stop_ramp_amp' instruction.
+pub fn stop_ramp_amp(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::StopRampAmp.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'initialize_multi' instruction.
+pub fn initialize_multi(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    pool_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    token_pubkeys: &[Pubkey],
+    fixed_fee_pubkeys: &[Pubkey],
+    nonce: u8,
+    fees: Fees,
+    swap_curve: MultiSwapCurve,
+) -> Result<Instruction, ProgramError> {
+    if token_pubkeys.len() != fixed_fee_pubkeys.len() {
+        return Err(AmmError::InvalidTokenCount.into());
+    }
+    let data = AmmInstruction::InitializeMulti(InitializeMultiInstruction {
+        nonce,
+        fees,
+        swap_curve,
+    })
+    .pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, true),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*pool_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+    ];
+    for (token_pubkey, fixed_fee_pubkey) in token_pubkeys.iter().zip(fixed_fee_pubkeys) {
+        accounts.extend_from_slice(&[
+            AccountMeta::new_readonly(*token_pubkey, false),
+            AccountMeta::new_readonly(*fixed_fee_pubkey, false),
+        ]);
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'multi_swap' instruction.
+pub fn multi_swap(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    fixed_fee_pubkey: &Pubkey,
+    fee_wallet_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    swap_token_pubkeys: &[Pubkey],
+    instruction: SwapInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::MultiSwap(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*fixed_fee_pubkey, false),
+        AccountMeta::new(*fee_wallet_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+    ];
+    accounts.extend(
+        swap_token_pubkeys
+            .iter()
+            .map(|pubkey| AccountMeta::new(*pubkey, false)),
+    );
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'multi_deposit_all_token_types' instruction.
+pub fn multi_deposit_all_token_types(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    deposit_token_pubkeys: &[Pubkey],
+    swap_token_pubkeys: &[Pubkey],
+    instruction: MultiDepositInstruction,
+) -> Result<Instruction, ProgramError> {
+    if deposit_token_pubkeys.len() != swap_token_pubkeys.len() {
+        return Err(AmmError::InvalidTokenCount.into());
+    }
+    let data = AmmInstruction::MultiDepositAllTokenTypes(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    for (deposit_token_pubkey, swap_token_pubkey) in
+        deposit_token_pubkeys.iter().zip(swap_token_pubkeys)
+    {
+        accounts.extend_from_slice(&[
+            AccountMeta::new(*deposit_token_pubkey, false),
+            AccountMeta::new(*swap_token_pubkey, false),
+        ]);
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'multi_withdraw_all_token_types' instruction.
+pub fn multi_withdraw_all_token_types(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_token_pubkeys: &[Pubkey],
+    destination_token_pubkeys: &[Pubkey],
+    instruction: MultiWithdrawInstruction,
+) -> Result<Instruction, ProgramError> {
+    if swap_token_pubkeys.len() != destination_token_pubkeys.len() {
+        return Err(AmmError::InvalidTokenCount.into());
+    }
+    let data = AmmInstruction::MultiWithdrawAllTokenTypes(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    for (swap_token_pubkey, destination_token_pubkey) in
+        swap_token_pubkeys.iter().zip(destination_token_pubkeys)
+    {
+        accounts.extend_from_slice(&[
+            AccountMeta::new(*swap_token_pubkey, false),
+            AccountMeta::new(*destination_token_pubkey, false),
+        ]);
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'multi_deposit_single_token_type_exact_amount_in' instruction.
+pub fn multi_deposit_single_token_type_exact_amount_in(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_token_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    swap_token_pubkeys: &[Pubkey],
+    instruction: DepositSingleTokenTypeExactAmountIn,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::MultiDepositSingleTokenTypeExactAmountIn(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_token_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    accounts.extend(
+        swap_token_pubkeys
+            .iter()
+            .map(|pubkey| AccountMeta::new(*pubkey, false)),
+    );
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'multi_withdraw_single_token_type_exact_amount_out' instruction.
+pub fn multi_withdraw_single_token_type_exact_amount_out(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    swap_token_pubkeys: &[Pubkey],
+    instruction: WithdrawSingleTokenTypeExactAmountOut,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::MultiWithdrawSingleTokenTypeExactAmountOut(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    accounts.extend(
+        swap_token_pubkeys
+            .iter()
+            .map(|pubkey| AccountMeta::new(*pubkey, false)),
+    );
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'initialize_concentrated' instruction.
+pub fn initialize_concentrated(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    pool_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    token_a_pubkey: &Pubkey,
+    token_b_pubkey: &Pubkey,
+    fixed_fee_a_pubkey: &Pubkey,
+    fixed_fee_b_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    instruction: InitializeConcentratedInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::InitializeConcentrated(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*pool_pubkey, true),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*token_a_pubkey, false),
+        AccountMeta::new_readonly(*token_b_pubkey, false),
+        AccountMeta::new_readonly(*fixed_fee_a_pubkey, false),
+        AccountMeta::new_readonly(*fixed_fee_b_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'open_position' instruction.
+pub fn open_position(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    pool_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    position_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    deposit_token_a_pubkey: &Pubkey,
+    deposit_token_b_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    instruction: OpenPositionInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::OpenPosition(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*pool_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*position_pubkey, false),
+        AccountMeta::new_readonly(*owner_pubkey, true),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*deposit_token_a_pubkey, false),
+        AccountMeta::new(*deposit_token_b_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'concentrated_swap' instruction.
+pub fn concentrated_swap(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    pool_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_source_pubkey: &Pubkey,
+    swap_destination_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    fixed_fee_pubkey: &Pubkey,
+    fee_wallet_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    instruction: SwapInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::ConcentratedSwap(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*pool_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_source_pubkey, false),
+        AccountMeta::new(*swap_destination_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*fixed_fee_pubkey, false),
+        AccountMeta::new(*fee_wallet_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Accounts of the `CollectFees` and `ClosePosition` instructions
+fn position_withdraw_accounts(
+    token_program_id: &Pubkey,
+    pool_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    position_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    destination_token_a_pubkey: &Pubkey,
+    destination_token_b_pubkey: &Pubkey,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*pool_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*position_pubkey, false),
+        AccountMeta::new(*owner_pubkey, true),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*destination_token_a_pubkey, false),
+        AccountMeta::new(*destination_token_b_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ]
+}
+
+/// Creates a 'collect_fees' instruction.
+pub fn collect_fees(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    pool_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    position_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    destination_token_a_pubkey: &Pubkey,
+    destination_token_b_pubkey: &Pubkey,
+    instruction: CollectFeesInstruction,
+) -> Result<Instruction, ProgramError> {
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: position_withdraw_accounts(
+            token_program_id,
+            pool_pubkey,
+            authority_pubkey,
+            position_pubkey,
+            owner_pubkey,
+            swap_token_a_pubkey,
+            swap_token_b_pubkey,
+            destination_token_a_pubkey,
+            destination_token_b_pubkey,
+        ),
+        data: AmmInstruction::CollectFees(instruction).pack(),
+    })
+}
+
+/// Creates a 'close_position' instruction.
+pub fn close_position(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    pool_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    position_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    destination_token_a_pubkey: &Pubkey,
+    destination_token_b_pubkey: &Pubkey,
+    instruction: ClosePositionInstruction,
+) -> Result<Instruction, ProgramError> {
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: position_withdraw_accounts(
+            token_program_id,
+            pool_pubkey,
+            authority_pubkey,
+            position_pubkey,
+            owner_pubkey,
+            swap_token_a_pubkey,
+            swap_token_b_pubkey,
+            destination_token_a_pubkey,
+            destination_token_b_pubkey,
+        ),
+        data: AmmInstruction::ClosePosition(instruction).pack(),
+    })
+}
+
+/// Creates a 'flash_loan' instruction.
+pub fn flash_loan(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    swap_token_pubkey: &Pubkey,
+    other_swap_token_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    fixed_fee_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    open_orders_pubkey: &Pubkey,
+    receiver_program_id: &Pubkey,
+    receiver_accounts: &[AccountMeta],
+    instruction: FlashLoanInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::FlashLoan(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*swap_token_pubkey, false),
+        AccountMeta::new_readonly(*other_swap_token_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*fixed_fee_pubkey, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new_readonly(*open_orders_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*receiver_program_id, false),
+    ];
+    accounts.extend_from_slice(receiver_accounts);
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'flash_swap' instruction.
+pub fn flash_swap(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    swap_source_pubkey: &Pubkey,
+    swap_destination_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    fixed_fee_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    open_orders_pubkey: &Pubkey,
+    receiver_program_id: &Pubkey,
+    receiver_accounts: &[AccountMeta],
+    instruction: FlashSwapInstruction,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::FlashSwap(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*swap_source_pubkey, false),
+        AccountMeta::new(*swap_destination_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*fixed_fee_pubkey, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new_readonly(*open_orders_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*receiver_program_id, false),
+    ];
+    accounts.extend_from_slice(receiver_accounts);
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_host_fee' instruction.
+pub fn set_host_fee(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    host_fee: HostFee,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetHostFee(SetHostFeeInstruction { host_fee }).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_protocol_fee' instruction.
+pub fn set_protocol_fee(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    protocol_fee: ProtocolFee,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetProtocolFee(SetProtocolFeeInstruction {
+        numerator: protocol_fee.numerator,
+        denominator: protocol_fee.denominator,
+    })
+    .pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new_readonly(protocol_fee.fee_account, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Seed used to derive the address of the temporary WSOL account of native
+/// SOL instructions
+pub const NATIVE_SOL_SEED: &[u8] = b"native_sol";
+
+/// Finds the address of the temporary WSOL account wrapping the native SOL of
+/// `user_pubkey` in instructions of a pool, and its nonce
+pub fn find_native_sol_address(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    user_pubkey: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            NATIVE_SOL_SEED,
+            &swap_pubkey.to_bytes(),
+            &user_pubkey.to_bytes(),
+        ],
+        program_id,
+    )
+}
+
+/// Creates the native SOL variant of a 'swap', 'swap_exact_amount_out',
+/// 'deposit_all_token_types', 'withdraw_all_token_types',
+/// 'deposit_single_token_type_exact_amount_in' or
+/// 'withdraw_single_token_type_exact_amount_out' instruction, built with the
+/// user wallet as user transfer authority and the address of
+/// `find_native_sol_address` as the account of the SOL side.
+pub fn native_sol(instruction: Instruction) -> Result<Instruction, ProgramError> {
+    let data = match AmmInstruction::unpack(&instruction.data)? {
+        AmmInstruction::Swap(swap) => AmmInstruction::SwapNativeSol(swap),
+        AmmInstruction::DepositAllTokenTypes(deposit) => {
+            AmmInstruction::DepositAllTokenTypesNativeSol(deposit)
+        }
+        AmmInstruction::WithdrawAllTokenTypes(withdraw) => {
+            AmmInstruction::WithdrawAllTokenTypesNativeSol(withdraw)
+        }
+        AmmInstruction::DepositSingleTokenTypeExactAmountIn(deposit) => {
+            AmmInstruction::DepositSingleTokenTypeExactAmountInNativeSol(deposit)
+        }
+        AmmInstruction::WithdrawSingleTokenTypeExactAmountOut(withdraw) => {
+            AmmInstruction::WithdrawSingleTokenTypeExactAmountOutNativeSol(withdraw)
+        }
+        AmmInstruction::SwapExactAmountOut(swap) => {
+            AmmInstruction::SwapExactAmountOutNativeSol(swap)
+        }
+        _ => return Err(AmmError::InvalidInstruction.into()),
+    }
+    .pack();
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(spl_token::native_mint::id(), false),
+        AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+    ];
+    // the user wallet pays for the temporary WSOL account and receives its
+    // lamports back
+    accounts.extend(
+        instruction
+            .accounts
+            .into_iter()
+            .enumerate()
+            .map(|(index, mut account)| {
+                account.is_writable |= index == 2;
+                account
+            }),
+    );
+
+    Ok(Instruction {
+        program_id: instruction.program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Appends the Token-2022 program and `token_2022_mints`, the mints of the
+/// pool tokens on Token-2022, to an 'initialize', 'swap',
+/// 'swap_exact_amount_out', 'deposit_all_token_types',
+/// 'withdraw_all_token_types', 'deposit_single_token_type_exact_amount_in' or
+/// 'withdraw_single_token_type_exact_amount_out' instruction of a two token
+/// pool holding Token-2022 tokens.
+pub fn with_token_2022(mut instruction: Instruction, token_2022_mints: &[Pubkey]) -> Instruction {
+    instruction
+        .accounts
+        .push(AccountMeta::new_readonly(spl_token_2022::id(), false));
+    instruction.accounts.extend(
+        token_2022_mints
+            .iter()
+            .map(|mint| AccountMeta::new_readonly(*mint, false)),
+    );
+    instruction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::curve::{
+        base::CurveType, constant_product::ConstantProductCurve, multi::MultiStableCurve,
+    };
+    use proptest::prelude::*;
+
+    fn check_round_trip(instruction: AmmInstruction) {
+        let packed = instruction.pack();
+        let unpacked = AmmInstruction::unpack(&packed).unwrap();
+        assert_eq!(instruction, unpacked);
+    }
+
+    #[test]
+    fn pack_initialize() {
+        let return_fee_numerator: u64 = 25;
+        let fixed_fee_numerator: u64 = 5;
+        let fee_denominator: u64 = 10000;
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let nonce: u8 = 255;
+        let curve_type = CurveType::ConstantProduct;
+        let calculator = Box::new(ConstantProductCurve {});
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator,
+        };
+        let check = AmmInstruction::Initialize(InitializeInstruction {
+            nonce,
+            fees: fees.clone(),
+            swap_curve: swap_curve.clone(),
+        });
+        let packed = check.pack();
+        let mut expect = vec![0u8, nonce];
+        let mut fees_slice = [0u8; Fees::LEN];
+        Pack::pack_into_slice(&fees, &mut fees_slice[..]);
+        expect.extend_from_slice(&fees_slice);
+        let mut swap_curve_slice = [0u8; SwapCurve::LEN];
+        Pack::pack_into_slice(&swap_curve, &mut swap_curve_slice[..]);
+        expect.extend_from_slice(&swap_curve_slice);
+        assert_eq!(packed, expect);
+        let unpacked = AmmInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        // the owner withdraw fee trails the curve
+        let fees = Fees {
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 100,
+            ..fees
+        };
+        let check = AmmInstruction::Initialize(InitializeInstruction {
+            nonce,
+            fees,
+            swap_curve,
+        });
+        expect.extend_from_slice(&1u64.to_le_bytes());
+        expect.extend_from_slice(&100u64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        assert_eq!(AmmInstruction::unpack(&expect).unwrap(), check);
+        assert_eq!(
+            AmmInstruction::unpack(&expect[..expect.len() - 1]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn pack_swap() {
+        let amount_in: u64 = 2;
+        let minimum_amount_out: u64 = 10;
+        let check = AmmInstruction::Swap(SwapInstruction {
+            amount_in,
+            minimum_amount_out,
+            deadline: None,
+        });
+        let packed = check.pack();
+        let mut expect = vec![1];
+        expect.extend_from_slice(&amount_in.to_le_bytes());
+        expect.extend_from_slice(&minimum_amount_out.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = AmmInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let deadline: i64 = 1_700_000_000;
+        let check = AmmInstruction::Swap(SwapInstruction {
+            amount_in,
+            minimum_amount_out,
+            deadline: Some(Deadline::UnixTimestamp(deadline)),
+        });
+        let packed = check.pack();
+        let mut expect_timestamp = expect.clone();
+        expect_timestamp.push(1);
+        expect_timestamp.extend_from_slice(&deadline.to_le_bytes());
+        assert_eq!(packed, expect_timestamp);
+        let unpacked = AmmInstruction::unpack(&expect_timestamp).unwrap();
+        assert_eq!(unpacked, check);
+
+        // a truncated deadline is rejected rather than ignored
+        assert_eq!(
+            AmmInstruction::unpack(&expect_timestamp[..expect_timestamp.len() - 1]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+
+        let check = AmmInstruction::Swap(SwapInstruction {
+            amount_in,
+            minimum_amount_out,
+            deadline: Some(Deadline::Slot(u64::MAX)),
+        });
+        let mut expect_slot = expect.clone();
+        expect_slot.push(2);
+        expect_slot.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(check.pack(), expect_slot);
+        assert_eq!(AmmInstruction::unpack(&expect_slot).unwrap(), check);
+
+        // unix timestamps beyond an i64 and unknown kinds are rejected
+        let mut overflow = expect.clone();
+        overflow.push(1);
+        overflow.extend_from_slice(&(i64::MAX as u64 + 1).to_le_bytes());
+        assert_eq!(
+            AmmInstruction::unpack(&overflow),
+            Err(AmmError::InvalidInstruction.into())
+        );
+        let mut unknown = expect.clone();
+        unknown.push(3);
+        unknown.extend_from_slice(&1u64.to_le_bytes());
+        assert_eq!(
+            AmmInstruction::unpack(&unknown),
+            Err(AmmError::InvalidInstruction.into())
+        );
+        // kind 0 is no deadline
+        expect.push(0);
+        assert_eq!(
+            AmmInstruction::unpack(&expect).unwrap(),
+            AmmInstruction::Swap(SwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline: None,
+            })
+        );
+    }
+
+    #[test]
+    fn route_swap_accounts() {
+        let program_id = Pubkey::new_unique();
+        let legs = vec![
+            RouteSwapLeg {
+                swap_pubkey: Pubkey::new_unique(),
+                authority_pubkey: Pubkey::new_unique(),
+                swap_source_pubkey: Pubkey::new_unique(),
+                swap_destination_pubkey: Pubkey::new_unique(),
+                destination_pubkey: Pubkey::new_unique(),
+                pool_mint_pubkey: Pubkey::new_unique(),
+                fixed_fee_pubkey: Pubkey::new_unique(),
+                open_orders_pubkey: Pubkey::new_unique(),
+            };
+            2
+        ];
+        let instruction = route_swap(
+            &program_id,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &legs,
+            RouteSwapInstruction {
+                amount_in: 10,
+                minimum_amount_out: 1,
+                deadline: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(instruction.accounts.len(), 6 + 2 * ROUTE_SWAP_LEG_ACCOUNTS);
+        assert!(instruction.accounts[0].is_signer);
+        assert_eq!(instruction.accounts[6].pubkey, legs[0].swap_pubkey);
+        assert_eq!(
+            instruction.accounts[6 + ROUTE_SWAP_LEG_ACCOUNTS - 1].pubkey,
+            legs[0].open_orders_pubkey
+        );
+        assert_eq!(
+            instruction.accounts[6 + ROUTE_SWAP_LEG_ACCOUNTS].pubkey,
+            legs[1].swap_pubkey
+        );
+    }
+
+    #[test]
+    fn unpack_invalid() {
+        assert_eq!(
+            AmmInstruction::unpack(&[]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+        assert_eq!(
+            AmmInstruction::unpack(&[u8::MAX]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+        // truncated payloads
+        let packed = AmmInstruction::Swap(SwapInstruction {
+            amount_in: 1,
+            minimum_amount_out: 1,
+            deadline: None,
+        })
+        .pack();
+        assert_eq!(
+            AmmInstruction::unpack(&packed[..packed.len() - 1]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+        assert_eq!(
+            AmmInstruction::unpack(&[0u8, 1u8]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn pack_config_instructions() {
+        let check = AmmInstruction::InitializeConfig(InitializeConfigInstruction { nonce: 254 });
+        assert_eq!(check.pack(), vec![6u8, 254]);
+        check_round_trip(check);
+
+        let check = AmmInstruction::SetFeeWallet;
+        assert_eq!(check.pack(), vec![7u8]);
+        check_round_trip(check);
+
+        let check = AmmInstruction::SetFixedFeeAccounts;
+        assert_eq!(check.pack(), vec![36u8]);
+        check_round_trip(check);
+    }
+
+    #[test]
+    fn pack_serum_instructions() {
+        let check = AmmInstruction::PlaceOrder(PlaceOrderInstruction {
+            side: OrderSide::Ask,
+            coin_lots: 2,
+            spread_bps: 3,
+            client_order_id: 4,
+        });
+        let packed = check.pack();
+        let mut expect = vec![12u8, 1u8];
+        expect.extend_from_slice(&2u64.to_le_bytes());
+        expect.extend_from_slice(&3u64.to_le_bytes());
+        expect.extend_from_slice(&4u64.to_le_bytes());
+        assert_eq!(packed, expect);
+        check_round_trip(check);
+        assert_eq!(
+            AmmInstruction::unpack(&[12u8, 2u8]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+
+        let check = AmmInstruction::CancelOrder(CancelOrderInstruction { client_order_id: 4 });
+        let mut expect = vec![13u8];
+        expect.extend_from_slice(&4u64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+
+        let check = AmmInstruction::SettleFunds;
+        assert_eq!(check.pack(), vec![14u8]);
+        check_round_trip(check);
+    }
+
+    #[test]
+    fn pack_amp_ramp_instructions() {
+        let check = AmmInstruction::RampAmp(RampAmpInstruction {
+            target_amp: 200,
+            stop_ramp_ts: 1_600_086_400,
+        });
+        let mut expect = vec![15u8];
+        expect.extend_from_slice(&200u64.to_le_bytes());
+        expect.extend_from_slice(&1_600_086_400i64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+        assert_eq!(
+            AmmInstruction::unpack(&expect[..16]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+
+        let check = AmmInstruction::StopRampAmp;
+        assert_eq!(check.pack(), vec![16u8]);
+        check_round_trip(check);
+    }
+
+    #[test]
+    fn pack_set_pool_status() {
+        for status in &[PoolStatus::Active, PoolStatus::WithdrawOnly, PoolStatus::Halted] {
+            let check = AmmInstruction::SetPoolStatus(SetPoolStatusInstruction { status: *status });
+            assert_eq!(check.pack(), vec![9u8, *status as u8]);
+            check_round_trip(check);
+        }
+        assert_eq!(
+            AmmInstruction::unpack(&[9u8, u8::MAX]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+        assert_eq!(
+            AmmInstruction::unpack(&[9u8]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn pack_set_host_fee() {
+        let check = AmmInstruction::SetHostFee(SetHostFeeInstruction {
+            host_fee: HostFee {
+                numerator: 20,
+                denominator: 100,
+            },
+        });
+        let mut expect = vec![30u8];
+        expect.extend_from_slice(&20u64.to_le_bytes());
+        expect.extend_from_slice(&100u64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+        assert_eq!(
+            AmmInstruction::unpack(&expect[..expect.len() - 1]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn pack_set_protocol_fee() {
+        let check = AmmInstruction::SetProtocolFee(SetProtocolFeeInstruction {
+            numerator: 1,
+            denominator: 6,
+        });
+        let mut expect = vec![32u8];
+        expect.extend_from_slice(&1u64.to_le_bytes());
+        expect.extend_from_slice(&6u64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+        assert_eq!(
+            AmmInstruction::unpack(&expect[..expect.len() - 1]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn pack_native_sol_instructions() {
+        let check = AmmInstruction::SwapNativeSol(SwapInstruction {
+            amount_in: 2,
+            minimum_amount_out: 10,
+            deadline: Some(Deadline::UnixTimestamp(1_700_000_000)),
+        });
+        let mut expect = vec![33u8];
+        expect.extend_from_slice(&2u64.to_le_bytes());
+        expect.extend_from_slice(&10u64.to_le_bytes());
+        expect.push(1);
+        expect.extend_from_slice(&1_700_000_000i64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+
+        let check = AmmInstruction::DepositAllTokenTypesNativeSol(DepositInstruction {
+            pool_token_amount: 5,
+            maximum_token_a_amount: 10,
+            maximum_token_b_amount: 20,
+            deadline: None,
+        });
+        let mut expect = vec![34u8];
+        expect.extend_from_slice(&5u64.to_le_bytes());
+        expect.extend_from_slice(&10u64.to_le_bytes());
+        expect.extend_from_slice(&20u64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+
+        let check = AmmInstruction::WithdrawAllTokenTypesNativeSol(WithdrawInstruction {
+            pool_token_amount: 1212438012089,
+            minimum_token_a_amount: 102198761982612,
+            minimum_token_b_amount: 2011239855213,
+            deadline: None,
+        });
+        let mut expect = vec![35u8];
+        expect.extend_from_slice(&1212438012089u64.to_le_bytes());
+        expect.extend_from_slice(&102198761982612u64.to_le_bytes());
+        expect.extend_from_slice(&2011239855213u64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+
+        let check = AmmInstruction::DepositSingleTokenTypeExactAmountInNativeSol(
+            DepositSingleTokenTypeExactAmountIn {
+                source_token_amount: 10,
+                minimum_pool_token_amount: 5,
+                deadline: None,
+            },
+        );
+        let mut expect = vec![37u8];
+        expect.extend_from_slice(&10u64.to_le_bytes());
+        expect.extend_from_slice(&5u64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+
+        let check = AmmInstruction::WithdrawSingleTokenTypeExactAmountOutNativeSol(
+            WithdrawSingleTokenTypeExactAmountOut {
+                destination_token_amount: 10,
+                maximum_pool_token_amount: 50,
+                deadline: None,
+            },
+        );
+        let mut expect = vec![38u8];
+        expect.extend_from_slice(&10u64.to_le_bytes());
+        expect.extend_from_slice(&50u64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+
+        let check = AmmInstruction::SwapExactAmountOutNativeSol(SwapExactAmountOutInstruction {
+            amount_out: 10,
+            maximum_amount_in: 12,
+            deadline: Some(Deadline::UnixTimestamp(1_700_000_000)),
+        });
+        let mut expect = vec![39u8];
+        expect.extend_from_slice(&10u64.to_le_bytes());
+        expect.extend_from_slice(&12u64.to_le_bytes());
+        expect.push(1);
+        expect.extend_from_slice(&1_700_000_000i64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+    }
+
+    #[test]
+    fn native_sol_variants() {
+        let program_id = Pubkey::new_unique();
+        let swap_pubkey = Pubkey::new_unique();
+        let user_pubkey = Pubkey::new_unique();
+        let (native_sol_pubkey, _nonce) =
+            find_native_sol_address(&program_id, &swap_pubkey, &user_pubkey);
+        let deposit = DepositInstruction {
+            pool_token_amount: 5,
+            maximum_token_a_amount: 10,
+            maximum_token_b_amount: 20,
+            deadline: None,
+        };
+        let instruction = deposit_all_token_types(
+            &program_id,
+            &spl_token::id(),
+            &swap_pubkey,
+            &Pubkey::new_unique(),
+            &user_pubkey,
+            &native_sol_pubkey,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            deposit,
+        )
+        .unwrap();
+        let native = native_sol(instruction.clone()).unwrap();
+        assert_eq!(native.program_id, program_id);
+        assert_eq!(
+            AmmInstruction::unpack(&native.data).unwrap(),
+            AmmInstruction::DepositAllTokenTypesNativeSol(DepositInstruction {
+                pool_token_amount: 5,
+                maximum_token_a_amount: 10,
+                maximum_token_b_amount: 20,
+                deadline: None,
+            })
+        );
+        assert_eq!(native.accounts.len(), instruction.accounts.len() + 3);
+        assert_eq!(native.accounts[0].pubkey, spl_token::native_mint::id());
+        assert_eq!(native.accounts[5], AccountMeta::new(user_pubkey, true));
+        assert_eq!(native.accounts[6..], instruction.accounts[3..]);
+
+        // only swaps, deposits and withdrawals take native SOL
+        let instruction = set_host_fee(
+            &program_id,
+            &swap_pubkey,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            HostFee::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            native_sol(instruction),
+            Err(AmmError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn pack_multi_instructions() {
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = MultiSwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Box::new(MultiStableCurve { amp: 100 }),
+        };
+        let check = AmmInstruction::InitializeMulti(InitializeMultiInstruction {
+            nonce: 255,
+            fees: fees.clone(),
+            swap_curve: swap_curve.clone(),
+        });
+        let mut expect = vec![17u8, 255];
+        let mut fees_slice = [0u8; Fees::LEN];
+        Pack::pack_into_slice(&fees, &mut fees_slice[..]);
+        expect.extend_from_slice(&fees_slice);
+        let mut swap_curve_slice = [0u8; MultiSwapCurve::LEN];
+        Pack::pack_into_slice(&swap_curve, &mut swap_curve_slice[..]);
+        expect.extend_from_slice(&swap_curve_slice);
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+
+        let check = AmmInstruction::MultiSwap(SwapInstruction {
+            amount_in: 2,
+            minimum_amount_out: 10,
+            deadline: None,
+        });
+        let mut expect = vec![18u8];
+        expect.extend_from_slice(&2u64.to_le_bytes());
+        expect.extend_from_slice(&10u64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+
+        let check = AmmInstruction::MultiDepositAllTokenTypes(MultiDepositInstruction {
+            pool_token_amount: 5,
+            maximum_token_amounts: vec![1, 2, 3],
+            deadline: None,
+        });
+        let mut expect = vec![19u8];
+        expect.extend_from_slice(&5u64.to_le_bytes());
+        expect.push(3);
+        for amount in &[1u64, 2, 3] {
+            expect.extend_from_slice(&amount.to_le_bytes());
+        }
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+        // missing an amount
+        assert_eq!(
+            AmmInstruction::unpack(&expect[..expect.len() - 8]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+
+        check_round_trip(AmmInstruction::MultiWithdrawAllTokenTypes(
+            MultiWithdrawInstruction {
+                pool_token_amount: 5,
+                minimum_token_amounts: vec![0; MAX_TOKENS],
+                deadline: None,
+            },
+        ));
+        let mut too_many = vec![20u8];
+        too_many.extend_from_slice(&5u64.to_le_bytes());
+        too_many.push(MAX_TOKENS as u8 + 1);
+        too_many.extend_from_slice(&[0u8; 8 * (MAX_TOKENS + 1)]);
+        assert_eq!(
+            AmmInstruction::unpack(&too_many),
+            Err(AmmError::InvalidInstruction.into())
+        );
+
+        let check = AmmInstruction::MultiDepositSingleTokenTypeExactAmountIn(
+            DepositSingleTokenTypeExactAmountIn {
+                source_token_amount: 7,
+                minimum_pool_token_amount: 1,
+                deadline: None,
+            },
+        );
+        assert_eq!(check.pack()[0], 21);
+        check_round_trip(check);
+
+        let check = AmmInstruction::MultiWithdrawSingleTokenTypeExactAmountOut(
+            WithdrawSingleTokenTypeExactAmountOut {
+                destination_token_amount: 7,
+                maximum_pool_token_amount: 100,
+                deadline: None,
+            },
+        );
+        assert_eq!(check.pack()[0], 22);
+        check_round_trip(check);
+    }
+
+    #[test]
+    fn pack_concentrated_instructions() {
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let check = AmmInstruction::InitializeConcentrated(InitializeConcentratedInstruction {
+            nonce: 255,
+            fees: fees.clone(),
+            tick_spacing: 64,
+            sqrt_price: 1 << 64,
+        });
+        let mut expect = vec![23u8, 255];
+        let mut fees_slice = [0u8; Fees::LEN];
+        Pack::pack_into_slice(&fees, &mut fees_slice[..]);
+        expect.extend_from_slice(&fees_slice);
+        expect.extend_from_slice(&64u16.to_le_bytes());
+        expect.extend_from_slice(&(1u128 << 64).to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+        assert_eq!(
+            AmmInstruction::unpack(&expect[..expect.len() - 1]),
+            Err(AmmError::InvalidInstruction.into())
+        );
+
+        let check = AmmInstruction::OpenPosition(OpenPositionInstruction {
+            tick_lower: -128,
+            tick_upper: 256,
+            liquidity: 1_000_000,
+            maximum_token_a_amount: 10,
+            maximum_token_b_amount: 20,
+            deadline: Some(Deadline::Slot(300)),
+        });
+        let mut expect = vec![24u8];
+        expect.extend_from_slice(&(-128i32).to_le_bytes());
+        expect.extend_from_slice(&256i32.to_le_bytes());
+        expect.extend_from_slice(&1_000_000u128.to_le_bytes());
+        expect.extend_from_slice(&10u64.to_le_bytes());
+        expect.extend_from_slice(&20u64.to_le_bytes());
+        expect.push(2);
+        expect.extend_from_slice(&300u64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+
+        let check = AmmInstruction::ConcentratedSwap(SwapInstruction {
+            amount_in: 2,
+            minimum_amount_out: 10,
+            deadline: None,
+        });
+        assert_eq!(check.pack()[0], 25);
+        check_round_trip(check);
+
+        let check = AmmInstruction::CollectFees(CollectFeesInstruction { deadline: None });
+        assert_eq!(check.pack(), vec![26u8]);
+        check_round_trip(check);
+        check_round_trip(AmmInstruction::CollectFees(CollectFeesInstruction {
+            deadline: Some(Deadline::UnixTimestamp(1_700_000_000)),
+        }));
+
+        let check = AmmInstruction::ClosePosition(ClosePositionInstruction {
+            minimum_token_a_amount: 1,
+            minimum_token_b_amount: 2,
+            deadline: None,
+        });
+        let mut expect = vec![27u8];
+        expect.extend_from_slice(&1u64.to_le_bytes());
+        expect.extend_from_slice(&2u64.to_le_bytes());
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+
+        // the deadline of a flash instruction precedes the receiver data, an
+        // unset one is packed as kind 0
+        let check = AmmInstruction::FlashLoan(FlashLoanInstruction {
+            amount: 1_000,
+            deadline: None,
+            data: vec![7, 8, 9],
+        });
+        let mut expect = vec![28u8];
+        expect.extend_from_slice(&1_000u64.to_le_bytes());
+        expect.push(0);
+        expect.extend_from_slice(&[7, 8, 9]);
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+        check_round_trip(AmmInstruction::FlashLoan(FlashLoanInstruction {
+            amount: 1,
+            deadline: Some(Deadline::Slot(10)),
+            data: vec![],
+        }));
+
+        let check = AmmInstruction::FlashSwap(FlashSwapInstruction {
+            amount_out: 500,
+            deadline: Some(Deadline::UnixTimestamp(1_700_000_000)),
+            data: vec![1],
+        });
+        let mut expect = vec![29u8];
+        expect.extend_from_slice(&500u64.to_le_bytes());
+        expect.push(1);
+        expect.extend_from_slice(&1_700_000_000i64.to_le_bytes());
+        expect.push(1);
+        assert_eq!(check.pack(), expect);
+        check_round_trip(check);
+    }
+
+    #[test]
+    fn multi_deposit_accounts() {
+        let deposit_token_pubkeys = (0..3).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let swap_token_pubkeys = (0..3).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let instruction = multi_deposit_all_token_types(
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &deposit_token_pubkeys,
+            &swap_token_pubkeys,
+            MultiDepositInstruction {
+                pool_token_amount: 5,
+                maximum_token_amounts: vec![1, 2, 3],
+                deadline: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(instruction.accounts.len(), 6 + 3 * MULTI_TOKEN_ACCOUNTS);
+        assert_eq!(instruction.accounts[6].pubkey, deposit_token_pubkeys[0]);
+        assert_eq!(instruction.accounts[7].pubkey, swap_token_pubkeys[0]);
+
+        assert_eq!(
+            multi_deposit_all_token_types(
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &deposit_token_pubkeys[..2],
+                &swap_token_pubkeys,
+                MultiDepositInstruction {
+                    pool_token_amount: 5,
+                    maximum_token_amounts: vec![1, 2, 3],
+                    deadline: None,
+                },
+            ),
+            Err(AmmError::InvalidTokenCount.into())
+        );
+    }
+
+    fn deadline() -> impl Strategy<Value = Option<Deadline>> {
+        prop_oneof![
+            Just(None),
+            (0..=i64::MAX).prop_map(|unix_timestamp| Some(Deadline::UnixTimestamp(unix_timestamp))),
+            any::<u64>().prop_map(|slot| Some(Deadline::Slot(slot))),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip_initialize(
+            nonce in any::<u8>(),
+            return_fee_numerator in any::<u64>(),
+            fixed_fee_numerator in any::<u64>(),
+            fee_denominator in any::<u64>(),
+            owner_withdraw_fee_numerator in any::<u64>(),
+            owner_withdraw_fee_denominator in any::<u64>(),
+        ) {
+            check_round_trip(AmmInstruction::Initialize(InitializeInstruction {
+                nonce,
+                fees: Fees {
+                    return_fee_numerator,
+                    fixed_fee_numerator,
+                    fee_denominator,
+                    owner_withdraw_fee_numerator,
+                    owner_withdraw_fee_denominator,
+                },
+                swap_curve: SwapCurve {
+                    curve_type: CurveType::ConstantProduct,
+                    calculator: Box::new(ConstantProductCurve {}),
+                },
+            }));
+        }
+
+        #[test]
+        fn round_trip_set_fees(
+            return_fee_numerator in any::<u64>(),
+            fixed_fee_numerator in any::<u64>(),
+            fee_denominator in any::<u64>(),
+            owner_withdraw_fee_numerator in any::<u64>(),
+            owner_withdraw_fee_denominator in any::<u64>(),
+        ) {
+            check_round_trip(AmmInstruction::SetFees(SetFeesInstruction {
+                fees: Fees {
+                    return_fee_numerator,
+                    fixed_fee_numerator,
+                    fee_denominator,
+                    owner_withdraw_fee_numerator,
+                    owner_withdraw_fee_denominator,
+                },
+            }));
+        }
+
+        #[test]
+        fn round_trip_swap(
+            amount_in in any::<u64>(),
+            minimum_amount_out in any::<u64>(),
+            deadline in deadline(),
+        ) {
+            check_round_trip(AmmInstruction::Swap(SwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            }));
+        }
+
+        #[test]
+        fn round_trip_swap_exact_amount_out(
+            amount_out in any::<u64>(),
+            maximum_amount_in in any::<u64>(),
+            deadline in deadline(),
+        ) {
+            check_round_trip(AmmInstruction::SwapExactAmountOut(SwapExactAmountOutInstruction {
+                amount_out,
+                maximum_amount_in,
+                deadline,
+            }));
+        }
+
+        #[test]
+        fn round_trip_route_swap(
+            amount_in in any::<u64>(),
+            minimum_amount_out in any::<u64>(),
+            deadline in deadline(),
+        ) {
+            check_round_trip(AmmInstruction::RouteSwap(RouteSwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            }));
+        }
+
+        #[test]
+        fn round_trip_place_order(
+            bid in any::<bool>(),
+            coin_lots in any::<u64>(),
+            spread_bps in any::<u64>(),
+            client_order_id in any::<u64>(),
+        ) {
+            let side = if bid { OrderSide::Bid } else { OrderSide::Ask };
+            check_round_trip(AmmInstruction::PlaceOrder(PlaceOrderInstruction {
+                side,
+                coin_lots,
+                spread_bps,
+                client_order_id,
+            }));
+        }
+
+        #[test]
+        fn round_trip_cancel_order(client_order_id in any::<u64>()) {
+            check_round_trip(AmmInstruction::CancelOrder(CancelOrderInstruction {
+                client_order_id,
+            }));
+        }
+
+        #[test]
+        fn round_trip_deposit_all_token_types(
+            pool_token_amount in any::<u64>(),
+            maximum_token_a_amount in any::<u64>(),
+            maximum_token_b_amount in any::<u64>(),
+            deadline in deadline(),
+        ) {
+            check_round_trip(AmmInstruction::DepositAllTokenTypes(DepositInstruction {
+                pool_token_amount,
+                maximum_token_a_amount,
+                maximum_token_b_amount,
+                deadline,
+            }));
+        }
+
+        #[test]
+        fn round_trip_withdraw_all_token_types(
+            pool_token_amount in any::<u64>(),
+            minimum_token_a_amount in any::<u64>(),
+            minimum_token_b_amount in any::<u64>(),
+            deadline in deadline(),
+        ) {
+            check_round_trip(AmmInstruction::WithdrawAllTokenTypes(WithdrawInstruction {
+                pool_token_amount,
+                minimum_token_a_amount,
+                minimum_token_b_amount,
+                deadline,
+            }));
+        }
+
+        #[test]
+        fn round_trip_deposit_single_token_type_exact_amount_in(
+            source_token_amount in any::<u64>(),
+            minimum_pool_token_amount in any::<u64>(),
+            deadline in deadline(),
+        ) {
+            check_round_trip(AmmInstruction::DepositSingleTokenTypeExactAmountIn(
+                DepositSingleTokenTypeExactAmountIn {
+                    source_token_amount,
+                    minimum_pool_token_amount,
+                    deadline,
+                },
+            ));
+        }
+
+        #[test]
+        fn round_trip_withdraw_single_token_type_exact_amount_out(
+            destination_token_amount in any::<u64>(),
+            maximum_pool_token_amount in any::<u64>(),
+            deadline in deadline(),
+        ) {
+            check_round_trip(AmmInstruction::WithdrawSingleTokenTypeExactAmountOut(
+                WithdrawSingleTokenTypeExactAmountOut {
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    deadline,
+                },
+            ));
+        }
+    }
+}
@@ -0,0 +1,3381 @@
+//! State transition types
+
+use crate::{
+    curve::{
+        base::SwapCurve,
+        calculator::TradeDirection,
+        concentrated::{
+            compute_swap_step, sqrt_price_at_tick, tick_at_sqrt_price, MAX_TICK, MIN_TICK,
+        },
+        fees::Fees,
+        multi::{MultiSwapCurve, MAX_TOKENS},
+        stable::AmpRamp,
+    },
+    error::AmmError,
+    oracle::{MultiPriceOracle, Observation, PriceOracle, OBSERVATIONS},
+};
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use enum_dispatch::enum_dispatch;
+use solana_program::{
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+    pubkey::Pubkey,
+};
+use spl_math::uint::U256;
+use std::convert::TryFrom;
+
+#[cfg(feature = "fuzz")]
+use arbitrary::Arbitrary;
+
+/// Trait representing access to program state across all versions
+#[enum_dispatch]
+pub trait AmmStatus {
+    /// Is the swap initialized, with data written to it
+    fn is_initialized(&self) -> bool;
+    /// Bump seed used to generate the program address / authority
+    fn nonce(&self) -> u8;
+    /// AMM id associated with the swap
+    fn amm_id(&self) -> &Pubkey;
+    /// Serum dex program ID associated with the swap
+    fn dex_program_id(&self) -> &Pubkey;
+    /// Serum market associated with the swap
+    fn market_id(&self) -> &Pubkey;
+    /// Serum open orders account of the pool authority on the market, zero
+    /// if the pool does not trade on the market
+    fn open_orders(&self) -> &Pubkey;
+    /// Ramp of the amplification of a stable curve
+    fn amp_ramp(&self) -> &AmpRamp;
+    /// Time weighted average price accumulators of the pool
+    fn price_oracle(&self) -> &PriceOracle;
+    /// Trading statistics of the pool
+    fn stats(&self) -> &PoolStats;
+    /// Token program ID associated with the swap
+    fn token_program_id(&self) -> &Pubkey;
+    /// Address of token A liquidity account
+    fn token_a_account(&self) -> &Pubkey;
+    /// Address of token B liquidity account
+    fn token_b_account(&self) -> &Pubkey;
+    /// Address of pool token mint
+    fn pool_mint(&self) -> &Pubkey;
+
+    /// Address of token A mint
+    fn token_a_mint(&self) -> &Pubkey;
+    /// Address of token B mint
+    fn token_b_mint(&self) -> &Pubkey;
+
+    /// Address of the fixed fee account for token A
+    fn fixed_fee_account_a(&self) -> &Pubkey;
+    /// Address of the fixed fee account for token B
+    fn fixed_fee_account_b(&self) -> &Pubkey;
+
+    /// Fees associated with swap
+    fn fees(&self) -> &Fees;
+    /// Curve associated with swap
+    fn swap_curve(&self) -> &SwapCurve;
+
+    /// Trading status of the swap
+    fn status(&self) -> PoolStatus;
+    /// Whether a flash loan of the pool is in progress
+    fn locked(&self) -> bool;
+    /// Share of the fixed fee of swaps paid to their host account
+    fn host_fee(&self) -> &HostFee;
+    /// Protocol fee minted in pool tokens on deposits and withdrawals
+    fn protocol_fee(&self) -> &ProtocolFee;
+
+    /// Replace the fees associated with swap, fails on versions without
+    /// room to store an owner withdraw fee when it is set
+    fn set_fees(&mut self, fees: Fees) -> Result<(), ProgramError>;
+    /// Replace the fixed fee accounts of token A and B
+    fn set_fixed_fee_accounts(&mut self, fixed_fee_account_a: Pubkey, fixed_fee_account_b: Pubkey);
+    /// Replace the trading status of the swap, fails on versions without
+    /// room to store it
+    fn set_status(&mut self, status: PoolStatus) -> Result<(), ProgramError>;
+    /// Replace the amplification ramp, fails on versions without room to
+    /// store it
+    fn set_amp_ramp(&mut self, amp_ramp: AmpRamp) -> Result<(), ProgramError>;
+    /// Lock or unlock the pool around a flash loan, fails on versions
+    /// without room to store the lock
+    fn set_locked(&mut self, locked: bool) -> Result<(), ProgramError>;
+    /// Replace the host fee, fails on versions without room to store it
+    fn set_host_fee(&mut self, host_fee: HostFee) -> Result<(), ProgramError>;
+    /// Replace the protocol fee, fails on versions without room to store it
+    fn set_protocol_fee(&mut self, protocol_fee: ProtocolFee) -> Result<(), ProgramError>;
+}
+
+/// Trading status of a swap, checked by every handler that moves tokens
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PoolStatus {
+    /// Swaps, deposits and withdrawals are all allowed
+    #[default]
+    Active,
+    /// Only withdrawals are allowed, so liquidity providers can exit
+    WithdrawOnly,
+    /// Nothing is allowed
+    Halted,
+}
+
+impl PoolStatus {
+    /// Whether swaps are allowed
+    pub fn can_swap(&self) -> bool {
+        *self == PoolStatus::Active
+    }
+
+    /// Whether deposits are allowed
+    pub fn can_deposit(&self) -> bool {
+        *self == PoolStatus::Active
+    }
+
+    /// Whether withdrawals are allowed
+    pub fn can_withdraw(&self) -> bool {
+        *self != PoolStatus::Halted
+    }
+}
+
+impl TryFrom<u8> for PoolStatus {
+    type Error = ProgramError;
+
+    fn try_from(status: u8) -> Result<Self, Self::Error> {
+        match status {
+            0 => Ok(PoolStatus::Active),
+            1 => Ok(PoolStatus::WithdrawOnly),
+            2 => Ok(PoolStatus::Halted),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+}
+
+/// All versions of AmmStatus
+#[enum_dispatch(AmmStatus)]
+#[allow(clippy::large_enum_variant)]
+pub enum SwapVersion {
+    /// Original version, still used by swaps created before SwapV2
+    SwapV1,
+    /// Version with a trading status, still used by swaps created before
+    /// SwapV3
+    SwapV2,
+    /// Latest version, used for all new swaps
+    SwapV3,
+}
+
+/// Version byte of SwapV3 accounts, after the ones of the multi token and
+/// concentrated liquidity pool accounts
+const SWAP_V3_VERSION: u8 = 6;
+
+/// SwapVersion does not implement program_pack::Pack because there are size
+/// checks on pack and unpack that would break backwards compatibility, so
+/// special implementations are provided here
+impl SwapVersion {
+    /// Size of the latest version of the AmmStatus
+    pub const LATEST_LEN: usize = 1 + SwapV3::LEN; // add one for the version enum
+
+    /// Pack a swap into a byte array, based on its version
+    pub fn pack(src: Self, dst: &mut [u8]) -> Result<(), ProgramError> {
+        match src {
+            Self::SwapV1(swap_info) => {
+                dst[0] = 1;
+                let dst = dst
+                    .get_mut(1..1 + SwapV1::LEN)
+                    .ok_or(ProgramError::AccountDataTooSmall)?;
+                SwapV1::pack(swap_info, dst)
+            }
+            Self::SwapV2(swap_info) => {
+                dst[0] = 2;
+                let dst = dst
+                    .get_mut(1..1 + SwapV2::LEN)
+                    .ok_or(ProgramError::AccountDataTooSmall)?;
+                SwapV2::pack(swap_info, dst)
+            }
+            Self::SwapV3(swap_info) => {
+                dst[0] = SWAP_V3_VERSION;
+                let dst = dst
+                    .get_mut(1..1 + SwapV3::LEN)
+                    .ok_or(ProgramError::AccountDataTooSmall)?;
+                SwapV3::pack(swap_info, dst)
+            }
+        }
+    }
+
+    /// Unpack the swap account based on its version, returning the versioned
+    /// state so that it can be modified and packed back
+    pub fn unpack_version(input: &[u8]) -> Result<Self, ProgramError> {
+        let (&version, rest) = input
+            .split_first()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        match version {
+            1 => {
+                let rest = rest
+                    .get(..SwapV1::LEN)
+                    .ok_or(ProgramError::InvalidAccountData)?;
+                Ok(Self::SwapV1(SwapV1::unpack(rest)?))
+            }
+            2 => {
+                let rest = rest
+                    .get(..SwapV2::LEN)
+                    .ok_or(ProgramError::InvalidAccountData)?;
+                Ok(Self::SwapV2(SwapV2::unpack(rest)?))
+            }
+            SWAP_V3_VERSION => {
+                let rest = rest
+                    .get(..SwapV3::LEN)
+                    .ok_or(ProgramError::InvalidAccountData)?;
+                Ok(Self::SwapV3(SwapV3::unpack(rest)?))
+            }
+            _ => Err(ProgramError::UninitializedAccount),
+        }
+    }
+
+    /// Unpack the swap account based on its version, returning the result as a
+    /// AmmStatus trait object
+    pub fn unpack(input: &[u8]) -> Result<Box<dyn AmmStatus>, ProgramError> {
+        match Self::unpack_version(input)? {
+            Self::SwapV1(swap_info) => Ok(Box::new(swap_info)),
+            Self::SwapV2(swap_info) => Ok(Box::new(swap_info)),
+            Self::SwapV3(swap_info) => Ok(Box::new(swap_info)),
+        }
+    }
+
+    /// Price oracle to update, None on versions without room for it
+    pub fn price_oracle_mut(&mut self) -> Option<&mut PriceOracle> {
+        match self {
+            Self::SwapV1(_) => None,
+            Self::SwapV2(swap_info) => Some(&mut swap_info.price_oracle),
+            Self::SwapV3(swap_info) => Some(&mut swap_info.swap.price_oracle),
+        }
+    }
+
+    /// Statistics to update, None on versions without room for them
+    pub fn stats_mut(&mut self) -> Option<&mut PoolStats> {
+        match self {
+            Self::SwapV3(swap_info) => Some(&mut swap_info.stats),
+            _ => None,
+        }
+    }
+
+    /// Upgrade the swap to the latest version.  Fields older versions have
+    /// no room for start from their defaults, with the price oracle starting
+    /// at `unix_timestamp`.
+    pub fn upgrade(self, unix_timestamp: i64) -> SwapV3 {
+        match self {
+            Self::SwapV1(swap_info) => SwapV3 {
+                swap: SwapV2 {
+                    is_initialized: swap_info.is_initialized,
+                    nonce: swap_info.nonce,
+                    amm_id: swap_info.amm_id,
+                    dex_program_id: swap_info.dex_program_id,
+                    market_id: swap_info.market_id,
+                    token_program_id: swap_info.token_program_id,
+                    token_a: swap_info.token_a,
+                    token_b: swap_info.token_b,
+                    pool_mint: swap_info.pool_mint,
+                    token_a_mint: swap_info.token_a_mint,
+                    token_b_mint: swap_info.token_b_mint,
+                    fixed_fee_account_a: swap_info.fixed_fee_account_a,
+                    fixed_fee_account_b: swap_info.fixed_fee_account_b,
+                    fees: swap_info.fees,
+                    swap_curve: swap_info.swap_curve,
+                    status: PoolStatus::Active,
+                    open_orders: NO_OPEN_ORDERS,
+                    amp_ramp: NO_AMP_RAMP,
+                    price_oracle: PriceOracle::new(unix_timestamp),
+                },
+                ..SwapV3::default()
+            },
+            Self::SwapV2(swap_info) => SwapV3 {
+                swap: swap_info,
+                ..SwapV3::default()
+            },
+            Self::SwapV3(swap_info) => swap_info,
+        }
+    }
+
+    /// Special check to be done before any instruction processing, works for
+    /// all versions, multi token and concentrated liquidity pools included
+    pub fn is_initialized(input: &[u8]) -> bool {
+        match Self::unpack(input) {
+            Ok(swap) => swap.is_initialized(),
+            Err(_) => MultiSwap::unpack_account(input)
+                .map(|multi_swap| multi_swap.is_initialized)
+                .or_else(|_| ConcentratedPool::unpack_account(input).map(|pool| pool.is_initialized))
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Program states.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct SwapV1 {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Nonce used in program address.
+    /// The program address is created deterministically with the nonce,
+    /// swap program id, and swap account pubkey.  This program address has
+    /// authority over the swap's token A account, token B account, and pool
+    /// token mint.
+    pub nonce: u8,
+
+    /// AMM id the pool is registered under
+    pub amm_id: Pubkey,
+    /// Program ID of the Serum dex the market lives on
+    pub dex_program_id: Pubkey,
+    /// Serum market linked to the pool
+    pub market_id: Pubkey,
+
+    /// Program ID of the tokens being exchanged.
+    pub token_program_id: Pubkey,
+
+    /// Token A
+    pub token_a: Pubkey,
+    /// Token B
+    pub token_b: Pubkey,
+
+    /// Pool tokens are issued when A or B tokens are deposited.
+    /// Pool tokens can be withdrawn back to the original A or B token.
+    pub pool_mint: Pubkey,
+
+    /// Mint information for token A
+    pub token_a_mint: Pubkey,
+    /// Mint information for token B
+    pub token_b_mint: Pubkey,
+
+    /// Fixed fee account receiving the fixed fee when swapping from token A
+    pub fixed_fee_account_a: Pubkey,
+    /// Fixed fee account receiving the fixed fee when swapping from token B
+    pub fixed_fee_account_b: Pubkey,
+
+    /// All fee information
+    pub fees: Fees,
+
+    /// Swap curve parameters, to be unpacked and used by the SwapCurve, which
+    /// calculates swaps, deposits, and withdrawals
+    pub swap_curve: SwapCurve,
+}
+
+impl AmmStatus for SwapV1 {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+
+    fn nonce(&self) -> u8 {
+        self.nonce
+    }
+
+    fn amm_id(&self) -> &Pubkey {
+        &self.amm_id
+    }
+
+    fn dex_program_id(&self) -> &Pubkey {
+        &self.dex_program_id
+    }
+
+    fn market_id(&self) -> &Pubkey {
+        &self.market_id
+    }
+
+    fn token_program_id(&self) -> &Pubkey {
+        &self.token_program_id
+    }
+
+    fn token_a_account(&self) -> &Pubkey {
+        &self.token_a
+    }
+
+    fn token_b_account(&self) -> &Pubkey {
+        &self.token_b
+    }
+
+    fn pool_mint(&self) -> &Pubkey {
+        &self.pool_mint
+    }
+
+    fn token_a_mint(&self) -> &Pubkey {
+        &self.token_a_mint
+    }
+
+    fn token_b_mint(&self) -> &Pubkey {
+        &self.token_b_mint
+    }
+
+    fn fixed_fee_account_a(&self) -> &Pubkey {
+        &self.fixed_fee_account_a
+    }
+
+    fn fixed_fee_account_b(&self) -> &Pubkey {
+        &self.fixed_fee_account_b
+    }
+
+    fn fees(&self) -> &Fees {
+        &self.fees
+    }
+
+    fn swap_curve(&self) -> &SwapCurve {
+        &self.swap_curve
+    }
+
+    fn status(&self) -> PoolStatus {
+        PoolStatus::Active
+    }
+
+    fn locked(&self) -> bool {
+        false
+    }
+
+    fn host_fee(&self) -> &HostFee {
+        &NO_HOST_FEE
+    }
+
+    fn protocol_fee(&self) -> &ProtocolFee {
+        &NO_PROTOCOL_FEE
+    }
+
+    fn open_orders(&self) -> &Pubkey {
+        &NO_OPEN_ORDERS
+    }
+
+    fn amp_ramp(&self) -> &AmpRamp {
+        &NO_AMP_RAMP
+    }
+
+    fn price_oracle(&self) -> &PriceOracle {
+        &NO_PRICE_ORACLE
+    }
+
+    fn stats(&self) -> &PoolStats {
+        &NO_POOL_STATS
+    }
+
+    fn set_fees(&mut self, fees: Fees) -> Result<(), ProgramError> {
+        if fees.has_owner_withdraw_fee() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        self.fees = fees;
+        Ok(())
+    }
+
+    fn set_fixed_fee_accounts(&mut self, fixed_fee_account_a: Pubkey, fixed_fee_account_b: Pubkey) {
+        self.fixed_fee_account_a = fixed_fee_account_a;
+        self.fixed_fee_account_b = fixed_fee_account_b;
+    }
+
+    fn set_status(&mut self, _status: PoolStatus) -> Result<(), ProgramError> {
+        Err(ProgramError::AccountDataTooSmall)
+    }
+
+    fn set_amp_ramp(&mut self, _amp_ramp: AmpRamp) -> Result<(), ProgramError> {
+        Err(ProgramError::AccountDataTooSmall)
+    }
+
+    fn set_locked(&mut self, _locked: bool) -> Result<(), ProgramError> {
+        Err(ProgramError::AccountDataTooSmall)
+    }
+
+    fn set_host_fee(&mut self, _host_fee: HostFee) -> Result<(), ProgramError> {
+        Err(ProgramError::AccountDataTooSmall)
+    }
+
+    fn set_protocol_fee(&mut self, _protocol_fee: ProtocolFee) -> Result<(), ProgramError> {
+        Err(ProgramError::AccountDataTooSmall)
+    }
+}
+
+impl Sealed for SwapV1 {}
+impl IsInitialized for SwapV1 {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for SwapV1 {
+    const LEN: usize = 354 + Fees::LEN + SwapCurve::LEN;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, SwapV1::LEN];
+        let (
+            is_initialized,
+            nonce,
+            amm_id,
+            dex_program_id,
+            market_id,
+            token_program_id,
+            token_a,
+            token_b,
+            pool_mint,
+            token_a_mint,
+            token_b_mint,
+            fixed_fee_account_a,
+            fixed_fee_account_b,
+            fees,
+            swap_curve,
+        ) = mut_array_refs![
+            output,
+            1,
+            1,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            Fees::LEN,
+            SwapCurve::LEN
+        ];
+        is_initialized[0] = self.is_initialized as u8;
+        nonce[0] = self.nonce;
+        amm_id.copy_from_slice(self.amm_id.as_ref());
+        dex_program_id.copy_from_slice(self.dex_program_id.as_ref());
+        market_id.copy_from_slice(self.market_id.as_ref());
+        token_program_id.copy_from_slice(self.token_program_id.as_ref());
+        token_a.copy_from_slice(self.token_a.as_ref());
+        token_b.copy_from_slice(self.token_b.as_ref());
+        pool_mint.copy_from_slice(self.pool_mint.as_ref());
+        token_a_mint.copy_from_slice(self.token_a_mint.as_ref());
+        token_b_mint.copy_from_slice(self.token_b_mint.as_ref());
+        fixed_fee_account_a.copy_from_slice(self.fixed_fee_account_a.as_ref());
+        fixed_fee_account_b.copy_from_slice(self.fixed_fee_account_b.as_ref());
+        self.fees.pack_into_slice(&mut fees[..]);
+        self.swap_curve.pack_into_slice(&mut swap_curve[..]);
+    }
+
+    /// Unpacks a byte buffer into a [SwapV1](struct.SwapV1.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, SwapV1::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            nonce,
+            amm_id,
+            dex_program_id,
+            market_id,
+            token_program_id,
+            token_a,
+            token_b,
+            pool_mint,
+            token_a_mint,
+            token_b_mint,
+            fixed_fee_account_a,
+            fixed_fee_account_b,
+            fees,
+            swap_curve,
+        ) = array_refs![
+            input,
+            1,
+            1,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            Fees::LEN,
+            SwapCurve::LEN
+        ];
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            nonce: nonce[0],
+            amm_id: Pubkey::new_from_array(*amm_id),
+            dex_program_id: Pubkey::new_from_array(*dex_program_id),
+            market_id: Pubkey::new_from_array(*market_id),
+            token_program_id: Pubkey::new_from_array(*token_program_id),
+            token_a: Pubkey::new_from_array(*token_a),
+            token_b: Pubkey::new_from_array(*token_b),
+            pool_mint: Pubkey::new_from_array(*pool_mint),
+            token_a_mint: Pubkey::new_from_array(*token_a_mint),
+            token_b_mint: Pubkey::new_from_array(*token_b_mint),
+            fixed_fee_account_a: Pubkey::new_from_array(*fixed_fee_account_a),
+            fixed_fee_account_b: Pubkey::new_from_array(*fixed_fee_account_b),
+            fees: Fees::unpack_from_slice(fees)?,
+            swap_curve: SwapCurve::unpack_from_slice(swap_curve)?,
+        })
+    }
+}
+
+/// Open orders of swap versions which cannot trade on the Serum market
+const NO_OPEN_ORDERS: Pubkey = Pubkey::new_from_array([0; 32]);
+
+/// Amplification ramp of swap versions which cannot ramp
+const NO_AMP_RAMP: AmpRamp = AmpRamp {
+    initial_amp: 0,
+    target_amp: 0,
+    start_ramp_ts: 0,
+    stop_ramp_ts: 0,
+};
+
+/// Price accumulators of swap versions which do not keep them
+const NO_PRICE_ORACLE: PriceOracle = PriceOracle {
+    price_a_cumulative: 0,
+    price_b_cumulative: 0,
+    last_update_ts: 0,
+    observation_index: 0,
+    observations: [Observation {
+        unix_timestamp: 0,
+        price_a_cumulative: 0,
+        price_b_cumulative: 0,
+    }; OBSERVATIONS],
+};
+
+/// Program states, with a trading status and space reserved for later fields.
+/// The reserved bytes are zero, so fields added there must treat zero as
+/// their default.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct SwapV2 {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Nonce used in program address.
+    /// The program address is created deterministically with the nonce,
+    /// swap program id, and swap account pubkey.  This program address has
+    /// authority over the swap's token A account, token B account, and pool
+    /// token mint.
+    pub nonce: u8,
+
+    /// AMM id the pool is registered under
+    pub amm_id: Pubkey,
+    /// Program ID of the Serum dex the market lives on
+    pub dex_program_id: Pubkey,
+    /// Serum market linked to the pool
+    pub market_id: Pubkey,
+
+    /// Program ID of the tokens being exchanged.
+    pub token_program_id: Pubkey,
+
+    /// Token A
+    pub token_a: Pubkey,
+    /// Token B
+    pub token_b: Pubkey,
+
+    /// Pool tokens are issued when A or B tokens are deposited.
+    /// Pool tokens can be withdrawn back to the original A or B token.
+    pub pool_mint: Pubkey,
+
+    /// Mint information for token A
+    pub token_a_mint: Pubkey,
+    /// Mint information for token B
+    pub token_b_mint: Pubkey,
+
+    /// Fixed fee account receiving the fixed fee when swapping from token A
+    pub fixed_fee_account_a: Pubkey,
+    /// Fixed fee account receiving the fixed fee when swapping from token B
+    pub fixed_fee_account_b: Pubkey,
+
+    /// All fee information
+    pub fees: Fees,
+
+    /// Swap curve parameters, to be unpacked and used by the SwapCurve, which
+    /// calculates swaps, deposits, and withdrawals
+    pub swap_curve: SwapCurve,
+
+    /// Trading status, set by the program config admin
+    pub status: PoolStatus,
+
+    /// Serum open orders account holding the orders placed by the pool
+    /// authority, zero if the pool does not trade on the market
+    pub open_orders: Pubkey,
+
+    /// Ramp of the amplification of a stable curve, set by the program
+    /// config admin
+    pub amp_ramp: AmpRamp,
+
+    /// Time weighted average price accumulators, updated by every
+    /// instruction moving the reserves
+    pub price_oracle: PriceOracle,
+}
+
+impl SwapV2 {
+    /// Bytes kept free at the end of the state for later fields
+    pub const RESERVED_LEN: usize = 31;
+}
+
+impl AmmStatus for SwapV2 {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+
+    fn nonce(&self) -> u8 {
+        self.nonce
+    }
+
+    fn amm_id(&self) -> &Pubkey {
+        &self.amm_id
+    }
+
+    fn dex_program_id(&self) -> &Pubkey {
+        &self.dex_program_id
+    }
+
+    fn market_id(&self) -> &Pubkey {
+        &self.market_id
+    }
+
+    fn token_program_id(&self) -> &Pubkey {
+        &self.token_program_id
+    }
+
+    fn token_a_account(&self) -> &Pubkey {
+        &self.token_a
+    }
+
+    fn token_b_account(&self) -> &Pubkey {
+        &self.token_b
+    }
+
+    fn pool_mint(&self) -> &Pubkey {
+        &self.pool_mint
+    }
+
+    fn token_a_mint(&self) -> &Pubkey {
+        &self.token_a_mint
+    }
+
+    fn token_b_mint(&self) -> &Pubkey {
+        &self.token_b_mint
+    }
+
+    fn fixed_fee_account_a(&self) -> &Pubkey {
+        &self.fixed_fee_account_a
+    }
+
+    fn fixed_fee_account_b(&self) -> &Pubkey {
+        &self.fixed_fee_account_b
+    }
+
+    fn fees(&self) -> &Fees {
+        &self.fees
+    }
+
+    fn swap_curve(&self) -> &SwapCurve {
+        &self.swap_curve
+    }
+
+    fn status(&self) -> PoolStatus {
+        self.status
+    }
+
+    fn locked(&self) -> bool {
+        false
+    }
+
+    fn host_fee(&self) -> &HostFee {
+        &NO_HOST_FEE
+    }
+
+    fn protocol_fee(&self) -> &ProtocolFee {
+        &NO_PROTOCOL_FEE
+    }
+
+    fn open_orders(&self) -> &Pubkey {
+        &self.open_orders
+    }
+
+    fn amp_ramp(&self) -> &AmpRamp {
+        &self.amp_ramp
+    }
+
+    fn price_oracle(&self) -> &PriceOracle {
+        &self.price_oracle
+    }
+
+    fn stats(&self) -> &PoolStats {
+        &NO_POOL_STATS
+    }
+
+    fn set_fees(&mut self, fees: Fees) -> Result<(), ProgramError> {
+        if fees.has_owner_withdraw_fee() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        self.fees = fees;
+        Ok(())
+    }
+
+    fn set_fixed_fee_accounts(&mut self, fixed_fee_account_a: Pubkey, fixed_fee_account_b: Pubkey) {
+        self.fixed_fee_account_a = fixed_fee_account_a;
+        self.fixed_fee_account_b = fixed_fee_account_b;
+    }
+
+    fn set_status(&mut self, status: PoolStatus) -> Result<(), ProgramError> {
+        self.status = status;
+        Ok(())
+    }
+
+    fn set_amp_ramp(&mut self, amp_ramp: AmpRamp) -> Result<(), ProgramError> {
+        self.amp_ramp = amp_ramp;
+        Ok(())
+    }
+
+    fn set_locked(&mut self, _locked: bool) -> Result<(), ProgramError> {
+        Err(ProgramError::AccountDataTooSmall)
+    }
+
+    fn set_host_fee(&mut self, _host_fee: HostFee) -> Result<(), ProgramError> {
+        Err(ProgramError::AccountDataTooSmall)
+    }
+
+    fn set_protocol_fee(&mut self, _protocol_fee: ProtocolFee) -> Result<(), ProgramError> {
+        Err(ProgramError::AccountDataTooSmall)
+    }
+}
+
+impl Sealed for SwapV2 {}
+impl IsInitialized for SwapV2 {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for SwapV2 {
+    const LEN: usize = 387
+        + Fees::LEN
+        + SwapCurve::LEN
+        + AmpRamp::LEN
+        + PriceOracle::LEN
+        + SwapV2::RESERVED_LEN;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, SwapV2::LEN];
+        let (
+            is_initialized,
+            nonce,
+            amm_id,
+            dex_program_id,
+            market_id,
+            token_program_id,
+            token_a,
+            token_b,
+            pool_mint,
+            token_a_mint,
+            token_b_mint,
+            fixed_fee_account_a,
+            fixed_fee_account_b,
+            fees,
+            swap_curve,
+            status,
+            open_orders,
+            amp_ramp,
+            price_oracle,
+            reserved,
+        ) = mut_array_refs![
+            output,
+            1,
+            1,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            Fees::LEN,
+            SwapCurve::LEN,
+            1,
+            32,
+            AmpRamp::LEN,
+            PriceOracle::LEN,
+            SwapV2::RESERVED_LEN
+        ];
+        is_initialized[0] = self.is_initialized as u8;
+        nonce[0] = self.nonce;
+        amm_id.copy_from_slice(self.amm_id.as_ref());
+        dex_program_id.copy_from_slice(self.dex_program_id.as_ref());
+        market_id.copy_from_slice(self.market_id.as_ref());
+        token_program_id.copy_from_slice(self.token_program_id.as_ref());
+        token_a.copy_from_slice(self.token_a.as_ref());
+        token_b.copy_from_slice(self.token_b.as_ref());
+        pool_mint.copy_from_slice(self.pool_mint.as_ref());
+        token_a_mint.copy_from_slice(self.token_a_mint.as_ref());
+        token_b_mint.copy_from_slice(self.token_b_mint.as_ref());
+        fixed_fee_account_a.copy_from_slice(self.fixed_fee_account_a.as_ref());
+        fixed_fee_account_b.copy_from_slice(self.fixed_fee_account_b.as_ref());
+        self.fees.pack_into_slice(&mut fees[..]);
+        self.swap_curve.pack_into_slice(&mut swap_curve[..]);
+        status[0] = self.status as u8;
+        open_orders.copy_from_slice(self.open_orders.as_ref());
+        self.amp_ramp.pack_into_slice(&mut amp_ramp[..]);
+        self.price_oracle.pack_into_slice(&mut price_oracle[..]);
+        *reserved = [0u8; SwapV2::RESERVED_LEN];
+    }
+
+    /// Unpacks a byte buffer into a [SwapV2](struct.SwapV2.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, SwapV2::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            nonce,
+            amm_id,
+            dex_program_id,
+            market_id,
+            token_program_id,
+            token_a,
+            token_b,
+            pool_mint,
+            token_a_mint,
+            token_b_mint,
+            fixed_fee_account_a,
+            fixed_fee_account_b,
+            fees,
+            swap_curve,
+            status,
+            open_orders,
+            amp_ramp,
+            price_oracle,
+            _reserved,
+        ) = array_refs![
+            input,
+            1,
+            1,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            Fees::LEN,
+            SwapCurve::LEN,
+            1,
+            32,
+            AmpRamp::LEN,
+            PriceOracle::LEN,
+            SwapV2::RESERVED_LEN
+        ];
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            nonce: nonce[0],
+            amm_id: Pubkey::new_from_array(*amm_id),
+            dex_program_id: Pubkey::new_from_array(*dex_program_id),
+            market_id: Pubkey::new_from_array(*market_id),
+            token_program_id: Pubkey::new_from_array(*token_program_id),
+            token_a: Pubkey::new_from_array(*token_a),
+            token_b: Pubkey::new_from_array(*token_b),
+            pool_mint: Pubkey::new_from_array(*pool_mint),
+            token_a_mint: Pubkey::new_from_array(*token_a_mint),
+            token_b_mint: Pubkey::new_from_array(*token_b_mint),
+            fixed_fee_account_a: Pubkey::new_from_array(*fixed_fee_account_a),
+            fixed_fee_account_b: Pubkey::new_from_array(*fixed_fee_account_b),
+            fees: Fees::unpack_from_slice(fees)?,
+            swap_curve: SwapCurve::unpack_from_slice(swap_curve)?,
+            status: PoolStatus::try_from(status[0])?,
+            open_orders: Pubkey::new_from_array(*open_orders),
+            amp_ramp: AmpRamp::unpack_from_slice(amp_ramp)?,
+            price_oracle: PriceOracle::unpack_from_slice(price_oracle)?,
+        })
+    }
+}
+
+/// Trading statistics of a pool.  The counters saturate instead of
+/// overflowing, so that they can never stop the pool from trading.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PoolStats {
+    /// Token A swapped into the pool for token B, fees included
+    pub volume_a_to_b: u128,
+    /// Token B swapped into the pool for token A, fees included
+    pub volume_b_to_a: u128,
+    /// Fixed fees paid in token A to the fee wallet
+    pub fixed_fees_a: u128,
+    /// Fixed fees paid in token B to the fee wallet
+    pub fixed_fees_b: u128,
+    /// Shares of the fixed fees paid in token A to host accounts
+    pub host_fees_a: u128,
+    /// Shares of the fixed fees paid in token B to host accounts
+    pub host_fees_b: u128,
+    /// Return fees in token A left in the pool for the liquidity providers
+    pub return_fees_a: u128,
+    /// Return fees in token B left in the pool for the liquidity providers
+    pub return_fees_b: u128,
+    /// Number of swaps
+    pub swap_count: u128,
+    /// Number of deposits, of both or of a single token
+    pub deposit_count: u128,
+    /// Number of withdrawals, of both or of a single token
+    pub withdraw_count: u128,
+}
+
+impl PoolStats {
+    /// Records a swap of `amount_in` source tokens paid by the trader, of
+    /// which `fixed_fee` went to the fee wallet, `host_fee` to the host
+    /// account and `return_fee` stays in the pool
+    pub fn record_swap(
+        &mut self,
+        trade_direction: TradeDirection,
+        amount_in: u128,
+        fixed_fee: u128,
+        host_fee: u128,
+        return_fee: u128,
+    ) {
+        let (volume, fixed_fees, host_fees, return_fees) = match trade_direction {
+            TradeDirection::AtoB => (
+                &mut self.volume_a_to_b,
+                &mut self.fixed_fees_a,
+                &mut self.host_fees_a,
+                &mut self.return_fees_a,
+            ),
+            TradeDirection::BtoA => (
+                &mut self.volume_b_to_a,
+                &mut self.fixed_fees_b,
+                &mut self.host_fees_b,
+                &mut self.return_fees_b,
+            ),
+        };
+        *volume = volume.saturating_add(amount_in);
+        *fixed_fees = fixed_fees.saturating_add(fixed_fee);
+        *host_fees = host_fees.saturating_add(host_fee);
+        *return_fees = return_fees.saturating_add(return_fee);
+        self.swap_count = self.swap_count.saturating_add(1);
+    }
+
+    /// Records a deposit into the pool
+    pub fn record_deposit(&mut self) {
+        self.deposit_count = self.deposit_count.saturating_add(1);
+    }
+
+    /// Records a withdrawal from the pool
+    pub fn record_withdraw(&mut self) {
+        self.withdraw_count = self.withdraw_count.saturating_add(1);
+    }
+}
+
+impl Sealed for PoolStats {}
+impl IsInitialized for PoolStats {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Pack for PoolStats {
+    const LEN: usize = 176;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, PoolStats::LEN];
+        let (
+            volume_a_to_b,
+            volume_b_to_a,
+            fixed_fees_a,
+            fixed_fees_b,
+            host_fees_a,
+            host_fees_b,
+            return_fees_a,
+            return_fees_b,
+            swap_count,
+            deposit_count,
+            withdraw_count,
+        ) = mut_array_refs![output, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16];
+        *volume_a_to_b = self.volume_a_to_b.to_le_bytes();
+        *volume_b_to_a = self.volume_b_to_a.to_le_bytes();
+        *fixed_fees_a = self.fixed_fees_a.to_le_bytes();
+        *fixed_fees_b = self.fixed_fees_b.to_le_bytes();
+        *host_fees_a = self.host_fees_a.to_le_bytes();
+        *host_fees_b = self.host_fees_b.to_le_bytes();
+        *return_fees_a = self.return_fees_a.to_le_bytes();
+        *return_fees_b = self.return_fees_b.to_le_bytes();
+        *swap_count = self.swap_count.to_le_bytes();
+        *deposit_count = self.deposit_count.to_le_bytes();
+        *withdraw_count = self.withdraw_count.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, PoolStats::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            volume_a_to_b,
+            volume_b_to_a,
+            fixed_fees_a,
+            fixed_fees_b,
+            host_fees_a,
+            host_fees_b,
+            return_fees_a,
+            return_fees_b,
+            swap_count,
+            deposit_count,
+            withdraw_count,
+        ) = array_refs![input, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16];
+        Ok(Self {
+            volume_a_to_b: u128::from_le_bytes(*volume_a_to_b),
+            volume_b_to_a: u128::from_le_bytes(*volume_b_to_a),
+            fixed_fees_a: u128::from_le_bytes(*fixed_fees_a),
+            fixed_fees_b: u128::from_le_bytes(*fixed_fees_b),
+            host_fees_a: u128::from_le_bytes(*host_fees_a),
+            host_fees_b: u128::from_le_bytes(*host_fees_b),
+            return_fees_a: u128::from_le_bytes(*return_fees_a),
+            return_fees_b: u128::from_le_bytes(*return_fees_b),
+            swap_count: u128::from_le_bytes(*swap_count),
+            deposit_count: u128::from_le_bytes(*deposit_count),
+            withdraw_count: u128::from_le_bytes(*withdraw_count),
+        })
+    }
+}
+
+/// Statistics of swap versions which do not keep them
+const NO_POOL_STATS: PoolStats = PoolStats {
+    volume_a_to_b: 0,
+    volume_b_to_a: 0,
+    fixed_fees_a: 0,
+    fixed_fees_b: 0,
+    host_fees_a: 0,
+    host_fees_b: 0,
+    return_fees_a: 0,
+    return_fees_b: 0,
+    swap_count: 0,
+    deposit_count: 0,
+    withdraw_count: 0,
+};
+
+/// Share of the fixed fee of a swap paid to the host account given with it,
+/// e.g. to the frontend that built the swap
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HostFee {
+    /// Numerator of the share of the fixed fee
+    pub numerator: u64,
+    /// Denominator of the share of the fixed fee, zero if the pool pays no
+    /// host fee
+    pub denominator: u64,
+}
+
+impl HostFee {
+    /// Checks that the share is at most the whole fixed fee
+    pub fn validate(&self) -> Result<(), AmmError> {
+        if self.numerator > self.denominator {
+            Err(AmmError::InvalidFee)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Part of `fixed_fee` paid to the host, rounded down
+    pub fn host_fee(&self, fixed_fee: u128) -> Option<u128> {
+        if self.numerator == 0 || self.denominator == 0 {
+            return Some(0);
+        }
+        fixed_fee
+            .checked_mul(u128::from(self.numerator))?
+            .checked_div(u128::from(self.denominator))
+    }
+}
+
+impl Sealed for HostFee {}
+impl IsInitialized for HostFee {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Pack for HostFee {
+    const LEN: usize = 16;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, HostFee::LEN];
+        let (numerator, denominator) = mut_array_refs![output, 8, 8];
+        *numerator = self.numerator.to_le_bytes();
+        *denominator = self.denominator.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, HostFee::LEN];
+        let (numerator, denominator) = array_refs![input, 8, 8];
+        Ok(Self {
+            numerator: u64::from_le_bytes(*numerator),
+            denominator: u64::from_le_bytes(*denominator),
+        })
+    }
+}
+
+/// Host fee of swap versions which have no room for it
+const NO_HOST_FEE: HostFee = HostFee {
+    numerator: 0,
+    denominator: 0,
+};
+
+/// Uniswap v2 style protocol fee.  Swaps leave their fixed fee in the pool,
+/// and each deposit or withdrawal mints a share of the growth of `sqrt(k)`
+/// since the last one in pool tokens to the protocol fee account.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProtocolFee {
+    /// Numerator of the share of the growth of `sqrt(k)` minted, zero if
+    /// swaps pay their fixed fee to the fixed fee accounts instead
+    pub numerator: u64,
+    /// Denominator of the share of the growth of `sqrt(k)` minted
+    pub denominator: u64,
+    /// Pool token account receiving the minted pool tokens
+    pub fee_account: Pubkey,
+    /// `sqrt(k)` of the reserves after the last deposit or withdrawal, zero
+    /// until the first one with the fee enabled
+    pub root_k_last: u64,
+}
+
+impl ProtocolFee {
+    /// Whether the pool takes the protocol fee instead of fixed fees
+    pub fn is_enabled(&self) -> bool {
+        self.numerator > 0
+    }
+
+    /// Checks that the protocol takes less than the whole growth of `sqrt(k)`
+    pub fn validate(&self) -> Result<(), AmmError> {
+        if self.is_enabled() && self.numerator >= self.denominator {
+            Err(AmmError::InvalidFee)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// `sqrt(k)` of a constant product pool, rounded down
+    pub fn root_k(token_a_amount: u64, token_b_amount: u64) -> u64 {
+        let k = u128::from(token_a_amount) * u128::from(token_b_amount);
+        if k < 2 {
+            return k as u64;
+        }
+        // Newton's method from a power of two above the root
+        let mut root = 1u128 << (64 - k.leading_zeros() / 2);
+        loop {
+            let next = (root + k / root) / 2;
+            if next >= root {
+                return root as u64;
+            }
+            root = next;
+        }
+    }
+
+    /// Pool tokens minted to the protocol out of `pool_supply` when `sqrt(k)`
+    /// has grown to `root_k`, so that they are worth the fee share of the
+    /// growth
+    pub fn protocol_fee(&self, root_k: u64, pool_supply: u128) -> Option<u128> {
+        if !self.is_enabled() || self.root_k_last == 0 || root_k <= self.root_k_last {
+            return Some(0);
+        }
+        let growth = U256::from(root_k - self.root_k_last);
+        let numerator = U256::from(pool_supply)
+            .checked_mul(growth)?
+            .checked_mul(U256::from(self.numerator))?;
+        let denominator = U256::from(self.denominator - self.numerator)
+            .checked_mul(U256::from(root_k))?
+            .checked_add(U256::from(self.numerator).checked_mul(U256::from(self.root_k_last))?)?;
+        Some(numerator.checked_div(denominator)?.low_u128())
+    }
+}
+
+impl Sealed for ProtocolFee {}
+impl IsInitialized for ProtocolFee {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Pack for ProtocolFee {
+    const LEN: usize = 56;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, ProtocolFee::LEN];
+        let (numerator, denominator, fee_account, root_k_last) =
+            mut_array_refs![output, 8, 8, 32, 8];
+        *numerator = self.numerator.to_le_bytes();
+        *denominator = self.denominator.to_le_bytes();
+        fee_account.copy_from_slice(self.fee_account.as_ref());
+        *root_k_last = self.root_k_last.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, ProtocolFee::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (numerator, denominator, fee_account, root_k_last) = array_refs![input, 8, 8, 32, 8];
+        Ok(Self {
+            numerator: u64::from_le_bytes(*numerator),
+            denominator: u64::from_le_bytes(*denominator),
+            fee_account: Pubkey::new_from_array(*fee_account),
+            root_k_last: u64::from_le_bytes(*root_k_last),
+        })
+    }
+}
+
+/// Protocol fee of swap versions which have no room for it
+const NO_PROTOCOL_FEE: ProtocolFee = ProtocolFee {
+    numerator: 0,
+    denominator: 0,
+    fee_account: Pubkey::new_from_array([0; 32]),
+    root_k_last: 0,
+};
+
+/// Program states keeping trading statistics: the SwapV2 state followed by
+/// the statistics and space reserved for later fields
+/// The owner withdraw fee of its fees, which SwapV2 has no room for, is
+/// packed after the host fee.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct SwapV3 {
+    /// State laid out as in SwapV2
+    pub swap: SwapV2,
+    /// Trading statistics
+    pub stats: PoolStats,
+    /// Set while a flash loan of the pool is in progress, every handler
+    /// moving its tokens fails meanwhile
+    pub locked: bool,
+    /// Share of the fixed fee of swaps paid to their host account
+    pub host_fee: HostFee,
+    /// Protocol fee minted in pool tokens on deposits and withdrawals
+    pub protocol_fee: ProtocolFee,
+}
+
+impl SwapV3 {
+    /// Bytes kept free at the end of the state for later fields
+    pub const RESERVED_LEN: usize = 39;
+}
+
+impl AmmStatus for SwapV3 {
+    fn is_initialized(&self) -> bool {
+        self.swap.is_initialized
+    }
+
+    fn nonce(&self) -> u8 {
+        self.swap.nonce
+    }
+
+    fn amm_id(&self) -> &Pubkey {
+        self.swap.amm_id()
+    }
+
+    fn dex_program_id(&self) -> &Pubkey {
+        self.swap.dex_program_id()
+    }
+
+    fn market_id(&self) -> &Pubkey {
+        self.swap.market_id()
+    }
+
+    fn token_program_id(&self) -> &Pubkey {
+        self.swap.token_program_id()
+    }
+
+    fn token_a_account(&self) -> &Pubkey {
+        self.swap.token_a_account()
+    }
+
+    fn token_b_account(&self) -> &Pubkey {
+        self.swap.token_b_account()
+    }
+
+    fn pool_mint(&self) -> &Pubkey {
+        self.swap.pool_mint()
+    }
+
+    fn token_a_mint(&self) -> &Pubkey {
+        self.swap.token_a_mint()
+    }
+
+    fn token_b_mint(&self) -> &Pubkey {
+        self.swap.token_b_mint()
+    }
+
+    fn fixed_fee_account_a(&self) -> &Pubkey {
+        self.swap.fixed_fee_account_a()
+    }
+
+    fn fixed_fee_account_b(&self) -> &Pubkey {
+        self.swap.fixed_fee_account_b()
+    }
+
+    fn fees(&self) -> &Fees {
+        self.swap.fees()
+    }
+
+    fn swap_curve(&self) -> &SwapCurve {
+        self.swap.swap_curve()
+    }
+
+    fn status(&self) -> PoolStatus {
+        self.swap.status()
+    }
+
+    fn locked(&self) -> bool {
+        self.locked
+    }
+
+    fn host_fee(&self) -> &HostFee {
+        &self.host_fee
+    }
+
+    fn protocol_fee(&self) -> &ProtocolFee {
+        &self.protocol_fee
+    }
+
+    fn open_orders(&self) -> &Pubkey {
+        self.swap.open_orders()
+    }
+
+    fn amp_ramp(&self) -> &AmpRamp {
+        self.swap.amp_ramp()
+    }
+
+    fn price_oracle(&self) -> &PriceOracle {
+        self.swap.price_oracle()
+    }
+
+    fn stats(&self) -> &PoolStats {
+        &self.stats
+    }
+
+    fn set_fees(&mut self, fees: Fees) -> Result<(), ProgramError> {
+        self.swap.fees = fees;
+        Ok(())
+    }
+
+    fn set_fixed_fee_accounts(&mut self, fixed_fee_account_a: Pubkey, fixed_fee_account_b: Pubkey) {
+        self.swap
+            .set_fixed_fee_accounts(fixed_fee_account_a, fixed_fee_account_b)
+    }
+
+    fn set_status(&mut self, status: PoolStatus) -> Result<(), ProgramError> {
+        self.swap.set_status(status)
+    }
+
+    fn set_amp_ramp(&mut self, amp_ramp: AmpRamp) -> Result<(), ProgramError> {
+        self.swap.set_amp_ramp(amp_ramp)
+    }
+
+    fn set_locked(&mut self, locked: bool) -> Result<(), ProgramError> {
+        self.locked = locked;
+        Ok(())
+    }
+
+    fn set_host_fee(&mut self, host_fee: HostFee) -> Result<(), ProgramError> {
+        self.host_fee = host_fee;
+        Ok(())
+    }
+
+    fn set_protocol_fee(&mut self, protocol_fee: ProtocolFee) -> Result<(), ProgramError> {
+        self.protocol_fee = protocol_fee;
+        Ok(())
+    }
+}
+
+impl Sealed for SwapV3 {}
+impl IsInitialized for SwapV3 {
+    fn is_initialized(&self) -> bool {
+        self.swap.is_initialized
+    }
+}
+
+impl Pack for SwapV3 {
+    const LEN: usize = SwapV2::LEN
+        + PoolStats::LEN
+        + 1
+        + HostFee::LEN
+        + Fees::OWNER_WITHDRAW_FEE_LEN
+        + ProtocolFee::LEN
+        + SwapV3::RESERVED_LEN;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, SwapV3::LEN];
+        let (swap, stats, locked, host_fee, withdraw_fee, protocol_fee, reserved) = mut_array_refs![
+            output,
+            SwapV2::LEN,
+            PoolStats::LEN,
+            1,
+            HostFee::LEN,
+            Fees::OWNER_WITHDRAW_FEE_LEN,
+            ProtocolFee::LEN,
+            SwapV3::RESERVED_LEN
+        ];
+        self.swap.pack_into_slice(&mut swap[..]);
+        self.stats.pack_into_slice(&mut stats[..]);
+        locked[0] = self.locked as u8;
+        self.host_fee.pack_into_slice(&mut host_fee[..]);
+        self.swap.fees.pack_owner_withdraw_fee(&mut withdraw_fee[..]);
+        self.protocol_fee.pack_into_slice(&mut protocol_fee[..]);
+        *reserved = [0u8; SwapV3::RESERVED_LEN];
+    }
+
+    /// Unpacks a byte buffer into a [SwapV3](struct.SwapV3.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, SwapV3::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (swap, stats, locked, host_fee, withdraw_fee, protocol_fee, _reserved) = array_refs![
+            input,
+            SwapV2::LEN,
+            PoolStats::LEN,
+            1,
+            HostFee::LEN,
+            Fees::OWNER_WITHDRAW_FEE_LEN,
+            ProtocolFee::LEN,
+            SwapV3::RESERVED_LEN
+        ];
+        let mut swap = SwapV2::unpack_from_slice(swap)?;
+        swap.fees.unpack_owner_withdraw_fee(withdraw_fee);
+        Ok(Self {
+            swap,
+            stats: PoolStats::unpack_from_slice(stats)?,
+            locked: match locked {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            host_fee: HostFee::unpack_from_slice(host_fee)?,
+            protocol_fee: ProtocolFee::unpack_from_slice(protocol_fee)?,
+        })
+    }
+}
+
+/// Version byte of a multi token pool account, following the SwapVersion ones
+const MULTI_SWAP_VERSION: u8 = 3;
+
+/// State of a pool holding between `MIN_TOKENS` and `MAX_TOKENS` tokens.
+/// Only the first `token_count` entries of the token arrays are used, the
+/// others are zero.
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct MultiSwap {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Nonce used in program address.
+    /// The program address is created deterministically with the nonce,
+    /// swap program id, and swap account pubkey.  This program address has
+    /// authority over the swap's token accounts and pool token mint.
+    pub nonce: u8,
+
+    /// Program ID of the tokens being exchanged.
+    pub token_program_id: Pubkey,
+
+    /// Pool tokens are issued when tokens are deposited.
+    /// Pool tokens can be withdrawn back to the original tokens.
+    pub pool_mint: Pubkey,
+
+    /// Number of tokens held by the pool
+    pub token_count: u8,
+    /// Token accounts holding the reserves
+    pub token_accounts: [Pubkey; MAX_TOKENS],
+    /// Mint information for each token
+    pub token_mints: [Pubkey; MAX_TOKENS],
+    /// Fixed fee account receiving the fixed fee when swapping from each token
+    pub fixed_fee_accounts: [Pubkey; MAX_TOKENS],
+
+    /// All fee information
+    pub fees: Fees,
+
+    /// Swap curve parameters, to be unpacked and used by the MultiSwapCurve,
+    /// which calculates swaps, deposits, and withdrawals
+    pub swap_curve: MultiSwapCurve,
+
+    /// Trading status, set by the program config admin
+    pub status: PoolStatus,
+
+    /// Statistics of the swaps, deposits and withdrawals of the pool
+    pub stats: MultiPoolStats,
+
+    /// Time weighted average price accumulators, updated by every
+    /// instruction moving the reserves
+    pub price_oracle: MultiPriceOracle,
+}
+
+impl MultiSwap {
+    /// Bytes kept free at the end of the state for later fields
+    pub const RESERVED_LEN: usize = 128;
+
+    /// Size of a multi token pool account, with its version byte
+    pub const ACCOUNT_LEN: usize = 1 + MultiSwap::LEN;
+
+    /// Token accounts holding the reserves, in pool order
+    pub fn reserve_accounts(&self) -> &[Pubkey] {
+        &self.token_accounts[..usize::from(self.token_count)]
+    }
+
+    /// Mints of the pool tokens, in pool order
+    pub fn mints(&self) -> &[Pubkey] {
+        &self.token_mints[..usize::from(self.token_count)]
+    }
+
+    /// Fixed fee accounts of the pool tokens, in pool order
+    pub fn fee_accounts(&self) -> &[Pubkey] {
+        &self.fixed_fee_accounts[..usize::from(self.token_count)]
+    }
+
+    /// Pack a multi token pool into a swap account, after its version byte
+    pub fn pack_account(src: Self, dst: &mut [u8]) -> Result<(), ProgramError> {
+        let (version, dst) = dst
+            .split_first_mut()
+            .ok_or(ProgramError::AccountDataTooSmall)?;
+        *version = MULTI_SWAP_VERSION;
+        let dst = dst
+            .get_mut(..MultiSwap::LEN)
+            .ok_or(ProgramError::AccountDataTooSmall)?;
+        MultiSwap::pack(src, dst)
+    }
+
+    /// Unpack a swap account holding a multi token pool
+    pub fn unpack_account(input: &[u8]) -> Result<Self, ProgramError> {
+        let (&version, rest) = input
+            .split_first()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if version != MULTI_SWAP_VERSION {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        let rest = rest
+            .get(..MultiSwap::LEN)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        MultiSwap::unpack(rest)
+    }
+}
+
+impl Sealed for MultiSwap {}
+impl IsInitialized for MultiSwap {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for MultiSwap {
+    const LEN: usize = 67
+        + 3 * 32 * MAX_TOKENS
+        + Fees::LEN
+        + MultiSwapCurve::LEN
+        + 1
+        + MultiPoolStats::LEN
+        + MultiPriceOracle::LEN
+        + MultiSwap::RESERVED_LEN;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, MultiSwap::LEN];
+        let (
+            is_initialized,
+            nonce,
+            token_program_id,
+            pool_mint,
+            token_count,
+            token_accounts,
+            token_mints,
+            fixed_fee_accounts,
+            fees,
+            swap_curve,
+            status,
+            stats,
+            price_oracle,
+            reserved,
+        ) = mut_array_refs![
+            output,
+            1,
+            1,
+            32,
+            32,
+            1,
+            32 * MAX_TOKENS,
+            32 * MAX_TOKENS,
+            32 * MAX_TOKENS,
+            Fees::LEN,
+            MultiSwapCurve::LEN,
+            1,
+            MultiPoolStats::LEN,
+            MultiPriceOracle::LEN,
+            MultiSwap::RESERVED_LEN
+        ];
+        is_initialized[0] = self.is_initialized as u8;
+        nonce[0] = self.nonce;
+        token_program_id.copy_from_slice(self.token_program_id.as_ref());
+        pool_mint.copy_from_slice(self.pool_mint.as_ref());
+        token_count[0] = self.token_count;
+        pack_pubkeys(&self.token_accounts, token_accounts);
+        pack_pubkeys(&self.token_mints, token_mints);
+        pack_pubkeys(&self.fixed_fee_accounts, fixed_fee_accounts);
+        self.fees.pack_into_slice(&mut fees[..]);
+        self.swap_curve.pack_into_slice(&mut swap_curve[..]);
+        status[0] = self.status as u8;
+        self.stats.pack_into_slice(&mut stats[..]);
+        self.price_oracle.pack_into_slice(&mut price_oracle[..]);
+        *reserved = [0u8; MultiSwap::RESERVED_LEN];
+    }
+
+    /// Unpacks a byte buffer into a [MultiSwap](struct.MultiSwap.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, MultiSwap::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            nonce,
+            token_program_id,
+            pool_mint,
+            token_count,
+            token_accounts,
+            token_mints,
+            fixed_fee_accounts,
+            fees,
+            swap_curve,
+            status,
+            stats,
+            price_oracle,
+            _reserved,
+        ) = array_refs![
+            input,
+            1,
+            1,
+            32,
+            32,
+            1,
+            32 * MAX_TOKENS,
+            32 * MAX_TOKENS,
+            32 * MAX_TOKENS,
+            Fees::LEN,
+            MultiSwapCurve::LEN,
+            1,
+            MultiPoolStats::LEN,
+            MultiPriceOracle::LEN,
+            MultiSwap::RESERVED_LEN
+        ];
+        if usize::from(token_count[0]) > MAX_TOKENS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            nonce: nonce[0],
+            token_program_id: Pubkey::new_from_array(*token_program_id),
+            pool_mint: Pubkey::new_from_array(*pool_mint),
+            token_count: token_count[0],
+            token_accounts: unpack_pubkeys(token_accounts),
+            token_mints: unpack_pubkeys(token_mints),
+            fixed_fee_accounts: unpack_pubkeys(fixed_fee_accounts),
+            fees: Fees::unpack_from_slice(fees)?,
+            swap_curve: MultiSwapCurve::unpack_from_slice(swap_curve)?,
+            status: PoolStatus::try_from(status[0])?,
+            stats: MultiPoolStats::unpack_from_slice(stats)?,
+            price_oracle: MultiPriceOracle::unpack_from_slice(price_oracle)?,
+        })
+    }
+}
+
+/// Trading statistics of a multi token pool, by token in pool order.  The
+/// counters saturate instead of overflowing, like the ones of
+/// [PoolStats](struct.PoolStats.html).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MultiPoolStats {
+    /// Tokens swapped into the pool, fees included
+    pub volumes: [u128; MAX_TOKENS],
+    /// Fixed fees paid to the fee wallet
+    pub fixed_fees: [u128; MAX_TOKENS],
+    /// Return fees left in the pool for the liquidity providers
+    pub return_fees: [u128; MAX_TOKENS],
+    /// Number of swaps
+    pub swap_count: u128,
+    /// Number of deposits, of all tokens or of a single one
+    pub deposit_count: u128,
+    /// Number of withdrawals, of all tokens or of a single one
+    pub withdraw_count: u128,
+}
+
+impl MultiPoolStats {
+    /// Records a swap of `amount_in` tokens of index `source_index` into the
+    /// pool, of which `fixed_fee` went to the fee wallet and `return_fee`
+    /// stays in the pool
+    pub fn record_swap(
+        &mut self,
+        source_index: usize,
+        amount_in: u128,
+        fixed_fee: u128,
+        return_fee: u128,
+    ) {
+        if let Some(volume) = self.volumes.get_mut(source_index) {
+            *volume = volume.saturating_add(amount_in);
+        }
+        if let Some(fixed_fees) = self.fixed_fees.get_mut(source_index) {
+            *fixed_fees = fixed_fees.saturating_add(fixed_fee);
+        }
+        if let Some(return_fees) = self.return_fees.get_mut(source_index) {
+            *return_fees = return_fees.saturating_add(return_fee);
+        }
+        self.swap_count = self.swap_count.saturating_add(1);
+    }
+
+    /// Records a deposit into the pool
+    pub fn record_deposit(&mut self) {
+        self.deposit_count = self.deposit_count.saturating_add(1);
+    }
+
+    /// Records a withdrawal from the pool
+    pub fn record_withdraw(&mut self) {
+        self.withdraw_count = self.withdraw_count.saturating_add(1);
+    }
+}
+
+impl Sealed for MultiPoolStats {}
+impl IsInitialized for MultiPoolStats {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Pack for MultiPoolStats {
+    const LEN: usize = 3 * 16 * MAX_TOKENS + 48;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, MultiPoolStats::LEN];
+        let (volumes, fixed_fees, return_fees, swap_count, deposit_count, withdraw_count) =
+            mut_array_refs![output, 16 * MAX_TOKENS, 16 * MAX_TOKENS, 16 * MAX_TOKENS, 16, 16, 16];
+        pack_amounts(&self.volumes, volumes);
+        pack_amounts(&self.fixed_fees, fixed_fees);
+        pack_amounts(&self.return_fees, return_fees);
+        *swap_count = self.swap_count.to_le_bytes();
+        *deposit_count = self.deposit_count.to_le_bytes();
+        *withdraw_count = self.withdraw_count.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, MultiPoolStats::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (volumes, fixed_fees, return_fees, swap_count, deposit_count, withdraw_count) =
+            array_refs![input, 16 * MAX_TOKENS, 16 * MAX_TOKENS, 16 * MAX_TOKENS, 16, 16, 16];
+        Ok(Self {
+            volumes: unpack_amounts(volumes),
+            fixed_fees: unpack_amounts(fixed_fees),
+            return_fees: unpack_amounts(return_fees),
+            swap_count: u128::from_le_bytes(*swap_count),
+            deposit_count: u128::from_le_bytes(*deposit_count),
+            withdraw_count: u128::from_le_bytes(*withdraw_count),
+        })
+    }
+}
+
+fn pack_amounts(amounts: &[u128; MAX_TOKENS], output: &mut [u8; 16 * MAX_TOKENS]) {
+    for (amount, output) in amounts.iter().zip(output.chunks_exact_mut(16)) {
+        output.copy_from_slice(&amount.to_le_bytes());
+    }
+}
+
+fn unpack_amounts(input: &[u8; 16 * MAX_TOKENS]) -> [u128; MAX_TOKENS] {
+    let mut amounts = [0u128; MAX_TOKENS];
+    for (amount, input) in amounts.iter_mut().zip(input.chunks_exact(16)) {
+        *amount = u128::from_le_bytes(<[u8; 16]>::try_from(input).unwrap());
+    }
+    amounts
+}
+
+fn pack_pubkeys(keys: &[Pubkey; MAX_TOKENS], output: &mut [u8; 32 * MAX_TOKENS]) {
+    for (key, output) in keys.iter().zip(output.chunks_exact_mut(32)) {
+        output.copy_from_slice(key.as_ref());
+    }
+}
+
+fn unpack_pubkeys(input: &[u8; 32 * MAX_TOKENS]) -> [Pubkey; MAX_TOKENS] {
+    let mut keys = [Pubkey::default(); MAX_TOKENS];
+    for (key, input) in keys.iter_mut().zip(input.chunks_exact(32)) {
+        *key = Pubkey::try_from(input).unwrap();
+    }
+    keys
+}
+
+/// Version byte of a concentrated liquidity pool account
+const CONCENTRATED_POOL_VERSION: u8 = 4;
+
+/// Version byte of a concentrated liquidity position account
+const POSITION_VERSION: u8 = 5;
+
+/// Most initialized ticks a concentrated liquidity pool holds
+pub const MAX_TICKS: usize = 64;
+
+/// Widest tick spacing of a concentrated liquidity pool
+pub const MAX_TICK_SPACING: u16 = 16_384;
+
+/// A tick bounding at least one position of a concentrated liquidity pool
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tick {
+    /// Index of the tick, its price is `1.0001^index`
+    pub index: i32,
+    /// Liquidity of all the positions bounded by the tick
+    pub liquidity_gross: u128,
+    /// Liquidity added to the pool when the price crosses the tick going up,
+    /// and removed going down
+    pub liquidity_net: i128,
+    /// Token A fee growth on the other side of the tick from the current
+    /// price
+    pub fee_growth_outside_a: u128,
+    /// Token B fee growth on the other side of the tick from the current
+    /// price
+    pub fee_growth_outside_b: u128,
+}
+
+impl Tick {
+    /// Packed size of a tick
+    pub const LEN: usize = 68;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, Tick::LEN];
+        let (index, liquidity_gross, liquidity_net, fee_growth_outside_a, fee_growth_outside_b) =
+            mut_array_refs![output, 4, 16, 16, 16, 16];
+        *index = self.index.to_le_bytes();
+        *liquidity_gross = self.liquidity_gross.to_le_bytes();
+        *liquidity_net = self.liquidity_net.to_le_bytes();
+        *fee_growth_outside_a = self.fee_growth_outside_a.to_le_bytes();
+        *fee_growth_outside_b = self.fee_growth_outside_b.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, Tick::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (index, liquidity_gross, liquidity_net, fee_growth_outside_a, fee_growth_outside_b) =
+            array_refs![input, 4, 16, 16, 16, 16];
+        Self {
+            index: i32::from_le_bytes(*index),
+            liquidity_gross: u128::from_le_bytes(*liquidity_gross),
+            liquidity_net: i128::from_le_bytes(*liquidity_net),
+            fee_growth_outside_a: u128::from_le_bytes(*fee_growth_outside_a),
+            fee_growth_outside_b: u128::from_le_bytes(*fee_growth_outside_b),
+        }
+    }
+}
+
+/// Amounts of a swap through a concentrated liquidity pool
+#[derive(Debug, PartialEq)]
+pub struct ConcentratedSwapResult {
+    /// Amount of source token swapped, fees included
+    pub source_amount_swapped: u128,
+    /// Amount of destination token paid out
+    pub destination_amount_swapped: u128,
+    /// Fee kept in the pool for the liquidity providers in range
+    pub trade_fee: u128,
+    /// Fixed fee paid to the fixed fee account
+    pub owner_fee: u128,
+}
+
+/// State of a concentrated liquidity pool.  Liquidity providers own
+/// positions over a price range instead of pool tokens, and only the
+/// positions in range at the current price trade.  The initialized ticks
+/// are kept in index order in the first `tick_count` entries of `ticks`.
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct ConcentratedPool {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Nonce used in program address.
+    /// The program address is created deterministically with the nonce,
+    /// swap program id, and swap account pubkey.  This program address has
+    /// authority over the pool's token accounts.
+    pub nonce: u8,
+
+    /// Program ID of the tokens being exchanged.
+    pub token_program_id: Pubkey,
+
+    /// Token A
+    pub token_a: Pubkey,
+    /// Token B
+    pub token_b: Pubkey,
+    /// Mint information for token A
+    pub token_a_mint: Pubkey,
+    /// Mint information for token B
+    pub token_b_mint: Pubkey,
+    /// Fixed fee account receiving the fixed fee when swapping from token A
+    pub fixed_fee_account_a: Pubkey,
+    /// Fixed fee account receiving the fixed fee when swapping from token B
+    pub fixed_fee_account_b: Pubkey,
+
+    /// All fee information
+    pub fees: Fees,
+
+    /// Trading status, set by the program config admin
+    pub status: PoolStatus,
+
+    /// Positions are bounded by multiples of the tick spacing
+    pub tick_spacing: u16,
+    /// Square root of the price of token A in token B, Q64.64
+    pub sqrt_price: u128,
+    /// Highest tick whose price is not above the current price
+    pub tick_current: i32,
+    /// Liquidity of the positions in range
+    pub liquidity: u128,
+    /// Token A trade fees earned per unit of liquidity, Q64.64
+    pub fee_growth_global_a: u128,
+    /// Token B trade fees earned per unit of liquidity, Q64.64
+    pub fee_growth_global_b: u128,
+
+    /// Number of initialized ticks
+    pub tick_count: u8,
+    /// Initialized ticks, in index order
+    pub ticks: [Tick; MAX_TICKS],
+
+    /// Statistics of the swaps, position openings and closings of the pool
+    pub stats: PoolStats,
+
+    /// Time weighted average price accumulators, updated by every swap
+    pub price_oracle: PriceOracle,
+}
+
+impl ConcentratedPool {
+    /// Bytes kept free at the end of the state for later fields
+    pub const RESERVED_LEN: usize = 64;
+
+    /// Size of a concentrated liquidity pool account, with its version byte
+    pub const ACCOUNT_LEN: usize = 1 + ConcentratedPool::LEN;
+
+    /// Pack a concentrated liquidity pool into a swap account, after its
+    /// version byte
+    pub fn pack_account(src: Self, dst: &mut [u8]) -> Result<(), ProgramError> {
+        let (version, dst) = dst
+            .split_first_mut()
+            .ok_or(ProgramError::AccountDataTooSmall)?;
+        *version = CONCENTRATED_POOL_VERSION;
+        let dst = dst
+            .get_mut(..ConcentratedPool::LEN)
+            .ok_or(ProgramError::AccountDataTooSmall)?;
+        ConcentratedPool::pack(src, dst)
+    }
+
+    /// Unpack a swap account holding a concentrated liquidity pool
+    pub fn unpack_account(input: &[u8]) -> Result<Self, ProgramError> {
+        let (&version, rest) = input
+            .split_first()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if version != CONCENTRATED_POOL_VERSION {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        let rest = rest
+            .get(..ConcentratedPool::LEN)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        ConcentratedPool::unpack(rest)
+    }
+
+    /// Initialized ticks, in index order
+    pub fn initialized_ticks(&self) -> &[Tick] {
+        &self.ticks[..usize::from(self.tick_count)]
+    }
+
+    /// The initialized tick at `index`
+    pub fn tick(&self, index: i32) -> Option<&Tick> {
+        let ticks = self.initialized_ticks();
+        ticks
+            .binary_search_by_key(&index, |tick| tick.index)
+            .ok()
+            .map(|position| &ticks[position])
+    }
+
+    /// Checks the bounds of a new position
+    pub fn validate_tick_range(&self, tick_lower: i32, tick_upper: i32) -> Result<(), AmmError> {
+        let tick_spacing = i32::from(self.tick_spacing);
+        if tick_lower >= tick_upper
+            || tick_lower < MIN_TICK
+            || tick_upper > MAX_TICK
+            || tick_lower % tick_spacing != 0
+            || tick_upper % tick_spacing != 0
+        {
+            return Err(AmmError::InvalidTickRange);
+        }
+        Ok(())
+    }
+
+    /// Fee growth of both tokens between two initialized ticks
+    pub fn fee_growth_inside(&self, tick_lower: i32, tick_upper: i32) -> Option<(u128, u128)> {
+        let lower = self.tick(tick_lower)?;
+        let upper = self.tick(tick_upper)?;
+        // fee growth below the lower tick and above the upper tick
+        let (below_a, below_b) = if self.tick_current >= tick_lower {
+            (lower.fee_growth_outside_a, lower.fee_growth_outside_b)
+        } else {
+            (
+                self.fee_growth_global_a
+                    .wrapping_sub(lower.fee_growth_outside_a),
+                self.fee_growth_global_b
+                    .wrapping_sub(lower.fee_growth_outside_b),
+            )
+        };
+        let (above_a, above_b) = if self.tick_current < tick_upper {
+            (upper.fee_growth_outside_a, upper.fee_growth_outside_b)
+        } else {
+            (
+                self.fee_growth_global_a
+                    .wrapping_sub(upper.fee_growth_outside_a),
+                self.fee_growth_global_b
+                    .wrapping_sub(upper.fee_growth_outside_b),
+            )
+        };
+        Some((
+            self.fee_growth_global_a
+                .wrapping_sub(below_a)
+                .wrapping_sub(above_a),
+            self.fee_growth_global_b
+                .wrapping_sub(below_b)
+                .wrapping_sub(above_b),
+        ))
+    }
+
+    /// Adds `liquidity_delta` to a bound of a position, initializing the tick
+    /// if needed and removing it once no position uses it
+    fn update_tick(
+        &mut self,
+        index: i32,
+        liquidity_delta: i128,
+        upper: bool,
+    ) -> Result<(), AmmError> {
+        let tick_count = usize::from(self.tick_count);
+        let position = match self.initialized_ticks().binary_search_by_key(&index, |tick| tick.index) {
+            Ok(position) => position,
+            Err(position) => {
+                if tick_count == MAX_TICKS {
+                    return Err(AmmError::TooManyTicks);
+                }
+                self.ticks.copy_within(position..tick_count, position + 1);
+                // all the fees so far were earned below the tick if the price
+                // is above it
+                self.ticks[position] = if index <= self.tick_current {
+                    Tick {
+                        index,
+                        fee_growth_outside_a: self.fee_growth_global_a,
+                        fee_growth_outside_b: self.fee_growth_global_b,
+                        ..Tick::default()
+                    }
+                } else {
+                    Tick {
+                        index,
+                        ..Tick::default()
+                    }
+                };
+                self.tick_count += 1;
+                position
+            }
+        };
+
+        let tick = &mut self.ticks[position];
+        tick.liquidity_gross = add_liquidity_delta(tick.liquidity_gross, liquidity_delta)?;
+        tick.liquidity_net = if upper {
+            tick.liquidity_net.checked_sub(liquidity_delta)
+        } else {
+            tick.liquidity_net.checked_add(liquidity_delta)
+        }
+        .ok_or(AmmError::CalculationFailure)?;
+        if tick.liquidity_gross == 0 {
+            let tick_count = usize::from(self.tick_count);
+            self.ticks.copy_within(position + 1..tick_count, position);
+            self.ticks[tick_count - 1] = Tick::default();
+            self.tick_count -= 1;
+        }
+        Ok(())
+    }
+
+    /// Adds `liquidity_delta` to a position of the pool, after crediting it
+    /// the fees earned since its last update
+    pub fn update_position(
+        &mut self,
+        position: &mut Position,
+        liquidity_delta: i128,
+    ) -> Result<(), AmmError> {
+        if liquidity_delta > 0 {
+            self.update_tick(position.tick_lower, liquidity_delta, false)?;
+            self.update_tick(position.tick_upper, liquidity_delta, true)?;
+        }
+        let (fee_growth_inside_a, fee_growth_inside_b) = self
+            .fee_growth_inside(position.tick_lower, position.tick_upper)
+            .ok_or(AmmError::InvalidTickRange)?;
+        position.update_fees(fee_growth_inside_a, fee_growth_inside_b)?;
+        if liquidity_delta < 0 {
+            self.update_tick(position.tick_lower, liquidity_delta, false)?;
+            self.update_tick(position.tick_upper, liquidity_delta, true)?;
+        }
+        position.liquidity = add_liquidity_delta(position.liquidity, liquidity_delta)?;
+        if (position.tick_lower..position.tick_upper).contains(&self.tick_current) {
+            self.liquidity = add_liquidity_delta(self.liquidity, liquidity_delta)?;
+        }
+        Ok(())
+    }
+
+    /// Swaps `amount_in` of token A for token B if `a_to_b`, or the reverse,
+    /// crossing initialized ticks until the amount is used.  The fixed fee is
+    /// taken from the amount first, and the trade fee of every step is
+    /// shared by the liquidity in range.
+    pub fn swap(
+        &mut self,
+        amount_in: u128,
+        a_to_b: bool,
+    ) -> Result<ConcentratedSwapResult, AmmError> {
+        let owner_fee = self
+            .fees
+            .fixed_fee(amount_in)
+            .ok_or(AmmError::FeeCalculationFailure)?;
+        let mut amount_remaining = amount_in
+            .checked_sub(owner_fee)
+            .ok_or(AmmError::FeeCalculationFailure)?;
+        let mut amount_out = 0u128;
+        let mut trade_fee = 0u128;
+
+        // every step ends at an initialized tick or uses up the amount, and
+        // the last one ends at the edge of the price range
+        for _ in 0..=usize::from(self.tick_count) + 1 {
+            if amount_remaining == 0 {
+                break;
+            }
+            let next_tick = if a_to_b {
+                self.initialized_ticks()
+                    .iter()
+                    .rev()
+                    .find(|tick| tick.index <= self.tick_current)
+                    .map(|tick| tick.index)
+            } else {
+                self.initialized_ticks()
+                    .iter()
+                    .find(|tick| tick.index > self.tick_current)
+                    .map(|tick| tick.index)
+            };
+            let target_tick = match next_tick {
+                Some(index) => index,
+                None if a_to_b => MIN_TICK,
+                None => MAX_TICK,
+            };
+            let sqrt_price_target =
+                sqrt_price_at_tick(target_tick).ok_or(AmmError::CalculationFailure)?;
+            let step = compute_swap_step(
+                self.sqrt_price,
+                sqrt_price_target,
+                self.liquidity,
+                amount_remaining,
+                self.fees.return_fee_numerator.into(),
+                self.fees.fee_denominator.into(),
+            )
+            .ok_or(AmmError::CalculationFailure)?;
+
+            amount_remaining -= step.amount_in + step.fee_amount;
+            amount_out = amount_out
+                .checked_add(step.amount_out)
+                .ok_or(AmmError::CalculationFailure)?;
+            trade_fee = trade_fee
+                .checked_add(step.fee_amount)
+                .ok_or(AmmError::CalculationFailure)?;
+            if self.liquidity > 0 {
+                let fee_growth = fee_growth(step.fee_amount, self.liquidity);
+                if a_to_b {
+                    self.fee_growth_global_a = self.fee_growth_global_a.wrapping_add(fee_growth);
+                } else {
+                    self.fee_growth_global_b = self.fee_growth_global_b.wrapping_add(fee_growth);
+                }
+            }
+
+            let start_tick = self.tick_current;
+            self.sqrt_price = step.sqrt_price_next;
+            if step.sqrt_price_next == sqrt_price_target {
+                if next_tick.is_none() {
+                    return Err(AmmError::InsufficientLiquidity);
+                }
+                self.cross_tick(target_tick, a_to_b)?;
+                self.tick_current = if a_to_b { target_tick - 1 } else { target_tick };
+            } else {
+                let (lower_tick, upper_tick) = if a_to_b {
+                    (target_tick, start_tick)
+                } else {
+                    (start_tick, target_tick)
+                };
+                self.tick_current = tick_at_sqrt_price(self.sqrt_price, lower_tick, upper_tick)
+                    .ok_or(AmmError::CalculationFailure)?;
+            }
+        }
+        if amount_remaining > 0 {
+            return Err(AmmError::InsufficientLiquidity);
+        }
+
+        Ok(ConcentratedSwapResult {
+            source_amount_swapped: amount_in,
+            destination_amount_swapped: amount_out,
+            trade_fee,
+            owner_fee,
+        })
+    }
+
+    /// Moves the price across an initialized tick: the fees outside of it
+    /// flip side and the liquidity of the positions it bounds enters or
+    /// leaves the range
+    fn cross_tick(&mut self, index: i32, a_to_b: bool) -> Result<(), AmmError> {
+        let (fee_growth_global_a, fee_growth_global_b) =
+            (self.fee_growth_global_a, self.fee_growth_global_b);
+        let tick_count = usize::from(self.tick_count);
+        let tick = self.ticks[..tick_count]
+            .iter_mut()
+            .find(|tick| tick.index == index)
+            .ok_or(AmmError::CalculationFailure)?;
+        tick.fee_growth_outside_a = fee_growth_global_a.wrapping_sub(tick.fee_growth_outside_a);
+        tick.fee_growth_outside_b = fee_growth_global_b.wrapping_sub(tick.fee_growth_outside_b);
+        let liquidity_delta = if a_to_b {
+            tick.liquidity_net
+                .checked_neg()
+                .ok_or(AmmError::CalculationFailure)?
+        } else {
+            tick.liquidity_net
+        };
+        self.liquidity = add_liquidity_delta(self.liquidity, liquidity_delta)?;
+        Ok(())
+    }
+}
+
+/// Q64.64 fee growth per unit of liquidity, wrapping like the fee growth
+/// totals it is added to
+fn fee_growth(fee_amount: u128, liquidity: u128) -> u128 {
+    ((U256::from(fee_amount) << 64) / U256::from(liquidity)).low_u128()
+}
+
+fn add_liquidity_delta(liquidity: u128, liquidity_delta: i128) -> Result<u128, AmmError> {
+    let magnitude = liquidity_delta.unsigned_abs();
+    if liquidity_delta < 0 {
+        liquidity.checked_sub(magnitude)
+    } else {
+        liquidity.checked_add(magnitude)
+    }
+    .ok_or(AmmError::CalculationFailure)
+}
+
+impl Sealed for ConcentratedPool {}
+impl IsInitialized for ConcentratedPool {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for ConcentratedPool {
+    const LEN: usize = 226
+        + Fees::LEN
+        + 72
+        + Tick::LEN * MAX_TICKS
+        + PoolStats::LEN
+        + PriceOracle::LEN
+        + ConcentratedPool::RESERVED_LEN;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, ConcentratedPool::LEN];
+        let (
+            is_initialized,
+            nonce,
+            token_program_id,
+            token_a,
+            token_b,
+            token_a_mint,
+            token_b_mint,
+            fixed_fee_account_a,
+            fixed_fee_account_b,
+            fees,
+            status,
+            tick_spacing,
+            sqrt_price,
+            tick_current,
+            liquidity,
+            fee_growth_global_a,
+            fee_growth_global_b,
+            tick_count,
+            ticks,
+            stats,
+            price_oracle,
+            reserved,
+        ) = mut_array_refs![
+            output,
+            1,
+            1,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            Fees::LEN,
+            1,
+            2,
+            16,
+            4,
+            16,
+            16,
+            16,
+            1,
+            Tick::LEN * MAX_TICKS,
+            PoolStats::LEN,
+            PriceOracle::LEN,
+            ConcentratedPool::RESERVED_LEN
+        ];
+        is_initialized[0] = self.is_initialized as u8;
+        nonce[0] = self.nonce;
+        token_program_id.copy_from_slice(self.token_program_id.as_ref());
+        token_a.copy_from_slice(self.token_a.as_ref());
+        token_b.copy_from_slice(self.token_b.as_ref());
+        token_a_mint.copy_from_slice(self.token_a_mint.as_ref());
+        token_b_mint.copy_from_slice(self.token_b_mint.as_ref());
+        fixed_fee_account_a.copy_from_slice(self.fixed_fee_account_a.as_ref());
+        fixed_fee_account_b.copy_from_slice(self.fixed_fee_account_b.as_ref());
+        self.fees.pack_into_slice(&mut fees[..]);
+        status[0] = self.status as u8;
+        *tick_spacing = self.tick_spacing.to_le_bytes();
+        *sqrt_price = self.sqrt_price.to_le_bytes();
+        *tick_current = self.tick_current.to_le_bytes();
+        *liquidity = self.liquidity.to_le_bytes();
+        *fee_growth_global_a = self.fee_growth_global_a.to_le_bytes();
+        *fee_growth_global_b = self.fee_growth_global_b.to_le_bytes();
+        tick_count[0] = self.tick_count;
+        for (tick, output) in self.ticks.iter().zip(ticks.chunks_exact_mut(Tick::LEN)) {
+            tick.pack_into_slice(output);
+        }
+        self.stats.pack_into_slice(&mut stats[..]);
+        self.price_oracle.pack_into_slice(&mut price_oracle[..]);
+        *reserved = [0u8; ConcentratedPool::RESERVED_LEN];
+    }
+
+    /// Unpacks a byte buffer into a [ConcentratedPool](struct.ConcentratedPool.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, ConcentratedPool::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            nonce,
+            token_program_id,
+            token_a,
+            token_b,
+            token_a_mint,
+            token_b_mint,
+            fixed_fee_account_a,
+            fixed_fee_account_b,
+            fees,
+            status,
+            tick_spacing,
+            sqrt_price,
+            tick_current,
+            liquidity,
+            fee_growth_global_a,
+            fee_growth_global_b,
+            tick_count,
+            ticks_input,
+            stats,
+            price_oracle,
+            _reserved,
+        ) = array_refs![
+            input,
+            1,
+            1,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            Fees::LEN,
+            1,
+            2,
+            16,
+            4,
+            16,
+            16,
+            16,
+            1,
+            Tick::LEN * MAX_TICKS,
+            PoolStats::LEN,
+            PriceOracle::LEN,
+            ConcentratedPool::RESERVED_LEN
+        ];
+        if usize::from(tick_count[0]) > MAX_TICKS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut ticks = [Tick::default(); MAX_TICKS];
+        for (tick, input) in ticks.iter_mut().zip(ticks_input.chunks_exact(Tick::LEN)) {
+            *tick = Tick::unpack_from_slice(input);
+        }
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            nonce: nonce[0],
+            token_program_id: Pubkey::new_from_array(*token_program_id),
+            token_a: Pubkey::new_from_array(*token_a),
+            token_b: Pubkey::new_from_array(*token_b),
+            token_a_mint: Pubkey::new_from_array(*token_a_mint),
+            token_b_mint: Pubkey::new_from_array(*token_b_mint),
+            fixed_fee_account_a: Pubkey::new_from_array(*fixed_fee_account_a),
+            fixed_fee_account_b: Pubkey::new_from_array(*fixed_fee_account_b),
+            fees: Fees::unpack_from_slice(fees)?,
+            status: PoolStatus::try_from(status[0])?,
+            tick_spacing: u16::from_le_bytes(*tick_spacing),
+            sqrt_price: u128::from_le_bytes(*sqrt_price),
+            tick_current: i32::from_le_bytes(*tick_current),
+            liquidity: u128::from_le_bytes(*liquidity),
+            fee_growth_global_a: u128::from_le_bytes(*fee_growth_global_a),
+            fee_growth_global_b: u128::from_le_bytes(*fee_growth_global_b),
+            tick_count: tick_count[0],
+            ticks,
+            stats: PoolStats::unpack_from_slice(stats)?,
+            price_oracle: PriceOracle::unpack_from_slice(price_oracle)?,
+        })
+    }
+}
+
+/// Liquidity of one provider in a concentrated liquidity pool, between two
+/// ticks.  Kept in its own account, owned by the program.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct Position {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Concentrated liquidity pool of the position
+    pub pool: Pubkey,
+    /// Key allowed to collect the fees and close the position
+    pub owner: Pubkey,
+    /// Lower bound of the price range
+    pub tick_lower: i32,
+    /// Upper bound of the price range
+    pub tick_upper: i32,
+    /// Liquidity provided over the range
+    pub liquidity: u128,
+    /// Token A fee growth inside the range at the last update, Q64.64
+    pub fee_growth_inside_a: u128,
+    /// Token B fee growth inside the range at the last update, Q64.64
+    pub fee_growth_inside_b: u128,
+    /// Token A fees earned and not yet collected
+    pub fees_owed_a: u64,
+    /// Token B fees earned and not yet collected
+    pub fees_owed_b: u64,
+}
+
+impl Position {
+    /// Size of a position account, with its version byte
+    pub const ACCOUNT_LEN: usize = 1 + Position::LEN;
+
+    /// Pack a position into its account, after its version byte
+    pub fn pack_account(src: Self, dst: &mut [u8]) -> Result<(), ProgramError> {
+        let (version, dst) = dst
+            .split_first_mut()
+            .ok_or(ProgramError::AccountDataTooSmall)?;
+        *version = POSITION_VERSION;
+        let dst = dst
+            .get_mut(..Position::LEN)
+            .ok_or(ProgramError::AccountDataTooSmall)?;
+        Position::pack(src, dst)
+    }
+
+    /// Unpack a position account
+    pub fn unpack_account(input: &[u8]) -> Result<Self, ProgramError> {
+        let (&version, rest) = input
+            .split_first()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if version != POSITION_VERSION {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        let rest = rest
+            .get(..Position::LEN)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        Position::unpack(rest)
+    }
+
+    /// Credits the fees earned by the liquidity since the last update, from
+    /// the current fee growth inside the range
+    pub fn update_fees(
+        &mut self,
+        fee_growth_inside_a: u128,
+        fee_growth_inside_b: u128,
+    ) -> Result<(), AmmError> {
+        let earned = |fee_growth_inside: u128, fee_growth_inside_last: u128| {
+            let fee_growth = fee_growth_inside.wrapping_sub(fee_growth_inside_last);
+            let fees = U256::from(self.liquidity)
+                .checked_mul(U256::from(fee_growth))
+                .ok_or(AmmError::CalculationFailure)?
+                >> 64;
+            u64::try_from(fees).map_err(|_| AmmError::CalculationFailure)
+        };
+        let earned_a = earned(fee_growth_inside_a, self.fee_growth_inside_a)?;
+        let earned_b = earned(fee_growth_inside_b, self.fee_growth_inside_b)?;
+        self.fees_owed_a = self
+            .fees_owed_a
+            .checked_add(earned_a)
+            .ok_or(AmmError::CalculationFailure)?;
+        self.fees_owed_b = self
+            .fees_owed_b
+            .checked_add(earned_b)
+            .ok_or(AmmError::CalculationFailure)?;
+        self.fee_growth_inside_a = fee_growth_inside_a;
+        self.fee_growth_inside_b = fee_growth_inside_b;
+        Ok(())
+    }
+}
+
+impl Sealed for Position {}
+impl IsInitialized for Position {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Position {
+    const LEN: usize = 137;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, Position::LEN];
+        let (
+            is_initialized,
+            pool,
+            owner,
+            tick_lower,
+            tick_upper,
+            liquidity,
+            fee_growth_inside_a,
+            fee_growth_inside_b,
+            fees_owed_a,
+            fees_owed_b,
+        ) = mut_array_refs![output, 1, 32, 32, 4, 4, 16, 16, 16, 8, 8];
+        is_initialized[0] = self.is_initialized as u8;
+        pool.copy_from_slice(self.pool.as_ref());
+        owner.copy_from_slice(self.owner.as_ref());
+        *tick_lower = self.tick_lower.to_le_bytes();
+        *tick_upper = self.tick_upper.to_le_bytes();
+        *liquidity = self.liquidity.to_le_bytes();
+        *fee_growth_inside_a = self.fee_growth_inside_a.to_le_bytes();
+        *fee_growth_inside_b = self.fee_growth_inside_b.to_le_bytes();
+        *fees_owed_a = self.fees_owed_a.to_le_bytes();
+        *fees_owed_b = self.fees_owed_b.to_le_bytes();
+    }
+
+    /// Unpacks a byte buffer into a [Position](struct.Position.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, Position::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            pool,
+            owner,
+            tick_lower,
+            tick_upper,
+            liquidity,
+            fee_growth_inside_a,
+            fee_growth_inside_b,
+            fees_owed_a,
+            fees_owed_b,
+        ) = array_refs![input, 1, 32, 32, 4, 4, 16, 16, 16, 8, 8];
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            pool: Pubkey::new_from_array(*pool),
+            owner: Pubkey::new_from_array(*owner),
+            tick_lower: i32::from_le_bytes(*tick_lower),
+            tick_upper: i32::from_le_bytes(*tick_upper),
+            liquidity: u128::from_le_bytes(*liquidity),
+            fee_growth_inside_a: u128::from_le_bytes(*fee_growth_inside_a),
+            fee_growth_inside_b: u128::from_le_bytes(*fee_growth_inside_b),
+            fees_owed_a: u64::from_le_bytes(*fees_owed_a),
+            fees_owed_b: u64::from_le_bytes(*fees_owed_b),
+        })
+    }
+}
+
+/// Seed used to derive the address of the program config account
+pub const CONFIG_SEED: &[u8] = b"config";
+
+/// Program-wide settings, stored in the account at the program address
+/// derived from [CONFIG_SEED](constant.CONFIG_SEED.html).
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct ProgramConfig {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Canonical bump used to derive the config account address
+    pub nonce: u8,
+    /// Key allowed to change the config
+    pub admin: Pubkey,
+    /// Wallet owning the fixed fee accounts, receives SOL fees directly
+    pub fee_wallet: Pubkey,
+}
+
+impl Sealed for ProgramConfig {}
+impl IsInitialized for ProgramConfig {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for ProgramConfig {
+    const LEN: usize = 66;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, ProgramConfig::LEN];
+        let (is_initialized, nonce, admin, fee_wallet) = mut_array_refs![output, 1, 1, 32, 32];
+        is_initialized[0] = self.is_initialized as u8;
+        nonce[0] = self.nonce;
+        admin.copy_from_slice(self.admin.as_ref());
+        fee_wallet.copy_from_slice(self.fee_wallet.as_ref());
+    }
+
+    /// Unpacks a byte buffer into a [ProgramConfig](struct.ProgramConfig.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, ProgramConfig::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (is_initialized, nonce, admin, fee_wallet) = array_refs![input, 1, 1, 32, 32];
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            nonce: nonce[0],
+            admin: Pubkey::new_from_array(*admin),
+            fee_wallet: Pubkey::new_from_array(*fee_wallet),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::curve::{
+        base::CurveType, constant_product::ConstantProductCurve, multi::MultiWeightedCurve,
+    };
+
+    use std::convert::TryInto;
+
+    const TEST_FEES: Fees = Fees {
+        return_fee_numerator: 25,
+        fixed_fee_numerator: 5,
+        fee_denominator: 10000,
+        owner_withdraw_fee_numerator: 0,
+        owner_withdraw_fee_denominator: 0,
+    };
+
+    const TEST_NONCE: u8 = 255;
+    const TEST_AMM_ID: Pubkey = Pubkey::new_from_array([1u8; 32]);
+    const TEST_DEX_PROGRAM_ID: Pubkey = Pubkey::new_from_array([2u8; 32]);
+    const TEST_MARKET_ID: Pubkey = Pubkey::new_from_array([3u8; 32]);
+    const TEST_TOKEN_PROGRAM_ID: Pubkey = Pubkey::new_from_array([4u8; 32]);
+    const TEST_TOKEN_A: Pubkey = Pubkey::new_from_array([5u8; 32]);
+    const TEST_TOKEN_B: Pubkey = Pubkey::new_from_array([6u8; 32]);
+    const TEST_POOL_MINT: Pubkey = Pubkey::new_from_array([7u8; 32]);
+    const TEST_TOKEN_A_MINT: Pubkey = Pubkey::new_from_array([8u8; 32]);
+    const TEST_TOKEN_B_MINT: Pubkey = Pubkey::new_from_array([9u8; 32]);
+    const TEST_FIXED_FEE_ACCOUNT_A: Pubkey = Pubkey::new_from_array([10u8; 32]);
+    const TEST_FIXED_FEE_ACCOUNT_B: Pubkey = Pubkey::new_from_array([11u8; 32]);
+
+    const TEST_CURVE_TYPE: u8 = 0;
+
+    fn test_swap_info() -> SwapV1 {
+        let curve_type = TEST_CURVE_TYPE.try_into().unwrap();
+        let calculator = Box::new(ConstantProductCurve {});
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator,
+        };
+        SwapV1 {
+            is_initialized: true,
+            nonce: TEST_NONCE,
+            amm_id: TEST_AMM_ID,
+            dex_program_id: TEST_DEX_PROGRAM_ID,
+            market_id: TEST_MARKET_ID,
+            token_program_id: TEST_TOKEN_PROGRAM_ID,
+            token_a: TEST_TOKEN_A,
+            token_b: TEST_TOKEN_B,
+            pool_mint: TEST_POOL_MINT,
+            token_a_mint: TEST_TOKEN_A_MINT,
+            token_b_mint: TEST_TOKEN_B_MINT,
+            fixed_fee_account_a: TEST_FIXED_FEE_ACCOUNT_A,
+            fixed_fee_account_b: TEST_FIXED_FEE_ACCOUNT_B,
+            fees: TEST_FEES,
+            swap_curve,
+        }
+    }
+
+    fn test_swap_v2_info() -> SwapV2 {
+        let swap_info = test_swap_info();
+        SwapV2 {
+            is_initialized: swap_info.is_initialized,
+            nonce: swap_info.nonce,
+            amm_id: swap_info.amm_id,
+            dex_program_id: swap_info.dex_program_id,
+            market_id: swap_info.market_id,
+            token_program_id: swap_info.token_program_id,
+            token_a: swap_info.token_a,
+            token_b: swap_info.token_b,
+            pool_mint: swap_info.pool_mint,
+            token_a_mint: swap_info.token_a_mint,
+            token_b_mint: swap_info.token_b_mint,
+            fixed_fee_account_a: swap_info.fixed_fee_account_a,
+            fixed_fee_account_b: swap_info.fixed_fee_account_b,
+            fees: swap_info.fees,
+            swap_curve: swap_info.swap_curve,
+            status: PoolStatus::WithdrawOnly,
+            open_orders: Pubkey::new_unique(),
+            amp_ramp: AmpRamp {
+                initial_amp: 100,
+                target_amp: 200,
+                start_ramp_ts: 1_600_000_000,
+                stop_ramp_ts: 1_600_086_400,
+            },
+            price_oracle: PriceOracle {
+                price_a_cumulative: u128::MAX,
+                price_b_cumulative: 1 << 70,
+                last_update_ts: 1_600_000_000,
+                observation_index: 1,
+                observations: [Observation {
+                    unix_timestamp: 1_599_999_000,
+                    price_a_cumulative: 1 << 80,
+                    price_b_cumulative: 1 << 60,
+                }; OBSERVATIONS],
+            },
+        }
+    }
+
+    #[test]
+    fn swap_version_pack() {
+        let swap_info = SwapVersion::SwapV1(test_swap_info());
+
+        let mut packed = [0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(swap_info, &mut packed).unwrap();
+        let unpacked = SwapVersion::unpack(&packed).unwrap();
+
+        assert!(unpacked.is_initialized());
+        assert_eq!(unpacked.nonce(), TEST_NONCE);
+        assert_eq!(*unpacked.amm_id(), TEST_AMM_ID);
+        assert_eq!(*unpacked.dex_program_id(), TEST_DEX_PROGRAM_ID);
+        assert_eq!(*unpacked.market_id(), TEST_MARKET_ID);
+        assert_eq!(*unpacked.token_program_id(), TEST_TOKEN_PROGRAM_ID);
+        assert_eq!(*unpacked.token_a_account(), TEST_TOKEN_A);
+        assert_eq!(*unpacked.token_b_account(), TEST_TOKEN_B);
+        assert_eq!(*unpacked.pool_mint(), TEST_POOL_MINT);
+        assert_eq!(*unpacked.token_a_mint(), TEST_TOKEN_A_MINT);
+        assert_eq!(*unpacked.token_b_mint(), TEST_TOKEN_B_MINT);
+        assert_eq!(*unpacked.fixed_fee_account_a(), TEST_FIXED_FEE_ACCOUNT_A);
+        assert_eq!(*unpacked.fixed_fee_account_b(), TEST_FIXED_FEE_ACCOUNT_B);
+        assert_eq!(*unpacked.fees(), TEST_FEES);
+        assert_eq!(
+            unpacked.swap_curve().curve_type,
+            TEST_CURVE_TYPE.try_into().unwrap()
+        );
+    }
+
+    #[test]
+    fn swap_version_upgrade() {
+        let swap_info = test_swap_info();
+        let swap_v3 = SwapVersion::SwapV1(test_swap_info()).upgrade(1_000);
+        assert_eq!(swap_v3.swap.amm_id, swap_info.amm_id);
+        assert_eq!(swap_v3.swap.pool_mint, swap_info.pool_mint);
+        assert_eq!(swap_v3.swap.fixed_fee_account_b, swap_info.fixed_fee_account_b);
+        assert_eq!(swap_v3.swap.fees, swap_info.fees);
+        assert_eq!(swap_v3.swap.swap_curve, swap_info.swap_curve);
+        assert_eq!(swap_v3.swap.status, PoolStatus::Active);
+        assert_eq!(swap_v3.swap.price_oracle, PriceOracle::new(1_000));
+        assert_eq!(swap_v3.stats, PoolStats::default());
+
+        let swap_v2 = test_swap_v2_info();
+        let open_orders = swap_v2.open_orders;
+        let price_oracle = swap_v2.price_oracle;
+        let swap_v3 = SwapVersion::SwapV2(swap_v2).upgrade(1_000);
+        assert_eq!(swap_v3.swap.open_orders, open_orders);
+        assert_eq!(swap_v3.swap.price_oracle, price_oracle);
+        assert_eq!(swap_v3.swap.status, PoolStatus::WithdrawOnly);
+        assert_eq!(swap_v3.host_fee, HostFee::default());
+    }
+
+    #[test]
+    fn swap_v1_pack() {
+        let swap_info = test_swap_info();
+
+        let mut packed = [0u8; SwapV1::LEN];
+        SwapV1::pack_into_slice(&swap_info, &mut packed);
+        let unpacked = SwapV1::unpack(&packed).unwrap();
+        assert_eq!(swap_info, unpacked);
+
+        let mut packed = vec![1u8, TEST_NONCE];
+        packed.extend_from_slice(&TEST_AMM_ID.to_bytes());
+        packed.extend_from_slice(&TEST_DEX_PROGRAM_ID.to_bytes());
+        packed.extend_from_slice(&TEST_MARKET_ID.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_PROGRAM_ID.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_A.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_B.to_bytes());
+        packed.extend_from_slice(&TEST_POOL_MINT.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_A_MINT.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_B_MINT.to_bytes());
+        packed.extend_from_slice(&TEST_FIXED_FEE_ACCOUNT_A.to_bytes());
+        packed.extend_from_slice(&TEST_FIXED_FEE_ACCOUNT_B.to_bytes());
+        let mut fees = [0u8; Fees::LEN];
+        TEST_FEES.pack_into_slice(&mut fees);
+        packed.extend_from_slice(&fees);
+        let mut swap_curve = [0u8; SwapCurve::LEN];
+        swap_info.swap_curve.pack_into_slice(&mut swap_curve);
+        packed.extend_from_slice(&swap_curve);
+        let unpacked = SwapV1::unpack(&packed).unwrap();
+        assert_eq!(swap_info, unpacked);
+
+        let packed = [0u8; SwapV1::LEN];
+        let swap_info: SwapV1 = Default::default();
+        let unpack_unchecked = SwapV1::unpack_unchecked(&packed).unwrap();
+        assert_eq!(unpack_unchecked, swap_info);
+        let err = SwapV1::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::UninitializedAccount);
+    }
+
+    #[test]
+    fn swap_v2_pack() {
+        let swap_info = test_swap_v2_info();
+
+        let mut packed = [0u8; SwapV2::LEN];
+        SwapV2::pack_into_slice(&swap_info, &mut packed);
+        let unpacked = SwapV2::unpack(&packed).unwrap();
+        assert_eq!(swap_info, unpacked);
+
+        // V2 extends the V1 layout with the status, the open orders, the
+        // amplification ramp, the price oracle and zeroed reserved space
+        let mut v1_packed = [0u8; SwapV1::LEN];
+        SwapV1::pack_into_slice(&test_swap_info(), &mut v1_packed);
+        assert_eq!(packed[..SwapV1::LEN], v1_packed[..]);
+        assert_eq!(packed[SwapV1::LEN], PoolStatus::WithdrawOnly as u8);
+        assert_eq!(
+            packed[SwapV1::LEN + 1..SwapV1::LEN + 33],
+            swap_info.open_orders.to_bytes()
+        );
+        let mut amp_ramp = [0u8; AmpRamp::LEN];
+        swap_info.amp_ramp.pack_into_slice(&mut amp_ramp);
+        assert_eq!(packed[SwapV1::LEN + 33..SwapV1::LEN + 65], amp_ramp);
+        let mut price_oracle = [0u8; PriceOracle::LEN];
+        swap_info.price_oracle.pack_into_slice(&mut price_oracle);
+        let oracle_end = SwapV1::LEN + 65 + PriceOracle::LEN;
+        assert_eq!(packed[SwapV1::LEN + 65..oracle_end], price_oracle[..]);
+        assert!(packed[oracle_end..].iter().all(|b| *b == 0));
+
+        packed[SwapV1::LEN] = u8::MAX;
+        assert_eq!(
+            SwapV2::unpack(&packed).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+
+    #[test]
+    fn swap_version_v1_account() {
+        // swaps created before SwapV2 keep their original account size
+        let mut packed = [0u8; 1 + SwapV1::LEN];
+        SwapVersion::pack(SwapVersion::SwapV1(test_swap_info()), &mut packed).unwrap();
+        assert!(SwapVersion::is_initialized(&packed));
+        let unpacked = SwapVersion::unpack(&packed).unwrap();
+        assert_eq!(*unpacked.pool_mint(), TEST_POOL_MINT);
+        assert_eq!(unpacked.status(), PoolStatus::Active);
+
+        let mut swap_version = SwapVersion::unpack_version(&packed).unwrap();
+        assert_eq!(
+            swap_version.set_status(PoolStatus::Halted),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        assert!(!swap_version.amp_ramp().is_set());
+        assert_eq!(*swap_version.price_oracle(), PriceOracle::default());
+        assert_eq!(
+            swap_version.set_amp_ramp(AmpRamp::default()),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+
+        assert_eq!(
+            SwapVersion::pack(
+                SwapVersion::SwapV2(test_swap_v2_info()),
+                &mut packed
+            ),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+    }
+
+    #[test]
+    fn swap_v3_pack() {
+        let swap_v2_info = test_swap_v2_info();
+        let mut v2_packed = [0u8; SwapV2::LEN];
+        SwapV2::pack_into_slice(&swap_v2_info, &mut v2_packed);
+        let swap_info = SwapV3 {
+            swap: swap_v2_info,
+            stats: PoolStats {
+                volume_a_to_b: u128::MAX,
+                volume_b_to_a: 1,
+                fixed_fees_a: 2,
+                fixed_fees_b: 3,
+                host_fees_a: 9,
+                host_fees_b: 10,
+                return_fees_a: 4,
+                return_fees_b: 5,
+                swap_count: 6,
+                deposit_count: 7,
+                withdraw_count: 8,
+            },
+            locked: false,
+            host_fee: HostFee::default(),
+            protocol_fee: ProtocolFee::default(),
+        };
+
+        let mut packed = [0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(SwapVersion::SwapV3(swap_info), &mut packed).unwrap();
+        assert_eq!(packed[0], SWAP_V3_VERSION);
+        assert!(SwapVersion::is_initialized(&packed));
+        let unpacked = SwapVersion::unpack(&packed).unwrap();
+        assert_eq!(unpacked.status(), PoolStatus::WithdrawOnly);
+        assert_eq!(unpacked.stats().volume_a_to_b, u128::MAX);
+        assert_eq!(unpacked.stats().withdraw_count, 8);
+
+        // V3 extends the V2 layout with the statistics and zeroed reserved
+        // space
+        assert_eq!(packed[1..1 + SwapV2::LEN], v2_packed[..]);
+        let stats_end = 1 + SwapV2::LEN + PoolStats::LEN;
+        assert!(packed[stats_end..].iter().all(|b| *b == 0));
+
+        let mut swap_version = SwapVersion::unpack_version(&packed).unwrap();
+        assert!(!swap_version.locked());
+        swap_version.set_locked(true).unwrap();
+        SwapVersion::pack(swap_version, &mut packed).unwrap();
+        assert_eq!(packed[stats_end], 1);
+        assert!(SwapVersion::unpack(&packed).unwrap().locked());
+        packed[stats_end] = 2;
+        assert_eq!(
+            SwapVersion::unpack(&packed).err(),
+            Some(ProgramError::InvalidAccountData)
+        );
+        packed[stats_end] = 0;
+
+        let host_fee = HostFee {
+            numerator: 20,
+            denominator: 100,
+        };
+        let mut swap_version = SwapVersion::unpack_version(&packed).unwrap();
+        assert_eq!(*swap_version.host_fee(), HostFee::default());
+        swap_version.set_host_fee(host_fee).unwrap();
+        SwapVersion::pack(swap_version, &mut packed).unwrap();
+        assert_eq!(packed[stats_end + 1..stats_end + 9], 20u64.to_le_bytes());
+        assert_eq!(*SwapVersion::unpack(&packed).unwrap().host_fee(), host_fee);
+
+        // the owner withdraw fee follows the host fee
+        let fees = Fees {
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 100,
+            ..TEST_FEES
+        };
+        let mut swap_version = SwapVersion::unpack_version(&packed).unwrap();
+        assert!(!swap_version.fees().has_owner_withdraw_fee());
+        swap_version.set_fees(fees.clone()).unwrap();
+        SwapVersion::pack(swap_version, &mut packed).unwrap();
+        assert_eq!(packed[stats_end + 17..stats_end + 25], 1u64.to_le_bytes());
+        assert_eq!(packed[1..1 + SwapV2::LEN], v2_packed[..]);
+        let unpacked = SwapVersion::unpack(&packed).unwrap();
+        assert_eq!(*unpacked.fees(), fees);
+        assert_eq!(*unpacked.host_fee(), host_fee);
+
+        let protocol_fee = ProtocolFee {
+            numerator: 1,
+            denominator: 6,
+            fee_account: Pubkey::new_unique(),
+            root_k_last: 1_000_000,
+        };
+        let mut swap_version = SwapVersion::unpack_version(&packed).unwrap();
+        swap_version.set_protocol_fee(protocol_fee).unwrap();
+        SwapVersion::pack(swap_version, &mut packed).unwrap();
+        let unpacked = SwapVersion::unpack(&packed).unwrap();
+        assert_eq!(*unpacked.protocol_fee(), protocol_fee);
+        assert_eq!(*unpacked.fees(), fees);
+
+        // older versions keep no statistics, can not be locked and take no
+        // host, withdraw or protocol fee
+        let mut packed = [0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(SwapVersion::SwapV2(test_swap_v2_info()), &mut packed).unwrap();
+        let mut swap_version = SwapVersion::unpack_version(&packed).unwrap();
+        assert_eq!(*swap_version.stats(), PoolStats::default());
+        assert!(swap_version.stats_mut().is_none());
+        assert!(swap_version.price_oracle_mut().is_some());
+        assert!(!swap_version.locked());
+        assert_eq!(
+            swap_version.set_locked(true),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        assert_eq!(*swap_version.host_fee(), HostFee::default());
+        assert_eq!(
+            swap_version.set_host_fee(HostFee::default()),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        assert!(!swap_version.fees().has_owner_withdraw_fee());
+        assert_eq!(
+            swap_version.set_fees(fees),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        swap_version.set_fees(TEST_FEES).unwrap();
+        assert_eq!(*swap_version.protocol_fee(), ProtocolFee::default());
+        assert_eq!(
+            swap_version.set_protocol_fee(ProtocolFee::default()),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+    }
+
+    #[test]
+    fn host_fee() {
+        let host_fee = HostFee {
+            numerator: 20,
+            denominator: 100,
+        };
+        assert_eq!(host_fee.validate(), Ok(()));
+        assert_eq!(host_fee.host_fee(1_000), Some(200));
+        assert_eq!(host_fee.host_fee(4), Some(0));
+        assert_eq!(HostFee::default().host_fee(1_000), Some(0));
+        assert_eq!(
+            HostFee {
+                numerator: 101,
+                denominator: 100,
+            }
+            .validate(),
+            Err(AmmError::InvalidFee)
+        );
+    }
+
+    #[test]
+    fn protocol_fee() {
+        assert_eq!(ProtocolFee::root_k(0, 1_000), 0);
+        assert_eq!(ProtocolFee::root_k(1, 1), 1);
+        assert_eq!(ProtocolFee::root_k(1_000, 4_000), 2_000);
+        assert_eq!(ProtocolFee::root_k(1_000, 4_001), 2_000);
+        assert_eq!(ProtocolFee::root_k(u64::MAX, u64::MAX), u64::MAX);
+
+        let mut protocol_fee = ProtocolFee {
+            numerator: 1,
+            denominator: 6,
+            fee_account: Pubkey::new_unique(),
+            root_k_last: 0,
+        };
+        assert_eq!(protocol_fee.validate(), Ok(()));
+        // nothing is minted until a liquidity event records `sqrt(k)`
+        assert_eq!(protocol_fee.protocol_fee(2_000, 1_000), Some(0));
+        protocol_fee.root_k_last = 1_000;
+        assert_eq!(protocol_fee.protocol_fee(1_000, 1_000), Some(0));
+        assert_eq!(protocol_fee.protocol_fee(900, 1_000), Some(0));
+        // a sixth of the doubled `sqrt(k)`: 1_000 * 1_000 / (5 * 2_000 + 1_000)
+        assert_eq!(protocol_fee.protocol_fee(2_000, 1_000), Some(90));
+        // the minted tokens own a sixth of the half of the pool that is growth
+        let minted = protocol_fee.protocol_fee(2_000, 1_000_000_000).unwrap();
+        assert_eq!(minted, 90_909_090);
+        assert_eq!((1_000_000_000 + minted) / minted, 12);
+
+        assert_eq!(ProtocolFee::default().protocol_fee(2_000, 1_000), Some(0));
+        assert_eq!(
+            ProtocolFee {
+                numerator: 6,
+                ..protocol_fee
+            }
+            .validate(),
+            Err(AmmError::InvalidFee)
+        );
+    }
+
+    #[test]
+    fn pool_stats_record() {
+        let mut stats = PoolStats::default();
+        stats.record_swap(TradeDirection::AtoB, 100, 1, 0, 3);
+        stats.record_swap(TradeDirection::AtoB, 100, 1, 1, 3);
+        stats.record_swap(TradeDirection::BtoA, 100, 1, 0, 3);
+        stats.record_deposit();
+        stats.record_withdraw();
+        stats.record_withdraw();
+        assert_eq!(
+            stats,
+            PoolStats {
+                volume_a_to_b: 200,
+                volume_b_to_a: 100,
+                fixed_fees_a: 2,
+                fixed_fees_b: 1,
+                host_fees_a: 1,
+                host_fees_b: 0,
+                return_fees_a: 6,
+                return_fees_b: 3,
+                swap_count: 3,
+                deposit_count: 1,
+                withdraw_count: 2,
+            }
+        );
+
+        // saturates instead of overflowing
+        stats.volume_a_to_b = u128::MAX - 1;
+        stats.record_swap(TradeDirection::AtoB, 100, 1, 0, 3);
+        assert_eq!(stats.volume_a_to_b, u128::MAX);
+        assert_eq!(stats.swap_count, 4);
+    }
+
+    #[test]
+    fn swap_version_set_status() {
+        let mut packed = [0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(SwapVersion::SwapV2(test_swap_v2_info()), &mut packed).unwrap();
+
+        let mut swap_version = SwapVersion::unpack_version(&packed).unwrap();
+        swap_version.set_status(PoolStatus::Halted).unwrap();
+        SwapVersion::pack(swap_version, &mut packed).unwrap();
+
+        let unpacked = SwapVersion::unpack(&packed).unwrap();
+        assert_eq!(unpacked.status(), PoolStatus::Halted);
+        assert_eq!(*unpacked.fees(), TEST_FEES);
+    }
+
+    #[test]
+    fn pool_status_policy() {
+        assert!(PoolStatus::Active.can_swap());
+        assert!(PoolStatus::Active.can_deposit());
+        assert!(PoolStatus::Active.can_withdraw());
+
+        assert!(!PoolStatus::WithdrawOnly.can_swap());
+        assert!(!PoolStatus::WithdrawOnly.can_deposit());
+        assert!(PoolStatus::WithdrawOnly.can_withdraw());
+
+        assert!(!PoolStatus::Halted.can_swap());
+        assert!(!PoolStatus::Halted.can_deposit());
+        assert!(!PoolStatus::Halted.can_withdraw());
+
+        for status in &[PoolStatus::Active, PoolStatus::WithdrawOnly, PoolStatus::Halted] {
+            assert_eq!(PoolStatus::try_from(*status as u8).unwrap(), *status);
+        }
+        assert_eq!(
+            PoolStatus::try_from(3).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+        assert_eq!(PoolStatus::default(), PoolStatus::Active);
+    }
+
+    #[test]
+    fn swap_version_unpack_unknown() {
+        let mut packed = [0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(SwapVersion::SwapV1(test_swap_info()), &mut packed).unwrap();
+        assert!(SwapVersion::is_initialized(&packed));
+
+        // an unknown version byte is never treated as a live pool
+        packed[0] = u8::MAX;
+        assert_eq!(
+            SwapVersion::unpack(&packed).err(),
+            Some(ProgramError::UninitializedAccount)
+        );
+        assert!(!SwapVersion::is_initialized(&packed));
+        assert!(!SwapVersion::is_initialized(&[]));
+    }
+
+    #[test]
+    fn swap_version_set_fees() {
+        let mut packed = [0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(SwapVersion::SwapV1(test_swap_info()), &mut packed).unwrap();
+
+        let fees = Fees {
+            return_fee_numerator: 30,
+            fixed_fee_numerator: 10,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let mut swap_version = SwapVersion::unpack_version(&packed).unwrap();
+        swap_version.set_fees(fees.clone()).unwrap();
+        SwapVersion::pack(swap_version, &mut packed).unwrap();
+
+        let unpacked = SwapVersion::unpack(&packed).unwrap();
+        assert_eq!(*unpacked.fees(), fees);
+        assert_eq!(*unpacked.pool_mint(), TEST_POOL_MINT);
+    }
+
+    #[test]
+    fn multi_swap_pack() {
+        let three_keys = || {
+            let mut keys = [Pubkey::default(); MAX_TOKENS];
+            keys[..3].iter_mut().for_each(|key| *key = Pubkey::new_unique());
+            keys
+        };
+        let token_accounts = three_keys();
+        let token_mints = three_keys();
+        let fixed_fee_accounts = three_keys();
+        let mut stats = MultiPoolStats::default();
+        stats.record_swap(2, 100, 1, 3);
+        stats.record_swap(2, 100, 1, 3);
+        stats.record_swap(0, 50, 0, 2);
+        stats.record_deposit();
+        stats.record_withdraw();
+        assert_eq!(stats.volumes[..3], [50, 0, 200]);
+        assert_eq!(stats.fixed_fees[..3], [0, 0, 2]);
+        assert_eq!(stats.return_fees[..3], [2, 0, 6]);
+        assert_eq!(
+            (stats.swap_count, stats.deposit_count, stats.withdraw_count),
+            (3, 1, 1)
+        );
+        let mut price_oracle = MultiPriceOracle::new(1_000);
+        price_oracle.update(1_100, &[1_000, 2_000, 4_000]).unwrap();
+        let multi_swap = || MultiSwap {
+            is_initialized: true,
+            nonce: TEST_NONCE,
+            token_program_id: TEST_TOKEN_PROGRAM_ID,
+            pool_mint: TEST_POOL_MINT,
+            token_count: 3,
+            token_accounts,
+            token_mints,
+            fixed_fee_accounts,
+            fees: TEST_FEES,
+            swap_curve: MultiSwapCurve {
+                curve_type: CurveType::Weighted,
+                calculator: Box::new(MultiWeightedCurve {
+                    weights: [50, 30, 20, 0, 0, 0, 0, 0],
+                }),
+            },
+            status: PoolStatus::WithdrawOnly,
+            stats,
+            price_oracle,
+        };
+
+        let mut packed = [0u8; MultiSwap::LEN];
+        MultiSwap::pack_into_slice(&multi_swap(), &mut packed);
+        let unpacked = MultiSwap::unpack(&packed).unwrap();
+        assert_eq!(unpacked, multi_swap());
+        assert_eq!(unpacked.reserve_accounts(), &token_accounts[..3]);
+        assert_eq!(unpacked.mints(), &token_mints[..3]);
+        assert_eq!(unpacked.fee_accounts(), &fixed_fee_accounts[..3]);
+
+        let mut account = [0u8; MultiSwap::ACCOUNT_LEN];
+        assert!(!SwapVersion::is_initialized(&account));
+        MultiSwap::pack_account(multi_swap(), &mut account).unwrap();
+        assert_eq!(MultiSwap::unpack_account(&account).unwrap(), multi_swap());
+        // a multi token pool is never mistaken for a two token one, but
+        // still counts as initialized
+        assert_eq!(
+            SwapVersion::unpack(&account).err(),
+            Some(ProgramError::UninitializedAccount)
+        );
+        assert!(SwapVersion::is_initialized(&account));
+
+        let mut swap_account = [0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(SwapVersion::SwapV1(test_swap_info()), &mut swap_account).unwrap();
+        assert_eq!(
+            MultiSwap::unpack_account(&swap_account).unwrap_err(),
+            ProgramError::UninitializedAccount
+        );
+
+        packed[66] = MAX_TOKENS as u8 + 1;
+        assert_eq!(
+            MultiSwap::unpack(&packed).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+
+    #[test]
+    fn program_config_pack() {
+        let config = ProgramConfig {
+            is_initialized: true,
+            nonce: TEST_NONCE,
+            admin: TEST_AMM_ID,
+            fee_wallet: TEST_FIXED_FEE_ACCOUNT_A,
+        };
+
+        let mut packed = [0u8; ProgramConfig::LEN];
+        ProgramConfig::pack(config, &mut packed).unwrap();
+        let mut expect = vec![1u8, TEST_NONCE];
+        expect.extend_from_slice(&TEST_AMM_ID.to_bytes());
+        expect.extend_from_slice(&TEST_FIXED_FEE_ACCOUNT_A.to_bytes());
+        assert_eq!(packed[..], expect[..]);
+
+        let unpacked = ProgramConfig::unpack(&packed).unwrap();
+        assert_eq!(unpacked.admin, TEST_AMM_ID);
+        assert_eq!(unpacked.fee_wallet, TEST_FIXED_FEE_ACCOUNT_A);
+
+        let packed = [0u8; ProgramConfig::LEN];
+        let err = ProgramConfig::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::UninitializedAccount);
+    }
+}
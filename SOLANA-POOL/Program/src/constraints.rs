@@ -1,9 +1,11 @@
 //! Various constraints as required for production environments
 
 use crate::{
+    amm_stats::HostFee,
     curve::{
         base::{CurveType, SwapCurve},
         fees::Fees,
+        multi::MultiSwapCurve,
     },
     error::AmmError,
 };
@@ -26,16 +28,24 @@ pub struct SwapConstraints<'a> {
     pub valid_curve_types: &'a [CurveType],
     /// Valid fees
     pub fees: &'a Fees,
+    /// Share of the fixed fee of swaps paid to their host
+    pub host_fee: &'a HostFee,
 }
 
 impl<'a> SwapConstraints<'a> {
     /// Checks that the provided curve is valid for the given constraints
     pub fn validate_curve(&self, swap_curve: &SwapCurve) -> Result<(), ProgramError> {
-        if self
-            .valid_curve_types
-            .iter()
-            .any(|x| *x == swap_curve.curve_type)
-        {
+        if self.valid_curve_types.contains(&swap_curve.curve_type) {
+            Ok(())
+        } else {
+            Err(AmmError::UnsupportedCurveType.into())
+        }
+    }
+
+    /// Checks that the provided curve of a multi token pool is valid for the
+    /// given constraints
+    pub fn validate_multi_curve(&self, swap_curve: &MultiSwapCurve) -> Result<(), ProgramError> {
+        if self.valid_curve_types.contains(&swap_curve.curve_type) {
             Ok(())
         } else {
             Err(AmmError::UnsupportedCurveType.into())
@@ -47,29 +57,47 @@ impl<'a> SwapConstraints<'a> {
         if fees.return_fee_numerator >= self.fees.return_fee_numerator
             && fees.fixed_fee_numerator >= self.fees.fixed_fee_numerator
             && fees.fee_denominator == self.fees.fee_denominator
+            && fees.owner_withdraw_fee_numerator >= self.fees.owner_withdraw_fee_numerator
+            && fees.owner_withdraw_fee_denominator == self.fees.owner_withdraw_fee_denominator
         {
             Ok(())
         } else {
             Err(AmmError::InvalidFee.into())
         }
     }
+
+    /// Checks that the provided host fee is the one of the constraints
+    pub fn validate_host_fee(&self, host_fee: &HostFee) -> Result<(), ProgramError> {
+        if host_fee == self.host_fee {
+            Ok(())
+        } else {
+            Err(AmmError::InvalidFee.into())
+        }
+    }
 }
 
 #[cfg(feature = "production")]
 const OWNER_KEY: &str = env!("SWAP_PROGRAM_OWNER_FEE_ADDRESS");
 #[cfg(feature = "production")]
 const FEES: &Fees = &Fees {
-    trade_fee_numerator: 0,
-    trade_fee_denominator: 10000,
-    owner_trade_fee_numerator: 5,
-    owner_trade_fee_denominator: 10000,
+    return_fee_numerator: 0,
+    fixed_fee_numerator: 5,
+    fee_denominator: 10000,
     owner_withdraw_fee_numerator: 0,
     owner_withdraw_fee_denominator: 0,
-    host_fee_numerator: 20,
-    host_fee_denominator: 100,
 };
 #[cfg(feature = "production")]
-const VALID_CURVE_TYPES: &[CurveType] = &[CurveType::ConstantPrice, CurveType::ConstantProduct];
+const HOST_FEE: &HostFee = &HostFee {
+    numerator: 20,
+    denominator: 100,
+};
+#[cfg(feature = "production")]
+const VALID_CURVE_TYPES: &[CurveType] = &[
+    CurveType::ConstantPrice,
+    CurveType::ConstantProduct,
+    CurveType::Stable,
+    CurveType::Weighted,
+];
 
 /// Fee structure defined by program creator in order to enforce certain
 /// fees when others use the program.  Adds checks on pool creation and
@@ -84,6 +112,7 @@ pub const SWAP_CONSTRAINTS: Option<SwapConstraints> = {
             owner_key: OWNER_KEY,
             valid_curve_types: VALID_CURVE_TYPES,
             fees: FEES,
+            host_fee: HOST_FEE,
         })
     }
     #[cfg(not(feature = "production"))]
@@ -103,22 +132,31 @@ mod tests {
         let return_fee_numerator = 2;
         let fixed_fee_numerator = 1;
         let fee_denominator = 100;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 10;
         let owner_key = "";
         let curve_type = CurveType::ConstantProduct;
         let valid_fees = Fees {
             return_fee_numerator,
             fixed_fee_numerator,
-            fee_denominator
+            fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
         };
         let calculator = ConstantProductCurve {};
         let swap_curve = SwapCurve {
             curve_type,
             calculator: Box::new(calculator.clone()),
         };
+        let host_fee = HostFee {
+            numerator: 20,
+            denominator: 100,
+        };
         let constraints = SwapConstraints {
             owner_key,
             valid_curve_types: &[curve_type],
             fees: &valid_fees,
+            host_fee: &host_fee,
         };
 
         constraints.validate_curve(&swap_curve).unwrap();
@@ -142,6 +180,20 @@ mod tests {
             Err(AmmError::InvalidFee.into()),
             constraints.validate_fees(&fees),
         );
+        fees.fee_denominator = fee_denominator;
+
+        fees.owner_withdraw_fee_numerator = owner_withdraw_fee_numerator - 1;
+        assert_eq!(
+            Err(AmmError::InvalidFee.into()),
+            constraints.validate_fees(&fees),
+        );
+        fees.owner_withdraw_fee_numerator = owner_withdraw_fee_numerator;
+
+        fees.owner_withdraw_fee_denominator = owner_withdraw_fee_denominator - 1;
+        assert_eq!(
+            Err(AmmError::InvalidFee.into()),
+            constraints.validate_fees(&fees),
+        );
 
         let swap_curve = SwapCurve {
             curve_type: CurveType::ConstantPrice,
@@ -151,5 +203,12 @@ mod tests {
             Err(AmmError::UnsupportedCurveType.into()),
             constraints.validate_curve(&swap_curve),
         );
+
+        // the host fee is a fixed share
+        constraints.validate_host_fee(&host_fee).unwrap();
+        assert_eq!(
+            Err(AmmError::InvalidFee.into()),
+            constraints.validate_host_fee(&HostFee::default()),
+        );
     }
 }
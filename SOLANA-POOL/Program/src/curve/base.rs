@@ -0,0 +1,471 @@
+//! Base curve implementation
+
+use crate::curve::{
+    calculator::{CurveCalculator, SwapWithoutFeesResult, TradeDirection},
+    constant_price::ConstantPriceCurve,
+    constant_product::ConstantProductCurve,
+    fees::Fees,
+    offset::OffsetCurve,
+    stable::StableCurve,
+    weighted::WeightedCurve,
+};
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+};
+use std::convert::{TryFrom, TryInto};
+use std::fmt::Debug;
+
+/// Curve types supported by the token-swap program.
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CurveType {
+    /// Uniswap-style constant product curve, invariant = token_a_amount * token_b_amount
+    ConstantProduct,
+    /// Flat line, always providing 1:1 from one token to another
+    ConstantPrice,
+    /// Stable, like uniswap, but with wide zone of 1:1 instead of one point
+    Stable,
+    /// Offset curve, like Uniswap, but the token B side has a faked offset
+    Offset,
+    /// Balancer-style weighted curve, invariant = token_a_amount^wa * token_b_amount^wb
+    Weighted,
+}
+
+/// Encodes all results of swapping from a source token to a destination token
+#[derive(Debug, PartialEq)]
+pub struct SwapResult {
+    /// New amount of source token
+    pub new_swap_source_amount: u128,
+    /// New amount of destination token
+    pub new_swap_destination_amount: u128,
+    /// Amount of source token swapped (includes fees)
+    pub source_amount_swapped: u128,
+    /// Amount of destination token swapped
+    pub destination_amount_swapped: u128,
+    /// Amount of source tokens going to pool holders
+    pub trade_fee: u128,
+    /// Amount of source tokens going to the fee accounts
+    pub owner_fee: u128,
+}
+
+/// Concrete struct to wrap around the trait object which performs calculation.
+#[derive(Debug)]
+pub struct SwapCurve {
+    /// The type of curve contained in the calculator, helpful for outside
+    /// queries
+    pub curve_type: CurveType,
+    /// The actual calculator, represented as a trait object to allow for many
+    /// different types of curves
+    pub calculator: Box<dyn CurveCalculator>,
+}
+
+impl SwapCurve {
+    /// Subtract fees and calculate how much destination token will be provided
+    /// given an amount of source token.  The fixed fee is paid out of the
+    /// source tokens, so it is not part of the new source reserve.
+    pub fn swap(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<SwapResult> {
+        // debit the fee to calculate the amount swapped
+        let trade_fee = fees.return_fee(source_amount)?;
+        let owner_fee = fees.fixed_fee(source_amount)?;
+
+        let total_fees = trade_fee.checked_add(owner_fee)?;
+        let source_amount_less_fees = source_amount.checked_sub(total_fees)?;
+
+        let SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped,
+        } = self.calculator.swap_without_fees(
+            source_amount_less_fees,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        )?;
+
+        let source_amount_swapped = source_amount_swapped.checked_add(total_fees)?;
+        Some(SwapResult {
+            new_swap_source_amount: swap_source_amount
+                .checked_add(source_amount_swapped)?
+                .checked_sub(owner_fee)?,
+            new_swap_destination_amount: swap_destination_amount
+                .checked_sub(destination_amount_swapped)?,
+            source_amount_swapped,
+            destination_amount_swapped,
+            trade_fee,
+            owner_fee,
+        })
+    }
+
+    /// Get the amount of pool tokens for the deposited amount of token A or B
+    pub fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        // Get the trading fee incurred if *half* the source amount is swapped
+        // for the other side. Reference at:
+        // https://github.com/balancer-labs/balancer-core/blob/f4ed5d65362a8d6cec21662fb6eae233b0babc1f/contracts/BMath.sol#L117
+        let half_source_amount = std::cmp::max(1, source_amount.checked_div(2)?);
+        let trade_fee = fees.return_fee(half_source_amount)?;
+        let source_amount = source_amount.checked_sub(trade_fee)?;
+        self.calculator.deposit_single_token_type(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    /// Get the amount of pool tokens for the withdrawn amount of token A or B
+    pub fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        // Get the trading fee incurred if *half* the source amount is swapped
+        // for the other side. Reference at:
+        // https://github.com/balancer-labs/balancer-core/blob/f4ed5d65362a8d6cec21662fb6eae233b0babc1f/contracts/BMath.sol#L117
+        let half_source_amount = std::cmp::max(1, source_amount.checked_div(2)?);
+        let trade_fee = fees.return_fee(half_source_amount)?;
+        let source_amount = source_amount.checked_add(trade_fee)?;
+        self.calculator.withdraw_single_token_type_exact_out(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )
+    }
+}
+
+/// Default implementation for SwapCurve cannot be derived because of
+/// the contained Box.
+impl Default for SwapCurve {
+    fn default() -> Self {
+        Self {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        }
+    }
+}
+
+/// Simple implementation for SwapCurve cannot be derived because of
+/// the contained Box.
+impl Clone for SwapCurve {
+    fn clone(&self) -> Self {
+        let mut packed_self = [0u8; Self::LEN];
+        Pack::pack_into_slice(self, &mut packed_self);
+        SwapCurve::unpack_from_slice(&packed_self).unwrap()
+    }
+}
+
+/// Simple implementation of PartialEq which just compares the curve types and
+/// packed calculator
+impl PartialEq for SwapCurve {
+    fn eq(&self, other: &Self) -> bool {
+        let mut packed_self = [0u8; Self::LEN];
+        Pack::pack_into_slice(self, &mut packed_self);
+        let mut packed_other = [0u8; Self::LEN];
+        Pack::pack_into_slice(other, &mut packed_other);
+        packed_self[..] == packed_other[..]
+    }
+}
+
+impl Sealed for SwapCurve {}
+impl IsInitialized for SwapCurve {
+    /// Is initialized
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Pack for SwapCurve {
+    /// Size of encoding of all curve parameters, which include fees and any
+    /// other constants used to calculate swaps, deposits, and withdrawals.
+    /// This includes 1 byte for the type, and 32 for the calculator to use as
+    /// it needs.  Some calculators may be smaller than 32 bytes.
+    const LEN: usize = 33;
+
+    /// Unpacks a byte buffer into a SwapCurve
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 33];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (curve_type, calculator) = array_refs![input, 1, 32];
+        let curve_type = curve_type[0].try_into()?;
+        Ok(Self {
+            curve_type,
+            calculator: match curve_type {
+                CurveType::ConstantProduct => {
+                    Box::new(ConstantProductCurve::unpack_from_slice(calculator)?)
+                }
+                CurveType::ConstantPrice => {
+                    Box::new(ConstantPriceCurve::unpack_from_slice(calculator)?)
+                }
+                CurveType::Stable => Box::new(StableCurve::unpack_from_slice(calculator)?),
+                CurveType::Offset => Box::new(OffsetCurve::unpack_from_slice(calculator)?),
+                CurveType::Weighted => Box::new(WeightedCurve::unpack_from_slice(calculator)?),
+            },
+        })
+    }
+
+    /// Pack SwapCurve into a byte buffer
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 33];
+        let (curve_type, calculator) = mut_array_refs![output, 1, 32];
+        curve_type[0] = self.curve_type as u8;
+        // calculators smaller than 32 bytes leave the rest of the buffer
+        // zeroed, so that equal curves pack to equal bytes
+        *calculator = [0u8; 32];
+        self.calculator.pack_into_slice(&mut calculator[..]);
+    }
+}
+
+impl TryFrom<u8> for CurveType {
+    type Error = ProgramError;
+
+    fn try_from(curve_type: u8) -> Result<Self, Self::Error> {
+        match curve_type {
+            0 => Ok(CurveType::ConstantProduct),
+            1 => Ok(CurveType::ConstantPrice),
+            2 => Ok(CurveType::Stable),
+            3 => Ok(CurveType::Offset),
+            4 => Ok(CurveType::Weighted),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::calculator::INITIAL_SWAP_POOL_AMOUNT;
+
+    #[test]
+    fn pack_swap_curve() {
+        let curve = ConstantProductCurve {};
+        let curve_type = CurveType::ConstantProduct;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Box::new(curve),
+        };
+
+        let mut packed = [0u8; SwapCurve::LEN];
+        Pack::pack_into_slice(&swap_curve, &mut packed[..]);
+        let unpacked = SwapCurve::unpack_from_slice(&packed).unwrap();
+        assert_eq!(swap_curve, unpacked);
+
+        let mut packed = vec![curve_type as u8];
+        packed.extend_from_slice(&[0u8; 32]); // 32 bytes reserved for curve
+        let unpacked = SwapCurve::unpack_from_slice(&packed).unwrap();
+        assert_eq!(swap_curve, unpacked);
+    }
+
+    #[test]
+    fn pack_every_curve_type() {
+        let swap_curves = [
+            SwapCurve {
+                curve_type: CurveType::ConstantProduct,
+                calculator: Box::new(ConstantProductCurve {}),
+            },
+            SwapCurve {
+                curve_type: CurveType::ConstantPrice,
+                calculator: Box::new(ConstantPriceCurve { token_b_price: 7 }),
+            },
+            SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Box::new(StableCurve { amp: 100 }),
+            },
+            SwapCurve {
+                curve_type: CurveType::Offset,
+                calculator: Box::new(OffsetCurve { token_b_offset: 9 }),
+            },
+            SwapCurve {
+                curve_type: CurveType::Weighted,
+                calculator: Box::new(WeightedCurve {
+                    token_a_weight: 80,
+                    token_b_weight: 20,
+                }),
+            },
+        ];
+        for swap_curve in swap_curves.iter() {
+            let mut packed = [0u8; SwapCurve::LEN];
+            Pack::pack_into_slice(swap_curve, &mut packed[..]);
+            assert_eq!(packed[0], swap_curve.curve_type as u8);
+            assert_eq!(SwapCurve::unpack_from_slice(&packed).unwrap(), *swap_curve);
+        }
+
+        let mut packed = [0u8; SwapCurve::LEN];
+        packed[0] = CurveType::Weighted as u8 + 1;
+        assert_eq!(
+            SwapCurve::unpack_from_slice(&packed).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+
+    #[test]
+    fn constant_product_trade_fee() {
+        // calculation on https://github.com/solana-labs/solana-program-library/issues/341
+        let swap_source_amount = 1000;
+        let swap_destination_amount = 50000;
+        let return_fee_numerator = 1;
+        let fixed_fee_numerator = 0;
+        let fee_denominator = 100;
+        let source_amount = 100;
+        let curve = ConstantProductCurve {};
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(curve),
+        };
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let result = swap_curve
+            .swap(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        assert_eq!(result.new_swap_source_amount, 1100);
+        assert_eq!(result.destination_amount_swapped, 4504);
+        assert_eq!(result.new_swap_destination_amount, 45496);
+        assert_eq!(result.trade_fee, 1);
+        assert_eq!(result.owner_fee, 0);
+    }
+
+    #[test]
+    fn constant_product_fixed_fee() {
+        let swap_source_amount = 10_000;
+        let swap_destination_amount = 50_000;
+        let return_fee_numerator = 1;
+        let fixed_fee_numerator = 2;
+        let fee_denominator = 100;
+        let source_amount: u128 = 100;
+        let swap_curve = SwapCurve::default();
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let result = swap_curve
+            .swap(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        assert_eq!(result.trade_fee, 1);
+        assert_eq!(result.owner_fee, 2);
+        assert_eq!(result.source_amount_swapped, source_amount);
+        // the fixed fee leaves the pool, the trade fee stays in it
+        assert_eq!(
+            result.new_swap_source_amount,
+            swap_source_amount + source_amount - 2
+        );
+        assert_eq!(result.destination_amount_swapped, 480);
+        assert_eq!(result.new_swap_destination_amount, 49_520);
+    }
+
+    #[test]
+    fn swap_cannot_overdraw_destination() {
+        // the offset prices token B while the pool holds none of it
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Offset,
+            calculator: Box::new(OffsetCurve {
+                token_b_offset: 1_000_000,
+            }),
+        };
+        assert!(swap_curve
+            .swap(1_000, 1_000_000, 0, TradeDirection::AtoB, &Fees::default())
+            .is_none());
+    }
+
+    #[test]
+    fn single_sided_fees() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees {
+            return_fee_numerator: 1,
+            fixed_fee_numerator: 0,
+            fee_denominator: 100,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let pool_supply = INITIAL_SWAP_POOL_AMOUNT;
+        let deposit = swap_curve
+            .deposit_single_token_type(
+                1_000,
+                100_000,
+                100_000,
+                pool_supply,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        let deposit_without_fees = swap_curve
+            .deposit_single_token_type(
+                1_000,
+                100_000,
+                100_000,
+                pool_supply,
+                TradeDirection::AtoB,
+                &Fees::default(),
+            )
+            .unwrap();
+        assert!(deposit < deposit_without_fees);
+
+        let withdraw = swap_curve
+            .withdraw_single_token_type_exact_out(
+                1_000,
+                100_000,
+                100_000,
+                pool_supply,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        let withdraw_without_fees = swap_curve
+            .withdraw_single_token_type_exact_out(
+                1_000,
+                100_000,
+                100_000,
+                pool_supply,
+                TradeDirection::AtoB,
+                &Fees::default(),
+            )
+            .unwrap();
+        assert!(withdraw > withdraw_without_fees);
+    }
+}
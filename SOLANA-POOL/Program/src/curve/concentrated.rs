@@ -0,0 +1,404 @@
+//! Concentrated liquidity math.  The price of token A in token B is kept as
+//! its square root in Q64.64 fixed point, and tick `i` is the price
+//! `1.0001^i`.  Liquidity `L` between the square root prices `sa < sb` is
+//! backed by `L (sb - sa) / (sa sb)` token A and `L (sb - sa)` token B.
+
+use spl_math::uint::U256;
+use std::convert::TryFrom;
+
+/// Lowest tick, its price is about 2^-64
+pub const MIN_TICK: i32 = -443_636;
+
+/// Highest tick, its price is about 2^64
+pub const MAX_TICK: i32 = 443_636;
+
+/// Fractional bits of the square root prices
+const RESOLUTION: u32 = 64;
+
+/// `1.0001^(2^(i-1))` in Q64.64: the square root price of tick `2^i`.  The
+/// square root price of a tick is the product of the ones of its bits.
+const SQRT_PRICE_POWERS: [u128; 19] = [
+    18_447_666_387_855_959_850,
+    18_448_588_748_116_922_571,
+    18_450_433_606_991_734_263,
+    18_454_123_878_217_468_680,
+    18_461_506_635_090_006_701,
+    18_476_281_010_653_910_144,
+    18_505_865_242_158_250_041,
+    18_565_175_891_880_433_522,
+    18_684_368_066_214_940_582,
+    18_925_053_041_275_764_671,
+    19_415_764_168_677_886_926,
+    20_435_687_552_633_177_494,
+    22_639_080_592_224_303_007,
+    27_784_196_929_998_399_742,
+    41_848_122_137_994_986_128,
+    94_936_283_578_220_370_716,
+    488_590_176_327_622_479_860,
+    12_941_056_668_319_229_769_860,
+    9_078_618_265_828_848_800_676_189,
+];
+
+/// Square root price of `tick`, or None outside `MIN_TICK..=MAX_TICK`
+pub fn sqrt_price_at_tick(tick: i32) -> Option<u128> {
+    if !(MIN_TICK..=MAX_TICK).contains(&tick) {
+        return None;
+    }
+    let abs_tick = i64::from(tick).abs();
+    let mut ratio = U256::one() << RESOLUTION;
+    for (bit, power) in SQRT_PRICE_POWERS.iter().enumerate() {
+        if abs_tick & (1 << bit) != 0 {
+            ratio = ratio.checked_mul(U256::from(*power))? >> RESOLUTION;
+        }
+    }
+    if tick < 0 {
+        ratio = (U256::one() << (2 * RESOLUTION)).checked_div(ratio)?;
+    }
+    u128::try_from(ratio).ok()
+}
+
+/// Highest tick in `lower_tick..=upper_tick` whose square root price is not
+/// above `sqrt_price`, found by bisection.  The search range should be kept
+/// small: every step prices a tick.
+pub fn tick_at_sqrt_price(sqrt_price: u128, lower_tick: i32, upper_tick: i32) -> Option<i32> {
+    let (mut low, mut high) = (lower_tick.max(MIN_TICK), upper_tick.min(MAX_TICK));
+    if low > high || sqrt_price_at_tick(low)? > sqrt_price {
+        return None;
+    }
+    while low < high {
+        let middle = low + (high - low + 1) / 2;
+        if sqrt_price_at_tick(middle)? <= sqrt_price {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    Some(low)
+}
+
+/// Token A backing `liquidity` between two square root prices
+pub fn token_a_amount(
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<u128> {
+    if sqrt_price_lower == 0 || sqrt_price_lower > sqrt_price_upper {
+        return None;
+    }
+    let numerator = (U256::from(liquidity) << RESOLUTION)
+        .checked_mul(U256::from(sqrt_price_upper - sqrt_price_lower))?;
+    let amount = if round_up {
+        ceil_div(
+            ceil_div(numerator, U256::from(sqrt_price_upper))?,
+            U256::from(sqrt_price_lower),
+        )?
+    } else {
+        numerator.checked_div(U256::from(sqrt_price_upper))? / U256::from(sqrt_price_lower)
+    };
+    u128::try_from(amount).ok()
+}
+
+/// Token B backing `liquidity` between two square root prices
+pub fn token_b_amount(
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<u128> {
+    if sqrt_price_lower > sqrt_price_upper {
+        return None;
+    }
+    let product =
+        U256::from(liquidity).checked_mul(U256::from(sqrt_price_upper - sqrt_price_lower))?;
+    let amount = if round_up {
+        ceil_div(product, U256::one() << RESOLUTION)?
+    } else {
+        product >> RESOLUTION
+    };
+    u128::try_from(amount).ok()
+}
+
+/// Token amounts backing `liquidity` between two ticks at the current
+/// square root price: only token A above the range, only token B below it
+pub fn token_amounts(
+    sqrt_price: u128,
+    tick_lower: i32,
+    tick_upper: i32,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<(u128, u128)> {
+    let sqrt_price_lower = sqrt_price_at_tick(tick_lower)?;
+    let sqrt_price_upper = sqrt_price_at_tick(tick_upper)?;
+    if sqrt_price <= sqrt_price_lower {
+        Some((
+            token_a_amount(sqrt_price_lower, sqrt_price_upper, liquidity, round_up)?,
+            0,
+        ))
+    } else if sqrt_price >= sqrt_price_upper {
+        Some((
+            0,
+            token_b_amount(sqrt_price_lower, sqrt_price_upper, liquidity, round_up)?,
+        ))
+    } else {
+        Some((
+            token_a_amount(sqrt_price, sqrt_price_upper, liquidity, round_up)?,
+            token_b_amount(sqrt_price_lower, sqrt_price, liquidity, round_up)?,
+        ))
+    }
+}
+
+/// Square root price once `amount_in` is added to `liquidity` at
+/// `sqrt_price`.  Adding token A lowers the price and is rounded up, adding
+/// token B raises it and is rounded down, so the pool always gets at least
+/// `amount_in` worth.
+pub fn next_sqrt_price_from_input(
+    sqrt_price: u128,
+    liquidity: u128,
+    amount_in: u128,
+    a_to_b: bool,
+) -> Option<u128> {
+    if liquidity == 0 {
+        return None;
+    }
+    if amount_in == 0 {
+        return Some(sqrt_price);
+    }
+    let next_sqrt_price = if a_to_b {
+        let liquidity = U256::from(liquidity) << RESOLUTION;
+        // L s / (L + amount s)
+        let product = U256::from(amount_in).checked_mul(U256::from(sqrt_price))?;
+        let denominator = liquidity.checked_add(product)?;
+        match liquidity.checked_mul(U256::from(sqrt_price)) {
+            Some(numerator) => ceil_div(numerator, denominator)?,
+            // same as above without multiplying the liquidity by the price
+            None => ceil_div(
+                liquidity,
+                (liquidity / U256::from(sqrt_price)).checked_add(U256::from(amount_in))?,
+            )?,
+        }
+    } else {
+        // s + amount / L
+        let amount_in = U256::from(amount_in) << RESOLUTION;
+        U256::from(sqrt_price).checked_add(amount_in / U256::from(liquidity))?
+    };
+    u128::try_from(next_sqrt_price).ok()
+}
+
+/// One step of a swap, within a range of constant liquidity
+#[derive(Debug, PartialEq)]
+pub struct SwapStep {
+    /// Square root price at the end of the step
+    pub sqrt_price_next: u128,
+    /// Amount of source token swapped, without the fee
+    pub amount_in: u128,
+    /// Amount of destination token paid out
+    pub amount_out: u128,
+    /// Trade fee taken from the source token for the liquidity providers
+    pub fee_amount: u128,
+}
+
+/// Swaps up to `amount_remaining`, fee included, from `sqrt_price` towards
+/// `sqrt_price_target` with constant `liquidity`.  The step stops early if
+/// the amount runs out before the target price.
+pub fn compute_swap_step(
+    sqrt_price: u128,
+    sqrt_price_target: u128,
+    liquidity: u128,
+    amount_remaining: u128,
+    fee_numerator: u128,
+    fee_denominator: u128,
+) -> Option<SwapStep> {
+    let a_to_b = sqrt_price >= sqrt_price_target;
+    let amount_remaining_less_fee = if fee_denominator == 0 {
+        amount_remaining
+    } else {
+        u128::try_from(
+            U256::from(amount_remaining)
+                .checked_mul(U256::from(fee_denominator.checked_sub(fee_numerator)?))?
+                .checked_div(U256::from(fee_denominator))?,
+        )
+        .ok()?
+    };
+    let amount_in_to_target = if a_to_b {
+        token_a_amount(sqrt_price_target, sqrt_price, liquidity, true)
+    } else {
+        token_b_amount(sqrt_price, sqrt_price_target, liquidity, true)
+    };
+    let (sqrt_price_next, reaches_target) = match amount_in_to_target {
+        Some(amount_in) if amount_in <= amount_remaining_less_fee => (sqrt_price_target, true),
+        _ => {
+            let next_sqrt_price = next_sqrt_price_from_input(
+                sqrt_price,
+                liquidity,
+                amount_remaining_less_fee,
+                a_to_b,
+            )?;
+            (next_sqrt_price, next_sqrt_price == sqrt_price_target)
+        }
+    };
+
+    let (amount_in, amount_out) = if a_to_b {
+        (
+            token_a_amount(sqrt_price_next, sqrt_price, liquidity, true)?,
+            token_b_amount(sqrt_price_next, sqrt_price, liquidity, false)?,
+        )
+    } else {
+        (
+            token_b_amount(sqrt_price, sqrt_price_next, liquidity, true)?,
+            token_a_amount(sqrt_price, sqrt_price_next, liquidity, false)?,
+        )
+    };
+    let fee_amount = if reaches_target {
+        if fee_denominator == 0 || fee_numerator == 0 {
+            0
+        } else {
+            u128::try_from(ceil_div(
+                U256::from(amount_in).checked_mul(U256::from(fee_numerator))?,
+                U256::from(fee_denominator - fee_numerator),
+            )?)
+            .ok()?
+        }
+    } else {
+        // the rest of the amount is the fee, it cannot move the price
+        amount_remaining.checked_sub(amount_in)?
+    };
+    if amount_in.checked_add(fee_amount)? > amount_remaining {
+        return None;
+    }
+    Some(SwapStep {
+        sqrt_price_next,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
+
+fn ceil_div(numerator: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    numerator
+        .checked_add(denominator - 1)?
+        .checked_div(denominator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const Q64: u128 = 1 << 64;
+
+    #[test]
+    fn sqrt_price_bounds() {
+        assert_eq!(sqrt_price_at_tick(0), Some(Q64));
+        assert_eq!(sqrt_price_at_tick(MIN_TICK - 1), None);
+        assert_eq!(sqrt_price_at_tick(MAX_TICK + 1), None);
+
+        // exact values are 4295048016 and 79226673515401279992447579061
+        let min_sqrt_price = sqrt_price_at_tick(MIN_TICK).unwrap();
+        assert!((4_295_048_015..=4_295_048_017).contains(&min_sqrt_price));
+        let max_sqrt_price = sqrt_price_at_tick(MAX_TICK).unwrap();
+        let exact_max_sqrt_price = 79_226_673_515_401_279_992_447_579_061u128;
+        assert!(max_sqrt_price <= exact_max_sqrt_price);
+        assert!(exact_max_sqrt_price - max_sqrt_price < exact_max_sqrt_price / 1_000_000_000);
+    }
+
+    #[test]
+    fn token_amounts_at_one() {
+        // a price of one with liquidity 1_000_000 between ticks -100 and 100
+        // holds about 1_000_000 * (1 - 1.0001^-50) of each token
+        let (token_a, token_b) = token_amounts(Q64, -100, 100, 1_000_000, true).unwrap();
+        assert_eq!(token_a, 4_988);
+        assert_eq!(token_b, 4_988);
+        let (token_a, token_b) = token_amounts(Q64, -100, 100, 1_000_000, false).unwrap();
+        assert_eq!(token_a, 4_987);
+        assert_eq!(token_b, 4_987);
+
+        // out of range
+        let (token_a, token_b) = token_amounts(Q64, 100, 200, 1_000_000, true).unwrap();
+        assert!(token_a > 0);
+        assert_eq!(token_b, 0);
+        let (token_a, token_b) = token_amounts(Q64, -200, -100, 1_000_000, true).unwrap();
+        assert_eq!(token_a, 0);
+        assert!(token_b > 0);
+    }
+
+    #[test]
+    fn swap_step_stops_at_target() {
+        let sqrt_price_target = sqrt_price_at_tick(-10).unwrap();
+        let step =
+            compute_swap_step(Q64, sqrt_price_target, 1_000_000, 1_000_000, 30, 10_000).unwrap();
+        assert_eq!(step.sqrt_price_next, sqrt_price_target);
+        assert_eq!(
+            step.amount_in,
+            token_a_amount(sqrt_price_target, Q64, 1_000_000, true).unwrap()
+        );
+        assert!(step.amount_in + step.fee_amount < 1_000_000);
+
+        // not enough to reach the target, everything is used
+        let step = compute_swap_step(Q64, sqrt_price_target, 1_000_000, 100, 30, 10_000).unwrap();
+        assert!(step.sqrt_price_next > sqrt_price_target);
+        assert_eq!(step.amount_in + step.fee_amount, 100);
+        assert!(step.amount_out < 100);
+    }
+
+    proptest! {
+        #[test]
+        fn sqrt_price_increasing(tick in MIN_TICK..MAX_TICK) {
+            let sqrt_price = sqrt_price_at_tick(tick).unwrap();
+            prop_assert!(sqrt_price < sqrt_price_at_tick(tick + 1).unwrap());
+            prop_assert_eq!(
+                tick_at_sqrt_price(sqrt_price, tick - 1_000, tick + 1_000),
+                Some(tick)
+            );
+            prop_assert_eq!(
+                tick_at_sqrt_price(sqrt_price_at_tick(tick + 1).unwrap() - 1, MIN_TICK, MAX_TICK),
+                Some(tick)
+            );
+        }
+
+        #[test]
+        fn swap_step_keeps_value(
+            tick in -100_000..100_000i32,
+            tick_distance in 1..10_000i32,
+            liquidity in 1_000..u64::MAX as u128,
+            amount_remaining in 1..u64::MAX as u128,
+            a_to_b: bool,
+        ) {
+            let sqrt_price = sqrt_price_at_tick(tick).unwrap();
+            let target_tick = if a_to_b { tick - tick_distance } else { tick + tick_distance };
+            let sqrt_price_target = sqrt_price_at_tick(target_tick).unwrap();
+            let step = compute_swap_step(
+                sqrt_price,
+                sqrt_price_target,
+                liquidity,
+                amount_remaining,
+                30,
+                10_000,
+            )
+            .unwrap();
+            prop_assert!(step.amount_in + step.fee_amount <= amount_remaining);
+
+            // the tokens held for the liquidity move by at least the amounts
+            // swapped in the pool's favour
+            let (sqrt_price_low, sqrt_price_high) = if a_to_b {
+                prop_assert!(step.sqrt_price_next >= sqrt_price_target);
+                (step.sqrt_price_next, sqrt_price)
+            } else {
+                prop_assert!(step.sqrt_price_next <= sqrt_price_target);
+                (sqrt_price, step.sqrt_price_next)
+            };
+            let a_exact = token_a_amount(sqrt_price_low, sqrt_price_high, liquidity, false).unwrap();
+            let b_exact = token_b_amount(sqrt_price_low, sqrt_price_high, liquidity, false).unwrap();
+            if a_to_b {
+                prop_assert!(step.amount_in >= a_exact);
+                prop_assert!(step.amount_out <= b_exact);
+            } else {
+                prop_assert!(step.amount_in >= b_exact);
+                prop_assert!(step.amount_out <= a_exact);
+            }
+        }
+    }
+}
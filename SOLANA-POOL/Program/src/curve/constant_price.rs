@@ -0,0 +1,396 @@
+//! Simple constant price swap curve, set at init
+
+use crate::{
+    curve::calculator::{
+        map_zero_to_none, CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult,
+        TradeDirection, TradingTokenResult,
+    },
+    error::AmmError,
+};
+use arrayref::{array_mut_ref, array_ref};
+use solana_program::{
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+};
+use spl_math::{checked_ceil_div::CheckedCeilDiv, uint::U256};
+use std::convert::TryFrom;
+
+/// Get the amount of pool tokens for the given amount of token A or B.
+///
+/// There is no slippage at a constant price, so the pool tokens are the share
+/// of the total value of the pool that the given tokens are worth.
+pub fn trading_tokens_to_pool_tokens(
+    token_b_price: u64,
+    source_amount: u128,
+    swap_token_a_amount: u128,
+    swap_token_b_amount: u128,
+    pool_supply: u128,
+    trade_direction: TradeDirection,
+    round_direction: RoundDirection,
+) -> Option<u128> {
+    let token_b_price = U256::from(token_b_price);
+    let given_value = match trade_direction {
+        TradeDirection::AtoB => U256::from(source_amount),
+        TradeDirection::BtoA => U256::from(source_amount).checked_mul(token_b_price)?,
+    };
+    let total_value = U256::from(swap_token_b_amount)
+        .checked_mul(token_b_price)?
+        .checked_add(U256::from(swap_token_a_amount))?;
+    let pool_supply = U256::from(pool_supply);
+    let pool_tokens = match round_direction {
+        RoundDirection::Floor => pool_supply
+            .checked_mul(given_value)?
+            .checked_div(total_value)?,
+        RoundDirection::Ceiling => pool_supply
+            .checked_mul(given_value)?
+            .checked_add(total_value)?
+            .checked_sub(U256::one())?
+            .checked_div(total_value)?,
+    };
+    u128::try_from(pool_tokens).ok()
+}
+
+/// ConstantPriceCurve struct implementing CurveCalculator
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConstantPriceCurve {
+    /// Amount of token A required to get 1 token B
+    pub token_b_price: u64,
+}
+
+impl ConstantPriceCurve {
+    /// Half the value of both reserves counted in token A, pool tokens being
+    /// worth the same value of each token
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<u128> {
+        swap_token_b_amount
+            .checked_mul(u128::from(self.token_b_price))?
+            .checked_add(swap_token_a_amount)?
+            .checked_div(2)
+    }
+}
+
+impl CurveCalculator for ConstantPriceCurve {
+    /// Constant price curve trades `token_b_price` token A for each token B
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        _swap_source_amount: u128,
+        _swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let token_b_price = u128::from(self.token_b_price);
+
+        let (source_amount_swapped, destination_amount_swapped) = match trade_direction {
+            TradeDirection::BtoA => (source_amount, source_amount.checked_mul(token_b_price)?),
+            TradeDirection::AtoB => {
+                let destination_amount_swapped = source_amount.checked_div(token_b_price)?;
+                let mut source_amount_swapped = source_amount;
+
+                // if there is a remainder from buying token B, floor
+                // token_a_amount to avoid taking too many tokens, but
+                // don't recalculate the fees
+                let remainder = source_amount_swapped.checked_rem(token_b_price)?;
+                if remainder > 0 {
+                    source_amount_swapped = source_amount.checked_sub(remainder)?;
+                }
+
+                (source_amount_swapped, destination_amount_swapped)
+            }
+        };
+        let source_amount_swapped = map_zero_to_none(source_amount_swapped)?;
+        let destination_amount_swapped = map_zero_to_none(destination_amount_swapped)?;
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped,
+        })
+    }
+
+    /// Get the amount of trading tokens for the given amount of pool tokens,
+    /// provided the total trading tokens and supply of pool tokens.
+    /// For the constant price curve, the total value of the pool is weighted
+    /// by the price of token B.
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        let token_b_price = u128::from(self.token_b_price);
+        let pool_value = self.normalized_value(swap_token_a_amount, swap_token_b_amount)?;
+
+        let (token_a_amount, token_b_amount) = match round_direction {
+            RoundDirection::Floor => {
+                let token_a_amount = pool_tokens
+                    .checked_mul(pool_value)?
+                    .checked_div(pool_token_supply)?;
+                let token_b_amount = pool_tokens
+                    .checked_mul(pool_value)?
+                    .checked_div(token_b_price)?
+                    .checked_div(pool_token_supply)?;
+                (token_a_amount, token_b_amount)
+            }
+            RoundDirection::Ceiling => {
+                let (token_a_amount, _) = pool_tokens
+                    .checked_mul(pool_value)?
+                    .checked_ceil_div(pool_token_supply)?;
+                let (pool_value_as_token_b, _) = pool_tokens
+                    .checked_mul(pool_value)?
+                    .checked_ceil_div(token_b_price)?;
+                let (token_b_amount, _) =
+                    pool_value_as_token_b.checked_ceil_div(pool_token_supply)?;
+                (token_a_amount, token_b_amount)
+            }
+        };
+        Some(TradingTokenResult {
+            token_a_amount,
+            token_b_amount,
+        })
+    }
+
+    /// For the constant price model, there is no slippage on conversion, so
+    /// the pool token value is simply the given token value over the total
+    /// value of the pool.
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        trading_tokens_to_pool_tokens(
+            self.token_b_price,
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+            RoundDirection::Floor,
+        )
+    }
+
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        trading_tokens_to_pool_tokens(
+            self.token_b_price,
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+            RoundDirection::Ceiling,
+        )
+    }
+
+    fn validate(&self) -> Result<(), AmmError> {
+        if self.token_b_price == 0 {
+            Err(AmmError::InvalidCurve)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for ConstantPriceCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for ConstantPriceCurve {}
+impl Pack for ConstantPriceCurve {
+    const LEN: usize = 8;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<ConstantPriceCurve, ProgramError> {
+        let token_b_price = array_ref![input, 0, 8];
+        Ok(Self {
+            token_b_price: u64::from_le_bytes(*token_b_price),
+        })
+    }
+}
+
+impl DynPack for ConstantPriceCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let token_b_price = array_mut_ref![output, 0, 8];
+        *token_b_price = self.token_b_price.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::calculator::INITIAL_SWAP_POOL_AMOUNT;
+    use proptest::prelude::*;
+
+    #[test]
+    fn swap_calculation_no_price() {
+        let swap_source_amount: u128 = 0;
+        let swap_destination_amount: u128 = 0;
+        let source_amount: u128 = 100;
+        let token_b_price = 1;
+        let curve = ConstantPriceCurve { token_b_price };
+
+        let expected_result = SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped: source_amount,
+        };
+
+        let result = curve
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result, expected_result);
+
+        let result = curve
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert_eq!(result, expected_result);
+    }
+
+    #[test]
+    fn pack_flat_curve() {
+        let token_b_price = 1_251_258;
+        let curve = ConstantPriceCurve { token_b_price };
+
+        let mut packed = [0u8; ConstantPriceCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = ConstantPriceCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&token_b_price.to_le_bytes());
+        let unpacked = ConstantPriceCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    #[test]
+    fn swap_calculation_large_price() {
+        let token_b_price = 1123513u128;
+        let curve = ConstantPriceCurve {
+            token_b_price: token_b_price as u64,
+        };
+        let token_b_amount = 500u128;
+        let token_a_amount = token_b_amount * token_b_price;
+        let bad_result = curve.swap_without_fees(
+            token_b_price - 1u128,
+            token_a_amount,
+            token_b_amount,
+            TradeDirection::AtoB,
+        );
+        assert!(bad_result.is_none());
+        let bad_result =
+            curve.swap_without_fees(1u128, token_a_amount, token_b_amount, TradeDirection::AtoB);
+        assert!(bad_result.is_none());
+        let result = curve
+            .swap_without_fees(
+                token_b_price,
+                token_a_amount,
+                token_b_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, token_b_price);
+        assert_eq!(result.destination_amount_swapped, 1u128);
+    }
+
+    #[test]
+    fn swap_calculation_max_min() {
+        let token_b_price = u64::MAX as u128;
+        let curve = ConstantPriceCurve {
+            token_b_price: token_b_price as u64,
+        };
+        let token_b_amount = 1u128;
+        let token_a_amount = token_b_price;
+        let bad_result = curve.swap_without_fees(
+            token_b_price - 1u128,
+            token_a_amount,
+            token_b_amount,
+            TradeDirection::AtoB,
+        );
+        assert!(bad_result.is_none());
+        let bad_result =
+            curve.swap_without_fees(1u128, token_a_amount, token_b_amount, TradeDirection::AtoB);
+        assert!(bad_result.is_none());
+        let bad_result =
+            curve.swap_without_fees(0u128, token_a_amount, token_b_amount, TradeDirection::AtoB);
+        assert!(bad_result.is_none());
+        let result = curve
+            .swap_without_fees(
+                token_b_price,
+                token_a_amount,
+                token_b_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, token_b_price);
+        assert_eq!(result.destination_amount_swapped, 1u128);
+    }
+
+    #[test]
+    fn validate_price() {
+        ConstantPriceCurve { token_b_price: 1 }.validate().unwrap();
+        assert_eq!(
+            ConstantPriceCurve { token_b_price: 0 }.validate(),
+            Err(AmmError::InvalidCurve)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn deposit_and_withdraw_at_constant_value(
+            source_token_amount in 1..u32::MAX as u128,
+            swap_token_a_amount in 1..u64::MAX as u128,
+            swap_token_b_amount in 1..u32::MAX as u128,
+            token_b_price in 1..u32::MAX as u64,
+        ) {
+            let curve = ConstantPriceCurve { token_b_price };
+            let pool_supply = INITIAL_SWAP_POOL_AMOUNT;
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+                let deposit = curve
+                    .deposit_single_token_type(
+                        source_token_amount,
+                        swap_token_a_amount,
+                        swap_token_b_amount,
+                        pool_supply,
+                        *trade_direction,
+                    )
+                    .unwrap();
+                let withdraw = curve
+                    .withdraw_single_token_type_exact_out(
+                        source_token_amount,
+                        swap_token_a_amount,
+                        swap_token_b_amount,
+                        pool_supply,
+                        *trade_direction,
+                    )
+                    .unwrap();
+                // the same value in and out only differs by the rounding
+                prop_assert!(withdraw >= deposit);
+                prop_assert!(withdraw - deposit <= 1);
+            }
+        }
+    }
+}
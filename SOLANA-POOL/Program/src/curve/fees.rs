@@ -0,0 +1,284 @@
+//! All fee information, to be used for validation currently
+
+use crate::error::AmmError;
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+};
+
+/// Encapsulates all fee information and calculations for swap operations
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fees {
+    /// Trade fees are extra token amounts that are held inside the token
+    /// accounts during a trade, making the value of liquidity tokens rise.
+    /// Trade fee numerator
+    pub return_fee_numerator: u64,
+    /// Fixed fees are token amounts taken from the source of a trade and
+    /// sent to the fee accounts of the pool, out of its reserves.
+    /// Fixed fee numerator
+    pub fixed_fee_numerator: u64,
+    /// Denominator of both the trade and the fixed fee
+    pub fee_denominator: u64,
+    /// Owner withdraw fees are extra liquidity pool token amounts that are
+    /// sent to a pool token account of the fee wallet on every withdrawal.
+    /// Owner withdraw fee numerator
+    pub owner_withdraw_fee_numerator: u64,
+    /// Owner withdraw fee denominator
+    pub owner_withdraw_fee_denominator: u64,
+}
+
+/// Helper function for calculating swap fee
+pub fn calculate_fee(
+    token_amount: u128,
+    fee_numerator: u128,
+    fee_denominator: u128,
+) -> Option<u128> {
+    if fee_numerator == 0 || token_amount == 0 {
+        Some(0)
+    } else {
+        let fee = token_amount
+            .checked_mul(fee_numerator)?
+            .checked_div(fee_denominator)?;
+        if fee == 0 {
+            Some(1) // minimum fee of one token
+        } else {
+            Some(fee)
+        }
+    }
+}
+
+fn validate_fraction(numerator: u64, denominator: u64) -> Result<(), AmmError> {
+    if denominator == 0 && numerator == 0 {
+        Ok(())
+    } else if numerator >= denominator {
+        Err(AmmError::InvalidFee)
+    } else {
+        Ok(())
+    }
+}
+
+impl Fees {
+    /// Length of the packed owner withdraw fee
+    pub const OWNER_WITHDRAW_FEE_LEN: usize = 16;
+
+    /// Calculate the trading fee in trading tokens, kept by the pool
+    pub fn return_fee(&self, trading_tokens: u128) -> Option<u128> {
+        calculate_fee(
+            trading_tokens,
+            u128::from(self.return_fee_numerator),
+            u128::from(self.fee_denominator),
+        )
+    }
+
+    /// Calculate the fixed fee in trading tokens, paid to the fee accounts
+    pub fn fixed_fee(&self, trading_tokens: u128) -> Option<u128> {
+        calculate_fee(
+            trading_tokens,
+            u128::from(self.fixed_fee_numerator),
+            u128::from(self.fee_denominator),
+        )
+    }
+
+    /// Calculate the owner withdraw fee in pool tokens
+    pub fn owner_withdraw_fee(&self, pool_tokens: u128) -> Option<u128> {
+        calculate_fee(
+            pool_tokens,
+            u128::from(self.owner_withdraw_fee_numerator),
+            u128::from(self.owner_withdraw_fee_denominator),
+        )
+    }
+
+    /// Whether the fees set an owner withdraw fee, which only the states
+    /// packing it with `pack_owner_withdraw_fee` can store
+    pub fn has_owner_withdraw_fee(&self) -> bool {
+        self.owner_withdraw_fee_numerator != 0 || self.owner_withdraw_fee_denominator != 0
+    }
+
+    /// Validate that the fees are reasonable, the trade and fixed fees are
+    /// taken from the same amount so together they must stay below it
+    pub fn validate(&self) -> Result<(), AmmError> {
+        validate_fraction(
+            self.return_fee_numerator
+                .checked_add(self.fixed_fee_numerator)
+                .ok_or(AmmError::InvalidFee)?,
+            self.fee_denominator,
+        )?;
+        validate_fraction(
+            self.owner_withdraw_fee_numerator,
+            self.owner_withdraw_fee_denominator,
+        )
+    }
+
+    /// Packs the owner withdraw fee, which follows the other fees in the
+    /// states and instructions carrying it
+    pub fn pack_owner_withdraw_fee(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, Fees::OWNER_WITHDRAW_FEE_LEN];
+        let (numerator, denominator) = mut_array_refs![output, 8, 8];
+        *numerator = self.owner_withdraw_fee_numerator.to_le_bytes();
+        *denominator = self.owner_withdraw_fee_denominator.to_le_bytes();
+    }
+
+    /// Unpacks the owner withdraw fee packed by `pack_owner_withdraw_fee`
+    pub fn unpack_owner_withdraw_fee(&mut self, input: &[u8]) {
+        let input = array_ref![input, 0, Fees::OWNER_WITHDRAW_FEE_LEN];
+        let (numerator, denominator) = array_refs![input, 8, 8];
+        self.owner_withdraw_fee_numerator = u64::from_le_bytes(*numerator);
+        self.owner_withdraw_fee_denominator = u64::from_le_bytes(*denominator);
+    }
+
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for Fees {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+
+impl Sealed for Fees {}
+/// Packs the trade and fixed fees, laid out as in the states predating the
+/// owner withdraw fee, which unpacks as zero
+impl Pack for Fees {
+    const LEN: usize = 24;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 24];
+        let (return_fee_numerator, fixed_fee_numerator, fee_denominator) =
+            mut_array_refs![output, 8, 8, 8];
+        *return_fee_numerator = self.return_fee_numerator.to_le_bytes();
+        *fixed_fee_numerator = self.fixed_fee_numerator.to_le_bytes();
+        *fee_denominator = self.fee_denominator.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Fees, ProgramError> {
+        let input = array_ref![input, 0, 24];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (return_fee_numerator, fixed_fee_numerator, fee_denominator) =
+            array_refs![input, 8, 8, 8];
+        Ok(Self {
+            return_fee_numerator: u64::from_le_bytes(*return_fee_numerator),
+            fixed_fee_numerator: u64::from_le_bytes(*fixed_fee_numerator),
+            fee_denominator: u64::from_le_bytes(*fee_denominator),
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_fees() {
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10_000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+
+        let mut packed = [0u8; Fees::LEN];
+        Pack::pack_into_slice(&fees, &mut packed[..]);
+        let unpacked = Fees::unpack_from_slice(&packed).unwrap();
+        assert_eq!(fees, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&25u64.to_le_bytes());
+        packed.extend_from_slice(&5u64.to_le_bytes());
+        packed.extend_from_slice(&10_000u64.to_le_bytes());
+        let unpacked = Fees::unpack_from_slice(&packed).unwrap();
+        assert_eq!(fees, unpacked);
+
+        // the owner withdraw fee is packed apart from the other fees
+        let fees = Fees {
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 100,
+            ..fees
+        };
+        let mut packed = [0u8; Fees::LEN];
+        Pack::pack_into_slice(&fees, &mut packed[..]);
+        let mut unpacked = Fees::unpack_from_slice(&packed).unwrap();
+        assert!(!unpacked.has_owner_withdraw_fee());
+        let mut packed = [0u8; Fees::OWNER_WITHDRAW_FEE_LEN];
+        fees.pack_owner_withdraw_fee(&mut packed);
+        unpacked.unpack_owner_withdraw_fee(&packed);
+        assert_eq!(fees, unpacked);
+    }
+
+    #[test]
+    fn fee_calculations() {
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10_000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        assert_eq!(fees.return_fee(1_000_000), Some(2_500));
+        assert_eq!(fees.fixed_fee(1_000_000), Some(500));
+        // a fee is never rounded down to nothing
+        assert_eq!(fees.fixed_fee(1), Some(1));
+        assert_eq!(fees.return_fee(0), Some(0));
+
+        let no_fees = Fees::default();
+        no_fees.validate().unwrap();
+        assert_eq!(no_fees.return_fee(1_000), Some(0));
+        assert_eq!(no_fees.fixed_fee(1_000), Some(0));
+        assert_eq!(no_fees.owner_withdraw_fee(1_000), Some(0));
+
+        let fees = Fees {
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 100,
+            ..fees
+        };
+        assert_eq!(fees.owner_withdraw_fee(1_000), Some(10));
+        assert_eq!(fees.owner_withdraw_fee(50), Some(1));
+        assert_eq!(fees.owner_withdraw_fee(0), Some(0));
+    }
+
+    #[test]
+    fn validate_fees() {
+        let fees = Fees {
+            return_fee_numerator: 9_000,
+            fixed_fee_numerator: 999,
+            fee_denominator: 10_000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        fees.validate().unwrap();
+        assert_eq!(
+            Fees {
+                fixed_fee_numerator: 1_000,
+                ..fees.clone()
+            }
+            .validate(),
+            Err(AmmError::InvalidFee)
+        );
+        assert_eq!(
+            Fees {
+                fee_denominator: 0,
+                ..fees.clone()
+            }
+            .validate(),
+            Err(AmmError::InvalidFee)
+        );
+        Fees {
+            owner_withdraw_fee_numerator: 99,
+            owner_withdraw_fee_denominator: 100,
+            ..fees.clone()
+        }
+        .validate()
+        .unwrap();
+        assert_eq!(
+            Fees {
+                owner_withdraw_fee_numerator: 100,
+                owner_withdraw_fee_denominator: 100,
+                ..fees
+            }
+            .validate(),
+            Err(AmmError::InvalidFee)
+        );
+    }
+}
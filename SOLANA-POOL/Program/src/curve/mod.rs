@@ -0,0 +1,12 @@
+//! Curve invariant implementations
+
+pub mod base;
+pub mod calculator;
+pub mod concentrated;
+pub mod constant_price;
+pub mod constant_product;
+pub mod fees;
+pub mod multi;
+pub mod offset;
+pub mod stable;
+pub mod weighted;
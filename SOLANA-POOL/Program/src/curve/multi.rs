@@ -0,0 +1,1026 @@
+//! Swap calculations for pools holding more than two tokens
+
+use crate::{
+    curve::{
+        base::{CurveType, SwapResult},
+        calculator::{DynPack, RoundDirection, SwapWithoutFeesResult, INITIAL_SWAP_POOL_AMOUNT},
+        fees::{calculate_fee, Fees},
+        stable::{ITERATIONS, MAX_AMP, MIN_AMP},
+        weighted::{self, MAX_WEIGHT, MAX_WEIGHT_RATIO},
+    },
+    error::AmmError,
+};
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+};
+use spl_math::uint::U256;
+use std::convert::{TryFrom, TryInto};
+use std::fmt::Debug;
+
+/// Fewest tokens a multi token pool holds, two token pools use `SwapCurve`
+pub const MIN_TOKENS: usize = 3;
+
+/// Most tokens a multi token pool holds
+pub const MAX_TOKENS: usize = 8;
+
+/// Trait representing operations required on a swap curve of a multi token
+/// pool.  Amounts are given for every token of the pool, in the order of its
+/// reserve accounts.
+pub trait MultiCurveCalculator: Debug + DynPack {
+    /// Calculate how much of the token at `destination_index` will be
+    /// provided given an amount of the token at `source_index`, without fees
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_token_amounts: &[u128],
+        source_index: usize,
+        destination_index: usize,
+    ) -> Option<SwapWithoutFeesResult>;
+
+    /// Get the supply for a new pool
+    fn new_pool_supply(&self) -> u128 {
+        INITIAL_SWAP_POOL_AMOUNT
+    }
+
+    /// Amount of each token that `pool_tokens` are worth, the same share of
+    /// every reserve whatever the curve
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_amounts: &[u128],
+        round_direction: RoundDirection,
+    ) -> Option<Vec<u128>> {
+        swap_token_amounts
+            .iter()
+            .map(|swap_token_amount| {
+                let numerator = pool_tokens.checked_mul(*swap_token_amount)?;
+                match round_direction {
+                    RoundDirection::Floor => numerator.checked_div(pool_token_supply),
+                    RoundDirection::Ceiling => ceil_div(numerator, pool_token_supply),
+                }
+            })
+            .collect()
+    }
+
+    /// Pool tokens minted for a deposit of the token at `token_index` alone,
+    /// without fees
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_amounts: &[u128],
+        token_index: usize,
+        pool_supply: u128,
+    ) -> Option<u128>;
+
+    /// Pool tokens burnt to withdraw exactly `destination_amount` of the
+    /// token at `token_index`, without fees
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_token_amounts: &[u128],
+        token_index: usize,
+        pool_supply: u128,
+    ) -> Option<u128>;
+
+    /// Validate the calculator configuration for a pool of `token_count`
+    /// tokens
+    fn validate(&self, token_count: usize) -> Result<(), AmmError>;
+}
+
+/// Swap curve of a multi token pool, only the stable and weighted curves
+/// extend beyond two tokens
+#[derive(Debug)]
+pub struct MultiSwapCurve {
+    /// The type of curve contained in the calculator, helpful for outside
+    /// queries
+    pub curve_type: CurveType,
+    /// The actual calculator, represented as a trait object to allow for many
+    /// different types of curves
+    pub calculator: Box<dyn MultiCurveCalculator>,
+}
+
+impl MultiSwapCurve {
+    /// Swap `source_amount` of the token at `source_index` for the one at
+    /// `destination_index`, fees included, as `SwapCurve::swap` does
+    pub fn swap(
+        &self,
+        source_amount: u128,
+        swap_token_amounts: &[u128],
+        source_index: usize,
+        destination_index: usize,
+        fees: &Fees,
+    ) -> Option<SwapResult> {
+        let trade_fee = return_fee(source_amount, fees)?;
+        let owner_fee = calculate_fee(
+            source_amount,
+            fees.fixed_fee_numerator.into(),
+            fees.fee_denominator.into(),
+        )?;
+        let total_fees = trade_fee.checked_add(owner_fee)?;
+        let source_amount_less_fees = source_amount.checked_sub(total_fees)?;
+
+        let SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped,
+        } = self.calculator.swap_without_fees(
+            source_amount_less_fees,
+            swap_token_amounts,
+            source_index,
+            destination_index,
+        )?;
+
+        let source_amount_swapped = source_amount_swapped.checked_add(total_fees)?;
+        Some(SwapResult {
+            new_swap_source_amount: swap_token_amounts
+                .get(source_index)?
+                .checked_add(source_amount_swapped)?
+                .checked_sub(owner_fee)?,
+            new_swap_destination_amount: swap_token_amounts
+                .get(destination_index)?
+                .checked_sub(destination_amount_swapped)?,
+            source_amount_swapped,
+            destination_amount_swapped,
+            trade_fee,
+            owner_fee,
+        })
+    }
+
+    /// Pool tokens minted for a deposit of one token.  The trade fee is paid
+    /// on the share of the deposit a balanced pool would swap into the other
+    /// tokens, `(n - 1) / n` of it.
+    pub fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_amounts: &[u128],
+        token_index: usize,
+        pool_supply: u128,
+        fees: &Fees,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let trade_fee = return_fee(
+            swapped_share(source_amount, swap_token_amounts.len())?,
+            fees,
+        )?;
+        let source_amount = source_amount.checked_sub(trade_fee)?;
+        self.calculator.deposit_single_token_type(
+            source_amount,
+            swap_token_amounts,
+            token_index,
+            pool_supply,
+        )
+    }
+
+    /// Pool tokens burnt to withdraw exactly `destination_amount` of one
+    /// token, with the trade fee on the share of it a balanced pool would
+    /// swap out of the other tokens
+    pub fn withdraw_single_token_type_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_token_amounts: &[u128],
+        token_index: usize,
+        pool_supply: u128,
+        fees: &Fees,
+    ) -> Option<u128> {
+        if destination_amount == 0 {
+            return Some(0);
+        }
+        let trade_fee = return_fee(
+            swapped_share(destination_amount, swap_token_amounts.len())?,
+            fees,
+        )?;
+        let destination_amount = destination_amount.checked_add(trade_fee)?;
+        self.calculator.withdraw_single_token_type_exact_out(
+            destination_amount,
+            swap_token_amounts,
+            token_index,
+            pool_supply,
+        )
+    }
+}
+
+/// Fee kept by the pool for its liquidity providers
+fn return_fee(token_amount: u128, fees: &Fees) -> Option<u128> {
+    calculate_fee(
+        token_amount,
+        fees.return_fee_numerator.into(),
+        fees.fee_denominator.into(),
+    )
+}
+
+/// `(n - 1) / n` of `amount`, at least one token
+fn swapped_share(amount: u128, token_count: usize) -> Option<u128> {
+    let token_count = u128::try_from(token_count).ok()?;
+    Some(
+        amount
+            .checked_mul(token_count.checked_sub(1)?)?
+            .checked_div(token_count)?
+            .max(1),
+    )
+}
+
+fn ceil_div(numerator: u128, denominator: u128) -> Option<u128> {
+    if denominator == 0 {
+        return None;
+    }
+    numerator
+        .checked_add(denominator - 1)?
+        .checked_div(denominator)
+}
+
+fn ceil_div_u256(numerator: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    numerator
+        .checked_add(denominator - 1)?
+        .checked_div(denominator)
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+/// Simple implementation of `Clone` which packs and unpacks the curve
+impl Clone for MultiSwapCurve {
+    fn clone(&self) -> Self {
+        let mut packed_self = [0u8; Self::LEN];
+        Pack::pack_into_slice(self, &mut packed_self);
+        MultiSwapCurve::unpack_from_slice(&packed_self).unwrap()
+    }
+}
+
+/// Simple implementation of PartialEq which just compares the curve types and
+/// packed calculator
+impl PartialEq for MultiSwapCurve {
+    fn eq(&self, other: &Self) -> bool {
+        let mut packed_self = [0u8; Self::LEN];
+        Pack::pack_into_slice(self, &mut packed_self);
+        let mut packed_other = [0u8; Self::LEN];
+        Pack::pack_into_slice(other, &mut packed_other);
+        packed_self[..] == packed_other[..]
+    }
+}
+
+impl Sealed for MultiSwapCurve {}
+impl IsInitialized for MultiSwapCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Pack for MultiSwapCurve {
+    /// Size of encoding of all curve parameters, which include fees and any
+    /// other constants used to calculate swaps, deposits, and withdrawals.
+    /// This includes 1 byte for the type, and 64 for the calculator to use as
+    /// it needs.
+    const LEN: usize = 65;
+
+    /// Unpacks a byte buffer into a MultiSwapCurve
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 65];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (curve_type, calculator) = array_refs![input, 1, 64];
+        let curve_type = curve_type[0].try_into()?;
+        Ok(Self {
+            curve_type,
+            calculator: match curve_type {
+                CurveType::Stable => Box::new(MultiStableCurve::unpack_from_slice(calculator)?),
+                CurveType::Weighted => Box::new(MultiWeightedCurve::unpack_from_slice(calculator)?),
+                _ => return Err(AmmError::UnsupportedCurveType.into()),
+            },
+        })
+    }
+
+    /// Pack MultiSwapCurve into a byte buffer
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 65];
+        let (curve_type, calculator) = mut_array_refs![output, 1, 64];
+        curve_type[0] = self.curve_type as u8;
+        *calculator = [0u8; 64];
+        self.calculator.pack_into_slice(&mut calculator[..]);
+    }
+}
+
+/// The StableSwap invariant over `n` tokens,
+/// `A n^n sum(x) + D = A n^n D + D^(n+1) / (n^n prod(x))`, see `StableCurve`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MultiStableCurve {
+    /// Amplification coefficient
+    pub amp: u64,
+}
+
+impl MultiStableCurve {
+    /// `A n^n`
+    fn ann(&self, token_count: usize) -> Option<u128> {
+        let token_count = u128::try_from(token_count).ok()?;
+        u128::from(self.amp).checked_mul(token_count.checked_pow(token_count.try_into().ok()?)?)
+    }
+
+    /// Computes the invariant `D` of the pool by Newton's method, starting
+    /// from `sum(x)`.
+    ///
+    /// The steps grow with the imbalance of the pool, one token 10_000 times
+    /// more or less plentiful than the others still converges within
+    /// `ITERATIONS`.  Returns None past that, or if a token amount is zero.
+    pub fn compute_d(&self, swap_token_amounts: &[u128]) -> Option<u128> {
+        let sum_x = swap_token_amounts
+            .iter()
+            .try_fold(0u128, |sum, amount| sum.checked_add(*amount))?;
+        if sum_x == 0 {
+            return Some(0);
+        }
+        let n_coins = U256::from(swap_token_amounts.len());
+        let ann = U256::from(self.ann(swap_token_amounts.len())?);
+        let leverage = ann.checked_mul(sum_x.into())?;
+        let mut sorted_amounts = swap_token_amounts.to_vec();
+        sorted_amounts.sort_unstable();
+
+        let mut d = U256::from(sum_x);
+        for _ in 0..ITERATIONS {
+            // D^(n+1) / (n^n prod(x)), one token at a time so that it stays
+            // within 256 bits.  The smallest amounts go first, the rounding
+            // of the later divisions is then not scaled up and Newton's
+            // method does not oscillate around D.
+            let mut d_product = d;
+            for swap_token_amount in &sorted_amounts {
+                d_product = d_product
+                    .checked_mul(d)?
+                    .checked_div(U256::from(*swap_token_amount).checked_mul(n_coins)?)?;
+            }
+            let d_previous = d;
+            // D = (Ann S + n D_P) D / ((Ann - 1) D + (n + 1) D_P)
+            let numerator = leverage
+                .checked_add(d_product.checked_mul(n_coins)?)?
+                .checked_mul(d)?;
+            let denominator = ann
+                .checked_sub(1.into())?
+                .checked_mul(d)?
+                .checked_add(d_product.checked_mul(n_coins.checked_add(1.into())?)?)?;
+            d = numerator.checked_div(denominator)?;
+            if abs_diff(d, d_previous) <= 1.into() {
+                return u128::try_from(d).ok();
+            }
+        }
+        None
+    }
+
+    /// Computes the amount of the token at `token_index` which keeps the
+    /// invariant at `d` with the other amounts, by Newton's method on
+    /// `y^2 + (b - D) y = c`.
+    ///
+    /// Returns None if it does not converge within `ITERATIONS` steps.
+    pub fn compute_new_amount(
+        &self,
+        swap_token_amounts: &[u128],
+        token_index: usize,
+        d: u128,
+    ) -> Option<u128> {
+        let n_coins = U256::from(swap_token_amounts.len());
+        let ann = U256::from(self.ann(swap_token_amounts.len())?);
+        let d = U256::from(d);
+
+        // c = D^(n+1) / (n^n prod(x) Ann) and s = sum(x), over the other
+        // tokens.  c is rounded up, which can only raise `y` and keep more
+        // in the pool, smallest amounts first as in `compute_d`.
+        let mut other_amounts: Vec<u128> = swap_token_amounts
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != token_index)
+            .map(|(_, swap_token_amount)| *swap_token_amount)
+            .collect();
+        other_amounts.sort_unstable();
+        let mut c = d;
+        let mut sum_x = U256::zero();
+        for swap_token_amount in other_amounts {
+            let swap_token_amount = U256::from(swap_token_amount);
+            sum_x = sum_x.checked_add(swap_token_amount)?;
+            c = ceil_div_u256(c.checked_mul(d)?, swap_token_amount.checked_mul(n_coins)?)?;
+        }
+        c = ceil_div_u256(c.checked_mul(d)?, ann.checked_mul(n_coins)?)?;
+        // b = s + D / Ann, with D moved to the denominator below
+        let b = sum_x.checked_add(d.checked_div(ann)?)?;
+
+        let mut y = d;
+        for _ in 0..ITERATIONS {
+            let y_previous = y;
+            // y = (y^2 + c) / (2 y + b - D)
+            let numerator = y.checked_mul(y)?.checked_add(c)?;
+            let denominator = y.checked_mul(2.into())?.checked_add(b)?.checked_sub(d)?;
+            y = numerator.checked_div(denominator)?;
+            if abs_diff(y, y_previous) <= 1.into() {
+                return u128::try_from(y).ok();
+            }
+        }
+        None
+    }
+
+    /// Invariant once `delta` is added to, or taken from, the token at
+    /// `token_index`
+    fn compute_d_after(
+        &self,
+        swap_token_amounts: &[u128],
+        token_index: usize,
+        delta: u128,
+        deposit: bool,
+    ) -> Option<u128> {
+        let mut new_amounts = swap_token_amounts.to_vec();
+        let amount = new_amounts.get_mut(token_index)?;
+        *amount = if deposit {
+            amount.checked_add(delta)?
+        } else {
+            amount.checked_sub(delta)?
+        };
+        self.compute_d(&new_amounts)
+    }
+}
+
+impl MultiCurveCalculator for MultiStableCurve {
+    /// Stable curve
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_token_amounts: &[u128],
+        source_index: usize,
+        destination_index: usize,
+    ) -> Option<SwapWithoutFeesResult> {
+        if source_amount == 0 {
+            return Some(SwapWithoutFeesResult {
+                source_amount_swapped: 0,
+                destination_amount_swapped: 0,
+            });
+        }
+        let d = self.compute_d(swap_token_amounts)?;
+        let mut new_amounts = swap_token_amounts.to_vec();
+        let new_swap_source_amount = new_amounts.get_mut(source_index)?;
+        *new_swap_source_amount = new_swap_source_amount.checked_add(source_amount)?;
+        // keep the last token in the pool so rounding in `y` never pays out
+        // more than the invariant allows
+        let new_swap_destination_amount = self
+            .compute_new_amount(&new_amounts, destination_index, d)?
+            .checked_add(1)?;
+        let destination_amount_swapped = swap_token_amounts
+            .get(destination_index)?
+            .checked_sub(new_swap_destination_amount)?;
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped,
+        })
+    }
+
+    /// Pool tokens minted for the growth of the invariant, rounded down
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_amounts: &[u128],
+        token_index: usize,
+        pool_supply: u128,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let d0 = self.compute_d(swap_token_amounts)?;
+        let d1 = self.compute_d_after(swap_token_amounts, token_index, source_amount, true)?;
+        let diff = d1.checked_sub(d0)?;
+        U256::from(pool_supply)
+            .checked_mul(diff.into())?
+            .checked_div(d0.into())
+            .and_then(|pool_tokens| u128::try_from(pool_tokens).ok())
+    }
+
+    /// Pool tokens burnt for the shrinking of the invariant, rounded up
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_token_amounts: &[u128],
+        token_index: usize,
+        pool_supply: u128,
+    ) -> Option<u128> {
+        if destination_amount == 0 {
+            return Some(0);
+        }
+        let d0 = self.compute_d(swap_token_amounts)?;
+        let d1 =
+            self.compute_d_after(swap_token_amounts, token_index, destination_amount, false)?;
+        let diff = d0.checked_sub(d1)?;
+        let d0 = U256::from(d0);
+        let pool_tokens = U256::from(pool_supply)
+            .checked_mul(diff.into())?
+            .checked_add(d0.checked_sub(1.into())?)?
+            .checked_div(d0)?;
+        u128::try_from(pool_tokens).ok()
+    }
+
+    fn validate(&self, _token_count: usize) -> Result<(), AmmError> {
+        if self.amp < MIN_AMP || self.amp > MAX_AMP {
+            Err(AmmError::InvalidCurve)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for MultiStableCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for MultiStableCurve {}
+impl Pack for MultiStableCurve {
+    const LEN: usize = 8;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<MultiStableCurve, ProgramError> {
+        let amp = array_ref![input, 0, 8];
+        Ok(Self {
+            amp: u64::from_le_bytes(*amp),
+        })
+    }
+}
+
+impl DynPack for MultiStableCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let amp = array_mut_ref![output, 0, 8];
+        *amp = self.amp.to_le_bytes();
+    }
+}
+
+/// The Balancer weighted invariant over `n` tokens, `V = prod(x^w)`, see
+/// `WeightedCurve`.  A swap only involves the two tokens traded, single
+/// token deposits and withdrawals weigh the token against all others.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MultiWeightedCurve {
+    /// Weight of each token, zero past the pool's tokens
+    pub weights: [u64; MAX_TOKENS],
+}
+
+impl MultiWeightedCurve {
+    /// Weight of the token at `token_index` and the weight of all tokens
+    fn token_weight(&self, token_index: usize, token_count: usize) -> Option<(u128, u128)> {
+        let total_weight = self
+            .weights
+            .get(..token_count)?
+            .iter()
+            .try_fold(0u128, |total, weight| total.checked_add((*weight).into()))?;
+        Some(((*self.weights.get(token_index)?).into(), total_weight))
+    }
+}
+
+impl MultiCurveCalculator for MultiWeightedCurve {
+    /// Weighted curve, `out = b (1 - (a / (a + in))^(wa / wb))`
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_token_amounts: &[u128],
+        source_index: usize,
+        destination_index: usize,
+    ) -> Option<SwapWithoutFeesResult> {
+        let destination_amount_swapped = weighted::swap_amount(
+            source_amount,
+            *swap_token_amounts.get(source_index)?,
+            *swap_token_amounts.get(destination_index)?,
+            (*self.weights.get(source_index)?).into(),
+            (*self.weights.get(destination_index)?).into(),
+        )?;
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped,
+        })
+    }
+
+    /// `supply ((1 + in / x)^(w / W) - 1)` pool tokens, rounded down
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_amounts: &[u128],
+        token_index: usize,
+        pool_supply: u128,
+    ) -> Option<u128> {
+        let (token_weight, total_weight) =
+            self.token_weight(token_index, swap_token_amounts.len())?;
+        weighted::deposit_pool_tokens(
+            source_amount,
+            *swap_token_amounts.get(token_index)?,
+            token_weight,
+            total_weight,
+            pool_supply,
+        )
+    }
+
+    /// `supply (1 - (1 - out / x)^(w / W))` pool tokens, rounded up
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_token_amounts: &[u128],
+        token_index: usize,
+        pool_supply: u128,
+    ) -> Option<u128> {
+        let (token_weight, total_weight) =
+            self.token_weight(token_index, swap_token_amounts.len())?;
+        weighted::withdraw_pool_tokens(
+            destination_amount,
+            *swap_token_amounts.get(token_index)?,
+            token_weight,
+            total_weight,
+            pool_supply,
+        )
+    }
+
+    fn validate(&self, token_count: usize) -> Result<(), AmmError> {
+        if token_count > MAX_TOKENS {
+            return Err(AmmError::InvalidCurve);
+        }
+        let (weights, unused) = self.weights.split_at(token_count);
+        let low = weights.iter().min().copied().unwrap_or(0);
+        let high = weights.iter().max().copied().unwrap_or(0);
+        if low == 0
+            || high > MAX_WEIGHT
+            || high > low.saturating_mul(MAX_WEIGHT_RATIO)
+            || unused.iter().any(|weight| *weight != 0)
+        {
+            Err(AmmError::InvalidCurve)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for MultiWeightedCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for MultiWeightedCurve {}
+impl Pack for MultiWeightedCurve {
+    const LEN: usize = 8 * MAX_TOKENS;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<MultiWeightedCurve, ProgramError> {
+        let input = array_ref![input, 0, MultiWeightedCurve::LEN];
+        let mut weights = [0u64; MAX_TOKENS];
+        for (weight, bytes) in weights.iter_mut().zip(input.chunks_exact(8)) {
+            *weight = u64::from_le_bytes(*array_ref![bytes, 0, 8]);
+        }
+        Ok(Self { weights })
+    }
+}
+
+impl DynPack for MultiWeightedCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, MultiWeightedCurve::LEN];
+        for (bytes, weight) in output.chunks_exact_mut(8).zip(self.weights.iter()) {
+            bytes.copy_from_slice(&weight.to_le_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{
+        calculator::{CurveCalculator, TradeDirection},
+        stable::StableCurve,
+        weighted::WeightedCurve,
+    };
+    use proptest::prelude::*;
+
+    fn weights(token_weights: &[u64]) -> [u64; MAX_TOKENS] {
+        let mut weights = [0u64; MAX_TOKENS];
+        weights[..token_weights.len()].copy_from_slice(token_weights);
+        weights
+    }
+
+    fn no_fees() -> Fees {
+        Fees {
+            return_fee_numerator: 0,
+            fixed_fee_numerator: 0,
+            fee_denominator: 10_000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        }
+    }
+
+    #[test]
+    fn pack_curves() {
+        for swap_curve in &[
+            MultiSwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Box::new(MultiStableCurve { amp: 100 }),
+            },
+            MultiSwapCurve {
+                curve_type: CurveType::Weighted,
+                calculator: Box::new(MultiWeightedCurve {
+                    weights: weights(&[50, 30, 20]),
+                }),
+            },
+        ] {
+            let mut packed = [0u8; MultiSwapCurve::LEN];
+            Pack::pack_into_slice(swap_curve, &mut packed[..]);
+            let unpacked = MultiSwapCurve::unpack(&packed).unwrap();
+            assert_eq!(*swap_curve, unpacked);
+            assert_eq!(*swap_curve, swap_curve.clone());
+        }
+
+        let mut packed = vec![CurveType::Weighted as u8];
+        for weight in &[50u64, 30, 20, 0, 0, 0, 0, 0] {
+            packed.extend_from_slice(&weight.to_le_bytes());
+        }
+        let unpacked = MultiSwapCurve::unpack(&packed).unwrap();
+        assert_eq!(unpacked.curve_type, CurveType::Weighted);
+
+        // the two token curves have no multi token version
+        let mut packed = [0u8; MultiSwapCurve::LEN];
+        packed[0] = CurveType::ConstantProduct as u8;
+        assert_eq!(
+            MultiSwapCurve::unpack(&packed).unwrap_err(),
+            AmmError::UnsupportedCurveType.into()
+        );
+    }
+
+    #[test]
+    fn validate_curves() {
+        let stable = |amp| MultiStableCurve { amp };
+        assert_eq!(stable(100).validate(3), Ok(()));
+        assert_eq!(stable(MIN_AMP - 1).validate(3), Err(AmmError::InvalidCurve));
+        assert_eq!(stable(MAX_AMP + 1).validate(3), Err(AmmError::InvalidCurve));
+
+        let weighted = |token_weights: &[u64]| MultiWeightedCurve {
+            weights: weights(token_weights),
+        };
+        assert_eq!(weighted(&[50, 30, 20]).validate(3), Ok(()));
+        assert_eq!(weighted(&[1, 49, 49, 1]).validate(4), Ok(()));
+        assert_eq!(
+            weighted(&[1, 50, 49]).validate(3),
+            Err(AmmError::InvalidCurve)
+        );
+        assert_eq!(
+            weighted(&[50, 0, 20]).validate(3),
+            Err(AmmError::InvalidCurve)
+        );
+        // weights past the pool's tokens
+        assert_eq!(
+            weighted(&[50, 30, 20]).validate(2),
+            Err(AmmError::InvalidCurve)
+        );
+        assert_eq!(
+            weighted(&[MAX_WEIGHT + 1, MAX_WEIGHT, MAX_WEIGHT]).validate(3),
+            Err(AmmError::InvalidCurve)
+        );
+    }
+
+    #[test]
+    fn stable_matches_two_token_curve() {
+        let amp = 85;
+        let multi = MultiStableCurve { amp };
+        let pair = StableCurve { amp };
+        for (a, b) in &[(1_000u128, 1_000u128), (1, 1_000_000_000), (1_000_000, 7)] {
+            assert_eq!(multi.compute_d(&[*a, *b]), pair.compute_d(*a, *b));
+            assert_eq!(
+                multi
+                    .swap_without_fees(100, &[*a, *b], 0, 1)
+                    .map(|result| result.destination_amount_swapped),
+                pair.swap_without_fees(100, *a, *b, TradeDirection::AtoB)
+                    .map(|result| result.destination_amount_swapped)
+            );
+        }
+    }
+
+    #[test]
+    fn stable_balanced_invariant() {
+        let curve = MultiStableCurve { amp: 100 };
+        for token_count in MIN_TOKENS..=MAX_TOKENS {
+            let amounts = vec![1_000_000_000u128; token_count];
+            assert_eq!(
+                curve.compute_d(&amounts),
+                Some(1_000_000_000 * token_count as u128)
+            );
+        }
+        // a pool missing a token has no invariant
+        assert_eq!(curve.compute_d(&[1_000, 0, 1_000]), None);
+    }
+
+    #[test]
+    fn stable_converges_when_unbalanced() {
+        for amp in &[MIN_AMP, 100, MAX_AMP] {
+            let curve = MultiStableCurve { amp: *amp };
+            for token_count in MIN_TOKENS..=MAX_TOKENS {
+                // one token 10_000 times more or less plentiful than the others
+                let mut amounts = vec![1_000_000_000_000u128; token_count];
+                amounts[0] *= 10_000;
+                assert!(curve.compute_d(&amounts).is_some());
+                let mut amounts = vec![10_000_000_000_000_000u128; token_count];
+                amounts[0] /= 10_000;
+                assert!(curve.compute_d(&amounts).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn stable_swap_near_peg() {
+        let curve = MultiStableCurve { amp: 100 };
+        let amounts = [
+            1_000_000_000u128,
+            1_000_000_000,
+            1_000_000_000,
+            1_000_000_000,
+        ];
+        let result = curve.swap_without_fees(1_000_000, &amounts, 3, 1).unwrap();
+        assert!(result.destination_amount_swapped < 1_000_000);
+        assert!(result.destination_amount_swapped > 999_000);
+    }
+
+    #[test]
+    fn weighted_matches_two_token_curve() {
+        let multi = MultiWeightedCurve {
+            weights: weights(&[60, 30, 10]),
+        };
+        let pair = WeightedCurve {
+            token_a_weight: 60,
+            token_b_weight: 10,
+        };
+        let amounts = [6_000_000_000u128, 3_000_000_000, 1_000_000_000];
+        // the third token plays no part in a swap between the other two
+        assert_eq!(
+            multi.swap_without_fees(1_000_000, &amounts, 0, 2),
+            pair.swap_without_fees(1_000_000, amounts[0], amounts[2], TradeDirection::AtoB)
+        );
+        // one percent of a reserve is worth more pool tokens the heavier
+        // the token
+        let pool_supply = 1_000_000_000;
+        let minted: Vec<u128> = (0..3)
+            .map(|index| {
+                multi
+                    .deposit_single_token_type(amounts[index] / 100, &amounts, index, pool_supply)
+                    .unwrap()
+            })
+            .collect();
+        // 1.01^(w / W) - 1 of the supply
+        assert_eq!(minted, vec![5_988_055, 2_989_559, 995_528]);
+    }
+
+    #[test]
+    fn swap_fees() {
+        let swap_curve = MultiSwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Box::new(MultiStableCurve { amp: 100 }),
+        };
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10_000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let amounts = [1_000_000u128, 1_000_000, 1_000_000];
+        let result = swap_curve.swap(10_000, &amounts, 0, 2, &fees).unwrap();
+        assert_eq!(result.trade_fee, 25);
+        assert_eq!(result.owner_fee, 5);
+        assert_eq!(result.source_amount_swapped, 10_000);
+        assert_eq!(result.new_swap_source_amount, 1_009_995);
+        assert_eq!(
+            result.new_swap_destination_amount,
+            1_000_000 - result.destination_amount_swapped
+        );
+        let without_fees = swap_curve.swap(9_970, &amounts, 0, 2, &no_fees()).unwrap();
+        assert_eq!(
+            result.destination_amount_swapped,
+            without_fees.destination_amount_swapped
+        );
+    }
+
+    #[test]
+    fn single_token_fees() {
+        let swap_curve = MultiSwapCurve {
+            curve_type: CurveType::Weighted,
+            calculator: Box::new(MultiWeightedCurve {
+                weights: weights(&[1, 1, 1, 1]),
+            }),
+        };
+        let fees = Fees {
+            return_fee_numerator: 100,
+            fixed_fee_numerator: 0,
+            fee_denominator: 10_000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let amounts = [1_000_000_000u128; 4];
+        let pool_supply = 1_000_000_000;
+        // three quarters of the deposit pay the fee
+        assert_eq!(
+            swap_curve.deposit_single_token_type(1_000_000, &amounts, 1, pool_supply, &fees),
+            swap_curve.deposit_single_token_type(992_500, &amounts, 1, pool_supply, &no_fees())
+        );
+        assert_eq!(
+            swap_curve.withdraw_single_token_type_exact_out(
+                1_000_000,
+                &amounts,
+                1,
+                pool_supply,
+                &fees
+            ),
+            swap_curve.withdraw_single_token_type_exact_out(
+                1_007_500,
+                &amounts,
+                1,
+                pool_supply,
+                &no_fees()
+            )
+        );
+    }
+
+    #[test]
+    fn proportional_trading_tokens() {
+        let curve = MultiStableCurve { amp: 100 };
+        let amounts = [1_000u128, 2_000, 3_001];
+        assert_eq!(
+            curve.pool_tokens_to_trading_tokens(10, 100, &amounts, RoundDirection::Floor),
+            Some(vec![100, 200, 300])
+        );
+        assert_eq!(
+            curve.pool_tokens_to_trading_tokens(10, 100, &amounts, RoundDirection::Ceiling),
+            Some(vec![100, 200, 301])
+        );
+    }
+
+    prop_compose! {
+        fn pool_amounts()(
+            amounts in prop::collection::vec(1_000..u64::MAX as u128 / 2, MIN_TOKENS..=MAX_TOKENS)
+        ) -> Vec<u128> {
+            amounts
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn stable_swap_keeps_invariant(
+            amp in 1..1_000u64,
+            amounts in pool_amounts(),
+            source_fraction in 1..1_000u128,
+        ) {
+            let curve = MultiStableCurve { amp };
+            let destination_index = amounts.len() - 1;
+            let source_amount = amounts[0] * source_fraction / 1_000;
+            // very unbalanced pools may not converge
+            if let (Some(d0), Some(result)) = (
+                curve.compute_d(&amounts),
+                curve.swap_without_fees(source_amount, &amounts, 0, destination_index),
+            ) {
+                let mut new_amounts = amounts.clone();
+                new_amounts[0] += source_amount;
+                new_amounts[destination_index] -= result.destination_amount_swapped;
+                if let Some(d1) = curve.compute_d(&new_amounts) {
+                    // D is only exact to a token
+                    prop_assert!(d1 + 1 >= d0);
+                }
+            }
+        }
+
+        #[test]
+        fn deposit_withdraw_single_round_trip(
+            amp in 1..1_000u64,
+            amounts in pool_amounts(),
+            pool_supply in 1_000..u64::MAX as u128,
+            source_fraction in 1..1_000u128,
+            token_weights in prop::collection::vec(1..50u64, MAX_TOKENS),
+        ) {
+            let token_count = amounts.len();
+            let curves: Vec<Box<dyn MultiCurveCalculator>> = vec![
+                Box::new(MultiStableCurve { amp }),
+                Box::new(MultiWeightedCurve {
+                    weights: weights(&token_weights[..token_count]),
+                }),
+            ];
+            for curve in curves {
+                let source_amount = amounts[1] * source_fraction / 3_000;
+                let deposited =
+                    curve.deposit_single_token_type(source_amount, &amounts, 1, pool_supply);
+                if let Some(deposited) = deposited {
+                    let mut new_amounts = amounts.clone();
+                    new_amounts[1] += source_amount;
+                    let burnt = curve.withdraw_single_token_type_exact_out(
+                        source_amount,
+                        &new_amounts,
+                        1,
+                        pool_supply + deposited,
+                    );
+                    if let Some(burnt) = burnt {
+                        prop_assert!(burnt >= deposited);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! The curve.fi invariant calculator.
+
+use crate::{
+    curve::{
+        calculator::{
+            CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+            TradingTokenResult,
+        },
+        constant_product::{
+            deposit_single_token_type, pool_tokens_to_trading_tokens, swap,
+            withdraw_single_token_type_exact_out,
+        },
+    },
+    error::AmmError,
+};
+use arrayref::{array_mut_ref, array_ref};
+use solana_program::{
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+};
+
+/// Offset curve, uses ConstantProduct under the hood, but adds an offset to
+/// one side on swap calculations
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OffsetCurve {
+    /// Amount to offset the token B liquidity account
+    pub token_b_offset: u64,
+}
+
+impl CurveCalculator for OffsetCurve {
+    /// Constant product swap ensures token a * (token b + offset) = constant
+    /// This is guaranteed to work for all values such that:
+    ///  - 1 <= source_amount <= u64::MAX
+    ///  - 1 <= (swap_source_amount * (swap_destination_amount + token_b_offset)) <= u128::MAX
+    ///
+    /// If the offset and token B are both close to u64::MAX, there can be
+    /// overflow errors with the invariant.
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let token_b_offset = u128::from(self.token_b_offset);
+        let swap_source_amount = match trade_direction {
+            TradeDirection::AtoB => swap_source_amount,
+            TradeDirection::BtoA => swap_source_amount.checked_add(token_b_offset)?,
+        };
+        let swap_destination_amount = match trade_direction {
+            TradeDirection::AtoB => swap_destination_amount.checked_add(token_b_offset)?,
+            TradeDirection::BtoA => swap_destination_amount,
+        };
+        swap(source_amount, swap_source_amount, swap_destination_amount)
+    }
+
+    /// The conversion for the offset curve needs to take into account the
+    /// offset
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        let token_b_offset = u128::from(self.token_b_offset);
+        pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount.checked_add(token_b_offset)?,
+            round_direction,
+        )
+    }
+
+    /// Get the amount of pool tokens for the given amount of token A and B,
+    /// taking into account the offset
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let token_b_offset = u128::from(self.token_b_offset);
+        deposit_single_token_type(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount.checked_add(token_b_offset)?,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let token_b_offset = u128::from(self.token_b_offset);
+        withdraw_single_token_type_exact_out(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount.checked_add(token_b_offset)?,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    fn validate(&self) -> Result<(), AmmError> {
+        if self.token_b_offset == 0 {
+            Err(AmmError::InvalidCurve)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn validate_supply(&self, token_a_amount: u64, _token_b_amount: u64) -> Result<(), AmmError> {
+        if token_a_amount == 0 {
+            return Err(AmmError::EmptySupply);
+        }
+        Ok(())
+    }
+
+    /// Offset curves can cause arbitrage opportunities if outside users are
+    /// allowed to deposit.  For example, in the offset curve, if there's swap
+    /// with 1 million of token A against an offset of 2 million token B,
+    /// someone else can deposit 1 million A and 2 million B for LP tokens.
+    /// The pool creator can then use their LP tokens to steal the 2 million B,
+    fn allows_deposits(&self) -> bool {
+        false
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for OffsetCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for OffsetCurve {}
+impl Pack for OffsetCurve {
+    const LEN: usize = 8;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<OffsetCurve, ProgramError> {
+        let token_b_offset = array_ref![input, 0, 8];
+        Ok(Self {
+            token_b_offset: u64::from_le_bytes(*token_b_offset),
+        })
+    }
+}
+
+impl DynPack for OffsetCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let token_b_offset = array_mut_ref![output, 0, 8];
+        *token_b_offset = self.token_b_offset.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_curve() {
+        let token_b_offset = u64::MAX;
+        let curve = OffsetCurve { token_b_offset };
+
+        let mut packed = [0u8; OffsetCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = OffsetCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&token_b_offset.to_le_bytes());
+        let unpacked = OffsetCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    #[test]
+    fn swap_no_offset() {
+        let swap_source_amount: u128 = 1_000;
+        let swap_destination_amount: u128 = 50_000;
+        let source_amount: u128 = 100;
+        let curve = OffsetCurve::default();
+        let result = curve
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, source_amount);
+        assert_eq!(result.destination_amount_swapped, 4545);
+        let result = curve
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, source_amount);
+        assert_eq!(result.destination_amount_swapped, 4545);
+    }
+
+    #[test]
+    fn swap_offset() {
+        let swap_source_amount: u128 = 1_000_000;
+        let swap_destination_amount: u128 = 0;
+        let source_amount: u128 = 100;
+        let token_b_offset = 1_000_000;
+        let curve = OffsetCurve { token_b_offset };
+        // the offset prices token B even though the pool holds none, taking it
+        // out is left to `SwapCurve::swap` to refuse
+        let result = curve
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, source_amount);
+        assert_eq!(result.destination_amount_swapped, 99);
+
+        let result = curve
+            .swap_without_fees(
+                source_amount,
+                swap_destination_amount,
+                swap_source_amount,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, source_amount);
+        assert_eq!(result.destination_amount_swapped, 99);
+    }
+
+    #[test]
+    fn validate_offset() {
+        assert_eq!(
+            OffsetCurve { token_b_offset: 0 }.validate(),
+            Err(AmmError::InvalidCurve)
+        );
+        let curve = OffsetCurve { token_b_offset: 1 };
+        curve.validate().unwrap();
+        curve.validate_supply(1, 0).unwrap();
+        assert_eq!(curve.validate_supply(0, 1), Err(AmmError::EmptySupply));
+        assert!(!curve.allows_deposits());
+    }
+}
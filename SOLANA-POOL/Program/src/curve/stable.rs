@@ -0,0 +1,679 @@
+//! The StableSwap invariant calculator, for pools of pegged tokens
+
+use crate::{
+    curve::calculator::{
+        CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+        TradingTokenResult,
+    },
+    error::AmmError,
+};
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+};
+use spl_math::uint::U256;
+use std::convert::TryFrom;
+
+/// Number of tokens in the pool
+const N_COINS: u8 = 2;
+
+/// Smallest amplification coefficient, the curve is a constant product
+/// around the peg
+pub const MIN_AMP: u64 = 1;
+
+/// Largest amplification coefficient, the curve is close to a constant price
+pub const MAX_AMP: u64 = 1_000_000;
+
+/// Newton's method steps allowed before a calculation is given up.  A
+/// balanced pool converges in a handful of steps and each order of magnitude
+/// of imbalance adds about two, the worst pool u64 reserves can hold, one
+/// token against `u64::MAX` with `MIN_AMP`, needs 40.  Bounding the loops
+/// keeps every calculation within the compute budget.
+pub(crate) const ITERATIONS: u8 = 40;
+
+/// Shortest time, in seconds, over which the amplification can change
+pub const MIN_RAMP_DURATION: i64 = 86_400;
+
+/// Largest factor by which one ramp can grow or shrink the amplification
+pub const MAX_AMP_CHANGE: u64 = 10;
+
+/// Linear change of the amplification coefficient of a stable curve over
+/// time, so that liquidity providers are not drained by an abrupt jump.
+///
+/// A zeroed ramp, the state of pools which never ramped, leaves the curve's
+/// own `amp` in effect.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AmpRamp {
+    /// Amplification at the start of the ramp
+    pub initial_amp: u64,
+    /// Amplification reached at the end of the ramp, and kept afterwards
+    pub target_amp: u64,
+    /// Unix timestamp the ramp starts at
+    pub start_ramp_ts: i64,
+    /// Unix timestamp the ramp ends at
+    pub stop_ramp_ts: i64,
+}
+
+impl AmpRamp {
+    /// Whether the pool ever ramped, the ramp then replaces the curve's `amp`
+    pub fn is_set(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// Whether the amplification is still changing at `unix_timestamp`
+    pub fn is_ramping(&self, unix_timestamp: i64) -> bool {
+        unix_timestamp < self.stop_ramp_ts
+    }
+
+    /// Amplification at `unix_timestamp`, interpolated between the initial
+    /// and the target amplification
+    pub fn amp_at(&self, unix_timestamp: i64) -> Option<u64> {
+        if unix_timestamp >= self.stop_ramp_ts {
+            return Some(self.target_amp);
+        }
+        if unix_timestamp <= self.start_ramp_ts {
+            return Some(self.initial_amp);
+        }
+        let elapsed = u128::try_from(unix_timestamp.checked_sub(self.start_ramp_ts)?).ok()?;
+        let duration = u128::try_from(self.stop_ramp_ts.checked_sub(self.start_ramp_ts)?).ok()?;
+        let initial_amp = u128::from(self.initial_amp);
+        let target_amp = u128::from(self.target_amp);
+        let amp = if target_amp > initial_amp {
+            initial_amp.checked_add(
+                target_amp
+                    .checked_sub(initial_amp)?
+                    .checked_mul(elapsed)?
+                    .checked_div(duration)?,
+            )?
+        } else {
+            initial_amp.checked_sub(
+                initial_amp
+                    .checked_sub(target_amp)?
+                    .checked_mul(elapsed)?
+                    .checked_div(duration)?,
+            )?
+        };
+        u64::try_from(amp).ok()
+    }
+
+    /// Checks the target is a valid amplification, reached slowly enough
+    pub fn validate(&self) -> Result<(), AmmError> {
+        StableCurve {
+            amp: self.target_amp,
+        }
+        .validate()?;
+        let duration = self
+            .stop_ramp_ts
+            .checked_sub(self.start_ramp_ts)
+            .ok_or(AmmError::InvalidAmpRamp)?;
+        if duration < MIN_RAMP_DURATION
+            || self.target_amp > self.initial_amp.saturating_mul(MAX_AMP_CHANGE)
+            || self.initial_amp > self.target_amp.saturating_mul(MAX_AMP_CHANGE)
+        {
+            return Err(AmmError::InvalidAmpRamp);
+        }
+        Ok(())
+    }
+}
+
+impl Sealed for AmpRamp {}
+impl IsInitialized for AmpRamp {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Pack for AmpRamp {
+    const LEN: usize = 32;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 32];
+        let (initial_amp, target_amp, start_ramp_ts, stop_ramp_ts) =
+            mut_array_refs![output, 8, 8, 8, 8];
+        *initial_amp = self.initial_amp.to_le_bytes();
+        *target_amp = self.target_amp.to_le_bytes();
+        *start_ramp_ts = self.start_ramp_ts.to_le_bytes();
+        *stop_ramp_ts = self.stop_ramp_ts.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<AmpRamp, ProgramError> {
+        let input = array_ref![input, 0, 32];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (initial_amp, target_amp, start_ramp_ts, stop_ramp_ts) = array_refs![input, 8, 8, 8, 8];
+        Ok(Self {
+            initial_amp: u64::from_le_bytes(*initial_amp),
+            target_amp: u64::from_le_bytes(*target_amp),
+            start_ramp_ts: i64::from_le_bytes(*start_ramp_ts),
+            stop_ramp_ts: i64::from_le_bytes(*stop_ramp_ts),
+        })
+    }
+}
+
+/// StableCurve struct implementing CurveCalculator
+///
+/// The invariant `A n^n sum(x) + D = A n^n D + D^(n+1) / (n^n prod(x))` is
+/// flat around the peg, where it trades close to one for one, and moves to a
+/// constant product as the pool gets unbalanced.  `amp` is `A`, an `AmpRamp`
+/// in the pool state replaces it while the amplification changes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StableCurve {
+    /// Amplification coefficient
+    pub amp: u64,
+}
+
+impl StableCurve {
+    /// `A n^n`
+    fn ann(&self) -> Option<u128> {
+        u128::from(self.amp).checked_mul(u128::from(N_COINS).checked_pow(N_COINS.into())?)
+    }
+
+    /// Computes the invariant `D` of the pool from the two token amounts by
+    /// Newton's method, starting from `sum(x)`.
+    ///
+    /// Returns None if it does not converge within `ITERATIONS` steps.
+    pub fn compute_d(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> Option<u128> {
+        let sum_x = swap_token_a_amount.checked_add(swap_token_b_amount)?;
+        if sum_x == 0 {
+            return Some(0);
+        }
+        let n_coins = U256::from(N_COINS);
+        let ann = U256::from(self.ann()?);
+        // n^n prod(x), fits as both amounts fit in a u128
+        let product_times_coins = U256::from(swap_token_a_amount)
+            .checked_mul(swap_token_b_amount.into())?
+            .checked_mul(n_coins.checked_pow(n_coins)?)?;
+        let leverage = ann.checked_mul(sum_x.into())?;
+
+        let mut d = U256::from(sum_x);
+        for _ in 0..ITERATIONS {
+            // D^(n+1) / (n^n prod(x))
+            let d_product = d
+                .checked_mul(d)?
+                .checked_mul(d)?
+                .checked_div(product_times_coins)?;
+            let d_previous = d;
+            // D = (Ann S + n D_P) D / ((Ann - 1) D + (n + 1) D_P)
+            let numerator = leverage
+                .checked_add(d_product.checked_mul(n_coins)?)?
+                .checked_mul(d)?;
+            let denominator = ann
+                .checked_sub(1.into())?
+                .checked_mul(d)?
+                .checked_add(d_product.checked_mul(n_coins.checked_add(1.into())?)?)?;
+            d = numerator.checked_div(denominator)?;
+            if abs_diff(d, d_previous) <= 1.into() {
+                return u128::try_from(d).ok();
+            }
+        }
+        None
+    }
+
+    /// Computes the amount `y` of the other token which keeps the invariant
+    /// at `d` once the pool holds `new_source_amount`, by Newton's method on
+    /// `y^2 + (b - D) y = c`, starting from `D`.
+    ///
+    /// Returns None if it does not converge within `ITERATIONS` steps.
+    pub fn compute_new_destination_amount(&self, new_source_amount: u128, d: u128) -> Option<u128> {
+        let n_coins = U256::from(N_COINS);
+        let ann = U256::from(self.ann()?);
+        let d = U256::from(d);
+        let new_source_amount = U256::from(new_source_amount);
+
+        // c = D^(n+1) / (n^n x Ann)
+        let c = d
+            .checked_mul(d)?
+            .checked_div(new_source_amount.checked_mul(n_coins)?)?
+            .checked_mul(d)?
+            .checked_div(ann.checked_mul(n_coins)?)?;
+        // b = x + D / Ann, with D moved to the denominator below
+        let b = new_source_amount.checked_add(d.checked_div(ann)?)?;
+
+        let mut y = d;
+        for _ in 0..ITERATIONS {
+            let y_previous = y;
+            // y = (y^2 + c) / (2 y + b - D)
+            let numerator = y.checked_mul(y)?.checked_add(c)?;
+            let denominator = y.checked_mul(2.into())?.checked_add(b)?.checked_sub(d)?;
+            y = numerator.checked_div(denominator)?;
+            if abs_diff(y, y_previous) <= 1.into() {
+                return u128::try_from(y).ok();
+            }
+        }
+        None
+    }
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+fn ceil_div(numerator: u128, denominator: u128) -> Option<u128> {
+    if denominator == 0 {
+        return None;
+    }
+    numerator
+        .checked_add(denominator - 1)?
+        .checked_div(denominator)
+}
+
+impl CurveCalculator for StableCurve {
+    /// Stable curve
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        _trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        if source_amount == 0 {
+            return Some(SwapWithoutFeesResult {
+                source_amount_swapped: 0,
+                destination_amount_swapped: 0,
+            });
+        }
+        let d = self.compute_d(swap_source_amount, swap_destination_amount)?;
+        let new_swap_source_amount = swap_source_amount.checked_add(source_amount)?;
+        // keep the last token in the pool so rounding in `y` never pays out
+        // more than the invariant allows
+        let new_swap_destination_amount = self
+            .compute_new_destination_amount(new_swap_source_amount, d)?
+            .checked_add(1)?;
+        let destination_amount_swapped =
+            swap_destination_amount.checked_sub(new_swap_destination_amount)?;
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped,
+        })
+    }
+
+    /// Pool tokens are a share of both reserves whatever the curve, as for
+    /// the constant product
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        let (token_a_amount, token_b_amount) = match round_direction {
+            RoundDirection::Floor => (
+                pool_tokens
+                    .checked_mul(swap_token_a_amount)?
+                    .checked_div(pool_token_supply)?,
+                pool_tokens
+                    .checked_mul(swap_token_b_amount)?
+                    .checked_div(pool_token_supply)?,
+            ),
+            RoundDirection::Ceiling => (
+                ceil_div(
+                    pool_tokens.checked_mul(swap_token_a_amount)?,
+                    pool_token_supply,
+                )?,
+                ceil_div(
+                    pool_tokens.checked_mul(swap_token_b_amount)?,
+                    pool_token_supply,
+                )?,
+            ),
+        };
+        Some(TradingTokenResult {
+            token_a_amount,
+            token_b_amount,
+        })
+    }
+
+    /// Pool tokens minted for the growth of the invariant, rounded down
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let d0 = self.compute_d(swap_token_a_amount, swap_token_b_amount)?;
+        let d1 = match trade_direction {
+            TradeDirection::AtoB => self.compute_d(
+                swap_token_a_amount.checked_add(source_amount)?,
+                swap_token_b_amount,
+            )?,
+            TradeDirection::BtoA => self.compute_d(
+                swap_token_a_amount,
+                swap_token_b_amount.checked_add(source_amount)?,
+            )?,
+        };
+        let diff = d1.checked_sub(d0)?;
+        U256::from(pool_supply)
+            .checked_mul(diff.into())?
+            .checked_div(d0.into())
+            .and_then(|pool_tokens| u128::try_from(pool_tokens).ok())
+    }
+
+    /// Pool tokens burnt for the shrinking of the invariant, rounded up
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let d0 = self.compute_d(swap_token_a_amount, swap_token_b_amount)?;
+        let d1 = match trade_direction {
+            TradeDirection::AtoB => self.compute_d(
+                swap_token_a_amount.checked_sub(source_amount)?,
+                swap_token_b_amount,
+            )?,
+            TradeDirection::BtoA => self.compute_d(
+                swap_token_a_amount,
+                swap_token_b_amount.checked_sub(source_amount)?,
+            )?,
+        };
+        let diff = d0.checked_sub(d1)?;
+        let d0 = U256::from(d0);
+        let pool_tokens = U256::from(pool_supply)
+            .checked_mul(diff.into())?
+            .checked_add(d0.checked_sub(1.into())?)?
+            .checked_div(d0)?;
+        u128::try_from(pool_tokens).ok()
+    }
+
+    fn validate(&self) -> Result<(), AmmError> {
+        if self.amp < MIN_AMP || self.amp > MAX_AMP {
+            Err(AmmError::InvalidCurve)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for StableCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for StableCurve {}
+impl Pack for StableCurve {
+    const LEN: usize = 8;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<StableCurve, ProgramError> {
+        let amp = array_ref![input, 0, 8];
+        Ok(Self {
+            amp: u64::from_le_bytes(*amp),
+        })
+    }
+}
+
+impl DynPack for StableCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let amp = array_mut_ref![output, 0, 8];
+        *amp = self.amp.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn pack_curve() {
+        let amp = 100;
+        let curve = StableCurve { amp };
+
+        let mut packed = [0u8; StableCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = StableCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        let packed = amp.to_le_bytes().to_vec();
+        let unpacked = StableCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    #[test]
+    fn validate_amp() {
+        assert_eq!(
+            StableCurve { amp: 0 }.validate(),
+            Err(AmmError::InvalidCurve)
+        );
+        assert_eq!(
+            StableCurve { amp: MAX_AMP + 1 }.validate(),
+            Err(AmmError::InvalidCurve)
+        );
+        assert_eq!(StableCurve { amp: MIN_AMP }.validate(), Ok(()));
+        assert_eq!(StableCurve { amp: MAX_AMP }.validate(), Ok(()));
+    }
+
+    #[test]
+    fn balanced_invariant() {
+        // a balanced pool's invariant is the sum of its tokens
+        for amp in &[MIN_AMP, 100, MAX_AMP] {
+            let curve = StableCurve { amp: *amp };
+            assert_eq!(curve.compute_d(0, 0), Some(0));
+            assert_eq!(curve.compute_d(1_000_000, 1_000_000), Some(2_000_000));
+            let d = curve.compute_d(u64::MAX.into(), u64::MAX.into()).unwrap();
+            assert_eq!(d, 2 * u128::from(u64::MAX));
+        }
+    }
+
+    #[test]
+    fn swap_near_peg() {
+        let swap_source_amount = 1_000_000_000;
+        let swap_destination_amount = 1_000_000_000;
+        let source_amount = 1_000_000;
+        let swap = |amp| {
+            StableCurve { amp }
+                .swap_without_fees(
+                    source_amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    TradeDirection::AtoB,
+                )
+                .unwrap()
+                .destination_amount_swapped
+        };
+        // the higher the amplification, the closer to one for one
+        let low = swap(1);
+        let high = swap(1_000);
+        assert!(low < high);
+        assert!(high < source_amount);
+        assert!(source_amount - high < 10);
+        // still better than the constant product
+        let constant_product = swap_destination_amount
+            - (swap_source_amount * swap_destination_amount / (swap_source_amount + source_amount)
+                + 1);
+        assert!(low >= constant_product);
+    }
+
+    #[test]
+    fn converges_when_unbalanced() {
+        for amp in &[MIN_AMP, 100, MAX_AMP] {
+            let curve = StableCurve { amp: *amp };
+            for (a, b) in &[
+                (1, u64::MAX as u128),
+                (u64::MAX as u128, 1),
+                (1_000, 1_000_000_000_000_000),
+            ] {
+                let d = curve.compute_d(*a, *b).unwrap();
+                assert!(d <= a + b);
+                curve.compute_new_destination_amount(*a, d).unwrap();
+                curve.compute_new_destination_amount(*b, d).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn cannot_drain_pool() {
+        let curve = StableCurve { amp: 100 };
+        let result = curve
+            .swap_without_fees(u64::MAX.into(), 1_000, 1_000, TradeDirection::AtoB)
+            .unwrap();
+        assert!(result.destination_amount_swapped < 1_000);
+    }
+
+    #[test]
+    fn pack_amp_ramp() {
+        let amp_ramp = AmpRamp {
+            initial_amp: 100,
+            target_amp: 1_000,
+            start_ramp_ts: 1_600_000_000,
+            stop_ramp_ts: 1_600_086_400,
+        };
+        let mut packed = [0u8; AmpRamp::LEN];
+        Pack::pack_into_slice(&amp_ramp, &mut packed[..]);
+        assert_eq!(AmpRamp::unpack(&packed).unwrap(), amp_ramp);
+        assert!(!AmpRamp::unpack(&[0u8; AmpRamp::LEN]).unwrap().is_set());
+    }
+
+    #[test]
+    fn amp_ramp_interpolation() {
+        let start_ramp_ts = 1_600_000_000;
+        let stop_ramp_ts = start_ramp_ts + MIN_RAMP_DURATION;
+        let ramp = |initial_amp, target_amp| AmpRamp {
+            initial_amp,
+            target_amp,
+            start_ramp_ts,
+            stop_ramp_ts,
+        };
+
+        let up = ramp(100, 1_000);
+        assert_eq!(up.amp_at(0), Some(100));
+        assert_eq!(up.amp_at(start_ramp_ts - 1), Some(100));
+        assert_eq!(up.amp_at(start_ramp_ts), Some(100));
+        assert_eq!(up.amp_at(start_ramp_ts + 1), Some(100));
+        assert_eq!(up.amp_at(start_ramp_ts + MIN_RAMP_DURATION / 2), Some(550));
+        assert_eq!(up.amp_at(stop_ramp_ts - 1), Some(999));
+        assert_eq!(up.amp_at(stop_ramp_ts), Some(1_000));
+        assert_eq!(up.amp_at(i64::MAX), Some(1_000));
+        assert!(up.is_ramping(stop_ramp_ts - 1));
+        assert!(!up.is_ramping(stop_ramp_ts));
+
+        let down = ramp(1_000, 100);
+        assert_eq!(down.amp_at(start_ramp_ts), Some(1_000));
+        assert_eq!(down.amp_at(start_ramp_ts + 1), Some(1_000));
+        assert_eq!(
+            down.amp_at(start_ramp_ts + MIN_RAMP_DURATION / 2),
+            Some(550)
+        );
+        assert_eq!(down.amp_at(stop_ramp_ts - 1), Some(101));
+        assert_eq!(down.amp_at(stop_ramp_ts), Some(100));
+
+        // a stopped ramp keeps the amplification it reached
+        let stopped = AmpRamp {
+            initial_amp: 550,
+            target_amp: 550,
+            start_ramp_ts: start_ramp_ts + 1,
+            stop_ramp_ts: start_ramp_ts + 1,
+        };
+        assert_eq!(stopped.amp_at(start_ramp_ts), Some(550));
+        assert_eq!(stopped.amp_at(stop_ramp_ts), Some(550));
+    }
+
+    #[test]
+    fn validate_amp_ramp() {
+        let start_ramp_ts = 1_600_000_000;
+        let ramp = |initial_amp, target_amp, duration| AmpRamp {
+            initial_amp,
+            target_amp,
+            start_ramp_ts,
+            stop_ramp_ts: start_ramp_ts + duration,
+        };
+        assert_eq!(ramp(100, 1_000, MIN_RAMP_DURATION).validate(), Ok(()));
+        assert_eq!(ramp(100, 10, MIN_RAMP_DURATION).validate(), Ok(()));
+        assert_eq!(
+            ramp(100, 1_000, MIN_RAMP_DURATION - 1).validate(),
+            Err(AmmError::InvalidAmpRamp)
+        );
+        assert_eq!(
+            ramp(100, 1_001, MIN_RAMP_DURATION).validate(),
+            Err(AmmError::InvalidAmpRamp)
+        );
+        assert_eq!(
+            ramp(100, 9, MIN_RAMP_DURATION).validate(),
+            Err(AmmError::InvalidAmpRamp)
+        );
+        assert_eq!(
+            ramp(MAX_AMP, MAX_AMP + 1, MIN_RAMP_DURATION).validate(),
+            Err(AmmError::InvalidCurve)
+        );
+        assert_eq!(
+            ramp(1, 0, MIN_RAMP_DURATION).validate(),
+            Err(AmmError::InvalidCurve)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn swap_keeps_invariant(
+            amp in MIN_AMP..=MAX_AMP,
+            swap_source_amount in 1_000..u64::MAX as u128 / 2,
+            swap_destination_amount in 1_000..u64::MAX as u128 / 2,
+            source_amount in 1..u64::MAX as u128 / 2,
+        ) {
+            let curve = StableCurve { amp };
+            let d0 = curve.compute_d(swap_source_amount, swap_destination_amount).unwrap();
+            let result = curve
+                .swap_without_fees(
+                    source_amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    TradeDirection::AtoB,
+                )
+                .unwrap();
+            let d1 = curve
+                .compute_d(
+                    swap_source_amount + result.source_amount_swapped,
+                    swap_destination_amount - result.destination_amount_swapped,
+                )
+                .unwrap();
+            // the invariant is only found within one token
+            prop_assert!(d1 + 1 >= d0);
+        }
+
+        #[test]
+        fn deposit_withdraw_single_round_trip(
+            amp in MIN_AMP..=MAX_AMP,
+            swap_token_a_amount in 1_000..u32::MAX as u128,
+            swap_token_b_amount in 1_000..u32::MAX as u128,
+            pool_supply in 1_000..u32::MAX as u128,
+            source_amount in 1..u32::MAX as u128,
+        ) {
+            let curve = StableCurve { amp };
+            let deposited = curve
+                .deposit_single_token_type(
+                    source_amount,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    pool_supply,
+                    TradeDirection::AtoB,
+                )
+                .unwrap();
+            let burnt = curve
+                .withdraw_single_token_type_exact_out(
+                    source_amount,
+                    swap_token_a_amount + source_amount,
+                    swap_token_b_amount,
+                    pool_supply + deposited,
+                    TradeDirection::AtoB,
+                )
+                .unwrap();
+            // withdrawing what was deposited never costs less pool tokens
+            // than the deposit minted
+            prop_assert!(burnt >= deposited);
+        }
+    }
+}
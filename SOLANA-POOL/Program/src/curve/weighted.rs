@@ -0,0 +1,686 @@
+//! The Balancer weighted invariant calculator, for pools which do not hold
+//! the same value of each token
+
+use crate::{
+    curve::calculator::{
+        CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+        TradingTokenResult,
+    },
+    error::AmmError,
+};
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+};
+
+/// Fixed point one, the power function works on values scaled by it
+const ONE: u128 = 1_000_000_000_000_000_000;
+
+/// Terms of the power series smaller than this are not computed, which
+/// costs at most 1e-10 of the amount traded
+const POW_PRECISION: u128 = ONE / 10_000_000_000;
+
+/// The series terms shrink at least twice as fast at each step, so about 34
+/// steps reach `POW_PRECISION`.  Bounding the loop keeps every calculation
+/// within the compute budget.
+const POW_ITERATIONS: u128 = 40;
+
+/// Largest weight of a token
+pub const MAX_WEIGHT: u64 = 1_000_000;
+
+/// Largest ratio between the two weights, 98/2
+pub const MAX_WEIGHT_RATIO: u64 = 49;
+
+/// WeightedCurve struct implementing CurveCalculator
+///
+/// The invariant is `V = a^wa b^wb`, so the pool holds a `wa / (wa + wb)`
+/// share of its value in token A.  Equal weights give the constant product.
+///
+/// The power function is only accurate for bases close to one, so a swap or
+/// single sided deposit cannot add more than half of the reserve of the
+/// token it brings in, and a single sided withdrawal cannot take more than a
+/// third of the reserve out.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WeightedCurve {
+    /// Weight of token A
+    pub token_a_weight: u64,
+    /// Weight of token B
+    pub token_b_weight: u64,
+}
+
+impl WeightedCurve {
+    /// Weights of the token going into the pool and the one going out
+    fn weights(&self, trade_direction: TradeDirection) -> (u128, u128) {
+        match trade_direction {
+            TradeDirection::AtoB => (self.token_a_weight.into(), self.token_b_weight.into()),
+            TradeDirection::BtoA => (self.token_b_weight.into(), self.token_a_weight.into()),
+        }
+    }
+
+    /// Reserve and weight of the token deposited or withdrawn, with the
+    /// weight of both tokens
+    fn single_token(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<(u128, u128, u128)> {
+        let total_weight =
+            u128::from(self.token_a_weight).checked_add(self.token_b_weight.into())?;
+        Some(match trade_direction {
+            TradeDirection::AtoB => (
+                swap_token_a_amount,
+                self.token_a_weight.into(),
+                total_weight,
+            ),
+            TradeDirection::BtoA => (
+                swap_token_b_amount,
+                self.token_b_weight.into(),
+                total_weight,
+            ),
+        })
+    }
+}
+
+/// `a * b`, fixed point
+fn mul(a: u128, b: u128, round_direction: RoundDirection) -> Option<u128> {
+    div_rounded(a.checked_mul(b)?, ONE, round_direction)
+}
+
+/// `a / b`, fixed point
+fn div(a: u128, b: u128, round_direction: RoundDirection) -> Option<u128> {
+    div_rounded(a.checked_mul(ONE)?, b, round_direction)
+}
+
+fn div_rounded(
+    numerator: u128,
+    denominator: u128,
+    round_direction: RoundDirection,
+) -> Option<u128> {
+    match round_direction {
+        RoundDirection::Floor => numerator.checked_div(denominator),
+        RoundDirection::Ceiling => ceil_div(numerator, denominator),
+    }
+}
+
+fn ceil_div(numerator: u128, denominator: u128) -> Option<u128> {
+    if denominator == 0 {
+        return None;
+    }
+    numerator
+        .checked_add(denominator - 1)?
+        .checked_div(denominator)
+}
+
+/// `base^exponent` for an integer exponent, by squaring.  Rounding each
+/// product the same way rounds the result that way, as all values are
+/// positive.
+fn pow_int(base: u128, exponent: u128, round_direction: RoundDirection) -> Option<u128> {
+    let mut result = ONE;
+    let mut base = base;
+    let mut exponent = exponent;
+    while exponent > 0 {
+        if exponent % 2 == 1 {
+            result = mul(result, base, round_direction)?;
+        }
+        exponent /= 2;
+        if exponent > 0 {
+            base = mul(base, base, round_direction)?;
+        }
+    }
+    Some(result)
+}
+
+/// `base^(numerator / denominator)` for a fraction below one and a base
+/// within a half of one, by the binomial series of `(1 + x)^f`.
+///
+/// The result is a bound on the exact power in `round_direction`, whatever
+/// the number of terms added:
+/// * below one all the terms are negative, so the truncated sum is above
+///   the power, and taking off the last term once more puts it below as the
+///   rest of the series adds up to less than that term
+/// * above one the terms alternate and shrink, so the sum is above the power
+///   after a positive term and below it after a negative one
+fn pow_frac(
+    base: u128,
+    numerator: u128,
+    denominator: u128,
+    round_direction: RoundDirection,
+) -> Option<u128> {
+    if numerator == 0 {
+        return Some(ONE);
+    }
+    let (x, negative) = if base < ONE {
+        (ONE - base, true)
+    } else {
+        (base - ONE, false)
+    };
+    if x > ONE / 2 {
+        return None;
+    }
+
+    // `|C(f, k) x^k|` rounded down and up, the lower bound is used for the
+    // terms moving the sum toward `round_direction` and the upper one for
+    // the others
+    let mut term_low = ONE;
+    let mut term_high = ONE;
+    let mut sum_low = ONE;
+    let mut sum_high = ONE;
+    for k in 1..=POW_ITERATIONS {
+        // |f - (k - 1)| / k
+        let previous = k.checked_sub(1)?.checked_mul(denominator)?;
+        let factor = numerator.max(previous) - numerator.min(previous);
+        let term_denominator = k.checked_mul(denominator)?;
+        term_low = mul(term_low, x, RoundDirection::Floor)?
+            .checked_mul(factor)?
+            .checked_div(term_denominator)?;
+        term_high = ceil_div(
+            mul(term_high, x, RoundDirection::Ceiling)?.checked_mul(factor)?,
+            term_denominator,
+        )?;
+        let subtract = negative || k % 2 == 0;
+        if subtract {
+            sum_low = sum_low.checked_sub(term_high)?;
+            sum_high = sum_high.checked_sub(term_low)?;
+        } else {
+            sum_low = sum_low.checked_add(term_low)?;
+            sum_high = sum_high.checked_add(term_high)?;
+        }
+
+        if term_high < POW_PRECISION {
+            match (round_direction, negative) {
+                (RoundDirection::Ceiling, true) => return Some(sum_high),
+                (RoundDirection::Floor, true) => return sum_low.checked_sub(term_high),
+                (RoundDirection::Ceiling, false) if !subtract => return Some(sum_high),
+                (RoundDirection::Floor, false) if subtract => return Some(sum_low),
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+/// `base^(numerator / denominator)`, fixed point, rounded in
+/// `round_direction`
+fn pow(
+    base: u128,
+    numerator: u128,
+    denominator: u128,
+    round_direction: RoundDirection,
+) -> Option<u128> {
+    let whole = pow_int(base, numerator.checked_div(denominator)?, round_direction)?;
+    let fraction = pow_frac(
+        base,
+        numerator.checked_rem(denominator)?,
+        denominator,
+        round_direction,
+    )?;
+    mul(whole, fraction, round_direction)
+}
+
+/// Destination amount paid for `source_amount` between two tokens of a
+/// weighted pool, `out = b (1 - (a / (a + in))^(wa / wb))` rounded down.
+/// The other tokens of the pool play no part.
+pub(crate) fn swap_amount(
+    source_amount: u128,
+    swap_source_amount: u128,
+    swap_destination_amount: u128,
+    source_weight: u128,
+    destination_weight: u128,
+) -> Option<u128> {
+    if source_amount > swap_source_amount.checked_div(2)? {
+        return None;
+    }
+    // the pool keeps more of the destination token the higher the power
+    let base = div(
+        swap_source_amount,
+        swap_source_amount.checked_add(source_amount)?,
+        RoundDirection::Ceiling,
+    )?;
+    let power = pow(
+        base,
+        source_weight,
+        destination_weight,
+        RoundDirection::Ceiling,
+    )?;
+    mul(
+        swap_destination_amount,
+        ONE.saturating_sub(power),
+        RoundDirection::Floor,
+    )
+}
+
+/// Pool tokens minted for `source_amount` of a token of weight
+/// `token_weight` out of `total_weight`, `supply ((1 + in / a)^(wa / w) - 1)`
+/// rounded down
+pub(crate) fn deposit_pool_tokens(
+    source_amount: u128,
+    swap_token_amount: u128,
+    token_weight: u128,
+    total_weight: u128,
+    pool_supply: u128,
+) -> Option<u128> {
+    if source_amount > swap_token_amount.checked_div(2)? {
+        return None;
+    }
+    let base = div(
+        swap_token_amount.checked_add(source_amount)?,
+        swap_token_amount,
+        RoundDirection::Floor,
+    )?;
+    let power = pow(base, token_weight, total_weight, RoundDirection::Floor)?;
+    mul(pool_supply, power.checked_sub(ONE)?, RoundDirection::Floor)
+}
+
+/// Pool tokens burnt for `destination_amount` of a token of weight
+/// `token_weight` out of `total_weight`,
+/// `supply (1 - (1 - out / a)^(wa / w))` rounded up
+pub(crate) fn withdraw_pool_tokens(
+    destination_amount: u128,
+    swap_token_amount: u128,
+    token_weight: u128,
+    total_weight: u128,
+    pool_supply: u128,
+) -> Option<u128> {
+    if destination_amount > swap_token_amount.checked_div(3)? {
+        return None;
+    }
+    let base = div(
+        swap_token_amount.checked_sub(destination_amount)?,
+        swap_token_amount,
+        RoundDirection::Floor,
+    )?;
+    let power = pow(base, token_weight, total_weight, RoundDirection::Floor)?;
+    mul(
+        pool_supply,
+        ONE.checked_sub(power)?,
+        RoundDirection::Ceiling,
+    )
+}
+
+impl CurveCalculator for WeightedCurve {
+    /// Weighted curve, `out = b (1 - (a / (a + in))^(wa / wb))`
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let (source_weight, destination_weight) = self.weights(trade_direction);
+        let destination_amount_swapped = swap_amount(
+            source_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            source_weight,
+            destination_weight,
+        )?;
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped,
+        })
+    }
+
+    /// Pool tokens are a share of both reserves whatever the curve, as for
+    /// the constant product
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        let token_a_amount = div_rounded(
+            pool_tokens.checked_mul(swap_token_a_amount)?,
+            pool_token_supply,
+            round_direction,
+        )?;
+        let token_b_amount = div_rounded(
+            pool_tokens.checked_mul(swap_token_b_amount)?,
+            pool_token_supply,
+            round_direction,
+        )?;
+        Some(TradingTokenResult {
+            token_a_amount,
+            token_b_amount,
+        })
+    }
+
+    /// `supply ((1 + in / a)^(wa / w) - 1)` pool tokens, rounded down
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let (swap_token_amount, token_weight, total_weight) =
+            self.single_token(swap_token_a_amount, swap_token_b_amount, trade_direction)?;
+        deposit_pool_tokens(
+            source_amount,
+            swap_token_amount,
+            token_weight,
+            total_weight,
+            pool_supply,
+        )
+    }
+
+    /// `supply (1 - (1 - out / a)^(wa / w))` pool tokens, rounded up
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let (swap_token_amount, token_weight, total_weight) =
+            self.single_token(swap_token_a_amount, swap_token_b_amount, trade_direction)?;
+        withdraw_pool_tokens(
+            source_amount,
+            swap_token_amount,
+            token_weight,
+            total_weight,
+            pool_supply,
+        )
+    }
+
+    fn validate(&self) -> Result<(), AmmError> {
+        let (low, high) = if self.token_a_weight < self.token_b_weight {
+            (self.token_a_weight, self.token_b_weight)
+        } else {
+            (self.token_b_weight, self.token_a_weight)
+        };
+        if low == 0 || high > MAX_WEIGHT || high > low.saturating_mul(MAX_WEIGHT_RATIO) {
+            Err(AmmError::InvalidCurve)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for WeightedCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for WeightedCurve {}
+impl Pack for WeightedCurve {
+    const LEN: usize = 16;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<WeightedCurve, ProgramError> {
+        let input = array_ref![input, 0, 16];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (token_a_weight, token_b_weight) = array_refs![input, 8, 8];
+        Ok(Self {
+            token_a_weight: u64::from_le_bytes(*token_a_weight),
+            token_b_weight: u64::from_le_bytes(*token_b_weight),
+        })
+    }
+}
+
+impl DynPack for WeightedCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 16];
+        let (token_a_weight, token_b_weight) = mut_array_refs![output, 8, 8];
+        *token_a_weight = self.token_a_weight.to_le_bytes();
+        *token_b_weight = self.token_b_weight.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::constant_product::ConstantProductCurve;
+    use proptest::prelude::*;
+
+    fn to_f64(value: u128) -> f64 {
+        value as f64 / ONE as f64
+    }
+
+    #[test]
+    fn pack_curve() {
+        let curve = WeightedCurve {
+            token_a_weight: 80,
+            token_b_weight: 20,
+        };
+
+        let mut packed = [0u8; WeightedCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = WeightedCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&80u64.to_le_bytes());
+        packed.extend_from_slice(&20u64.to_le_bytes());
+        let unpacked = WeightedCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    #[test]
+    fn validate_weights() {
+        let curve = |token_a_weight, token_b_weight| WeightedCurve {
+            token_a_weight,
+            token_b_weight,
+        };
+        assert_eq!(curve(80, 20).validate(), Ok(()));
+        assert_eq!(curve(2, 98).validate(), Ok(()));
+        assert_eq!(curve(2, 99).validate(), Err(AmmError::InvalidCurve));
+        assert_eq!(curve(1, 49).validate(), Ok(()));
+        assert_eq!(curve(0, 1).validate(), Err(AmmError::InvalidCurve));
+        assert_eq!(curve(1, 50).validate(), Err(AmmError::InvalidCurve));
+        assert_eq!(curve(MAX_WEIGHT, MAX_WEIGHT).validate(), Ok(()));
+        assert_eq!(
+            curve(MAX_WEIGHT + 1, MAX_WEIGHT).validate(),
+            Err(AmmError::InvalidCurve)
+        );
+    }
+
+    #[test]
+    fn power_bounds() {
+        for (base, numerator, denominator) in &[
+            (ONE / 2, 1u128, 3u128),
+            (ONE * 2 / 3, 49, 1),
+            (ONE * 2 / 3, 80, 20),
+            (ONE * 9 / 10, 20, 80),
+            (ONE * 3 / 2, 1, 2),
+            (ONE * 3 / 2, 20, 100),
+            (ONE + 1, 999_999, 1_000_000),
+            (ONE - 1, 1, 1_000_000),
+            (ONE, 7, 3),
+        ] {
+            let low = pow(*base, *numerator, *denominator, RoundDirection::Floor).unwrap();
+            let high = pow(*base, *numerator, *denominator, RoundDirection::Ceiling).unwrap();
+            let exact = to_f64(*base).powf(*numerator as f64 / *denominator as f64);
+            assert!(low <= high);
+            assert!((to_f64(low) - exact).abs() < 1e-9);
+            assert!((to_f64(high) - exact).abs() < 1e-9);
+        }
+        // out of the range of the series
+        assert_eq!(pow(ONE / 2 - 1, 1, 2, RoundDirection::Floor), None);
+        assert_eq!(pow(ONE * 3 / 2 + 1, 1, 2, RoundDirection::Floor), None);
+    }
+
+    #[test]
+    fn equal_weights_are_constant_product() {
+        let curve = WeightedCurve {
+            token_a_weight: 50,
+            token_b_weight: 50,
+        };
+        let swap_source_amount = 1_000_000_000;
+        let swap_destination_amount = 5_000_000_000;
+        let source_amount = 100_000_000;
+        let weighted = curve
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        let constant_product = ConstantProductCurve {}
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert!(weighted.destination_amount_swapped <= constant_product.destination_amount_swapped);
+        assert!(
+            constant_product.destination_amount_swapped - weighted.destination_amount_swapped <= 1
+        );
+    }
+
+    #[test]
+    fn weighted_spot_price() {
+        // 80/20 pool holding 4 times more value in token A, so both tokens
+        // have the same price
+        let curve = WeightedCurve {
+            token_a_weight: 80,
+            token_b_weight: 20,
+        };
+        let swap_token_a_amount = 4_000_000_000;
+        let swap_token_b_amount = 1_000_000_000;
+        let small = 1_000;
+        for trade_direction in &[TradeDirection::AtoB, TradeDirection::BtoA] {
+            let (swap_source_amount, swap_destination_amount) = match trade_direction {
+                TradeDirection::AtoB => (swap_token_a_amount, swap_token_b_amount),
+                TradeDirection::BtoA => (swap_token_b_amount, swap_token_a_amount),
+            };
+            let result = curve
+                .swap_without_fees(
+                    small,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    *trade_direction,
+                )
+                .unwrap();
+            assert!(result.destination_amount_swapped <= small);
+            assert!(result.destination_amount_swapped >= small - 2);
+        }
+    }
+
+    #[test]
+    fn ratio_limits() {
+        let curve = WeightedCurve {
+            token_a_weight: 80,
+            token_b_weight: 20,
+        };
+        assert!(curve
+            .swap_without_fees(500, 1_000, 1_000, TradeDirection::AtoB)
+            .is_some());
+        assert!(curve
+            .swap_without_fees(501, 1_000, 1_000, TradeDirection::AtoB)
+            .is_none());
+        assert!(curve
+            .deposit_single_token_type(500, 1_000, 1_000, 1_000, TradeDirection::AtoB)
+            .is_some());
+        assert!(curve
+            .deposit_single_token_type(501, 1_000, 1_000, 1_000, TradeDirection::AtoB)
+            .is_none());
+        assert!(curve
+            .withdraw_single_token_type_exact_out(333, 1_000, 1_000, 1_000, TradeDirection::AtoB)
+            .is_some());
+        assert!(curve
+            .withdraw_single_token_type_exact_out(334, 1_000, 1_000, 1_000, TradeDirection::AtoB)
+            .is_none());
+    }
+
+    #[test]
+    fn single_token_respects_weights() {
+        // the same amount deposited on the heavier side is a smaller share
+        // of its reserve, but that side holds more of the pool's value
+        let curve = WeightedCurve {
+            token_a_weight: 80,
+            token_b_weight: 20,
+        };
+        let pool_supply = 1_000_000_000;
+        let deposit = |trade_direction| {
+            curve
+                .deposit_single_token_type(
+                    10_000_000,
+                    1_000_000_000,
+                    1_000_000_000,
+                    pool_supply,
+                    trade_direction,
+                )
+                .unwrap()
+        };
+        let a = deposit(TradeDirection::AtoB);
+        let b = deposit(TradeDirection::BtoA);
+        // (1.01^0.8 - 1) and (1.01^0.2 - 1) of the supply
+        assert_eq!(a, 7_992_031);
+        assert_eq!(b, 1_992_047);
+    }
+
+    proptest! {
+        #[test]
+        fn swap_keeps_invariant(
+            token_a_weight in 1..50u64,
+            token_b_weight in 1..50u64,
+            swap_source_amount in 1_000..u64::MAX as u128,
+            swap_destination_amount in 1_000..u64::MAX as u128,
+            source_fraction in 1..1_000u128,
+        ) {
+            let curve = WeightedCurve { token_a_weight, token_b_weight };
+            let source_amount = swap_source_amount * source_fraction / 2_000;
+            let result = curve
+                .swap_without_fees(
+                    source_amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    TradeDirection::AtoB,
+                )
+                .unwrap();
+            // ln V = wa ln a + wb ln b must not go down
+            let ln_before = token_a_weight as f64 * (swap_source_amount as f64).ln()
+                + token_b_weight as f64 * (swap_destination_amount as f64).ln();
+            let ln_after = token_a_weight as f64
+                * ((swap_source_amount + source_amount) as f64).ln()
+                + token_b_weight as f64
+                    * ((swap_destination_amount - result.destination_amount_swapped) as f64)
+                        .ln();
+            prop_assert!(ln_after >= ln_before - 1e-12 * ln_before.abs());
+        }
+
+        #[test]
+        fn deposit_withdraw_single_round_trip(
+            token_a_weight in 1..50u64,
+            token_b_weight in 1..50u64,
+            swap_token_a_amount in 1_000..u64::MAX as u128,
+            swap_token_b_amount in 1_000..u64::MAX as u128,
+            pool_supply in 1_000..u64::MAX as u128,
+            source_fraction in 1..1_000u128,
+        ) {
+            let curve = WeightedCurve { token_a_weight, token_b_weight };
+            let source_amount = swap_token_a_amount * source_fraction / 3_000;
+            let deposited = curve
+                .deposit_single_token_type(
+                    source_amount,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    pool_supply,
+                    TradeDirection::AtoB,
+                )
+                .unwrap();
+            let burnt = curve
+                .withdraw_single_token_type_exact_out(
+                    source_amount,
+                    swap_token_a_amount + source_amount,
+                    swap_token_b_amount,
+                    pool_supply + deposited,
+                    TradeDirection::AtoB,
+                )
+                .unwrap();
+            prop_assert!(burnt >= deposited);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! Serum dex types used by the pool to trade its reserves on the linked market
+
+use crate::{
+    curve::{base::SwapCurve, calculator::TradeDirection, fees::Fees},
+    exact_out::swap_exact_out,
+};
+use arrayref::{array_ref, array_refs};
+use serum_dex::matching::Side;
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use std::convert::TryFrom;
+
+#[cfg(feature = "fuzz")]
+use arbitrary::Arbitrary;
+
+/// Program id of the Serum dex v3, which the pool authority signs for when
+/// trading the reserves, so no other dex program is accepted
+pub mod serum_dex_program {
+    solana_program::declare_id!("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin");
+}
+
+/// Side of the Serum order book, the market's coin is token A and its price
+/// currency token B
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OrderSide {
+    /// Buy token A with token B
+    Bid = 0,
+    /// Sell token A for token B
+    Ask = 1,
+}
+
+impl TryFrom<u8> for OrderSide {
+    type Error = ProgramError;
+
+    fn try_from(side: u8) -> Result<Self, Self::Error> {
+        match side {
+            0 => Ok(OrderSide::Bid),
+            1 => Ok(OrderSide::Ask),
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+}
+
+impl From<OrderSide> for Side {
+    fn from(side: OrderSide) -> Self {
+        match side {
+            OrderSide::Bid => Side::Bid,
+            OrderSide::Ask => Side::Ask,
+        }
+    }
+}
+
+/// Balances of a Serum open orders account, the part of its layout the pool
+/// needs
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OpenOrders {
+    /// Market the orders are placed on
+    pub market: Pubkey,
+    /// Owner allowed to place, cancel and settle the orders
+    pub owner: Pubkey,
+    /// Coin not locked in orders, waiting to be settled
+    pub native_coin_free: u64,
+    /// All coin held by the account, in orders or free
+    pub native_coin_total: u64,
+    /// Price currency not locked in orders, waiting to be settled
+    pub native_pc_free: u64,
+    /// All price currency held by the account, in orders or free
+    pub native_pc_total: u64,
+}
+
+impl OpenOrders {
+    /// Padding the dex writes in front of its accounts
+    const HEAD_PADDING: &'static [u8; 5] = b"serum";
+    /// `AccountFlag::Initialized | AccountFlag::OpenOrders`
+    const FLAGS: u64 = 1 | 1 << 2;
+    /// Length of the account data read by `unpack`
+    const LEN: usize = 109;
+
+    /// Reads the balances out of the account data.  An account which the dex
+    /// has not used yet is zeroed and holds no funds.
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let input = array_ref![data, 0, OpenOrders::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            head_padding,
+            account_flags,
+            market,
+            owner,
+            native_coin_free,
+            native_coin_total,
+            native_pc_free,
+            native_pc_total,
+        ) = array_refs![input, 5, 8, 32, 32, 8, 8, 8, 8];
+        if input.iter().all(|b| *b == 0) {
+            return Ok(Self::default());
+        }
+        if head_padding != Self::HEAD_PADDING
+            || u64::from_le_bytes(*account_flags) & Self::FLAGS != Self::FLAGS
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(Self {
+            market: Pubkey::new_from_array(*market),
+            owner: Pubkey::new_from_array(*owner),
+            native_coin_free: u64::from_le_bytes(*native_coin_free),
+            native_coin_total: u64::from_le_bytes(*native_coin_total),
+            native_pc_free: u64::from_le_bytes(*native_pc_free),
+            native_pc_total: u64::from_le_bytes(*native_pc_total),
+        })
+    }
+}
+
+/// Converts a public key stored by the dex as little endian words
+pub fn pubkey_from_words(words: [u64; 4]) -> Pubkey {
+    let mut bytes = [0u8; 32];
+    for (chunk, word) in bytes.chunks_exact_mut(8).zip(words.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    Pubkey::new_from_array(bytes)
+}
+
+/// Denominator of the spread around the curve price, in basis points
+pub const SPREAD_DENOMINATOR: u64 = 10_000;
+
+/// Limit price, in token B lots per token A lot, at which the pool quotes
+/// `coin_lots` lots of token A on `side`.
+///
+/// The price is the one the curve gives for the same amount, fees included,
+/// moved away from it by `spread_bps`.  Asks round up and bids round down, so
+/// a filled order never trades at a worse price than a swap would.
+#[allow(clippy::too_many_arguments)]
+pub fn order_price(
+    swap_curve: &SwapCurve,
+    fees: &Fees,
+    side: OrderSide,
+    coin_lots: u64,
+    coin_lot_size: u64,
+    pc_lot_size: u64,
+    spread_bps: u64,
+    token_a_amount: u128,
+    token_b_amount: u128,
+) -> Option<u64> {
+    let coin_amount = u128::from(coin_lots).checked_mul(u128::from(coin_lot_size))?;
+    let denominator = u128::from(SPREAD_DENOMINATOR);
+    let pc_lots = u128::from(coin_lots).checked_mul(u128::from(pc_lot_size))?;
+    let price = match side {
+        OrderSide::Bid => {
+            // pay at most what the curve gives for the same token A
+            let pc_amount = swap_curve
+                .swap(
+                    coin_amount,
+                    token_a_amount,
+                    token_b_amount,
+                    TradeDirection::AtoB,
+                    fees,
+                )?
+                .destination_amount_swapped;
+            let pc_amount = pc_amount
+                .checked_mul(denominator.checked_sub(u128::from(spread_bps))?)?
+                .checked_div(denominator)?;
+            pc_amount.checked_div(pc_lots)?
+        }
+        OrderSide::Ask => {
+            // ask at least what the curve charges for the same token A
+            let pc_amount = swap_exact_out(
+                swap_curve,
+                coin_amount,
+                token_b_amount,
+                token_a_amount,
+                TradeDirection::BtoA,
+                fees,
+            )?
+            .source_amount_swapped;
+            let pc_amount = ceil_div(
+                pc_amount.checked_mul(denominator.checked_add(u128::from(spread_bps))?)?,
+                denominator,
+            )?;
+            ceil_div(pc_amount, pc_lots)?
+        }
+    };
+    if price == 0 {
+        return None;
+    }
+    u64::try_from(price).ok()
+}
+
+fn ceil_div(numerator: u128, denominator: u128) -> Option<u128> {
+    if denominator == 0 {
+        return None;
+    }
+    numerator
+        .checked_add(denominator - 1)?
+        .checked_div(denominator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{base::CurveType, constant_product::ConstantProductCurve};
+
+    fn open_orders_data(owner: &Pubkey, coin_total: u64, pc_total: u64) -> Vec<u8> {
+        let mut data = vec![0u8; 3228];
+        data[..5].copy_from_slice(b"serum");
+        data[5..13].copy_from_slice(&OpenOrders::FLAGS.to_le_bytes());
+        data[45..77].copy_from_slice(owner.as_ref());
+        data[85..93].copy_from_slice(&coin_total.to_le_bytes());
+        data[101..109].copy_from_slice(&pc_total.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn unpack_open_orders() {
+        let owner = Pubkey::new_unique();
+        let open_orders = OpenOrders::unpack(&open_orders_data(&owner, 10, 20)).unwrap();
+        assert_eq!(open_orders.owner, owner);
+        assert_eq!(open_orders.native_coin_total, 10);
+        assert_eq!(open_orders.native_pc_total, 20);
+
+        // not used by the dex yet
+        assert_eq!(
+            OpenOrders::unpack(&[0u8; 3228]).unwrap(),
+            OpenOrders::default()
+        );
+
+        let mut data = open_orders_data(&owner, 10, 20);
+        data[0] = 0;
+        assert_eq!(
+            OpenOrders::unpack(&data).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+        let mut data = open_orders_data(&owner, 10, 20);
+        data[5] = 0;
+        assert_eq!(
+            OpenOrders::unpack(&data).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+        assert_eq!(
+            OpenOrders::unpack(&[0u8; OpenOrders::LEN - 1]).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+
+    #[test]
+    fn pubkey_words() {
+        let key = Pubkey::new_unique();
+        let bytes = key.to_bytes();
+        let mut words = [0u64; 4];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(8)) {
+            *word = u64::from_le_bytes([
+                chunk[0], chunk[1], chunk[2], chunk[3], chunk[4], chunk[5], chunk[6], chunk[7],
+            ]);
+        }
+        assert_eq!(pubkey_from_words(words), key);
+    }
+
+    #[test]
+    fn order_side() {
+        assert_eq!(OrderSide::try_from(0).unwrap(), OrderSide::Bid);
+        assert_eq!(OrderSide::try_from(1).unwrap(), OrderSide::Ask);
+        assert_eq!(
+            OrderSide::try_from(2).unwrap_err(),
+            ProgramError::InvalidInstructionData
+        );
+    }
+
+    #[test]
+    fn order_price_around_curve() {
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let fees = Fees {
+            return_fee_numerator: 0,
+            fixed_fee_numerator: 0,
+            fee_denominator: 0,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let token_a_amount = 1_000_000_000;
+        let token_b_amount = 4_000_000_000;
+        // 1_000 lots of 1_000 token A, prices in lots of 10 token B
+        let price = |side, spread_bps| {
+            order_price(
+                &swap_curve,
+                &fees,
+                side,
+                1_000,
+                1_000,
+                10,
+                spread_bps,
+                token_a_amount,
+                token_b_amount,
+            )
+        };
+
+        // 1_000_000 token A are worth a bit less than 4_000_000 token B when
+        // sold to the curve, and a bit more when bought from it
+        let bid = price(OrderSide::Bid, 0).unwrap();
+        let ask = price(OrderSide::Ask, 0).unwrap();
+        assert_eq!(bid, 399);
+        assert_eq!(ask, 401);
+
+        assert_eq!(price(OrderSide::Bid, 100).unwrap(), 395);
+        assert_eq!(price(OrderSide::Ask, 100).unwrap(), 405);
+        assert_eq!(price(OrderSide::Bid, SPREAD_DENOMINATOR), None);
+        assert_eq!(price(OrderSide::Bid, SPREAD_DENOMINATOR + 1), None);
+
+        // the pool cannot sell more token A than it holds
+        assert_eq!(
+            order_price(
+                &swap_curve,
+                &fees,
+                OrderSide::Ask,
+                1_000_000,
+                1_000,
+                10,
+                0,
+                token_a_amount,
+                token_b_amount,
+            ),
+            None
+        );
+    }
+}
@@ -102,6 +102,80 @@ pub enum AmmError {
     /// The operation cannot be performed on the given curve
     #[error("The operation cannot be performed on the given curve")]
     UnsupportedCurveOperation,
+    /// The provided program config account is incorrect
+    #[error("Program config account incorrect")]
+    IncorrectConfigAccount,
+    /// The signer is not the admin stored in the program config
+    #[error("Signer is not the program config admin")]
+    InvalidAdmin,
+
+    // 30.
+    /// The pool status does not allow this operation
+    #[error("Pool is paused")]
+    PoolPaused,
+    /// The dex program or market does not match the ones stored in the pool
+    #[error("Market account does not match the pool")]
+    IncorrectMarketAccount,
+    /// The open orders account does not match the one stored in the pool
+    #[error("Open orders account does not match the pool")]
+    IncorrectOpenOrdersAccount,
+    /// The market is not owned by the given dex program
+    #[error("Market is not owned by the dex program")]
+    IncorrectMarketOwner,
+    /// The base and quote mints of the market are not token A and token B
+    #[error("Market mints do not match the pool tokens")]
+    IncorrectMarketMint,
+
+    // 35.
+    /// The open orders account is not owned by the swap authority
+    #[error("Open orders account is not owned by the swap authority")]
+    IncorrectOpenOrdersOwner,
+    /// The amplification ramp is too fast, too large, or cannot be stopped
+    #[error("Invalid amplification ramp")]
+    InvalidAmpRamp,
+    /// A multi token pool needs between `MIN_TOKENS` and `MAX_TOKENS` tokens
+    #[error("Invalid number of tokens for a multi token pool")]
+    InvalidTokenCount,
+    /// The liquidity in range runs out before the swap amount is used
+    #[error("Not enough liquidity for the swap")]
+    InsufficientLiquidity,
+    /// The ticks of a position are out of range, out of order, or not a
+    /// multiple of the tick spacing
+    #[error("Invalid tick range")]
+    InvalidTickRange,
+
+    // 40.
+    /// A concentrated liquidity pool cannot hold more initialized ticks
+    #[error("Too many initialized ticks in the pool")]
+    TooManyTicks,
+    /// The position does not belong to the pool or to the signer
+    #[error("Position account does not match the pool or owner")]
+    IncorrectPositionAccount,
+    /// A flash loan of the pool is in progress
+    #[error("Pool is locked by a flash loan")]
+    PoolLocked,
+    /// The reserves did not come back with the fee after a flash loan
+    #[error("Flash loan not repaid")]
+    FlashLoanNotRepaid,
+    /// The tokens paid in after a flash swap do not buy its output
+    #[error("Flash swap not paid for")]
+    FlashSwapNotPaid,
+
+    // 45.
+    /// The instruction was processed after its deadline
+    #[error("Transaction deadline expired")]
+    Expired,
+    /// The WSOL mint or the temporary WSOL account of a native SOL
+    /// instruction is not the expected one
+    #[error("Native SOL account incorrect")]
+    IncorrectNativeSolAccount,
+    /// The dex program is not the Serum dex, the only one a pool trades on
+    #[error("Dex program is not the Serum dex")]
+    IncorrectDexProgram,
+    /// A Token-2022 mint of the pool has an extension that would let others
+    /// move or hold back its reserves
+    #[error("Mint extension not supported by the pool")]
+    UnsupportedMintExtension,
 }
 impl From<AmmError> for ProgramError {
     fn from(e: AmmError) -> Self {
@@ -0,0 +1,1061 @@
+//! Events logged by the program for indexers
+//!
+//! Every swap, deposit, withdrawal and flash loan of a pool, and every
+//! change to a position of a concentrated pool, logs one event through
+//! `sol_log_data`, so that indexers read the amounts moved and the reserves
+//! left instead of deriving them from token balances.  Every change the
+//! admin makes to a pool or to the program config logs one too, with the
+//! new settings.  An event is a version
+//! byte, a tag byte for its kind, then its fields in little endian order.
+//! Decoders share these types by depending on this crate with the
+//! `no-entrypoint` feature.
+
+use crate::{
+    amm_stats::PoolStatus,
+    curve::{calculator::TradeDirection, multi::MAX_TOKENS},
+};
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{log::sol_log_data, program_error::ProgramError, pubkey::Pubkey};
+use std::convert::TryFrom;
+
+/// Version of the event layout, bumped on any change to it
+pub const EVENT_VERSION: u8 = 1;
+
+/// Swap of tokens through a pool
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwapEvent {
+    /// Pool swapped through
+    pub pool: Pubkey,
+    /// Authority of the source tokens
+    pub user: Pubkey,
+    /// Direction of the swap
+    pub trade_direction: TradeDirection,
+    /// Source tokens paid by the user, owner fee included
+    pub amount_in: u64,
+    /// Destination tokens received by the user
+    pub amount_out: u64,
+    /// Source tokens paid as owner fee
+    pub owner_fee: u64,
+    /// Token A reserves of the pool after the swap
+    pub reserve_a: u64,
+    /// Token B reserves of the pool after the swap
+    pub reserve_b: u64,
+}
+
+impl SwapEvent {
+    const LEN: usize = 105;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, SwapEvent::LEN];
+        let (pool, user, trade_direction, amount_in, amount_out, owner_fee, reserve_a, reserve_b) =
+            mut_array_refs![output, 32, 32, 1, 8, 8, 8, 8, 8];
+        pool.copy_from_slice(self.pool.as_ref());
+        user.copy_from_slice(self.user.as_ref());
+        trade_direction[0] = match self.trade_direction {
+            TradeDirection::AtoB => 0,
+            TradeDirection::BtoA => 1,
+        };
+        *amount_in = self.amount_in.to_le_bytes();
+        *amount_out = self.amount_out.to_le_bytes();
+        *owner_fee = self.owner_fee.to_le_bytes();
+        *reserve_a = self.reserve_a.to_le_bytes();
+        *reserve_b = self.reserve_b.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, SwapEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (pool, user, trade_direction, amount_in, amount_out, owner_fee, reserve_a, reserve_b) =
+            array_refs![input, 32, 32, 1, 8, 8, 8, 8, 8];
+        Ok(Self {
+            pool: Pubkey::new_from_array(*pool),
+            user: Pubkey::new_from_array(*user),
+            trade_direction: match trade_direction[0] {
+                0 => TradeDirection::AtoB,
+                1 => TradeDirection::BtoA,
+                _ => return Err(ProgramError::InvalidArgument),
+            },
+            amount_in: u64::from_le_bytes(*amount_in),
+            amount_out: u64::from_le_bytes(*amount_out),
+            owner_fee: u64::from_le_bytes(*owner_fee),
+            reserve_a: u64::from_le_bytes(*reserve_a),
+            reserve_b: u64::from_le_bytes(*reserve_b),
+        })
+    }
+}
+
+/// Deposit or withdrawal of liquidity, of one or both tokens of a pool
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LiquidityEvent {
+    /// Pool deposited into or withdrawn from
+    pub pool: Pubkey,
+    /// Authority of the deposited tokens or of the burned pool tokens
+    pub user: Pubkey,
+    /// Token A deposited or withdrawn
+    pub token_a_amount: u64,
+    /// Token B deposited or withdrawn
+    pub token_b_amount: u64,
+    /// Pool tokens minted or burned
+    pub pool_token_amount: u64,
+    /// Pool tokens paid as owner withdraw fee
+    pub owner_fee: u64,
+    /// Token A reserves of the pool after the instruction
+    pub reserve_a: u64,
+    /// Token B reserves of the pool after the instruction
+    pub reserve_b: u64,
+}
+
+impl LiquidityEvent {
+    const LEN: usize = 112;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, LiquidityEvent::LEN];
+        let (
+            pool,
+            user,
+            token_a_amount,
+            token_b_amount,
+            pool_token_amount,
+            owner_fee,
+            reserve_a,
+            reserve_b,
+        ) = mut_array_refs![output, 32, 32, 8, 8, 8, 8, 8, 8];
+        pool.copy_from_slice(self.pool.as_ref());
+        user.copy_from_slice(self.user.as_ref());
+        *token_a_amount = self.token_a_amount.to_le_bytes();
+        *token_b_amount = self.token_b_amount.to_le_bytes();
+        *pool_token_amount = self.pool_token_amount.to_le_bytes();
+        *owner_fee = self.owner_fee.to_le_bytes();
+        *reserve_a = self.reserve_a.to_le_bytes();
+        *reserve_b = self.reserve_b.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, LiquidityEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            pool,
+            user,
+            token_a_amount,
+            token_b_amount,
+            pool_token_amount,
+            owner_fee,
+            reserve_a,
+            reserve_b,
+        ) = array_refs![input, 32, 32, 8, 8, 8, 8, 8, 8];
+        Self {
+            pool: Pubkey::new_from_array(*pool),
+            user: Pubkey::new_from_array(*user),
+            token_a_amount: u64::from_le_bytes(*token_a_amount),
+            token_b_amount: u64::from_le_bytes(*token_b_amount),
+            pool_token_amount: u64::from_le_bytes(*pool_token_amount),
+            owner_fee: u64::from_le_bytes(*owner_fee),
+            reserve_a: u64::from_le_bytes(*reserve_a),
+            reserve_b: u64::from_le_bytes(*reserve_b),
+        }
+    }
+}
+
+/// Flash loan of the tokens of a pool, repaid within the same instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlashLoanEvent {
+    /// Pool lending its tokens
+    pub pool: Pubkey,
+    /// Program receiving the loan
+    pub receiver: Pubkey,
+    /// Token account of the pool the tokens are lent from
+    pub swap_token: Pubkey,
+    /// Tokens lent
+    pub amount: u64,
+    /// Tokens paid back, fees included
+    pub repaid_amount: u64,
+    /// Tokens paid as fixed fee to the fee account
+    pub fixed_fee: u64,
+}
+
+impl FlashLoanEvent {
+    const LEN: usize = 120;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, FlashLoanEvent::LEN];
+        let (pool, receiver, swap_token, amount, repaid_amount, fixed_fee) =
+            mut_array_refs![output, 32, 32, 32, 8, 8, 8];
+        pool.copy_from_slice(self.pool.as_ref());
+        receiver.copy_from_slice(self.receiver.as_ref());
+        swap_token.copy_from_slice(self.swap_token.as_ref());
+        *amount = self.amount.to_le_bytes();
+        *repaid_amount = self.repaid_amount.to_le_bytes();
+        *fixed_fee = self.fixed_fee.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, FlashLoanEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (pool, receiver, swap_token, amount, repaid_amount, fixed_fee) =
+            array_refs![input, 32, 32, 32, 8, 8, 8];
+        Self {
+            pool: Pubkey::new_from_array(*pool),
+            receiver: Pubkey::new_from_array(*receiver),
+            swap_token: Pubkey::new_from_array(*swap_token),
+            amount: u64::from_le_bytes(*amount),
+            repaid_amount: u64::from_le_bytes(*repaid_amount),
+            fixed_fee: u64::from_le_bytes(*fixed_fee),
+        }
+    }
+}
+
+/// Swap of tokens through a multi token pool
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MultiSwapEvent {
+    /// Pool swapped through
+    pub pool: Pubkey,
+    /// Authority of the source tokens
+    pub user: Pubkey,
+    /// Index of the source token in the pool
+    pub source_index: u8,
+    /// Index of the destination token in the pool
+    pub destination_index: u8,
+    /// Source tokens paid by the user, owner fee included
+    pub amount_in: u64,
+    /// Destination tokens received by the user
+    pub amount_out: u64,
+    /// Source tokens paid as owner fee
+    pub owner_fee: u64,
+    /// Source token reserves of the pool after the swap
+    pub reserve_in: u64,
+    /// Destination token reserves of the pool after the swap
+    pub reserve_out: u64,
+}
+
+impl MultiSwapEvent {
+    const LEN: usize = 106;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, MultiSwapEvent::LEN];
+        let (
+            pool,
+            user,
+            source_index,
+            destination_index,
+            amount_in,
+            amount_out,
+            owner_fee,
+            reserve_in,
+            reserve_out,
+        ) = mut_array_refs![output, 32, 32, 1, 1, 8, 8, 8, 8, 8];
+        pool.copy_from_slice(self.pool.as_ref());
+        user.copy_from_slice(self.user.as_ref());
+        source_index[0] = self.source_index;
+        destination_index[0] = self.destination_index;
+        *amount_in = self.amount_in.to_le_bytes();
+        *amount_out = self.amount_out.to_le_bytes();
+        *owner_fee = self.owner_fee.to_le_bytes();
+        *reserve_in = self.reserve_in.to_le_bytes();
+        *reserve_out = self.reserve_out.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, MultiSwapEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            pool,
+            user,
+            source_index,
+            destination_index,
+            amount_in,
+            amount_out,
+            owner_fee,
+            reserve_in,
+            reserve_out,
+        ) = array_refs![input, 32, 32, 1, 1, 8, 8, 8, 8, 8];
+        Self {
+            pool: Pubkey::new_from_array(*pool),
+            user: Pubkey::new_from_array(*user),
+            source_index: source_index[0],
+            destination_index: destination_index[0],
+            amount_in: u64::from_le_bytes(*amount_in),
+            amount_out: u64::from_le_bytes(*amount_out),
+            owner_fee: u64::from_le_bytes(*owner_fee),
+            reserve_in: u64::from_le_bytes(*reserve_in),
+            reserve_out: u64::from_le_bytes(*reserve_out),
+        }
+    }
+}
+
+/// Deposit or withdrawal of liquidity, of one or all tokens of a multi token
+/// pool.  The token arrays are in pool order, unused entries are zero.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MultiLiquidityEvent {
+    /// Pool deposited into or withdrawn from
+    pub pool: Pubkey,
+    /// Authority of the deposited tokens or of the burned pool tokens
+    pub user: Pubkey,
+    /// Tokens deposited or withdrawn
+    pub token_amounts: [u64; MAX_TOKENS],
+    /// Pool tokens minted or burned
+    pub pool_token_amount: u64,
+    /// Reserves of the pool after the instruction
+    pub reserves: [u64; MAX_TOKENS],
+}
+
+impl MultiLiquidityEvent {
+    const LEN: usize = 72 + 16 * MAX_TOKENS;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, MultiLiquidityEvent::LEN];
+        let (pool, user, token_amounts, pool_token_amount, reserves) =
+            mut_array_refs![output, 32, 32, 8 * MAX_TOKENS, 8, 8 * MAX_TOKENS];
+        pool.copy_from_slice(self.pool.as_ref());
+        user.copy_from_slice(self.user.as_ref());
+        pack_amounts(&self.token_amounts, token_amounts);
+        *pool_token_amount = self.pool_token_amount.to_le_bytes();
+        pack_amounts(&self.reserves, reserves);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, MultiLiquidityEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (pool, user, token_amounts, pool_token_amount, reserves) =
+            array_refs![input, 32, 32, 8 * MAX_TOKENS, 8, 8 * MAX_TOKENS];
+        Self {
+            pool: Pubkey::new_from_array(*pool),
+            user: Pubkey::new_from_array(*user),
+            token_amounts: unpack_amounts(token_amounts),
+            pool_token_amount: u64::from_le_bytes(*pool_token_amount),
+            reserves: unpack_amounts(reserves),
+        }
+    }
+}
+
+fn pack_amounts(amounts: &[u64; MAX_TOKENS], output: &mut [u8; 8 * MAX_TOKENS]) {
+    for (amount, output) in amounts.iter().zip(output.chunks_exact_mut(8)) {
+        output.copy_from_slice(&amount.to_le_bytes());
+    }
+}
+
+fn unpack_amounts(input: &[u8; 8 * MAX_TOKENS]) -> [u64; MAX_TOKENS] {
+    let mut amounts = [0u64; MAX_TOKENS];
+    for (amount, input) in amounts.iter_mut().zip(input.chunks_exact(8)) {
+        *amount = u64::from_le_bytes(<[u8; 8]>::try_from(input).unwrap());
+    }
+    amounts
+}
+
+/// Opening, fee collection or closing of a position of a concentrated
+/// liquidity pool
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionEvent {
+    /// Concentrated liquidity pool of the position
+    pub pool: Pubkey,
+    /// Position account
+    pub position: Pubkey,
+    /// Owner of the position
+    pub owner: Pubkey,
+    /// Lower tick of the position
+    pub tick_lower: i32,
+    /// Upper tick of the position
+    pub tick_upper: i32,
+    /// Liquidity added or removed, zero when only collecting fees
+    pub liquidity: u128,
+    /// Token A deposited or withdrawn, fees included
+    pub token_a_amount: u64,
+    /// Token B deposited or withdrawn, fees included
+    pub token_b_amount: u64,
+}
+
+impl PositionEvent {
+    const LEN: usize = 136;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, PositionEvent::LEN];
+        let (
+            pool,
+            position,
+            owner,
+            tick_lower,
+            tick_upper,
+            liquidity,
+            token_a_amount,
+            token_b_amount,
+        ) = mut_array_refs![output, 32, 32, 32, 4, 4, 16, 8, 8];
+        pool.copy_from_slice(self.pool.as_ref());
+        position.copy_from_slice(self.position.as_ref());
+        owner.copy_from_slice(self.owner.as_ref());
+        *tick_lower = self.tick_lower.to_le_bytes();
+        *tick_upper = self.tick_upper.to_le_bytes();
+        *liquidity = self.liquidity.to_le_bytes();
+        *token_a_amount = self.token_a_amount.to_le_bytes();
+        *token_b_amount = self.token_b_amount.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, PositionEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            pool,
+            position,
+            owner,
+            tick_lower,
+            tick_upper,
+            liquidity,
+            token_a_amount,
+            token_b_amount,
+        ) = array_refs![input, 32, 32, 32, 4, 4, 16, 8, 8];
+        Self {
+            pool: Pubkey::new_from_array(*pool),
+            position: Pubkey::new_from_array(*position),
+            owner: Pubkey::new_from_array(*owner),
+            tick_lower: i32::from_le_bytes(*tick_lower),
+            tick_upper: i32::from_le_bytes(*tick_upper),
+            liquidity: u128::from_le_bytes(*liquidity),
+            token_a_amount: u64::from_le_bytes(*token_a_amount),
+            token_b_amount: u64::from_le_bytes(*token_b_amount),
+        }
+    }
+}
+
+/// Change of the fees of a pool by the admin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeesEvent {
+    /// Pool whose fees changed
+    pub pool: Pubkey,
+    /// Admin of the program
+    pub admin: Pubkey,
+    /// Trade fee numerator
+    pub return_fee_numerator: u64,
+    /// Fixed fee numerator
+    pub fixed_fee_numerator: u64,
+    /// Denominator of both the trade and the fixed fee
+    pub fee_denominator: u64,
+    /// Owner withdraw fee numerator
+    pub owner_withdraw_fee_numerator: u64,
+    /// Owner withdraw fee denominator
+    pub owner_withdraw_fee_denominator: u64,
+}
+
+impl FeesEvent {
+    const LEN: usize = 104;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, FeesEvent::LEN];
+        let (
+            pool,
+            admin,
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+        ) = mut_array_refs![output, 32, 32, 8, 8, 8, 8, 8];
+        pool.copy_from_slice(self.pool.as_ref());
+        admin.copy_from_slice(self.admin.as_ref());
+        *return_fee_numerator = self.return_fee_numerator.to_le_bytes();
+        *fixed_fee_numerator = self.fixed_fee_numerator.to_le_bytes();
+        *fee_denominator = self.fee_denominator.to_le_bytes();
+        *owner_withdraw_fee_numerator = self.owner_withdraw_fee_numerator.to_le_bytes();
+        *owner_withdraw_fee_denominator = self.owner_withdraw_fee_denominator.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, FeesEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            pool,
+            admin,
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+        ) = array_refs![input, 32, 32, 8, 8, 8, 8, 8];
+        Self {
+            pool: Pubkey::new_from_array(*pool),
+            admin: Pubkey::new_from_array(*admin),
+            return_fee_numerator: u64::from_le_bytes(*return_fee_numerator),
+            fixed_fee_numerator: u64::from_le_bytes(*fixed_fee_numerator),
+            fee_denominator: u64::from_le_bytes(*fee_denominator),
+            owner_withdraw_fee_numerator: u64::from_le_bytes(*owner_withdraw_fee_numerator),
+            owner_withdraw_fee_denominator: u64::from_le_bytes(*owner_withdraw_fee_denominator),
+        }
+    }
+}
+
+/// Change of the status of a pool by the admin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoolStatusEvent {
+    /// Pool whose status changed
+    pub pool: Pubkey,
+    /// Admin of the program
+    pub admin: Pubkey,
+    /// New status of the pool
+    pub status: PoolStatus,
+}
+
+impl PoolStatusEvent {
+    const LEN: usize = 65;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, PoolStatusEvent::LEN];
+        let (pool, admin, status) = mut_array_refs![output, 32, 32, 1];
+        pool.copy_from_slice(self.pool.as_ref());
+        admin.copy_from_slice(self.admin.as_ref());
+        status[0] = self.status as u8;
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, PoolStatusEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (pool, admin, status) = array_refs![input, 32, 32, 1];
+        Ok(Self {
+            pool: Pubkey::new_from_array(*pool),
+            admin: Pubkey::new_from_array(*admin),
+            status: match status[0] {
+                0 => PoolStatus::Active,
+                1 => PoolStatus::WithdrawOnly,
+                2 => PoolStatus::Halted,
+                _ => return Err(ProgramError::InvalidArgument),
+            },
+        })
+    }
+}
+
+/// Change of the fixed fee account of one token of a pool by the admin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FixedFeeAccountEvent {
+    /// Pool whose fixed fee account changed
+    pub pool: Pubkey,
+    /// Admin of the program
+    pub admin: Pubkey,
+    /// Index of the token in the pool, 0 for token A and 1 for token B
+    pub token_index: u8,
+    /// New fixed fee account of the token
+    pub fee_account: Pubkey,
+}
+
+impl FixedFeeAccountEvent {
+    const LEN: usize = 97;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, FixedFeeAccountEvent::LEN];
+        let (pool, admin, token_index, fee_account) = mut_array_refs![output, 32, 32, 1, 32];
+        pool.copy_from_slice(self.pool.as_ref());
+        admin.copy_from_slice(self.admin.as_ref());
+        token_index[0] = self.token_index;
+        fee_account.copy_from_slice(self.fee_account.as_ref());
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, FixedFeeAccountEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (pool, admin, token_index, fee_account) = array_refs![input, 32, 32, 1, 32];
+        Self {
+            pool: Pubkey::new_from_array(*pool),
+            admin: Pubkey::new_from_array(*admin),
+            token_index: token_index[0],
+            fee_account: Pubkey::new_from_array(*fee_account),
+        }
+    }
+}
+
+/// Change of the host fee of a pool by the admin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HostFeeEvent {
+    /// Pool whose host fee changed
+    pub pool: Pubkey,
+    /// Admin of the program
+    pub admin: Pubkey,
+    /// Numerator of the share of the fixed fee paid to hosts
+    pub numerator: u64,
+    /// Denominator of the share of the fixed fee paid to hosts
+    pub denominator: u64,
+}
+
+impl HostFeeEvent {
+    const LEN: usize = 80;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, HostFeeEvent::LEN];
+        let (pool, admin, numerator, denominator) = mut_array_refs![output, 32, 32, 8, 8];
+        pool.copy_from_slice(self.pool.as_ref());
+        admin.copy_from_slice(self.admin.as_ref());
+        *numerator = self.numerator.to_le_bytes();
+        *denominator = self.denominator.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, HostFeeEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (pool, admin, numerator, denominator) = array_refs![input, 32, 32, 8, 8];
+        Self {
+            pool: Pubkey::new_from_array(*pool),
+            admin: Pubkey::new_from_array(*admin),
+            numerator: u64::from_le_bytes(*numerator),
+            denominator: u64::from_le_bytes(*denominator),
+        }
+    }
+}
+
+/// Change of the protocol fee of a pool by the admin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProtocolFeeEvent {
+    /// Pool whose protocol fee changed
+    pub pool: Pubkey,
+    /// Admin of the program
+    pub admin: Pubkey,
+    /// Numerator of the share of the growth of `sqrt(k)` minted
+    pub numerator: u64,
+    /// Denominator of the share of the growth of `sqrt(k)` minted
+    pub denominator: u64,
+    /// Pool token account receiving the minted pool tokens
+    pub fee_account: Pubkey,
+}
+
+impl ProtocolFeeEvent {
+    const LEN: usize = 112;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, ProtocolFeeEvent::LEN];
+        let (pool, admin, numerator, denominator, fee_account) =
+            mut_array_refs![output, 32, 32, 8, 8, 32];
+        pool.copy_from_slice(self.pool.as_ref());
+        admin.copy_from_slice(self.admin.as_ref());
+        *numerator = self.numerator.to_le_bytes();
+        *denominator = self.denominator.to_le_bytes();
+        fee_account.copy_from_slice(self.fee_account.as_ref());
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, ProtocolFeeEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (pool, admin, numerator, denominator, fee_account) =
+            array_refs![input, 32, 32, 8, 8, 32];
+        Self {
+            pool: Pubkey::new_from_array(*pool),
+            admin: Pubkey::new_from_array(*admin),
+            numerator: u64::from_le_bytes(*numerator),
+            denominator: u64::from_le_bytes(*denominator),
+            fee_account: Pubkey::new_from_array(*fee_account),
+        }
+    }
+}
+
+/// Start or stop of a ramp of the amplification of a stable pool by the
+/// admin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmpRampEvent {
+    /// Stable pool whose amplification ramps
+    pub pool: Pubkey,
+    /// Admin of the program
+    pub admin: Pubkey,
+    /// Amplification at the start of the ramp
+    pub initial_amp: u64,
+    /// Amplification reached at the end of the ramp
+    pub target_amp: u64,
+    /// Unix timestamp the ramp starts at
+    pub start_ramp_ts: i64,
+    /// Unix timestamp the ramp ends at
+    pub stop_ramp_ts: i64,
+}
+
+impl AmpRampEvent {
+    const LEN: usize = 96;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, AmpRampEvent::LEN];
+        let (pool, admin, initial_amp, target_amp, start_ramp_ts, stop_ramp_ts) =
+            mut_array_refs![output, 32, 32, 8, 8, 8, 8];
+        pool.copy_from_slice(self.pool.as_ref());
+        admin.copy_from_slice(self.admin.as_ref());
+        *initial_amp = self.initial_amp.to_le_bytes();
+        *target_amp = self.target_amp.to_le_bytes();
+        *start_ramp_ts = self.start_ramp_ts.to_le_bytes();
+        *stop_ramp_ts = self.stop_ramp_ts.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, AmpRampEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (pool, admin, initial_amp, target_amp, start_ramp_ts, stop_ramp_ts) =
+            array_refs![input, 32, 32, 8, 8, 8, 8];
+        Self {
+            pool: Pubkey::new_from_array(*pool),
+            admin: Pubkey::new_from_array(*admin),
+            initial_amp: u64::from_le_bytes(*initial_amp),
+            target_amp: u64::from_le_bytes(*target_amp),
+            start_ramp_ts: i64::from_le_bytes(*start_ramp_ts),
+            stop_ramp_ts: i64::from_le_bytes(*stop_ramp_ts),
+        }
+    }
+}
+
+/// Change of the fee wallet of the program by the admin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeeWalletEvent {
+    /// Config account of the program
+    pub config: Pubkey,
+    /// Admin of the program
+    pub admin: Pubkey,
+    /// New fee wallet
+    pub fee_wallet: Pubkey,
+}
+
+impl FeeWalletEvent {
+    const LEN: usize = 96;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, FeeWalletEvent::LEN];
+        let (config, admin, fee_wallet) = mut_array_refs![output, 32, 32, 32];
+        config.copy_from_slice(self.config.as_ref());
+        admin.copy_from_slice(self.admin.as_ref());
+        fee_wallet.copy_from_slice(self.fee_wallet.as_ref());
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, FeeWalletEvent::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (config, admin, fee_wallet) = array_refs![input, 32, 32, 32];
+        Self {
+            config: Pubkey::new_from_array(*config),
+            admin: Pubkey::new_from_array(*admin),
+            fee_wallet: Pubkey::new_from_array(*fee_wallet),
+        }
+    }
+}
+
+/// Event logged by an instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AmmEvent {
+    /// Tokens swapped through a pool, tag 0
+    Swap(SwapEvent),
+    /// Liquidity deposited, pool tokens minted, tag 1
+    Deposit(LiquidityEvent),
+    /// Liquidity withdrawn, pool tokens burned, tag 2
+    Withdraw(LiquidityEvent),
+    /// Tokens of a pool lent and repaid, tag 3
+    FlashLoan(FlashLoanEvent),
+    /// Tokens swapped through a multi token pool, tag 4
+    MultiSwap(MultiSwapEvent),
+    /// Liquidity deposited into a multi token pool, tag 5
+    MultiDeposit(MultiLiquidityEvent),
+    /// Liquidity withdrawn from a multi token pool, tag 6
+    MultiWithdraw(MultiLiquidityEvent),
+    /// Position of a concentrated pool opened, tag 7
+    OpenPosition(PositionEvent),
+    /// Fees of a position of a concentrated pool collected, tag 8
+    CollectFees(PositionEvent),
+    /// Position of a concentrated pool closed, tag 9
+    ClosePosition(PositionEvent),
+    /// Fees of a pool set by the admin, tag 10
+    SetFees(FeesEvent),
+    /// Status of a pool set by the admin, tag 11
+    SetPoolStatus(PoolStatusEvent),
+    /// Fixed fee account of a token of a pool set by the admin, tag 12
+    SetFixedFeeAccount(FixedFeeAccountEvent),
+    /// Host fee of a pool set by the admin, tag 13
+    SetHostFee(HostFeeEvent),
+    /// Protocol fee of a pool set by the admin, tag 14
+    SetProtocolFee(ProtocolFeeEvent),
+    /// Amplification ramp of a stable pool started by the admin, tag 15
+    RampAmp(AmpRampEvent),
+    /// Amplification ramp of a stable pool stopped by the admin, tag 16
+    StopRampAmp(AmpRampEvent),
+    /// Fee wallet of the program set by the admin, tag 17
+    SetFeeWallet(FeeWalletEvent),
+}
+
+impl AmmEvent {
+    /// Packs the event with its version and tag
+    pub fn pack(&self) -> Vec<u8> {
+        let (tag, len) = match self {
+            Self::Swap(_) => (0, SwapEvent::LEN),
+            Self::Deposit(_) => (1, LiquidityEvent::LEN),
+            Self::Withdraw(_) => (2, LiquidityEvent::LEN),
+            Self::FlashLoan(_) => (3, FlashLoanEvent::LEN),
+            Self::MultiSwap(_) => (4, MultiSwapEvent::LEN),
+            Self::MultiDeposit(_) => (5, MultiLiquidityEvent::LEN),
+            Self::MultiWithdraw(_) => (6, MultiLiquidityEvent::LEN),
+            Self::OpenPosition(_) => (7, PositionEvent::LEN),
+            Self::CollectFees(_) => (8, PositionEvent::LEN),
+            Self::ClosePosition(_) => (9, PositionEvent::LEN),
+            Self::SetFees(_) => (10, FeesEvent::LEN),
+            Self::SetPoolStatus(_) => (11, PoolStatusEvent::LEN),
+            Self::SetFixedFeeAccount(_) => (12, FixedFeeAccountEvent::LEN),
+            Self::SetHostFee(_) => (13, HostFeeEvent::LEN),
+            Self::SetProtocolFee(_) => (14, ProtocolFeeEvent::LEN),
+            Self::RampAmp(_) => (15, AmpRampEvent::LEN),
+            Self::StopRampAmp(_) => (16, AmpRampEvent::LEN),
+            Self::SetFeeWallet(_) => (17, FeeWalletEvent::LEN),
+        };
+        let mut buf = vec![0u8; 2 + len];
+        buf[0] = EVENT_VERSION;
+        buf[1] = tag;
+        let output = &mut buf[2..];
+        match self {
+            Self::Swap(event) => event.pack_into_slice(output),
+            Self::Deposit(event) | Self::Withdraw(event) => event.pack_into_slice(output),
+            Self::FlashLoan(event) => event.pack_into_slice(output),
+            Self::MultiSwap(event) => event.pack_into_slice(output),
+            Self::MultiDeposit(event) | Self::MultiWithdraw(event) => {
+                event.pack_into_slice(output)
+            }
+            Self::OpenPosition(event) | Self::CollectFees(event) | Self::ClosePosition(event) => {
+                event.pack_into_slice(output)
+            }
+            Self::SetFees(event) => event.pack_into_slice(output),
+            Self::SetPoolStatus(event) => event.pack_into_slice(output),
+            Self::SetFixedFeeAccount(event) => event.pack_into_slice(output),
+            Self::SetHostFee(event) => event.pack_into_slice(output),
+            Self::SetProtocolFee(event) => event.pack_into_slice(output),
+            Self::RampAmp(event) | Self::StopRampAmp(event) => event.pack_into_slice(output),
+            Self::SetFeeWallet(event) => event.pack_into_slice(output),
+        }
+        buf
+    }
+
+    /// Unpacks an event, failing on layouts of versions other than
+    /// `EVENT_VERSION`
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let (&version, rest) = input.split_first().ok_or(ProgramError::InvalidArgument)?;
+        if version != EVENT_VERSION {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let (&tag, rest) = rest.split_first().ok_or(ProgramError::InvalidArgument)?;
+        let len = match tag {
+            0 => SwapEvent::LEN,
+            1 | 2 => LiquidityEvent::LEN,
+            3 => FlashLoanEvent::LEN,
+            4 => MultiSwapEvent::LEN,
+            5 | 6 => MultiLiquidityEvent::LEN,
+            7..=9 => PositionEvent::LEN,
+            10 => FeesEvent::LEN,
+            11 => PoolStatusEvent::LEN,
+            12 => FixedFeeAccountEvent::LEN,
+            13 => HostFeeEvent::LEN,
+            14 => ProtocolFeeEvent::LEN,
+            15 | 16 => AmpRampEvent::LEN,
+            17 => FeeWalletEvent::LEN,
+            _ => return Err(ProgramError::InvalidArgument),
+        };
+        if rest.len() != len {
+            return Err(ProgramError::InvalidArgument);
+        }
+        Ok(match tag {
+            0 => Self::Swap(SwapEvent::unpack_from_slice(rest)?),
+            1 => Self::Deposit(LiquidityEvent::unpack_from_slice(rest)),
+            2 => Self::Withdraw(LiquidityEvent::unpack_from_slice(rest)),
+            3 => Self::FlashLoan(FlashLoanEvent::unpack_from_slice(rest)),
+            4 => Self::MultiSwap(MultiSwapEvent::unpack_from_slice(rest)),
+            5 => Self::MultiDeposit(MultiLiquidityEvent::unpack_from_slice(rest)),
+            6 => Self::MultiWithdraw(MultiLiquidityEvent::unpack_from_slice(rest)),
+            7 => Self::OpenPosition(PositionEvent::unpack_from_slice(rest)),
+            8 => Self::CollectFees(PositionEvent::unpack_from_slice(rest)),
+            9 => Self::ClosePosition(PositionEvent::unpack_from_slice(rest)),
+            10 => Self::SetFees(FeesEvent::unpack_from_slice(rest)),
+            11 => Self::SetPoolStatus(PoolStatusEvent::unpack_from_slice(rest)?),
+            12 => Self::SetFixedFeeAccount(FixedFeeAccountEvent::unpack_from_slice(rest)),
+            13 => Self::SetHostFee(HostFeeEvent::unpack_from_slice(rest)),
+            14 => Self::SetProtocolFee(ProtocolFeeEvent::unpack_from_slice(rest)),
+            15 => Self::RampAmp(AmpRampEvent::unpack_from_slice(rest)),
+            16 => Self::StopRampAmp(AmpRampEvent::unpack_from_slice(rest)),
+            _ => Self::SetFeeWallet(FeeWalletEvent::unpack_from_slice(rest)),
+        })
+    }
+
+    /// Logs the packed event
+    pub fn emit(&self) {
+        sol_log_data(&[&self.pack()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swap_event() -> SwapEvent {
+        SwapEvent {
+            pool: Pubkey::new_unique(),
+            user: Pubkey::new_unique(),
+            trade_direction: TradeDirection::BtoA,
+            amount_in: 1_000,
+            amount_out: 990,
+            owner_fee: 3,
+            reserve_a: 99_010,
+            reserve_b: 100_997,
+        }
+    }
+
+    fn liquidity_event() -> LiquidityEvent {
+        LiquidityEvent {
+            pool: Pubkey::new_unique(),
+            user: Pubkey::new_unique(),
+            token_a_amount: 500,
+            token_b_amount: 0,
+            pool_token_amount: 2_500,
+            owner_fee: 0,
+            reserve_a: 100_500,
+            reserve_b: 100_000,
+        }
+    }
+
+    fn multi_liquidity_event() -> MultiLiquidityEvent {
+        let mut token_amounts = [0; MAX_TOKENS];
+        token_amounts[..3].copy_from_slice(&[100, 200, 300]);
+        let mut reserves = [0; MAX_TOKENS];
+        reserves[..3].copy_from_slice(&[10_100, 20_200, 30_300]);
+        MultiLiquidityEvent {
+            pool: Pubkey::new_unique(),
+            user: Pubkey::new_unique(),
+            token_amounts,
+            pool_token_amount: 1_000,
+            reserves,
+        }
+    }
+
+    fn position_event() -> PositionEvent {
+        PositionEvent {
+            pool: Pubkey::new_unique(),
+            position: Pubkey::new_unique(),
+            owner: Pubkey::new_unique(),
+            tick_lower: -100,
+            tick_upper: 100,
+            liquidity: u128::MAX,
+            token_a_amount: 1_000,
+            token_b_amount: 2_000,
+        }
+    }
+
+    fn pool_status_event() -> PoolStatusEvent {
+        PoolStatusEvent {
+            pool: Pubkey::new_unique(),
+            admin: Pubkey::new_unique(),
+            status: PoolStatus::WithdrawOnly,
+        }
+    }
+
+    fn amp_ramp_event() -> AmpRampEvent {
+        AmpRampEvent {
+            pool: Pubkey::new_unique(),
+            admin: Pubkey::new_unique(),
+            initial_amp: 100,
+            target_amp: 200,
+            start_ramp_ts: 1_000,
+            stop_ramp_ts: 87_400,
+        }
+    }
+
+    #[test]
+    fn pack_events() {
+        let events = [
+            AmmEvent::Swap(swap_event()),
+            AmmEvent::Deposit(liquidity_event()),
+            AmmEvent::Withdraw(liquidity_event()),
+            AmmEvent::FlashLoan(FlashLoanEvent {
+                pool: Pubkey::new_unique(),
+                receiver: Pubkey::new_unique(),
+                swap_token: Pubkey::new_unique(),
+                amount: 1_000,
+                repaid_amount: 1_004,
+                fixed_fee: 1,
+            }),
+            AmmEvent::MultiSwap(MultiSwapEvent {
+                pool: Pubkey::new_unique(),
+                user: Pubkey::new_unique(),
+                source_index: 2,
+                destination_index: 0,
+                amount_in: 1_000,
+                amount_out: 990,
+                owner_fee: 3,
+                reserve_in: 100_997,
+                reserve_out: 99_010,
+            }),
+            AmmEvent::MultiDeposit(multi_liquidity_event()),
+            AmmEvent::MultiWithdraw(multi_liquidity_event()),
+            AmmEvent::OpenPosition(position_event()),
+            AmmEvent::CollectFees(position_event()),
+            AmmEvent::ClosePosition(position_event()),
+            AmmEvent::SetFees(FeesEvent {
+                pool: Pubkey::new_unique(),
+                admin: Pubkey::new_unique(),
+                return_fee_numerator: 25,
+                fixed_fee_numerator: 5,
+                fee_denominator: 10_000,
+                owner_withdraw_fee_numerator: 1,
+                owner_withdraw_fee_denominator: 1_000,
+            }),
+            AmmEvent::SetPoolStatus(pool_status_event()),
+            AmmEvent::SetFixedFeeAccount(FixedFeeAccountEvent {
+                pool: Pubkey::new_unique(),
+                admin: Pubkey::new_unique(),
+                token_index: 1,
+                fee_account: Pubkey::new_unique(),
+            }),
+            AmmEvent::SetHostFee(HostFeeEvent {
+                pool: Pubkey::new_unique(),
+                admin: Pubkey::new_unique(),
+                numerator: 1,
+                denominator: 5,
+            }),
+            AmmEvent::SetProtocolFee(ProtocolFeeEvent {
+                pool: Pubkey::new_unique(),
+                admin: Pubkey::new_unique(),
+                numerator: 1,
+                denominator: 6,
+                fee_account: Pubkey::new_unique(),
+            }),
+            AmmEvent::RampAmp(amp_ramp_event()),
+            AmmEvent::StopRampAmp(amp_ramp_event()),
+            AmmEvent::SetFeeWallet(FeeWalletEvent {
+                config: Pubkey::new_unique(),
+                admin: Pubkey::new_unique(),
+                fee_wallet: Pubkey::new_unique(),
+            }),
+        ];
+        for (tag, event) in events.iter().enumerate() {
+            let packed = event.pack();
+            assert_eq!(packed[0], EVENT_VERSION);
+            assert_eq!(usize::from(packed[1]), tag);
+            assert_eq!(AmmEvent::unpack(&packed).unwrap(), *event);
+        }
+        assert_eq!(AmmEvent::Swap(swap_event()).pack().len(), 2 + 105);
+        assert_eq!(AmmEvent::Deposit(liquidity_event()).pack().len(), 2 + 112);
+    }
+
+    #[test]
+    fn unpack_invalid_events() {
+        let packed = AmmEvent::Swap(swap_event()).pack();
+        assert_eq!(AmmEvent::unpack(&[]), Err(ProgramError::InvalidArgument));
+
+        let mut other_version = packed.clone();
+        other_version[0] = EVENT_VERSION + 1;
+        assert_eq!(
+            AmmEvent::unpack(&other_version),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        let mut unknown_tag = packed.clone();
+        unknown_tag[1] = 18;
+        assert_eq!(
+            AmmEvent::unpack(&unknown_tag),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        let mut bad_direction = packed.clone();
+        bad_direction[2 + 64] = 2;
+        assert_eq!(
+            AmmEvent::unpack(&bad_direction),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        assert_eq!(
+            AmmEvent::unpack(&packed[..packed.len() - 1]),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        let mut bad_status = AmmEvent::SetPoolStatus(pool_status_event()).pack();
+        bad_status[2 + 64] = 3;
+        assert_eq!(
+            AmmEvent::unpack(&bad_status),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+}
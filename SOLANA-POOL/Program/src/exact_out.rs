@@ -0,0 +1,380 @@
+//! Inverse swap calculations, finding the source amount that pays out an
+//! exact destination amount
+
+use crate::curve::{
+    base::{CurveType, SwapCurve, SwapResult},
+    calculator::TradeDirection,
+    constant_price::ConstantPriceCurve,
+    fees::Fees,
+    offset::OffsetCurve,
+    stable::StableCurve,
+};
+use solana_program::program_pack::Pack;
+
+/// Calculates the swap paying out at least `destination_amount` for the
+/// smallest source amount, including fees.
+///
+/// The inverse curve gives an estimate which is then checked against the
+/// forward `SwapCurve::swap`, so rounding in the curve or the fees can never
+/// make the pool pay out less than asked.  Returns None if the pool cannot pay
+/// out `destination_amount`.
+pub fn swap_exact_out(
+    swap_curve: &SwapCurve,
+    destination_amount: u128,
+    swap_source_amount: u128,
+    swap_destination_amount: u128,
+    trade_direction: TradeDirection,
+    fees: &Fees,
+) -> Option<SwapResult> {
+    if destination_amount == 0 || destination_amount >= swap_destination_amount {
+        return None;
+    }
+    let swap = |source_amount: u128| {
+        swap_curve
+            .swap(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+                fees,
+            )
+            .filter(|result| result.destination_amount_swapped >= destination_amount)
+    };
+
+    let estimate = source_amount_without_fees(
+        swap_curve,
+        destination_amount,
+        swap_source_amount,
+        swap_destination_amount,
+        trade_direction,
+    )
+    .and_then(|source_amount| source_amount_with_fees(source_amount, fees))
+    .unwrap_or(1)
+    .max(1)
+    .min(u64::MAX as u128);
+
+    // find `low` which does not pay out enough and `high` which does
+    let (mut low, mut high) = if swap(estimate).is_some() {
+        let mut step = 1;
+        let mut low = estimate.saturating_sub(step);
+        while low > 0 && swap(low).is_some() {
+            step = step.checked_mul(2)?;
+            low = low.saturating_sub(step);
+        }
+        (low, estimate)
+    } else {
+        let mut step = 1;
+        let mut high = estimate.checked_add(step)?;
+        while swap(high).is_none() {
+            if high >= u64::MAX as u128 {
+                return None;
+            }
+            step = step.checked_mul(2)?;
+            high = high.checked_add(step)?.min(u64::MAX as u128);
+        }
+        (estimate, high)
+    };
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        if swap(middle).is_some() {
+            high = middle;
+        } else {
+            low = middle;
+        }
+    }
+    swap(high)
+}
+
+/// Source amount, before fees, that the curve needs to pay out
+/// `destination_amount`, or None if there is no closed form for the curve
+pub fn source_amount_without_fees(
+    swap_curve: &SwapCurve,
+    destination_amount: u128,
+    swap_source_amount: u128,
+    swap_destination_amount: u128,
+    trade_direction: TradeDirection,
+) -> Option<u128> {
+    match swap_curve.curve_type {
+        CurveType::ConstantProduct => constant_product_source_amount(
+            destination_amount,
+            swap_source_amount,
+            swap_destination_amount,
+        ),
+        CurveType::ConstantPrice => {
+            let token_b_price = u128::from(
+                unpack_calculator::<ConstantPriceCurve>(swap_curve)?.token_b_price,
+            );
+            match trade_direction {
+                // every token B costs `token_b_price` token A
+                TradeDirection::AtoB => destination_amount.checked_mul(token_b_price),
+                TradeDirection::BtoA => ceil_div(destination_amount, token_b_price),
+            }
+        }
+        CurveType::Offset => {
+            let token_b_offset =
+                u128::from(unpack_calculator::<OffsetCurve>(swap_curve)?.token_b_offset);
+            match trade_direction {
+                TradeDirection::AtoB => constant_product_source_amount(
+                    destination_amount,
+                    swap_source_amount,
+                    swap_destination_amount.checked_add(token_b_offset)?,
+                ),
+                TradeDirection::BtoA => constant_product_source_amount(
+                    destination_amount,
+                    swap_source_amount.checked_add(token_b_offset)?,
+                    swap_destination_amount,
+                ),
+            }
+        }
+        CurveType::Stable => {
+            // the source side which keeps the invariant once
+            // `destination_amount` is taken out
+            let calculator = unpack_calculator::<StableCurve>(swap_curve)?;
+            let invariant = calculator.compute_d(swap_source_amount, swap_destination_amount)?;
+            let new_swap_destination_amount =
+                swap_destination_amount.checked_sub(destination_amount)?;
+            calculator
+                .compute_new_destination_amount(new_swap_destination_amount, invariant)?
+                .checked_sub(swap_source_amount)
+        }
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/// Inverse of the constant product swap: the source amount keeping
+/// `source * destination` constant once `destination_amount` is taken out
+fn constant_product_source_amount(
+    destination_amount: u128,
+    swap_source_amount: u128,
+    swap_destination_amount: u128,
+) -> Option<u128> {
+    let invariant = swap_source_amount.checked_mul(swap_destination_amount)?;
+    let new_swap_destination_amount = swap_destination_amount.checked_sub(destination_amount)?;
+    ceil_div(invariant, new_swap_destination_amount)?.checked_sub(swap_source_amount)
+}
+
+/// Grows a source amount so that it is still `source_amount` once the return
+/// and fixed fees are taken out
+fn source_amount_with_fees(source_amount: u128, fees: &Fees) -> Option<u128> {
+    let fee_denominator = u128::from(fees.fee_denominator);
+    if fee_denominator == 0 {
+        return Some(source_amount);
+    }
+    let fee_numerator =
+        u128::from(fees.return_fee_numerator).checked_add(u128::from(fees.fixed_fee_numerator))?;
+    ceil_div(
+        source_amount.checked_mul(fee_denominator)?,
+        fee_denominator.checked_sub(fee_numerator)?,
+    )
+}
+
+fn ceil_div(numerator: u128, denominator: u128) -> Option<u128> {
+    if denominator == 0 {
+        return None;
+    }
+    numerator
+        .checked_add(denominator - 1)?
+        .checked_div(denominator)
+}
+
+/// Reads the concrete calculator back out of the packed curve parameters
+pub fn unpack_calculator<T: Pack>(swap_curve: &SwapCurve) -> Option<T> {
+    let mut packed = [0u8; SwapCurve::LEN];
+    Pack::pack_into_slice(swap_curve, &mut packed);
+    T::unpack_from_slice(&packed[1..]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::constant_product::ConstantProductCurve;
+    use proptest::prelude::*;
+
+    fn fees() -> Fees {
+        Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        }
+    }
+
+    fn check_minimal_swap(
+        swap_curve: &SwapCurve,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) {
+        let result = swap_exact_out(
+            swap_curve,
+            destination_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+            fees,
+        )
+        .unwrap();
+        assert!(result.destination_amount_swapped >= destination_amount);
+
+        // one token less does not pay out enough
+        let source_amount = result.source_amount_swapped;
+        let short = swap_curve.swap(
+            source_amount - 1,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+            fees,
+        );
+        if let Some(short) = short {
+            assert!(short.destination_amount_swapped < destination_amount);
+        }
+    }
+
+    #[test]
+    fn constant_product_closed_form() {
+        // 1_000 * 5_000 = 5_000_000, taking 1_000 out leaves 4_000 so 1_250
+        // must be in the pool
+        assert_eq!(
+            constant_product_source_amount(1_000, 1_000, 5_000),
+            Some(250)
+        );
+        // rounds up
+        assert_eq!(constant_product_source_amount(1, 1_000, 5_000), Some(1));
+        assert_eq!(constant_product_source_amount(5_000, 1_000, 5_000), None);
+    }
+
+    #[test]
+    fn fees_round_up() {
+        let fees = fees();
+        let with_fees = source_amount_with_fees(9_970, &fees).unwrap();
+        assert_eq!(with_fees, 10_000);
+        assert_eq!(source_amount_with_fees(9_971, &fees).unwrap(), 10_002);
+        let no_fees = Fees {
+            return_fee_numerator: 0,
+            fixed_fee_numerator: 0,
+            fee_denominator: 0,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        assert_eq!(source_amount_with_fees(123, &no_fees), Some(123));
+    }
+
+    #[test]
+    fn pool_cannot_pay_out() {
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let fees = fees();
+        assert!(swap_exact_out(&swap_curve, 0, 1_000, 5_000, TradeDirection::AtoB, &fees).is_none());
+        assert!(
+            swap_exact_out(&swap_curve, 5_000, 1_000, 5_000, TradeDirection::AtoB, &fees).is_none()
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn constant_product_exact_out(
+            swap_source_amount in 1_000..u64::MAX as u128 / 4,
+            swap_destination_amount in 1_000..u64::MAX as u128 / 4,
+            destination_fraction in 1..1_000u128,
+        ) {
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantProduct,
+                calculator: Box::new(ConstantProductCurve {}),
+            };
+            let destination_amount = swap_destination_amount * destination_fraction / 2_000;
+            prop_assume!(destination_amount > 0);
+            check_minimal_swap(
+                &swap_curve,
+                destination_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+                &fees(),
+            );
+        }
+
+        #[test]
+        fn constant_price_exact_out(
+            token_b_price in 1..1_000_000u64,
+            swap_source_amount in 1_000..u32::MAX as u128,
+            swap_destination_amount in 1_000..u32::MAX as u128,
+            destination_fraction in 1..1_000u128,
+        ) {
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantPrice,
+                calculator: Box::new(ConstantPriceCurve { token_b_price }),
+            };
+            let destination_amount = swap_destination_amount * destination_fraction / 2_000;
+            prop_assume!(destination_amount > 0);
+            // token A comes out in whole token B worths, one more of which
+            // must still fit in the pool
+            prop_assume!(u128::from(token_b_price) <= swap_destination_amount / 2);
+            for trade_direction in &[TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_minimal_swap(
+                    &swap_curve,
+                    destination_amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    *trade_direction,
+                    &fees(),
+                );
+            }
+        }
+
+        #[test]
+        fn stable_exact_out(
+            amp in 1..10_000u64,
+            swap_source_amount in 1_000..u32::MAX as u128,
+            swap_destination_amount in 1_000..u32::MAX as u128,
+            destination_fraction in 1..1_000u128,
+        ) {
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Box::new(StableCurve { amp }),
+            };
+            let destination_amount = swap_destination_amount * destination_fraction / 2_000;
+            prop_assume!(destination_amount > 0);
+            for trade_direction in &[TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_minimal_swap(
+                    &swap_curve,
+                    destination_amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    *trade_direction,
+                    &fees(),
+                );
+            }
+        }
+
+        #[test]
+        fn offset_exact_out(
+            token_b_offset in 1..u32::MAX as u64,
+            swap_source_amount in 1_000..u32::MAX as u128,
+            swap_destination_amount in 1_000..u32::MAX as u128,
+            destination_fraction in 1..1_000u128,
+        ) {
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::Offset,
+                calculator: Box::new(OffsetCurve { token_b_offset }),
+            };
+            let destination_amount = swap_destination_amount * destination_fraction / 2_000;
+            prop_assume!(destination_amount > 0);
+            for trade_direction in &[TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_minimal_swap(
+                    &swap_curve,
+                    destination_amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    *trade_direction,
+                    &fees(),
+                );
+            }
+        }
+    }
+}
@@ -6,7 +6,11 @@ pub mod amm_instruction;
 pub mod amm_stats;
 pub mod constraints;
 pub mod curve;
+pub mod dex;
 pub mod error;
+pub mod events;
+pub mod exact_out;
+pub mod oracle;
 pub mod processor;
 
 #[cfg(not(feature = "no-entrypoint"))]
@@ -0,0 +1,682 @@
+//! Time weighted average prices of a pool
+//!
+//! Every instruction moving the reserves of a pool first adds the price which
+//! held since the previous update, times the seconds elapsed, to cumulative
+//! prices.  The difference of the cumulative prices at two times, divided by
+//! the time between them, is the average price over that window, which a
+//! single transaction can not move much.  Prices are the ratio of the
+//! reserves, or the square of the square root price of concentrated liquidity
+//! pools, as Q64.64 fixed point numbers.  Multi token pools price every token
+//! in their first one, the ratio of the reserves being the price up to the
+//! ratio of the weights of a weighted pool.  The cumulative prices wrap on
+//! overflow, only their differences are meaningful.
+
+use crate::curve::multi::MAX_TOKENS;
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use spl_math::uint::U256;
+use solana_program::{
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+};
+use std::convert::TryFrom;
+
+/// Longest window, in seconds, a TWAP can be taken over from the ring buffer
+/// of observations once it is full
+pub const TWAP_WINDOW: i64 = 60 * 60;
+
+/// Minimum number of seconds between two observations of the ring buffer
+pub const OBSERVATION_PERIOD: i64 = 10 * 60;
+
+/// Number of observations kept in the ring buffer of a pool, enough for the
+/// oldest one to be at least `TWAP_WINDOW` seconds older than the latest
+pub const OBSERVATIONS: usize = (TWAP_WINDOW / OBSERVATION_PERIOD) as usize + 1;
+
+/// Cumulative prices of a pool at some time
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Observation {
+    /// Unix timestamp of the observation, zero for unused observations
+    pub unix_timestamp: i64,
+    /// Sum of the Q64.64 prices of token A, in token B, times the seconds
+    /// they held
+    pub price_a_cumulative: u128,
+    /// Sum of the Q64.64 prices of token B, in token A, times the seconds
+    /// they held
+    pub price_b_cumulative: u128,
+}
+
+impl Observation {
+    const LEN: usize = 40;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, Observation::LEN];
+        let (unix_timestamp, price_a_cumulative, price_b_cumulative) =
+            mut_array_refs![output, 8, 16, 16];
+        *unix_timestamp = self.unix_timestamp.to_le_bytes();
+        *price_a_cumulative = self.price_a_cumulative.to_le_bytes();
+        *price_b_cumulative = self.price_b_cumulative.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, Observation::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (unix_timestamp, price_a_cumulative, price_b_cumulative) =
+            array_refs![input, 8, 16, 16];
+        Self {
+            unix_timestamp: i64::from_le_bytes(*unix_timestamp),
+            price_a_cumulative: u128::from_le_bytes(*price_a_cumulative),
+            price_b_cumulative: u128::from_le_bytes(*price_b_cumulative),
+        }
+    }
+}
+
+/// Price accumulators of a pool, with a ring buffer of past observations
+/// taken at least `OBSERVATION_PERIOD` seconds apart.
+///
+/// A zeroed oracle, the state of pools created before it existed, starts
+/// accumulating at its first update.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PriceOracle {
+    /// Cumulative Q64.64 price of token A, in token B
+    pub price_a_cumulative: u128,
+    /// Cumulative Q64.64 price of token B, in token A
+    pub price_b_cumulative: u128,
+    /// Unix timestamp of the last update of the cumulative prices
+    pub last_update_ts: i64,
+    /// Index of the latest observation in `observations`
+    pub observation_index: u8,
+    /// Ring buffer of observations
+    pub observations: [Observation; OBSERVATIONS],
+}
+
+impl PriceOracle {
+    /// Oracle starting to accumulate at `unix_timestamp`
+    pub fn new(unix_timestamp: i64) -> Self {
+        let mut oracle = Self {
+            last_update_ts: unix_timestamp,
+            ..Self::default()
+        };
+        oracle.observations[0].unix_timestamp = unix_timestamp;
+        oracle
+    }
+
+    /// Cumulative prices as of the last update
+    pub fn latest(&self) -> Observation {
+        Observation {
+            unix_timestamp: self.last_update_ts,
+            price_a_cumulative: self.price_a_cumulative,
+            price_b_cumulative: self.price_b_cumulative,
+        }
+    }
+
+    /// Cumulative prices at `unix_timestamp`, if the reserves did not change
+    /// since the last update
+    pub fn observe(
+        &self,
+        unix_timestamp: i64,
+        token_a_amount: u64,
+        token_b_amount: u64,
+    ) -> Option<Observation> {
+        self.observe_prices(
+            unix_timestamp,
+            spot_prices(token_a_amount, token_b_amount),
+        )
+    }
+
+    /// Cumulative prices at `unix_timestamp`, if the square root price of a
+    /// concentrated liquidity pool did not change since the last update
+    pub fn observe_sqrt_price(&self, unix_timestamp: i64, sqrt_price: u128) -> Option<Observation> {
+        self.observe_prices(unix_timestamp, sqrt_price_prices(sqrt_price))
+    }
+
+    /// Cumulative prices at `unix_timestamp`, accumulating `prices` since the
+    /// last update, or nothing if the pool has no price
+    fn observe_prices(
+        &self,
+        unix_timestamp: i64,
+        prices: Option<(u128, u128)>,
+    ) -> Option<Observation> {
+        let mut observation = self.latest();
+        observation.unix_timestamp = unix_timestamp;
+        let (price_a, price_b) = match prices {
+            Some(prices) if self.last_update_ts != 0 => prices,
+            _ => return Some(observation),
+        };
+        let elapsed = u128::try_from(unix_timestamp.checked_sub(self.last_update_ts)?).ok()?;
+        observation.price_a_cumulative = observation
+            .price_a_cumulative
+            .wrapping_add(price_a.wrapping_mul(elapsed));
+        observation.price_b_cumulative = observation
+            .price_b_cumulative
+            .wrapping_add(price_b.wrapping_mul(elapsed));
+        Some(observation)
+    }
+
+    /// Accumulates the prices given by the reserves up to `unix_timestamp`,
+    /// before the reserves change, recording an observation if the latest one
+    /// is old enough
+    pub fn update(
+        &mut self,
+        unix_timestamp: i64,
+        token_a_amount: u64,
+        token_b_amount: u64,
+    ) -> Option<()> {
+        self.update_prices(
+            unix_timestamp,
+            spot_prices(token_a_amount, token_b_amount),
+        )
+    }
+
+    /// Accumulates the prices given by the square root price of a
+    /// concentrated liquidity pool up to `unix_timestamp`, before a swap
+    /// moves it
+    pub fn update_sqrt_price(&mut self, unix_timestamp: i64, sqrt_price: u128) -> Option<()> {
+        self.update_prices(unix_timestamp, sqrt_price_prices(sqrt_price))
+    }
+
+    fn update_prices(&mut self, unix_timestamp: i64, prices: Option<(u128, u128)>) -> Option<()> {
+        if unix_timestamp < self.last_update_ts {
+            return Some(());
+        }
+        let observation = self.observe_prices(unix_timestamp, prices)?;
+        self.price_a_cumulative = observation.price_a_cumulative;
+        self.price_b_cumulative = observation.price_b_cumulative;
+        self.last_update_ts = unix_timestamp;
+
+        let latest = &self.observations[usize::from(self.observation_index) % OBSERVATIONS];
+        if let Some(index) =
+            observation_slot(latest.unix_timestamp, self.observation_index, unix_timestamp)
+        {
+            self.observations[index] = observation;
+            self.observation_index = u8::try_from(index).ok()?;
+        }
+        Some(())
+    }
+
+    /// Recorded observations, oldest first
+    pub fn observations(&self) -> Vec<Observation> {
+        let start = usize::from(self.observation_index) + 1;
+        (start..start + OBSERVATIONS)
+            .map(|index| self.observations[index % OBSERVATIONS])
+            .filter(|observation| observation.unix_timestamp != 0)
+            .collect()
+    }
+
+    /// Latest recorded observation taken at or before `unix_timestamp`, the
+    /// start of a TWAP over the window ending now when given
+    /// `now - window`
+    pub fn observation_before(&self, unix_timestamp: i64) -> Option<Observation> {
+        self.observations()
+            .into_iter()
+            .rev()
+            .find(|observation| observation.unix_timestamp <= unix_timestamp)
+    }
+}
+
+impl Sealed for PriceOracle {}
+impl IsInitialized for PriceOracle {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Pack for PriceOracle {
+    const LEN: usize = 41 + Observation::LEN * OBSERVATIONS;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, PriceOracle::LEN];
+        let (
+            price_a_cumulative,
+            price_b_cumulative,
+            last_update_ts,
+            observation_index,
+            observations,
+        ) = mut_array_refs![output, 16, 16, 8, 1, Observation::LEN * OBSERVATIONS];
+        *price_a_cumulative = self.price_a_cumulative.to_le_bytes();
+        *price_b_cumulative = self.price_b_cumulative.to_le_bytes();
+        *last_update_ts = self.last_update_ts.to_le_bytes();
+        observation_index[0] = self.observation_index;
+        for (observation, output) in self
+            .observations
+            .iter()
+            .zip(observations.chunks_exact_mut(Observation::LEN))
+        {
+            observation.pack_into_slice(output);
+        }
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, PriceOracle::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            price_a_cumulative,
+            price_b_cumulative,
+            last_update_ts,
+            observation_index,
+            observations,
+        ) = array_refs![input, 16, 16, 8, 1, Observation::LEN * OBSERVATIONS];
+        let mut oracle = Self {
+            price_a_cumulative: u128::from_le_bytes(*price_a_cumulative),
+            price_b_cumulative: u128::from_le_bytes(*price_b_cumulative),
+            last_update_ts: i64::from_le_bytes(*last_update_ts),
+            observation_index: observation_index[0],
+            observations: [Observation::default(); OBSERVATIONS],
+        };
+        if usize::from(oracle.observation_index) >= OBSERVATIONS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        for (observation, input) in oracle
+            .observations
+            .iter_mut()
+            .zip(observations.chunks_exact(Observation::LEN))
+        {
+            *observation = Observation::unpack_from_slice(input);
+        }
+        Ok(oracle)
+    }
+}
+
+/// Q64.64 prices of token A in token B, and of token B in token A
+fn spot_prices(token_a_amount: u64, token_b_amount: u64) -> Option<(u128, u128)> {
+    let token_a_amount = u128::from(token_a_amount);
+    let token_b_amount = u128::from(token_b_amount);
+    Some((
+        (token_b_amount << 64).checked_div(token_a_amount)?,
+        (token_a_amount << 64).checked_div(token_b_amount)?,
+    ))
+}
+
+/// Q64.64 prices of token A in token B, and of token B in token A, at a Q64.64
+/// square root price of token A, saturating at the range of a u128
+fn sqrt_price_prices(sqrt_price: u128) -> Option<(u128, u128)> {
+    let squared = U256::from(sqrt_price).checked_mul(U256::from(sqrt_price))?;
+    let price_a = squared >> 64;
+    let price_b = (U256::one() << 192).checked_div(squared)?;
+    let saturate = |price: U256| u128::try_from(price).unwrap_or(u128::MAX);
+    Some((saturate(price_a), saturate(price_b)))
+}
+
+/// Index of the ring buffer recording an observation at `unix_timestamp`, or
+/// None if the latest one, taken at `latest_ts` and kept at
+/// `observation_index`, is too recent
+fn observation_slot(latest_ts: i64, observation_index: u8, unix_timestamp: i64) -> Option<usize> {
+    let index = usize::from(observation_index) % OBSERVATIONS;
+    if latest_ts == 0 {
+        Some(index)
+    } else if unix_timestamp.saturating_sub(latest_ts) >= OBSERVATION_PERIOD {
+        Some((index + 1) % OBSERVATIONS)
+    } else {
+        None
+    }
+}
+
+/// Q64.64 average prices of token A and token B between two observations,
+/// None if `newer` is not after `older`
+pub fn twap(older: &Observation, newer: &Observation) -> Option<(u128, u128)> {
+    let elapsed = u128::try_from(newer.unix_timestamp.checked_sub(older.unix_timestamp)?).ok()?;
+    if elapsed == 0 {
+        return None;
+    }
+    Some((
+        newer
+            .price_a_cumulative
+            .wrapping_sub(older.price_a_cumulative)
+            / elapsed,
+        newer
+            .price_b_cumulative
+            .wrapping_sub(older.price_b_cumulative)
+            / elapsed,
+    ))
+}
+
+/// Cumulative prices of the tokens of a multi token pool at some time
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MultiObservation {
+    /// Unix timestamp of the observation, zero for unused observations
+    pub unix_timestamp: i64,
+    /// Sums of the Q64.64 prices of each token, in the first token of the
+    /// pool, times the seconds they held
+    pub price_cumulatives: [u128; MAX_TOKENS],
+}
+
+impl MultiObservation {
+    const LEN: usize = 8 + 16 * MAX_TOKENS;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, MultiObservation::LEN];
+        let (unix_timestamp, price_cumulatives) = mut_array_refs![output, 8, 16 * MAX_TOKENS];
+        *unix_timestamp = self.unix_timestamp.to_le_bytes();
+        pack_cumulatives(&self.price_cumulatives, price_cumulatives);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Self {
+        let input = array_ref![input, 0, MultiObservation::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (unix_timestamp, price_cumulatives) = array_refs![input, 8, 16 * MAX_TOKENS];
+        Self {
+            unix_timestamp: i64::from_le_bytes(*unix_timestamp),
+            price_cumulatives: unpack_cumulatives(price_cumulatives),
+        }
+    }
+}
+
+/// Price accumulators of a multi token pool, pricing every token in the
+/// first one, with a ring buffer of past observations taken at least
+/// `OBSERVATION_PERIOD` seconds apart.  Only the first `token_count` prices
+/// of the pool are used, the others stay zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MultiPriceOracle {
+    /// Cumulative Q64.64 prices of each token, in the first token
+    pub price_cumulatives: [u128; MAX_TOKENS],
+    /// Unix timestamp of the last update of the cumulative prices
+    pub last_update_ts: i64,
+    /// Index of the latest observation in `observations`
+    pub observation_index: u8,
+    /// Ring buffer of observations
+    pub observations: [MultiObservation; OBSERVATIONS],
+}
+
+impl MultiPriceOracle {
+    /// Oracle starting to accumulate at `unix_timestamp`
+    pub fn new(unix_timestamp: i64) -> Self {
+        let mut oracle = Self {
+            last_update_ts: unix_timestamp,
+            ..Self::default()
+        };
+        oracle.observations[0].unix_timestamp = unix_timestamp;
+        oracle
+    }
+
+    /// Cumulative prices as of the last update
+    pub fn latest(&self) -> MultiObservation {
+        MultiObservation {
+            unix_timestamp: self.last_update_ts,
+            price_cumulatives: self.price_cumulatives,
+        }
+    }
+
+    /// Cumulative prices at `unix_timestamp`, if the reserves, in pool order,
+    /// did not change since the last update
+    pub fn observe(&self, unix_timestamp: i64, reserves: &[u64]) -> Option<MultiObservation> {
+        let mut observation = self.latest();
+        observation.unix_timestamp = unix_timestamp;
+        let (&first, _) = reserves.split_first()?;
+        if self.last_update_ts == 0 || reserves.contains(&0) {
+            return Some(observation);
+        }
+        let elapsed = u128::try_from(unix_timestamp.checked_sub(self.last_update_ts)?).ok()?;
+        for (cumulative, reserve) in observation.price_cumulatives.iter_mut().zip(reserves) {
+            let price = (u128::from(first) << 64).checked_div(u128::from(*reserve))?;
+            *cumulative = cumulative.wrapping_add(price.wrapping_mul(elapsed));
+        }
+        Some(observation)
+    }
+
+    /// Accumulates the prices given by the reserves, in pool order, up to
+    /// `unix_timestamp`, before the reserves change, recording an observation
+    /// if the latest one is old enough
+    pub fn update(&mut self, unix_timestamp: i64, reserves: &[u64]) -> Option<()> {
+        if unix_timestamp < self.last_update_ts {
+            return Some(());
+        }
+        let observation = self.observe(unix_timestamp, reserves)?;
+        self.price_cumulatives = observation.price_cumulatives;
+        self.last_update_ts = unix_timestamp;
+
+        let latest = &self.observations[usize::from(self.observation_index) % OBSERVATIONS];
+        if let Some(index) =
+            observation_slot(latest.unix_timestamp, self.observation_index, unix_timestamp)
+        {
+            self.observations[index] = observation;
+            self.observation_index = u8::try_from(index).ok()?;
+        }
+        Some(())
+    }
+
+    /// Recorded observations, oldest first
+    pub fn observations(&self) -> Vec<MultiObservation> {
+        let start = usize::from(self.observation_index) + 1;
+        (start..start + OBSERVATIONS)
+            .map(|index| self.observations[index % OBSERVATIONS])
+            .filter(|observation| observation.unix_timestamp != 0)
+            .collect()
+    }
+}
+
+impl Sealed for MultiPriceOracle {}
+impl IsInitialized for MultiPriceOracle {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Pack for MultiPriceOracle {
+    const LEN: usize = 9 + 16 * MAX_TOKENS + MultiObservation::LEN * OBSERVATIONS;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, MultiPriceOracle::LEN];
+        let (price_cumulatives, last_update_ts, observation_index, observations) = mut_array_refs![
+            output,
+            16 * MAX_TOKENS,
+            8,
+            1,
+            MultiObservation::LEN * OBSERVATIONS
+        ];
+        pack_cumulatives(&self.price_cumulatives, price_cumulatives);
+        *last_update_ts = self.last_update_ts.to_le_bytes();
+        observation_index[0] = self.observation_index;
+        for (observation, output) in self
+            .observations
+            .iter()
+            .zip(observations.chunks_exact_mut(MultiObservation::LEN))
+        {
+            observation.pack_into_slice(output);
+        }
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, MultiPriceOracle::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (price_cumulatives, last_update_ts, observation_index, observations) = array_refs![
+            input,
+            16 * MAX_TOKENS,
+            8,
+            1,
+            MultiObservation::LEN * OBSERVATIONS
+        ];
+        let mut oracle = Self {
+            price_cumulatives: unpack_cumulatives(price_cumulatives),
+            last_update_ts: i64::from_le_bytes(*last_update_ts),
+            observation_index: observation_index[0],
+            observations: [MultiObservation::default(); OBSERVATIONS],
+        };
+        if usize::from(oracle.observation_index) >= OBSERVATIONS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        for (observation, input) in oracle
+            .observations
+            .iter_mut()
+            .zip(observations.chunks_exact(MultiObservation::LEN))
+        {
+            *observation = MultiObservation::unpack_from_slice(input);
+        }
+        Ok(oracle)
+    }
+}
+
+fn pack_cumulatives(cumulatives: &[u128; MAX_TOKENS], output: &mut [u8; 16 * MAX_TOKENS]) {
+    for (cumulative, output) in cumulatives.iter().zip(output.chunks_exact_mut(16)) {
+        output.copy_from_slice(&cumulative.to_le_bytes());
+    }
+}
+
+fn unpack_cumulatives(input: &[u8; 16 * MAX_TOKENS]) -> [u128; MAX_TOKENS] {
+    let mut cumulatives = [0u128; MAX_TOKENS];
+    for (cumulative, input) in cumulatives.iter_mut().zip(input.chunks_exact(16)) {
+        *cumulative = u128::from_le_bytes(*array_ref![input, 0, 16]);
+    }
+    cumulatives
+}
+
+/// Q64.64 average prices of each token of a multi token pool, in its first
+/// token, between two observations, None if `newer` is not after `older`
+pub fn multi_twap(
+    older: &MultiObservation,
+    newer: &MultiObservation,
+) -> Option<[u128; MAX_TOKENS]> {
+    let elapsed = u128::try_from(newer.unix_timestamp.checked_sub(older.unix_timestamp)?).ok()?;
+    if elapsed == 0 {
+        return None;
+    }
+    let mut prices = [0u128; MAX_TOKENS];
+    for ((price, older), newer) in prices
+        .iter_mut()
+        .zip(&older.price_cumulatives)
+        .zip(&newer.price_cumulatives)
+    {
+        *price = newer.wrapping_sub(*older) / elapsed;
+    }
+    Some(prices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: u128 = 1 << 64;
+
+    #[test]
+    fn pack_oracle() {
+        let mut oracle = PriceOracle::new(1_000);
+        oracle.update(1_100, 1_000, 2_000).unwrap();
+        oracle
+            .update(1_000 + OBSERVATION_PERIOD, 2_000, 1_000)
+            .unwrap();
+        let mut packed = [0u8; PriceOracle::LEN];
+        oracle.pack_into_slice(&mut packed);
+        assert_eq!(PriceOracle::unpack_from_slice(&packed).unwrap(), oracle);
+
+        packed[40] = OBSERVATIONS as u8;
+        assert_eq!(
+            PriceOracle::unpack_from_slice(&packed),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn twap_over_window() {
+        let mut oracle = PriceOracle::new(1_000);
+        // price of A is 2 for 100 seconds, then 1/2 for 300 seconds
+        oracle.update(1_100, 1_000, 2_000).unwrap();
+        oracle.update(1_400, 2_000, 1_000).unwrap();
+        let (price_a, price_b) = twap(&oracle.observations[0], &oracle.latest()).unwrap();
+        assert_eq!(price_a, (2 * ONE * 100 + ONE / 2 * 300) / 400);
+        assert_eq!(price_b, (ONE / 2 * 100 + 2 * ONE * 300) / 400);
+
+        // extrapolated to later, at the current reserves
+        let now = oracle.observe(1_800, 1_000, 1_000).unwrap();
+        let (price_a, _) = twap(&oracle.latest(), &now).unwrap();
+        assert_eq!(price_a, ONE);
+
+        assert_eq!(twap(&now, &now), None);
+        assert_eq!(twap(&now, &oracle.latest()), None);
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let mut oracle = PriceOracle::new(1);
+        assert_eq!(oracle.observations().len(), 1);
+        // too close to the latest observation
+        oracle.update(OBSERVATION_PERIOD, 1_000, 1_000).unwrap();
+        assert_eq!(oracle.observations().len(), 1);
+        let periods = OBSERVATIONS as i64 + 1;
+        for period in 1..=periods {
+            oracle
+                .update(1 + period * OBSERVATION_PERIOD, 1_000, 1_000)
+                .unwrap();
+        }
+        let observations = oracle.observations();
+        assert_eq!(
+            observations
+                .iter()
+                .map(|observation| observation.unix_timestamp)
+                .collect::<Vec<_>>(),
+            (2..=periods)
+                .map(|period| 1 + period * OBSERVATION_PERIOD)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(*observations.last().unwrap(), oracle.latest());
+
+        // a full ring buffer covers the TWAP window
+        let now = oracle.latest().unix_timestamp;
+        assert!(now - observations[0].unix_timestamp >= TWAP_WINDOW);
+        assert_eq!(
+            oracle.observation_before(now - TWAP_WINDOW),
+            Some(observations[0])
+        );
+        assert_eq!(
+            oracle.observation_before(now - OBSERVATION_PERIOD),
+            Some(observations[OBSERVATIONS - 2])
+        );
+        assert_eq!(oracle.observation_before(0), None);
+
+        // a zeroed oracle starts at its first update
+        let mut oracle = PriceOracle::default();
+        oracle.update(500, 1_000, 1_000).unwrap();
+        assert_eq!(oracle.latest().price_a_cumulative, 0);
+        assert_eq!(oracle.observations(), vec![oracle.latest()]);
+        // empty reserves have no price
+        oracle.update(600, 0, 1_000).unwrap();
+        assert_eq!(oracle.latest().price_a_cumulative, 0);
+        assert_eq!(oracle.last_update_ts, 600);
+    }
+
+    #[test]
+    fn sqrt_price_oracle() {
+        let mut oracle = PriceOracle::new(1_000);
+        // square root price of 2 for 100 seconds
+        oracle.update_sqrt_price(1_100, 2 * ONE).unwrap();
+        let (price_a, price_b) = twap(&oracle.observations[0], &oracle.latest()).unwrap();
+        assert_eq!(price_a, 4 * ONE);
+        assert_eq!(price_b, ONE / 4);
+
+        let latest = oracle.latest();
+        // a zero square root price has no price
+        let observation = oracle.observe_sqrt_price(1_200, 0).unwrap();
+        assert_eq!(observation.price_a_cumulative, latest.price_a_cumulative);
+        // the prices saturate at the range of a u128
+        let observation = oracle.observe_sqrt_price(1_101, u128::MAX).unwrap();
+        assert_eq!(
+            observation.price_a_cumulative,
+            latest.price_a_cumulative.wrapping_add(u128::MAX)
+        );
+        assert_eq!(observation.price_b_cumulative, latest.price_b_cumulative);
+    }
+
+    #[test]
+    fn multi_oracle() {
+        let mut oracle = MultiPriceOracle::new(1_000);
+        // prices of 1, 1/2 and 4 in the first token for 100 seconds
+        oracle.update(1_100, &[1_000, 2_000, 250]).unwrap();
+        let prices = multi_twap(&oracle.observations[0], &oracle.latest()).unwrap();
+        assert_eq!(prices[..4], [ONE, ONE / 2, 4 * ONE, 0]);
+        assert_eq!(multi_twap(&oracle.latest(), &oracle.latest()), None);
+
+        // empty reserves have no price
+        let observation = oracle.observe(1_200, &[1_000, 0, 1_000]).unwrap();
+        assert_eq!(observation.price_cumulatives, oracle.price_cumulatives);
+        assert_eq!(oracle.observe(1_200, &[]), None);
+
+        oracle
+            .update(1_000 + OBSERVATION_PERIOD, &[1_000, 1_000, 1_000])
+            .unwrap();
+        assert_eq!(oracle.observations().len(), 2);
+        assert_eq!(*oracle.observations().last().unwrap(), oracle.latest());
+
+        let mut packed = [0u8; MultiPriceOracle::LEN];
+        oracle.pack_into_slice(&mut packed);
+        assert_eq!(MultiPriceOracle::unpack_from_slice(&packed).unwrap(), oracle);
+        packed[16 * MAX_TOKENS + 8] = OBSERVATIONS as u8;
+        assert_eq!(
+            MultiPriceOracle::unpack_from_slice(&packed),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+}
@@ -3,23 +3,53 @@
 use crate::constraints::{SwapConstraints, SWAP_CONSTRAINTS};
 use crate::{
     curve::{
-        base::SwapCurve,
+        base::{CurveType, SwapCurve, SwapResult},
         calculator::{RoundDirection, TradeDirection},
         fees::Fees,
+        concentrated::{tick_at_sqrt_price, token_amounts, MAX_TICK, MIN_TICK},
+        multi::{MultiSwapCurve, MAX_TOKENS, MIN_TOKENS},
+        stable::{AmpRamp, StableCurve},
     },
+    dex::{order_price, pubkey_from_words, serum_dex_program, OpenOrders, OrderSide},
     error::AmmError,
+    events::{
+        AmmEvent, AmpRampEvent, FeeWalletEvent, FeesEvent, FixedFeeAccountEvent,
+        FlashLoanEvent, HostFeeEvent, LiquidityEvent, MultiLiquidityEvent, MultiSwapEvent,
+        PoolStatusEvent, PositionEvent, ProtocolFeeEvent, SwapEvent,
+    },
+    exact_out::{swap_exact_out, unpack_calculator},
+    oracle::{MultiPriceOracle, PriceOracle},
     amm_instruction::{
         DepositInstruction, DepositSingleTokenTypeExactAmountIn, InitializeInstruction, SwapInstruction,
         AmmInstruction, WithdrawInstruction, WithdrawSingleTokenTypeExactAmountOut,
+        InitializeConfigInstruction, SetFeesInstruction, SetPoolStatusInstruction,
+        SwapExactAmountOutInstruction, RouteSwapInstruction, ROUTE_SWAP_LEG_ACCOUNTS,
+        PlaceOrderInstruction, CancelOrderInstruction, RampAmpInstruction,
+        InitializeMultiInstruction, MultiDepositInstruction, MultiWithdrawInstruction,
+        MULTI_TOKEN_ACCOUNTS, InitializeConcentratedInstruction, OpenPositionInstruction,
+        ClosePositionInstruction, CollectFeesInstruction, Deadline, FlashLoanInstruction,
+        FlashSwapInstruction,
+        SetHostFeeInstruction, SetProtocolFeeInstruction,
+        NATIVE_SOL_SEED, find_native_sol_address,
+    },
+    amm_stats::{
+        AmmStatus, ConcentratedPool, HostFee, MultiPoolStats, MultiSwap, PoolStats, PoolStatus,
+        Position, ProgramConfig, ProtocolFee, SwapV2, SwapV3, SwapVersion, Tick,
+        CONFIG_SEED, MAX_TICKS, MAX_TICK_SPACING,
     },
-    amm_stats::{AmmStatus, SwapV1, SwapVersion},
 };
 use std::str::FromStr;
 use num_traits::FromPrimitive;
+use serum_dex::{
+    instruction::SelfTradeBehavior,
+    matching::OrderType,
+    state::MarketState,
+};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     decode_error::DecodeError,
     entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
     msg,
     program::invoke_signed,
     program::invoke,
@@ -28,40 +58,268 @@ use solana_program::{
     program_pack::Pack,
     pubkey::Pubkey,
     system_instruction,
-
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
-use std::convert::TryInto;
-const FEE_WALLET_ADDRESS:&str = "2Pv5mjmKYAtXNpr3mcsXf7HjtS3fieJeFoWPATVT5rWa";
+use spl_token_2022::extension::{
+    transfer_fee::{TransferFee, TransferFeeConfig},
+    BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+};
+use std::convert::{TryFrom, TryInto};
+use std::num::NonZeroU64;
 const WSOL_MINT_ADDRESS:&str = "So11111111111111111111111111111111111111112";
+/// Accounts taking part in a swap through one pool
+#[derive(Clone, Copy)]
+struct SwapAccounts<'a, 'b> {
+    swap_info: &'a AccountInfo<'b>,
+    authority_info: &'a AccountInfo<'b>,
+    user_transfer_authority_info: &'a AccountInfo<'b>,
+    source_info: &'a AccountInfo<'b>,
+    swap_source_info: &'a AccountInfo<'b>,
+    swap_destination_info: &'a AccountInfo<'b>,
+    destination_info: &'a AccountInfo<'b>,
+    pool_mint_info: &'a AccountInfo<'b>,
+    fixed_fee_account_info: &'a AccountInfo<'b>,
+    fixed_fee_wallet_info: &'a AccountInfo<'b>,
+    token_program_info: &'a AccountInfo<'b>,
+    system_program_info: &'a AccountInfo<'b>,
+    config_info: &'a AccountInfo<'b>,
+    open_orders_info: Option<&'a AccountInfo<'b>>,
+    host_fee_info: Option<&'a AccountInfo<'b>>,
+    /// Accounts of the instruction, among which the token programs and
+    /// Token-2022 mints of the pool tokens are found
+    instruction_infos: &'a [AccountInfo<'b>],
+}
+
+/// Token program and mint of the tokens on one side of a two token pool,
+/// which may be on Token-2022 with a transfer fee
+struct TokenSide<'a, 'b> {
+    token_program_info: &'a AccountInfo<'b>,
+    /// Mint and decimals of Token-2022 tokens, moved with `TransferChecked`
+    mint: Option<(&'a AccountInfo<'b>, u8)>,
+    /// Transfer fee of the mint in the current epoch
+    transfer_fee: Option<TransferFee>,
+}
+
+impl<'a, 'b> TokenSide<'a, 'b> {
+    /// Side of tokens moved with a plain `Transfer` of `token_program_info`
+    fn plain(token_program_info: &'a AccountInfo<'b>) -> Self {
+        TokenSide {
+            token_program_info,
+            mint: None,
+            transfer_fee: None,
+        }
+    }
+
+    /// Amount received by the destination of a transfer of `amount`
+    fn amount_received(&self, amount: u64) -> Result<u64, AmmError> {
+        match &self.transfer_fee {
+            Some(transfer_fee) => transfer_fee
+                .calculate_post_fee_amount(amount)
+                .ok_or(AmmError::FeeCalculationFailure),
+            None => Ok(amount),
+        }
+    }
+
+    /// Amount to transfer for the destination to receive `amount`
+    fn amount_sent(&self, amount: u64) -> Result<u64, AmmError> {
+        match &self.transfer_fee {
+            Some(transfer_fee) => transfer_fee
+                .calculate_pre_fee_amount(amount)
+                .filter(|sent| transfer_fee.calculate_post_fee_amount(*sent) == Some(amount))
+                .ok_or(AmmError::FeeCalculationFailure),
+            None => Ok(amount),
+        }
+    }
+
+    /// Transfers `amount` of the side's tokens, signed by the swap authority
+    /// when it is the `authority`
+    fn transfer(
+        &self,
+        swap: &Pubkey,
+        source: &AccountInfo<'b>,
+        destination: &AccountInfo<'b>,
+        authority: &AccountInfo<'b>,
+        nonce: u8,
+        amount: u64,
+    ) -> ProgramResult {
+        let (mint_info, decimals) = match self.mint {
+            Some(mint) => mint,
+            None => {
+                return Processor::token_transfer(
+                    swap,
+                    self.token_program_info.clone(),
+                    source.clone(),
+                    destination.clone(),
+                    authority.clone(),
+                    nonce,
+                    amount,
+                )
+            }
+        };
+        let swap_bytes = swap.to_bytes();
+        let authority_signature_seeds = [&swap_bytes[..32], &[nonce]];
+        let signers = &[&authority_signature_seeds[..]];
+        let ix = spl_token_2022::instruction::transfer_checked(
+            self.token_program_info.key,
+            source.key,
+            mint_info.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+            decimals,
+        )?;
+        invoke_signed(
+            &ix,
+            &[
+                source.clone(),
+                mint_info.clone(),
+                destination.clone(),
+                authority.clone(),
+                self.token_program_info.clone(),
+            ],
+            signers,
+        )
+    }
+}
+
 /// Program state handler.
 pub struct Processor {}
 impl Processor {
-    /// Unpacks a spl_token `Account`.
+    /// Unpacks a spl_token `Account`, or the base account of a Token-2022
+    /// account with its extensions.
     pub fn unpack_token_account(
         account_info: &AccountInfo,
         token_program_id: &Pubkey,
     ) -> Result<spl_token::state::Account, AmmError> {
         if account_info.owner != token_program_id {
             Err(AmmError::IncorrectTokenProgramId)
+        } else if *token_program_id == spl_token_2022::id() {
+            let data = account_info.data.borrow();
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(&data)
+                .and_then(|_| {
+                    spl_token::state::Account::unpack_from_slice(
+                        &data[..spl_token::state::Account::LEN],
+                    )
+                })
+                .map_err(|_| AmmError::ExpectedAccount)
         } else {
             spl_token::state::Account::unpack(&account_info.data.borrow())
                 .map_err(|_| AmmError::ExpectedAccount)
         }
     }
 
-    /// Unpacks a spl_token `Mint`.
+    /// Unpacks a spl_token `Mint`, or the base mint of a Token-2022 mint with
+    /// its extensions.
     pub fn unpack_mint(
         account_info: &AccountInfo,
         token_program_id: &Pubkey,
     ) -> Result<spl_token::state::Mint, AmmError> {
         if account_info.owner != token_program_id {
             Err(AmmError::IncorrectTokenProgramId)
+        } else if *token_program_id == spl_token_2022::id() {
+            let data = account_info.data.borrow();
+            StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)
+                .and_then(|_| {
+                    spl_token::state::Mint::unpack_from_slice(&data[..spl_token::state::Mint::LEN])
+                })
+                .map_err(|_| AmmError::ExpectedMint)
         } else {
             spl_token::state::Mint::unpack(&account_info.data.borrow())
                 .map_err(|_| AmmError::ExpectedMint)
         }
     }
 
+    /// Token program of a token account, spl_token or Token-2022
+    fn token_program_id_of<'a>(account_info: &'a AccountInfo) -> Result<&'a Pubkey, AmmError> {
+        if *account_info.owner == spl_token::id() || *account_info.owner == spl_token_2022::id() {
+            Ok(account_info.owner)
+        } else {
+            Err(AmmError::IncorrectTokenProgramId)
+        }
+    }
+
+    /// Whether the account is a token program or a Token-2022 mint, which
+    /// instructions on pools with Token-2022 tokens take after their listed
+    /// accounts and which are skipped when reading the optional ones
+    fn is_token_side_account(account_info: &AccountInfo) -> bool {
+        *account_info.key == spl_token::id()
+            || *account_info.key == spl_token_2022::id()
+            || (*account_info.owner == spl_token_2022::id()
+                && StateWithExtensions::<spl_token_2022::state::Mint>::unpack(
+                    &account_info.data.borrow(),
+                )
+                .is_ok())
+    }
+
+    /// Next optional account of an instruction on a two token pool, past the
+    /// token programs and Token-2022 mints given for its tokens
+    fn next_optional_account<'a, 'b>(
+        account_info_iter: &mut std::slice::Iter<'a, AccountInfo<'b>>,
+    ) -> Option<&'a AccountInfo<'b>> {
+        account_info_iter.find(|account_info| !Self::is_token_side_account(account_info))
+    }
+
+    /// Token program and mint of the pool tokens held in `swap_token_info`.
+    /// Tokens on another program than the pool's `token_program_info`, and
+    /// the mints of Token-2022 tokens, are found by key among `accounts`.
+    fn token_side<'a, 'b>(
+        accounts: &'a [AccountInfo<'b>],
+        token_program_info: &'a AccountInfo<'b>,
+        swap_token_info: &AccountInfo<'b>,
+        mint: &Pubkey,
+    ) -> Result<TokenSide<'a, 'b>, ProgramError> {
+        let token_program_id = Self::token_program_id_of(swap_token_info)?;
+        let find = |key: &Pubkey| accounts.iter().find(|info| info.key == key);
+        let token_program_info = if token_program_info.key == token_program_id {
+            token_program_info
+        } else {
+            find(token_program_id).ok_or(AmmError::IncorrectTokenProgramId)?
+        };
+        if *token_program_id != spl_token_2022::id() {
+            return Ok(TokenSide::plain(token_program_info));
+        }
+        let mint_info = find(mint).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if mint_info.owner != token_program_id {
+            return Err(AmmError::IncorrectTokenProgramId.into());
+        }
+        let data = mint_info.data.borrow();
+        let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)
+            .map_err(|_| AmmError::ExpectedMint)?;
+        let transfer_fee = match mint.get_extension::<TransferFeeConfig>() {
+            Ok(config) => Some(*config.get_epoch_fee(Clock::get()?.epoch)),
+            Err(_) => None,
+        };
+        Ok(TokenSide {
+            token_program_info,
+            mint: Some((mint_info, mint.base.decimals)),
+            transfer_fee,
+        })
+    }
+
+    /// Checks that a Token-2022 mint of a pool token has no extension that
+    /// lets someone else than the swap authority move or hold back the
+    /// reserves, or that runs other programs on their transfers
+    fn check_mint_extensions(mint_info: &AccountInfo) -> ProgramResult {
+        if *mint_info.owner != spl_token_2022::id() {
+            return Ok(());
+        }
+        let data = mint_info.data.borrow();
+        let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)
+            .map_err(|_| AmmError::ExpectedMint)?;
+        for extension in mint.get_extension_types()? {
+            if matches!(
+                extension,
+                ExtensionType::TransferHook
+                    | ExtensionType::PermanentDelegate
+                    | ExtensionType::NonTransferable
+            ) {
+                return Err(AmmError::UnsupportedMintExtension.into());
+            }
+        }
+        Ok(())
+    }
+
     /// Calculates the authority id by generating a program address.
     pub fn authority_id(
         program_id: &Pubkey,
@@ -72,6 +330,54 @@ impl Processor {
             .or(Err(AmmError::InvalidProgramAddress))
     }
 
+    /// Calculates the program config id and its nonce, the canonical bump of
+    /// the config seed, so that the program has a single config.
+    pub fn config_id(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[CONFIG_SEED], program_id)
+    }
+
+    /// Unpacks the program config, checking that it lives at the config address
+    /// and records its canonical nonce.
+    pub fn unpack_config(
+        program_id: &Pubkey,
+        config_info: &AccountInfo,
+    ) -> Result<ProgramConfig, ProgramError> {
+        if config_info.owner != program_id {
+            return Err(AmmError::IncorrectConfigAccount.into());
+        }
+        let config = ProgramConfig::unpack(&config_info.data.borrow())?;
+        let (config_id, nonce) = Self::config_id(program_id);
+        if *config_info.key != config_id || config.nonce != nonce {
+            return Err(AmmError::IncorrectConfigAccount.into());
+        }
+        Ok(config)
+    }
+
+    fn check_admin(config: &ProgramConfig, admin_info: &AccountInfo) -> ProgramResult {
+        if !admin_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if *admin_info.key != config.admin {
+            return Err(AmmError::InvalidAdmin.into());
+        }
+        Ok(())
+    }
+
+    /// Fails the instruction once the `Clock` is past its deadline, if any
+    fn check_deadline(deadline: Option<Deadline>) -> ProgramResult {
+        let expired = match deadline {
+            Some(Deadline::UnixTimestamp(unix_timestamp)) => {
+                Clock::get()?.unix_timestamp > unix_timestamp
+            }
+            Some(Deadline::Slot(slot)) => Clock::get()?.slot > slot,
+            None => false,
+        };
+        if expired {
+            return Err(AmmError::Expired.into());
+        }
+        Ok(())
+    }
+
     /// Issue a spl_token `Burn` instruction.
     pub fn token_burn<'a>(
         swap: &Pubkey,
@@ -155,6 +461,383 @@ impl Processor {
         )
     }
 
+    /// Issue a Serum dex instruction signed by the swap authority
+    pub fn dex_invoke_signed(
+        swap: &Pubkey,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        nonce: u8,
+    ) -> Result<(), ProgramError> {
+        let swap_bytes = swap.to_bytes();
+        let authority_signature_seeds = [&swap_bytes[..32], &[nonce]];
+        let signers = &[&authority_signature_seeds[..]];
+        invoke_signed(instruction, account_infos, signers)
+    }
+
+    /// Amounts of token A and B owned by the pool: the balances of its token
+    /// accounts plus the funds held in its Serum open orders account, which
+    /// is required once the pool has one.  The open orders must still be
+    /// those of the pool market and swap authority, unless the dex has not
+    /// initialized them yet and they hold nothing, and be owned by the Serum
+    /// dex even for pools created before the dex program was pinned.
+    fn pool_reserves(
+        token_swap: &dyn AmmStatus,
+        authority_info: &AccountInfo,
+        token_a_amount: u64,
+        token_b_amount: u64,
+        open_orders_info: Option<&AccountInfo>,
+    ) -> Result<(u64, u64), ProgramError> {
+        if *token_swap.open_orders() == Pubkey::default() {
+            return Ok((token_a_amount, token_b_amount));
+        }
+        let open_orders_info = open_orders_info.ok_or(AmmError::IncorrectOpenOrdersAccount)?;
+        if open_orders_info.key != token_swap.open_orders()
+            || open_orders_info.owner != token_swap.dex_program_id()
+            || *open_orders_info.owner != serum_dex_program::id()
+        {
+            return Err(AmmError::IncorrectOpenOrdersAccount.into());
+        }
+        let open_orders = OpenOrders::unpack(&open_orders_info.data.borrow())?;
+        if open_orders != OpenOrders::default() {
+            if open_orders.market != *token_swap.market_id() {
+                return Err(AmmError::IncorrectOpenOrdersAccount.into());
+            }
+            if open_orders.owner != *authority_info.key {
+                return Err(AmmError::IncorrectOpenOrdersOwner.into());
+            }
+        }
+        Ok((
+            token_a_amount
+                .checked_add(open_orders.native_coin_total)
+                .ok_or(AmmError::CalculationFailure)?,
+            token_b_amount
+                .checked_add(open_orders.native_pc_total)
+                .ok_or(AmmError::CalculationFailure)?,
+        ))
+    }
+
+    /// Reserves of the pool held in the base account `swap_token_info` and
+    /// in `other_swap_token_info`, in that order, open orders included
+    fn ordered_pool_reserves(
+        token_swap: &dyn AmmStatus,
+        authority_info: &AccountInfo,
+        swap_token_info: &AccountInfo,
+        other_swap_token_info: &AccountInfo,
+        open_orders_info: &AccountInfo,
+    ) -> Result<(u64, u64), ProgramError> {
+        let swap_token = Self::unpack_token_account(swap_token_info, token_swap.token_program_id())?;
+        let other_swap_token =
+            Self::unpack_token_account(other_swap_token_info, token_swap.token_program_id())?;
+        if *swap_token_info.key == *token_swap.token_a_account() {
+            Self::pool_reserves(
+                token_swap,
+                authority_info,
+                swap_token.amount,
+                other_swap_token.amount,
+                Some(open_orders_info),
+            )
+        } else {
+            let (token_a_amount, token_b_amount) = Self::pool_reserves(
+                token_swap,
+                authority_info,
+                other_swap_token.amount,
+                swap_token.amount,
+                Some(open_orders_info),
+            )?;
+            Ok((token_b_amount, token_a_amount))
+        }
+    }
+
+    /// Accumulates the prices given by the reserves into the price oracle of
+    /// the pool, before the instruction moves them.  Pools created before
+    /// SwapV2 have no room for the oracle and are left as they are.
+    fn update_price_oracle(
+        swap_info: &AccountInfo,
+        token_a_amount: u64,
+        token_b_amount: u64,
+    ) -> ProgramResult {
+        let mut token_swap = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        if let Some(price_oracle) = token_swap.price_oracle_mut() {
+            price_oracle
+                .update(Clock::get()?.unix_timestamp, token_a_amount, token_b_amount)
+                .ok_or(AmmError::CalculationFailure)?;
+            SwapVersion::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        }
+        Ok(())
+    }
+
+    /// Records an instruction in the statistics of the pool.  Pools created
+    /// before SwapV3 have no room for them and are left as they are.
+    fn record_stats<F>(swap_info: &AccountInfo, record: F) -> ProgramResult
+    where
+        F: FnOnce(&mut PoolStats),
+    {
+        let mut token_swap = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        if let Some(stats) = token_swap.stats_mut() {
+            record(stats);
+            SwapVersion::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        }
+        Ok(())
+    }
+
+    /// Records an instruction in the statistics of a multi token pool
+    fn record_multi_stats<F>(swap_info: &AccountInfo, record: F) -> ProgramResult
+    where
+        F: FnOnce(&mut MultiPoolStats),
+    {
+        let mut multi_swap = MultiSwap::unpack_account(&swap_info.data.borrow())?;
+        record(&mut multi_swap.stats);
+        MultiSwap::pack_account(multi_swap, &mut swap_info.data.borrow_mut())
+    }
+
+    /// Accumulates the prices given by the reserves, in pool order, into the
+    /// price oracle of a multi token pool, before the instruction moves them
+    fn update_multi_price_oracle(swap_info: &AccountInfo, reserves: &[u128]) -> ProgramResult {
+        let reserves = reserves
+            .iter()
+            .map(|reserve| to_u64(*reserve))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut multi_swap = MultiSwap::unpack_account(&swap_info.data.borrow())?;
+        multi_swap
+            .price_oracle
+            .update(Clock::get()?.unix_timestamp, &reserves)
+            .ok_or(AmmError::CalculationFailure)?;
+        MultiSwap::pack_account(multi_swap, &mut swap_info.data.borrow_mut())
+    }
+
+    /// Builds the event of a deposit into or withdrawal from a multi token
+    /// pool, `token_amounts` being in pool order, with the reserves left
+    fn multi_liquidity_event(
+        swap_info: &AccountInfo,
+        user_info: &AccountInfo,
+        multi_swap: &MultiSwap,
+        token_infos: &[&AccountInfo],
+        token_amounts: &[u64],
+        pool_token_amount: u64,
+    ) -> Result<MultiLiquidityEvent, ProgramError> {
+        let mut event = MultiLiquidityEvent {
+            pool: *swap_info.key,
+            user: *user_info.key,
+            token_amounts: [0; MAX_TOKENS],
+            pool_token_amount,
+            reserves: [0; MAX_TOKENS],
+        };
+        event.token_amounts[..token_amounts.len()].copy_from_slice(token_amounts);
+        for (reserve, swap_token_amount) in event
+            .reserves
+            .iter_mut()
+            .zip(Self::multi_pool_reserves(multi_swap, token_infos)?)
+        {
+            *reserve = to_u64(swap_token_amount)?;
+        }
+        Ok(event)
+    }
+
+    /// Locks or unlocks the pool around a flash loan or flash swap
+    fn set_pool_locked(swap_info: &AccountInfo, locked: bool) -> ProgramResult {
+        let mut token_swap = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        token_swap.set_locked(locked)?;
+        SwapVersion::pack(token_swap, &mut swap_info.data.borrow_mut())
+    }
+
+    /// Invokes the receiver of a flash loan or flash swap with the pool
+    /// token account to pay INTO, the destination of the lent tokens and the
+    /// token program, followed by the accounts given for the receiver
+    fn invoke_receiver<'a>(
+        receiver_program_info: &AccountInfo<'a>,
+        swap_token_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        receiver_infos: &[AccountInfo<'a>],
+        data: &[u8],
+    ) -> ProgramResult {
+        let mut accounts = vec![
+            AccountMeta::new(*swap_token_info.key, false),
+            AccountMeta::new(*destination_info.key, false),
+            AccountMeta::new_readonly(*token_program_info.key, false),
+        ];
+        accounts.extend(receiver_infos.iter().map(|info| AccountMeta {
+            pubkey: *info.key,
+            is_signer: info.is_signer,
+            is_writable: info.is_writable,
+        }));
+        let mut account_infos = vec![
+            swap_token_info.clone(),
+            destination_info.clone(),
+            token_program_info.clone(),
+        ];
+        account_infos.extend(receiver_infos.iter().cloned());
+        account_infos.push(receiver_program_info.clone());
+        invoke(
+            &Instruction {
+                program_id: *receiver_program_info.key,
+                accounts,
+                data: data.to_vec(),
+            },
+            &account_infos,
+        )
+    }
+
+    /// Amplification of a stable curve at `unix_timestamp`, the one its ramp
+    /// reached if the pool ever ramped
+    fn current_amp(token_swap: &dyn AmmStatus, unix_timestamp: i64) -> Result<u64, ProgramError> {
+        let amp_ramp = token_swap.amp_ramp();
+        let amp = if amp_ramp.is_set() {
+            amp_ramp.amp_at(unix_timestamp)
+        } else {
+            unpack_calculator::<StableCurve>(token_swap.swap_curve()).map(|curve| curve.amp)
+        };
+        Ok(amp.ok_or(AmmError::InvalidCurve)?)
+    }
+
+    /// Swap curve to trade with now, a ramping stable curve takes the
+    /// amplification interpolated at the current `Clock` time
+    fn current_swap_curve(token_swap: &dyn AmmStatus) -> Result<SwapCurve, ProgramError> {
+        let swap_curve = token_swap.swap_curve();
+        if swap_curve.curve_type != CurveType::Stable || !token_swap.amp_ramp().is_set() {
+            return Ok(swap_curve.clone());
+        }
+        let amp = Self::current_amp(token_swap, Clock::get()?.unix_timestamp)?;
+        Ok(SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Box::new(StableCurve { amp }),
+        })
+    }
+
+    /// Initializes the open orders of a new pool with the swap authority as
+    /// their owner, unless the dex already did, so that no one else can take
+    /// them between the creation of the account and the first order
+    fn init_open_orders<'a>(
+        swap_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        dex_program_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        open_orders_info: &AccountInfo<'a>,
+        rent_info: Option<&AccountInfo<'a>>,
+        nonce: u8,
+    ) -> ProgramResult {
+        if *open_orders_info.key == Pubkey::default()
+            || open_orders_info.owner != dex_program_info.key
+            || OpenOrders::unpack(&open_orders_info.data.borrow())? != OpenOrders::default()
+        {
+            return Ok(());
+        }
+        let rent_info = rent_info.ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let ix = serum_dex::instruction::init_open_orders(
+            dex_program_info.key,
+            open_orders_info.key,
+            authority_info.key,
+            market_info.key,
+            None,
+        )?;
+        Self::dex_invoke_signed(
+            swap_info.key,
+            &ix,
+            &[
+                open_orders_info.clone(),
+                authority_info.clone(),
+                market_info.clone(),
+                rent_info.clone(),
+                dex_program_info.clone(),
+            ],
+            nonce,
+        )
+    }
+
+    /// Checks that the Serum market trades token A against token B and that
+    /// the open orders, if any, are initialized for the market and can only
+    /// be used by the swap authority
+    fn validate_market(
+        authority_info: &AccountInfo,
+        dex_program_info: &AccountInfo,
+        market_info: &AccountInfo,
+        open_orders_info: &AccountInfo,
+        token_a_mint: &Pubkey,
+        token_b_mint: &Pubkey,
+    ) -> ProgramResult {
+        if market_info.owner != dex_program_info.key {
+            return Err(AmmError::IncorrectMarketOwner.into());
+        }
+        {
+            let market = MarketState::load(market_info, dex_program_info.key)?;
+            if pubkey_from_words(market.coin_mint) != *token_a_mint
+                || pubkey_from_words(market.pc_mint) != *token_b_mint
+            {
+                return Err(AmmError::IncorrectMarketMint.into());
+            }
+        }
+
+        if *open_orders_info.key == Pubkey::default() {
+            return Ok(());
+        }
+        if open_orders_info.owner != dex_program_info.key {
+            return Err(AmmError::IncorrectOpenOrdersAccount.into());
+        }
+        let open_orders = OpenOrders::unpack(&open_orders_info.data.borrow())?;
+        if open_orders == OpenOrders::default() || open_orders.market != *market_info.key {
+            return Err(AmmError::IncorrectOpenOrdersAccount.into());
+        }
+        if open_orders.owner != *authority_info.key {
+            return Err(AmmError::IncorrectOpenOrdersOwner.into());
+        }
+        Ok(())
+    }
+
+    /// Checks the Serum accounts given to trade the pool reserves, which a
+    /// flash loan or flash swap must not move while the pool is locked
+    fn check_market_accounts(
+        token_swap: &dyn AmmStatus,
+        program_id: &Pubkey,
+        swap_info: &AccountInfo,
+        authority_info: &AccountInfo,
+        dex_program_info: &AccountInfo,
+        market_info: &AccountInfo,
+        open_orders_info: &AccountInfo,
+    ) -> ProgramResult {
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if token_swap.locked() {
+            return Err(AmmError::PoolLocked.into());
+        }
+        if *authority_info.key != Self::authority_id(program_id, swap_info.key, token_swap.nonce())?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        if *dex_program_info.key != serum_dex_program::id() {
+            return Err(AmmError::IncorrectDexProgram.into());
+        }
+        if dex_program_info.key != token_swap.dex_program_id()
+            || market_info.key != token_swap.market_id()
+        {
+            return Err(AmmError::IncorrectMarketAccount.into());
+        }
+        if *token_swap.open_orders() == Pubkey::default()
+            || open_orders_info.key != token_swap.open_orders()
+        {
+            return Err(AmmError::IncorrectOpenOrdersAccount.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that a fixed fee account is the fee wallet of the config or a
+    /// token account of the fee wallet for `mint`
+    fn check_fixed_fee_account(
+        config: &ProgramConfig,
+        fee_account_info: &AccountInfo,
+        mint: &Pubkey,
+        token_program_id: &Pubkey,
+    ) -> ProgramResult {
+        if *fee_account_info.key == config.fee_wallet {
+            return Ok(());
+        }
+        let fee_account = Self::unpack_token_account(fee_account_info, token_program_id)?;
+        if fee_account.owner != config.fee_wallet || fee_account.mint != *mint {
+            return Err(AmmError::IncorrectFeeAccount.into());
+        }
+        Ok(())
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn check_accounts(
         token_swap: &dyn AmmStatus,
@@ -237,6 +920,9 @@ impl Processor {
         let token_program_info = next_account_info(account_info_iter)?;
         let dex_program_info = next_account_info(account_info_iter)?;
         let market_info = next_account_info(account_info_iter)?;
+        let open_orders_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let rent_info = Self::next_optional_account(account_info_iter);
         
         //validate account info
         let token_program_id = *token_program_info.key;
@@ -247,8 +933,23 @@ impl Processor {
         if *authority_info.key != Self::authority_id(program_id, swap_info.key, nonce)? {
             return Err(AmmError::InvalidProgramAddress.into());
         }
-        let token_a = Self::unpack_token_account(token_a_info, &token_program_id)?;
-        let token_b = Self::unpack_token_account(token_b_info, &token_program_id)?;
+        // the swap authority signs for the dex program, so it must be Serum's
+        if *dex_program_info.key != serum_dex_program::id() {
+            return Err(AmmError::IncorrectDexProgram.into());
+        }
+        // the pool tokens may each be on spl_token or Token-2022, while the
+        // pool mint stays on the token program of the pool
+        let token_a_program_id = Self::token_program_id_of(token_a_info)?;
+        let token_b_program_id = Self::token_program_id_of(token_b_info)?;
+        let token_a = Self::unpack_token_account(token_a_info, token_a_program_id)?;
+        let token_b = Self::unpack_token_account(token_b_info, token_b_program_id)?;
+        for (token_info, mint) in [(token_a_info, &token_a.mint), (token_b_info, &token_b.mint)] {
+            if let Some((mint_info, _)) =
+                Self::token_side(accounts, token_program_info, token_info, mint)?.mint
+            {
+                Self::check_mint_extensions(mint_info)?;
+            }
+        }
 
         let destination = Self::unpack_token_account(destination_info, &token_program_id)?;
         let pool_mint = Self::unpack_mint(pool_mint_info, &token_program_id)?;
@@ -261,11 +962,18 @@ impl Processor {
         if *authority_info.key == destination.owner {
             return Err(AmmError::InvalidOutputOwner.into());
         }
+        if token_a.mint == token_b.mint {
+            return Err(AmmError::RepeatedMint.into());
+        }
         //check the fee accounts are set corretly
-        let fixed_fee_acc_owner:Pubkey = Pubkey::from_str(FEE_WALLET_ADDRESS).unwrap();
+        let config = Self::unpack_config(program_id, config_info)?;
+        let fixed_fee_acc_owner = config.fee_wallet;
         if fixed_fee_acc_owner != *fee_account_a_info.key 
         {
-            let fee_account_a = Self::unpack_token_account(fee_account_a_info, &token_program_id)?;
+            let fee_account_a = Self::unpack_token_account(fee_account_a_info, token_a_program_id)?;
+            if *authority_info.key == fee_account_a.owner {
+                return Err(AmmError::InvalidOutputOwner.into());
+            }
             if fixed_fee_acc_owner != fee_account_a.owner || token_a.mint != fee_account_a.mint
             {
                 return Err(AmmError::IncorrectFeeAccount.into());
@@ -274,7 +982,10 @@ impl Processor {
 
         if fixed_fee_acc_owner != *fee_account_b_info.key
         {
-            let fee_account_b = Self::unpack_token_account(fee_account_b_info, &token_program_id)?;
+            let fee_account_b = Self::unpack_token_account(fee_account_b_info, token_b_program_id)?;
+            if *authority_info.key == fee_account_b.owner {
+                return Err(AmmError::InvalidOutputOwner.into());
+            }
             if fixed_fee_acc_owner != fee_account_b.owner || token_b.mint != fee_account_b.mint
             {
                 return Err(AmmError::IncorrectFeeAccount.into());
@@ -285,9 +996,6 @@ impl Processor {
             return Err(AmmError::InvalidOwner.into());
         }
 
-        if token_a.mint == token_b.mint {
-            return Err(AmmError::RepeatedMint.into());
-        }
         swap_curve
             .calculator
             .validate_supply(token_a.amount, token_b.amount)?;
@@ -312,19 +1020,37 @@ impl Processor {
         }
 
         if let Some(swap_constraints) = swap_constraints {
-            //@zhaohui
-            // let owner_key = swap_constraints
-            //     .owner_key
-            //     .parse::<Pubkey>()
-            //     .map_err(|_| AmmError::InvalidOwner)?;
-            // if fee_account.owner != owner_key {
-            //     return Err(AmmError::InvalidOwner.into());
-            // }
+            // fees are paid to the wallet of the config, which must be run by
+            // the owner of the program
+            let owner_key = swap_constraints
+                .owner_key
+                .parse::<Pubkey>()
+                .map_err(|_| AmmError::InvalidOwner)?;
+            if config.admin != owner_key {
+                return Err(AmmError::InvalidOwner.into());
+            }
             swap_constraints.validate_curve(&swap_curve)?;
             swap_constraints.validate_fees(&fees)?;
         }
         fees.validate()?;
         swap_curve.calculator.validate()?;
+        Self::init_open_orders(
+            swap_info,
+            authority_info,
+            dex_program_info,
+            market_info,
+            open_orders_info,
+            rent_info,
+            nonce,
+        )?;
+        Self::validate_market(
+            authority_info,
+            dex_program_info,
+            market_info,
+            open_orders_info,
+            &token_a.mint,
+            &token_b.mint,
+        )?;
 
         let initial_amount = swap_curve.calculator.new_pool_supply();
 
@@ -340,22 +1066,36 @@ impl Processor {
         )?;
 
         //Save the pool account info
-        let obj = SwapVersion::SwapV1(SwapV1 {
-            is_initialized: true,
-            nonce,
-            amm_id: *amm_id_info.key,
-            dex_program_id: *dex_program_info.key,
-            market_id: *market_info.key,
-            token_program_id,
-            token_a: *token_a_info.key,
-            token_b: *token_b_info.key,
-            pool_mint: *pool_mint_info.key,
-            token_a_mint: token_a.mint,
-            token_b_mint: token_b.mint,
-            fixed_fee_account_a: *fee_account_a_info.key,
-            fixed_fee_account_b: *fee_account_b_info.key,
-            fees,
-            swap_curve,
+        let obj = SwapVersion::SwapV3(SwapV3 {
+            swap: SwapV2 {
+                is_initialized: true,
+                nonce,
+                amm_id: *amm_id_info.key,
+                dex_program_id: *dex_program_info.key,
+                market_id: *market_info.key,
+                token_program_id,
+                token_a: *token_a_info.key,
+                token_b: *token_b_info.key,
+                pool_mint: *pool_mint_info.key,
+                token_a_mint: token_a.mint,
+                token_b_mint: token_b.mint,
+                fixed_fee_account_a: *fee_account_a_info.key,
+                fixed_fee_account_b: *fee_account_b_info.key,
+                fees,
+                swap_curve,
+                status: PoolStatus::Active,
+                open_orders: *open_orders_info.key,
+                amp_ramp: AmpRamp::default(),
+                price_oracle: PriceOracle::new(Clock::get()?.unix_timestamp),
+            },
+            stats: PoolStats::default(),
+            locked: false,
+            // start with the only host fee the constraints accept, so that
+            // SetHostFee is not needed before the pool can take host fees
+            host_fee: swap_constraints
+                .as_ref()
+                .map_or_else(HostFee::default, |constraints| *constraints.host_fee),
+            protocol_fee: ProtocolFee::default(),
         });
         SwapVersion::pack(obj, &mut swap_info.data.borrow_mut())?;
         Ok(())
@@ -368,27 +1108,236 @@ impl Processor {
         minimum_amount_out: u64,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
-        
-        //load account info
-        let account_info_iter = &mut accounts.iter();
-        let swap_info = next_account_info(account_info_iter)?;
-        let authority_info = next_account_info(account_info_iter)?;
+        Self::process_swap_with(
+            program_id,
+            accounts,
+            |swap_curve, fees, swap_source_amount, swap_destination_amount, trade_direction, source,
+             destination| {
+                // transfer fees are withheld from the tokens going in and out
+                let result = swap_curve
+                    .swap(
+                        to_u128(source.amount_received(amount_in)?)?,
+                        swap_source_amount,
+                        swap_destination_amount,
+                        trade_direction,
+                        fees,
+                    )
+                    .ok_or(AmmError::ZeroTradingTokens)?;
+                let amount_out =
+                    destination.amount_received(to_u64(result.destination_amount_swapped)?)?;
+                if amount_out < minimum_amount_out {
+                    return Err(AmmError::ExceededSlippage.into());
+                }
+                Ok(result)
+            },
+        )
+    }
+
+    /// Processes a [SwapExactAmountOut](enum.Instruction.html).
+    pub fn process_swap_exact_amount_out(
+        program_id: &Pubkey,
+        amount_out: u64,
+        maximum_amount_in: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        Self::process_swap_with(
+            program_id,
+            accounts,
+            |swap_curve, fees, swap_source_amount, swap_destination_amount, trade_direction, source,
+             destination| {
+                // the pool pays out the transfer fee of `amount_out` on top of it
+                let amount_out = to_u128(destination.amount_sent(amount_out)?)?;
+                let mut result = swap_exact_out(
+                    swap_curve,
+                    amount_out,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    trade_direction,
+                    fees,
+                )
+                .ok_or(AmmError::ZeroTradingTokens)?;
+                if source.amount_sent(to_u64(result.source_amount_swapped)?)? > maximum_amount_in {
+                    return Err(AmmError::ExceededSlippage.into());
+                }
+                // anything paid out above `amount_out` through rounding stays in the pool
+                let surplus = result
+                    .destination_amount_swapped
+                    .checked_sub(amount_out)
+                    .ok_or(AmmError::CalculationFailure)?;
+                result.new_swap_destination_amount = result
+                    .new_swap_destination_amount
+                    .checked_add(surplus)
+                    .ok_or(AmmError::CalculationFailure)?;
+                result.destination_amount_swapped = amount_out;
+                Ok(result)
+            },
+        )
+    }
+
+    /// Loads the accounts of a single pool swap and runs it with the
+    /// [SwapResult](struct.SwapResult.html) given by `calculate`
+    fn process_swap_with<F>(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        calculate: F,
+    ) -> ProgramResult
+    where
+        F: FnOnce(
+            &SwapCurve,
+            &Fees,
+            u128,
+            u128,
+            TradeDirection,
+            &TokenSide,
+            &TokenSide,
+        ) -> Result<SwapResult, ProgramError>,
+    {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_accounts = SwapAccounts {
+            swap_info: next_account_info(account_info_iter)?,
+            authority_info: next_account_info(account_info_iter)?,
+            user_transfer_authority_info: next_account_info(account_info_iter)?,
+            source_info: next_account_info(account_info_iter)?,
+            swap_source_info: next_account_info(account_info_iter)?,
+            swap_destination_info: next_account_info(account_info_iter)?,
+            destination_info: next_account_info(account_info_iter)?,
+            pool_mint_info: next_account_info(account_info_iter)?,
+            fixed_fee_account_info: next_account_info(account_info_iter)?,
+            fixed_fee_wallet_info: next_account_info(account_info_iter)?,
+            token_program_info: next_account_info(account_info_iter)?,
+            system_program_info: next_account_info(account_info_iter)?,
+            config_info: next_account_info(account_info_iter)?,
+            open_orders_info: Self::next_optional_account(account_info_iter),
+            host_fee_info: Self::next_optional_account(account_info_iter),
+            instruction_infos: accounts,
+        };
+        Self::swap_through_pool(program_id, swap_accounts, calculate)?;
+        Ok(())
+    }
+
+    /// Processes a [RouteSwap](enum.Instruction.html).
+    pub fn process_route_swap(
+        program_id: &Pubkey,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
         let user_transfer_authority_info = next_account_info(account_info_iter)?;
         let source_info = next_account_info(account_info_iter)?;
-        let swap_source_info = next_account_info(account_info_iter)?;
-        let swap_destination_info = next_account_info(account_info_iter)?;
-        let destination_info = next_account_info(account_info_iter)?;
-        let pool_mint_info = next_account_info(account_info_iter)?;
-        let fixed_fee_account_info = next_account_info(account_info_iter)?;
         let fixed_fee_wallet_info = next_account_info(account_info_iter)?;
         let token_program_info = next_account_info(account_info_iter)?;
         let system_program_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+
+        //validate account info
+        let leg_infos = account_info_iter.as_slice();
+        if leg_infos.is_empty() || !leg_infos.len().is_multiple_of(ROUTE_SWAP_LEG_ACCOUNTS) {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        let mut source_info = source_info;
+        let mut amount = amount_in;
+        for leg in leg_infos.chunks(ROUTE_SWAP_LEG_ACCOUNTS) {
+            let swap_accounts = SwapAccounts {
+                swap_info: &leg[0],
+                authority_info: &leg[1],
+                user_transfer_authority_info,
+                source_info,
+                swap_source_info: &leg[2],
+                swap_destination_info: &leg[3],
+                destination_info: &leg[4],
+                pool_mint_info: &leg[5],
+                fixed_fee_account_info: &leg[6],
+                fixed_fee_wallet_info,
+                token_program_info,
+                system_program_info,
+                config_info,
+                open_orders_info: Some(&leg[7]),
+                host_fee_info: None,
+                instruction_infos: accounts,
+            };
+            let mut amount_out = 0;
+            Self::swap_through_pool(
+                program_id,
+                swap_accounts,
+                |swap_curve, fees, swap_source_amount, swap_destination_amount, trade_direction,
+                 source, destination| {
+                    let result = swap_curve
+                        .swap(
+                            to_u128(source.amount_received(amount)?)?,
+                            swap_source_amount,
+                            swap_destination_amount,
+                            trade_direction,
+                            fees,
+                        )
+                        .ok_or(AmmError::ZeroTradingTokens)?;
+                    amount_out =
+                        destination.amount_received(to_u64(result.destination_amount_swapped)?)?;
+                    Ok(result)
+                },
+            )?;
+            // the tokens received on this leg are swapped on the next one
+            source_info = &leg[4];
+            amount = amount_out;
+        }
+
+        if amount < minimum_amount_out {
+            return Err(AmmError::ExceededSlippage.into());
+        }
+        Ok(())
+    }
+
+    /// Validates the accounts of a swap through one pool, then moves the
+    /// tokens for the [SwapResult](struct.SwapResult.html) given by `calculate`
+    fn swap_through_pool<F>(
+        program_id: &Pubkey,
+        accounts: SwapAccounts,
+        calculate: F,
+    ) -> Result<SwapResult, ProgramError>
+    where
+        F: FnOnce(
+            &SwapCurve,
+            &Fees,
+            u128,
+            u128,
+            TradeDirection,
+            &TokenSide,
+            &TokenSide,
+        ) -> Result<SwapResult, ProgramError>,
+    {
+        let SwapAccounts {
+            swap_info,
+            authority_info,
+            user_transfer_authority_info,
+            source_info,
+            swap_source_info,
+            swap_destination_info,
+            destination_info,
+            pool_mint_info,
+            fixed_fee_account_info,
+            fixed_fee_wallet_info,
+            token_program_info,
+            system_program_info,
+            config_info,
+            open_orders_info,
+            host_fee_info,
+            instruction_infos,
+        } = accounts;
 
         //validate account info
         if swap_info.owner != program_id {
             return Err(ProgramError::IncorrectProgramId);
         }
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if !token_swap.status().can_swap() {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if token_swap.locked() {
+            return Err(AmmError::PoolLocked.into());
+        }
 
         if *authority_info.key != Self::authority_id(program_id, swap_info.key, token_swap.nonce())?
         {
@@ -426,37 +1375,85 @@ impl Processor {
         };
         
         //Validate the fee account is correct
-        if !(trade_direction == TradeDirection::AtoB  && *fixed_fee_account_info.key == *token_swap.fixed_fee_account_a()) 
-            && !(trade_direction == TradeDirection::BtoA  && *fixed_fee_account_info.key == *token_swap.fixed_fee_account_b()) 
-        {
+        let fixed_fee_account = match trade_direction {
+            TradeDirection::AtoB => token_swap.fixed_fee_account_a(),
+            TradeDirection::BtoA => token_swap.fixed_fee_account_b(),
+        };
+        if *fixed_fee_account_info.key != *fixed_fee_account {
             return Err(AmmError::IncorrectFeeAccount.into());
         }
 
-        let fixed_fee_acc_owner:Pubkey = Pubkey::from_str(FEE_WALLET_ADDRESS).unwrap();
+        let fixed_fee_acc_owner = Self::unpack_config(program_id, config_info)?.fee_wallet;
         if fixed_fee_acc_owner != *fixed_fee_wallet_info.key
         {
             return Err(AmmError::IncorrectFeeAccount.into());
         }
-        let source_account =
-            Self::unpack_token_account(swap_source_info, token_swap.token_program_id())?;
-        let dest_account =
-            Self::unpack_token_account(swap_destination_info, token_swap.token_program_id())?;
+        let source_account = Self::unpack_token_account(
+            swap_source_info,
+            Self::token_program_id_of(swap_source_info)?,
+        )?;
+        let dest_account = Self::unpack_token_account(
+            swap_destination_info,
+            Self::token_program_id_of(swap_destination_info)?,
+        )?;
+        let source_side = Self::token_side(
+            instruction_infos,
+            token_program_info,
+            swap_source_info,
+            &source_account.mint,
+        )?;
+        let destination_side = Self::token_side(
+            instruction_infos,
+            token_program_info,
+            swap_destination_info,
+            &dest_account.mint,
+        )?;
+        if let Some(host_fee_info) = host_fee_info {
+            let host_fee_account =
+                Self::unpack_token_account(host_fee_info, source_side.token_program_info.key)?;
+            if host_fee_account.mint != source_account.mint {
+                return Err(AmmError::IncorrectFeeAccount.into());
+            }
+        }
         // let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        let (token_a_amount, token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => Self::pool_reserves(
+                token_swap.as_ref(),
+                authority_info,
+                source_account.amount,
+                dest_account.amount,
+                open_orders_info,
+            )?,
+            TradeDirection::BtoA => Self::pool_reserves(
+                token_swap.as_ref(),
+                authority_info,
+                dest_account.amount,
+                source_account.amount,
+                open_orders_info,
+            )?,
+        };
+        Self::update_price_oracle(swap_info, token_a_amount, token_b_amount)?;
+        let (swap_source_amount, swap_destination_amount) = match trade_direction {
+            TradeDirection::AtoB => (token_a_amount, token_b_amount),
+            TradeDirection::BtoA => (token_b_amount, token_a_amount),
+        };
 
-        let result = token_swap
-            .swap_curve()
-            .swap(
-                to_u128(amount_in)?,
-                to_u128(source_account.amount)?,
-                to_u128(dest_account.amount)?,
-                trade_direction,
-                token_swap.fees(),
-            )
-            .ok_or(AmmError::ZeroTradingTokens)?;
-
-        if result.destination_amount_swapped < to_u128(minimum_amount_out)? {
-            return Err(AmmError::ExceededSlippage.into());
-        }
+        let result = calculate(
+            &Self::current_swap_curve(token_swap.as_ref())?,
+            token_swap.fees(),
+            to_u128(swap_source_amount)?,
+            to_u128(swap_destination_amount)?,
+            trade_direction,
+            &source_side,
+            &destination_side,
+        )?;
+        // pools taking a protocol fee keep the fixed fee, where it grows
+        // `sqrt(k)` until the next deposit or withdrawal
+        let owner_fee = if token_swap.protocol_fee().is_enabled() {
+            0
+        } else {
+            result.owner_fee
+        };
         //@zhaohui
         // let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
         //     TradeDirection::AtoB => (
@@ -469,92 +1466,765 @@ impl Processor {
         //     ),
         // };
 
-        Self::token_transfer(
+        // the pool receives the source amount net of its transfer fee
+        source_side.transfer(
             swap_info.key,
-            token_program_info.clone(),
-            source_info.clone(),
-            swap_source_info.clone(),
-            user_transfer_authority_info.clone(),
+            source_info,
+            swap_source_info,
+            user_transfer_authority_info,
+            token_swap.nonce(),
+            source_side.amount_sent(to_u64(result.source_amount_swapped - owner_fee)?)?,
+        )?;
+
+        // the host, if any, takes its share of the fixed fee in tokens
+        let mut fixed_fee = to_u64(owner_fee)?;
+        let mut host_fee = 0;
+        if let Some(host_fee_info) = host_fee_info {
+            host_fee = to_u64(
+                token_swap
+                    .host_fee()
+                    .host_fee(owner_fee)
+                    .ok_or(AmmError::FeeCalculationFailure)?,
+            )?;
+            if host_fee > 0 {
+                source_side.transfer(
+                    swap_info.key,
+                    source_info,
+                    host_fee_info,
+                    user_transfer_authority_info,
+                    token_swap.nonce(),
+                    host_fee,
+                )?;
+                fixed_fee -= host_fee;
+            }
+        }
+
+        if fixed_fee > 0 {
+            Self::pay_fixed_fee(
+                swap_info.key,
+                &source_side,
+                system_program_info,
+                user_transfer_authority_info,
+                source_info,
+                fixed_fee_account_info,
+                fixed_fee_wallet_info,
+                &source_account.mint,
+                token_swap.nonce(),
+                fixed_fee,
+            )?;
+        }
+        
+        //Transfer pc token from pool
+        destination_side.transfer(
+            swap_info.key,
+            swap_destination_info,
+            destination_info,
+            authority_info,
             token_swap.nonce(),
-            to_u64(result.source_amount_swapped-result.owner_fee)?,
+            to_u64(result.destination_amount_swapped)?,
         )?;
+        // a fixed fee kept by the pool is returned to the liquidity providers
+        Self::record_stats(swap_info, |stats| {
+            stats.record_swap(
+                trade_direction,
+                result.source_amount_swapped,
+                u128::from(fixed_fee),
+                u128::from(host_fee),
+                result.trade_fee + (result.owner_fee - owner_fee),
+            )
+        })?;
+
+        let reserve_source = swap_source_amount
+            .checked_add(to_u64(result.source_amount_swapped - owner_fee)?)
+            .ok_or(AmmError::CalculationFailure)?;
+        let reserve_destination = swap_destination_amount
+            .checked_sub(to_u64(result.destination_amount_swapped)?)
+            .ok_or(AmmError::CalculationFailure)?;
+        let (reserve_a, reserve_b) = match trade_direction {
+            TradeDirection::AtoB => (reserve_source, reserve_destination),
+            TradeDirection::BtoA => (reserve_destination, reserve_source),
+        };
+        AmmEvent::Swap(SwapEvent {
+            pool: *swap_info.key,
+            user: *user_transfer_authority_info.key,
+            trade_direction,
+            amount_in: to_u64(result.source_amount_swapped)?,
+            amount_out: to_u64(result.destination_amount_swapped)?,
+            owner_fee: to_u64(owner_fee)?,
+            reserve_a,
+            reserve_b,
+        })
+        .emit();
+
+        Ok(result)
+    }
 
+    /// Pays the fixed fee of a swap into the fixed fee account of its source
+    /// token, or straight to the fee wallet in SOL when the source is WSOL
+    #[allow(clippy::too_many_arguments)]
+    fn pay_fixed_fee<'a>(
+        swap: &Pubkey,
+        source_side: &TokenSide<'_, 'a>,
+        system_program_info: &AccountInfo<'a>,
+        user_transfer_authority_info: &AccountInfo<'a>,
+        source_info: &AccountInfo<'a>,
+        fixed_fee_account_info: &AccountInfo<'a>,
+        fixed_fee_wallet_info: &AccountInfo<'a>,
+        source_mint: &Pubkey,
+        nonce: u8,
+        amount: u64,
+    ) -> ProgramResult {
         let wsol_mint =  Pubkey::from_str(WSOL_MINT_ADDRESS).unwrap();
 
         //if the fee token is WSOL, then transfer SOL to fee account directly
-        if source_account.mint == wsol_mint
+        if *source_mint == wsol_mint
         {
             let source = user_transfer_authority_info.clone();
             let destination = fixed_fee_wallet_info.clone();
             invoke(
-                &system_instruction::transfer(
-                    source.key,
-                    destination.key,
-                    to_u64(result.owner_fee)?,
-                ),
+                &system_instruction::transfer(source.key, destination.key, amount),
                 &[source, destination, system_program_info.clone()]
-            )?;
+            )
         }
         else
         {
-            //otherwise transfer SPL_Token
-            Self::token_transfer(
+            //otherwise transfer SPL_Token, to an account of the current fee wallet
+            let fixed_fee_account = Self::unpack_token_account(
+                fixed_fee_account_info,
+                source_side.token_program_info.key,
+            )?;
+            if fixed_fee_account.owner != *fixed_fee_wallet_info.key {
+                return Err(AmmError::IncorrectFeeAccount.into());
+            }
+            source_side.transfer(
+                swap,
+                source_info,
+                fixed_fee_account_info,
+                user_transfer_authority_info,
+                nonce,
+                amount,
+            )
+        }
+    }
+
+    /// Owner fee in pool tokens on a withdrawal of `pool_token_amount` from
+    /// `source_info`, checking that the pool fee account receiving it is a
+    /// pool token account of the fee wallet.  The pool fee account itself
+    /// withdraws without fee.
+    fn owner_withdraw_fee(
+        program_id: &Pubkey,
+        token_swap: &dyn AmmStatus,
+        config_info: &AccountInfo,
+        source_info: &AccountInfo,
+        pool_fee_info: &AccountInfo,
+        pool_token_amount: u128,
+    ) -> Result<u128, ProgramError> {
+        let fees = token_swap.fees();
+        if fees.owner_withdraw_fee_numerator == 0 || *source_info.key == *pool_fee_info.key {
+            return Ok(0);
+        }
+        let config = Self::unpack_config(program_id, config_info)?;
+        let pool_fee_account =
+            Self::unpack_token_account(pool_fee_info, token_swap.token_program_id())?;
+        if pool_fee_account.owner != config.fee_wallet
+            || pool_fee_account.mint != *token_swap.pool_mint()
+        {
+            return Err(AmmError::IncorrectFeeAccount.into());
+        }
+        fees.owner_withdraw_fee(pool_token_amount)
+            .ok_or_else(|| AmmError::FeeCalculationFailure.into())
+    }
+
+    /// Mints the protocol fee on the growth of `sqrt(k)` of the reserves
+    /// since the last deposit or withdrawal, returning the pool token supply
+    /// after it
+    #[allow(clippy::too_many_arguments)]
+    fn mint_protocol_fee<'a>(
+        token_swap: &dyn AmmStatus,
+        swap_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        pool_mint_info: &AccountInfo<'a>,
+        protocol_fee_info: Option<&AccountInfo<'a>>,
+        token_program_info: &AccountInfo<'a>,
+        token_a_amount: u64,
+        token_b_amount: u64,
+        pool_mint_supply: u64,
+    ) -> Result<u64, ProgramError> {
+        let protocol_fee = token_swap.protocol_fee();
+        if !protocol_fee.is_enabled() {
+            return Ok(pool_mint_supply);
+        }
+        let protocol_fee_info = match protocol_fee_info {
+            Some(protocol_fee_info) if *protocol_fee_info.key == protocol_fee.fee_account => {
+                protocol_fee_info
+            }
+            _ => return Err(AmmError::IncorrectFeeAccount.into()),
+        };
+        let amount = protocol_fee
+            .protocol_fee(
+                ProtocolFee::root_k(token_a_amount, token_b_amount),
+                to_u128(pool_mint_supply)?,
+            )
+            .ok_or(AmmError::FeeCalculationFailure)?;
+        let amount = to_u64(amount)?;
+        if amount > 0 {
+            Self::token_mint_to(
                 swap_info.key,
                 token_program_info.clone(),
-                source_info.clone(),
-                fixed_fee_account_info.clone(),
-                user_transfer_authority_info.clone(),
+                pool_mint_info.clone(),
+                protocol_fee_info.clone(),
+                authority_info.clone(),
                 token_swap.nonce(),
-                to_u64(result.owner_fee)?,
+                amount,
             )?;
         }
-        
-        //Transfer pc token from pool
-        Self::token_transfer(
-            swap_info.key,
-            token_program_info.clone(),
-            swap_destination_info.clone(),
-            destination_info.clone(),
-            authority_info.clone(),
-            token_swap.nonce(),
-            to_u64(result.destination_amount_swapped)?,
-        )?;
+        pool_mint_supply
+            .checked_add(amount)
+            .ok_or_else(|| AmmError::CalculationFailure.into())
+    }
 
+    /// Records `sqrt(k)` of the reserves left by a deposit or withdrawal, the
+    /// base of the next protocol fee
+    fn record_root_k(
+        swap_info: &AccountInfo,
+        token_a_amount: u64,
+        token_b_amount: u64,
+    ) -> ProgramResult {
+        let mut token_swap = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        let mut protocol_fee = *token_swap.protocol_fee();
+        if protocol_fee.is_enabled() {
+            protocol_fee.root_k_last = ProtocolFee::root_k(token_a_amount, token_b_amount);
+            token_swap.set_protocol_fee(protocol_fee)?;
+            SwapVersion::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        }
         Ok(())
     }
 
-    /// Processes an [DepositAllTokenTypes](enum.Instruction.html).
-    pub fn process_deposit_all_token_types(
+    /// Wraps native SOL of the user wallet, the user transfer authority of an
+    /// instruction, into its temporary WSOL account, processes the
+    /// instruction, then closes the account back into the user wallet.
+    /// `native_sol_sides` gives the indices of the accounts of the
+    /// instruction which may be the temporary account, with the lamports
+    /// wrapped when it is.
+    fn process_native_sol<'a>(
         program_id: &Pubkey,
-        pool_token_amount: u64,
-        maximum_token_a_amount: u64,
-        maximum_token_b_amount: u64,
+        accounts: &[AccountInfo<'a>],
+        token_program_index: usize,
+        native_sol_sides: &[(usize, u64)],
+        process: impl FnOnce(&[AccountInfo<'a>]) -> ProgramResult,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let wsol_mint_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let accounts = account_info_iter.as_slice();
+        let swap_info = accounts.first().ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let user_info = accounts.get(2).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let token_program_info = accounts
+            .get(token_program_index)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+        if *wsol_mint_info.key != Pubkey::from_str(WSOL_MINT_ADDRESS).unwrap() {
+            return Err(AmmError::IncorrectNativeSolAccount.into());
+        }
+        if *token_program_info.key != spl_token::id() {
+            return Err(AmmError::IncorrectTokenProgramId.into());
+        }
+        let (native_sol_key, nonce) =
+            find_native_sol_address(program_id, swap_info.key, user_info.key);
+        let (native_sol_info, wrapped_amount) = native_sol_sides
+            .iter()
+            .find_map(|(index, amount)| {
+                accounts
+                    .get(*index)
+                    .filter(|account_info| *account_info.key == native_sol_key)
+                    .map(|account_info| (account_info, *amount))
+            })
+            .ok_or(AmmError::IncorrectNativeSolAccount)?;
+
+        let rent = Rent::from_account_info(rent_info)?;
+        let lamports = rent
+            .minimum_balance(spl_token::state::Account::LEN)
+            .checked_add(wrapped_amount)
+            .ok_or(AmmError::CalculationFailure)?;
+        let native_sol_seeds: &[&[u8]] = &[
+            NATIVE_SOL_SEED,
+            &swap_info.key.to_bytes(),
+            &user_info.key.to_bytes(),
+            &[nonce],
+        ];
+        if native_sol_info.lamports() == 0 {
+            invoke_signed(
+                &system_instruction::create_account(
+                    user_info.key,
+                    native_sol_info.key,
+                    lamports,
+                    spl_token::state::Account::LEN as u64,
+                    token_program_info.key,
+                ),
+                &[
+                    user_info.clone(),
+                    native_sol_info.clone(),
+                    system_program_info.clone(),
+                ],
+                &[native_sol_seeds],
+            )?;
+        } else {
+            // anyone can send lamports to the address, which makes creating
+            // the account fail, so top it up and allocate it in place
+            let top_up = lamports.saturating_sub(native_sol_info.lamports());
+            if top_up > 0 {
+                invoke(
+                    &system_instruction::transfer(user_info.key, native_sol_info.key, top_up),
+                    &[
+                        user_info.clone(),
+                        native_sol_info.clone(),
+                        system_program_info.clone(),
+                    ],
+                )?;
+            }
+            invoke_signed(
+                &system_instruction::allocate(
+                    native_sol_info.key,
+                    spl_token::state::Account::LEN as u64,
+                ),
+                &[native_sol_info.clone(), system_program_info.clone()],
+                &[native_sol_seeds],
+            )?;
+            invoke_signed(
+                &system_instruction::assign(native_sol_info.key, token_program_info.key),
+                &[native_sol_info.clone(), system_program_info.clone()],
+                &[native_sol_seeds],
+            )?;
+        }
+        invoke(
+            &spl_token::instruction::initialize_account(
+                token_program_info.key,
+                native_sol_info.key,
+                wsol_mint_info.key,
+                user_info.key,
+            )?,
+            &[
+                native_sol_info.clone(),
+                wsol_mint_info.clone(),
+                user_info.clone(),
+                rent_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        process(accounts)?;
+
+        // unwraps what the instruction left in or paid into the account
+        invoke(
+            &spl_token::instruction::close_account(
+                token_program_info.key,
+                native_sol_info.key,
+                user_info.key,
+                user_info.key,
+                &[],
+            )?,
+            &[
+                native_sol_info.clone(),
+                user_info.clone(),
+                token_program_info.clone(),
+            ],
+        )
+    }
+
+    /// Processes a [FlashLoan](enum.Instruction.html).
+    pub fn process_flash_loan(
+        program_id: &Pubkey,
+        amount: u64,
+        data: &[u8],
         accounts: &[AccountInfo],
     ) -> ProgramResult {
-        
         //load account info
         let account_info_iter = &mut accounts.iter();
         let swap_info = next_account_info(account_info_iter)?;
         let authority_info = next_account_info(account_info_iter)?;
-        let user_transfer_authority_info = next_account_info(account_info_iter)?;
-        let source_a_info = next_account_info(account_info_iter)?;
-        let source_b_info = next_account_info(account_info_iter)?;
-        let token_a_info = next_account_info(account_info_iter)?;
-        let token_b_info = next_account_info(account_info_iter)?;
-        let pool_mint_info = next_account_info(account_info_iter)?;
-        let dest_info = next_account_info(account_info_iter)?;
+        let swap_token_info = next_account_info(account_info_iter)?;
+        let other_swap_token_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let fixed_fee_account_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let open_orders_info = next_account_info(account_info_iter)?;
         let token_program_info = next_account_info(account_info_iter)?;
+        let receiver_program_info = next_account_info(account_info_iter)?;
+        let receiver_infos = account_info_iter.as_slice();
 
-        //validate account
+        //validate account info
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let config = Self::unpack_config(program_id, config_info)?;
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
-        let calculator = &token_swap.swap_curve().calculator;
-        if !calculator.allows_deposits() {
-            return Err(AmmError::UnsupportedCurveOperation.into());
+        if !token_swap.status().can_swap() {
+            return Err(AmmError::PoolPaused.into());
         }
-        Self::check_accounts(
+        if token_swap.locked() {
+            return Err(AmmError::PoolLocked.into());
+        }
+        if *authority_info.key != Self::authority_id(program_id, swap_info.key, token_swap.nonce())?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        let (fixed_fee_account, mint) = if *swap_token_info.key == *token_swap.token_a_account()
+            && *other_swap_token_info.key == *token_swap.token_b_account()
+        {
+            (token_swap.fixed_fee_account_a(), token_swap.token_a_mint())
+        } else if *swap_token_info.key == *token_swap.token_b_account()
+            && *other_swap_token_info.key == *token_swap.token_a_account()
+        {
+            (token_swap.fixed_fee_account_b(), token_swap.token_b_mint())
+        } else {
+            return Err(AmmError::IncorrectSwapAccount.into());
+        };
+        if *fixed_fee_account_info.key != *fixed_fee_account {
+            return Err(AmmError::IncorrectFeeAccount.into());
+        }
+        if swap_token_info.key == destination_info.key {
+            return Err(AmmError::InvalidInput.into());
+        }
+        if *token_program_info.key != *token_swap.token_program_id() {
+            return Err(AmmError::IncorrectTokenProgramId.into());
+        }
+        if amount == 0 {
+            return Err(AmmError::ZeroTradingTokens.into());
+        }
+        let return_fee = to_u64(
+            token_swap
+                .fees()
+                .return_fee(to_u128(amount)?)
+                .ok_or(AmmError::FeeCalculationFailure)?,
+        )?;
+        let fixed_fee = to_u64(
+            token_swap
+                .fees()
+                .fixed_fee(to_u128(amount)?)
+                .ok_or(AmmError::FeeCalculationFailure)?,
+        )?;
+        // the loan is repaid once the reserves of the pool, open orders
+        // included, grew by the fees
+        let (reserve, other_reserve) = Self::ordered_pool_reserves(
             token_swap.as_ref(),
-            program_id,
-            swap_info,
+            authority_info,
+            swap_token_info,
+            other_swap_token_info,
+            open_orders_info,
+        )?;
+        let minimum_repaid_reserve = reserve
+            .checked_add(return_fee)
+            .and_then(|repaid| repaid.checked_add(fixed_fee))
+            .ok_or(AmmError::CalculationFailure)?;
+        let nonce = token_swap.nonce();
+
+        Self::set_pool_locked(swap_info, true)?;
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            swap_token_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            nonce,
+            amount,
+        )?;
+
+        Self::invoke_receiver(
+            receiver_program_info,
+            swap_token_info,
+            destination_info,
+            token_program_info,
+            receiver_infos,
+            data,
+        )?;
+
+        let (new_reserve, new_other_reserve) = Self::ordered_pool_reserves(
+            token_swap.as_ref(),
+            authority_info,
+            swap_token_info,
+            other_swap_token_info,
+            open_orders_info,
+        )?;
+        if new_reserve < minimum_repaid_reserve || new_other_reserve < other_reserve {
+            return Err(AmmError::FlashLoanNotRepaid.into());
+        }
+        let repaid_amount = (new_reserve - reserve)
+            .checked_add(amount)
+            .ok_or(AmmError::CalculationFailure)?;
+        // pools paying the fixed fee of this token in SOL keep it
+        let fixed_fee = if fixed_fee_account_info.owner == token_program_info.key {
+            fixed_fee
+        } else {
+            0
+        };
+        if fixed_fee > 0 {
+            Self::check_fixed_fee_account(
+                &config,
+                fixed_fee_account_info,
+                mint,
+                token_program_info.key,
+            )?;
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                swap_token_info.clone(),
+                fixed_fee_account_info.clone(),
+                authority_info.clone(),
+                nonce,
+                fixed_fee,
+            )?;
+        }
+        AmmEvent::FlashLoan(FlashLoanEvent {
+            pool: *swap_info.key,
+            receiver: *receiver_program_info.key,
+            swap_token: *swap_token_info.key,
+            amount,
+            repaid_amount,
+            fixed_fee,
+        })
+        .emit();
+
+        Self::set_pool_locked(swap_info, false)
+    }
+
+    /// Processes a [FlashSwap](enum.Instruction.html).
+    pub fn process_flash_swap(
+        program_id: &Pubkey,
+        amount_out: u64,
+        data: &[u8],
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let swap_source_info = next_account_info(account_info_iter)?;
+        let swap_destination_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let fixed_fee_account_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let open_orders_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let receiver_program_info = next_account_info(account_info_iter)?;
+        let receiver_infos = account_info_iter.as_slice();
+
+        //validate account info
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let config = Self::unpack_config(program_id, config_info)?;
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if !token_swap.status().can_swap() {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if token_swap.locked() {
+            return Err(AmmError::PoolLocked.into());
+        }
+        if *authority_info.key != Self::authority_id(program_id, swap_info.key, token_swap.nonce())?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        let trade_direction = if *swap_source_info.key == *token_swap.token_a_account()
+            && *swap_destination_info.key == *token_swap.token_b_account()
+        {
+            TradeDirection::AtoB
+        } else if *swap_source_info.key == *token_swap.token_b_account()
+            && *swap_destination_info.key == *token_swap.token_a_account()
+        {
+            TradeDirection::BtoA
+        } else {
+            return Err(AmmError::IncorrectSwapAccount.into());
+        };
+        if swap_destination_info.key == destination_info.key {
+            return Err(AmmError::InvalidInput.into());
+        }
+        let (fixed_fee_account, source_mint) = match trade_direction {
+            TradeDirection::AtoB => (token_swap.fixed_fee_account_a(), token_swap.token_a_mint()),
+            TradeDirection::BtoA => (token_swap.fixed_fee_account_b(), token_swap.token_b_mint()),
+        };
+        if *fixed_fee_account_info.key != *fixed_fee_account {
+            return Err(AmmError::IncorrectFeeAccount.into());
+        }
+        if *token_program_info.key != *token_swap.token_program_id() {
+            return Err(AmmError::IncorrectTokenProgramId.into());
+        }
+        if amount_out == 0 {
+            return Err(AmmError::ZeroTradingTokens.into());
+        }
+        let destination = Self::unpack_token_account(destination_info, token_swap.token_program_id())?;
+        let (swap_source_amount, swap_destination_amount) = Self::ordered_pool_reserves(
+            token_swap.as_ref(),
+            authority_info,
+            swap_source_info,
+            swap_destination_info,
+            open_orders_info,
+        )?;
+        let (token_a_amount, token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_source_amount, swap_destination_amount),
+            TradeDirection::BtoA => (swap_destination_amount, swap_source_amount),
+        };
+        Self::update_price_oracle(swap_info, token_a_amount, token_b_amount)?;
+        let nonce = token_swap.nonce();
+
+        Self::set_pool_locked(swap_info, true)?;
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            swap_destination_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            nonce,
+            amount_out,
+        )?;
+        Self::invoke_receiver(
+            receiver_program_info,
+            swap_source_info,
+            destination_info,
+            token_program_info,
+            receiver_infos,
+            data,
+        )?;
+
+        // the SOURCE reserves paid in, open orders included, must buy at least
+        // `amount_out` on the curve, fees included, without the DESTINATION
+        // reserves losing more than `amount_out`
+        let (new_swap_source_amount, new_swap_destination_amount) = Self::ordered_pool_reserves(
+            token_swap.as_ref(),
+            authority_info,
+            swap_source_info,
+            swap_destination_info,
+            open_orders_info,
+        )?;
+        if new_swap_destination_amount < swap_destination_amount.saturating_sub(amount_out) {
+            return Err(AmmError::FlashSwapNotPaid.into());
+        }
+        let paid_amount = new_swap_source_amount
+            .checked_sub(swap_source_amount)
+            .ok_or(AmmError::FlashSwapNotPaid)?;
+        let mut result = Self::current_swap_curve(token_swap.as_ref())?
+            .swap(
+                to_u128(paid_amount)?,
+                to_u128(swap_source_amount)?,
+                to_u128(swap_destination_amount)?,
+                trade_direction,
+                token_swap.fees(),
+            )
+            .ok_or(AmmError::FlashSwapNotPaid)?;
+        let amount_out = to_u128(amount_out)?;
+        if result.destination_amount_swapped < amount_out {
+            return Err(AmmError::FlashSwapNotPaid.into());
+        }
+        // anything the payment buys above `amount_out` stays in the pool
+        let surplus = result.destination_amount_swapped - amount_out;
+        result.new_swap_destination_amount = result
+            .new_swap_destination_amount
+            .checked_add(surplus)
+            .ok_or(AmmError::CalculationFailure)?;
+        result.destination_amount_swapped = amount_out;
+        // pools taking a protocol fee keep the fixed fee, as do pools paying
+        // the fixed fee of this token in SOL
+        let owner_fee = if token_swap.protocol_fee().is_enabled() {
+            0
+        } else {
+            result.owner_fee
+        };
+        let fixed_fee = if fixed_fee_account_info.owner == token_program_info.key {
+            owner_fee
+        } else {
+            0
+        };
+        if fixed_fee > 0 {
+            Self::check_fixed_fee_account(
+                &config,
+                fixed_fee_account_info,
+                source_mint,
+                token_program_info.key,
+            )?;
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                swap_source_info.clone(),
+                fixed_fee_account_info.clone(),
+                authority_info.clone(),
+                nonce,
+                to_u64(fixed_fee)?,
+            )?;
+        }
+        // everything paid in but the fixed fee sent out stays in the pool
+        let paid_amount = to_u128(paid_amount)?;
+        let return_fee = result.trade_fee
+            + (result.owner_fee - fixed_fee)
+            + (paid_amount - result.source_amount_swapped);
+        Self::record_stats(swap_info, |stats| {
+            stats.record_swap(trade_direction, paid_amount, fixed_fee, 0, return_fee)
+        })?;
+
+        let reserve_source = swap_source_amount
+            .checked_add(to_u64(result.source_amount_swapped - owner_fee)?)
+            .ok_or(AmmError::CalculationFailure)?;
+        let reserve_destination = swap_destination_amount
+            .checked_sub(to_u64(result.destination_amount_swapped)?)
+            .ok_or(AmmError::CalculationFailure)?;
+        let (reserve_a, reserve_b) = match trade_direction {
+            TradeDirection::AtoB => (reserve_source, reserve_destination),
+            TradeDirection::BtoA => (reserve_destination, reserve_source),
+        };
+        AmmEvent::Swap(SwapEvent {
+            pool: *swap_info.key,
+            user: destination.owner,
+            trade_direction,
+            amount_in: to_u64(result.source_amount_swapped)?,
+            amount_out: to_u64(result.destination_amount_swapped)?,
+            owner_fee: to_u64(owner_fee)?,
+            reserve_a,
+            reserve_b,
+        })
+        .emit();
+
+        Self::set_pool_locked(swap_info, false)
+    }
+
+    /// Processes an [DepositAllTokenTypes](enum.Instruction.html).
+    pub fn process_deposit_all_token_types(
+        program_id: &Pubkey,
+        pool_token_amount: u64,
+        maximum_token_a_amount: u64,
+        maximum_token_b_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_a_info = next_account_info(account_info_iter)?;
+        let source_b_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let dest_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let open_orders_info = Self::next_optional_account(account_info_iter);
+        let protocol_fee_info = Self::next_optional_account(account_info_iter);
+
+        //validate account
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if !token_swap.status().can_deposit() {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if token_swap.locked() {
+            return Err(AmmError::PoolLocked.into());
+        }
+        let calculator = &token_swap.swap_curve().calculator;
+        if !calculator.allows_deposits() {
+            return Err(AmmError::UnsupportedCurveOperation.into());
+        }
+        Self::check_accounts(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
             authority_info,
             token_a_info,
             token_b_info,
@@ -566,10 +2236,34 @@ impl Processor {
             None
         )?;
 
-        let token_a = Self::unpack_token_account(token_a_info, token_swap.token_program_id())?;
-        let token_b = Self::unpack_token_account(token_b_info, token_swap.token_program_id())?;
+        let token_a =
+            Self::unpack_token_account(token_a_info, Self::token_program_id_of(token_a_info)?)?;
+        let token_b =
+            Self::unpack_token_account(token_b_info, Self::token_program_id_of(token_b_info)?)?;
+        let token_a_side =
+            Self::token_side(accounts, token_program_info, token_a_info, &token_a.mint)?;
+        let token_b_side =
+            Self::token_side(accounts, token_program_info, token_b_info, &token_b.mint)?;
+        let (swap_token_a_amount, swap_token_b_amount) = Self::pool_reserves(
+            token_swap.as_ref(),
+            authority_info,
+            token_a.amount,
+            token_b.amount,
+            open_orders_info,
+        )?;
+        Self::update_price_oracle(swap_info, swap_token_a_amount, swap_token_b_amount)?;
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
-        let current_pool_mint_supply = to_u128(pool_mint.supply)?;
+        let current_pool_mint_supply = to_u128(Self::mint_protocol_fee(
+            token_swap.as_ref(),
+            swap_info,
+            authority_info,
+            pool_mint_info,
+            protocol_fee_info,
+            token_program_info,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_mint.supply,
+        )?)?;
         let (pool_token_amount, pool_mint_supply) = if current_pool_mint_supply > 0 {
             (to_u128(pool_token_amount)?, current_pool_mint_supply)
         } else {
@@ -580,20 +2274,21 @@ impl Processor {
             .pool_tokens_to_trading_tokens(
                 pool_token_amount,
                 pool_mint_supply,
-                to_u128(token_a.amount)?,
-                to_u128(token_b.amount)?,
+                to_u128(swap_token_a_amount)?,
+                to_u128(swap_token_b_amount)?,
                 RoundDirection::Ceiling,
             )
             .ok_or(AmmError::ZeroTradingTokens)?;
+        // the depositor also pays the transfer fees on the tokens the pool needs
         let token_a_amount = to_u64(results.token_a_amount)?;
-        if token_a_amount > maximum_token_a_amount {
+        if token_a_side.amount_sent(token_a_amount)? > maximum_token_a_amount {
             return Err(AmmError::ExceededSlippage.into());
         }
         if token_a_amount == 0 {
             return Err(AmmError::ZeroTradingTokens.into());
         }
         let token_b_amount = to_u64(results.token_b_amount)?;
-        if token_b_amount > maximum_token_b_amount {
+        if token_b_side.amount_sent(token_b_amount)? > maximum_token_b_amount {
             return Err(AmmError::ExceededSlippage.into());
         }
         if token_b_amount == 0 {
@@ -602,23 +2297,21 @@ impl Processor {
 
         let pool_token_amount = to_u64(pool_token_amount)?;
         //transfer token to pool
-        Self::token_transfer(
+        token_a_side.transfer(
             swap_info.key,
-            token_program_info.clone(),
-            source_a_info.clone(),
-            token_a_info.clone(),
-            user_transfer_authority_info.clone(),
+            source_a_info,
+            token_a_info,
+            user_transfer_authority_info,
             token_swap.nonce(),
-            token_a_amount,
+            token_a_side.amount_sent(token_a_amount)?,
         )?;
-        Self::token_transfer(
+        token_b_side.transfer(
             swap_info.key,
-            token_program_info.clone(),
-            source_b_info.clone(),
-            token_b_info.clone(),
-            user_transfer_authority_info.clone(),
+            source_b_info,
+            token_b_info,
+            user_transfer_authority_info,
             token_swap.nonce(),
-            token_b_amount,
+            token_b_side.amount_sent(token_b_amount)?,
         )?;
         //mint lp token to wallet
         Self::token_mint_to(
@@ -630,6 +2323,25 @@ impl Processor {
             token_swap.nonce(),
             pool_token_amount,
         )?;
+        Self::record_stats(swap_info, PoolStats::record_deposit)?;
+        let reserve_a = swap_token_a_amount
+            .checked_add(token_a_amount)
+            .ok_or(AmmError::CalculationFailure)?;
+        let reserve_b = swap_token_b_amount
+            .checked_add(token_b_amount)
+            .ok_or(AmmError::CalculationFailure)?;
+        Self::record_root_k(swap_info, reserve_a, reserve_b)?;
+        AmmEvent::Deposit(LiquidityEvent {
+            pool: *swap_info.key,
+            user: *user_transfer_authority_info.key,
+            token_a_amount,
+            token_b_amount,
+            pool_token_amount,
+            owner_fee: 0,
+            reserve_a,
+            reserve_b,
+        })
+        .emit();
 
         Ok(())
     }
@@ -654,9 +2366,19 @@ impl Processor {
         let dest_token_a_info = next_account_info(account_info_iter)?;
         let dest_token_b_info = next_account_info(account_info_iter)?;
         let token_program_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let pool_fee_info = next_account_info(account_info_iter)?;
+        let open_orders_info = Self::next_optional_account(account_info_iter);
+        let protocol_fee_info = Self::next_optional_account(account_info_iter);
 
         //validate accounts
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if !token_swap.status().can_withdraw() {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if token_swap.locked() {
+            return Err(AmmError::PoolLocked.into());
+        }
         Self::check_accounts(
             token_swap.as_ref(),
             program_id,
@@ -672,22 +2394,45 @@ impl Processor {
             None
         )?;
 
-        let token_a = Self::unpack_token_account(token_a_info, token_swap.token_program_id())?;
-        let token_b = Self::unpack_token_account(token_b_info, token_swap.token_program_id())?;
+        let token_a =
+            Self::unpack_token_account(token_a_info, Self::token_program_id_of(token_a_info)?)?;
+        let token_b =
+            Self::unpack_token_account(token_b_info, Self::token_program_id_of(token_b_info)?)?;
+        let token_a_side =
+            Self::token_side(accounts, token_program_info, token_a_info, &token_a.mint)?;
+        let token_b_side =
+            Self::token_side(accounts, token_program_info, token_b_info, &token_b.mint)?;
+        let (swap_token_a_amount, swap_token_b_amount) = Self::pool_reserves(
+            token_swap.as_ref(),
+            authority_info,
+            token_a.amount,
+            token_b.amount,
+            open_orders_info,
+        )?;
+        Self::update_price_oracle(swap_info, swap_token_a_amount, swap_token_b_amount)?;
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        let pool_mint_supply = Self::mint_protocol_fee(
+            token_swap.as_ref(),
+            swap_info,
+            authority_info,
+            pool_mint_info,
+            protocol_fee_info,
+            token_program_info,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_mint.supply,
+        )?;
 
         let calculator = &token_swap.swap_curve().calculator;
 
-        let withdraw_fee: u128 = 0;
-        // if *fixed_fee_account_info.key == *source_info.key {
-        //     // withdrawing from the fee account, don't assess withdraw fee
-        //     0
-        // } else {
-        //     token_swap
-        //         .fees()
-        //         .owner_withdraw_fee(to_u128(pool_token_amount)?)
-        //         .ok_or(AmmError::FeeCalculationFailure)?
-        // };
+        let withdraw_fee = Self::owner_withdraw_fee(
+            program_id,
+            token_swap.as_ref(),
+            config_info,
+            source_info,
+            pool_fee_info,
+            to_u128(pool_token_amount)?,
+        )?;
         let pool_token_amount = to_u128(pool_token_amount)?
             .checked_sub(withdraw_fee)
             .ok_or(AmmError::CalculationFailure)?;
@@ -695,40 +2440,41 @@ impl Processor {
         let results = calculator
             .pool_tokens_to_trading_tokens(
                 pool_token_amount,
-                to_u128(pool_mint.supply)?,
-                to_u128(token_a.amount)?,
-                to_u128(token_b.amount)?,
+                to_u128(pool_mint_supply)?,
+                to_u128(swap_token_a_amount)?,
+                to_u128(swap_token_b_amount)?,
                 RoundDirection::Floor,
             )
             .ok_or(AmmError::ZeroTradingTokens)?;
         let token_a_amount = to_u64(results.token_a_amount)?;
-        let token_a_amount = std::cmp::min(token_a.amount, token_a_amount);
-        if token_a_amount < minimum_token_a_amount {
+        let token_a_amount = std::cmp::min(swap_token_a_amount, token_a_amount);
+        // the minimums apply to the tokens received after transfer fees
+        if token_a_side.amount_received(token_a_amount)? < minimum_token_a_amount {
             return Err(AmmError::ExceededSlippage.into());
         }
-        if token_a_amount == 0 && token_a.amount != 0 {
+        if token_a_amount == 0 && swap_token_a_amount != 0 {
             return Err(AmmError::ZeroTradingTokens.into());
         }
         let token_b_amount = to_u64(results.token_b_amount)?;
-        let token_b_amount = std::cmp::min(token_b.amount, token_b_amount);
-        if token_b_amount < minimum_token_b_amount {
+        let token_b_amount = std::cmp::min(swap_token_b_amount, token_b_amount);
+        if token_b_side.amount_received(token_b_amount)? < minimum_token_b_amount {
             return Err(AmmError::ExceededSlippage.into());
         }
-        if token_b_amount == 0 && token_b.amount != 0 {
+        if token_b_amount == 0 && swap_token_b_amount != 0 {
             return Err(AmmError::ZeroTradingTokens.into());
         }
 
-        // if withdraw_fee > 0 {
-        //     Self::token_transfer(
-        //         swap_info.key,
-        //         token_program_info.clone(),
-        //         source_info.clone(),
-        //         fixed_fee_account_info.clone(),
-        //         user_transfer_authority_info.clone(),
-        //         token_swap.nonce(),
-        //         to_u64(withdraw_fee)?,
-        //     )?;
-        // }
+        if withdraw_fee > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                source_info.clone(),
+                pool_fee_info.clone(),
+                user_transfer_authority_info.clone(),
+                token_swap.nonce(),
+                to_u64(withdraw_fee)?,
+            )?;
+        }
         //remove lp token from wallet
         Self::token_burn(
             swap_info.key,
@@ -741,28 +2487,41 @@ impl Processor {
         )?;
         //transfer coin token to wallet
         if token_a_amount > 0 {
-            Self::token_transfer(
+            token_a_side.transfer(
                 swap_info.key,
-                token_program_info.clone(),
-                token_a_info.clone(),
-                dest_token_a_info.clone(),
-                authority_info.clone(),
+                token_a_info,
+                dest_token_a_info,
+                authority_info,
                 token_swap.nonce(),
                 token_a_amount,
             )?;
         }
         //transfer pc token to wallet
         if token_b_amount > 0 {
-            Self::token_transfer(
+            token_b_side.transfer(
                 swap_info.key,
-                token_program_info.clone(),
-                token_b_info.clone(),
-                dest_token_b_info.clone(),
-                authority_info.clone(),
+                token_b_info,
+                dest_token_b_info,
+                authority_info,
                 token_swap.nonce(),
                 token_b_amount,
             )?;
         }
+        Self::record_stats(swap_info, PoolStats::record_withdraw)?;
+        let reserve_a = swap_token_a_amount - token_a_amount;
+        let reserve_b = swap_token_b_amount - token_b_amount;
+        Self::record_root_k(swap_info, reserve_a, reserve_b)?;
+        AmmEvent::Withdraw(LiquidityEvent {
+            pool: *swap_info.key,
+            user: *user_transfer_authority_info.key,
+            token_a_amount,
+            token_b_amount,
+            pool_token_amount: to_u64(pool_token_amount)?,
+            owner_fee: to_u64(withdraw_fee)?,
+            reserve_a,
+            reserve_b,
+        })
+        .emit();
         Ok(())
     }
 
@@ -783,14 +2542,26 @@ impl Processor {
         let pool_mint_info = next_account_info(account_info_iter)?;
         let destination_info = next_account_info(account_info_iter)?;
         let token_program_info = next_account_info(account_info_iter)?;
+        let open_orders_info = Self::next_optional_account(account_info_iter);
+        let protocol_fee_info = Self::next_optional_account(account_info_iter);
 
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if !token_swap.status().can_deposit() {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if token_swap.locked() {
+            return Err(AmmError::PoolLocked.into());
+        }
         let source_account =
-            Self::unpack_token_account(source_info, token_swap.token_program_id())?;
-        let swap_token_a =
-            Self::unpack_token_account(swap_token_a_info, token_swap.token_program_id())?;
-        let swap_token_b =
-            Self::unpack_token_account(swap_token_b_info, token_swap.token_program_id())?;
+            Self::unpack_token_account(source_info, Self::token_program_id_of(source_info)?)?;
+        let swap_token_a = Self::unpack_token_account(
+            swap_token_a_info,
+            Self::token_program_id_of(swap_token_a_info)?,
+        )?;
+        let swap_token_b = Self::unpack_token_account(
+            swap_token_b_info,
+            Self::token_program_id_of(swap_token_b_info)?,
+        )?;
 
         let trade_direction = if source_account.mint == swap_token_a.mint {
             TradeDirection::AtoB
@@ -804,6 +2575,16 @@ impl Processor {
             TradeDirection::AtoB => (Some(source_info), None),
             TradeDirection::BtoA => (None, Some(source_info)),
         };
+        let swap_source_info = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_info,
+            TradeDirection::BtoA => swap_token_b_info,
+        };
+        let source_side = Self::token_side(
+            accounts,
+            token_program_info,
+            swap_source_info,
+            &source_account.mint,
+        )?;
 
         Self::check_accounts(
             token_swap.as_ref(),
@@ -820,15 +2601,34 @@ impl Processor {
             None
         )?;
 
+        let (swap_token_a_amount, swap_token_b_amount) = Self::pool_reserves(
+            token_swap.as_ref(),
+            authority_info,
+            swap_token_a.amount,
+            swap_token_b.amount,
+            open_orders_info,
+        )?;
+        Self::update_price_oracle(swap_info, swap_token_a_amount, swap_token_b_amount)?;
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
-        let pool_mint_supply = to_u128(pool_mint.supply)?;
+        let pool_mint_supply = to_u128(Self::mint_protocol_fee(
+            token_swap.as_ref(),
+            swap_info,
+            authority_info,
+            pool_mint_info,
+            protocol_fee_info,
+            token_program_info,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_mint.supply,
+        )?)?;
+        // the pool receives the source amount net of its transfer fee
+        let received_token_amount = source_side.amount_received(source_token_amount)?;
         let pool_token_amount = if pool_mint_supply > 0 {
-            token_swap
-                .swap_curve()
+            Self::current_swap_curve(token_swap.as_ref())?
                 .deposit_single_token_type(
-                    to_u128(source_token_amount)?,
-                    to_u128(swap_token_a.amount)?,
-                    to_u128(swap_token_b.amount)?,
+                    to_u128(received_token_amount)?,
+                    to_u128(swap_token_a_amount)?,
+                    to_u128(swap_token_b_amount)?,
                     pool_mint_supply,
                     trade_direction,
                     token_swap.fees(),
@@ -846,30 +2646,14 @@ impl Processor {
             return Err(AmmError::ZeroTradingTokens.into());
         }
 
-        match trade_direction {
-            TradeDirection::AtoB => {
-                Self::token_transfer(
-                    swap_info.key,
-                    token_program_info.clone(),
-                    source_info.clone(),
-                    swap_token_a_info.clone(),
-                    user_transfer_authority_info.clone(),
-                    token_swap.nonce(),
-                    source_token_amount,
-                )?;
-            }
-            TradeDirection::BtoA => {
-                Self::token_transfer(
-                    swap_info.key,
-                    token_program_info.clone(),
-                    source_info.clone(),
-                    swap_token_b_info.clone(),
-                    user_transfer_authority_info.clone(),
-                    token_swap.nonce(),
-                    source_token_amount,
-                )?;
-            }
-        }
+        source_side.transfer(
+            swap_info.key,
+            source_info,
+            swap_source_info,
+            user_transfer_authority_info,
+            token_swap.nonce(),
+            source_token_amount,
+        )?;
         Self::token_mint_to(
             swap_info.key,
             token_program_info.clone(),
@@ -879,6 +2663,29 @@ impl Processor {
             token_swap.nonce(),
             pool_token_amount,
         )?;
+        Self::record_stats(swap_info, PoolStats::record_deposit)?;
+        let (token_a_amount, token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (received_token_amount, 0),
+            TradeDirection::BtoA => (0, received_token_amount),
+        };
+        let reserve_a = swap_token_a_amount
+            .checked_add(token_a_amount)
+            .ok_or(AmmError::CalculationFailure)?;
+        let reserve_b = swap_token_b_amount
+            .checked_add(token_b_amount)
+            .ok_or(AmmError::CalculationFailure)?;
+        Self::record_root_k(swap_info, reserve_a, reserve_b)?;
+        AmmEvent::Deposit(LiquidityEvent {
+            pool: *swap_info.key,
+            user: *user_transfer_authority_info.key,
+            token_a_amount,
+            token_b_amount,
+            pool_token_amount,
+            owner_fee: 0,
+            reserve_a,
+            reserve_b,
+        })
+        .emit();
 
         Ok(())
     }
@@ -902,14 +2709,30 @@ impl Processor {
         let fixed_fee_account_info_a = next_account_info(account_info_iter)?;
         let fixed_fee_account_info_b = next_account_info(account_info_iter)?;
         let token_program_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let pool_fee_info = next_account_info(account_info_iter)?;
+        let open_orders_info = Self::next_optional_account(account_info_iter);
+        let protocol_fee_info = Self::next_optional_account(account_info_iter);
 
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
-        let destination_account =
-            Self::unpack_token_account(destination_info, token_swap.token_program_id())?;
-        let swap_token_a =
-            Self::unpack_token_account(swap_token_a_info, token_swap.token_program_id())?;
-        let swap_token_b =
-            Self::unpack_token_account(swap_token_b_info, token_swap.token_program_id())?;
+        if !token_swap.status().can_withdraw() {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if token_swap.locked() {
+            return Err(AmmError::PoolLocked.into());
+        }
+        let destination_account = Self::unpack_token_account(
+            destination_info,
+            Self::token_program_id_of(destination_info)?,
+        )?;
+        let swap_token_a = Self::unpack_token_account(
+            swap_token_a_info,
+            Self::token_program_id_of(swap_token_a_info)?,
+        )?;
+        let swap_token_b = Self::unpack_token_account(
+            swap_token_b_info,
+            Self::token_program_id_of(swap_token_b_info)?,
+        )?;
 
         let trade_direction = if destination_account.mint == swap_token_a.mint {
             TradeDirection::AtoB
@@ -923,6 +2746,16 @@ impl Processor {
             TradeDirection::AtoB => (Some(destination_info), None),
             TradeDirection::BtoA => (None, Some(destination_info)),
         };
+        let swap_destination_info = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_info,
+            TradeDirection::BtoA => swap_token_b_info,
+        };
+        let destination_side = Self::token_side(
+            accounts,
+            token_program_info,
+            swap_destination_info,
+            &destination_account.mint,
+        )?;
         Self::check_accounts(
             token_swap.as_ref(),
             program_id,
@@ -934,19 +2767,38 @@ impl Processor {
             token_program_info,
             destination_a_info,
             destination_b_info,
-            Some(fixed_fee_account_info_b),
             Some(fixed_fee_account_info_a),
+            Some(fixed_fee_account_info_b),
         )?;
 
+        let (swap_token_a_amount, swap_token_b_amount) = Self::pool_reserves(
+            token_swap.as_ref(),
+            authority_info,
+            swap_token_a.amount,
+            swap_token_b.amount,
+            open_orders_info,
+        )?;
+        Self::update_price_oracle(swap_info, swap_token_a_amount, swap_token_b_amount)?;
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
-        let pool_mint_supply = to_u128(pool_mint.supply)?;
-        let swap_token_a_amount = to_u128(swap_token_a.amount)?;
-        let swap_token_b_amount = to_u128(swap_token_b.amount)?;
-
-        let burn_pool_token_amount = token_swap
-            .swap_curve()
+        let pool_mint_supply = to_u128(Self::mint_protocol_fee(
+            token_swap.as_ref(),
+            swap_info,
+            authority_info,
+            pool_mint_info,
+            protocol_fee_info,
+            token_program_info,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_mint.supply,
+        )?)?;
+        let swap_token_a_amount = to_u128(swap_token_a_amount)?;
+        let swap_token_b_amount = to_u128(swap_token_b_amount)?;
+
+        // the pool pays out the transfer fee of the destination amount on top of it
+        let sent_token_amount = destination_side.amount_sent(destination_token_amount)?;
+        let burn_pool_token_amount = Self::current_swap_curve(token_swap.as_ref())?
             .withdraw_single_token_type_exact_out(
-                to_u128(destination_token_amount)?,
+                to_u128(sent_token_amount)?,
                 swap_token_a_amount,
                 swap_token_b_amount,
                 pool_mint_supply,
@@ -955,16 +2807,14 @@ impl Processor {
             )
             .ok_or(AmmError::ZeroTradingTokens)?;
 
-        let withdraw_fee: u128 = 0;
-        // if *fixed_fee_account_info.key == *source_info.key {
-        //     // withdrawing from the fee account, don't assess withdraw fee
-        //     0
-        // } else {
-        //     token_swap
-        //         .fees()
-        //         .owner_withdraw_fee(burn_pool_token_amount)
-        //         .ok_or(AmmError::FeeCalculationFailure)?
-        // };
+        let withdraw_fee = Self::owner_withdraw_fee(
+            program_id,
+            token_swap.as_ref(),
+            config_info,
+            source_info,
+            pool_fee_info,
+            burn_pool_token_amount,
+        )?;
         let pool_token_amount = burn_pool_token_amount
             .checked_add(withdraw_fee)
             .ok_or(AmmError::CalculationFailure)?;
@@ -976,17 +2826,17 @@ impl Processor {
             return Err(AmmError::ZeroTradingTokens.into());
         }
 
-        // if withdraw_fee > 0 {
-        //     Self::token_transfer(
-        //         swap_info.key,
-        //         token_program_info.clone(),
-        //         source_info.clone(),
-        //         fixed_fee_account_info.clone(),
-        //         user_transfer_authority_info.clone(),
-        //         token_swap.nonce(),
-        //         to_u64(withdraw_fee)?,
-        //     )?;
-        // }
+        if withdraw_fee > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                source_info.clone(),
+                pool_fee_info.clone(),
+                user_transfer_authority_info.clone(),
+                token_swap.nonce(),
+                to_u64(withdraw_fee)?,
+            )?;
+        }
         Self::token_burn(
             swap_info.key,
             token_program_info.clone(),
@@ -997,1899 +2847,7901 @@ impl Processor {
             to_u64(burn_pool_token_amount)?,
         )?;
 
-        match trade_direction {
-            TradeDirection::AtoB => {
-                Self::token_transfer(
-                    swap_info.key,
-                    token_program_info.clone(),
-                    swap_token_a_info.clone(),
-                    destination_info.clone(),
-                    authority_info.clone(),
-                    token_swap.nonce(),
-                    destination_token_amount,
-                )?;
-            }
-            TradeDirection::BtoA => {
-                Self::token_transfer(
-                    swap_info.key,
-                    token_program_info.clone(),
-                    swap_token_b_info.clone(),
-                    destination_info.clone(),
-                    authority_info.clone(),
-                    token_swap.nonce(),
-                    destination_token_amount,
-                )?;
-            }
-        }
+        destination_side.transfer(
+            swap_info.key,
+            swap_destination_info,
+            destination_info,
+            authority_info,
+            token_swap.nonce(),
+            sent_token_amount,
+        )?;
+        Self::record_stats(swap_info, PoolStats::record_withdraw)?;
+        let (token_a_amount, token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (sent_token_amount, 0),
+            TradeDirection::BtoA => (0, sent_token_amount),
+        };
+        let reserve_a = to_u64(swap_token_a_amount)?
+            .checked_sub(token_a_amount)
+            .ok_or(AmmError::CalculationFailure)?;
+        let reserve_b = to_u64(swap_token_b_amount)?
+            .checked_sub(token_b_amount)
+            .ok_or(AmmError::CalculationFailure)?;
+        Self::record_root_k(swap_info, reserve_a, reserve_b)?;
+        AmmEvent::Withdraw(LiquidityEvent {
+            pool: *swap_info.key,
+            user: *user_transfer_authority_info.key,
+            token_a_amount,
+            token_b_amount,
+            pool_token_amount: to_u64(burn_pool_token_amount)?,
+            owner_fee: to_u64(withdraw_fee)?,
+            reserve_a,
+            reserve_b,
+        })
+        .emit();
 
         Ok(())
     }
 
-    /// Processes an [Instruction](enum.Instruction.html).
-    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
-        Self::process_with_constraints(program_id, accounts, input, &SWAP_CONSTRAINTS)
+    /// Unpacks a multi token pool, checking the swap account and its
+    /// authority
+    fn unpack_multi_swap(
+        program_id: &Pubkey,
+        swap_info: &AccountInfo,
+        authority_info: &AccountInfo,
+    ) -> Result<MultiSwap, ProgramError> {
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let multi_swap = MultiSwap::unpack_account(&swap_info.data.borrow())?;
+        if *authority_info.key != Self::authority_id(program_id, swap_info.key, multi_swap.nonce)?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        Ok(multi_swap)
     }
 
-    /// Processes an instruction given extra constraint
-    pub fn process_with_constraints(
+    /// Amounts held by the token accounts of a multi token pool, which must
+    /// all be given in pool order
+    fn multi_pool_reserves(
+        multi_swap: &MultiSwap,
+        token_infos: &[&AccountInfo],
+    ) -> Result<Vec<u128>, ProgramError> {
+        if token_infos.len() != multi_swap.reserve_accounts().len() {
+            return Err(AmmError::InvalidTokenCount.into());
+        }
+        token_infos
+            .iter()
+            .zip(multi_swap.reserve_accounts())
+            .map(|(token_info, token_account)| {
+                if token_info.key != token_account {
+                    return Err(AmmError::IncorrectSwapAccount.into());
+                }
+                let token = Self::unpack_token_account(token_info, &multi_swap.token_program_id)?;
+                Ok(to_u128(token.amount)?)
+            })
+            .collect()
+    }
+
+    /// Position in the pool of the token with the given mint
+    fn multi_token_index(multi_swap: &MultiSwap, mint: &Pubkey) -> Result<usize, ProgramError> {
+        multi_swap
+            .mints()
+            .iter()
+            .position(|token_mint| token_mint == mint)
+            .ok_or_else(|| AmmError::IncorrectSwapAccount.into())
+    }
+
+    /// Processes an [InitializeMulti](enum.Instruction.html).
+    pub fn process_initialize_multi(
         program_id: &Pubkey,
+        nonce: u8,
+        fees: Fees,
+        swap_curve: MultiSwapCurve,
         accounts: &[AccountInfo],
-        input: &[u8],
         swap_constraints: &Option<SwapConstraints>,
     ) -> ProgramResult {
-        let instruction = AmmInstruction::unpack(input)?;
-        match instruction {
-            AmmInstruction::Initialize(InitializeInstruction {
-                nonce,
-                fees,
-                swap_curve,
-            }) => {
-                msg!("Instruction: Init");
-                Self::process_initialize(
-                    program_id,
-                    nonce,
-                    fees,
-                    swap_curve,
-                    accounts,
-                    swap_constraints,
-                )
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let token_infos = account_info_iter.as_slice();
+
+        //validate account info
+        let token_program_id = *token_program_info.key;
+        if SwapVersion::is_initialized(&swap_info.data.borrow()) {
+            return Err(AmmError::AlreadyInUse.into());
+        }
+        if *authority_info.key != Self::authority_id(program_id, swap_info.key, nonce)? {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        if !token_infos.len().is_multiple_of(MULTI_TOKEN_ACCOUNTS) {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        let token_count = token_infos.len() / MULTI_TOKEN_ACCOUNTS;
+        if !(MIN_TOKENS..=MAX_TOKENS).contains(&token_count) {
+            return Err(AmmError::InvalidTokenCount.into());
+        }
+
+        let destination = Self::unpack_token_account(destination_info, &token_program_id)?;
+        let pool_mint = Self::unpack_mint(pool_mint_info, &token_program_id)?;
+        if *authority_info.key == destination.owner {
+            return Err(AmmError::InvalidOutputOwner.into());
+        }
+        if COption::Some(*authority_info.key) != pool_mint.mint_authority {
+            return Err(AmmError::InvalidOwner.into());
+        }
+        if pool_mint.supply != 0 {
+            return Err(AmmError::InvalidSupply.into());
+        }
+        if pool_mint.freeze_authority.is_some() {
+            return Err(AmmError::InvalidFreezeAuthority.into());
+        }
+
+        let fixed_fee_acc_owner = Self::unpack_config(program_id, config_info)?.fee_wallet;
+        let mut token_accounts = [Pubkey::default(); MAX_TOKENS];
+        let mut token_mints = [Pubkey::default(); MAX_TOKENS];
+        let mut fixed_fee_accounts = [Pubkey::default(); MAX_TOKENS];
+        for (index, infos) in token_infos.chunks(MULTI_TOKEN_ACCOUNTS).enumerate() {
+            let (token_info, fee_account_info) = (&infos[0], &infos[1]);
+            let token = Self::unpack_token_account(token_info, &token_program_id)?;
+            if *authority_info.key != token.owner {
+                return Err(AmmError::InvalidOwner.into());
             }
-            AmmInstruction::Swap(SwapInstruction {
-                amount_in,
-                minimum_amount_out,
-            }) => {
-                msg!("Instruction: Swap");
-                Self::process_swap(program_id, amount_in, minimum_amount_out, accounts)
+            if token.amount == 0 {
+                return Err(AmmError::EmptySupply.into());
             }
-            AmmInstruction::DepositAllTokenTypes(DepositInstruction {
-                pool_token_amount,
-                maximum_token_a_amount,
-                maximum_token_b_amount,
-            }) => {
-                msg!("Instruction: DepositAllTokenTypes");
-                Self::process_deposit_all_token_types(
-                    program_id,
-                    pool_token_amount,
-                    maximum_token_a_amount,
-                    maximum_token_b_amount,
-                    accounts,
-                )
+            if token.delegate.is_some() {
+                return Err(AmmError::InvalidDelegate.into());
             }
-            AmmInstruction::WithdrawAllTokenTypes(WithdrawInstruction {
-                pool_token_amount,
-                minimum_token_a_amount,
-                minimum_token_b_amount,
-            }) => {
-                msg!("Instruction: WithdrawAllTokenTypes");
-                Self::process_withdraw_all_token_types(
-                    program_id,
-                    pool_token_amount,
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                    accounts,
-                )
+            if token.close_authority.is_some() {
+                return Err(AmmError::InvalidCloseAuthority.into());
             }
-            AmmInstruction::DepositSingleTokenTypeExactAmountIn(
-                DepositSingleTokenTypeExactAmountIn {
-                    source_token_amount,
-                    minimum_pool_token_amount,
-                },
-            ) => {
-                msg!("Instruction: DepositSingleTokenTypeExactAmountIn");
-                Self::process_deposit_single_token_type_exact_amount_in(
-                    program_id,
-                    source_token_amount,
-                    minimum_pool_token_amount,
-                    accounts,
-                )
+            if token_mints[..index].contains(&token.mint) {
+                return Err(AmmError::RepeatedMint.into());
             }
-            AmmInstruction::WithdrawSingleTokenTypeExactAmountOut(
-                WithdrawSingleTokenTypeExactAmountOut {
-                    destination_token_amount,
-                    maximum_pool_token_amount,
-                },
-            ) => {
-                msg!("Instruction: WithdrawSingleTokenTypeExactAmountOut");
-                Self::process_withdraw_single_token_type_exact_amount_out(
-                    program_id,
-                    destination_token_amount,
-                    maximum_pool_token_amount,
-                    accounts,
-                )
+            //check the fee account is set correctly
+            if fixed_fee_acc_owner != *fee_account_info.key {
+                let fee_account = Self::unpack_token_account(fee_account_info, &token_program_id)?;
+                if fixed_fee_acc_owner != fee_account.owner || token.mint != fee_account.mint {
+                    return Err(AmmError::IncorrectFeeAccount.into());
+                }
             }
+            token_accounts[index] = *token_info.key;
+            token_mints[index] = token.mint;
+            fixed_fee_accounts[index] = *fee_account_info.key;
+        }
+
+        if let Some(swap_constraints) = swap_constraints {
+            swap_constraints.validate_multi_curve(&swap_curve)?;
+            swap_constraints.validate_fees(&fees)?;
         }
+        fees.validate()?;
+        swap_curve.calculator.validate(token_count)?;
+
+        let initial_amount = swap_curve.calculator.new_pool_supply();
+
+        //Mint Initial supply
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            nonce,
+            to_u64(initial_amount)?,
+        )?;
+
+        //Save the pool account info
+        let obj = MultiSwap {
+            is_initialized: true,
+            nonce,
+            token_program_id,
+            pool_mint: *pool_mint_info.key,
+            token_count: token_count as u8,
+            token_accounts,
+            token_mints,
+            fixed_fee_accounts,
+            fees,
+            swap_curve,
+            status: PoolStatus::Active,
+            stats: MultiPoolStats::default(),
+            price_oracle: MultiPriceOracle::new(Clock::get()?.unix_timestamp),
+        };
+        MultiSwap::pack_account(obj, &mut swap_info.data.borrow_mut())?;
+        Ok(())
     }
-}
 
-impl PrintProgramError for AmmError {
-    fn print<E>(&self)
-    where
-        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
-    {
-        match self {
-            AmmError::AlreadyInUse => msg!("Error: Swap account already in use"),
-            AmmError::InvalidProgramAddress => {
-                msg!("Error: Invalid program address generated from nonce and key")
-            }
-            AmmError::InvalidOwner => {
-                msg!("Error: The input account owner is not the program address")
-            }
-            AmmError::InvalidOutputOwner => {
-                msg!("Error: Output pool account owner cannot be the program address")
-            }
-            AmmError::ExpectedMint => msg!("Error: Deserialized account is not an SPL Token mint"),
-            AmmError::ExpectedAccount => {
-                msg!("Error: Deserialized account is not an SPL Token account")
-            }
-            AmmError::EmptySupply => msg!("Error: Input token account empty"),
-            AmmError::InvalidSupply => msg!("Error: Pool token mint has a non-zero supply"),
-            AmmError::RepeatedMint => msg!("Error: Swap input token accounts have the same mint"),
-            AmmError::InvalidDelegate => msg!("Error: Token account has a delegate"),
-            AmmError::InvalidInput => msg!("Error: InvalidInput"),
-            AmmError::IncorrectSwapAccount => {
-                msg!("Error: Address of the provided swap token account is incorrect")
-            }
-            AmmError::IncorrectPoolMint => {
-                msg!("Error: Address of the provided pool token mint is incorrect")
-            }
-            AmmError::InvalidOutput => msg!("Error: InvalidOutput"),
-            AmmError::CalculationFailure => msg!("Error: CalculationFailure"),
-            AmmError::InvalidInstruction => msg!("Error: InvalidInstruction"),
-            AmmError::ExceededSlippage => {
-                msg!("Error: Swap instruction exceeds desired slippage limit")
-            }
-            AmmError::InvalidCloseAuthority => msg!("Error: Token account has a close authority"),
-            AmmError::InvalidFreezeAuthority => {
-                msg!("Error: Pool token mint has a freeze authority")
-            }
-            AmmError::IncorrectFeeAccount => msg!("Error: Pool fee token account incorrect"),
-            AmmError::ZeroTradingTokens => {
-                msg!("Error: Given pool token amount results in zero trading tokens")
-            }
-            AmmError::FeeCalculationFailure => msg!(
-                "Error: The fee calculation failed due to overflow, underflow, or unexpected 0"
-            ),
-            AmmError::ConversionFailure => msg!("Error: Conversion to or from u64 failed."),
-            AmmError::InvalidFee => {
-                msg!("Error: The provided fee does not match the program owner's constraints")
-            }
-            AmmError::IncorrectTokenProgramId => {
-                msg!("Error: The provided token program does not match the token program expected by the swap")
-            }
-            AmmError::UnsupportedCurveType => {
-                msg!("Error: The provided curve type is not supported by the program owner")
-            }
-            AmmError::InvalidCurve => {
-                msg!("Error: The provided curve parameters are invalid")
-            }
-            AmmError::UnsupportedCurveOperation => {
-                msg!("Error: The operation cannot be performed on the given curve")
-            }
-        }
-    }
-}
+    /// Processes a [MultiSwap](enum.Instruction.html).
+    pub fn process_multi_swap(
+        program_id: &Pubkey,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let fixed_fee_account_info = next_account_info(account_info_iter)?;
+        let fixed_fee_wallet_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let token_infos: Vec<&AccountInfo> = account_info_iter.collect();
 
-fn to_u128(val: u64) -> Result<u128, AmmError> {
-    val.try_into().map_err(|_| AmmError::ConversionFailure)
-}
+        //validate account info
+        let multi_swap = Self::unpack_multi_swap(program_id, swap_info, authority_info)?;
+        if !multi_swap.status.can_swap() {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if *token_program_info.key != multi_swap.token_program_id {
+            return Err(AmmError::IncorrectTokenProgramId.into());
+        }
+        let swap_token_amounts = Self::multi_pool_reserves(&multi_swap, &token_infos)?;
+        Self::update_multi_price_oracle(swap_info, &swap_token_amounts)?;
+        if multi_swap.reserve_accounts().contains(source_info.key)
+            || multi_swap.reserve_accounts().contains(destination_info.key)
+        {
+            return Err(AmmError::InvalidInput.into());
+        }
+        let source = Self::unpack_token_account(source_info, &multi_swap.token_program_id)?;
+        let destination =
+            Self::unpack_token_account(destination_info, &multi_swap.token_program_id)?;
+        let source_index = Self::multi_token_index(&multi_swap, &source.mint)?;
+        let destination_index = Self::multi_token_index(&multi_swap, &destination.mint)?;
+        if source_index == destination_index {
+            return Err(AmmError::InvalidInput.into());
+        }
+        if *fixed_fee_account_info.key != multi_swap.fixed_fee_accounts[source_index] {
+            return Err(AmmError::IncorrectFeeAccount.into());
+        }
+        let fixed_fee_acc_owner = Self::unpack_config(program_id, config_info)?.fee_wallet;
+        if fixed_fee_acc_owner != *fixed_fee_wallet_info.key {
+            return Err(AmmError::IncorrectFeeAccount.into());
+        }
 
-fn to_u64(val: u128) -> Result<u64, AmmError> {
-    val.try_into().map_err(|_| AmmError::ConversionFailure)
-}
+        let result = multi_swap
+            .swap_curve
+            .swap(
+                to_u128(amount_in)?,
+                &swap_token_amounts,
+                source_index,
+                destination_index,
+                &multi_swap.fees,
+            )
+            .ok_or(AmmError::ZeroTradingTokens)?;
+        if result.destination_amount_swapped < to_u128(minimum_amount_out)? {
+            return Err(AmmError::ExceededSlippage.into());
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        curve::calculator::{CurveCalculator, INITIAL_SWAP_POOL_AMOUNT},
-        curve::{
-            base::CurveType, constant_price::ConstantPriceCurve,
-            constant_product::ConstantProductCurve, offset::OffsetCurve,
-        },
-        amm_instruction::{
-            deposit_all_token_types, deposit_single_token_type_exact_amount_in, initialize, swap,
-            withdraw_all_token_types, withdraw_single_token_type_exact_amount_out,
-        },
-    };
-    use solana_program::{instruction::Instruction, program_stubs, rent::Rent};
-    use solana_sdk::account::{create_account_for_test, create_is_signer_account_infos, Account};
-    use spl_token::{
-        error::TokenError,
-        instruction::{
-            approve, initialize_account, initialize_mint, mint_to, revoke, set_authority,
-            AuthorityType,
-        },
-    };
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            token_infos[source_index].clone(),
+            user_transfer_authority_info.clone(),
+            multi_swap.nonce,
+            to_u64(result.source_amount_swapped - result.owner_fee)?,
+        )?;
+        Self::pay_fixed_fee(
+            swap_info.key,
+            &TokenSide::plain(token_program_info),
+            system_program_info,
+            user_transfer_authority_info,
+            source_info,
+            fixed_fee_account_info,
+            fixed_fee_wallet_info,
+            &source.mint,
+            multi_swap.nonce,
+            to_u64(result.owner_fee)?,
+        )?;
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            token_infos[destination_index].clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            multi_swap.nonce,
+            to_u64(result.destination_amount_swapped)?,
+        )?;
+        Self::record_multi_stats(swap_info, |stats| {
+            stats.record_swap(
+                source_index,
+                result.source_amount_swapped,
+                result.owner_fee,
+                result.trade_fee,
+            )
+        })?;
 
-    // Test program id for the swap program.
-    const SWAP_PROGRAM_ID: Pubkey = Pubkey::new_from_array([2u8; 32]);
+        let reserve_in = swap_token_amounts[source_index]
+            .checked_add(result.source_amount_swapped - result.owner_fee)
+            .ok_or(AmmError::CalculationFailure)?;
+        let reserve_out = swap_token_amounts[destination_index]
+            .checked_sub(result.destination_amount_swapped)
+            .ok_or(AmmError::CalculationFailure)?;
+        AmmEvent::MultiSwap(MultiSwapEvent {
+            pool: *swap_info.key,
+            user: *user_transfer_authority_info.key,
+            source_index: source_index as u8,
+            destination_index: destination_index as u8,
+            amount_in: to_u64(result.source_amount_swapped)?,
+            amount_out: to_u64(result.destination_amount_swapped)?,
+            owner_fee: to_u64(result.owner_fee)?,
+            reserve_in: to_u64(reserve_in)?,
+            reserve_out: to_u64(reserve_out)?,
+        })
+        .emit();
+        Ok(())
+    }
 
-    struct TestSyscallStubs {}
-    impl program_stubs::SyscallStubs for TestSyscallStubs {
-        fn sol_invoke_signed(
-            &self,
-            instruction: &Instruction,
-            account_infos: &[AccountInfo],
-            signers_seeds: &[&[&[u8]]],
-        ) -> ProgramResult {
-            msg!("TestSyscallStubs::sol_invoke_signed()");
+    /// Processes a [MultiDepositAllTokenTypes](enum.Instruction.html).
+    pub fn process_multi_deposit_all_token_types(
+        program_id: &Pubkey,
+        pool_token_amount: u64,
+        maximum_token_amounts: &[u64],
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let dest_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let token_pair_infos = account_info_iter.as_slice();
 
-            let mut new_account_infos = vec![];
+        //validate account
+        let multi_swap = Self::unpack_multi_swap(program_id, swap_info, authority_info)?;
+        if !multi_swap.status.can_deposit() {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if *pool_mint_info.key != multi_swap.pool_mint {
+            return Err(AmmError::IncorrectPoolMint.into());
+        }
+        if *token_program_info.key != multi_swap.token_program_id {
+            return Err(AmmError::IncorrectTokenProgramId.into());
+        }
+        let source_infos: Vec<&AccountInfo> =
+            token_pair_infos.iter().step_by(MULTI_TOKEN_ACCOUNTS).collect();
+        let token_infos: Vec<&AccountInfo> = token_pair_infos
+            .iter()
+            .skip(1)
+            .step_by(MULTI_TOKEN_ACCOUNTS)
+            .collect();
+        if source_infos.len() != token_infos.len()
+            || maximum_token_amounts.len() != token_infos.len()
+        {
+            return Err(AmmError::InvalidTokenCount.into());
+        }
+        let swap_token_amounts = Self::multi_pool_reserves(&multi_swap, &token_infos)?;
+        Self::update_multi_price_oracle(swap_info, &swap_token_amounts)?;
+        if source_infos
+            .iter()
+            .any(|source_info| multi_swap.reserve_accounts().contains(source_info.key))
+        {
+            return Err(AmmError::InvalidInput.into());
+        }
 
-            // mimic check for token program in accounts
-            if !account_infos.iter().any(|x| *x.key == spl_token::id()) {
-                return Err(ProgramError::InvalidAccountData);
-            }
+        let calculator = &multi_swap.swap_curve.calculator;
+        let pool_mint = Self::unpack_mint(pool_mint_info, &multi_swap.token_program_id)?;
+        let current_pool_mint_supply = to_u128(pool_mint.supply)?;
+        let (pool_token_amount, pool_mint_supply) = if current_pool_mint_supply > 0 {
+            (to_u128(pool_token_amount)?, current_pool_mint_supply)
+        } else {
+            (calculator.new_pool_supply(), calculator.new_pool_supply())
+        };
 
-            for meta in instruction.accounts.iter() {
-                for account_info in account_infos.iter() {
-                    if meta.pubkey == *account_info.key {
-                        let mut new_account_info = account_info.clone();
-                        for seeds in signers_seeds.iter() {
-                            let signer =
-                                Pubkey::create_program_address(seeds, &SWAP_PROGRAM_ID).unwrap();
-                            if *account_info.key == signer {
-                                new_account_info.is_signer = true;
-                            }
-                        }
-                        new_account_infos.push(new_account_info);
-                    }
+        let token_amounts = calculator
+            .pool_tokens_to_trading_tokens(
+                pool_token_amount,
+                pool_mint_supply,
+                &swap_token_amounts,
+                RoundDirection::Ceiling,
+            )
+            .ok_or(AmmError::ZeroTradingTokens)?;
+        let token_amounts = token_amounts
+            .into_iter()
+            .zip(maximum_token_amounts)
+            .map(|(token_amount, maximum_token_amount)| {
+                let token_amount = to_u64(token_amount)?;
+                if token_amount > *maximum_token_amount {
+                    return Err(AmmError::ExceededSlippage.into());
                 }
-            }
+                if token_amount == 0 {
+                    return Err(AmmError::ZeroTradingTokens.into());
+                }
+                Ok(token_amount)
+            })
+            .collect::<Result<Vec<u64>, ProgramError>>()?;
 
-            spl_token::processor::Processor::process(
-                &instruction.program_id,
-                &new_account_infos,
-                &instruction.data,
-            )
+        //transfer tokens to pool
+        for ((source_info, token_info), token_amount) in
+            source_infos.iter().zip(&token_infos).zip(token_amounts.iter().copied())
+        {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                (*source_info).clone(),
+                (*token_info).clone(),
+                user_transfer_authority_info.clone(),
+                multi_swap.nonce,
+                token_amount,
+            )?;
         }
-    }
-
-    fn test_syscall_stubs() {
-        use std::sync::Once;
-        static ONCE: Once = Once::new();
+        //mint lp token to wallet
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            dest_info.clone(),
+            authority_info.clone(),
+            multi_swap.nonce,
+            to_u64(pool_token_amount)?,
+        )?;
 
-        ONCE.call_once(|| {
-            program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs {}));
-        });
+        Self::record_multi_stats(swap_info, MultiPoolStats::record_deposit)?;
+        AmmEvent::MultiDeposit(Self::multi_liquidity_event(
+            swap_info,
+            user_transfer_authority_info,
+            &multi_swap,
+            &token_infos,
+            &token_amounts,
+            to_u64(pool_token_amount)?,
+        )?)
+        .emit();
+        Ok(())
     }
 
-    struct SwapAccountInfo {
-        nonce: u8,
-        authority_key: Pubkey,
-        fees: Fees,
-        swap_curve: SwapCurve,
-        swap_key: Pubkey,
-        swap_account: Account,
-        pool_mint_key: Pubkey,
-        pool_mint_account: Account,
-        fee_a_key: Pubkey,
-        fixed_fee_account_a: Account,
-        fee_b_key: Pubkey,
-        fixed_fee_account_b: Account,
-        pool_token_key: Pubkey,
-        pool_token_account: Account,
-        token_a_key: Pubkey,
-        token_a_account: Account,
-        token_a_mint_key: Pubkey,
-        token_a_mint_account: Account,
-        token_b_key: Pubkey,
-        token_b_account: Account,
-        token_b_mint_key: Pubkey,
-        token_b_mint_account: Account,
-    }
-
-    impl SwapAccountInfo {
-        pub fn new(
-            user_key: &Pubkey,
-            fees: Fees,
-            swap_curve: SwapCurve,
-            token_a_amount: u64,
-            token_b_amount: u64,
-        ) -> Self {
-            let swap_key = Pubkey::new_unique();
-            let swap_account = Account::new(0, SwapVersion::LATEST_LEN, &SWAP_PROGRAM_ID);
-            let (authority_key, nonce) =
-                Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
-
-            let (pool_mint_key, mut pool_mint_account) =
-                create_mint(&spl_token::id(), &authority_key, None);
-            let (pool_token_key, pool_token_account) = mint_token(
-                &spl_token::id(),
-                &pool_mint_key,
-                &mut pool_mint_account,
-                &authority_key,
-                user_key,
-                0,
-            );
-
-            let (token_a_mint_key, mut token_a_mint_account) =
-            create_mint(&spl_token::id(), user_key, None);
-            let (token_a_key, token_a_account) = mint_token(
-                &spl_token::id(),
-                &token_a_mint_key,
-                &mut token_a_mint_account,
-                user_key,
-                &authority_key,
-                token_a_amount,
-            );
-            let (fee_a_key, fixed_fee_account_a) = mint_token(
-                &spl_token::id(),
-                &token_a_mint_key,
-                &mut pool_mint_account,
-                &authority_key,
-                user_key,
-                0,
-            );
+    /// Processes a [MultiWithdrawAllTokenTypes](enum.Instruction.html).
+    pub fn process_multi_withdraw_all_token_types(
+        program_id: &Pubkey,
+        pool_token_amount: u64,
+        minimum_token_amounts: &[u64],
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let token_pair_infos = account_info_iter.as_slice();
 
-            let (token_b_mint_key, mut token_b_mint_account) =
-                create_mint(&spl_token::id(), user_key, None);
-            let (token_b_key, token_b_account) = mint_token(
-                &spl_token::id(),
-                &token_b_mint_key,
-                &mut token_b_mint_account,
-                user_key,
-                &authority_key,
-                token_b_amount,
-            );
-            let (fee_b_key, fixed_fee_account_b) = mint_token(
-                &spl_token::id(),
-                &token_b_mint_key,
-                &mut pool_mint_account,
-                &authority_key,
-                user_key,
-                0,
-            );
-            SwapAccountInfo {
-                nonce,
-                authority_key,
-                fees,
-                swap_curve,
-                swap_key,
-                swap_account,
-                pool_mint_key,
-                pool_mint_account,
-                fee_a_key,
-                fixed_fee_account_a,
-                fee_b_key,
-                fixed_fee_account_b,
-                pool_token_key,
-                pool_token_account,
-                token_a_key,
-                token_a_account,
-                token_a_mint_key,
-                token_a_mint_account,
-                token_b_key,
-                token_b_account,
-                token_b_mint_key,
-                token_b_mint_account,
-            }
+        //validate accounts
+        let multi_swap = Self::unpack_multi_swap(program_id, swap_info, authority_info)?;
+        if !multi_swap.status.can_withdraw() {
+            return Err(AmmError::PoolPaused.into());
         }
-
-        pub fn initialize_swap(&mut self) -> ProgramResult {
-            do_process_instruction(
-                initialize(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    &self.pool_mint_key,
-                    &self.fee_a_key,
-                    &self.fee_b_key,
-                    &self.pool_token_key,
-                    self.nonce,
-                    self.fees.clone(),
-                    self.swap_curve.clone(),
-                )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    &mut self.pool_mint_account,
-                    &mut self.fixed_fee_account_a,
-                    &mut self.fixed_fee_account_b,
-                    &mut self.pool_token_account,
-                    &mut Account::default(),
-                ],
-            )
+        if *pool_mint_info.key != multi_swap.pool_mint {
+            return Err(AmmError::IncorrectPoolMint.into());
+        }
+        if *token_program_info.key != multi_swap.token_program_id {
+            return Err(AmmError::IncorrectTokenProgramId.into());
+        }
+        let token_infos: Vec<&AccountInfo> =
+            token_pair_infos.iter().step_by(MULTI_TOKEN_ACCOUNTS).collect();
+        let dest_infos: Vec<&AccountInfo> = token_pair_infos
+            .iter()
+            .skip(1)
+            .step_by(MULTI_TOKEN_ACCOUNTS)
+            .collect();
+        if dest_infos.len() != token_infos.len()
+            || minimum_token_amounts.len() != token_infos.len()
+        {
+            return Err(AmmError::InvalidTokenCount.into());
+        }
+        let swap_token_amounts = Self::multi_pool_reserves(&multi_swap, &token_infos)?;
+        Self::update_multi_price_oracle(swap_info, &swap_token_amounts)?;
+        if dest_infos
+            .iter()
+            .any(|dest_info| multi_swap.reserve_accounts().contains(dest_info.key))
+        {
+            return Err(AmmError::InvalidInput.into());
         }
 
-        pub fn setup_token_accounts(
-            &mut self,
-            mint_owner: &Pubkey,
-            account_owner: &Pubkey,
-            a_amount: u64,
-            b_amount: u64,
-            pool_amount: u64,
-        ) -> (Pubkey, Account, Pubkey, Account, Pubkey, Account) {
-            let (token_a_key, token_a_account) = mint_token(
-                &spl_token::id(),
-                &self.token_a_mint_key,
-                &mut self.token_a_mint_account,
-                mint_owner,
-                account_owner,
-                a_amount,
-            );
-            let (token_b_key, token_b_account) = mint_token(
-                &spl_token::id(),
-                &self.token_b_mint_key,
-                &mut self.token_b_mint_account,
-                mint_owner,
-                account_owner,
-                b_amount,
-            );
-            let (pool_key, pool_account) = mint_token(
-                &spl_token::id(),
-                &self.pool_mint_key,
-                &mut self.pool_mint_account,
-                &self.authority_key,
-                account_owner,
-                pool_amount,
-            );
-            (
-                token_a_key,
-                token_a_account,
-                token_b_key,
-                token_b_account,
-                pool_key,
-                pool_account,
+        let pool_mint = Self::unpack_mint(pool_mint_info, &multi_swap.token_program_id)?;
+        let token_amounts = multi_swap
+            .swap_curve
+            .calculator
+            .pool_tokens_to_trading_tokens(
+                to_u128(pool_token_amount)?,
+                to_u128(pool_mint.supply)?,
+                &swap_token_amounts,
+                RoundDirection::Floor,
             )
-        }
+            .ok_or(AmmError::ZeroTradingTokens)?;
+        let token_amounts = token_amounts
+            .into_iter()
+            .zip(&swap_token_amounts)
+            .zip(minimum_token_amounts)
+            .map(|((token_amount, swap_token_amount), minimum_token_amount)| {
+                let token_amount = to_u64(std::cmp::min(token_amount, *swap_token_amount))?;
+                if token_amount < *minimum_token_amount {
+                    return Err(AmmError::ExceededSlippage.into());
+                }
+                if token_amount == 0 && *swap_token_amount != 0 {
+                    return Err(AmmError::ZeroTradingTokens.into());
+                }
+                Ok(token_amount)
+            })
+            .collect::<Result<Vec<u64>, ProgramError>>()?;
 
-        fn get_token_account(&self, account_key: &Pubkey) -> &Account {
-            if *account_key == self.token_a_key {
-                return &self.token_a_account;
-            } else if *account_key == self.token_b_key {
-                return &self.token_b_account;
+        //remove lp token from wallet
+        Self::token_burn(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            pool_mint_info.clone(),
+            user_transfer_authority_info.clone(),
+            multi_swap.nonce,
+            pool_token_amount,
+        )?;
+        //transfer tokens to wallet
+        for ((token_info, dest_info), token_amount) in
+            token_infos.iter().zip(&dest_infos).zip(token_amounts.iter().copied())
+        {
+            if token_amount > 0 {
+                Self::token_transfer(
+                    swap_info.key,
+                    token_program_info.clone(),
+                    (*token_info).clone(),
+                    (*dest_info).clone(),
+                    authority_info.clone(),
+                    multi_swap.nonce,
+                    token_amount,
+                )?;
             }
-            panic!("Could not find matching swap token account");
         }
+        Self::record_multi_stats(swap_info, MultiPoolStats::record_withdraw)?;
+        AmmEvent::MultiWithdraw(Self::multi_liquidity_event(
+            swap_info,
+            user_transfer_authority_info,
+            &multi_swap,
+            &token_infos,
+            &token_amounts,
+            pool_token_amount,
+        )?)
+        .emit();
+        Ok(())
+    }
 
-        fn set_token_account(&mut self, account_key: &Pubkey, account: Account) {
-            if *account_key == self.token_a_key {
-                self.token_a_account = account;
-                return;
-            } else if *account_key == self.token_b_key {
-                self.token_b_account = account;
-                return;
-            }
-            panic!("Could not find matching swap token account");
+    /// Processes a [MultiDepositSingleTokenTypeExactAmountIn](enum.Instruction.html).
+    pub fn process_multi_deposit_single_token_type_exact_amount_in(
+        program_id: &Pubkey,
+        source_token_amount: u64,
+        minimum_pool_token_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let token_infos: Vec<&AccountInfo> = account_info_iter.collect();
+
+        //validate account info
+        let multi_swap = Self::unpack_multi_swap(program_id, swap_info, authority_info)?;
+        if !multi_swap.status.can_deposit() {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if *pool_mint_info.key != multi_swap.pool_mint {
+            return Err(AmmError::IncorrectPoolMint.into());
+        }
+        if *token_program_info.key != multi_swap.token_program_id {
+            return Err(AmmError::IncorrectTokenProgramId.into());
         }
+        let swap_token_amounts = Self::multi_pool_reserves(&multi_swap, &token_infos)?;
+        Self::update_multi_price_oracle(swap_info, &swap_token_amounts)?;
+        if multi_swap.reserve_accounts().contains(source_info.key) {
+            return Err(AmmError::InvalidInput.into());
+        }
+        let source = Self::unpack_token_account(source_info, &multi_swap.token_program_id)?;
+        let token_index = Self::multi_token_index(&multi_swap, &source.mint)?;
 
-        #[allow(clippy::too_many_arguments)]
-        pub fn swap(
-            &mut self,
-            user_key: &Pubkey,
-            user_source_key: &Pubkey,
-            mut user_source_account: &mut Account,
-            swap_source_key: &Pubkey,
-            swap_destination_key: &Pubkey,
-            user_destination_key: &Pubkey,
-            mut user_destination_account: &mut Account,
-            amount_in: u64,
-            minimum_amount_out: u64,
-        ) -> ProgramResult {
-            let user_transfer_key = Pubkey::new_unique();
-            // approve moving from user source account
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    user_source_key,
-                    &user_transfer_key,
-                    user_key,
-                    &[],
-                    amount_in,
+        let pool_mint = Self::unpack_mint(pool_mint_info, &multi_swap.token_program_id)?;
+        let pool_mint_supply = to_u128(pool_mint.supply)?;
+        let pool_token_amount = if pool_mint_supply > 0 {
+            multi_swap
+                .swap_curve
+                .deposit_single_token_type(
+                    to_u128(source_token_amount)?,
+                    &swap_token_amounts,
+                    token_index,
+                    pool_mint_supply,
+                    &multi_swap.fees,
                 )
-                .unwrap(),
-                vec![
-                    &mut user_source_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
+                .ok_or(AmmError::ZeroTradingTokens)?
+        } else {
+            multi_swap.swap_curve.calculator.new_pool_supply()
+        };
 
-            let mut swap_source_account = self.get_token_account(swap_source_key).clone();
-            let mut swap_destination_account = self.get_token_account(swap_destination_key).clone();
+        let pool_token_amount = to_u64(pool_token_amount)?;
+        if pool_token_amount < minimum_pool_token_amount {
+            return Err(AmmError::ExceededSlippage.into());
+        }
+        if pool_token_amount == 0 {
+            return Err(AmmError::ZeroTradingTokens.into());
+        }
 
-            // perform the swap
-            do_process_instruction(
-                swap(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_key,
-                    user_source_key,
-                    swap_source_key,
-                    swap_destination_key,
-                    user_destination_key,
-                    &self.pool_mint_key,
-                    &self.fee_a_key,
-                    None,
-                    SwapInstruction {
-                        amount_in,
-                        minimum_amount_out,
-                    },
-                )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    &mut user_source_account,
-                    &mut swap_source_account,
-                    &mut swap_destination_account,
-                    &mut user_destination_account,
-                    &mut self.pool_mint_account,
-                    &mut self.fixed_fee_account_a,
-                    &mut self.fixed_fee_account_b,
-                    &mut Account::default(),
-                ],
-            )?;
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            token_infos[token_index].clone(),
+            user_transfer_authority_info.clone(),
+            multi_swap.nonce,
+            source_token_amount,
+        )?;
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            multi_swap.nonce,
+            pool_token_amount,
+        )?;
 
-            self.set_token_account(swap_source_key, swap_source_account);
-            self.set_token_account(swap_destination_key, swap_destination_account);
+        Self::record_multi_stats(swap_info, MultiPoolStats::record_deposit)?;
+        let mut token_amounts = vec![0; token_infos.len()];
+        token_amounts[token_index] = source_token_amount;
+        AmmEvent::MultiDeposit(Self::multi_liquidity_event(
+            swap_info,
+            user_transfer_authority_info,
+            &multi_swap,
+            &token_infos,
+            &token_amounts,
+            pool_token_amount,
+        )?)
+        .emit();
+        Ok(())
+    }
 
-            Ok(())
-        }
+    /// Processes a [MultiWithdrawSingleTokenTypeExactAmountOut](enum.Instruction.html).
+    pub fn process_multi_withdraw_single_token_type_exact_amount_out(
+        program_id: &Pubkey,
+        destination_token_amount: u64,
+        maximum_pool_token_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let token_infos: Vec<&AccountInfo> = account_info_iter.collect();
 
-        #[allow(clippy::too_many_arguments)]
-        pub fn deposit_all_token_types(
-            &mut self,
-            depositor_key: &Pubkey,
-            depositor_token_a_key: &Pubkey,
-            mut depositor_token_a_account: &mut Account,
-            depositor_token_b_key: &Pubkey,
-            mut depositor_token_b_account: &mut Account,
-            depositor_pool_key: &Pubkey,
-            mut depositor_pool_account: &mut Account,
-            pool_token_amount: u64,
-            maximum_token_a_amount: u64,
-            maximum_token_b_amount: u64,
-        ) -> ProgramResult {
-            let user_transfer_authority = Pubkey::new_unique();
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    depositor_token_a_key,
-                    &user_transfer_authority,
-                    depositor_key,
-                    &[],
-                    maximum_token_a_amount,
-                )
-                .unwrap(),
-                vec![
-                    &mut depositor_token_a_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
+        //validate account info
+        let multi_swap = Self::unpack_multi_swap(program_id, swap_info, authority_info)?;
+        if !multi_swap.status.can_withdraw() {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if *pool_mint_info.key != multi_swap.pool_mint {
+            return Err(AmmError::IncorrectPoolMint.into());
+        }
+        if *token_program_info.key != multi_swap.token_program_id {
+            return Err(AmmError::IncorrectTokenProgramId.into());
+        }
+        let swap_token_amounts = Self::multi_pool_reserves(&multi_swap, &token_infos)?;
+        Self::update_multi_price_oracle(swap_info, &swap_token_amounts)?;
+        if multi_swap.reserve_accounts().contains(destination_info.key) {
+            return Err(AmmError::InvalidInput.into());
+        }
+        let destination =
+            Self::unpack_token_account(destination_info, &multi_swap.token_program_id)?;
+        let token_index = Self::multi_token_index(&multi_swap, &destination.mint)?;
 
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    depositor_token_b_key,
-                    &user_transfer_authority,
-                    depositor_key,
-                    &[],
-                    maximum_token_b_amount,
-                )
-                .unwrap(),
-                vec![
-                    &mut depositor_token_b_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
+        let pool_mint = Self::unpack_mint(pool_mint_info, &multi_swap.token_program_id)?;
+        let pool_token_amount = multi_swap
+            .swap_curve
+            .withdraw_single_token_type_exact_out(
+                to_u128(destination_token_amount)?,
+                &swap_token_amounts,
+                token_index,
+                to_u128(pool_mint.supply)?,
+                &multi_swap.fees,
             )
-            .unwrap();
+            .ok_or(AmmError::ZeroTradingTokens)?;
 
-            do_process_instruction(
-                deposit_all_token_types(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_authority,
-                    depositor_token_a_key,
-                    depositor_token_b_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    &self.pool_mint_key,
-                    depositor_pool_key,
-                    DepositAllTokenTypes {
-                        pool_token_amount,
-                        maximum_token_a_amount,
-                        maximum_token_b_amount,
-                    },
-                )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    &mut depositor_token_a_account,
-                    &mut depositor_token_b_account,
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    &mut self.pool_mint_account,
-                    &mut depositor_pool_account,
-                    &mut Account::default(),
-                ],
-            )
+        let pool_token_amount = to_u64(pool_token_amount)?;
+        if pool_token_amount > maximum_pool_token_amount {
+            return Err(AmmError::ExceededSlippage.into());
+        }
+        if pool_token_amount == 0 {
+            return Err(AmmError::ZeroTradingTokens.into());
         }
 
-        #[allow(clippy::too_many_arguments)]
-        pub fn withdraw_all_token_types(
-            &mut self,
-            user_key: &Pubkey,
-            pool_key: &Pubkey,
-            mut pool_account: &mut Account,
-            token_a_key: &Pubkey,
-            mut token_a_account: &mut Account,
-            token_b_key: &Pubkey,
-            mut token_b_account: &mut Account,
-            pool_token_amount: u64,
-            minimum_token_a_amount: u64,
-            minimum_token_b_amount: u64,
-        ) -> ProgramResult {
-            let user_transfer_authority_key = Pubkey::new_unique();
-            // approve user transfer authority to take out pool tokens
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    pool_key,
-                    &user_transfer_authority_key,
-                    user_key,
-                    &[],
-                    pool_token_amount,
-                )
-                .unwrap(),
-                vec![
-                    &mut pool_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
+        Self::token_burn(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            pool_mint_info.clone(),
+            user_transfer_authority_info.clone(),
+            multi_swap.nonce,
+            pool_token_amount,
+        )?;
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            token_infos[token_index].clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            multi_swap.nonce,
+            destination_token_amount,
+        )?;
 
-            // withdraw token a and b correctly
-            do_process_instruction(
-                withdraw_all_token_types(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_authority_key,
-                    &self.pool_mint_key,
-                    pool_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    token_a_key,
-                    token_b_key,
-                    WithdrawAllTokenTypes {
-                        pool_token_amount,
-                        minimum_token_a_amount,
-                        minimum_token_b_amount,
-                    },
-                )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    &mut self.pool_mint_account,
-                    &mut pool_account,
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    &mut token_a_account,
-                    &mut token_b_account,
-                    &mut self.fixed_fee_account_a,
-                    &mut self.fixed_fee_account_b,
-                    &mut Account::default(),
-                ],
-            )
-        }
-
-        #[allow(clippy::too_many_arguments)]
-        pub fn deposit_single_token_type_exact_amount_in(
-            &mut self,
-            depositor_key: &Pubkey,
-            deposit_account_key: &Pubkey,
-            mut deposit_token_account: &mut Account,
-            deposit_pool_key: &Pubkey,
-            mut deposit_pool_account: &mut Account,
-            source_token_amount: u64,
-            minimum_pool_token_amount: u64,
-        ) -> ProgramResult {
-            let user_transfer_authority_key = Pubkey::new_unique();
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    deposit_account_key,
-                    &user_transfer_authority_key,
-                    depositor_key,
-                    &[],
-                    source_token_amount,
-                )
-                .unwrap(),
-                vec![
-                    &mut deposit_token_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
+        Self::record_multi_stats(swap_info, MultiPoolStats::record_withdraw)?;
+        let mut token_amounts = vec![0; token_infos.len()];
+        token_amounts[token_index] = destination_token_amount;
+        AmmEvent::MultiWithdraw(Self::multi_liquidity_event(
+            swap_info,
+            user_transfer_authority_info,
+            &multi_swap,
+            &token_infos,
+            &token_amounts,
+            pool_token_amount,
+        )?)
+        .emit();
+        Ok(())
+    }
 
-            do_process_instruction(
-                deposit_single_token_type_exact_amount_in(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_authority_key,
-                    deposit_account_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    &self.pool_mint_key,
-                    deposit_pool_key,
-                    DepositSingleTokenTypeExactAmountIn {
-                        source_token_amount,
-                        minimum_pool_token_amount,
-                    },
-                )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    &mut deposit_token_account,
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    &mut self.pool_mint_account,
-                    &mut deposit_pool_account,
-                    &mut Account::default(),
-                ],
-            )
+    /// Unpacks a concentrated liquidity pool, checking the pool account and
+    /// its authority
+    fn unpack_concentrated_pool(
+        program_id: &Pubkey,
+        pool_info: &AccountInfo,
+        authority_info: &AccountInfo,
+    ) -> Result<ConcentratedPool, ProgramError> {
+        if pool_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
         }
-
-        #[allow(clippy::too_many_arguments)]
-        pub fn withdraw_single_token_type_exact_amount_out(
-            &mut self,
-            user_key: &Pubkey,
-            pool_key: &Pubkey,
-            mut pool_account: &mut Account,
-            destination_key: &Pubkey,
-            mut destination_account: &mut Account,
-            destination_token_amount: u64,
-            maximum_pool_token_amount: u64,
-        ) -> ProgramResult {
-            let user_transfer_authority_key = Pubkey::new_unique();
-            // approve user transfer authority to take out pool tokens
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    pool_key,
-                    &user_transfer_authority_key,
-                    user_key,
-                    &[],
-                    maximum_pool_token_amount,
-                )
-                .unwrap(),
-                vec![
-                    &mut pool_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
-
-            do_process_instruction(
-                withdraw_single_token_type_exact_amount_out(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_authority_key,
-                    &self.pool_mint_key,
-                    pool_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    destination_key,
-                    WithdrawSingleTokenTypeExactAmountOut {
-                        destination_token_amount,
-                        maximum_pool_token_amount,
-                    },
-                )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    &mut self.pool_mint_account,
-                    &mut pool_account,
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    &mut destination_account,
-                    &mut self.fixed_fee_account,
-                    &mut Account::default(),
-                ],
-            )
+        let pool = ConcentratedPool::unpack_account(&pool_info.data.borrow())?;
+        if *authority_info.key != Self::authority_id(program_id, pool_info.key, pool.nonce)? {
+            return Err(AmmError::InvalidProgramAddress.into());
         }
+        Ok(pool)
     }
 
-    fn mint_minimum_balance() -> u64 {
-        Rent::default().minimum_balance(spl_token::state::Mint::get_packed_len())
-    }
-
-    fn account_minimum_balance() -> u64 {
-        Rent::default().minimum_balance(spl_token::state::Account::get_packed_len())
+    /// Unpacks a position of the pool, checking that its owner signed
+    fn unpack_position(
+        program_id: &Pubkey,
+        position_info: &AccountInfo,
+        pool_info: &AccountInfo,
+        owner_info: &AccountInfo,
+    ) -> Result<Position, ProgramError> {
+        if position_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let position = Position::unpack_account(&position_info.data.borrow())?;
+        if position.pool != *pool_info.key || position.owner != *owner_info.key {
+            return Err(AmmError::IncorrectPositionAccount.into());
+        }
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Ok(position)
     }
 
-    fn do_process_instruction_with_fee_constraints(
-        instruction: Instruction,
-        accounts: Vec<&mut Account>,
+    /// Processes an [InitializeConcentrated](enum.Instruction.html).
+    pub fn process_initialize_concentrated(
+        program_id: &Pubkey,
+        nonce: u8,
+        fees: Fees,
+        tick_spacing: u16,
+        sqrt_price: u128,
+        accounts: &[AccountInfo],
         swap_constraints: &Option<SwapConstraints>,
     ) -> ProgramResult {
-        test_syscall_stubs();
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let pool_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let fixed_fee_account_a_info = next_account_info(account_info_iter)?;
+        let fixed_fee_account_b_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
 
-        // approximate the logic in the actual runtime which runs the instruction
-        // and only updates accounts if the instruction is successful
-        let mut account_clones = accounts.iter().map(|x| (*x).clone()).collect::<Vec<_>>();
-        let mut meta = instruction
-            .accounts
-            .iter()
-            .zip(account_clones.iter_mut())
-            .map(|(account_meta, account)| (&account_meta.pubkey, account_meta.is_signer, account))
-            .collect::<Vec<_>>();
-        let mut account_infos = create_is_signer_account_infos(&mut meta);
-        let res = if instruction.program_id == SWAP_PROGRAM_ID {
-            Processor::process_with_constraints(
-                &instruction.program_id,
-                &account_infos,
-                &instruction.data,
-                swap_constraints,
-            )
-        } else {
-            spl_token::processor::Processor::process(
-                &instruction.program_id,
-                &account_infos,
-                &instruction.data,
-            )
-        };
+        //validate account info
+        let token_program_id = *token_program_info.key;
+        if SwapVersion::is_initialized(&pool_info.data.borrow()) {
+            return Err(AmmError::AlreadyInUse.into());
+        }
+        if *authority_info.key != Self::authority_id(program_id, pool_info.key, nonce)? {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        let token_a = Self::unpack_token_account(token_a_info, &token_program_id)?;
+        let token_b = Self::unpack_token_account(token_b_info, &token_program_id)?;
+        if *authority_info.key != token_a.owner || *authority_info.key != token_b.owner {
+            return Err(AmmError::InvalidOwner.into());
+        }
+        if token_a.delegate.is_some() || token_b.delegate.is_some() {
+            return Err(AmmError::InvalidDelegate.into());
+        }
+        if token_a.close_authority.is_some() || token_b.close_authority.is_some() {
+            return Err(AmmError::InvalidCloseAuthority.into());
+        }
+        if token_a.mint == token_b.mint {
+            return Err(AmmError::RepeatedMint.into());
+        }
 
-        if res.is_ok() {
-            let mut account_metas = instruction
-                .accounts
-                .iter()
-                .zip(accounts)
-                .map(|(account_meta, account)| (&account_meta.pubkey, account))
-                .collect::<Vec<_>>();
-            for account_info in account_infos.iter_mut() {
-                for account_meta in account_metas.iter_mut() {
-                    if account_info.key == account_meta.0 {
-                        let account = &mut account_meta.1;
-                        account.owner = *account_info.owner;
-                        account.lamports = **account_info.lamports.borrow();
-                        account.data = account_info.data.borrow().to_vec();
-                    }
+        //check the fee accounts are set correctly
+        let fixed_fee_acc_owner = Self::unpack_config(program_id, config_info)?.fee_wallet;
+        for (fee_account_info, mint) in &[
+            (fixed_fee_account_a_info, token_a.mint),
+            (fixed_fee_account_b_info, token_b.mint),
+        ] {
+            if fixed_fee_acc_owner != *fee_account_info.key {
+                let fee_account = Self::unpack_token_account(fee_account_info, &token_program_id)?;
+                if fixed_fee_acc_owner != fee_account.owner || *mint != fee_account.mint {
+                    return Err(AmmError::IncorrectFeeAccount.into());
                 }
             }
         }
-        res
-    }
-
-    fn do_process_instruction(
-        instruction: Instruction,
-        accounts: Vec<&mut Account>,
-    ) -> ProgramResult {
-        do_process_instruction_with_fee_constraints(instruction, accounts, &SWAP_CONSTRAINTS)
-    }
 
-    fn mint_token(
-        program_id: &Pubkey,
-        mint_key: &Pubkey,
-        mut mint_account: &mut Account,
-        mint_authority_key: &Pubkey,
-        account_owner_key: &Pubkey,
-        amount: u64,
-    ) -> (Pubkey, Account) {
-        let account_key = Pubkey::new_unique();
-        let mut account_account = Account::new(
-            account_minimum_balance(),
-            spl_token::state::Account::get_packed_len(),
-            program_id,
-        );
-        let mut mint_authority_account = Account::default();
-        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
-
-        do_process_instruction(
-            initialize_account(program_id, &account_key, mint_key, account_owner_key).unwrap(),
-            vec![
-                &mut account_account,
-                &mut mint_account,
-                &mut mint_authority_account,
-                &mut rent_sysvar_account,
-            ],
-        )
-        .unwrap();
-
-        if amount > 0 {
-            do_process_instruction(
-                mint_to(
-                    program_id,
-                    mint_key,
-                    &account_key,
-                    mint_authority_key,
-                    &[],
-                    amount,
-                )
-                .unwrap(),
-                vec![
-                    &mut mint_account,
-                    &mut account_account,
-                    &mut mint_authority_account,
-                ],
-            )
-            .unwrap();
+        if let Some(swap_constraints) = swap_constraints {
+            swap_constraints.validate_fees(&fees)?;
+        }
+        fees.validate()?;
+        if tick_spacing == 0 || tick_spacing > MAX_TICK_SPACING {
+            return Err(AmmError::InvalidCurve.into());
+        }
+        let tick_current =
+            tick_at_sqrt_price(sqrt_price, MIN_TICK, MAX_TICK).ok_or(AmmError::InvalidCurve)?;
+        if tick_current == MAX_TICK {
+            return Err(AmmError::InvalidCurve.into());
         }
 
-        (account_key, account_account)
+        //Save the pool account info
+        let obj = ConcentratedPool {
+            is_initialized: true,
+            nonce,
+            token_program_id,
+            token_a: *token_a_info.key,
+            token_b: *token_b_info.key,
+            token_a_mint: token_a.mint,
+            token_b_mint: token_b.mint,
+            fixed_fee_account_a: *fixed_fee_account_a_info.key,
+            fixed_fee_account_b: *fixed_fee_account_b_info.key,
+            fees,
+            status: PoolStatus::Active,
+            tick_spacing,
+            sqrt_price,
+            tick_current,
+            liquidity: 0,
+            fee_growth_global_a: 0,
+            fee_growth_global_b: 0,
+            tick_count: 0,
+            ticks: [Tick::default(); MAX_TICKS],
+            stats: PoolStats::default(),
+            price_oracle: PriceOracle::new(Clock::get()?.unix_timestamp),
+        };
+        ConcentratedPool::pack_account(obj, &mut pool_info.data.borrow_mut())?;
+        Ok(())
     }
 
-    fn create_mint(
+    /// Processes an [OpenPosition](enum.Instruction.html).
+    pub fn process_open_position(
         program_id: &Pubkey,
-        authority_key: &Pubkey,
-        freeze_authority: Option<&Pubkey>,
-    ) -> (Pubkey, Account) {
-        let mint_key = Pubkey::new_unique();
-        let mut mint_account = Account::new(
-            mint_minimum_balance(),
-            spl_token::state::Mint::get_packed_len(),
-            program_id,
-        );
-        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
-
-        do_process_instruction(
-            initialize_mint(program_id, &mint_key, authority_key, freeze_authority, 2).unwrap(),
-            vec![&mut mint_account, &mut rent_sysvar_account],
-        )
-        .unwrap();
-
-        (mint_key, mint_account)
-    }
+        instruction: OpenPositionInstruction,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let OpenPositionInstruction {
+            tick_lower,
+            tick_upper,
+            liquidity,
+            maximum_token_a_amount,
+            maximum_token_b_amount,
+            ..
+        } = instruction;
 
-    #[test]
-    fn test_token_program_id_error() {
-        test_syscall_stubs();
-        let swap_key = Pubkey::new_unique();
-        let mut mint = (Pubkey::new_unique(), Account::default());
-        let mut destination = (Pubkey::new_unique(), Account::default());
-        let token_program = (spl_token::id(), Account::default());
-        let (authority_key, nonce) =
-            Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
-        let mut authority = (authority_key, Account::default());
-        let swap_bytes = swap_key.to_bytes();
-        let authority_signature_seeds = [&swap_bytes[..32], &[nonce]];
-        let signers = &[&authority_signature_seeds[..]];
-        let ix = mint_to(
-            &token_program.0,
-            &mint.0,
-            &destination.0,
-            &authority.0,
-            &[],
-            10,
-        )
-        .unwrap();
-        let mint = (&mut mint).into();
-        let destination = (&mut destination).into();
-        let authority = (&mut authority).into();
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let pool_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let position_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_a_info = next_account_info(account_info_iter)?;
+        let source_b_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
 
-        let err = invoke_signed(&ix, &[mint, destination, authority], signers).unwrap_err();
-        assert_eq!(err, ProgramError::InvalidAccountData);
-    }
+        //validate account info
+        let mut pool = Self::unpack_concentrated_pool(program_id, pool_info, authority_info)?;
+        if !pool.status.can_deposit() {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if *token_program_info.key != pool.token_program_id {
+            return Err(AmmError::IncorrectTokenProgramId.into());
+        }
+        if *token_a_info.key != pool.token_a || *token_b_info.key != pool.token_b {
+            return Err(AmmError::IncorrectSwapAccount.into());
+        }
+        if source_a_info.key == token_a_info.key || source_b_info.key == token_b_info.key {
+            return Err(AmmError::InvalidInput.into());
+        }
+        if position_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if Position::unpack_account(&position_info.data.borrow()).is_ok() {
+            return Err(AmmError::AlreadyInUse.into());
+        }
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        pool.validate_tick_range(tick_lower, tick_upper)?;
+        if liquidity == 0 {
+            return Err(AmmError::InvalidInput.into());
+        }
+        let liquidity_delta =
+            i128::try_from(liquidity).map_err(|_| AmmError::ConversionFailure)?;
 
-    #[test]
-    fn test_initialize() {
-        let user_key = Pubkey::new_unique();
-        let return_fee_numerator = 2;
-        let fixed_fee_numerator = 1;
-        let fee_denominator = 100;
-        let fees = Fees {
-            return_fee_numerator,
-            fixed_fee_numerator,
-            fee_denominator
+        let mut position = Position {
+            is_initialized: true,
+            pool: *pool_info.key,
+            owner: *owner_info.key,
+            tick_lower,
+            tick_upper,
+            ..Position::default()
         };
+        pool.update_position(&mut position, liquidity_delta)?;
+        let (token_a_amount, token_b_amount) =
+            token_amounts(pool.sqrt_price, tick_lower, tick_upper, liquidity, true)
+                .ok_or(AmmError::CalculationFailure)?;
+        let token_a_amount = to_u64(token_a_amount)?;
+        let token_b_amount = to_u64(token_b_amount)?;
+        if token_a_amount > maximum_token_a_amount || token_b_amount > maximum_token_b_amount {
+            return Err(AmmError::ExceededSlippage.into());
+        }
 
-        let token_a_amount = 1000;
-        let token_b_amount = 2000;
-        let pool_token_amount = 10;
-        let curve_type = CurveType::ConstantProduct;
-        let swap_curve = SwapCurve {
-            curve_type,
-            calculator: Box::new(ConstantProductCurve {}),
-        };
+        for (source_info, token_info, amount) in &[
+            (source_a_info, token_a_info, token_a_amount),
+            (source_b_info, token_b_info, token_b_amount),
+        ] {
+            if *amount > 0 {
+                Self::token_transfer(
+                    pool_info.key,
+                    token_program_info.clone(),
+                    (*source_info).clone(),
+                    (*token_info).clone(),
+                    user_transfer_authority_info.clone(),
+                    pool.nonce,
+                    *amount,
+                )?;
+            }
+        }
+        pool.stats.record_deposit();
+        Position::pack_account(position, &mut position_info.data.borrow_mut())?;
+        ConcentratedPool::pack_account(pool, &mut pool_info.data.borrow_mut())?;
+        AmmEvent::OpenPosition(PositionEvent {
+            pool: *pool_info.key,
+            position: *position_info.key,
+            owner: *owner_info.key,
+            tick_lower,
+            tick_upper,
+            liquidity,
+            token_a_amount,
+            token_b_amount,
+        })
+        .emit();
+        Ok(())
+    }
 
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+    /// Processes a [ConcentratedSwap](enum.Instruction.html).
+    pub fn process_concentrated_swap(
+        program_id: &Pubkey,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let pool_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let swap_source_info = next_account_info(account_info_iter)?;
+        let swap_destination_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let fixed_fee_account_info = next_account_info(account_info_iter)?;
+        let fixed_fee_wallet_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
 
-        // wrong nonce for authority_key
-        {
-            let old_nonce = accounts.nonce;
-            accounts.nonce = old_nonce - 1;
-            assert_eq!(
-                Err(AmmError::InvalidProgramAddress.into()),
-                accounts.initialize_swap()
-            );
-            accounts.nonce = old_nonce;
+        //validate account info
+        let mut pool = Self::unpack_concentrated_pool(program_id, pool_info, authority_info)?;
+        if !pool.status.can_swap() {
+            return Err(AmmError::PoolPaused.into());
         }
-
-        // uninitialized token a account
-        {
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = Account::new(0, 0, &spl_token::id());
-            assert_eq!(
-                Err(AmmError::ExpectedAccount.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_a_account = old_account;
+        if *token_program_info.key != pool.token_program_id {
+            return Err(AmmError::IncorrectTokenProgramId.into());
         }
-
-        // uninitialized token b account
+        let a_to_b = if *swap_source_info.key == pool.token_a
+            && *swap_destination_info.key == pool.token_b
         {
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = Account::new(0, 0, &spl_token::id());
-            assert_eq!(
-                Err(AmmError::ExpectedAccount.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
-        }
-
-        // uninitialized pool mint
+            true
+        } else if *swap_source_info.key == pool.token_b
+            && *swap_destination_info.key == pool.token_a
         {
-            let old_account = accounts.pool_mint_account;
-            accounts.pool_mint_account = Account::new(0, 0, &spl_token::id());
-            assert_eq!(
-                Err(AmmError::ExpectedMint.into()),
-                accounts.initialize_swap()
-            );
-            accounts.pool_mint_account = old_account;
+            false
+        } else {
+            return Err(AmmError::IncorrectSwapAccount.into());
+        };
+        if swap_source_info.key == source_info.key
+            || swap_destination_info.key == destination_info.key
+        {
+            return Err(AmmError::InvalidInput.into());
+        }
+        let fixed_fee_account = if a_to_b {
+            pool.fixed_fee_account_a
+        } else {
+            pool.fixed_fee_account_b
+        };
+        if *fixed_fee_account_info.key != fixed_fee_account {
+            return Err(AmmError::IncorrectFeeAccount.into());
+        }
+        let fixed_fee_acc_owner = Self::unpack_config(program_id, config_info)?.fee_wallet;
+        if fixed_fee_acc_owner != *fixed_fee_wallet_info.key {
+            return Err(AmmError::IncorrectFeeAccount.into());
         }
+        let source = Self::unpack_token_account(source_info, &pool.token_program_id)?;
 
-        // token A account owner is not swap authority
-        {
-            let (_token_a_key, token_a_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &user_key,
-                0,
-            );
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = token_a_account;
-            assert_eq!(
-                Err(AmmError::InvalidOwner.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_a_account = old_account;
+        pool.price_oracle
+            .update_sqrt_price(Clock::get()?.unix_timestamp, pool.sqrt_price)
+            .ok_or(AmmError::CalculationFailure)?;
+        let result = pool.swap(to_u128(amount_in)?, a_to_b)?;
+        if result.destination_amount_swapped < to_u128(minimum_amount_out)? {
+            return Err(AmmError::ExceededSlippage.into());
         }
 
-        // token B account owner is not swap authority
-        {
-            let (_token_b_key, token_b_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_b_mint_key,
-                &mut accounts.token_b_mint_account,
-                &user_key,
-                &user_key,
-                0,
-            );
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_b_account;
-            assert_eq!(
-                Err(AmmError::InvalidOwner.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
-        }
+        Self::token_transfer(
+            pool_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            swap_source_info.clone(),
+            user_transfer_authority_info.clone(),
+            pool.nonce,
+            to_u64(result.source_amount_swapped - result.owner_fee)?,
+        )?;
+        Self::pay_fixed_fee(
+            pool_info.key,
+            &TokenSide::plain(token_program_info),
+            system_program_info,
+            user_transfer_authority_info,
+            source_info,
+            fixed_fee_account_info,
+            fixed_fee_wallet_info,
+            &source.mint,
+            pool.nonce,
+            to_u64(result.owner_fee)?,
+        )?;
+        Self::token_transfer(
+            pool_info.key,
+            token_program_info.clone(),
+            swap_destination_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            pool.nonce,
+            to_u64(result.destination_amount_swapped)?,
+        )?;
+        let trade_direction = if a_to_b {
+            TradeDirection::AtoB
+        } else {
+            TradeDirection::BtoA
+        };
+        pool.stats.record_swap(
+            trade_direction,
+            result.source_amount_swapped,
+            result.owner_fee,
+            0,
+            result.trade_fee,
+        );
+        let (token_a_info, token_b_info) = if a_to_b {
+            (swap_source_info, swap_destination_info)
+        } else {
+            (swap_destination_info, swap_source_info)
+        };
+        let reserve_a = Self::unpack_token_account(token_a_info, &pool.token_program_id)?.amount;
+        let reserve_b = Self::unpack_token_account(token_b_info, &pool.token_program_id)?.amount;
+        ConcentratedPool::pack_account(pool, &mut pool_info.data.borrow_mut())?;
+        AmmEvent::Swap(SwapEvent {
+            pool: *pool_info.key,
+            user: *user_transfer_authority_info.key,
+            trade_direction,
+            amount_in: to_u64(result.source_amount_swapped)?,
+            amount_out: to_u64(result.destination_amount_swapped)?,
+            owner_fee: to_u64(result.owner_fee)?,
+            reserve_a,
+            reserve_b,
+        })
+        .emit();
+        Ok(())
+    }
 
-        // pool token account owner is swap authority
-        {
-            let (_pool_token_key, pool_token_account) = mint_token(
-                &spl_token::id(),
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.pool_token_account;
-            accounts.pool_token_account = pool_token_account;
-            assert_eq!(
-                Err(AmmError::InvalidOutputOwner.into()),
-                accounts.initialize_swap()
-            );
-            accounts.pool_token_account = old_account;
-        }
+    /// Processes a [CollectFees](enum.Instruction.html) or a
+    /// [ClosePosition](enum.Instruction.html), which also withdraws all the
+    /// liquidity of the position
+    fn process_position_withdraw(
+        program_id: &Pubkey,
+        close: Option<ClosePositionInstruction>,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let pool_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let position_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let dest_token_a_info = next_account_info(account_info_iter)?;
+        let dest_token_b_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
 
-        // pool fee account owner is swap authority
-        {
-            let (_pool_fee_key, fixed_fee_account) = mint_token(
-                &spl_token::id(),
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.fixed_fee_account_a;
-            accounts.fixed_fee_account_a = fixed_fee_account;
-            assert_eq!(
-                Err(AmmError::InvalidOutputOwner.into()),
-                accounts.initialize_swap()
-            );
-            accounts.fixed_fee_account_a = old_account;
+        //validate account info
+        let mut pool = Self::unpack_concentrated_pool(program_id, pool_info, authority_info)?;
+        if !pool.status.can_withdraw() {
+            return Err(AmmError::PoolPaused.into());
         }
-
-        // pool mint authority is not swap authority
-        {
-            let (_pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &user_key, None);
-            let old_mint = accounts.pool_mint_account;
-            accounts.pool_mint_account = pool_mint_account;
-            assert_eq!(
-                Err(AmmError::InvalidOwner.into()),
-                accounts.initialize_swap()
-            );
-            accounts.pool_mint_account = old_mint;
+        if *token_program_info.key != pool.token_program_id {
+            return Err(AmmError::IncorrectTokenProgramId.into());
         }
-
-        // pool mint token has freeze authority
-        {
-            let (_pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, Some(&user_key));
-            let old_mint = accounts.pool_mint_account;
-            accounts.pool_mint_account = pool_mint_account;
-            assert_eq!(
-                Err(AmmError::InvalidFreezeAuthority.into()),
-                accounts.initialize_swap()
-            );
-            accounts.pool_mint_account = old_mint;
+        if *token_a_info.key != pool.token_a || *token_b_info.key != pool.token_b {
+            return Err(AmmError::IncorrectSwapAccount.into());
         }
-
-        // token A account owned by wrong program
-        {
-            let (_token_a_key, mut token_a_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                token_a_amount,
-            );
-            token_a_account.owner = SWAP_PROGRAM_ID;
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = token_a_account;
-            assert_eq!(
-                Err(AmmError::IncorrectTokenProgramId.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_a_account = old_account;
+        if dest_token_a_info.key == token_a_info.key || dest_token_b_info.key == token_b_info.key {
+            return Err(AmmError::InvalidInput.into());
         }
+        let mut position = Self::unpack_position(program_id, position_info, pool_info, owner_info)?;
 
-        // token B account owned by wrong program
-        {
-            let (_token_b_key, mut token_b_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_b_mint_key,
-                &mut accounts.token_b_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                token_b_amount,
-            );
-            token_b_account.owner = SWAP_PROGRAM_ID;
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_b_account;
-            assert_eq!(
-                Err(AmmError::IncorrectTokenProgramId.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
-        }
+        let (liquidity, token_a_amount, token_b_amount) = match &close {
+            Some(ClosePositionInstruction {
+                minimum_token_a_amount,
+                minimum_token_b_amount,
+                ..
+            }) => {
+                let liquidity = position.liquidity;
+                let liquidity_delta =
+                    i128::try_from(liquidity).map_err(|_| AmmError::ConversionFailure)?;
+                pool.update_position(&mut position, -liquidity_delta)?;
+                let (token_a_amount, token_b_amount) = token_amounts(
+                    pool.sqrt_price,
+                    position.tick_lower,
+                    position.tick_upper,
+                    liquidity,
+                    false,
+                )
+                .ok_or(AmmError::CalculationFailure)?;
+                let token_a_amount = to_u64(token_a_amount)?;
+                let token_b_amount = to_u64(token_b_amount)?;
+                if token_a_amount < *minimum_token_a_amount
+                    || token_b_amount < *minimum_token_b_amount
+                {
+                    return Err(AmmError::ExceededSlippage.into());
+                }
+                (liquidity, token_a_amount, token_b_amount)
+            }
+            None => {
+                pool.update_position(&mut position, 0)?;
+                (0, 0, 0)
+            }
+        };
+        let token_a_amount = token_a_amount
+            .checked_add(position.fees_owed_a)
+            .ok_or(AmmError::CalculationFailure)?;
+        let token_b_amount = token_b_amount
+            .checked_add(position.fees_owed_b)
+            .ok_or(AmmError::CalculationFailure)?;
+        position.fees_owed_a = 0;
+        position.fees_owed_b = 0;
+        let event = PositionEvent {
+            pool: *pool_info.key,
+            position: *position_info.key,
+            owner: *owner_info.key,
+            tick_lower: position.tick_lower,
+            tick_upper: position.tick_upper,
+            liquidity,
+            token_a_amount,
+            token_b_amount,
+        };
 
-        // empty token A account
-        {
-            let (_token_a_key, token_a_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = token_a_account;
-            assert_eq!(
-                Err(AmmError::EmptySupply.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_a_account = old_account;
+        for (token_info, dest_info, amount) in &[
+            (token_a_info, dest_token_a_info, token_a_amount),
+            (token_b_info, dest_token_b_info, token_b_amount),
+        ] {
+            if *amount > 0 {
+                Self::token_transfer(
+                    pool_info.key,
+                    token_program_info.clone(),
+                    (*token_info).clone(),
+                    (*dest_info).clone(),
+                    authority_info.clone(),
+                    pool.nonce,
+                    *amount,
+                )?;
+            }
         }
 
-        // empty token B account
-        {
-            let (_token_b_key, token_b_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_b_mint_key,
-                &mut accounts.token_b_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_b_account;
-            assert_eq!(
-                Err(AmmError::EmptySupply.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
+        if close.is_some() {
+            pool.stats.record_withdraw();
+            //return the rent of the position to its owner
+            let lamports = owner_info
+                .lamports()
+                .checked_add(position_info.lamports())
+                .ok_or(AmmError::CalculationFailure)?;
+            **owner_info.lamports.borrow_mut() = lamports;
+            **position_info.lamports.borrow_mut() = 0;
+            for byte in position_info.data.borrow_mut().iter_mut() {
+                *byte = 0;
+            }
+            AmmEvent::ClosePosition(event).emit();
+        } else {
+            Position::pack_account(position, &mut position_info.data.borrow_mut())?;
+            AmmEvent::CollectFees(event).emit();
         }
+        ConcentratedPool::pack_account(pool, &mut pool_info.data.borrow_mut())?;
+        Ok(())
+    }
 
-        // invalid pool tokens
-        {
-            let old_mint = accounts.pool_mint_account;
-            let old_pool_account = accounts.pool_token_account;
-
-            let (_pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            accounts.pool_mint_account = pool_mint_account;
+    /// Processes a [CollectFees](enum.Instruction.html).
+    pub fn process_collect_fees(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        Self::process_position_withdraw(program_id, None, accounts)
+    }
 
-            let (_empty_pool_token_key, empty_pool_token_account) = mint_token(
-                &spl_token::id(),
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &user_key,
-                0,
-            );
+    /// Processes a [ClosePosition](enum.Instruction.html).
+    pub fn process_close_position(
+        program_id: &Pubkey,
+        instruction: ClosePositionInstruction,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        Self::process_position_withdraw(program_id, Some(instruction), accounts)
+    }
 
-            let (_pool_token_key, pool_token_account) = mint_token(
-                &spl_token::id(),
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &user_key,
-                pool_token_amount,
-            );
+    /// Processes a [PlaceOrder](enum.Instruction.html).
+    pub fn process_place_order(
+        program_id: &Pubkey,
+        side: OrderSide,
+        coin_lots: u64,
+        spread_bps: u64,
+        client_order_id: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let dex_program_info = next_account_info(account_info_iter)?;
+        let market_info = next_account_info(account_info_iter)?;
+        let open_orders_info = next_account_info(account_info_iter)?;
+        let request_queue_info = next_account_info(account_info_iter)?;
+        let event_queue_info = next_account_info(account_info_iter)?;
+        let bids_info = next_account_info(account_info_iter)?;
+        let asks_info = next_account_info(account_info_iter)?;
+        let coin_vault_info = next_account_info(account_info_iter)?;
+        let pc_vault_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
 
-            // non-empty pool token account
-            accounts.pool_token_account = pool_token_account;
-            assert_eq!(
-                Err(AmmError::InvalidSupply.into()),
-                accounts.initialize_swap()
-            );
-
-            // pool tokens already in circulation
-            accounts.pool_token_account = empty_pool_token_account;
-            assert_eq!(
-                Err(AmmError::InvalidSupply.into()),
-                accounts.initialize_swap()
-            );
-
-            accounts.pool_mint_account = old_mint;
-            accounts.pool_token_account = old_pool_account;
+        //validate account info
+        let config = Self::unpack_config(program_id, config_info)?;
+        Self::check_admin(&config, admin_info)?;
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if !token_swap.status().can_swap() {
+            return Err(AmmError::PoolPaused.into());
         }
-
-        // pool fee account has wrong mint
+        Self::check_market_accounts(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            authority_info,
+            dex_program_info,
+            market_info,
+            open_orders_info,
+        )?;
+        if *token_a_info.key != *token_swap.token_a_account()
+            || *token_b_info.key != *token_swap.token_b_account()
         {
-            let (_pool_fee_key, fixed_fee_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &user_key,
-                0,
-            );
-            let old_account = accounts.fixed_fee_account_a;
-            accounts.fixed_fee_account_a = fixed_fee_account;
-            assert_eq!(
-                Err(AmmError::IncorrectPoolMint.into()),
-                accounts.initialize_swap()
-            );
-            accounts.fixed_fee_account_a = old_account;
+            return Err(AmmError::IncorrectSwapAccount.into());
+        }
+        if *token_program_info.key != *token_swap.token_program_id() {
+            return Err(AmmError::IncorrectTokenProgramId.into());
         }
 
-        // token A account is delegated
-        {
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    &accounts.token_a_key,
-                    &user_key,
-                    &accounts.authority_key,
-                    &[],
-                    1,
-                )
-                .unwrap(),
-                vec![
-                    &mut accounts.token_a_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
-            assert_eq!(
-                Err(AmmError::InvalidDelegate.into()),
-                accounts.initialize_swap()
-            );
+        let token_a = Self::unpack_token_account(token_a_info, token_swap.token_program_id())?;
+        let token_b = Self::unpack_token_account(token_b_info, token_swap.token_program_id())?;
+        let (token_a_amount, token_b_amount) = Self::pool_reserves(
+            token_swap.as_ref(),
+            authority_info,
+            token_a.amount,
+            token_b.amount,
+            Some(open_orders_info),
+        )?;
+        Self::update_price_oracle(swap_info, token_a_amount, token_b_amount)?;
+        let (coin_lot_size, pc_lot_size) = {
+            let market = MarketState::load(market_info, dex_program_info.key)?;
+            (market.coin_lot_size, market.pc_lot_size)
+        };
+        let limit_price = order_price(
+            &Self::current_swap_curve(token_swap.as_ref())?,
+            token_swap.fees(),
+            side,
+            coin_lots,
+            coin_lot_size,
+            pc_lot_size,
+            spread_bps,
+            to_u128(token_a_amount)?,
+            to_u128(token_b_amount)?,
+        )
+        .ok_or(AmmError::ZeroTradingTokens)?;
+        let max_native_pc_qty = limit_price
+            .checked_mul(coin_lots)
+            .and_then(|pc_lots| pc_lots.checked_mul(pc_lot_size))
+            .ok_or(AmmError::CalculationFailure)?;
+        let order_payer_info = match side {
+            OrderSide::Bid => token_b_info,
+            OrderSide::Ask => token_a_info,
+        };
+        msg!(
+            "Order {}: {:?} {} lots at {}",
+            client_order_id,
+            side,
+            coin_lots,
+            limit_price
+        );
 
-            do_process_instruction(
-                revoke(
-                    &spl_token::id(),
-                    &accounts.token_a_key,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_a_account, &mut Account::default()],
-            )
-            .unwrap();
-        }
+        let ix = serum_dex::instruction::new_order(
+            market_info.key,
+            open_orders_info.key,
+            request_queue_info.key,
+            event_queue_info.key,
+            bids_info.key,
+            asks_info.key,
+            order_payer_info.key,
+            authority_info.key,
+            coin_vault_info.key,
+            pc_vault_info.key,
+            token_program_info.key,
+            rent_info.key,
+            None,
+            dex_program_info.key,
+            side.into(),
+            NonZeroU64::new(limit_price).ok_or(AmmError::ZeroTradingTokens)?,
+            NonZeroU64::new(coin_lots).ok_or(AmmError::ZeroTradingTokens)?,
+            OrderType::PostOnly,
+            client_order_id,
+            SelfTradeBehavior::DecrementTake,
+            u16::MAX,
+            NonZeroU64::new(max_native_pc_qty).ok_or(AmmError::ZeroTradingTokens)?,
+        )?;
+        Self::dex_invoke_signed(
+            swap_info.key,
+            &ix,
+            &[
+                market_info.clone(),
+                open_orders_info.clone(),
+                request_queue_info.clone(),
+                event_queue_info.clone(),
+                bids_info.clone(),
+                asks_info.clone(),
+                order_payer_info.clone(),
+                authority_info.clone(),
+                coin_vault_info.clone(),
+                pc_vault_info.clone(),
+                token_program_info.clone(),
+                rent_info.clone(),
+                dex_program_info.clone(),
+            ],
+            token_swap.nonce(),
+        )
+    }
 
-        // token B account is delegated
-        {
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    &accounts.token_b_key,
-                    &user_key,
-                    &accounts.authority_key,
-                    &[],
-                    1,
-                )
-                .unwrap(),
-                vec![
-                    &mut accounts.token_b_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
-            assert_eq!(
-                Err(AmmError::InvalidDelegate.into()),
-                accounts.initialize_swap()
-            );
+    /// Processes a [CancelOrder](enum.Instruction.html).
+    pub fn process_cancel_order(
+        program_id: &Pubkey,
+        client_order_id: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let dex_program_info = next_account_info(account_info_iter)?;
+        let market_info = next_account_info(account_info_iter)?;
+        let open_orders_info = next_account_info(account_info_iter)?;
+        let bids_info = next_account_info(account_info_iter)?;
+        let asks_info = next_account_info(account_info_iter)?;
+        let event_queue_info = next_account_info(account_info_iter)?;
 
-            do_process_instruction(
-                revoke(
-                    &spl_token::id(),
-                    &accounts.token_b_key,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_b_account, &mut Account::default()],
-            )
-            .unwrap();
-        }
+        //validate account info
+        let config = Self::unpack_config(program_id, config_info)?;
+        Self::check_admin(&config, admin_info)?;
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::check_market_accounts(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            authority_info,
+            dex_program_info,
+            market_info,
+            open_orders_info,
+        )?;
 
-        // token A account has close authority
-        {
-            do_process_instruction(
-                set_authority(
-                    &spl_token::id(),
-                    &accounts.token_a_key,
-                    Some(&user_key),
-                    AuthorityType::CloseAccount,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_a_account, &mut Account::default()],
-            )
-            .unwrap();
-            assert_eq!(
-                Err(AmmError::InvalidCloseAuthority.into()),
-                accounts.initialize_swap()
-            );
+        let ix = serum_dex::instruction::cancel_order_by_client_order_id(
+            dex_program_info.key,
+            market_info.key,
+            bids_info.key,
+            asks_info.key,
+            open_orders_info.key,
+            authority_info.key,
+            event_queue_info.key,
+            client_order_id,
+        )?;
+        Self::dex_invoke_signed(
+            swap_info.key,
+            &ix,
+            &[
+                market_info.clone(),
+                bids_info.clone(),
+                asks_info.clone(),
+                open_orders_info.clone(),
+                authority_info.clone(),
+                event_queue_info.clone(),
+                dex_program_info.clone(),
+            ],
+            token_swap.nonce(),
+        )
+    }
 
-            do_process_instruction(
-                set_authority(
-                    &spl_token::id(),
-                    &accounts.token_a_key,
-                    None,
-                    AuthorityType::CloseAccount,
-                    &user_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_a_account, &mut Account::default()],
-            )
-            .unwrap();
-        }
+    /// Processes a [SettleFunds](enum.Instruction.html).
+    pub fn process_settle_funds(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let dex_program_info = next_account_info(account_info_iter)?;
+        let market_info = next_account_info(account_info_iter)?;
+        let open_orders_info = next_account_info(account_info_iter)?;
+        let coin_vault_info = next_account_info(account_info_iter)?;
+        let pc_vault_info = next_account_info(account_info_iter)?;
+        let vault_signer_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
 
-        // token B account has close authority
+        //validate account info
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::check_market_accounts(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            authority_info,
+            dex_program_info,
+            market_info,
+            open_orders_info,
+        )?;
+        if *token_a_info.key != *token_swap.token_a_account()
+            || *token_b_info.key != *token_swap.token_b_account()
         {
-            do_process_instruction(
-                set_authority(
-                    &spl_token::id(),
-                    &accounts.token_b_key,
-                    Some(&user_key),
-                    AuthorityType::CloseAccount,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_b_account, &mut Account::default()],
-            )
-            .unwrap();
-            assert_eq!(
-                Err(AmmError::InvalidCloseAuthority.into()),
-                accounts.initialize_swap()
-            );
-
-            do_process_instruction(
-                set_authority(
-                    &spl_token::id(),
-                    &accounts.token_b_key,
-                    None,
-                    AuthorityType::CloseAccount,
-                    &user_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_b_account, &mut Account::default()],
-            )
-            .unwrap();
+            return Err(AmmError::IncorrectSwapAccount.into());
+        }
+        if *token_program_info.key != *token_swap.token_program_id() {
+            return Err(AmmError::IncorrectTokenProgramId.into());
         }
 
-        // wrong token program id
-        {
-            let wrong_program_id = Pubkey::new_unique();
-            assert_eq!(
-                Err(AmmError::IncorrectTokenProgramId.into()),
-                do_process_instruction(
-                    initialize(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_program_id,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &accounts.fee_a_key,
-                        &accounts.fee_b_key,
-                        &accounts.pool_token_key,
-                        accounts.nonce,
-                        accounts.fees.clone(),
-                        accounts.swap_curve.clone(),
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut accounts.fixed_fee_account_a,
-                        &mut accounts.fixed_fee_account_b,
-                        &mut accounts.pool_token_account,
-                        &mut Account::default(),
-                    ],
-                )
-            );
+        let ix = serum_dex::instruction::settle_funds(
+            dex_program_info.key,
+            market_info.key,
+            token_program_info.key,
+            open_orders_info.key,
+            authority_info.key,
+            coin_vault_info.key,
+            token_a_info.key,
+            pc_vault_info.key,
+            token_b_info.key,
+            None,
+            vault_signer_info.key,
+        )?;
+        Self::dex_invoke_signed(
+            swap_info.key,
+            &ix,
+            &[
+                market_info.clone(),
+                open_orders_info.clone(),
+                authority_info.clone(),
+                coin_vault_info.clone(),
+                pc_vault_info.clone(),
+                token_a_info.clone(),
+                token_b_info.clone(),
+                vault_signer_info.clone(),
+                token_program_info.clone(),
+                dex_program_info.clone(),
+            ],
+            token_swap.nonce(),
+        )
+    }
+
+    /// Processes an [InitializeConfig](enum.Instruction.html).
+    pub fn process_initialize_config(
+        program_id: &Pubkey,
+        nonce: u8,
+        accounts: &[AccountInfo],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let config_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let fee_wallet_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+
+        if !admin_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        // only the canonical nonce derives the config, so it is initialized once
+        let (config_id, config_nonce) = Self::config_id(program_id);
+        if *config_info.key != config_id || nonce != config_nonce {
+            return Err(AmmError::IncorrectConfigAccount.into());
+        }
+        if config_info.owner == program_id {
+            return Err(AmmError::AlreadyInUse.into());
+        }
+        if let Some(swap_constraints) = swap_constraints {
+            let owner_key = swap_constraints
+                .owner_key
+                .parse::<Pubkey>()
+                .map_err(|_| AmmError::InvalidOwner)?;
+            if *admin_info.key != owner_key {
+                return Err(AmmError::InvalidAdmin.into());
+            }
         }
 
-        // create swap with same token A and B
-        {
-            let (_token_a_repeat_key, token_a_repeat_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                10,
-            );
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_a_repeat_account;
-            assert_eq!(
-                Err(AmmError::RepeatedMint.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
+        let rent = Rent::get()?;
+        invoke_signed(
+            &system_instruction::create_account(
+                admin_info.key,
+                config_info.key,
+                rent.minimum_balance(ProgramConfig::LEN),
+                ProgramConfig::LEN as u64,
+                program_id,
+            ),
+            &[
+                admin_info.clone(),
+                config_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[&[CONFIG_SEED, &[nonce]]],
+        )?;
+
+        let config = ProgramConfig {
+            is_initialized: true,
+            nonce,
+            admin: *admin_info.key,
+            fee_wallet: *fee_wallet_info.key,
+        };
+        ProgramConfig::pack(config, &mut config_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes a [SetFeeWallet](enum.Instruction.html).
+    pub fn process_set_fee_wallet(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let config_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let fee_wallet_info = next_account_info(account_info_iter)?;
+
+        let mut config = Self::unpack_config(program_id, config_info)?;
+        Self::check_admin(&config, admin_info)?;
+
+        msg!("Fee wallet: {} -> {}", config.fee_wallet, fee_wallet_info.key);
+        config.fee_wallet = *fee_wallet_info.key;
+        ProgramConfig::pack(config, &mut config_info.data.borrow_mut())?;
+        AmmEvent::SetFeeWallet(FeeWalletEvent {
+            config: *config_info.key,
+            admin: *admin_info.key,
+            fee_wallet: *fee_wallet_info.key,
+        })
+        .emit();
+        Ok(())
+    }
+
+    /// Processes a [SetFixedFeeAccounts](enum.Instruction.html).
+    pub fn process_set_fixed_fee_accounts(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let fee_account_infos = account_info_iter.as_slice();
+
+        //validate account info
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let config = Self::unpack_config(program_id, config_info)?;
+        Self::check_admin(&config, admin_info)?;
+
+        let multi_swap = MultiSwap::unpack_account(&swap_info.data.borrow());
+        if let Ok(mut multi_swap) = multi_swap {
+            let fee_account_infos = fee_account_infos
+                .get(..usize::from(multi_swap.token_count))
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            for ((fixed_fee_account, mint), fee_account_info) in multi_swap
+                .fixed_fee_accounts
+                .iter_mut()
+                .zip(multi_swap.token_mints.iter())
+                .zip(fee_account_infos.iter())
+            {
+                Self::check_fixed_fee_account(
+                    &config,
+                    fee_account_info,
+                    mint,
+                    &multi_swap.token_program_id,
+                )?;
+                msg!("Fixed fee account: {} -> {}", fixed_fee_account, fee_account_info.key);
+                *fixed_fee_account = *fee_account_info.key;
+            }
+            MultiSwap::pack_account(multi_swap, &mut swap_info.data.borrow_mut())?;
+            Self::emit_fixed_fee_accounts(swap_info, admin_info, fee_account_infos);
+            return Ok(());
+        }
+        let (fee_account_a_info, fee_account_b_info) = match fee_account_infos {
+            [fee_account_a_info, fee_account_b_info, ..] => (fee_account_a_info, fee_account_b_info),
+            _ => return Err(ProgramError::NotEnoughAccountKeys),
+        };
+        let pool = ConcentratedPool::unpack_account(&swap_info.data.borrow());
+        if let Ok(mut pool) = pool {
+            Self::check_fixed_fee_account(
+                &config,
+                fee_account_a_info,
+                &pool.token_a_mint,
+                &pool.token_program_id,
+            )?;
+            Self::check_fixed_fee_account(
+                &config,
+                fee_account_b_info,
+                &pool.token_b_mint,
+                &pool.token_program_id,
+            )?;
+            msg!("Fixed fee account A: {} -> {}", pool.fixed_fee_account_a, fee_account_a_info.key);
+            msg!("Fixed fee account B: {} -> {}", pool.fixed_fee_account_b, fee_account_b_info.key);
+            pool.fixed_fee_account_a = *fee_account_a_info.key;
+            pool.fixed_fee_account_b = *fee_account_b_info.key;
+            ConcentratedPool::pack_account(pool, &mut swap_info.data.borrow_mut())?;
+            Self::emit_fixed_fee_accounts(swap_info, admin_info, &fee_account_infos[..2]);
+            return Ok(());
         }
+        let mut token_swap = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        // the tokens of two token pools may be on Token-2022, which the mint
+        // of a fee account then pins it to
+        let fee_program_id = |fee_account_info: &AccountInfo| {
+            *Self::token_program_id_of(fee_account_info).unwrap_or(token_swap.token_program_id())
+        };
+        Self::check_fixed_fee_account(
+            &config,
+            fee_account_a_info,
+            token_swap.token_a_mint(),
+            &fee_program_id(fee_account_a_info),
+        )?;
+        Self::check_fixed_fee_account(
+            &config,
+            fee_account_b_info,
+            token_swap.token_b_mint(),
+            &fee_program_id(fee_account_b_info),
+        )?;
 
-        // create valid swap
-        accounts.initialize_swap().unwrap();
+        msg!(
+            "Fixed fee account A: {} -> {}",
+            token_swap.fixed_fee_account_a(),
+            fee_account_a_info.key
+        );
+        msg!(
+            "Fixed fee account B: {} -> {}",
+            token_swap.fixed_fee_account_b(),
+            fee_account_b_info.key
+        );
+        token_swap.set_fixed_fee_accounts(*fee_account_a_info.key, *fee_account_b_info.key);
+        SwapVersion::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        Self::emit_fixed_fee_accounts(swap_info, admin_info, &fee_account_infos[..2]);
+        Ok(())
+    }
 
-        // create invalid flat swap
-        {
-            let token_b_price = 0;
-            let fees = Fees {
-                return_fee_numerator,
-                fixed_fee_numerator,
-                fee_denominator
-            };
-    
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::ConstantPrice,
-                calculator: Box::new(ConstantPriceCurve { token_b_price }),
-            };
-            let mut accounts =
-                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-            assert_eq!(
-                Err(AmmError::InvalidCurve.into()),
-                accounts.initialize_swap()
-            );
+    /// Logs the change of the fixed fee account of each token of a pool, in
+    /// pool order
+    fn emit_fixed_fee_accounts(
+        swap_info: &AccountInfo,
+        admin_info: &AccountInfo,
+        fee_account_infos: &[AccountInfo],
+    ) {
+        for (token_index, fee_account_info) in (0u8..).zip(fee_account_infos) {
+            AmmEvent::SetFixedFeeAccount(FixedFeeAccountEvent {
+                pool: *swap_info.key,
+                admin: *admin_info.key,
+                token_index,
+                fee_account: *fee_account_info.key,
+            })
+            .emit();
         }
+    }
 
-        // create valid flat swap
-        {
-            let fees = Fees {
-                return_fee_numerator,
-                fixed_fee_numerator,
-                fee_denominator
-            };
-                let token_b_price = 10_000;
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::ConstantPrice,
-                calculator: Box::new(ConstantPriceCurve { token_b_price }),
-            };
-            let mut accounts =
-                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-            accounts.initialize_swap().unwrap();
+    /// Processes a [SetFees](enum.Instruction.html).
+    pub fn process_set_fees(
+        program_id: &Pubkey,
+        fees: Fees,
+        accounts: &[AccountInfo],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+
+        //validate account info
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let config = Self::unpack_config(program_id, config_info)?;
+        Self::check_admin(&config, admin_info)?;
+        if let Some(swap_constraints) = swap_constraints {
+            swap_constraints.validate_fees(&fees)?;
         }
+        fees.validate()?;
+        let event = AmmEvent::SetFees(FeesEvent {
+            pool: *swap_info.key,
+            admin: *admin_info.key,
+            return_fee_numerator: fees.return_fee_numerator,
+            fixed_fee_numerator: fees.fixed_fee_numerator,
+            fee_denominator: fees.fee_denominator,
+            owner_withdraw_fee_numerator: fees.owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator: fees.owner_withdraw_fee_denominator,
+        });
 
-        // create invalid offset swap
-        {
-            let token_b_offset = 0;
-            let fees = Fees {
-                return_fee_numerator,
-                fixed_fee_numerator,
-                fee_denominator
-            };
-    
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::Offset,
-                calculator: Box::new(OffsetCurve { token_b_offset }),
-            };
-            let mut accounts =
-                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-            assert_eq!(
-                Err(AmmError::InvalidCurve.into()),
-                accounts.initialize_swap()
-            );
+        let multi_swap = MultiSwap::unpack_account(&swap_info.data.borrow());
+        if let Ok(mut multi_swap) = multi_swap {
+            if fees.has_owner_withdraw_fee() {
+                return Err(ProgramError::AccountDataTooSmall);
+            }
+            msg!("Old fees: {:?}", multi_swap.fees);
+            msg!("New fees: {:?}", fees);
+            multi_swap.fees = fees;
+            MultiSwap::pack_account(multi_swap, &mut swap_info.data.borrow_mut())?;
+            event.emit();
+            return Ok(());
+        }
+        let pool = ConcentratedPool::unpack_account(&swap_info.data.borrow());
+        if let Ok(mut pool) = pool {
+            if fees.has_owner_withdraw_fee() {
+                return Err(ProgramError::AccountDataTooSmall);
+            }
+            msg!("Old fees: {:?}", pool.fees);
+            msg!("New fees: {:?}", fees);
+            pool.fees = fees;
+            ConcentratedPool::pack_account(pool, &mut swap_info.data.borrow_mut())?;
+            event.emit();
+            return Ok(());
         }
+        let mut token_swap = SwapVersion::unpack_version(&swap_info.data.borrow())?;
 
-        // create valid offset swap
-        {
-            let token_b_offset = 10;
-            let fees = Fees {
-                return_fee_numerator,
-                fixed_fee_numerator,
-                fee_denominator
-            };
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::Offset,
-                calculator: Box::new(OffsetCurve { token_b_offset }),
-            };
-            let mut accounts =
-                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-            accounts.initialize_swap().unwrap();
+        msg!("Old fees: {:?}", token_swap.fees());
+        msg!("New fees: {:?}", fees);
+        token_swap.set_fees(fees)?;
+        SwapVersion::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        event.emit();
+        Ok(())
+    }
+
+    /// Processes a [SetHostFee](enum.Instruction.html).
+    pub fn process_set_host_fee(
+        program_id: &Pubkey,
+        host_fee: HostFee,
+        accounts: &[AccountInfo],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+
+        //validate account info
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let config = Self::unpack_config(program_id, config_info)?;
+        Self::check_admin(&config, admin_info)?;
+        if let Some(swap_constraints) = swap_constraints {
+            swap_constraints.validate_host_fee(&host_fee)?;
         }
+        host_fee.validate()?;
+        let mut token_swap = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+
+        msg!("Old host fee: {:?}", token_swap.host_fee());
+        msg!("New host fee: {:?}", host_fee);
+        token_swap.set_host_fee(host_fee)?;
+        SwapVersion::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        AmmEvent::SetHostFee(HostFeeEvent {
+            pool: *swap_info.key,
+            admin: *admin_info.key,
+            numerator: host_fee.numerator,
+            denominator: host_fee.denominator,
+        })
+        .emit();
+        Ok(())
+    }
 
-        // wrong owner key in constraint
+    /// Processes a [SetProtocolFee](enum.Instruction.html).
+    pub fn process_set_protocol_fee(
+        program_id: &Pubkey,
+        numerator: u64,
+        denominator: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let protocol_fee_info = next_account_info(account_info_iter)?;
+
+        //validate account info
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let config = Self::unpack_config(program_id, config_info)?;
+        Self::check_admin(&config, admin_info)?;
+        let mut token_swap = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        let protocol_fee_account =
+            Self::unpack_token_account(protocol_fee_info, token_swap.token_program_id())?;
+        if protocol_fee_account.mint != *token_swap.pool_mint() {
+            return Err(AmmError::IncorrectPoolMint.into());
+        }
+        let protocol_fee = ProtocolFee {
+            numerator,
+            denominator,
+            fee_account: *protocol_fee_info.key,
+            root_k_last: 0,
+        };
+        protocol_fee.validate()?;
+        // `sqrt(k)` only measures the fees earned by constant product pools
+        if protocol_fee.is_enabled()
+            && token_swap.swap_curve().curve_type != CurveType::ConstantProduct
         {
-            let new_key = Pubkey::new_unique();
-            let return_fee_numerator = 20;
-            let fixed_fee_numerator = 10;
-            let fee_denominator = 10000;
-            let fees = Fees {
-                return_fee_numerator,
-                fixed_fee_numerator,
-                fee_denominator
-            };
-            let curve = ConstantProductCurve {};
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::ConstantProduct,
-                calculator: Box::new(curve),
-            };
-            let owner_key = &new_key.to_string();
-            let valid_curve_types = &[CurveType::ConstantProduct];
-            let constraints = Some(SwapConstraints {
-                owner_key,
-                valid_curve_types,
-                fees: &fees,
-            });
-            let mut accounts = SwapAccountInfo::new(
-                &user_key,
-                fees.clone(),
-                swap_curve,
-                token_a_amount,
-                token_b_amount,
-            );
-            assert_eq!(
-                Err(AmmError::InvalidOwner.into()),
-                do_process_instruction_with_fee_constraints(
-                    initialize(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &accounts.fee_a_key,
-                        &accounts.fee_b_key,
-                        &accounts.pool_token_key,
-                        accounts.nonce,
-                        accounts.fees.clone(),
-                        accounts.swap_curve.clone(),
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut accounts.fixed_fee_account_a,
-                        &mut accounts.fixed_fee_account_b,
-                        &mut accounts.pool_token_account,
-                        &mut Account::default(),
-                    ],
-                    &constraints,
-                )
-            );
+            return Err(AmmError::UnsupportedCurveOperation.into());
         }
 
-        // wrong fee in constraint
-        {
-            let return_fee_numerator = 25;
-            let fixed_fee_numerator = 5;
-            let fee_denominator = 10000;
-            let fees = Fees {
-                return_fee_numerator,
-                fixed_fee_numerator,
-                fee_denominator
-            };
-            let curve = ConstantProductCurve {};
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::ConstantProduct,
-                calculator: Box::new(curve),
-            };
-            let owner_key = &user_key.to_string();
-            let valid_curve_types = &[CurveType::ConstantProduct];
-            let constraints = Some(SwapConstraints {
-                owner_key,
-                valid_curve_types,
-                fees: &fees,
-            });
-            let mut bad_fees = fees.clone();
-            bad_fees.return_fee_numerator = return_fee_numerator - 1;
-            let mut accounts = SwapAccountInfo::new(
-                &user_key,
-                bad_fees,
-                swap_curve,
-                token_a_amount,
-                token_b_amount,
-            );
-            assert_eq!(
-                Err(AmmError::InvalidFee.into()),
-                do_process_instruction_with_fee_constraints(
-                    initialize(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &accounts.fee_a_key,
-                        &accounts.fee_b_key,
-                        &accounts.pool_token_key,
-                        accounts.nonce,
-                        accounts.fees.clone(),
-                        accounts.swap_curve.clone(),
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut accounts.fixed_fee_account_a,
-                        &mut accounts.fixed_fee_account_b,
-                        &mut accounts.pool_token_account,
-                        &mut Account::default(),
-                    ],
-                    &constraints,
-                )
-            );
+        msg!("Old protocol fee: {:?}", token_swap.protocol_fee());
+        msg!("New protocol fee: {:?}", protocol_fee);
+        token_swap.set_protocol_fee(protocol_fee)?;
+        SwapVersion::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        AmmEvent::SetProtocolFee(ProtocolFeeEvent {
+            pool: *swap_info.key,
+            admin: *admin_info.key,
+            numerator,
+            denominator,
+            fee_account: *protocol_fee_info.key,
+        })
+        .emit();
+        Ok(())
+    }
+
+    /// Processes a [SetPoolStatus](enum.Instruction.html).
+    pub fn process_set_pool_status(
+        program_id: &Pubkey,
+        status: PoolStatus,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+
+        //validate account info
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let config = Self::unpack_config(program_id, config_info)?;
+        Self::check_admin(&config, admin_info)?;
+        let event = AmmEvent::SetPoolStatus(PoolStatusEvent {
+            pool: *swap_info.key,
+            admin: *admin_info.key,
+            status,
+        });
+        let multi_swap = MultiSwap::unpack_account(&swap_info.data.borrow());
+        if let Ok(mut multi_swap) = multi_swap {
+            msg!("Pool status: {:?} -> {:?}", multi_swap.status, status);
+            multi_swap.status = status;
+            MultiSwap::pack_account(multi_swap, &mut swap_info.data.borrow_mut())?;
+            event.emit();
+            return Ok(());
+        }
+        let pool = ConcentratedPool::unpack_account(&swap_info.data.borrow());
+        if let Ok(mut pool) = pool {
+            msg!("Pool status: {:?} -> {:?}", pool.status, status);
+            pool.status = status;
+            ConcentratedPool::pack_account(pool, &mut swap_info.data.borrow_mut())?;
+            event.emit();
+            return Ok(());
+        }
+        let mut token_swap = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        if let SwapVersion::SwapV1(_) = token_swap {
+            let system_program_info = next_account_info(account_info_iter)?;
+            Self::grow_swap_account(swap_info, admin_info, system_program_info)?;
+            msg!("Upgrading swap to the latest version");
+            token_swap = SwapVersion::SwapV3(token_swap.upgrade(Clock::get()?.unix_timestamp));
         }
 
-        // create valid swap with constraints
-        {
-            let return_fee_numerator = 25;
-            let fixed_fee_numerator = 5;
-            let fee_denominator = 10000;
-            let fees = Fees {
-                return_fee_numerator,
-                fixed_fee_numerator,
-                fee_denominator
-            };
-            let curve = ConstantProductCurve {};
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::ConstantProduct,
-                calculator: Box::new(curve),
-            };
-            let owner_key = &user_key.to_string();
-            let valid_curve_types = &[CurveType::ConstantProduct];
-            let constraints = Some(SwapConstraints {
-                owner_key,
-                valid_curve_types,
-                fees: &fees,
-            });
-            let mut accounts = SwapAccountInfo::new(
-                &user_key,
-                fees.clone(),
-                swap_curve,
-                token_a_amount,
-                token_b_amount,
-            );
-            do_process_instruction_with_fee_constraints(
-                initialize(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &accounts.swap_key,
-                    &accounts.authority_key,
-                    &accounts.token_a_key,
-                    &accounts.token_b_key,
-                    &accounts.pool_mint_key,
-                    &accounts.fee_a_key,
-                    &accounts.fee_b_key,
-                    &accounts.pool_token_key,
-                    accounts.nonce,
-                    accounts.fees,
-                    accounts.swap_curve.clone(),
-                )
-                .unwrap(),
-                vec![
-                    &mut accounts.swap_account,
-                    &mut Account::default(),
-                    &mut accounts.token_a_account,
-                    &mut accounts.token_b_account,
-                    &mut accounts.pool_mint_account,
-                    &mut accounts.fixed_fee_account_a,
-                    &mut accounts.fixed_fee_account_b,
-                    &mut accounts.pool_token_account,
-                    &mut Account::default(),
+        msg!("Pool status: {:?} -> {:?}", token_swap.status(), status);
+        token_swap.set_status(status)?;
+        SwapVersion::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        event.emit();
+        Ok(())
+    }
+
+    /// Grows a swap account created by an older version to the size of the
+    /// latest one, the payer topping it up to stay rent exempt.
+    fn grow_swap_account<'a>(
+        swap_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        if swap_info.data_len() >= SwapVersion::LATEST_LEN {
+            return Ok(());
+        }
+        let lamports = Rent::get()?.minimum_balance(SwapVersion::LATEST_LEN);
+        let top_up = lamports.saturating_sub(swap_info.lamports());
+        if top_up > 0 {
+            invoke(
+                &system_instruction::transfer(payer_info.key, swap_info.key, top_up),
+                &[
+                    payer_info.clone(),
+                    swap_info.clone(),
+                    system_program_info.clone(),
                 ],
-                &constraints,
-            )
-            .unwrap();
+            )?;
         }
+        swap_info.realloc(SwapVersion::LATEST_LEN, true)
+    }
 
-        // create again
-        {
-            assert_eq!(
-                Err(AmmError::AlreadyInUse.into()),
-                accounts.initialize_swap()
-            );
+    /// Processes a [RampAmp](enum.Instruction.html).
+    pub fn process_ramp_amp(
+        program_id: &Pubkey,
+        target_amp: u64,
+        stop_ramp_ts: i64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+
+        //validate account info
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
         }
-        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
-        assert!(swap_state.is_initialized());
-        assert_eq!(swap_state.nonce(), accounts.nonce);
-        assert_eq!(
-            swap_state.swap_curve().curve_type,
-            accounts.swap_curve.curve_type
-        );
-        assert_eq!(*swap_state.token_a_account(), accounts.token_a_key);
-        assert_eq!(*swap_state.token_b_account(), accounts.token_b_key);
-        assert_eq!(*swap_state.pool_mint(), accounts.pool_mint_key);
-        assert_eq!(*swap_state.token_a_mint(), accounts.token_a_mint_key);
-        assert_eq!(*swap_state.token_b_mint(), accounts.token_b_mint_key);
-        assert_eq!(*swap_state.fixed_fee_account_a(), accounts.fee_a_key);
-        assert_eq!(*swap_state.fixed_fee_account_b(), accounts.fee_b_key);
-        let token_a = spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-        assert_eq!(token_a.amount, token_a_amount);
-        let token_b = spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-        assert_eq!(token_b.amount, token_b_amount);
-        let pool_account =
-            spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
-        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-        assert_eq!(pool_mint.supply, pool_account.amount);
+        let config = Self::unpack_config(program_id, config_info)?;
+        Self::check_admin(&config, admin_info)?;
+        let mut token_swap = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        if token_swap.swap_curve().curve_type != CurveType::Stable {
+            return Err(AmmError::UnsupportedCurveOperation.into());
+        }
+
+        let now = Clock::get()?.unix_timestamp;
+        if token_swap.amp_ramp().is_ramping(now) {
+            return Err(AmmError::InvalidAmpRamp.into());
+        }
+        let amp_ramp = AmpRamp {
+            initial_amp: Self::current_amp(&token_swap, now)?,
+            target_amp,
+            start_ramp_ts: now,
+            stop_ramp_ts,
+        };
+        amp_ramp.validate()?;
+
+        msg!("Amp ramp: {:?}", amp_ramp);
+        token_swap.set_amp_ramp(amp_ramp)?;
+        SwapVersion::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        AmmEvent::RampAmp(Self::amp_ramp_event(swap_info, admin_info, &amp_ramp)).emit();
+        Ok(())
     }
 
-    #[test]
-    fn test_deposit() {
-        let user_key = Pubkey::new_unique();
-        let depositor_key = Pubkey::new_unique();
+    /// Processes a [StopRampAmp](enum.Instruction.html).
+    pub fn process_stop_ramp_amp(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        //load account info
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
 
-        let return_fee_numerator = 25;
-        let fixed_fee_numerator = 5;
-        let fee_denominator = 10000;
-        let fees = Fees {
-            return_fee_numerator,
-            fixed_fee_numerator,
-            fee_denominator
-        };
+        //validate account info
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let config = Self::unpack_config(program_id, config_info)?;
+        Self::check_admin(&config, admin_info)?;
+        let mut token_swap = SwapVersion::unpack_version(&swap_info.data.borrow())?;
 
-        let token_a_amount = 1000;
-        let token_b_amount = 9000;
-        let curve_type = CurveType::ConstantProduct;
-        let swap_curve = SwapCurve {
-            curve_type,
-            calculator: Box::new(ConstantProductCurve {}),
+        let now = Clock::get()?.unix_timestamp;
+        if !token_swap.amp_ramp().is_ramping(now) {
+            return Err(AmmError::InvalidAmpRamp.into());
+        }
+        // the ramp ends now, at the amplification it reached
+        let amp = Self::current_amp(&token_swap, now)?;
+        let amp_ramp = AmpRamp {
+            initial_amp: amp,
+            target_amp: amp,
+            start_ramp_ts: now,
+            stop_ramp_ts: now,
         };
 
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        msg!("Amp ramp stopped at {}", amp);
+        token_swap.set_amp_ramp(amp_ramp)?;
+        SwapVersion::pack(token_swap, &mut swap_info.data.borrow_mut())?;
+        AmmEvent::StopRampAmp(Self::amp_ramp_event(swap_info, admin_info, &amp_ramp)).emit();
+        Ok(())
+    }
 
-        // depositing 10% of the current pool amount in token A and B means
-        // that our pool tokens will be worth 1 / 10 of the current pool amount
-        let pool_amount = INITIAL_SWAP_POOL_AMOUNT / 10;
-        let deposit_a = token_a_amount / 10;
-        let deposit_b = token_b_amount / 10;
+    /// Event of an amplification ramp set on a stable pool
+    fn amp_ramp_event(
+        swap_info: &AccountInfo,
+        admin_info: &AccountInfo,
+        amp_ramp: &AmpRamp,
+    ) -> AmpRampEvent {
+        AmpRampEvent {
+            pool: *swap_info.key,
+            admin: *admin_info.key,
+            initial_amp: amp_ramp.initial_amp,
+            target_amp: amp_ramp.target_amp,
+            start_ramp_ts: amp_ramp.start_ramp_ts,
+            stop_ramp_ts: amp_ramp.stop_ramp_ts,
+        }
+    }
 
-        // swap not initialized
-        {
+    /// Processes an [Instruction](enum.Instruction.html).
+    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
+        Self::process_with_constraints(program_id, accounts, input, &SWAP_CONSTRAINTS)
+    }
+
+    /// Processes an instruction given extra constraint
+    pub fn process_with_constraints(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        input: &[u8],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        let instruction = AmmInstruction::unpack(input)?;
+        match instruction {
+            AmmInstruction::Initialize(InitializeInstruction {
+                nonce,
+                fees,
+                swap_curve,
+            }) => {
+                msg!("Instruction: Init");
+                Self::process_initialize(
+                    program_id,
+                    nonce,
+                    fees,
+                    swap_curve,
+                    accounts,
+                    swap_constraints,
+                )
+            }
+            AmmInstruction::Swap(SwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            }) => {
+                msg!("Instruction: Swap");
+                Self::check_deadline(deadline)?;
+                Self::process_swap(program_id, amount_in, minimum_amount_out, accounts)
+            }
+            AmmInstruction::DepositAllTokenTypes(DepositInstruction {
+                pool_token_amount,
+                maximum_token_a_amount,
+                maximum_token_b_amount,
+                deadline,
+            }) => {
+                msg!("Instruction: DepositAllTokenTypes");
+                Self::check_deadline(deadline)?;
+                Self::process_deposit_all_token_types(
+                    program_id,
+                    pool_token_amount,
+                    maximum_token_a_amount,
+                    maximum_token_b_amount,
+                    accounts,
+                )
+            }
+            AmmInstruction::WithdrawAllTokenTypes(WithdrawInstruction {
+                pool_token_amount,
+                minimum_token_a_amount,
+                minimum_token_b_amount,
+                deadline,
+            }) => {
+                msg!("Instruction: WithdrawAllTokenTypes");
+                Self::check_deadline(deadline)?;
+                Self::process_withdraw_all_token_types(
+                    program_id,
+                    pool_token_amount,
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                    accounts,
+                )
+            }
+            AmmInstruction::DepositSingleTokenTypeExactAmountIn(
+                DepositSingleTokenTypeExactAmountIn {
+                    source_token_amount,
+                    minimum_pool_token_amount,
+                    deadline,
+                },
+            ) => {
+                msg!("Instruction: DepositSingleTokenTypeExactAmountIn");
+                Self::check_deadline(deadline)?;
+                Self::process_deposit_single_token_type_exact_amount_in(
+                    program_id,
+                    source_token_amount,
+                    minimum_pool_token_amount,
+                    accounts,
+                )
+            }
+            AmmInstruction::WithdrawSingleTokenTypeExactAmountOut(
+                WithdrawSingleTokenTypeExactAmountOut {
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    deadline,
+                },
+            ) => {
+                msg!("Instruction: WithdrawSingleTokenTypeExactAmountOut");
+                Self::check_deadline(deadline)?;
+                Self::process_withdraw_single_token_type_exact_amount_out(
+                    program_id,
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    accounts,
+                )
+            }
+            AmmInstruction::InitializeConfig(InitializeConfigInstruction { nonce }) => {
+                msg!("Instruction: InitializeConfig");
+                Self::process_initialize_config(program_id, nonce, accounts, swap_constraints)
+            }
+            AmmInstruction::SetFeeWallet => {
+                msg!("Instruction: SetFeeWallet");
+                Self::process_set_fee_wallet(program_id, accounts)
+            }
+            AmmInstruction::SetFees(SetFeesInstruction { fees }) => {
+                msg!("Instruction: SetFees");
+                Self::process_set_fees(program_id, fees, accounts, swap_constraints)
+            }
+            AmmInstruction::SetPoolStatus(SetPoolStatusInstruction { status }) => {
+                msg!("Instruction: SetPoolStatus");
+                Self::process_set_pool_status(program_id, status, accounts)
+            }
+            AmmInstruction::SwapExactAmountOut(SwapExactAmountOutInstruction {
+                amount_out,
+                maximum_amount_in,
+                deadline,
+            }) => {
+                msg!("Instruction: SwapExactAmountOut");
+                Self::check_deadline(deadline)?;
+                Self::process_swap_exact_amount_out(
+                    program_id,
+                    amount_out,
+                    maximum_amount_in,
+                    accounts,
+                )
+            }
+            AmmInstruction::RouteSwap(RouteSwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            }) => {
+                msg!("Instruction: RouteSwap");
+                Self::check_deadline(deadline)?;
+                Self::process_route_swap(program_id, amount_in, minimum_amount_out, accounts)
+            }
+            AmmInstruction::PlaceOrder(PlaceOrderInstruction {
+                side,
+                coin_lots,
+                spread_bps,
+                client_order_id,
+            }) => {
+                msg!("Instruction: PlaceOrder");
+                Self::process_place_order(
+                    program_id,
+                    side,
+                    coin_lots,
+                    spread_bps,
+                    client_order_id,
+                    accounts,
+                )
+            }
+            AmmInstruction::CancelOrder(CancelOrderInstruction { client_order_id }) => {
+                msg!("Instruction: CancelOrder");
+                Self::process_cancel_order(program_id, client_order_id, accounts)
+            }
+            AmmInstruction::SettleFunds => {
+                msg!("Instruction: SettleFunds");
+                Self::process_settle_funds(program_id, accounts)
+            }
+            AmmInstruction::RampAmp(RampAmpInstruction {
+                target_amp,
+                stop_ramp_ts,
+            }) => {
+                msg!("Instruction: RampAmp");
+                Self::process_ramp_amp(program_id, target_amp, stop_ramp_ts, accounts)
+            }
+            AmmInstruction::StopRampAmp => {
+                msg!("Instruction: StopRampAmp");
+                Self::process_stop_ramp_amp(program_id, accounts)
+            }
+            AmmInstruction::InitializeMulti(InitializeMultiInstruction {
+                nonce,
+                fees,
+                swap_curve,
+            }) => {
+                msg!("Instruction: InitializeMulti");
+                Self::process_initialize_multi(
+                    program_id,
+                    nonce,
+                    fees,
+                    swap_curve,
+                    accounts,
+                    swap_constraints,
+                )
+            }
+            AmmInstruction::MultiSwap(SwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            }) => {
+                msg!("Instruction: MultiSwap");
+                Self::check_deadline(deadline)?;
+                Self::process_multi_swap(program_id, amount_in, minimum_amount_out, accounts)
+            }
+            AmmInstruction::MultiDepositAllTokenTypes(MultiDepositInstruction {
+                pool_token_amount,
+                maximum_token_amounts,
+                deadline,
+            }) => {
+                msg!("Instruction: MultiDepositAllTokenTypes");
+                Self::check_deadline(deadline)?;
+                Self::process_multi_deposit_all_token_types(
+                    program_id,
+                    pool_token_amount,
+                    &maximum_token_amounts,
+                    accounts,
+                )
+            }
+            AmmInstruction::MultiWithdrawAllTokenTypes(MultiWithdrawInstruction {
+                pool_token_amount,
+                minimum_token_amounts,
+                deadline,
+            }) => {
+                msg!("Instruction: MultiWithdrawAllTokenTypes");
+                Self::check_deadline(deadline)?;
+                Self::process_multi_withdraw_all_token_types(
+                    program_id,
+                    pool_token_amount,
+                    &minimum_token_amounts,
+                    accounts,
+                )
+            }
+            AmmInstruction::MultiDepositSingleTokenTypeExactAmountIn(
+                DepositSingleTokenTypeExactAmountIn {
+                    source_token_amount,
+                    minimum_pool_token_amount,
+                    deadline,
+                },
+            ) => {
+                msg!("Instruction: MultiDepositSingleTokenTypeExactAmountIn");
+                Self::check_deadline(deadline)?;
+                Self::process_multi_deposit_single_token_type_exact_amount_in(
+                    program_id,
+                    source_token_amount,
+                    minimum_pool_token_amount,
+                    accounts,
+                )
+            }
+            AmmInstruction::MultiWithdrawSingleTokenTypeExactAmountOut(
+                WithdrawSingleTokenTypeExactAmountOut {
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    deadline,
+                },
+            ) => {
+                msg!("Instruction: MultiWithdrawSingleTokenTypeExactAmountOut");
+                Self::check_deadline(deadline)?;
+                Self::process_multi_withdraw_single_token_type_exact_amount_out(
+                    program_id,
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    accounts,
+                )
+            }
+            AmmInstruction::InitializeConcentrated(InitializeConcentratedInstruction {
+                nonce,
+                fees,
+                tick_spacing,
+                sqrt_price,
+            }) => {
+                msg!("Instruction: InitializeConcentrated");
+                Self::process_initialize_concentrated(
+                    program_id,
+                    nonce,
+                    fees,
+                    tick_spacing,
+                    sqrt_price,
+                    accounts,
+                    swap_constraints,
+                )
+            }
+            AmmInstruction::OpenPosition(instruction) => {
+                msg!("Instruction: OpenPosition");
+                Self::check_deadline(instruction.deadline)?;
+                Self::process_open_position(program_id, instruction, accounts)
+            }
+            AmmInstruction::ConcentratedSwap(SwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            }) => {
+                msg!("Instruction: ConcentratedSwap");
+                Self::check_deadline(deadline)?;
+                Self::process_concentrated_swap(program_id, amount_in, minimum_amount_out, accounts)
+            }
+            AmmInstruction::CollectFees(CollectFeesInstruction { deadline }) => {
+                msg!("Instruction: CollectFees");
+                Self::check_deadline(deadline)?;
+                Self::process_collect_fees(program_id, accounts)
+            }
+            AmmInstruction::ClosePosition(instruction) => {
+                msg!("Instruction: ClosePosition");
+                Self::check_deadline(instruction.deadline)?;
+                Self::process_close_position(program_id, instruction, accounts)
+            }
+            AmmInstruction::FlashLoan(FlashLoanInstruction {
+                amount,
+                deadline,
+                data,
+            }) => {
+                msg!("Instruction: FlashLoan");
+                Self::check_deadline(deadline)?;
+                Self::process_flash_loan(program_id, amount, &data, accounts)
+            }
+            AmmInstruction::FlashSwap(FlashSwapInstruction {
+                amount_out,
+                deadline,
+                data,
+            }) => {
+                msg!("Instruction: FlashSwap");
+                Self::check_deadline(deadline)?;
+                Self::process_flash_swap(program_id, amount_out, &data, accounts)
+            }
+            AmmInstruction::SetHostFee(SetHostFeeInstruction { host_fee }) => {
+                msg!("Instruction: SetHostFee");
+                Self::process_set_host_fee(program_id, host_fee, accounts, swap_constraints)
+            }
+            AmmInstruction::SetProtocolFee(SetProtocolFeeInstruction {
+                numerator,
+                denominator,
+            }) => {
+                msg!("Instruction: SetProtocolFee");
+                Self::process_set_protocol_fee(program_id, numerator, denominator, accounts)
+            }
+            AmmInstruction::SwapNativeSol(SwapInstruction {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            }) => {
+                msg!("Instruction: SwapNativeSol");
+                Self::check_deadline(deadline)?;
+                Self::process_native_sol(
+                    program_id,
+                    accounts,
+                    10,
+                    &[(3, amount_in), (6, 0)],
+                    |accounts| {
+                        Self::process_swap(program_id, amount_in, minimum_amount_out, accounts)
+                    },
+                )
+            }
+            AmmInstruction::DepositAllTokenTypesNativeSol(DepositInstruction {
+                pool_token_amount,
+                maximum_token_a_amount,
+                maximum_token_b_amount,
+                deadline,
+            }) => {
+                msg!("Instruction: DepositAllTokenTypesNativeSol");
+                Self::check_deadline(deadline)?;
+                Self::process_native_sol(
+                    program_id,
+                    accounts,
+                    9,
+                    &[(3, maximum_token_a_amount), (4, maximum_token_b_amount)],
+                    |accounts| {
+                        Self::process_deposit_all_token_types(
+                            program_id,
+                            pool_token_amount,
+                            maximum_token_a_amount,
+                            maximum_token_b_amount,
+                            accounts,
+                        )
+                    },
+                )
+            }
+            AmmInstruction::WithdrawAllTokenTypesNativeSol(WithdrawInstruction {
+                pool_token_amount,
+                minimum_token_a_amount,
+                minimum_token_b_amount,
+                deadline,
+            }) => {
+                msg!("Instruction: WithdrawAllTokenTypesNativeSol");
+                Self::check_deadline(deadline)?;
+                Self::process_native_sol(program_id, accounts, 9, &[(7, 0), (8, 0)], |accounts| {
+                    Self::process_withdraw_all_token_types(
+                        program_id,
+                        pool_token_amount,
+                        minimum_token_a_amount,
+                        minimum_token_b_amount,
+                        accounts,
+                    )
+                })
+            }
+            AmmInstruction::SetFixedFeeAccounts => {
+                msg!("Instruction: SetFixedFeeAccounts");
+                Self::process_set_fixed_fee_accounts(program_id, accounts)
+            }
+            AmmInstruction::DepositSingleTokenTypeExactAmountInNativeSol(
+                DepositSingleTokenTypeExactAmountIn {
+                    source_token_amount,
+                    minimum_pool_token_amount,
+                    deadline,
+                },
+            ) => {
+                msg!("Instruction: DepositSingleTokenTypeExactAmountInNativeSol");
+                Self::check_deadline(deadline)?;
+                Self::process_native_sol(
+                    program_id,
+                    accounts,
+                    8,
+                    &[(3, source_token_amount)],
+                    |accounts| {
+                        Self::process_deposit_single_token_type_exact_amount_in(
+                            program_id,
+                            source_token_amount,
+                            minimum_pool_token_amount,
+                            accounts,
+                        )
+                    },
+                )
+            }
+            AmmInstruction::WithdrawSingleTokenTypeExactAmountOutNativeSol(
+                WithdrawSingleTokenTypeExactAmountOut {
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    deadline,
+                },
+            ) => {
+                msg!("Instruction: WithdrawSingleTokenTypeExactAmountOutNativeSol");
+                Self::check_deadline(deadline)?;
+                Self::process_native_sol(program_id, accounts, 10, &[(7, 0)], |accounts| {
+                    Self::process_withdraw_single_token_type_exact_amount_out(
+                        program_id,
+                        destination_token_amount,
+                        maximum_pool_token_amount,
+                        accounts,
+                    )
+                })
+            }
+            AmmInstruction::SwapExactAmountOutNativeSol(SwapExactAmountOutInstruction {
+                amount_out,
+                maximum_amount_in,
+                deadline,
+            }) => {
+                msg!("Instruction: SwapExactAmountOutNativeSol");
+                Self::check_deadline(deadline)?;
+                Self::process_native_sol(
+                    program_id,
+                    accounts,
+                    10,
+                    &[(3, maximum_amount_in), (6, 0)],
+                    |accounts| {
+                        Self::process_swap_exact_amount_out(
+                            program_id,
+                            amount_out,
+                            maximum_amount_in,
+                            accounts,
+                        )
+                    },
+                )
+            }
+        }
+    }
+}
+
+impl PrintProgramError for AmmError {
+    fn print<E>(&self)
+    where
+        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
+    {
+        match self {
+            AmmError::AlreadyInUse => msg!("Error: Swap account already in use"),
+            AmmError::InvalidProgramAddress => {
+                msg!("Error: Invalid program address generated from nonce and key")
+            }
+            AmmError::InvalidOwner => {
+                msg!("Error: The input account owner is not the program address")
+            }
+            AmmError::InvalidOutputOwner => {
+                msg!("Error: Output pool account owner cannot be the program address")
+            }
+            AmmError::ExpectedMint => msg!("Error: Deserialized account is not an SPL Token mint"),
+            AmmError::ExpectedAccount => {
+                msg!("Error: Deserialized account is not an SPL Token account")
+            }
+            AmmError::EmptySupply => msg!("Error: Input token account empty"),
+            AmmError::InvalidSupply => msg!("Error: Pool token mint has a non-zero supply"),
+            AmmError::RepeatedMint => msg!("Error: Swap input token accounts have the same mint"),
+            AmmError::InvalidDelegate => msg!("Error: Token account has a delegate"),
+            AmmError::InvalidInput => msg!("Error: InvalidInput"),
+            AmmError::IncorrectSwapAccount => {
+                msg!("Error: Address of the provided swap token account is incorrect")
+            }
+            AmmError::IncorrectPoolMint => {
+                msg!("Error: Address of the provided pool token mint is incorrect")
+            }
+            AmmError::InvalidOutput => msg!("Error: InvalidOutput"),
+            AmmError::CalculationFailure => msg!("Error: CalculationFailure"),
+            AmmError::InvalidInstruction => msg!("Error: InvalidInstruction"),
+            AmmError::ExceededSlippage => {
+                msg!("Error: Swap instruction exceeds desired slippage limit")
+            }
+            AmmError::InvalidCloseAuthority => msg!("Error: Token account has a close authority"),
+            AmmError::InvalidFreezeAuthority => {
+                msg!("Error: Pool token mint has a freeze authority")
+            }
+            AmmError::IncorrectFeeAccount => msg!("Error: Pool fee token account incorrect"),
+            AmmError::ZeroTradingTokens => {
+                msg!("Error: Given pool token amount results in zero trading tokens")
+            }
+            AmmError::FeeCalculationFailure => msg!(
+                "Error: The fee calculation failed due to overflow, underflow, or unexpected 0"
+            ),
+            AmmError::ConversionFailure => msg!("Error: Conversion to or from u64 failed."),
+            AmmError::InvalidFee => {
+                msg!("Error: The provided fee does not match the program owner's constraints")
+            }
+            AmmError::IncorrectTokenProgramId => {
+                msg!("Error: The provided token program does not match the token program expected by the swap")
+            }
+            AmmError::UnsupportedCurveType => {
+                msg!("Error: The provided curve type is not supported by the program owner")
+            }
+            AmmError::InvalidCurve => {
+                msg!("Error: The provided curve parameters are invalid")
+            }
+            AmmError::UnsupportedCurveOperation => {
+                msg!("Error: The operation cannot be performed on the given curve")
+            }
+            AmmError::IncorrectConfigAccount => msg!("Error: Program config account incorrect"),
+            AmmError::InvalidAdmin => msg!("Error: Signer is not the program config admin"),
+            AmmError::PoolPaused => msg!("Error: Pool is paused"),
+            AmmError::IncorrectMarketAccount => {
+                msg!("Error: Market account does not match the pool")
+            }
+            AmmError::IncorrectOpenOrdersAccount => {
+                msg!("Error: Open orders account does not match the pool")
+            }
+            AmmError::IncorrectMarketOwner => msg!("Error: Market is not owned by the dex program"),
+            AmmError::IncorrectMarketMint => {
+                msg!("Error: Market mints do not match the pool tokens")
+            }
+            AmmError::IncorrectOpenOrdersOwner => {
+                msg!("Error: Open orders account is not owned by the swap authority")
+            }
+            AmmError::InvalidAmpRamp => msg!("Error: Invalid amplification ramp"),
+            AmmError::InvalidTokenCount => {
+                msg!("Error: Invalid number of tokens for a multi token pool")
+            }
+            AmmError::InsufficientLiquidity => msg!("Error: Not enough liquidity for the swap"),
+            AmmError::InvalidTickRange => msg!("Error: Invalid tick range"),
+            AmmError::TooManyTicks => msg!("Error: Too many initialized ticks in the pool"),
+            AmmError::IncorrectPositionAccount => {
+                msg!("Error: Position account does not match the pool or owner")
+            }
+            AmmError::PoolLocked => msg!("Error: Pool is locked by a flash loan"),
+            AmmError::FlashLoanNotRepaid => msg!("Error: Flash loan not repaid"),
+            AmmError::FlashSwapNotPaid => msg!("Error: Flash swap not paid for"),
+            AmmError::Expired => msg!("Error: Transaction deadline expired"),
+            AmmError::IncorrectNativeSolAccount => msg!("Error: Native SOL account incorrect"),
+            AmmError::IncorrectDexProgram => msg!("Error: Dex program is not the Serum dex"),
+            AmmError::UnsupportedMintExtension => {
+                msg!("Error: Mint extension not supported by the pool")
+            }
+        }
+    }
+}
+
+fn to_u128(val: u64) -> Result<u128, AmmError> {
+    Ok(u128::from(val))
+}
+
+fn to_u64(val: u128) -> Result<u64, AmmError> {
+    val.try_into().map_err(|_| AmmError::ConversionFailure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        curve::calculator::{CurveCalculator, INITIAL_SWAP_POOL_AMOUNT},
+        curve::{
+            base::CurveType, constant_price::ConstantPriceCurve,
+            constant_product::ConstantProductCurve, offset::OffsetCurve,
+            stable::{StableCurve, MIN_RAMP_DURATION},
+            weighted::WeightedCurve,
+            multi::{MultiStableCurve, MultiWeightedCurve},
+        },
+        amm_instruction::{
+            deposit_all_token_types, deposit_single_token_type_exact_amount_in, initialize,
+            initialize_config, place_order, ramp_amp, route_swap, set_fee_wallet, set_fees,
+            set_fixed_fee_accounts, set_pool_status,
+            settle_funds, stop_ramp_amp, swap, swap_exact_amount_out,
+            withdraw_all_token_types, RouteSwapLeg, SerumMarketAccounts,
+            withdraw_single_token_type_exact_amount_out, initialize_multi, multi_swap,
+            multi_deposit_all_token_types, multi_withdraw_all_token_types,
+            multi_deposit_single_token_type_exact_amount_in,
+            multi_withdraw_single_token_type_exact_amount_out, initialize_concentrated,
+            open_position, concentrated_swap, collect_fees, close_position, flash_loan,
+            flash_swap, set_host_fee, set_protocol_fee, native_sol, with_token_2022,
+        },
+        amm_stats::SwapV1,
+        oracle::{twap, OBSERVATION_PERIOD},
+    };
+    use serum_dex::instruction::MarketInstruction;
+    use solana_program::{
+        entrypoint::SUCCESS,
+        instruction::{AccountMeta, Instruction},
+        program_stubs,
+        rent::Rent,
+        system_program,
+    };
+    use std::cell::{Cell, RefCell};
+    use solana_sdk::account::{create_account_for_test, create_is_signer_account_infos, Account};
+    use spl_token_2022::extension::transfer_fee::instruction::initialize_transfer_fee_config;
+    use spl_token::{
+        error::TokenError,
+        instruction::{
+            approve, initialize_account, initialize_mint, mint_to, revoke, set_authority,
+            AuthorityType,
+        },
+    };
+
+    // Test program id for the swap program.
+    const SWAP_PROGRAM_ID: Pubkey = Pubkey::new_from_array([2u8; 32]);
+
+    // Test program id for the receiver of flash loans.
+    const FLASH_LOAN_RECEIVER_ID: Pubkey = Pubkey::new_from_array([3u8; 32]);
+
+    // `Clock` unix timestamp seen by the program in tests, per test thread
+    thread_local! {
+        static TEST_UNIX_TIMESTAMP: Cell<i64> = const { Cell::new(0) };
+        static TEST_SLOT: Cell<u64> = const { Cell::new(0) };
+        static TEST_EVENTS: RefCell<Vec<AmmEvent>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn set_unix_timestamp(unix_timestamp: i64) {
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(unix_timestamp));
+    }
+
+    fn set_slot(slot: u64) {
+        TEST_SLOT.with(|test_slot| test_slot.set(slot));
+    }
+
+    // Events logged by the instructions processed on this thread since the
+    // last call
+    fn take_events() -> Vec<AmmEvent> {
+        TEST_EVENTS.with(|events| events.take())
+    }
+
+    struct TestSyscallStubs {}
+    impl program_stubs::SyscallStubs for TestSyscallStubs {
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            let clock = Clock {
+                slot: TEST_SLOT.with(|slot| slot.get()),
+                unix_timestamp: TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.get()),
+                ..Clock::default()
+            };
+            unsafe {
+                *(var_addr as *mut Clock) = clock;
+            }
+            SUCCESS
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe {
+                *(var_addr as *mut Rent) = Rent::default();
+            }
+            SUCCESS
+        }
+
+        fn sol_log_data(&self, fields: &[&[u8]]) {
+            TEST_EVENTS.with(|events| {
+                events
+                    .borrow_mut()
+                    .extend(fields.iter().map(|field| AmmEvent::unpack(field).unwrap()))
+            });
+        }
+
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            msg!("TestSyscallStubs::sol_invoke_signed()");
+
+            let mut new_account_infos = vec![];
+
+            // mimic check for token program in accounts
+            if instruction.program_id != solana_program::system_program::id()
+                && instruction.data != MarketInstruction::InitOpenOrders.pack()
+                && !account_infos
+                    .iter()
+                    .any(|x| *x.key == spl_token::id() || *x.key == spl_token_2022::id())
+            {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            for meta in instruction.accounts.iter() {
+                for account_info in account_infos.iter() {
+                    if meta.pubkey == *account_info.key {
+                        let mut new_account_info = account_info.clone();
+                        for seeds in signers_seeds.iter() {
+                            let signer =
+                                Pubkey::create_program_address(seeds, &SWAP_PROGRAM_ID).unwrap();
+                            if *account_info.key == signer {
+                                new_account_info.is_signer = true;
+                            }
+                        }
+                        new_account_infos.push(new_account_info);
+                    }
+                }
+            }
+
+            if instruction.program_id == FLASH_LOAN_RECEIVER_ID {
+                return flash_loan_receiver(&new_account_infos, &instruction.data);
+            }
+            if instruction.data == MarketInstruction::InitOpenOrders.pack() {
+                return init_open_orders_stub(&new_account_infos);
+            }
+            if instruction.program_id == solana_program::system_program::id() {
+                return system_program_stub(&new_account_infos, &instruction.data);
+            }
+            if instruction.program_id == spl_token_2022::id() {
+                return spl_token_2022::processor::Processor::process(
+                    &instruction.program_id,
+                    &new_account_infos,
+                    &instruction.data,
+                );
+            }
+            spl_token::processor::Processor::process(
+                &instruction.program_id,
+                &new_account_infos,
+                &instruction.data,
+            )
+        }
+    }
+
+    // Initializes open orders like the dex does, for the market and the owner
+    // signing
+    fn init_open_orders_stub(account_infos: &[AccountInfo]) -> ProgramResult {
+        let (open_orders_info, owner_info, market_info) =
+            (&account_infos[0], &account_infos[1], &account_infos[2]);
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let open_orders_account =
+            create_open_orders(open_orders_info.owner, market_info.key, owner_info.key, 0, 0);
+        open_orders_info
+            .data
+            .borrow_mut()
+            .copy_from_slice(&open_orders_account.data);
+        Ok(())
+    }
+
+    // Moves the lamports of system program transfers and account creations,
+    // and assigns allocated accounts, whose data the test sizes beforehand
+    fn system_program_stub(account_infos: &[AccountInfo], data: &[u8]) -> ProgramResult {
+        let to_info = account_infos.last().unwrap();
+        let instruction = u32::from_le_bytes(data[..4].try_into().unwrap());
+        // only transfers do not need the signature of the account they change
+        if instruction != 2 && !to_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let lamports = match instruction {
+            // CreateAccount
+            0 => {
+                let space = u64::from_le_bytes(data[12..20].try_into().unwrap());
+                let owner = Pubkey::try_from(&data[20..52]).unwrap();
+                if to_info.lamports() > 0 {
+                    return Err(ProgramError::AccountAlreadyInitialized);
+                }
+                assert_eq!(to_info.data_len() as u64, space);
+                to_info.assign(&owner);
+                u64::from_le_bytes(data[4..12].try_into().unwrap())
+            }
+            // Assign
+            1 => {
+                to_info.assign(&Pubkey::try_from(&data[4..36]).unwrap());
+                return Ok(());
+            }
+            // Transfer
+            2 => u64::from_le_bytes(data[4..12].try_into().unwrap()),
+            // Allocate
+            8 => {
+                let space = u64::from_le_bytes(data[4..12].try_into().unwrap());
+                assert_eq!(to_info.data_len() as u64, space);
+                return Ok(());
+            }
+            _ => return Err(ProgramError::InvalidInstructionData),
+        };
+        let from_info = &account_infos[0];
+        if !from_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let from_lamports = from_info
+            .lamports()
+            .checked_sub(lamports)
+            .ok_or(ProgramError::InsufficientFunds)?;
+        **from_info.lamports.borrow_mut() = from_lamports;
+        **to_info.lamports.borrow_mut() += lamports;
+        Ok(())
+    }
+
+    // Pays the amount given in its data into the pool, from the account given
+    // after the authority or else from the loan destination, with the
+    // authority given after the accounts of the loan
+    fn flash_loan_receiver(account_infos: &[AccountInfo], data: &[u8]) -> ProgramResult {
+        let repaid_amount = u64::from_le_bytes(data.try_into().unwrap());
+        let (swap_token_info, authority_info) = (&account_infos[0], &account_infos[3]);
+        let payer_info = account_infos.get(4).unwrap_or(&account_infos[1]);
+        let instruction = spl_token::instruction::transfer(
+            &spl_token::id(),
+            payer_info.key,
+            swap_token_info.key,
+            authority_info.key,
+            &[],
+            repaid_amount,
+        )?;
+        spl_token::processor::Processor::process(
+            &instruction.program_id,
+            &[
+                payer_info.clone(),
+                swap_token_info.clone(),
+                authority_info.clone(),
+            ],
+            &instruction.data,
+        )
+    }
+
+    fn test_syscall_stubs() {
+        use std::sync::Once;
+        static ONCE: Once = Once::new();
+
+        ONCE.call_once(|| {
+            program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs {}));
+        });
+    }
+
+    struct SwapAccountInfo {
+        nonce: u8,
+        authority_key: Pubkey,
+        fees: Fees,
+        swap_curve: SwapCurve,
+        swap_key: Pubkey,
+        swap_account: Account,
+        amm_id_key: Pubkey,
+        dex_program_id: Pubkey,
+        market_key: Pubkey,
+        market_account: Account,
+        open_orders_key: Pubkey,
+        open_orders_account: Account,
+        admin_key: Pubkey,
+        config_key: Pubkey,
+        config_account: Account,
+        pool_mint_key: Pubkey,
+        pool_mint_account: Account,
+        fee_wallet_key: Pubkey,
+        fee_a_key: Pubkey,
+        fixed_fee_account_a: Account,
+        fee_b_key: Pubkey,
+        fixed_fee_account_b: Account,
+        pool_token_key: Pubkey,
+        pool_token_account: Account,
+        pool_fee_key: Pubkey,
+        pool_fee_account: Account,
+        protocol_fee_key: Pubkey,
+        protocol_fee_account: Account,
+        token_a_key: Pubkey,
+        token_a_account: Account,
+        token_a_mint_key: Pubkey,
+        token_a_mint_account: Account,
+        token_b_key: Pubkey,
+        token_b_account: Account,
+        token_b_mint_key: Pubkey,
+        token_b_mint_account: Account,
+    }
+
+    impl SwapAccountInfo {
+        pub fn new(
+            user_key: &Pubkey,
+            fees: Fees,
+            swap_curve: SwapCurve,
+            token_a_amount: u64,
+            token_b_amount: u64,
+        ) -> Self {
+            let swap_key = Pubkey::new_unique();
+            let swap_account = Account::new(0, SwapVersion::LATEST_LEN, &SWAP_PROGRAM_ID);
+            let (authority_key, nonce) =
+                Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+            let admin_key = Pubkey::new_unique();
+            let fee_wallet_key = Pubkey::new_unique();
+            let (config_key, config_account) = create_config(&admin_key, &fee_wallet_key);
+
+            let (pool_mint_key, mut pool_mint_account) =
+                create_mint(&spl_token::id(), &authority_key, None);
+            let (pool_token_key, pool_token_account) = mint_token(
+                &spl_token::id(),
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                user_key,
+                0,
+            );
+            let (pool_fee_key, pool_fee_account) = mint_token(
+                &spl_token::id(),
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                &fee_wallet_key,
+                0,
+            );
+            let (protocol_fee_key, protocol_fee_account) = mint_token(
+                &spl_token::id(),
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                &fee_wallet_key,
+                0,
+            );
+
+            let (token_a_mint_key, mut token_a_mint_account) =
+            create_mint(&spl_token::id(), user_key, None);
+            let (token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &token_a_mint_key,
+                &mut token_a_mint_account,
+                user_key,
+                &authority_key,
+                token_a_amount,
+            );
+            let (fee_a_key, fixed_fee_account_a) = mint_token(
+                &spl_token::id(),
+                &token_a_mint_key,
+                &mut token_a_mint_account,
+                user_key,
+                &fee_wallet_key,
+                0,
+            );
+
+            let (token_b_mint_key, mut token_b_mint_account) =
+                create_mint(&spl_token::id(), user_key, None);
+            let dex_program_id = serum_dex_program::id();
+            let market_key = Pubkey::new_unique();
+            let market_account = create_market(
+                &dex_program_id,
+                &market_key,
+                &token_a_mint_key,
+                &token_b_mint_key,
+            );
+            let (token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &token_b_mint_key,
+                &mut token_b_mint_account,
+                user_key,
+                &authority_key,
+                token_b_amount,
+            );
+            let (fee_b_key, fixed_fee_account_b) = mint_token(
+                &spl_token::id(),
+                &token_b_mint_key,
+                &mut token_b_mint_account,
+                user_key,
+                &fee_wallet_key,
+                0,
+            );
+            SwapAccountInfo {
+                nonce,
+                authority_key,
+                fees,
+                swap_curve,
+                swap_key,
+                swap_account,
+                amm_id_key: Pubkey::new_unique(),
+                dex_program_id,
+                market_key,
+                market_account,
+                open_orders_key: Pubkey::default(),
+                open_orders_account: Account::default(),
+                admin_key,
+                config_key,
+                config_account,
+                pool_mint_key,
+                pool_mint_account,
+                fee_wallet_key,
+                fee_a_key,
+                fixed_fee_account_a,
+                fee_b_key,
+                fixed_fee_account_b,
+                pool_token_key,
+                pool_token_account,
+                pool_fee_key,
+                pool_fee_account,
+                protocol_fee_key,
+                protocol_fee_account,
+                token_a_key,
+                token_a_account,
+                token_a_mint_key,
+                token_a_mint_account,
+                token_b_key,
+                token_b_account,
+                token_b_mint_key,
+                token_b_mint_account,
+            }
+        }
+
+        pub fn initialize_swap(&mut self) -> ProgramResult {
+            do_process_instruction(
+                initialize(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &self.amm_id_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_mint_key,
+                    &self.fee_a_key,
+                    &self.fee_b_key,
+                    &self.pool_token_key,
+                    &self.dex_program_id,
+                    &self.market_key,
+                    &self.open_orders_key,
+                    &self.config_key,
+                    self.nonce,
+                    self.fees.clone(),
+                    self.swap_curve.clone(),
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    &mut self.fixed_fee_account_a,
+                    &mut self.fixed_fee_account_b,
+                    &mut self.pool_token_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.market_account,
+                    &mut self.open_orders_account,
+                    &mut self.config_account,
+                    &mut create_account_for_test(&Rent::free()),
+                ],
+            )
+        }
+
+        pub fn set_config_admin(&mut self, admin_key: &Pubkey) {
+            let (_config_key, config_account) = create_config(admin_key, &self.fee_wallet_key);
+            self.admin_key = *admin_key;
+            self.config_account = config_account;
+        }
+
+        pub fn set_pool_status(&mut self, admin_key: &Pubkey, status: PoolStatus) -> ProgramResult {
+            do_process_instruction(
+                set_pool_status(
+                    &SWAP_PROGRAM_ID,
+                    &self.swap_key,
+                    &self.config_key,
+                    admin_key,
+                    status,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut self.config_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        pub fn set_protocol_fee(
+            &mut self,
+            admin_key: &Pubkey,
+            numerator: u64,
+            denominator: u64,
+        ) -> ProgramResult {
+            do_process_instruction(
+                set_protocol_fee(
+                    &SWAP_PROGRAM_ID,
+                    &self.swap_key,
+                    &self.config_key,
+                    admin_key,
+                    ProtocolFee {
+                        numerator,
+                        denominator,
+                        fee_account: self.protocol_fee_key,
+                        root_k_last: 0,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut self.config_account,
+                    &mut Account::default(),
+                    &mut self.protocol_fee_account,
+                ],
+            )
+        }
+
+        fn protocol_fee_enabled(&self) -> bool {
+            SwapVersion::unpack_version(&self.swap_account.data)
+                .map(|token_swap| token_swap.protocol_fee().is_enabled())
+                .unwrap_or(false)
+        }
+
+        pub fn ramp_amp(
+            &mut self,
+            admin_key: &Pubkey,
+            target_amp: u64,
+            stop_ramp_ts: i64,
+        ) -> ProgramResult {
+            do_process_instruction(
+                ramp_amp(
+                    &SWAP_PROGRAM_ID,
+                    &self.swap_key,
+                    &self.config_key,
+                    admin_key,
+                    target_amp,
+                    stop_ramp_ts,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut self.config_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        pub fn stop_ramp_amp(&mut self, admin_key: &Pubkey) -> ProgramResult {
+            do_process_instruction(
+                stop_ramp_amp(&SWAP_PROGRAM_ID, &self.swap_key, &self.config_key, admin_key)
+                    .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut self.config_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        pub fn setup_token_accounts(
+            &mut self,
+            mint_owner: &Pubkey,
+            account_owner: &Pubkey,
+            a_amount: u64,
+            b_amount: u64,
+            pool_amount: u64,
+        ) -> (Pubkey, Account, Pubkey, Account, Pubkey, Account) {
+            let (token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &self.token_a_mint_key,
+                &mut self.token_a_mint_account,
+                mint_owner,
+                account_owner,
+                a_amount,
+            );
+            let (token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &self.token_b_mint_key,
+                &mut self.token_b_mint_account,
+                mint_owner,
+                account_owner,
+                b_amount,
+            );
+            let (pool_key, pool_account) = mint_token(
+                &spl_token::id(),
+                &self.pool_mint_key,
+                &mut self.pool_mint_account,
+                &self.authority_key,
+                account_owner,
+                pool_amount,
+            );
+            (
+                token_a_key,
+                token_a_account,
+                token_b_key,
+                token_b_account,
+                pool_key,
+                pool_account,
+            )
+        }
+
+        fn get_token_account(&self, account_key: &Pubkey) -> &Account {
+            if *account_key == self.token_a_key {
+                return &self.token_a_account;
+            } else if *account_key == self.token_b_key {
+                return &self.token_b_account;
+            }
+            panic!("Could not find matching swap token account");
+        }
+
+        fn set_token_account(&mut self, account_key: &Pubkey, account: Account) {
+            if *account_key == self.token_a_key {
+                self.token_a_account = account;
+                return;
+            } else if *account_key == self.token_b_key {
+                self.token_b_account = account;
+                return;
+            }
+            panic!("Could not find matching swap token account");
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn swap(
+            &mut self,
+            user_key: &Pubkey,
+            user_source_key: &Pubkey,
+            mut user_source_account: &mut Account,
+            swap_source_key: &Pubkey,
+            swap_destination_key: &Pubkey,
+            user_destination_key: &Pubkey,
+            mut user_destination_account: &mut Account,
+            amount_in: u64,
+            minimum_amount_out: u64,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            // approve moving from user source account
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    user_source_key,
+                    &user_transfer_key,
+                    user_key,
+                    &[],
+                    amount_in,
+                )
+                .unwrap(),
+                vec![
+                    &mut user_source_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            let mut swap_source_account = self.get_token_account(swap_source_key).clone();
+            let mut swap_destination_account = self.get_token_account(swap_destination_key).clone();
+            let (fixed_fee_key, mut fixed_fee_account) = if *swap_source_key == self.token_a_key {
+                (self.fee_a_key, self.fixed_fee_account_a.clone())
+            } else {
+                (self.fee_b_key, self.fixed_fee_account_b.clone())
+            };
+
+            // perform the swap
+            let mut instruction = swap(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &user_transfer_key,
+                user_source_key,
+                swap_source_key,
+                swap_destination_key,
+                user_destination_key,
+                &self.pool_mint_key,
+                &fixed_fee_key,
+                &self.fee_wallet_key,
+                &self.config_key,
+                SwapInstruction {
+                    amount_in,
+                    minimum_amount_out,
+                    deadline: None,
+                },
+            )
+            .unwrap();
+            instruction
+                .accounts
+                .push(AccountMeta::new_readonly(self.open_orders_key, false));
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut user_source_account,
+                    &mut swap_source_account,
+                    &mut swap_destination_account,
+                    &mut user_destination_account,
+                    &mut self.pool_mint_account,
+                    &mut fixed_fee_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.config_account,
+                    &mut self.open_orders_account,
+                ],
+            )?;
+
+            self.set_token_account(swap_source_key, swap_source_account);
+            self.set_token_account(swap_destination_key, swap_destination_account);
+            if fixed_fee_key == self.fee_a_key {
+                self.fixed_fee_account_a = fixed_fee_account;
+            } else {
+                self.fixed_fee_account_b = fixed_fee_account;
+            }
+
+            Ok(())
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn swap_exact_amount_out(
+            &mut self,
+            user_key: &Pubkey,
+            user_source_key: &Pubkey,
+            mut user_source_account: &mut Account,
+            swap_source_key: &Pubkey,
+            swap_destination_key: &Pubkey,
+            user_destination_key: &Pubkey,
+            mut user_destination_account: &mut Account,
+            amount_out: u64,
+            maximum_amount_in: u64,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            // approve moving from user source account
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    user_source_key,
+                    &user_transfer_key,
+                    user_key,
+                    &[],
+                    maximum_amount_in,
+                )
+                .unwrap(),
+                vec![
+                    &mut user_source_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            let mut swap_source_account = self.get_token_account(swap_source_key).clone();
+            let mut swap_destination_account = self.get_token_account(swap_destination_key).clone();
+            let (fixed_fee_key, mut fixed_fee_account) = if *swap_source_key == self.token_a_key {
+                (self.fee_a_key, self.fixed_fee_account_a.clone())
+            } else {
+                (self.fee_b_key, self.fixed_fee_account_b.clone())
+            };
+
+            // perform the swap
+            do_process_instruction(
+                swap_exact_amount_out(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_key,
+                    user_source_key,
+                    swap_source_key,
+                    swap_destination_key,
+                    user_destination_key,
+                    &self.pool_mint_key,
+                    &fixed_fee_key,
+                    &self.fee_wallet_key,
+                    &self.config_key,
+                    SwapExactAmountOutInstruction {
+                        amount_out,
+                        maximum_amount_in,
+                        deadline: None,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut user_source_account,
+                    &mut swap_source_account,
+                    &mut swap_destination_account,
+                    &mut user_destination_account,
+                    &mut self.pool_mint_account,
+                    &mut fixed_fee_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.config_account,
+                ],
+            )?;
+
+            self.set_token_account(swap_source_key, swap_source_account);
+            self.set_token_account(swap_destination_key, swap_destination_account);
+            if fixed_fee_key == self.fee_a_key {
+                self.fixed_fee_account_a = fixed_fee_account;
+            } else {
+                self.fixed_fee_account_b = fixed_fee_account;
+            }
+
+            Ok(())
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn deposit_all_token_types(
+            &mut self,
+            depositor_key: &Pubkey,
+            depositor_token_a_key: &Pubkey,
+            mut depositor_token_a_account: &mut Account,
+            depositor_token_b_key: &Pubkey,
+            mut depositor_token_b_account: &mut Account,
+            depositor_pool_key: &Pubkey,
+            mut depositor_pool_account: &mut Account,
+            pool_token_amount: u64,
+            maximum_token_a_amount: u64,
+            maximum_token_b_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority = Pubkey::new_unique();
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    depositor_token_a_key,
+                    &user_transfer_authority,
+                    depositor_key,
+                    &[],
+                    maximum_token_a_amount,
+                )
+                .unwrap(),
+                vec![
+                    &mut depositor_token_a_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    depositor_token_b_key,
+                    &user_transfer_authority,
+                    depositor_key,
+                    &[],
+                    maximum_token_b_amount,
+                )
+                .unwrap(),
+                vec![
+                    &mut depositor_token_b_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            let mut instruction = deposit_all_token_types(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &user_transfer_authority,
+                depositor_token_a_key,
+                depositor_token_b_key,
+                &self.token_a_key,
+                &self.token_b_key,
+                &self.pool_mint_key,
+                depositor_pool_key,
+                DepositInstruction {
+                    pool_token_amount,
+                    maximum_token_a_amount,
+                    maximum_token_b_amount,
+                    deadline: None,
+                },
+            )
+            .unwrap();
+            let protocol_fee_accounts: Vec<&mut Account> =
+                if self.push_protocol_fee_accounts(&mut instruction) {
+                    vec![
+                        &mut self.open_orders_account,
+                        &mut self.protocol_fee_account,
+                    ]
+                } else {
+                    Vec::new()
+                };
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut depositor_token_a_account,
+                    &mut depositor_token_b_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    &mut depositor_pool_account,
+                    &mut Account::default(),
+                ]
+                .into_iter()
+                .chain(protocol_fee_accounts)
+                .collect(),
+            )
+        }
+
+        /// Passes the open orders and protocol fee accounts to a deposit of a
+        /// pool taking a protocol fee
+        fn push_protocol_fee_accounts(&self, instruction: &mut Instruction) -> bool {
+            if !self.protocol_fee_enabled() {
+                return false;
+            }
+            instruction
+                .accounts
+                .push(AccountMeta::new_readonly(self.open_orders_key, false));
+            instruction
+                .accounts
+                .push(AccountMeta::new(self.protocol_fee_key, false));
+            true
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn withdraw_all_token_types(
+            &mut self,
+            user_key: &Pubkey,
+            pool_key: &Pubkey,
+            mut pool_account: &mut Account,
+            token_a_key: &Pubkey,
+            mut token_a_account: &mut Account,
+            token_b_key: &Pubkey,
+            mut token_b_account: &mut Account,
+            pool_token_amount: u64,
+            minimum_token_a_amount: u64,
+            minimum_token_b_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority_key = Pubkey::new_unique();
+            // approve user transfer authority to take out pool tokens
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    pool_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    pool_token_amount,
+                )
+                .unwrap(),
+                vec![
+                    &mut pool_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            // withdraw token a and b correctly
+            let mut instruction = withdraw_all_token_types(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &user_transfer_authority_key,
+                &self.pool_mint_key,
+                pool_key,
+                &self.token_a_key,
+                &self.token_b_key,
+                token_a_key,
+                token_b_key,
+                &self.config_key,
+                &self.pool_fee_key,
+                WithdrawInstruction {
+                    pool_token_amount,
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                    deadline: None,
+                },
+            )
+            .unwrap();
+            instruction
+                .accounts
+                .push(AccountMeta::new_readonly(self.open_orders_key, false));
+            let protocol_fee = self.protocol_fee_enabled();
+            // the pool fee account may withdraw from itself, and then is
+            // passed twice
+            let mut source_pool_fee_account = self.pool_fee_account.clone();
+            let pool_fee_account = if *pool_key == self.pool_fee_key {
+                &mut source_pool_fee_account
+            } else {
+                &mut self.pool_fee_account
+            };
+            let protocol_fee_account = if protocol_fee {
+                instruction
+                    .accounts
+                    .push(AccountMeta::new(self.protocol_fee_key, false));
+                Some(&mut self.protocol_fee_account)
+            } else {
+                None
+            };
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.pool_mint_account,
+                    &mut pool_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut token_a_account,
+                    &mut token_b_account,
+                    &mut Account::default(),
+                    &mut self.config_account,
+                    pool_fee_account,
+                    &mut self.open_orders_account,
+                ]
+                .into_iter()
+                .chain(protocol_fee_account)
+                .collect(),
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn deposit_single_token_type_exact_amount_in(
+            &mut self,
+            depositor_key: &Pubkey,
+            deposit_account_key: &Pubkey,
+            mut deposit_token_account: &mut Account,
+            deposit_pool_key: &Pubkey,
+            mut deposit_pool_account: &mut Account,
+            source_token_amount: u64,
+            minimum_pool_token_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority_key = Pubkey::new_unique();
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    deposit_account_key,
+                    &user_transfer_authority_key,
+                    depositor_key,
+                    &[],
+                    source_token_amount,
+                )
+                .unwrap(),
+                vec![
+                    &mut deposit_token_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            let mut instruction = deposit_single_token_type_exact_amount_in(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &user_transfer_authority_key,
+                deposit_account_key,
+                &self.token_a_key,
+                &self.token_b_key,
+                &self.pool_mint_key,
+                deposit_pool_key,
+                DepositSingleTokenTypeExactAmountIn {
+                    source_token_amount,
+                    minimum_pool_token_amount,
+                    deadline: None,
+                },
+            )
+            .unwrap();
+            let protocol_fee_accounts: Vec<&mut Account> =
+                if self.push_protocol_fee_accounts(&mut instruction) {
+                    vec![
+                        &mut self.open_orders_account,
+                        &mut self.protocol_fee_account,
+                    ]
+                } else {
+                    Vec::new()
+                };
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut deposit_token_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    &mut deposit_pool_account,
+                    &mut Account::default(),
+                ]
+                .into_iter()
+                .chain(protocol_fee_accounts)
+                .collect(),
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn withdraw_single_token_type_exact_amount_out(
+            &mut self,
+            user_key: &Pubkey,
+            pool_key: &Pubkey,
+            mut pool_account: &mut Account,
+            destination_key: &Pubkey,
+            mut destination_account: &mut Account,
+            destination_token_amount: u64,
+            maximum_pool_token_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority_key = Pubkey::new_unique();
+            // approve user transfer authority to take out pool tokens
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    pool_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    maximum_pool_token_amount,
+                )
+                .unwrap(),
+                vec![
+                    &mut pool_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            let mut instruction = withdraw_single_token_type_exact_amount_out(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &user_transfer_authority_key,
+                &self.pool_mint_key,
+                pool_key,
+                &self.token_a_key,
+                &self.token_b_key,
+                destination_key,
+                &self.fee_a_key,
+                &self.fee_b_key,
+                &self.config_key,
+                &self.pool_fee_key,
+                WithdrawSingleTokenTypeExactAmountOut {
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    deadline: None,
+                },
+            )
+            .unwrap();
+            instruction
+                .accounts
+                .push(AccountMeta::new_readonly(self.open_orders_key, false));
+            let protocol_fee = self.protocol_fee_enabled();
+            let mut source_pool_fee_account = self.pool_fee_account.clone();
+            let pool_fee_account = if *pool_key == self.pool_fee_key {
+                &mut source_pool_fee_account
+            } else {
+                &mut self.pool_fee_account
+            };
+            let protocol_fee_account = if protocol_fee {
+                instruction
+                    .accounts
+                    .push(AccountMeta::new(self.protocol_fee_key, false));
+                Some(&mut self.protocol_fee_account)
+            } else {
+                None
+            };
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.pool_mint_account,
+                    &mut pool_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut destination_account,
+                    &mut self.fixed_fee_account_a,
+                    &mut self.fixed_fee_account_b,
+                    &mut Account::default(),
+                    &mut self.config_account,
+                    pool_fee_account,
+                    &mut self.open_orders_account,
+                ]
+                .into_iter()
+                .chain(protocol_fee_account)
+                .collect(),
+            )
+        }
+    }
+
+    struct MultiSwapAccountInfo {
+        nonce: u8,
+        authority_key: Pubkey,
+        fees: Fees,
+        swap_curve: MultiSwapCurve,
+        swap_key: Pubkey,
+        swap_account: Account,
+        admin_key: Pubkey,
+        config_key: Pubkey,
+        config_account: Account,
+        fee_wallet_key: Pubkey,
+        pool_mint_key: Pubkey,
+        pool_mint_account: Account,
+        pool_token_key: Pubkey,
+        pool_token_account: Account,
+        token_keys: Vec<Pubkey>,
+        token_accounts: Vec<Account>,
+        token_mint_keys: Vec<Pubkey>,
+        token_mint_accounts: Vec<Account>,
+        fee_keys: Vec<Pubkey>,
+        fee_accounts: Vec<Account>,
+    }
+
+    impl MultiSwapAccountInfo {
+        pub fn new(
+            user_key: &Pubkey,
+            fees: Fees,
+            swap_curve: MultiSwapCurve,
+            token_amounts: &[u64],
+        ) -> Self {
+            let swap_key = Pubkey::new_unique();
+            let swap_account = Account::new(0, MultiSwap::ACCOUNT_LEN, &SWAP_PROGRAM_ID);
+            let (authority_key, nonce) =
+                Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+            let admin_key = Pubkey::new_unique();
+            let fee_wallet_key = Pubkey::new_unique();
+            let (config_key, config_account) = create_config(&admin_key, &fee_wallet_key);
+
+            let (pool_mint_key, mut pool_mint_account) =
+                create_mint(&spl_token::id(), &authority_key, None);
+            let (pool_token_key, pool_token_account) = mint_token(
+                &spl_token::id(),
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                user_key,
+                0,
+            );
+
+            let mut accounts = MultiSwapAccountInfo {
+                nonce,
+                authority_key,
+                fees,
+                swap_curve,
+                swap_key,
+                swap_account,
+                admin_key,
+                config_key,
+                config_account,
+                fee_wallet_key,
+                pool_mint_key,
+                pool_mint_account,
+                pool_token_key,
+                pool_token_account,
+                token_keys: vec![],
+                token_accounts: vec![],
+                token_mint_keys: vec![],
+                token_mint_accounts: vec![],
+                fee_keys: vec![],
+                fee_accounts: vec![],
+            };
+            for token_amount in token_amounts {
+                let (mint_key, mut mint_account) = create_mint(&spl_token::id(), user_key, None);
+                let (token_key, token_account) = mint_token(
+                    &spl_token::id(),
+                    &mint_key,
+                    &mut mint_account,
+                    user_key,
+                    &authority_key,
+                    *token_amount,
+                );
+                let (fee_key, fee_account) = mint_token(
+                    &spl_token::id(),
+                    &mint_key,
+                    &mut mint_account,
+                    user_key,
+                    &fee_wallet_key,
+                    0,
+                );
+                accounts.token_keys.push(token_key);
+                accounts.token_accounts.push(token_account);
+                accounts.token_mint_keys.push(mint_key);
+                accounts.token_mint_accounts.push(mint_account);
+                accounts.fee_keys.push(fee_key);
+                accounts.fee_accounts.push(fee_account);
+            }
+            accounts
+        }
+
+        pub fn initialize_swap(&mut self) -> ProgramResult {
+            let mut authority_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut account_infos = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut self.pool_mint_account,
+                &mut self.pool_token_account,
+                &mut token_program_account,
+                &mut self.config_account,
+            ];
+            for (token_account, fee_account) in
+                self.token_accounts.iter_mut().zip(self.fee_accounts.iter_mut())
+            {
+                account_infos.push(token_account);
+                account_infos.push(fee_account);
+            }
+            do_process_instruction(
+                initialize_multi(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &self.pool_mint_key,
+                    &self.pool_token_key,
+                    &self.config_key,
+                    &self.token_keys,
+                    &self.fee_keys,
+                    self.nonce,
+                    self.fees.clone(),
+                    self.swap_curve.clone(),
+                )
+                .unwrap(),
+                account_infos,
+            )
+        }
+
+        pub fn set_pool_status(&mut self, admin_key: &Pubkey, status: PoolStatus) -> ProgramResult {
+            do_process_instruction(
+                set_pool_status(
+                    &SWAP_PROGRAM_ID,
+                    &self.swap_key,
+                    &self.config_key,
+                    admin_key,
+                    status,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut self.config_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        /// One account per pool token holding `token_amount`, and a pool
+        /// token account
+        pub fn setup_token_accounts(
+            &mut self,
+            user_key: &Pubkey,
+            token_amount: u64,
+        ) -> (Vec<Pubkey>, Vec<Account>, Pubkey, Account) {
+            let mut keys = vec![];
+            let mut accounts = vec![];
+            for (mint_key, mint_account) in self
+                .token_mint_keys
+                .iter()
+                .zip(self.token_mint_accounts.iter_mut())
+            {
+                let (key, account) = mint_token(
+                    &spl_token::id(),
+                    mint_key,
+                    mint_account,
+                    user_key,
+                    user_key,
+                    token_amount,
+                );
+                keys.push(key);
+                accounts.push(account);
+            }
+            let (pool_key, pool_account) = mint_token(
+                &spl_token::id(),
+                &self.pool_mint_key,
+                &mut self.pool_mint_account,
+                &self.authority_key,
+                user_key,
+                0,
+            );
+            (keys, accounts, pool_key, pool_account)
+        }
+
+        fn swap_token_amounts(&self) -> Vec<u128> {
+            user_token_amounts(&self.token_accounts)
+        }
+
+        fn pool_supply(&self) -> u128 {
+            u128::from(
+                spl_token::state::Mint::unpack(&self.pool_mint_account.data)
+                    .unwrap()
+                    .supply,
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn swap(
+            &mut self,
+            user_key: &Pubkey,
+            source_key: &Pubkey,
+            source_account: &mut Account,
+            destination_key: &Pubkey,
+            destination_account: &mut Account,
+            fixed_fee_index: usize,
+            amount_in: u64,
+            minimum_amount_out: u64,
+        ) -> ProgramResult {
+            let user_transfer_key =
+                approve_transfer(user_key, source_key, source_account, amount_in);
+            let mut authority_account = Account::default();
+            let mut user_transfer_authority_account = Account::default();
+            let mut fee_wallet_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut system_program_account = Account::default();
+            let mut account_infos = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut user_transfer_authority_account,
+                source_account,
+                destination_account,
+                &mut self.fee_accounts[fixed_fee_index],
+                &mut fee_wallet_account,
+                &mut token_program_account,
+                &mut system_program_account,
+                &mut self.config_account,
+            ];
+            account_infos.extend(self.token_accounts.iter_mut());
+            do_process_instruction(
+                multi_swap(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_key,
+                    source_key,
+                    destination_key,
+                    &self.fee_keys[fixed_fee_index],
+                    &self.fee_wallet_key,
+                    &self.config_key,
+                    &self.token_keys,
+                    SwapInstruction {
+                        amount_in,
+                        minimum_amount_out,
+                        deadline: None,
+                    },
+                )
+                .unwrap(),
+                account_infos,
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn deposit_all_token_types(
+            &mut self,
+            user_key: &Pubkey,
+            source_keys: &[Pubkey],
+            source_accounts: &mut [Account],
+            pool_key: &Pubkey,
+            pool_account: &mut Account,
+            pool_token_amount: u64,
+            maximum_token_amounts: Vec<u64>,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            for ((source_key, source_account), maximum_token_amount) in source_keys
+                .iter()
+                .zip(source_accounts.iter_mut())
+                .zip(&maximum_token_amounts)
+            {
+                approve_transfer_to(
+                    user_key,
+                    &user_transfer_key,
+                    source_key,
+                    source_account,
+                    *maximum_token_amount,
+                );
+            }
+            let mut authority_account = Account::default();
+            let mut user_transfer_authority_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut account_infos = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut user_transfer_authority_account,
+                &mut self.pool_mint_account,
+                pool_account,
+                &mut token_program_account,
+            ];
+            for (source_account, token_account) in
+                source_accounts.iter_mut().zip(self.token_accounts.iter_mut())
+            {
+                account_infos.push(source_account);
+                account_infos.push(token_account);
+            }
+            do_process_instruction(
+                multi_deposit_all_token_types(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_key,
+                    &self.pool_mint_key,
+                    pool_key,
+                    source_keys,
+                    &self.token_keys,
+                    MultiDepositInstruction {
+                        pool_token_amount,
+                        maximum_token_amounts,
+                        deadline: None,
+                    },
+                )
+                .unwrap(),
+                account_infos,
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn withdraw_all_token_types(
+            &mut self,
+            user_key: &Pubkey,
+            pool_key: &Pubkey,
+            pool_account: &mut Account,
+            destination_keys: &[Pubkey],
+            destination_accounts: &mut [Account],
+            pool_token_amount: u64,
+            minimum_token_amounts: Vec<u64>,
+        ) -> ProgramResult {
+            let user_transfer_key =
+                approve_transfer(user_key, pool_key, pool_account, pool_token_amount);
+            let mut authority_account = Account::default();
+            let mut user_transfer_authority_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut account_infos = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut user_transfer_authority_account,
+                &mut self.pool_mint_account,
+                pool_account,
+                &mut token_program_account,
+            ];
+            for (token_account, destination_account) in self
+                .token_accounts
+                .iter_mut()
+                .zip(destination_accounts.iter_mut())
+            {
+                account_infos.push(token_account);
+                account_infos.push(destination_account);
+            }
+            do_process_instruction(
+                multi_withdraw_all_token_types(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_key,
+                    &self.pool_mint_key,
+                    pool_key,
+                    &self.token_keys,
+                    destination_keys,
+                    MultiWithdrawInstruction {
+                        pool_token_amount,
+                        minimum_token_amounts,
+                        deadline: None,
+                    },
+                )
+                .unwrap(),
+                account_infos,
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn deposit_single_token_type_exact_amount_in(
+            &mut self,
+            user_key: &Pubkey,
+            source_key: &Pubkey,
+            source_account: &mut Account,
+            pool_key: &Pubkey,
+            pool_account: &mut Account,
+            source_token_amount: u64,
+            minimum_pool_token_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_key =
+                approve_transfer(user_key, source_key, source_account, source_token_amount);
+            let mut authority_account = Account::default();
+            let mut user_transfer_authority_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut account_infos = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut user_transfer_authority_account,
+                source_account,
+                &mut self.pool_mint_account,
+                pool_account,
+                &mut token_program_account,
+            ];
+            account_infos.extend(self.token_accounts.iter_mut());
+            do_process_instruction(
+                multi_deposit_single_token_type_exact_amount_in(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_key,
+                    source_key,
+                    &self.pool_mint_key,
+                    pool_key,
+                    &self.token_keys,
+                    DepositSingleTokenTypeExactAmountIn {
+                        source_token_amount,
+                        minimum_pool_token_amount,
+                        deadline: None,
+                    },
+                )
+                .unwrap(),
+                account_infos,
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn withdraw_single_token_type_exact_amount_out(
+            &mut self,
+            user_key: &Pubkey,
+            pool_key: &Pubkey,
+            pool_account: &mut Account,
+            destination_key: &Pubkey,
+            destination_account: &mut Account,
+            destination_token_amount: u64,
+            maximum_pool_token_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_key =
+                approve_transfer(user_key, pool_key, pool_account, maximum_pool_token_amount);
+            let mut authority_account = Account::default();
+            let mut user_transfer_authority_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut account_infos = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut user_transfer_authority_account,
+                &mut self.pool_mint_account,
+                pool_account,
+                destination_account,
+                &mut token_program_account,
+            ];
+            account_infos.extend(self.token_accounts.iter_mut());
+            do_process_instruction(
+                multi_withdraw_single_token_type_exact_amount_out(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_key,
+                    &self.pool_mint_key,
+                    pool_key,
+                    destination_key,
+                    &self.token_keys,
+                    WithdrawSingleTokenTypeExactAmountOut {
+                        destination_token_amount,
+                        maximum_pool_token_amount,
+                        deadline: None,
+                    },
+                )
+                .unwrap(),
+                account_infos,
+            )
+        }
+    }
+
+    /// Approves a new transfer authority to move `amount` out of the user
+    /// account
+    fn approve_transfer(
+        user_key: &Pubkey,
+        account_key: &Pubkey,
+        account: &mut Account,
+        amount: u64,
+    ) -> Pubkey {
+        let user_transfer_key = Pubkey::new_unique();
+        approve_transfer_to(user_key, &user_transfer_key, account_key, account, amount);
+        user_transfer_key
+    }
+
+    fn approve_transfer_to(
+        user_key: &Pubkey,
+        user_transfer_key: &Pubkey,
+        account_key: &Pubkey,
+        account: &mut Account,
+        amount: u64,
+    ) {
+        do_process_instruction(
+            approve(
+                &spl_token::id(),
+                account_key,
+                user_transfer_key,
+                user_key,
+                &[],
+                amount,
+            )
+            .unwrap(),
+            vec![account, &mut Account::default(), &mut Account::default()],
+        )
+        .unwrap();
+    }
+
+    fn mint_minimum_balance() -> u64 {
+        Rent::default().minimum_balance(spl_token::state::Mint::get_packed_len())
+    }
+
+    fn account_minimum_balance() -> u64 {
+        Rent::default().minimum_balance(spl_token::state::Account::get_packed_len())
+    }
+
+    fn do_process_instruction_with_fee_constraints(
+        instruction: Instruction,
+        accounts: Vec<&mut Account>,
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        test_syscall_stubs();
+
+        // approximate the logic in the actual runtime which runs the instruction
+        // and only updates accounts if the instruction is successful
+        let mut account_clones = accounts.iter().map(|x| (*x).clone()).collect::<Vec<_>>();
+        let mut meta = instruction
+            .accounts
+            .iter()
+            .zip(account_clones.iter_mut())
+            .map(|(account_meta, account)| (&account_meta.pubkey, account_meta.is_signer, account))
+            .collect::<Vec<_>>();
+        let mut account_infos = create_is_signer_account_infos(&mut meta);
+        let res = if instruction.program_id == SWAP_PROGRAM_ID {
+            Processor::process_with_constraints(
+                &instruction.program_id,
+                &account_infos,
+                &instruction.data,
+                swap_constraints,
+            )
+        } else if instruction.program_id == spl_token_2022::id() {
+            spl_token_2022::processor::Processor::process(
+                &instruction.program_id,
+                &account_infos,
+                &instruction.data,
+            )
+        } else {
+            spl_token::processor::Processor::process(
+                &instruction.program_id,
+                &account_infos,
+                &instruction.data,
+            )
+        };
+
+        if res.is_ok() {
+            let mut account_metas = instruction
+                .accounts
+                .iter()
+                .zip(accounts)
+                .map(|(account_meta, account)| (&account_meta.pubkey, account))
+                .collect::<Vec<_>>();
+            for account_info in account_infos.iter_mut() {
+                for account_meta in account_metas.iter_mut() {
+                    if account_info.key == account_meta.0 {
+                        let account = &mut account_meta.1;
+                        account.owner = *account_info.owner;
+                        account.lamports = **account_info.lamports.borrow();
+                        account.data = account_info.data.borrow().to_vec();
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    fn do_process_instruction(
+        instruction: Instruction,
+        accounts: Vec<&mut Account>,
+    ) -> ProgramResult {
+        do_process_instruction_with_fee_constraints(instruction, accounts, &SWAP_CONSTRAINTS)
+    }
+
+    fn mint_token(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        mut mint_account: &mut Account,
+        mint_authority_key: &Pubkey,
+        account_owner_key: &Pubkey,
+        amount: u64,
+    ) -> (Pubkey, Account) {
+        let account_key = Pubkey::new_unique();
+        let mut account_account = Account::new(
+            account_minimum_balance(),
+            spl_token::state::Account::get_packed_len(),
+            program_id,
+        );
+        let mut mint_authority_account = Account::default();
+        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
+
+        do_process_instruction(
+            initialize_account(program_id, &account_key, mint_key, account_owner_key).unwrap(),
+            vec![
+                &mut account_account,
+                &mut mint_account,
+                &mut mint_authority_account,
+                &mut rent_sysvar_account,
+            ],
+        )
+        .unwrap();
+
+        if amount > 0 {
+            do_process_instruction(
+                mint_to(
+                    program_id,
+                    mint_key,
+                    &account_key,
+                    mint_authority_key,
+                    &[],
+                    amount,
+                )
+                .unwrap(),
+                vec![
+                    &mut mint_account,
+                    &mut account_account,
+                    &mut mint_authority_account,
+                ],
+            )
+            .unwrap();
+        }
+
+        (account_key, account_account)
+    }
+
+    fn create_mint(
+        program_id: &Pubkey,
+        authority_key: &Pubkey,
+        freeze_authority: Option<&Pubkey>,
+    ) -> (Pubkey, Account) {
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account = Account::new(
+            mint_minimum_balance(),
+            spl_token::state::Mint::get_packed_len(),
+            program_id,
+        );
+        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
+
+        do_process_instruction(
+            initialize_mint(program_id, &mint_key, authority_key, freeze_authority, 2).unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar_account],
+        )
+        .unwrap();
+
+        (mint_key, mint_account)
+    }
+
+    // Creates a Token-2022 mint with `extension`, initialized by the
+    // instruction `init_extension` gives for the mint
+    fn create_token_2022_mint<F>(
+        authority_key: &Pubkey,
+        extension: ExtensionType,
+        init_extension: F,
+    ) -> (Pubkey, Account)
+    where
+        F: FnOnce(&Pubkey) -> Instruction,
+    {
+        let mint_key = Pubkey::new_unique();
+        let space =
+            ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[extension])
+                .unwrap();
+        let mut mint_account = Account::new(
+            Rent::default().minimum_balance(space),
+            space,
+            &spl_token_2022::id(),
+        );
+        do_process_instruction(init_extension(&mint_key), vec![&mut mint_account]).unwrap();
+        do_process_instruction(
+            spl_token_2022::instruction::initialize_mint2(
+                &spl_token_2022::id(),
+                &mint_key,
+                authority_key,
+                None,
+                2,
+            )
+            .unwrap(),
+            vec![&mut mint_account],
+        )
+        .unwrap();
+        (mint_key, mint_account)
+    }
+
+    // Creates a Token-2022 account of `account_owner_key` holding `amount`,
+    // with the extensions its mint requires
+    fn mint_token_2022(
+        mint_key: &Pubkey,
+        mint_account: &mut Account,
+        mint_authority_key: &Pubkey,
+        account_owner_key: &Pubkey,
+        amount: u64,
+    ) -> (Pubkey, Account) {
+        let account_key = Pubkey::new_unique();
+        let mint_extensions =
+            StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_account.data)
+                .unwrap()
+                .get_extension_types()
+                .unwrap();
+        let space = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(
+            &ExtensionType::get_required_init_account_extensions(&mint_extensions),
+        )
+        .unwrap();
+        let mut account_account = Account::new(
+            Rent::default().minimum_balance(space),
+            space,
+            &spl_token_2022::id(),
+        );
+        do_process_instruction(
+            spl_token_2022::instruction::initialize_account3(
+                &spl_token_2022::id(),
+                &account_key,
+                mint_key,
+                account_owner_key,
+            )
+            .unwrap(),
+            vec![&mut account_account, mint_account],
+        )
+        .unwrap();
+        if amount > 0 {
+            do_process_instruction(
+                spl_token_2022::instruction::mint_to(
+                    &spl_token_2022::id(),
+                    mint_key,
+                    &account_key,
+                    mint_authority_key,
+                    &[],
+                    amount,
+                )
+                .unwrap(),
+                vec![mint_account, &mut account_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+        (account_key, account_account)
+    }
+
+    fn create_market(
+        dex_program_id: &Pubkey,
+        market_key: &Pubkey,
+        coin_mint_key: &Pubkey,
+        pc_mint_key: &Pubkey,
+    ) -> Account {
+        // `MarketState` between the dex account paddings, keys are stored as
+        // little endian words
+        let mut market_account = Account::new(0, 388, dex_program_id);
+        let data = &mut market_account.data;
+        data[..5].copy_from_slice(b"serum");
+        data[5..13].copy_from_slice(&3u64.to_le_bytes());
+        data[13..45].copy_from_slice(market_key.as_ref());
+        data[53..85].copy_from_slice(coin_mint_key.as_ref());
+        data[85..117].copy_from_slice(pc_mint_key.as_ref());
+        data[349..357].copy_from_slice(&1_000u64.to_le_bytes());
+        data[357..365].copy_from_slice(&10u64.to_le_bytes());
+        data[381..].copy_from_slice(b"padding");
+        market_account
+    }
+
+    fn create_config(admin_key: &Pubkey, fee_wallet_key: &Pubkey) -> (Pubkey, Account) {
+        let (config_key, nonce) = Pubkey::find_program_address(&[CONFIG_SEED], &SWAP_PROGRAM_ID);
+        let mut config_account = Account::new(0, ProgramConfig::LEN, &SWAP_PROGRAM_ID);
+        ProgramConfig::pack(
+            ProgramConfig {
+                is_initialized: true,
+                nonce,
+                admin: *admin_key,
+                fee_wallet: *fee_wallet_key,
+            },
+            &mut config_account.data,
+        )
+        .unwrap();
+        (config_key, config_account)
+    }
+
+    #[test]
+    fn test_token_program_id_error() {
+        test_syscall_stubs();
+        let swap_key = Pubkey::new_unique();
+        let mut mint = (Pubkey::new_unique(), Account::default());
+        let mut destination = (Pubkey::new_unique(), Account::default());
+        let token_program = (spl_token::id(), Account::default());
+        let (authority_key, nonce) =
+            Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+        let mut authority = (authority_key, Account::default());
+        let swap_bytes = swap_key.to_bytes();
+        let authority_signature_seeds = [&swap_bytes[..32], &[nonce]];
+        let signers = &[&authority_signature_seeds[..]];
+        let ix = mint_to(
+            &token_program.0,
+            &mint.0,
+            &destination.0,
+            &authority.0,
+            &[],
+            10,
+        )
+        .unwrap();
+        let mint = (&mut mint).into();
+        let destination = (&mut destination).into();
+        let authority = (&mut authority).into();
+
+        let err = invoke_signed(&ix, &[mint, destination, authority], signers).unwrap_err();
+        assert_eq!(err, ProgramError::InvalidAccountData);
+    }
+
+    #[test]
+    fn test_initialize() {
+        let user_key = Pubkey::new_unique();
+        let return_fee_numerator = 2;
+        let fixed_fee_numerator = 1;
+        let fee_denominator = 100;
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+
+        let token_a_amount = 1000;
+        let token_b_amount = 2000;
+        let pool_token_amount = 10;
+        let curve_type = CurveType::ConstantProduct;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        // wrong nonce for authority_key
+        {
+            let old_nonce = accounts.nonce;
+            accounts.nonce = old_nonce - 1;
+            assert_eq!(
+                Err(AmmError::InvalidProgramAddress.into()),
+                accounts.initialize_swap()
+            );
+            accounts.nonce = old_nonce;
+        }
+
+        // uninitialized token a account
+        {
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = Account::new(0, 0, &spl_token::id());
+            assert_eq!(
+                Err(AmmError::ExpectedAccount.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // uninitialized token b account
+        {
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = Account::new(0, 0, &spl_token::id());
+            assert_eq!(
+                Err(AmmError::ExpectedAccount.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // uninitialized pool mint
+        {
+            let old_account = accounts.pool_mint_account;
+            accounts.pool_mint_account = Account::new(0, 0, &spl_token::id());
+            assert_eq!(
+                Err(AmmError::ExpectedMint.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_mint_account = old_account;
+        }
+
+        // token A account owner is not swap authority
+        {
+            let (_token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &user_key,
+                0,
+            );
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = token_a_account;
+            assert_eq!(
+                Err(AmmError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // token B account owner is not swap authority
+        {
+            let (_token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_b_mint_key,
+                &mut accounts.token_b_mint_account,
+                &user_key,
+                &user_key,
+                0,
+            );
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_b_account;
+            assert_eq!(
+                Err(AmmError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // pool token account owner is swap authority
+        {
+            let (_pool_token_key, pool_token_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.pool_token_account;
+            accounts.pool_token_account = pool_token_account;
+            assert_eq!(
+                Err(AmmError::InvalidOutputOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_token_account = old_account;
+        }
+
+        // pool fee account owner is swap authority
+        {
+            let (_pool_fee_key, fixed_fee_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.fixed_fee_account_a;
+            accounts.fixed_fee_account_a = fixed_fee_account;
+            assert_eq!(
+                Err(AmmError::InvalidOutputOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.fixed_fee_account_a = old_account;
+        }
+
+        // pool mint authority is not swap authority
+        {
+            let (_pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &user_key, None);
+            let old_mint = accounts.pool_mint_account;
+            accounts.pool_mint_account = pool_mint_account;
+            assert_eq!(
+                Err(AmmError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_mint_account = old_mint;
+        }
+
+        // pool mint token has freeze authority
+        {
+            let (_pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, Some(&user_key));
+            let old_mint = accounts.pool_mint_account;
+            accounts.pool_mint_account = pool_mint_account;
+            assert_eq!(
+                Err(AmmError::InvalidFreezeAuthority.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_mint_account = old_mint;
+        }
+
+        // token A account owned by wrong program
+        {
+            let (_token_a_key, mut token_a_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                token_a_amount,
+            );
+            token_a_account.owner = SWAP_PROGRAM_ID;
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = token_a_account;
+            assert_eq!(
+                Err(AmmError::IncorrectTokenProgramId.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // token B account owned by wrong program
+        {
+            let (_token_b_key, mut token_b_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_b_mint_key,
+                &mut accounts.token_b_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                token_b_amount,
+            );
+            token_b_account.owner = SWAP_PROGRAM_ID;
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_b_account;
+            assert_eq!(
+                Err(AmmError::IncorrectTokenProgramId.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // empty token A account
+        {
+            let (_token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = token_a_account;
+            assert_eq!(
+                Err(AmmError::EmptySupply.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // empty token B account
+        {
+            let (_token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_b_mint_key,
+                &mut accounts.token_b_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_b_account;
+            assert_eq!(
+                Err(AmmError::EmptySupply.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // invalid pool tokens
+        {
+            let old_mint = accounts.pool_mint_account;
+            let old_pool_account = accounts.pool_token_account;
+
+            let (_pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            accounts.pool_mint_account = pool_mint_account;
+
+            let (_empty_pool_token_key, empty_pool_token_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &user_key,
+                0,
+            );
+
+            let (_pool_token_key, pool_token_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &user_key,
+                pool_token_amount,
+            );
+
+            // non-empty pool token account
+            accounts.pool_token_account = pool_token_account;
+            assert_eq!(
+                Err(AmmError::InvalidSupply.into()),
+                accounts.initialize_swap()
+            );
+
+            // pool tokens already in circulation
+            accounts.pool_token_account = empty_pool_token_account;
+            assert_eq!(
+                Err(AmmError::InvalidSupply.into()),
+                accounts.initialize_swap()
+            );
+
+            accounts.pool_mint_account = old_mint;
+            accounts.pool_token_account = old_pool_account;
+        }
+
+        // pool fee account has wrong mint, fixed fees are taken in the
+        // trading tokens
+        {
+            let fee_wallet_key = accounts.fee_wallet_key;
+            let (_pool_fee_key, fixed_fee_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_b_mint_key,
+                &mut accounts.token_b_mint_account,
+                &user_key,
+                &fee_wallet_key,
+                0,
+            );
+            let old_account = accounts.fixed_fee_account_a;
+            accounts.fixed_fee_account_a = fixed_fee_account;
+            assert_eq!(
+                Err(AmmError::IncorrectFeeAccount.into()),
+                accounts.initialize_swap()
+            );
+            accounts.fixed_fee_account_a = old_account;
+        }
+
+        // token A account is delegated
+        {
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    &accounts.token_a_key,
+                    &user_key,
+                    &accounts.authority_key,
+                    &[],
+                    1,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.token_a_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(AmmError::InvalidDelegate.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                revoke(
+                    &spl_token::id(),
+                    &accounts.token_a_key,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_a_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // token B account is delegated
+        {
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    &accounts.token_b_key,
+                    &user_key,
+                    &accounts.authority_key,
+                    &[],
+                    1,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.token_b_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(AmmError::InvalidDelegate.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                revoke(
+                    &spl_token::id(),
+                    &accounts.token_b_key,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_b_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // token A account has close authority
+        {
+            do_process_instruction(
+                set_authority(
+                    &spl_token::id(),
+                    &accounts.token_a_key,
+                    Some(&user_key),
+                    AuthorityType::CloseAccount,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_a_account, &mut Account::default()],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(AmmError::InvalidCloseAuthority.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                set_authority(
+                    &spl_token::id(),
+                    &accounts.token_a_key,
+                    None,
+                    AuthorityType::CloseAccount,
+                    &user_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_a_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // token B account has close authority
+        {
+            do_process_instruction(
+                set_authority(
+                    &spl_token::id(),
+                    &accounts.token_b_key,
+                    Some(&user_key),
+                    AuthorityType::CloseAccount,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_b_account, &mut Account::default()],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(AmmError::InvalidCloseAuthority.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                set_authority(
+                    &spl_token::id(),
+                    &accounts.token_b_key,
+                    None,
+                    AuthorityType::CloseAccount,
+                    &user_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_b_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // wrong token program id
+        {
+            let wrong_program_id = Pubkey::new_unique();
+            assert_eq!(
+                Err(AmmError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    initialize(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_program_id,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.amm_id_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &accounts.fee_a_key,
+                        &accounts.fee_b_key,
+                        &accounts.pool_token_key,
+                        &accounts.dex_program_id,
+                        &accounts.market_key,
+                        &accounts.open_orders_key,
+                        &accounts.config_key,
+                        accounts.nonce,
+                        accounts.fees.clone(),
+                        accounts.swap_curve.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.fixed_fee_account_a,
+                        &mut accounts.fixed_fee_account_b,
+                        &mut accounts.pool_token_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.market_account,
+                        &mut accounts.open_orders_account,
+                        &mut accounts.config_account,
+                    ],
+                )
+            );
+        }
+
+        // create swap with same token A and B
+        {
+            let (_token_a_repeat_key, token_a_repeat_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                10,
+            );
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_a_repeat_account;
+            assert_eq!(
+                Err(AmmError::RepeatedMint.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // create valid swap
+        accounts.initialize_swap().unwrap();
+
+        // create invalid flat swap
+        {
+            let token_b_price = 0;
+            let fees = Fees {
+                return_fee_numerator,
+                fixed_fee_numerator,
+                fee_denominator,
+                owner_withdraw_fee_numerator: 0,
+                owner_withdraw_fee_denominator: 0,
+            };
+    
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantPrice,
+                calculator: Box::new(ConstantPriceCurve { token_b_price }),
+            };
+            let mut accounts =
+                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+            assert_eq!(
+                Err(AmmError::InvalidCurve.into()),
+                accounts.initialize_swap()
+            );
+        }
+
+        // create valid flat swap
+        {
+            let fees = Fees {
+                return_fee_numerator,
+                fixed_fee_numerator,
+                fee_denominator,
+                owner_withdraw_fee_numerator: 0,
+                owner_withdraw_fee_denominator: 0,
+            };
+                let token_b_price = 10_000;
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantPrice,
+                calculator: Box::new(ConstantPriceCurve { token_b_price }),
+            };
+            let mut accounts =
+                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+            accounts.initialize_swap().unwrap();
+        }
+
+        // create invalid offset swap
+        {
+            let token_b_offset = 0;
+            let fees = Fees {
+                return_fee_numerator,
+                fixed_fee_numerator,
+                fee_denominator,
+                owner_withdraw_fee_numerator: 0,
+                owner_withdraw_fee_denominator: 0,
+            };
+    
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::Offset,
+                calculator: Box::new(OffsetCurve { token_b_offset }),
+            };
+            let mut accounts =
+                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+            assert_eq!(
+                Err(AmmError::InvalidCurve.into()),
+                accounts.initialize_swap()
+            );
+        }
+
+        // create valid offset swap
+        {
+            let token_b_offset = 10;
+            let fees = Fees {
+                return_fee_numerator,
+                fixed_fee_numerator,
+                fee_denominator,
+                owner_withdraw_fee_numerator: 0,
+                owner_withdraw_fee_denominator: 0,
+            };
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::Offset,
+                calculator: Box::new(OffsetCurve { token_b_offset }),
+            };
+            let mut accounts =
+                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+            accounts.initialize_swap().unwrap();
+        }
+
+        // wrong owner key in constraint
+        {
+            let new_key = Pubkey::new_unique();
+            let return_fee_numerator = 20;
+            let fixed_fee_numerator = 10;
+            let fee_denominator = 10000;
+            let fees = Fees {
+                return_fee_numerator,
+                fixed_fee_numerator,
+                fee_denominator,
+                owner_withdraw_fee_numerator: 0,
+                owner_withdraw_fee_denominator: 0,
+            };
+            let curve = ConstantProductCurve {};
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantProduct,
+                calculator: Box::new(curve),
+            };
+            let owner_key = &new_key.to_string();
+            let valid_curve_types = &[CurveType::ConstantProduct];
+            let constraints = Some(SwapConstraints {
+                owner_key,
+                valid_curve_types,
+                fees: &fees,
+                host_fee: &HostFee {
+                    numerator: 0,
+                    denominator: 0,
+                },
+            });
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                fees.clone(),
+                swap_curve,
+                token_a_amount,
+                token_b_amount,
+            );
+            assert_eq!(
+                Err(AmmError::InvalidOwner.into()),
+                do_process_instruction_with_fee_constraints(
+                    initialize(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.amm_id_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &accounts.fee_a_key,
+                        &accounts.fee_b_key,
+                        &accounts.pool_token_key,
+                        &accounts.dex_program_id,
+                        &accounts.market_key,
+                        &accounts.open_orders_key,
+                        &accounts.config_key,
+                        accounts.nonce,
+                        accounts.fees.clone(),
+                        accounts.swap_curve.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.fixed_fee_account_a,
+                        &mut accounts.fixed_fee_account_b,
+                        &mut accounts.pool_token_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.market_account,
+                        &mut accounts.open_orders_account,
+                        &mut accounts.config_account,
+                    ],
+                    &constraints,
+                )
+            );
+        }
+
+        // wrong fee in constraint
+        {
+            let return_fee_numerator = 25;
+            let fixed_fee_numerator = 5;
+            let fee_denominator = 10000;
+            let fees = Fees {
+                return_fee_numerator,
+                fixed_fee_numerator,
+                fee_denominator,
+                owner_withdraw_fee_numerator: 0,
+                owner_withdraw_fee_denominator: 0,
+            };
+            let curve = ConstantProductCurve {};
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantProduct,
+                calculator: Box::new(curve),
+            };
+            let owner_key = &user_key.to_string();
+            let valid_curve_types = &[CurveType::ConstantProduct];
+            let constraints = Some(SwapConstraints {
+                owner_key,
+                valid_curve_types,
+                fees: &fees,
+                host_fee: &HostFee {
+                    numerator: 0,
+                    denominator: 0,
+                },
+            });
+            let mut bad_fees = fees.clone();
+            bad_fees.return_fee_numerator = return_fee_numerator - 1;
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                bad_fees,
+                swap_curve,
+                token_a_amount,
+                token_b_amount,
+            );
+            accounts.set_config_admin(&user_key);
+            assert_eq!(
+                Err(AmmError::InvalidFee.into()),
+                do_process_instruction_with_fee_constraints(
+                    initialize(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.amm_id_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &accounts.fee_a_key,
+                        &accounts.fee_b_key,
+                        &accounts.pool_token_key,
+                        &accounts.dex_program_id,
+                        &accounts.market_key,
+                        &accounts.open_orders_key,
+                        &accounts.config_key,
+                        accounts.nonce,
+                        accounts.fees.clone(),
+                        accounts.swap_curve.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.fixed_fee_account_a,
+                        &mut accounts.fixed_fee_account_b,
+                        &mut accounts.pool_token_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.market_account,
+                        &mut accounts.open_orders_account,
+                        &mut accounts.config_account,
+                    ],
+                    &constraints,
+                )
+            );
+        }
+
+        // create valid swap with constraints
+        {
+            let return_fee_numerator = 25;
+            let fixed_fee_numerator = 5;
+            let fee_denominator = 10000;
+            let fees = Fees {
+                return_fee_numerator,
+                fixed_fee_numerator,
+                fee_denominator,
+                owner_withdraw_fee_numerator: 0,
+                owner_withdraw_fee_denominator: 0,
+            };
+            let curve = ConstantProductCurve {};
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantProduct,
+                calculator: Box::new(curve),
+            };
+            let owner_key = &user_key.to_string();
+            let valid_curve_types = &[CurveType::ConstantProduct];
+            let constraints = Some(SwapConstraints {
+                owner_key,
+                valid_curve_types,
+                fees: &fees,
+                host_fee: &HostFee {
+                    numerator: 0,
+                    denominator: 0,
+                },
+            });
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                fees.clone(),
+                swap_curve,
+                token_a_amount,
+                token_b_amount,
+            );
+            accounts.set_config_admin(&user_key);
+            do_process_instruction_with_fee_constraints(
+                initialize(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &accounts.amm_id_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
+                    &accounts.pool_mint_key,
+                    &accounts.fee_a_key,
+                    &accounts.fee_b_key,
+                    &accounts.pool_token_key,
+                    &accounts.dex_program_id,
+                    &accounts.market_key,
+                    &accounts.open_orders_key,
+                    &accounts.config_key,
+                    accounts.nonce,
+                    accounts.fees,
+                    accounts.swap_curve.clone(),
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    &mut accounts.pool_mint_account,
+                    &mut accounts.fixed_fee_account_a,
+                    &mut accounts.fixed_fee_account_b,
+                    &mut accounts.pool_token_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.market_account,
+                    &mut accounts.open_orders_account,
+                    &mut accounts.config_account,
+                ],
+                &constraints,
+            )
+            .unwrap();
+        }
+
+        // create again
+        {
+            assert_eq!(
+                Err(AmmError::AlreadyInUse.into()),
+                accounts.initialize_swap()
+            );
+        }
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert!(swap_state.is_initialized());
+        assert_eq!(swap_state.nonce(), accounts.nonce);
+        assert_eq!(
+            swap_state.swap_curve().curve_type,
+            accounts.swap_curve.curve_type
+        );
+        assert_eq!(*swap_state.token_a_account(), accounts.token_a_key);
+        assert_eq!(*swap_state.token_b_account(), accounts.token_b_key);
+        assert_eq!(*swap_state.pool_mint(), accounts.pool_mint_key);
+        assert_eq!(*swap_state.token_a_mint(), accounts.token_a_mint_key);
+        assert_eq!(*swap_state.token_b_mint(), accounts.token_b_mint_key);
+        assert_eq!(*swap_state.fixed_fee_account_a(), accounts.fee_a_key);
+        assert_eq!(*swap_state.fixed_fee_account_b(), accounts.fee_b_key);
+        let token_a = spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, token_a_amount);
+        let token_b = spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, token_b_amount);
+        let pool_account =
+            spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
+        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+        assert_eq!(pool_mint.supply, pool_account.amount);
+    }
+
+    #[test]
+    fn test_deposit() {
+        let user_key = Pubkey::new_unique();
+        let depositor_key = Pubkey::new_unique();
+
+        let return_fee_numerator = 25;
+        let fixed_fee_numerator = 5;
+        let fee_denominator = 10000;
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+
+        let token_a_amount = 1000;
+        let token_b_amount = 9000;
+        let curve_type = CurveType::ConstantProduct;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        // depositing 10% of the current pool amount in token A and B means
+        // that our pool tokens will be worth 1 / 10 of the current pool amount
+        let pool_amount = INITIAL_SWAP_POOL_AMOUNT / 10;
+        let deposit_a = token_a_amount / 10;
+        let deposit_b = token_b_amount / 10;
+
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // wrong owner for swap account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_swap_account = accounts.swap_account;
+            let mut wrong_swap_account = old_swap_account.clone();
+            wrong_swap_account.owner = spl_token::id();
+            accounts.swap_account = wrong_swap_account;
+            assert_eq!(
+                Err(ProgramError::IncorrectProgramId),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+            accounts.swap_account = old_swap_account;
+        }
+
+        // wrong nonce for authority_key
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &spl_token::id(),
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(AmmError::InvalidProgramAddress.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // not enough token A
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &depositor_key,
+                deposit_a / 2,
+                deposit_b,
+                0,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // not enough token B
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &depositor_key,
+                deposit_a,
+                deposit_b / 2,
+                0,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // wrong pool token account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                mut _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let (
+                wrong_token_key,
+                mut wrong_token_account,
+                _token_b_key,
+                mut _token_b_account,
+                _pool_key,
+                mut _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &wrong_token_key,
+                    &mut wrong_token_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // no approval
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let user_transfer_authority_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    deposit_all_token_types(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_transfer_authority_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        DepositInstruction {
+                            pool_token_amount: pool_amount.try_into().unwrap(),
+                            maximum_token_a_amount: deposit_a,
+                            maximum_token_b_amount: deposit_b,
+                            deadline: None,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong token program id
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let wrong_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(AmmError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    deposit_all_token_types(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_key,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.authority_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        DepositInstruction {
+                            pool_token_amount: pool_amount.try_into().unwrap(),
+                            maximum_token_a_amount: deposit_a,
+                            maximum_token_b_amount: deposit_b,
+                            deadline: None,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account;
+
+            accounts.token_a_key = token_a_key;
+            accounts.token_a_account = token_a_account.clone();
+
+            // wrong swap token a account
+            assert_eq!(
+                Err(AmmError::IncorrectSwapAccount.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
+
+            let old_b_key = accounts.token_b_key;
+            let old_b_account = accounts.token_b_account;
+
+            accounts.token_b_key = token_b_key;
+            accounts.token_b_account = token_b_account.clone();
+
+            // wrong swap token b account
+            assert_eq!(
+                Err(AmmError::IncorrectSwapAccount.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+
+            accounts.token_b_key = old_b_key;
+            accounts.token_b_account = old_b_account;
+        }
+
+        // wrong mint
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let (pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
+
+            assert_eq!(
+                Err(AmmError::IncorrectPoolMint.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
+        }
+
+        // deposit 1 pool token fails beacuse it equates to 0 swap tokens
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(AmmError::ZeroTradingTokens.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    1,
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // slippage exceeded
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            // maximum A amount in too low
+            assert_eq!(
+                Err(AmmError::ExceededSlippage.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a / 10,
+                    deposit_b,
+                )
+            );
+            // maximum B amount in too low
+            assert_eq!(
+                Err(AmmError::ExceededSlippage.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b / 10,
+                )
+            );
+        }
+
+        // invalid input: can't use swap pool tokens as source
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let swap_token_a_key = accounts.token_a_key;
+            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+            let swap_token_b_key = accounts.token_b_key;
+            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
+            let authority_key = accounts.authority_key;
+            assert_eq!(
+                Err(AmmError::InvalidInput.into()),
+                accounts.deposit_all_token_types(
+                    &authority_key,
+                    &swap_token_a_key,
+                    &mut swap_token_a_account,
+                    &swap_token_b_key,
+                    &mut swap_token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // correctly deposit
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            accounts
+                .deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, 0);
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, 0);
+            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            let swap_pool_account =
+                spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(
+                pool_mint.supply,
+                pool_account.amount + swap_pool_account.amount
+            );
+        }
+    }
+
+    #[test]
+    fn test_withdraw() {
+        let user_key = Pubkey::new_unique();
+        let return_fee_numerator = 25;
+        let fixed_fee_numerator = 5;
+        let fee_denominator = 10000;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 6;
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+        };
+
+        let token_a_amount = 1000;
+        let token_b_amount = 2000;
+        let curve_type = CurveType::ConstantProduct;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+
+        let withdrawer_key = Pubkey::new_unique();
+        let initial_a = token_a_amount / 10;
+        let initial_b = token_b_amount / 10;
+        let initial_pool = swap_curve.calculator.new_pool_supply() / 10;
+        let withdraw_amount = initial_pool / 4;
+        let minimum_token_a_amount = initial_a / 40;
+        let minimum_token_b_amount = initial_b / 40;
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // wrong owner for swap account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let old_swap_account = accounts.swap_account;
+            let mut wrong_swap_account = old_swap_account.clone();
+            wrong_swap_account.owner = spl_token::id();
+            accounts.swap_account = wrong_swap_account;
+            assert_eq!(
+                Err(ProgramError::IncorrectProgramId),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+            accounts.swap_account = old_swap_account;
+        }
+
+        // wrong nonce for authority_key
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &spl_token::id(),
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(AmmError::InvalidProgramAddress.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // not enough pool tokens
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                to_u64(withdraw_amount).unwrap() / 2u64,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount / 2,
+                    minimum_token_b_amount / 2,
+                )
+            );
+        }
+
+        // wrong token a / b accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount.try_into().unwrap(),
+            );
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+        }
+
+        // wrong pool token account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount.try_into().unwrap(),
+            );
+            let (
+                wrong_token_a_key,
+                mut wrong_token_a_account,
+                _token_b_key,
+                _token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                withdraw_amount.try_into().unwrap(),
+                initial_b,
+                withdraw_amount.try_into().unwrap(),
+            );
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &wrong_token_a_key,
+                    &mut wrong_token_a_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+        }
+
+        // wrong pool fee account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                wrong_pool_key,
+                wrong_pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount.try_into().unwrap(),
+            );
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount.try_into().unwrap(),
+            );
+            let old_pool_fee_account = accounts.pool_fee_account;
+            let old_pool_fee_key = accounts.pool_fee_key;
+            accounts.pool_fee_account = wrong_pool_account;
+            accounts.pool_fee_key = wrong_pool_key;
+            assert_eq!(
+                Err(AmmError::IncorrectFeeAccount.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                ),
+            );
+            accounts.pool_fee_account = old_pool_fee_account;
+            accounts.pool_fee_key = old_pool_fee_key;
+        }
+
+        // no approval
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                0,
+                0,
+                withdraw_amount.try_into().unwrap(),
+            );
+            let user_transfer_authority_key = Pubkey::new_unique();
+            let mut instruction = withdraw_all_token_types(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &user_transfer_authority_key,
+                &accounts.pool_mint_key,
+                &pool_key,
+                &accounts.token_a_key,
+                &accounts.token_b_key,
+                &token_a_key,
+                &token_b_key,
+                &accounts.config_key,
+                &accounts.pool_fee_key,
+                WithdrawInstruction {
+                    pool_token_amount: withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                    deadline: None,
+                },
+            )
+            .unwrap();
+            instruction
+                .accounts
+                .push(AccountMeta::new_readonly(accounts.open_orders_key, false));
+            assert_eq!(
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    instruction,
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut Account::default(),
+                        &mut accounts.config_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.open_orders_account,
+                    ],
+                )
+            );
+        }
+
+        // wrong token program id
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount.try_into().unwrap(),
+            );
+            let wrong_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(AmmError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    withdraw_all_token_types(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_key,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.authority_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &accounts.config_key,
+                        &accounts.pool_fee_key,
+                        WithdrawInstruction {
+                            pool_token_amount: withdraw_amount.try_into().unwrap(),
+                            minimum_token_a_amount,
+                            minimum_token_b_amount,
+                            deadline: None,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut Account::default(),
+                        &mut accounts.config_account,
+                        &mut accounts.pool_fee_account,
+                    ],
+                )
+            );
+        }
+
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account;
+
+            accounts.token_a_key = token_a_key;
+            accounts.token_a_account = token_a_account.clone();
+
+            // wrong swap token a account
+            assert_eq!(
+                Err(AmmError::IncorrectSwapAccount.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
+
+            let old_b_key = accounts.token_b_key;
+            let old_b_account = accounts.token_b_account;
+
+            accounts.token_b_key = token_b_key;
+            accounts.token_b_account = token_b_account.clone();
+
+            // wrong swap token b account
+            assert_eq!(
+                Err(AmmError::IncorrectSwapAccount.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+
+            accounts.token_b_key = old_b_key;
+            accounts.token_b_account = old_b_account;
+        }
+
+        // wrong mint
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+            let (pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
+
+            assert_eq!(
+                Err(AmmError::IncorrectPoolMint.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
+        }
+
+        // withdrawing 1 pool token fails because it equates to 0 output tokens
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+            assert_eq!(
+                Err(AmmError::ZeroTradingTokens.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    1,
+                    0,
+                    0,
+                )
+            );
+        }
+
+        // slippage exceeded
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+            // minimum A amount out too high
+            assert_eq!(
+                Err(AmmError::ExceededSlippage.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount * 10,
+                    minimum_token_b_amount,
+                )
+            );
+            // minimum B amount out too high
+            assert_eq!(
+                Err(AmmError::ExceededSlippage.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount * 10,
+                )
+            );
+        }
+
+        // invalid input: can't use swap pool tokens as destination
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+            let swap_token_a_key = accounts.token_a_key;
+            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+            assert_eq!(
+                Err(AmmError::InvalidInput.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &swap_token_a_key,
+                    &mut swap_token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+            let swap_token_b_key = accounts.token_b_key;
+            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
+            assert_eq!(
+                Err(AmmError::InvalidInput.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_b_key,
+                    &mut swap_token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+        }
+
+        // correct withdrawal
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+
+            accounts
+                .withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            let withdraw_fee = accounts.fees.owner_withdraw_fee(withdraw_amount).unwrap();
+            let results = accounts
+                .swap_curve
+                .calculator
+                .pool_tokens_to_trading_tokens(
+                    withdraw_amount - withdraw_fee,
+                    pool_mint.supply.into(),
+                    swap_token_a.amount.into(),
+                    swap_token_b.amount.into(),
+                    RoundDirection::Floor,
+                )
+                .unwrap();
+            assert_eq!(
+                swap_token_a.amount,
+                token_a_amount - to_u64(results.token_a_amount).unwrap()
+            );
+            assert_eq!(
+                swap_token_b.amount,
+                token_b_amount - to_u64(results.token_b_amount).unwrap()
+            );
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(
+                token_a.amount,
+                initial_a + to_u64(results.token_a_amount).unwrap()
+            );
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(
+                token_b.amount,
+                initial_b + to_u64(results.token_b_amount).unwrap()
+            );
+            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(
+                pool_account.amount,
+                to_u64(initial_pool - withdraw_amount).unwrap()
+            );
+            let pool_fee_account =
+                spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+            assert_eq!(
+                pool_fee_account.amount,
+                TryInto::<u64>::try_into(withdraw_fee).unwrap()
+            );
+        }
+
+        // correct withdrawal from fee account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                mut _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
+
+            let fee_wallet_key = accounts.fee_wallet_key;
+            let pool_fee_key = accounts.pool_fee_key;
+            let mut pool_fee_account = accounts.pool_fee_account.clone();
+            let fee_account = spl_token::state::Account::unpack(&pool_fee_account.data).unwrap();
+            let pool_fee_amount = fee_account.amount;
+            assert!(pool_fee_amount > 0);
+
+            accounts
+                .withdraw_all_token_types(
+                    &fee_wallet_key,
+                    &pool_fee_key,
+                    &mut pool_fee_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    pool_fee_amount,
+                    0,
+                    0,
+                )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            let results = accounts
+                .swap_curve
+                .calculator
+                .pool_tokens_to_trading_tokens(
+                    pool_fee_amount.into(),
+                    pool_mint.supply.into(),
+                    swap_token_a.amount.into(),
+                    swap_token_b.amount.into(),
+                    RoundDirection::Floor,
+                )
+                .unwrap();
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(
+                token_a.amount,
+                TryInto::<u64>::try_into(results.token_a_amount).unwrap()
+            );
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(
+                token_b.amount,
+                TryInto::<u64>::try_into(results.token_b_amount).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_deposit_one_exact_in() {
+        let user_key = Pubkey::new_unique();
+        let depositor_key = Pubkey::new_unique();
+        
+        let return_fee_numerator = 25;
+        let fixed_fee_numerator = 5;
+        let fee_denominator = 10000;
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        
+        let token_a_amount = 1000;
+        let token_b_amount = 9000;
+        let curve_type = CurveType::ConstantProduct;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        let deposit_a = token_a_amount / 10;
+        let deposit_b = token_b_amount / 10;
+        let pool_amount = to_u64(INITIAL_SWAP_POOL_AMOUNT / 100).unwrap();
+
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // wrong owner for swap account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_swap_account = accounts.swap_account;
+            let mut wrong_swap_account = old_swap_account.clone();
+            wrong_swap_account.owner = spl_token::id();
+            accounts.swap_account = wrong_swap_account;
+            assert_eq!(
+                Err(ProgramError::IncorrectProgramId),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+            accounts.swap_account = old_swap_account;
+        }
+
+        // wrong nonce for authority_key
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &spl_token::id(),
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(AmmError::InvalidProgramAddress.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // not enough token A / B
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &depositor_key,
+                deposit_a / 2,
+                deposit_b / 2,
+                0,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    0,
+                )
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_b,
+                    0,
+                )
+            );
+        }
+
+        // wrong pool token account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                mut _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+        }
+
+        // no approval
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let user_transfer_authority_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    deposit_single_token_type_exact_amount_in(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_transfer_authority_key,
+                        &token_a_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        DepositSingleTokenTypeExactAmountIn {
+                            source_token_amount: deposit_a,
+                            minimum_pool_token_amount: pool_amount,
+                            deadline: None,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong token program id
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let wrong_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(AmmError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    deposit_single_token_type_exact_amount_in(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_key,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.authority_key,
+                        &token_a_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        DepositSingleTokenTypeExactAmountIn {
+                            source_token_amount: deposit_a,
+                            minimum_pool_token_amount: pool_amount,
+                            deadline: None,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account;
+
+            accounts.token_a_key = token_a_key;
+            accounts.token_a_account = token_a_account.clone();
+
+            // wrong swap token a account
+            assert_eq!(
+                Err(AmmError::IncorrectSwapAccount.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
+
+            let old_b_key = accounts.token_b_key;
+            let old_b_account = accounts.token_b_account;
+
+            accounts.token_b_key = token_b_key;
+            accounts.token_b_account = token_b_account;
+
+            // wrong swap token b account
+            assert_eq!(
+                Err(AmmError::IncorrectSwapAccount.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+
+            accounts.token_b_key = old_b_key;
+            accounts.token_b_account = old_b_account;
+        }
+
+        // wrong mint
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let (pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
+
+            assert_eq!(
+                Err(AmmError::IncorrectPoolMint.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
+        }
+
+        // slippage exceeded
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            // minimum pool amount too high
+            assert_eq!(
+                Err(AmmError::ExceededSlippage.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a / 10,
+                    pool_amount,
+                )
+            );
+            // minimum pool amount too high
+            assert_eq!(
+                Err(AmmError::ExceededSlippage.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_b / 10,
+                    pool_amount,
+                )
+            );
+        }
+
+        // invalid input: can't use swap pool tokens as source
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let swap_token_a_key = accounts.token_a_key;
+            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+            let swap_token_b_key = accounts.token_b_key;
+            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
+            let authority_key = accounts.authority_key;
+            assert_eq!(
+                Err(AmmError::InvalidInput.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &authority_key,
+                    &swap_token_a_key,
+                    &mut swap_token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+            assert_eq!(
+                Err(AmmError::InvalidInput.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &authority_key,
+                    &swap_token_b_key,
+                    &mut swap_token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_b,
+                    pool_amount,
+                )
+            );
+        }
+
+        // correctly deposit
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            accounts
+                .deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
+
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, 0);
+
+            accounts
+                .deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_b,
+                    pool_amount,
+                )
+                .unwrap();
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
+
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, 0);
+
+            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            let swap_pool_account =
+                spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(
+                pool_mint.supply,
+                pool_account.amount + swap_pool_account.amount
+            );
+        }
+    }
+
+    #[test]
+    fn test_withdraw_one_exact_out() {
+        let user_key = Pubkey::new_unique();
+
+        let return_fee_numerator = 25;
+        let fixed_fee_numerator = 5;
+        let fee_denominator = 10000;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 6;
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+        };
+
+        let token_a_amount = 100_000;
+        let token_b_amount = 200_000;
+        let curve_type = CurveType::ConstantProduct;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+
+        let withdrawer_key = Pubkey::new_unique();
+        let initial_a = token_a_amount / 10;
+        let initial_b = token_b_amount / 10;
+        let initial_pool = swap_curve.calculator.new_pool_supply() / 10;
+        let maximum_pool_token_amount = to_u64(initial_pool / 4).unwrap();
+        let destination_a_amount = initial_a / 40;
+        let destination_b_amount = initial_b / 40;
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // wrong owner for swap account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let old_swap_account = accounts.swap_account;
+            let mut wrong_swap_account = old_swap_account.clone();
+            wrong_swap_account.owner = spl_token::id();
+            accounts.swap_account = wrong_swap_account;
+            assert_eq!(
+                Err(ProgramError::IncorrectProgramId),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+            accounts.swap_account = old_swap_account;
+        }
+
+        // wrong nonce for authority_key
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &spl_token::id(),
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(AmmError::InvalidProgramAddress.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // not enough pool tokens
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount / 1000,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+        }
+
+        // wrong pool token account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                maximum_pool_token_amount,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+        }
+
+        // wrong pool fee account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                wrong_pool_key,
+                wrong_pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let old_fixed_fee_account = accounts.fixed_fee_account_a;
+            let old_pool_fee_key = accounts.fee_a_key;
+            accounts.fixed_fee_account_a = wrong_pool_account;
+            accounts.fee_a_key = wrong_pool_key;
+            assert_eq!(
+                Err(AmmError::IncorrectFeeAccount.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+            accounts.fixed_fee_account_a = old_fixed_fee_account;
+            accounts.fee_a_key = old_pool_fee_key;
+        }
+
+        // wrong owner withdraw fee account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                wrong_pool_key,
+                wrong_pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let old_pool_fee_account = accounts.pool_fee_account;
+            let old_pool_fee_key = accounts.pool_fee_key;
+            accounts.pool_fee_account = wrong_pool_account;
+            accounts.pool_fee_key = wrong_pool_key;
+            assert_eq!(
+                Err(AmmError::IncorrectFeeAccount.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+            accounts.pool_fee_account = old_pool_fee_account;
+            accounts.pool_fee_key = old_pool_fee_key;
+        }
+
+        // no approval
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                0,
+                0,
+                maximum_pool_token_amount,
+            );
+            let user_transfer_authority_key = Pubkey::new_unique();
+            let mut instruction = withdraw_single_token_type_exact_amount_out(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &user_transfer_authority_key,
+                &accounts.pool_mint_key,
+                &pool_key,
+                &accounts.token_a_key,
+                &accounts.token_b_key,
+                &token_a_key,
+                &accounts.fee_a_key,
+                &accounts.fee_b_key,
+                &accounts.config_key,
+                &accounts.pool_fee_key,
+                WithdrawSingleTokenTypeExactAmountOut {
+                    destination_token_amount: destination_a_amount,
+                    maximum_pool_token_amount,
+                    deadline: None,
+                },
+            )
+            .unwrap();
+            instruction
+                .accounts
+                .push(AccountMeta::new_readonly(accounts.open_orders_key, false));
+            assert_eq!(
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    instruction,
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut accounts.fixed_fee_account_a,
+                        &mut accounts.fixed_fee_account_b,
+                        &mut Account::default(),
+                        &mut accounts.config_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.open_orders_account,
+                    ],
+                )
+            );
+        }
+
+        // wrong token program id
+        {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
+                _token_b_key,
+                _token_b_account,
                 pool_key,
                 mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let wrong_key = Pubkey::new_unique();
             assert_eq!(
-                Err(ProgramError::UninitializedAccount),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                Err(AmmError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    withdraw_single_token_type_exact_amount_out(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_key,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.authority_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        &accounts.fee_a_key,
+                        &accounts.fee_b_key,
+                        &accounts.config_key,
+                        &accounts.pool_fee_key,
+                        WithdrawSingleTokenTypeExactAmountOut {
+                            destination_token_amount: destination_a_amount,
+                            maximum_pool_token_amount,
+                            deadline: None,
+                        }
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut accounts.fixed_fee_account_a,
+                        &mut accounts.fixed_fee_account_b,
+                        &mut Account::default(),
+                        &mut accounts.config_account,
+                        &mut accounts.pool_fee_account,
+                    ],
                 )
             );
         }
 
-        accounts.initialize_swap().unwrap();
-
-        // wrong owner for swap account
+        // wrong swap token accounts
         {
             let (
                 token_a_key,
@@ -2898,30 +10750,102 @@ mod tests {
                 mut token_b_account,
                 pool_key,
                 mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let old_swap_account = accounts.swap_account;
-            let mut wrong_swap_account = old_swap_account.clone();
-            wrong_swap_account.owner = spl_token::id();
-            accounts.swap_account = wrong_swap_account;
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account;
+
+            accounts.token_a_key = token_a_key;
+            accounts.token_a_account = token_a_account.clone();
+
+            // wrong swap token a account
             assert_eq!(
-                Err(ProgramError::IncorrectProgramId),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+                Err(AmmError::IncorrectSwapAccount.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
+
+            let old_b_key = accounts.token_b_key;
+            let old_b_account = accounts.token_b_account;
+
+            accounts.token_b_key = token_b_key;
+            accounts.token_b_account = token_b_account.clone();
+
+            // wrong swap token b account
+            assert_eq!(
+                Err(AmmError::IncorrectSwapAccount.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_b_key,
                     &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+
+            accounts.token_b_key = old_b_key;
+            accounts.token_b_account = old_b_account;
+        }
+
+        // wrong mint
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+            let (pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
+
+            assert_eq!(
+                Err(AmmError::IncorrectPoolMint.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
                 )
             );
-            accounts.swap_account = old_swap_account;
+
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
         }
 
-        // wrong nonce for authority_key
+        // slippage exceeded
         {
             let (
                 token_a_key,
@@ -2930,2459 +10854,4311 @@ mod tests {
                 mut token_b_account,
                 pool_key,
                 mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &spl_token::id(),
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
             );
-            accounts.authority_key = bad_authority_key;
+
+            // maximum pool token amount too low
             assert_eq!(
-                Err(AmmError::InvalidProgramAddress.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+                Err(AmmError::ExceededSlippage.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount / 1000,
+                )
+            );
+            assert_eq!(
+                Err(AmmError::ExceededSlippage.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_b_key,
                     &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount / 1000,
+                )
+            );
+        }
+
+        // invalid input: can't use swap pool tokens as destination
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let swap_token_a_key = accounts.token_a_key;
+            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+            assert_eq!(
+                Err(AmmError::InvalidInput.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    &swap_token_a_key,
+                    &mut swap_token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+            let swap_token_b_key = accounts.token_b_key;
+            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
+            assert_eq!(
+                Err(AmmError::InvalidInput.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &swap_token_b_key,
+                    &mut swap_token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
                 )
             );
-            accounts.authority_key = old_authority;
         }
 
-        // not enough token A
+        // correct withdrawal
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
+                _token_b_key,
+                _token_b_account,
                 pool_key,
                 mut pool_account,
             ) = accounts.setup_token_accounts(
                 &user_key,
-                &depositor_key,
-                deposit_a / 2,
-                deposit_b,
-                0,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
             );
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+
+            let pool_token_amount = accounts
+                .swap_curve
+                .withdraw_single_token_type_exact_out(
+                    destination_a_amount.into(),
+                    swap_token_a.amount.into(),
+                    swap_token_b.amount.into(),
+                    pool_mint.supply.into(),
+                    TradeDirection::AtoB,
+                    &accounts.fees,
+                )
+                .unwrap();
+            let withdraw_fee = accounts.fees.owner_withdraw_fee(pool_token_amount).unwrap();
+
+            accounts
+                .withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
                 )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+
+            assert_eq!(swap_token_a.amount, token_a_amount - destination_a_amount);
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, initial_a + destination_a_amount);
+
+            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(
+                pool_account.amount,
+                to_u64(initial_pool - pool_token_amount - withdraw_fee).unwrap()
             );
+            let pool_fee_account =
+                spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+            assert_eq!(pool_fee_account.amount, to_u64(withdraw_fee).unwrap());
         }
 
-        // not enough token B
+        // correct withdrawal from fee account
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &depositor_key,
-                deposit_a,
-                deposit_b / 2,
-                0,
-            );
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+                _token_b_key,
+                _token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+
+            let fee_a_amount = 2;
+            let fee_wallet_key = accounts.fee_wallet_key;
+            let pool_fee_key = accounts.pool_fee_key;
+            let mut pool_fee_account = accounts.pool_fee_account.clone();
+            let fee_account = spl_token::state::Account::unpack(&pool_fee_account.data).unwrap();
+            let pool_fee_amount = fee_account.amount;
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+
+            let token_a_amount = swap_token_a.amount;
+            accounts
+                .withdraw_single_token_type_exact_amount_out(
+                    &fee_wallet_key,
+                    &pool_fee_key,
+                    &mut pool_fee_account,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    fee_a_amount,
+                    pool_fee_amount,
                 )
-            );
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+
+            assert_eq!(swap_token_a.amount, token_a_amount - fee_a_amount);
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, initial_a + fee_a_amount);
         }
+    }
+
+    fn check_valid_swap_curve(
+        fees: Fees,
+        curve_type: CurveType,
+        calculator: Box<dyn CurveCalculator>,
+        token_a_amount: u64,
+        token_b_amount: u64,
+    ) {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator,
+        };
+
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            token_a_amount,
+            token_b_amount,
+        );
+        let initial_a = token_a_amount / 5;
+        let initial_b = token_b_amount / 5;
+        accounts.initialize_swap().unwrap();
+
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+        // swap one way
+        let a_to_b_amount = initial_a / 10;
+        let minimum_token_b_amount = 0;
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                a_to_b_amount,
+                minimum_token_b_amount,
+            )
+            .unwrap();
+
+        let results = swap_curve
+            .swap(
+                a_to_b_amount.into(),
+                token_a_amount.into(),
+                token_b_amount.into(),
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        let token_a_amount = swap_token_a.amount;
+        assert_eq!(
+            token_a_amount,
+            TryInto::<u64>::try_into(results.new_swap_source_amount).unwrap()
+        );
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, initial_a - a_to_b_amount);
+
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        let token_b_amount = swap_token_b.amount;
+        assert_eq!(
+            token_b_amount,
+            TryInto::<u64>::try_into(results.new_swap_destination_amount).unwrap()
+        );
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(
+            token_b.amount,
+            initial_b + to_u64(results.destination_amount_swapped).unwrap()
+        );
+
+        let fee_account_a =
+            spl_token::state::Account::unpack(&accounts.fixed_fee_account_a.data).unwrap();
+        assert_eq!(u128::from(fee_account_a.amount), results.owner_fee);
+
+        let first_swap_amount = results.destination_amount_swapped;
+
+        // swap the other way
+
+        let b_to_a_amount = initial_b / 10;
+        let minimum_a_amount = 0;
+        accounts
+            .swap(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                b_to_a_amount,
+                minimum_a_amount,
+            )
+            .unwrap();
+
+        let results = swap_curve
+            .swap(
+                b_to_a_amount.into(),
+                token_b_amount.into(),
+                token_a_amount.into(),
+                TradeDirection::BtoA,
+                &fees,
+            )
+            .unwrap();
+
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        let token_a_amount = swap_token_a.amount;
+        assert_eq!(
+            token_a_amount,
+            TryInto::<u64>::try_into(results.new_swap_destination_amount).unwrap()
+        );
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(
+            token_a.amount,
+            initial_a - a_to_b_amount + to_u64(results.destination_amount_swapped).unwrap()
+        );
+
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        let token_b_amount = swap_token_b.amount;
+        assert_eq!(
+            token_b_amount,
+            TryInto::<u64>::try_into(results.new_swap_source_amount).unwrap()
+        );
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(
+            token_b.amount,
+            initial_b + to_u64(first_swap_amount).unwrap()
+                - to_u64(results.source_amount_swapped).unwrap()
+        );
+
+        let fee_account_b =
+            spl_token::state::Account::unpack(&accounts.fixed_fee_account_b.data).unwrap();
+        assert_eq!(u128::from(fee_account_b.amount), results.owner_fee);
+    }
 
-        // wrong swap token accounts
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
-                )
-            );
-        }
+    #[test]
+    fn test_valid_swap_curves_all_fees() {
+        // All fees
+        let return_fee_numerator = 1;
+        let fixed_fee_numerator = 2;
+        let fee_denominator = 10;
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
 
-        // wrong pool token account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let (
-                wrong_token_key,
-                mut wrong_token_account,
-                _token_b_key,
-                mut _token_b_account,
-                _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &wrong_token_key,
-                    &mut wrong_token_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
-                )
-            );
-        }
+        let token_a_amount = 10_000_000_000;
+        let token_b_amount = 50_000_000_000;
 
-        // no approval
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let user_transfer_authority_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    deposit_all_token_types(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &user_transfer_authority_key,
-                        &token_a_key,
-                        &token_b_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        DepositAllTokenTypes {
-                            pool_token_amount: pool_amount.try_into().unwrap(),
-                            maximum_token_a_amount: deposit_a,
-                            maximum_token_b_amount: deposit_b,
-                        },
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut token_a_account,
-                        &mut token_b_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut Account::default(),
-                    ],
-                )
-            );
-        }
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::ConstantProduct,
+            Box::new(ConstantProductCurve {}),
+            token_a_amount,
+            token_b_amount,
+        );
+        let token_b_price = 1;
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::ConstantPrice,
+            Box::new(ConstantPriceCurve { token_b_price }),
+            token_a_amount,
+            token_b_amount,
+        );
+        let token_b_offset = 10_000_000_000;
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::Offset,
+            Box::new(OffsetCurve { token_b_offset }),
+            token_a_amount,
+            token_b_amount,
+        );
+        let amp = 100;
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::Stable,
+            Box::new(StableCurve { amp }),
+            token_a_amount,
+            token_b_amount,
+        );
+        check_valid_swap_curve(
+            fees,
+            CurveType::Weighted,
+            Box::new(WeightedCurve {
+                token_a_weight: 80,
+                token_b_weight: 20,
+            }),
+            token_a_amount,
+            token_b_amount,
+        );
+    }
 
-        // wrong token program id
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let wrong_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(AmmError::IncorrectTokenProgramId.into()),
-                do_process_instruction(
-                    deposit_all_token_types(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_key,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.authority_key,
-                        &token_a_key,
-                        &token_b_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        DepositAllTokenTypes {
-                            pool_token_amount: pool_amount.try_into().unwrap(),
-                            maximum_token_a_amount: deposit_a,
-                            maximum_token_b_amount: deposit_b,
-                        },
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut token_a_account,
-                        &mut token_b_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut Account::default(),
-                    ],
-                )
-            );
-        }
+    #[test]
+    fn test_valid_swap_curves_trade_fee_only() {
+        let return_fee_numerator = 25;
+        let fixed_fee_numerator = 5;
+        let fee_denominator = 10000;
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
 
-        // wrong swap token accounts
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+        let token_a_amount = 10_000_000_000;
+        let token_b_amount = 50_000_000_000;
+
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::ConstantProduct,
+            Box::new(ConstantProductCurve {}),
+            token_a_amount,
+            token_b_amount,
+        );
+        let token_b_price = 10_000;
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::ConstantPrice,
+            Box::new(ConstantPriceCurve { token_b_price }),
+            token_a_amount,
+            token_b_amount / token_b_price,
+        );
+        let token_b_offset = 1;
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::Offset,
+            Box::new(OffsetCurve { token_b_offset }),
+            token_a_amount,
+            token_b_amount,
+        );
+        let amp = 100;
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::Stable,
+            Box::new(StableCurve { amp }),
+            token_a_amount,
+            token_b_amount,
+        );
+        check_valid_swap_curve(
+            fees,
+            CurveType::Weighted,
+            Box::new(WeightedCurve {
+                token_a_weight: 80,
+                token_b_weight: 20,
+            }),
+            token_a_amount,
+            token_b_amount,
+        );
+    }
 
-            let old_a_key = accounts.token_a_key;
-            let old_a_account = accounts.token_a_account;
+    #[test]
+    fn test_valid_swap_with_fee_constraints() {
+        let owner_key = Pubkey::new_unique();
 
-            accounts.token_a_key = token_a_key;
-            accounts.token_a_account = token_a_account.clone();
+        let return_fee_numerator = 25;
+        let fixed_fee_numerator = 5;
+        let fee_denominator = 10000;
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 5_000_000;
 
-            // wrong swap token a account
-            assert_eq!(
-                Err(AmmError::IncorrectSwapAccount.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
-                )
-            );
 
-            accounts.token_a_key = old_a_key;
-            accounts.token_a_account = old_a_account;
+        let curve = ConstantProductCurve {};
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(curve),
+        };
 
-            let old_b_key = accounts.token_b_key;
-            let old_b_account = accounts.token_b_account;
+        let host_fee_numerator = 20;
+        let host_fee_denominator = 100;
+        let host_fee = HostFee {
+            numerator: host_fee_numerator,
+            denominator: host_fee_denominator,
+        };
 
-            accounts.token_b_key = token_b_key;
-            accounts.token_b_account = token_b_account.clone();
+        let owner_key_str = &owner_key.to_string();
+        let valid_curve_types = &[CurveType::ConstantProduct];
+        let constraints = Some(SwapConstraints {
+            owner_key: owner_key_str,
+            valid_curve_types,
+            fees: &fees,
+            host_fee: &host_fee,
+        });
+        let mut accounts = SwapAccountInfo::new(
+            &owner_key,
+            fees.clone(),
+            swap_curve,
+            token_a_amount,
+            token_b_amount,
+        );
+        accounts.set_config_admin(&owner_key);
 
-            // wrong swap token b account
-            assert_eq!(
-                Err(AmmError::IncorrectSwapAccount.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
-                )
-            );
+        // initialize swap
+        do_process_instruction_with_fee_constraints(
+            initialize(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &accounts.amm_id_key,
+                &accounts.token_a_key,
+                &accounts.token_b_key,
+                &accounts.pool_mint_key,
+                &accounts.fee_a_key,
+                &accounts.fee_b_key,
+                &accounts.pool_token_key,
+                &accounts.dex_program_id,
+                &accounts.market_key,
+                &accounts.open_orders_key,
+                &accounts.config_key,
+                accounts.nonce,
+                accounts.fees.clone(),
+                accounts.swap_curve.clone(),
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut accounts.pool_mint_account,
+                &mut accounts.fixed_fee_account_a,
+                &mut accounts.fixed_fee_account_b,
+                &mut accounts.pool_token_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.market_account,
+                &mut accounts.open_orders_account,
+                &mut accounts.config_account,
+            ],
+            &constraints,
+        )
+        .unwrap();
 
-            accounts.token_b_key = old_b_key;
-            accounts.token_b_account = old_b_account;
-        }
+        // the pool starts with the host fee of the constraints
+        assert_eq!(
+            *SwapVersion::unpack(&accounts.swap_account.data)
+                .unwrap()
+                .host_fee(),
+            host_fee
+        );
 
-        // wrong mint
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let (pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
+        let authority_key = accounts.authority_key;
 
-            assert_eq!(
-                Err(AmmError::IncorrectPoolMint.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(
+            &owner_key,
+            &authority_key,
+            token_a_amount,
+            token_b_amount,
+            0,
+        );
+
+        // only the host fee of the constraints can be set
+        let admin_key = accounts.admin_key;
+        let set_host_fee_with = |accounts: &mut SwapAccountInfo, host_fee: HostFee| {
+            do_process_instruction_with_fee_constraints(
+                set_host_fee(
+                    &SWAP_PROGRAM_ID,
+                    &accounts.swap_key,
+                    &accounts.config_key,
+                    &admin_key,
+                    host_fee,
                 )
-            );
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut accounts.config_account,
+                    &mut Account::default(),
+                ],
+                &constraints,
+            )
+        };
+        assert_eq!(
+            Err(AmmError::InvalidFee.into()),
+            set_host_fee_with(
+                &mut accounts,
+                HostFee {
+                    numerator: host_fee_numerator + 1,
+                    denominator: host_fee_denominator,
+                }
+            )
+        );
+        take_events();
+        set_host_fee_with(&mut accounts, host_fee).unwrap();
+        assert_eq!(
+            take_events(),
+            vec![AmmEvent::SetHostFee(HostFeeEvent {
+                pool: accounts.swap_key,
+                admin: admin_key,
+                numerator: host_fee.numerator,
+                denominator: host_fee.denominator,
+            })]
+        );
 
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
-        }
+        let amount_in = token_a_amount / 2;
+        let minimum_amount_out = 0;
+        let (host_fee_key, mut host_fee_account) = mint_token(
+            &spl_token::id(),
+            &accounts.token_a_mint_key,
+            &mut accounts.token_a_mint_account,
+            &owner_key,
+            &Pubkey::new_unique(),
+            0,
+        );
+        let swap_with_host = |accounts: &mut SwapAccountInfo,
+                              token_a_account: &mut Account,
+                              token_b_account: &mut Account,
+                              host_fee_key: &Pubkey,
+                              host_fee_account: &mut Account| {
+            let mut instruction = swap(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &accounts.authority_key,
+                &token_a_key,
+                &accounts.token_a_key,
+                &accounts.token_b_key,
+                &token_b_key,
+                &accounts.pool_mint_key,
+                &accounts.fee_a_key,
+                &accounts.fee_wallet_key,
+                &accounts.config_key,
+                SwapInstruction {
+                    amount_in,
+                    minimum_amount_out,
+                    deadline: None,
+                },
+            )
+            .unwrap();
+            instruction
+                .accounts
+                .push(AccountMeta::new_readonly(accounts.open_orders_key, false));
+            instruction
+                .accounts
+                .push(AccountMeta::new(*host_fee_key, false));
+            do_process_instruction_with_fee_constraints(
+                instruction,
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    token_a_account,
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    token_b_account,
+                    &mut accounts.pool_mint_account,
+                    &mut accounts.fixed_fee_account_a,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.config_account,
+                    &mut accounts.open_orders_account,
+                    host_fee_account,
+                ],
+                &constraints,
+            )
+        };
 
-        // deposit 1 pool token fails beacuse it equates to 0 swap tokens
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            assert_eq!(
-                Err(AmmError::ZeroTradingTokens.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    1,
-                    deposit_a,
-                    deposit_b,
-                )
-            );
-        }
+        // the host fee account must hold the SOURCE token
+        let (wrong_host_fee_key, mut wrong_host_fee_account) = mint_token(
+            &spl_token::id(),
+            &accounts.token_b_mint_key,
+            &mut accounts.token_b_mint_account,
+            &owner_key,
+            &Pubkey::new_unique(),
+            0,
+        );
+        assert_eq!(
+            Err(AmmError::IncorrectFeeAccount.into()),
+            swap_with_host(
+                &mut accounts,
+                &mut token_a_account,
+                &mut token_b_account,
+                &wrong_host_fee_key,
+                &mut wrong_host_fee_account,
+            )
+        );
 
-        // slippage exceeded
+        // perform the swap
+        swap_with_host(
+            &mut accounts,
+            &mut token_a_account,
+            &mut token_b_account,
+            &host_fee_key,
+            &mut host_fee_account,
+        )
+        .unwrap();
+
+        // check that fees were taken in the host fee account
+        let host_fee_amount = token_amount(&host_fee_account);
+        let owner_fee_amount = token_amount(&accounts.fixed_fee_account_a);
+        let total_fee = fees.fixed_fee(u128::from(amount_in)).unwrap() as u64;
+        assert!(host_fee_amount > 0);
+        assert_eq!(
+            host_fee_amount,
+            total_fee * host_fee_numerator / host_fee_denominator
+        );
+        assert_eq!(total_fee, host_fee_amount + owner_fee_amount);
+        assert_eq!(token_amount(&token_a_account), token_a_amount - amount_in);
+
+        // the stats split the fixed fee as it was paid
+        let stats = *SwapVersion::unpack(&accounts.swap_account.data)
+            .unwrap()
+            .stats();
+        assert_eq!(stats.fixed_fees_a, u128::from(owner_fee_amount));
+        assert_eq!(stats.host_fees_a, u128::from(host_fee_amount));
+    }
+
+    #[test]
+    fn test_initialize_config() {
+        let admin_key = Pubkey::new_unique();
+        let fee_wallet_key = Pubkey::new_unique();
+        let (config_key, nonce) = Pubkey::find_program_address(&[CONFIG_SEED], &SWAP_PROGRAM_ID);
+        let (other_config_key, other_nonce) = (0..nonce)
+            .rev()
+            .find_map(|other_nonce| {
+                Pubkey::create_program_address(&[CONFIG_SEED, &[other_nonce]], &SWAP_PROGRAM_ID)
+                    .ok()
+                    .map(|key| (key, other_nonce))
+            })
+            .unwrap();
+        let config_lamports = Rent::default().minimum_balance(ProgramConfig::LEN);
+        let mut admin_account = Account::new(config_lamports, 0, &system_program::id());
+        let mut config_account = Account::new(0, ProgramConfig::LEN, &system_program::id());
+
+        // another bump derives a valid program address, but not the config
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            // maximum A amount in too low
+            let mut other_config_account = config_account.clone();
             assert_eq!(
-                Err(AmmError::ExceededSlippage.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a / 10,
-                    deposit_b,
+                Err(AmmError::IncorrectConfigAccount.into()),
+                do_process_instruction(
+                    initialize_config(
+                        &SWAP_PROGRAM_ID,
+                        &other_config_key,
+                        &admin_key,
+                        &fee_wallet_key,
+                        other_nonce,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut other_config_account,
+                        &mut admin_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
                 )
             );
-            // maximum B amount in too low
             assert_eq!(
-                Err(AmmError::ExceededSlippage.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b / 10,
+                Err(AmmError::IncorrectConfigAccount.into()),
+                do_process_instruction(
+                    initialize_config(
+                        &SWAP_PROGRAM_ID,
+                        &config_key,
+                        &admin_key,
+                        &fee_wallet_key,
+                        other_nonce,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut config_account,
+                        &mut admin_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
                 )
             );
         }
 
-        // invalid input: can't use swap pool tokens as source
+        do_process_instruction(
+            initialize_config(
+                &SWAP_PROGRAM_ID,
+                &config_key,
+                &admin_key,
+                &fee_wallet_key,
+                nonce,
+            )
+            .unwrap(),
+            vec![
+                &mut config_account,
+                &mut admin_account,
+                &mut Account::default(),
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config_account.owner, SWAP_PROGRAM_ID);
+        assert_eq!(
+            ProgramConfig::unpack(&config_account.data).unwrap(),
+            ProgramConfig {
+                is_initialized: true,
+                nonce,
+                admin: admin_key,
+                fee_wallet: fee_wallet_key,
+            }
+        );
+
+        // the config is initialized once, by whoever signs
         {
-            let (
-                _token_a_key,
-                _token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let swap_token_a_key = accounts.token_a_key;
-            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
-            let swap_token_b_key = accounts.token_b_key;
-            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
-            let authority_key = accounts.authority_key;
+            let other_admin_key = Pubkey::new_unique();
+            let mut other_admin_account = Account::new(config_lamports, 0, &system_program::id());
             assert_eq!(
-                Err(AmmError::InvalidInput.into()),
-                accounts.deposit_all_token_types(
-                    &authority_key,
-                    &swap_token_a_key,
-                    &mut swap_token_a_account,
-                    &swap_token_b_key,
-                    &mut swap_token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                Err(AmmError::AlreadyInUse.into()),
+                do_process_instruction(
+                    initialize_config(
+                        &SWAP_PROGRAM_ID,
+                        &config_key,
+                        &other_admin_key,
+                        &other_admin_key,
+                        nonce,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut config_account,
+                        &mut other_admin_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
                 )
             );
         }
 
-        // correctly deposit
+        // a config at another bump names no admin, even when program owned
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            accounts
-                .deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
-                )
-                .unwrap();
-
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
-            let swap_token_b =
-                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, 0);
-            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-            assert_eq!(token_b.amount, 0);
-            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
-            let swap_pool_account =
-                spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
-            let pool_mint =
-                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            let mut other_config_account = Account::new(0, ProgramConfig::LEN, &SWAP_PROGRAM_ID);
+            ProgramConfig::pack(
+                ProgramConfig {
+                    is_initialized: true,
+                    nonce: other_nonce,
+                    admin: admin_key,
+                    fee_wallet: fee_wallet_key,
+                },
+                &mut other_config_account.data,
+            )
+            .unwrap();
             assert_eq!(
-                pool_mint.supply,
-                pool_account.amount + swap_pool_account.amount
+                Err(AmmError::IncorrectConfigAccount.into()),
+                do_process_instruction(
+                    set_fee_wallet(&SWAP_PROGRAM_ID, &other_config_key, &admin_key, &admin_key)
+                        .unwrap(),
+                    vec![
+                        &mut other_config_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
             );
         }
     }
 
     #[test]
-    fn test_withdraw() {
+    fn test_set_fee_wallet() {
         let user_key = Pubkey::new_unique();
-        let return_fee_numerator = 25;
-        let fixed_fee_numerator = 5;
-        let fee_denominator = 10000;
         let fees = Fees {
-            return_fee_numerator,
-            fixed_fee_numerator,
-            fee_denominator
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
         };
-
-        let token_a_amount = 1000;
-        let token_b_amount = 2000;
-        let curve_type = CurveType::ConstantProduct;
         let swap_curve = SwapCurve {
-            curve_type,
+            curve_type: CurveType::ConstantProduct,
             calculator: Box::new(ConstantProductCurve {}),
         };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 5_000_000;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            token_a_amount,
+            token_b_amount,
+        );
+        accounts.initialize_swap().unwrap();
+        let old_fee_wallet_key = accounts.fee_wallet_key;
+        let new_fee_wallet_key = Pubkey::new_unique();
 
-        let withdrawer_key = Pubkey::new_unique();
-        let initial_a = token_a_amount / 10;
-        let initial_b = token_b_amount / 10;
-        let initial_pool = swap_curve.calculator.new_pool_supply() / 10;
-        let withdraw_amount = initial_pool / 4;
-        let minimum_token_a_amount = initial_a / 40;
-        let minimum_token_b_amount = initial_b / 40;
+        // admin did not sign
+        {
+            let mut instruction = set_fee_wallet(
+                &SWAP_PROGRAM_ID,
+                &accounts.config_key,
+                &accounts.admin_key,
+                &new_fee_wallet_key,
+            )
+            .unwrap();
+            instruction.accounts[1].is_signer = false;
+            assert_eq!(
+                Err(ProgramError::MissingRequiredSignature),
+                do_process_instruction(
+                    instruction,
+                    vec![
+                        &mut accounts.config_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
 
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        // signer is not the admin
+        {
+            let wrong_admin_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(AmmError::InvalidAdmin.into()),
+                do_process_instruction(
+                    set_fee_wallet(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.config_key,
+                        &wrong_admin_key,
+                        &new_fee_wallet_key,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.config_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
 
-        // swap not initialized
+        // config account is not at the config address
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let mut wrong_config_account = accounts.config_account.clone();
             assert_eq!(
-                Err(ProgramError::UninitializedAccount),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
+                Err(AmmError::IncorrectConfigAccount.into()),
+                do_process_instruction(
+                    set_fee_wallet(
+                        &SWAP_PROGRAM_ID,
+                        &Pubkey::new_unique(),
+                        &accounts.admin_key,
+                        &new_fee_wallet_key,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut wrong_config_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
                 )
             );
         }
 
-        accounts.initialize_swap().unwrap();
+        // rotate the fee wallet
+        take_events();
+        do_process_instruction(
+            set_fee_wallet(
+                &SWAP_PROGRAM_ID,
+                &accounts.config_key,
+                &accounts.admin_key,
+                &new_fee_wallet_key,
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.config_account,
+                &mut Account::default(),
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        let config = ProgramConfig::unpack(&accounts.config_account.data).unwrap();
+        assert_eq!(config.fee_wallet, new_fee_wallet_key);
+        assert_eq!(config.admin, accounts.admin_key);
+        assert_eq!(
+            take_events(),
+            vec![AmmEvent::SetFeeWallet(FeeWalletEvent {
+                config: accounts.config_key,
+                admin: accounts.admin_key,
+                fee_wallet: new_fee_wallet_key,
+            })]
+        );
+
+        let initial_a = token_a_amount / 5;
+        let (token_a_key, mut token_a_account, token_b_key, mut token_b_account, _pool_key, _pool_account) =
+            accounts.setup_token_accounts(&user_key, &user_key, initial_a, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
 
-        // wrong owner for swap account
+        // swap with the old fee wallet is rejected
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            let old_swap_account = accounts.swap_account;
-            let mut wrong_swap_account = old_swap_account.clone();
-            wrong_swap_account.owner = spl_token::id();
-            accounts.swap_account = wrong_swap_account;
             assert_eq!(
-                Err(ProgramError::IncorrectProgramId),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+                Err(AmmError::IncorrectFeeAccount.into()),
+                accounts.swap(
+                    &user_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
+                    initial_a,
+                    0,
                 )
             );
-            accounts.swap_account = old_swap_account;
         }
 
-        // wrong nonce for authority_key
+        // swap paying the fee accounts of the old fee wallet is rejected
+        accounts.fee_wallet_key = new_fee_wallet_key;
+        assert_eq!(
+            Err(AmmError::IncorrectFeeAccount.into()),
+            accounts.swap(
+                &user_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                initial_a,
+                0,
+            )
+        );
+
+        let (new_fee_a_key, new_fee_account_a) = mint_token(
+            &spl_token::id(),
+            &accounts.token_a_mint_key,
+            &mut accounts.token_a_mint_account,
+            &user_key,
+            &new_fee_wallet_key,
+            0,
+        );
+        let (new_fee_b_key, new_fee_account_b) = mint_token(
+            &spl_token::id(),
+            &accounts.token_b_mint_key,
+            &mut accounts.token_b_mint_account,
+            &user_key,
+            &new_fee_wallet_key,
+            0,
+        );
+        let set_fixed_fee_accounts_with = |accounts: &mut SwapAccountInfo,
+                                           admin_key: &Pubkey,
+                                           (fee_a_key, mut fee_account_a): (Pubkey, Account),
+                                           (fee_b_key, mut fee_account_b): (Pubkey, Account)| {
+            do_process_instruction(
+                set_fixed_fee_accounts(
+                    &SWAP_PROGRAM_ID,
+                    &accounts.swap_key,
+                    &accounts.config_key,
+                    admin_key,
+                    &[fee_a_key, fee_b_key],
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut accounts.config_account,
+                    &mut Account::default(),
+                    &mut fee_account_a,
+                    &mut fee_account_b,
+                ],
+            )
+        };
+        let new_fee_a = (new_fee_a_key, new_fee_account_a.clone());
+        let new_fee_b = (new_fee_b_key, new_fee_account_b.clone());
+
+        // only the admin points the pool at accounts of the new fee wallet,
+        // for the token at the same index
+        let admin_key = accounts.admin_key;
+        assert_eq!(
+            Err(AmmError::InvalidAdmin.into()),
+            set_fixed_fee_accounts_with(
+                &mut accounts,
+                &Pubkey::new_unique(),
+                new_fee_a.clone(),
+                new_fee_b.clone()
+            )
+        );
+        let old_fee_a = (accounts.fee_a_key, accounts.fixed_fee_account_a.clone());
+        assert_eq!(
+            Err(AmmError::IncorrectFeeAccount.into()),
+            set_fixed_fee_accounts_with(&mut accounts, &admin_key, old_fee_a, new_fee_b.clone())
+        );
+        assert_eq!(
+            Err(AmmError::IncorrectFeeAccount.into()),
+            set_fixed_fee_accounts_with(
+                &mut accounts,
+                &admin_key,
+                new_fee_a.clone(),
+                new_fee_a.clone()
+            )
+        );
+        take_events();
+        set_fixed_fee_accounts_with(&mut accounts, &admin_key, new_fee_a, new_fee_b).unwrap();
+        let token_swap = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(*token_swap.fixed_fee_account_a(), new_fee_a_key);
+        assert_eq!(*token_swap.fixed_fee_account_b(), new_fee_b_key);
+        let fixed_fee_account_event = |token_index, fee_account| {
+            AmmEvent::SetFixedFeeAccount(FixedFeeAccountEvent {
+                pool: accounts.swap_key,
+                admin: admin_key,
+                token_index,
+                fee_account,
+            })
+        };
+        assert_eq!(
+            take_events(),
+            vec![
+                fixed_fee_account_event(0, new_fee_a_key),
+                fixed_fee_account_event(1, new_fee_b_key),
+            ]
+        );
+
+        // swap with the new fee wallet pays it the fixed fee
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &spl_token::id(),
-            );
-            accounts.authority_key = bad_authority_key;
-            assert_eq!(
-                Err(AmmError::InvalidProgramAddress.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+            let old_fixed_fee_account_a = accounts.fixed_fee_account_a.clone();
+            accounts.fee_a_key = new_fee_a_key;
+            accounts.fixed_fee_account_a = new_fee_account_a;
+            accounts.fee_b_key = new_fee_b_key;
+            accounts.fixed_fee_account_b = new_fee_account_b;
+            let owner_fee = swap_curve
+                .swap(
+                    u128::from(initial_a),
+                    u128::from(token_a_amount),
+                    u128::from(token_b_amount),
+                    TradeDirection::AtoB,
+                    &fees,
+                )
+                .unwrap()
+                .owner_fee;
+            assert!(owner_fee > 0);
+            accounts
+                .swap(
+                    &user_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
+                    initial_a,
+                    0,
                 )
+                .unwrap();
+            assert_eq!(
+                u128::from(token_amount(&accounts.fixed_fee_account_a)),
+                owner_fee
             );
-            accounts.authority_key = old_authority;
+            assert_eq!(token_amount(&old_fixed_fee_account_a), 0);
         }
 
-        // not enough pool tokens
+        // new pool with fee accounts owned by the old fee wallet is rejected
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
+            let mut new_accounts =
+                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+            let (_fee_a_key, fixed_fee_account_a) = mint_token(
+                &spl_token::id(),
+                &new_accounts.token_a_mint_key,
+                &mut new_accounts.token_a_mint_account,
                 &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                to_u64(withdraw_amount).unwrap() / 2u64,
+                &old_fee_wallet_key,
+                0,
             );
+            let (_fee_b_key, fixed_fee_account_b) = mint_token(
+                &spl_token::id(),
+                &new_accounts.token_b_mint_key,
+                &mut new_accounts.token_b_mint_account,
+                &user_key,
+                &old_fee_wallet_key,
+                0,
+            );
+            new_accounts.fixed_fee_account_a = fixed_fee_account_a;
+            new_accounts.fixed_fee_account_b = fixed_fee_account_b;
+            new_accounts.config_account = accounts.config_account.clone();
             assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount / 2,
-                    minimum_token_b_amount / 2,
-                )
+                Err(AmmError::IncorrectFeeAccount.into()),
+                new_accounts.initialize_swap()
             );
         }
+    }
 
-        // wrong token a / b accounts
+    #[test]
+    fn test_set_fees() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees.clone(), swap_curve, 1_000_000, 5_000_000);
+        accounts.initialize_swap().unwrap();
+
+        let new_fees = Fees {
+            return_fee_numerator: 30,
+            fixed_fee_numerator: 10,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 100,
+        };
+
+        // signer is not the admin
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount.try_into().unwrap(),
-            );
             assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
+                Err(AmmError::InvalidAdmin.into()),
+                do_process_instruction(
+                    set_fees(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &accounts.config_key,
+                        &user_key,
+                        new_fees.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut accounts.config_account,
+                        &mut Account::default(),
+                    ],
                 )
             );
         }
 
-        // wrong pool token account
+        // invalid fees
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount.try_into().unwrap(),
-            );
-            let (
-                wrong_token_a_key,
-                mut wrong_token_a_account,
-                _token_b_key,
-                _token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                withdraw_amount.try_into().unwrap(),
-                initial_b,
-                withdraw_amount.try_into().unwrap(),
-            );
+            let invalid_fees = Fees {
+                return_fee_numerator: 30,
+                fixed_fee_numerator: 10,
+                fee_denominator: 0,
+                owner_withdraw_fee_numerator: 0,
+                owner_withdraw_fee_denominator: 0,
+            };
             assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &wrong_token_a_key,
-                    &mut wrong_token_a_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
+                Err(AmmError::InvalidFee.into()),
+                do_process_instruction(
+                    set_fees(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &accounts.config_key,
+                        &accounts.admin_key,
+                        invalid_fees,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut accounts.config_account,
+                        &mut Account::default(),
+                    ],
                 )
             );
         }
 
-        // wrong pool fee account
+        // fees below the constraints
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                wrong_pool_key,
-                wrong_pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount.try_into().unwrap(),
-            );
-            let (
-                _token_a_key,
-                _token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount.try_into().unwrap(),
-            );
-            let old_fixed_fee_account = accounts.fixed_fee_account_a;
-            let old_pool_fee_key = accounts.fee_a_key;
-            accounts.fixed_fee_account_a = wrong_pool_account;
-            accounts.fee_a_key = wrong_pool_key;
+            let owner_key_str = &user_key.to_string();
+            let valid_curve_types = &[CurveType::ConstantProduct];
+            let constraints = Some(SwapConstraints {
+                owner_key: owner_key_str,
+                valid_curve_types,
+                fees: &new_fees,
+                host_fee: &HostFee {
+                    numerator: 0,
+                    denominator: 0,
+                },
+            });
             assert_eq!(
-                Err(AmmError::IncorrectFeeAccount.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+                Err(AmmError::InvalidFee.into()),
+                do_process_instruction_with_fee_constraints(
+                    set_fees(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &accounts.config_key,
+                        &accounts.admin_key,
+                        fees.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut accounts.config_account,
+                        &mut Account::default(),
+                    ],
+                    &constraints,
+                )
+            );
+        }
+
+        // valid update
+        take_events();
+        do_process_instruction(
+            set_fees(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &accounts.config_key,
+                &accounts.admin_key,
+                new_fees.clone(),
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut accounts.config_account,
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(*swap_state.fees(), new_fees);
+        assert_eq!(*swap_state.pool_mint(), accounts.pool_mint_key);
+        assert_eq!(
+            take_events(),
+            vec![AmmEvent::SetFees(FeesEvent {
+                pool: accounts.swap_key,
+                admin: accounts.admin_key,
+                return_fee_numerator: new_fees.return_fee_numerator,
+                fixed_fee_numerator: new_fees.fixed_fee_numerator,
+                fee_denominator: new_fees.fee_denominator,
+                owner_withdraw_fee_numerator: new_fees.owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator: new_fees.owner_withdraw_fee_denominator,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_amp_ramp() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Box::new(StableCurve { amp: 100 }),
+        };
+        let token_a_amount = 1_000_000_000;
+        let token_b_amount = 1_000_000_000;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve,
+            token_a_amount,
+            token_b_amount,
+        );
+        accounts.initialize_swap().unwrap();
+
+        let admin_key = accounts.admin_key;
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let amount_in = 100_000_000;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &user_key, 4 * amount_in, 0, 0);
+
+        // swaps from A to B trade at the amplification given
+        let mut check_swap = |accounts: &mut SwapAccountInfo, amp| {
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            let results = SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Box::new(StableCurve { amp }),
+            }
+            .swap(
+                u128::from(amount_in),
+                u128::from(swap_token_a.amount),
+                u128::from(swap_token_b.amount),
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+            accounts
+                .swap(
+                    &user_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
+                    amount_in,
+                    0,
+                )
+                .unwrap();
+            let received = spl_token::state::Account::unpack(&token_b_account.data)
+                .unwrap()
+                .amount
+                - token_b.amount;
+            assert_eq!(u128::from(received), results.destination_amount_swapped);
+        };
+
+        let start_ramp_ts = 1_600_000_000;
+        let stop_ramp_ts = start_ramp_ts + MIN_RAMP_DURATION;
+        set_unix_timestamp(start_ramp_ts);
+
+        // signer is not the admin
+        assert_eq!(
+            Err(AmmError::InvalidAdmin.into()),
+            accounts.ramp_amp(&user_key, 1_000, stop_ramp_ts)
+        );
+        assert_eq!(
+            Err(AmmError::InvalidAdmin.into()),
+            accounts.stop_ramp_amp(&user_key)
+        );
+
+        // too fast or too far
+        assert_eq!(
+            Err(AmmError::InvalidAmpRamp.into()),
+            accounts.ramp_amp(&admin_key, 1_000, stop_ramp_ts - 1)
+        );
+        assert_eq!(
+            Err(AmmError::InvalidAmpRamp.into()),
+            accounts.ramp_amp(&admin_key, 1_001, stop_ramp_ts)
+        );
+
+        // nothing to stop
+        assert_eq!(
+            Err(AmmError::InvalidAmpRamp.into()),
+            accounts.stop_ramp_amp(&admin_key)
+        );
+
+        take_events();
+        accounts.ramp_amp(&admin_key, 1_000, stop_ramp_ts).unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(
+            *swap_state.amp_ramp(),
+            AmpRamp {
+                initial_amp: 100,
+                target_amp: 1_000,
+                start_ramp_ts,
+                stop_ramp_ts,
+            }
+        );
+        assert_eq!(
+            take_events(),
+            vec![AmmEvent::RampAmp(AmpRampEvent {
+                pool: accounts.swap_key,
+                admin: admin_key,
+                initial_amp: 100,
+                target_amp: 1_000,
+                start_ramp_ts,
+                stop_ramp_ts,
+            })]
+        );
+        assert_eq!(
+            Err(AmmError::InvalidAmpRamp.into()),
+            accounts.ramp_amp(&admin_key, 200, stop_ramp_ts + 1)
+        );
+
+        // the ramp starts from the current amplification
+        check_swap(&mut accounts, 100);
+
+        // halfway through
+        set_unix_timestamp(start_ramp_ts + MIN_RAMP_DURATION / 2);
+        check_swap(&mut accounts, 550);
+
+        // stopped, the amplification it reached is kept
+        take_events();
+        accounts.stop_ramp_amp(&admin_key).unwrap();
+        let stopped_ts = start_ramp_ts + MIN_RAMP_DURATION / 2;
+        assert_eq!(
+            take_events(),
+            vec![AmmEvent::StopRampAmp(AmpRampEvent {
+                pool: accounts.swap_key,
+                admin: admin_key,
+                initial_amp: 550,
+                target_amp: 550,
+                start_ramp_ts: stopped_ts,
+                stop_ramp_ts: stopped_ts,
+            })]
+        );
+        assert_eq!(
+            Err(AmmError::InvalidAmpRamp.into()),
+            accounts.stop_ramp_amp(&admin_key)
+        );
+        set_unix_timestamp(stop_ramp_ts);
+        check_swap(&mut accounts, 550);
+
+        // a new ramp goes on from there
+        accounts
+            .ramp_amp(&admin_key, 55, stop_ramp_ts + MIN_RAMP_DURATION)
+            .unwrap();
+        set_unix_timestamp(stop_ramp_ts + MIN_RAMP_DURATION);
+        check_swap(&mut accounts, 55);
+
+        // only stable curves ramp
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees,
+            swap_curve,
+            token_a_amount,
+            token_b_amount,
+        );
+        accounts.initialize_swap().unwrap();
+        let admin_key = accounts.admin_key;
+        assert_eq!(
+            Err(AmmError::UnsupportedCurveOperation.into()),
+            accounts.ramp_amp(&admin_key, 1_000, i64::MAX)
+        );
+    }
+
+    #[test]
+    fn test_price_oracle() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let start_ts = 1_600_000_000;
+        set_unix_timestamp(start_ts);
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, 1_000_000, 5_000_000);
+        accounts.initialize_swap().unwrap();
+        let price_oracle = |accounts: &SwapAccountInfo| {
+            *SwapVersion::unpack(&accounts.swap_account.data)
+                .unwrap()
+                .price_oracle()
+        };
+        let reserves = |accounts: &SwapAccountInfo| {
+            (
+                spl_token::state::Account::unpack(&accounts.token_a_account.data)
+                    .unwrap()
+                    .amount,
+                spl_token::state::Account::unpack(&accounts.token_b_account.data)
+                    .unwrap()
+                    .amount,
+            )
+        };
+        assert_eq!(price_oracle(&accounts), PriceOracle::new(start_ts));
+
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &user_key, 1_000_000, 1_000_000, 0);
+
+        // the price of A is 5 B until the first swap
+        set_unix_timestamp(start_ts + 100);
+        accounts
+            .swap(
+                &user_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                100_000,
+                0,
+            )
+            .unwrap();
+        let oracle = price_oracle(&accounts);
+        assert_eq!(oracle.last_update_ts, start_ts + 100);
+        assert_eq!(oracle.price_a_cumulative, 100 * (5 << 64));
+        assert_eq!(oracle.price_b_cumulative, 100 * ((1 << 64) / 5));
+        assert_eq!(oracle.observations().len(), 1);
+
+        // a deposit keeps the price, but still accumulates the one since the
+        // swap
+        let (token_a_amount, token_b_amount) = reserves(&accounts);
+        let observed = oracle
+            .observe(start_ts + OBSERVATION_PERIOD, token_a_amount, token_b_amount)
+            .unwrap();
+        set_unix_timestamp(start_ts + OBSERVATION_PERIOD);
+        accounts
+            .deposit_all_token_types(
+                &user_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                1_000_000,
+                1_000_000,
+                1_000_000,
+            )
+            .unwrap();
+        let oracle = price_oracle(&accounts);
+        assert_eq!(oracle.latest(), observed);
+        let observations = oracle.observations();
+        assert_eq!(observations.len(), 2);
+        assert_eq!(observations[1], observed);
+
+        // time weighted between the two prices
+        let (price_a, _) = twap(&observations[0], &observations[1]).unwrap();
+        let swap_price_a = (u128::from(token_b_amount) << 64) / u128::from(token_a_amount);
+        let period = OBSERVATION_PERIOD as u128;
+        assert_eq!(
+            price_a,
+            (100 * (5 << 64) + (period - 100) * swap_price_a) / period
+        );
+
+        // failed instructions leave the oracle as it was
+        set_unix_timestamp(start_ts + 2 * OBSERVATION_PERIOD);
+        assert_eq!(
+            Err(AmmError::ExceededSlippage.into()),
+            accounts.swap(
+                &user_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                1_000,
+                u64::MAX,
+            )
+        );
+        assert_eq!(price_oracle(&accounts), oracle);
+    }
+
+    #[test]
+    fn test_pool_stats() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            1_000_000,
+            5_000_000,
+        );
+        accounts.initialize_swap().unwrap();
+        let stats = |accounts: &SwapAccountInfo| {
+            *SwapVersion::unpack(&accounts.swap_account.data)
+                .unwrap()
+                .stats()
+        };
+        let reserves = |accounts: &SwapAccountInfo| {
+            (
+                u128::from(
+                    spl_token::state::Account::unpack(&accounts.token_a_account.data)
+                        .unwrap()
+                        .amount,
                 ),
-            );
-            accounts.fixed_fee_account_a = old_fixed_fee_account;
-            accounts.fee_a_key = old_pool_fee_key;
-        }
+                u128::from(
+                    spl_token::state::Account::unpack(&accounts.token_b_account.data)
+                        .unwrap()
+                        .amount,
+                ),
+            )
+        };
+        assert_eq!(stats(&accounts), PoolStats::default());
 
-        // no approval
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                0,
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &user_key, 1_000_000, 1_000_000, 0);
+
+        let mut expected = PoolStats::default();
+        for (trade_direction, amount_in) in &[
+            (TradeDirection::AtoB, 100_000),
+            (TradeDirection::AtoB, 50_000),
+            (TradeDirection::BtoA, 200_000),
+        ] {
+            let (token_a_amount, token_b_amount) = reserves(&accounts);
+            let result = match trade_direction {
+                TradeDirection::AtoB => {
+                    let result = swap_curve
+                        .swap(
+                            *amount_in,
+                            token_a_amount,
+                            token_b_amount,
+                            *trade_direction,
+                            &fees,
+                        )
+                        .unwrap();
+                    accounts
+                        .swap(
+                            &user_key,
+                            &token_a_key,
+                            &mut token_a_account,
+                            &swap_token_a_key,
+                            &swap_token_b_key,
+                            &token_b_key,
+                            &mut token_b_account,
+                            *amount_in as u64,
+                            0,
+                        )
+                        .unwrap();
+                    result
+                }
+                TradeDirection::BtoA => {
+                    let result = swap_curve
+                        .swap(
+                            *amount_in,
+                            token_b_amount,
+                            token_a_amount,
+                            *trade_direction,
+                            &fees,
+                        )
+                        .unwrap();
+                    accounts
+                        .swap(
+                            &user_key,
+                            &token_b_key,
+                            &mut token_b_account,
+                            &swap_token_b_key,
+                            &swap_token_a_key,
+                            &token_a_key,
+                            &mut token_a_account,
+                            *amount_in as u64,
+                            0,
+                        )
+                        .unwrap();
+                    result
+                }
+            };
+            expected.record_swap(
+                *trade_direction,
+                result.source_amount_swapped,
+                result.owner_fee,
                 0,
-                withdraw_amount.try_into().unwrap(),
-            );
-            let user_transfer_authority_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    withdraw_all_token_types(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &user_transfer_authority_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        &token_b_key,
-                        WithdrawAllTokenTypes {
-                            pool_token_amount: withdraw_amount.try_into().unwrap(),
-                            minimum_token_a_amount,
-                            minimum_token_b_amount,
-                        }
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut token_b_account,
-                        &mut accounts.fixed_fee_account_a,
-                        &mut accounts.fixed_fee_account_b,
-                        &mut Account::default(),
-                    ],
-                )
+                result.trade_fee,
             );
         }
+        let swap_stats = stats(&accounts);
+        assert_eq!(swap_stats, expected);
+        assert_eq!(swap_stats.swap_count, 3);
+        assert_eq!(swap_stats.volume_a_to_b, 150_000);
+        // the volume is what the pool took in, the curve may round the last
+        // tokens of the input away
+        assert!(swap_stats.volume_b_to_a <= 200_000);
+        assert!(swap_stats.volume_b_to_a > 199_990);
+        assert_eq!(
+            swap_stats.fixed_fees_a,
+            u128::from(
+                spl_token::state::Account::unpack(&accounts.fixed_fee_account_a.data)
+                    .unwrap()
+                    .amount
+            )
+        );
+        assert!(swap_stats.return_fees_b > 0);
 
-        // wrong token program id
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
+        // a failed swap is not counted
+        assert_eq!(
+            Err(AmmError::ExceededSlippage.into()),
+            accounts.swap(
                 &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount.try_into().unwrap(),
-            );
-            let wrong_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(AmmError::IncorrectTokenProgramId.into()),
-                do_process_instruction(
-                    withdraw_all_token_types(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_key,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.authority_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        &token_b_key,
-                        WithdrawAllTokenTypes {
-                            pool_token_amount: withdraw_amount.try_into().unwrap(),
-                            minimum_token_a_amount,
-                            minimum_token_b_amount,
-                        },
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut token_b_account,
-                        &mut accounts.fixed_fee_account_a,
-                        &mut accounts.fixed_fee_account_b,
-                        &mut Account::default(),
-                    ],
-                )
-            );
-        }
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                1_000,
+                u64::MAX,
+            )
+        );
+        assert_eq!(stats(&accounts), expected);
 
-        // wrong swap token accounts
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
+        accounts
+            .deposit_all_token_types(
                 &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                1_000_000,
+                1_000_000,
+                1_000_000,
+            )
+            .unwrap();
+        accounts
+            .withdraw_all_token_types(
+                &user_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                500_000,
+                0,
+                0,
+            )
+            .unwrap();
+        expected.record_deposit();
+        expected.record_withdraw();
+        assert_eq!(stats(&accounts), expected);
+    }
 
-            let old_a_key = accounts.token_a_key;
-            let old_a_account = accounts.token_a_account;
+    #[test]
+    fn test_protocol_fee() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            1_000_000,
+            5_000_000,
+        );
+        accounts.initialize_swap().unwrap();
+        let admin_key = accounts.admin_key;
+        let protocol_fee = |accounts: &SwapAccountInfo| {
+            *SwapVersion::unpack(&accounts.swap_account.data)
+                .unwrap()
+                .protocol_fee()
+        };
+        let pool_supply = |accounts: &SwapAccountInfo| {
+            spl_token::state::Mint::unpack(&accounts.pool_mint_account.data)
+                .unwrap()
+                .supply
+        };
 
-            accounts.token_a_key = token_a_key;
-            accounts.token_a_account = token_a_account.clone();
+        // the protocol takes a share of the growth of the whole pool
+        assert_eq!(
+            Err(AmmError::InvalidFee.into()),
+            accounts.set_protocol_fee(&admin_key, 1, 1)
+        );
+        assert_eq!(
+            Err(AmmError::InvalidAdmin.into()),
+            accounts.set_protocol_fee(&user_key, 1, 6)
+        );
+        take_events();
+        accounts.set_protocol_fee(&admin_key, 1, 6).unwrap();
+        assert_eq!(
+            protocol_fee(&accounts),
+            ProtocolFee {
+                numerator: 1,
+                denominator: 6,
+                fee_account: accounts.protocol_fee_key,
+                root_k_last: 0,
+            }
+        );
+        assert_eq!(
+            take_events(),
+            vec![AmmEvent::SetProtocolFee(ProtocolFeeEvent {
+                pool: accounts.swap_key,
+                admin: admin_key,
+                numerator: 1,
+                denominator: 6,
+                fee_account: accounts.protocol_fee_key,
+            })]
+        );
 
-            // wrong swap token a account
-            assert_eq!(
-                Err(AmmError::IncorrectSwapAccount.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                )
-            );
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &user_key, 1_000_000, 5_000_000, 0);
 
-            accounts.token_a_key = old_a_key;
-            accounts.token_a_account = old_a_account;
+        // the first deposit only records sqrt(k)
+        accounts
+            .deposit_all_token_types(
+                &user_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                pool_supply(&accounts) / 10,
+                u64::MAX,
+                u64::MAX,
+            )
+            .unwrap();
+        assert_eq!(token_amount(&accounts.protocol_fee_account), 0);
+        let root_k_last = ProtocolFee::root_k(
+            token_amount(&accounts.token_a_account),
+            token_amount(&accounts.token_b_account),
+        );
+        assert_eq!(protocol_fee(&accounts).root_k_last, root_k_last);
 
-            let old_b_key = accounts.token_b_key;
-            let old_b_account = accounts.token_b_account;
+        // swaps keep their fixed fee in the pool
+        let token_a_amount = token_amount(&accounts.token_a_account);
+        accounts
+            .swap(
+                &user_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                100_000,
+                0,
+            )
+            .unwrap();
+        assert_eq!(token_amount(&accounts.fixed_fee_account_a), 0);
+        assert_eq!(
+            token_amount(&accounts.token_a_account),
+            token_a_amount + 100_000
+        );
+        // and the stats count it as returned to the pool
+        let stats = *SwapVersion::unpack(&accounts.swap_account.data)
+            .unwrap()
+            .stats();
+        assert_eq!(stats.volume_a_to_b, 100_000);
+        assert_eq!(stats.fixed_fees_a, 0);
+        assert_eq!(
+            stats.return_fees_a,
+            fees.return_fee(100_000).unwrap() + fees.fixed_fee(100_000).unwrap()
+        );
 
-            accounts.token_b_key = token_b_key;
-            accounts.token_b_account = token_b_account.clone();
+        // the next liquidity event pays the protocol in pool tokens
+        let root_k = ProtocolFee::root_k(
+            token_amount(&accounts.token_a_account),
+            token_amount(&accounts.token_b_account),
+        );
+        assert!(root_k > root_k_last);
+        let supply = pool_supply(&accounts);
+        let expected = protocol_fee(&accounts)
+            .protocol_fee(root_k, u128::from(supply))
+            .unwrap() as u64;
+        assert!(expected > 0);
+
+        let old_protocol_fee_key = accounts.protocol_fee_key;
+        accounts.protocol_fee_key = Pubkey::new_unique();
+        assert_eq!(
+            Err(AmmError::IncorrectFeeAccount.into()),
+            accounts.withdraw_all_token_types(
+                &user_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                1_000,
+                0,
+                0,
+            )
+        );
+        accounts.protocol_fee_key = old_protocol_fee_key;
 
-            // wrong swap token b account
-            assert_eq!(
-                Err(AmmError::IncorrectSwapAccount.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                )
-            );
+        accounts
+            .withdraw_all_token_types(
+                &user_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                1_000,
+                0,
+                0,
+            )
+            .unwrap();
+        assert_eq!(token_amount(&accounts.protocol_fee_account), expected);
+        assert_eq!(pool_supply(&accounts), supply + expected - 1_000);
+        assert_eq!(
+            protocol_fee(&accounts).root_k_last,
+            ProtocolFee::root_k(
+                token_amount(&accounts.token_a_account),
+                token_amount(&accounts.token_b_account),
+            )
+        );
 
-            accounts.token_b_key = old_b_key;
-            accounts.token_b_account = old_b_account;
-        }
+        // sqrt(k) only measures fees of constant product pools
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees,
+            SwapCurve {
+                curve_type: CurveType::ConstantPrice,
+                calculator: Box::new(ConstantPriceCurve { token_b_price: 1 }),
+            },
+            1_000_000,
+            1_000_000,
+        );
+        accounts.initialize_swap().unwrap();
+        let admin_key = accounts.admin_key;
+        assert_eq!(
+            Err(AmmError::UnsupportedCurveOperation.into()),
+            accounts.set_protocol_fee(&admin_key, 1, 6)
+        );
+        accounts.set_protocol_fee(&admin_key, 0, 0).unwrap();
+    }
 
-        // wrong mint
+    #[test]
+    fn test_native_sol() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            1_000_000,
+            5_000_000,
+        );
+        // token A of the pool is WSOL
+        accounts.token_a_mint_key = spl_token::native_mint::id();
+        for account in [
+            &mut accounts.token_a_account,
+            &mut accounts.fixed_fee_account_a,
+        ]
+        .iter_mut()
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
-            let (pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
+            let mut token_account = spl_token::state::Account::unpack(&account.data).unwrap();
+            token_account.mint = spl_token::native_mint::id();
+            token_account.is_native = COption::Some(account.lamports);
+            account.lamports += token_account.amount;
+            spl_token::state::Account::pack(token_account, &mut account.data).unwrap();
+        }
+        accounts.market_account = create_market(
+            &accounts.dex_program_id,
+            &accounts.market_key,
+            &accounts.token_a_mint_key,
+            &accounts.token_b_mint_key,
+        );
+        accounts.initialize_swap().unwrap();
 
-            assert_eq!(
-                Err(AmmError::IncorrectPoolMint.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
+        let wallet_key = Pubkey::new_unique();
+        let mut wallet_account =
+            Account::new(1_000_000_000, 0, &solana_program::system_program::id());
+        let (native_sol_key, _nonce) =
+            find_native_sol_address(&SWAP_PROGRAM_ID, &accounts.swap_key, &wallet_key);
+        let mut native_sol_account =
+            Account::new(0, spl_token::state::Account::LEN, &spl_token::id());
+        let mut rent_sysvar_account = create_account_for_test(&Rent::default());
+        let mut fee_wallet_account = Account::default();
+        let (_, _, token_b_key, mut token_b_account, pool_key, mut pool_account) =
+            accounts.setup_token_accounts(&user_key, &wallet_key, 0, 1_000_000, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let native_sol_swap = |accounts: &SwapAccountInfo,
+                               source_key: &Pubkey,
+                               swap_source_key: &Pubkey,
+                               swap_destination_key: &Pubkey,
+                               destination_key: &Pubkey,
+                               fixed_fee_key: &Pubkey,
+                               amount_in: u64| {
+            native_sol(
+                swap(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &wallet_key,
+                    source_key,
+                    swap_source_key,
+                    swap_destination_key,
+                    destination_key,
+                    &accounts.pool_mint_key,
+                    fixed_fee_key,
+                    &accounts.fee_wallet_key,
+                    &accounts.config_key,
+                    SwapInstruction {
+                        amount_in,
+                        minimum_amount_out: 0,
+                        deadline: None,
+                    },
                 )
-            );
+                .unwrap(),
+            )
+            .unwrap()
+        };
 
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
-        }
+        // SOL in, the fixed fee paid in SOL to the fee wallet
+        let amount_in = 100_000;
+        let result = swap_curve
+            .swap(amount_in, 1_000_000, 5_000_000, TradeDirection::AtoB, &fees)
+            .unwrap();
+        let owner_fee = result.owner_fee as u64;
+        let amount_out = result.destination_amount_swapped as u64;
+        do_process_instruction(
+            native_sol_swap(
+                &accounts,
+                &native_sol_key,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &accounts.fee_a_key,
+                amount_in as u64,
+            ),
+            vec![
+                &mut Account::default(),
+                &mut rent_sysvar_account,
+                &mut Account::default(),
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut wallet_account,
+                &mut native_sol_account,
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut token_b_account,
+                &mut accounts.pool_mint_account,
+                &mut accounts.fixed_fee_account_a,
+                &mut fee_wallet_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.config_account,
+            ],
+        )
+        .unwrap();
+        assert_eq!(wallet_account.lamports, 1_000_000_000 - amount_in as u64);
+        assert_eq!(native_sol_account.lamports, 0);
+        assert_eq!(fee_wallet_account.lamports, owner_fee);
+        assert_eq!(
+            token_amount(&accounts.token_a_account),
+            1_000_000 + amount_in as u64 - owner_fee
+        );
+        assert_eq!(token_amount(&token_b_account), 1_000_000 + amount_out);
 
-        // withdrawing 1 pool token fails because it equates to 0 output tokens
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
-            assert_eq!(
-                Err(AmmError::ZeroTradingTokens.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    1,
-                    0,
-                    0,
-                )
-            );
-        }
+        // SOL out
+        let token_a_amount = token_amount(&accounts.token_a_account);
+        let token_b_amount = token_amount(&accounts.token_b_account);
+        let result = swap_curve
+            .swap(
+                500_000,
+                token_b_amount.into(),
+                token_a_amount.into(),
+                TradeDirection::BtoA,
+                &fees,
+            )
+            .unwrap();
+        let amount_out = result.destination_amount_swapped as u64;
+        let wallet_lamports = wallet_account.lamports;
+        do_process_instruction(
+            native_sol_swap(
+                &accounts,
+                &token_b_key,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &native_sol_key,
+                &accounts.fee_b_key,
+                500_000,
+            ),
+            vec![
+                &mut Account::default(),
+                &mut rent_sysvar_account,
+                &mut Account::default(),
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut wallet_account,
+                &mut token_b_account,
+                &mut accounts.token_b_account,
+                &mut accounts.token_a_account,
+                &mut native_sol_account,
+                &mut accounts.pool_mint_account,
+                &mut accounts.fixed_fee_account_b,
+                &mut fee_wallet_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.config_account,
+            ],
+        )
+        .unwrap();
+        assert_eq!(wallet_account.lamports, wallet_lamports + amount_out);
+        assert_eq!(native_sol_account.lamports, 0);
+        assert_eq!(
+            token_amount(&accounts.token_a_account),
+            token_a_amount - amount_out
+        );
 
-        // slippage exceeded
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
-            // minimum A amount out too high
-            assert_eq!(
-                Err(AmmError::ExceededSlippage.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
+        // deposits wrap the maximum amount and refund what is left
+        let token_a_amount = token_amount(&accounts.token_a_account);
+        let pool_token_amount = 1_000_000;
+        let pool_supply = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data)
+            .unwrap()
+            .supply;
+        let results = swap_curve
+            .calculator
+            .pool_tokens_to_trading_tokens(
+                pool_token_amount.into(),
+                pool_supply.into(),
+                token_a_amount.into(),
+                token_amount(&accounts.token_b_account).into(),
+                RoundDirection::Ceiling,
+            )
+            .unwrap();
+        let wallet_lamports = wallet_account.lamports;
+        do_process_instruction(
+            native_sol(
+                deposit_all_token_types(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &wallet_key,
+                    &native_sol_key,
                     &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount * 10,
-                    minimum_token_b_amount,
-                )
-            );
-            // minimum B amount out too high
-            assert_eq!(
-                Err(AmmError::ExceededSlippage.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &accounts.pool_mint_key,
                     &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount * 10,
+                    DepositInstruction {
+                        pool_token_amount,
+                        maximum_token_a_amount: 10_000_000,
+                        maximum_token_b_amount: u64::MAX,
+                        deadline: None,
+                    },
                 )
-            );
-        }
+                .unwrap(),
+            )
+            .unwrap(),
+            vec![
+                &mut Account::default(),
+                &mut rent_sysvar_account,
+                &mut Account::default(),
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut wallet_account,
+                &mut native_sol_account,
+                &mut token_b_account,
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut accounts.pool_mint_account,
+                &mut pool_account,
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        let deposited_amount = results.token_a_amount as u64;
+        assert_eq!(wallet_account.lamports, wallet_lamports - deposited_amount);
+        assert_eq!(native_sol_account.lamports, 0);
+        assert_eq!(
+            token_amount(&accounts.token_a_account),
+            token_a_amount + deposited_amount
+        );
+        assert_eq!(token_amount(&pool_account), pool_token_amount);
 
-        // invalid input: can't use swap pool tokens as destination
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
-            let swap_token_a_key = accounts.token_a_key;
-            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
-            assert_eq!(
-                Err(AmmError::InvalidInput.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
+        // withdrawals unwrap into the wallet
+        let token_a_amount = token_amount(&accounts.token_a_account);
+        let wallet_lamports = wallet_account.lamports;
+        let withdraw_with = |accounts: &SwapAccountInfo, destination_token_a_key: &Pubkey| {
+            native_sol(
+                withdraw_all_token_types(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &wallet_key,
+                    &accounts.pool_mint_key,
                     &pool_key,
-                    &mut pool_account,
                     &swap_token_a_key,
-                    &mut swap_token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                )
-            );
-            let swap_token_b_key = accounts.token_b_key;
-            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
-            assert_eq!(
-                Err(AmmError::InvalidInput.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
                     &swap_token_b_key,
-                    &mut swap_token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
+                    destination_token_a_key,
+                    &token_b_key,
+                    &accounts.config_key,
+                    &accounts.pool_fee_key,
+                    WithdrawInstruction {
+                        pool_token_amount,
+                        minimum_token_a_amount: 0,
+                        minimum_token_b_amount: 0,
+                        deadline: None,
+                    },
                 )
-            );
-        }
-
-        // correct withdrawal
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
-
-            accounts
-                .withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
+                .unwrap(),
+            )
+            .unwrap()
+        };
+        // only the temporary account of the wallet is wrapped
+        let mut token_a_account = native_sol_account.clone();
+        assert_eq!(
+            Err(AmmError::IncorrectNativeSolAccount.into()),
+            do_process_instruction(
+                withdraw_with(&accounts, &Pubkey::new_unique()),
+                vec![
+                    &mut Account::default(),
+                    &mut rent_sysvar_account,
+                    &mut Account::default(),
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut wallet_account,
+                    &mut accounts.pool_mint_account,
                     &mut pool_account,
-                    &token_a_key,
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
                     &mut token_a_account,
-                    &token_b_key,
                     &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                )
-                .unwrap();
+                    &mut Account::default(),
+                ],
+            )
+        );
+        do_process_instruction(
+            withdraw_with(&accounts, &native_sol_key),
+            vec![
+                &mut Account::default(),
+                &mut rent_sysvar_account,
+                &mut Account::default(),
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut wallet_account,
+                &mut accounts.pool_mint_account,
+                &mut pool_account,
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut native_sol_account,
+                &mut token_b_account,
+                &mut Account::default(),
+                &mut accounts.config_account,
+                &mut accounts.pool_fee_account,
+            ],
+        )
+        .unwrap();
+        let withdrawn_amount = token_a_amount - token_amount(&accounts.token_a_account);
+        assert!(withdrawn_amount > 0);
+        assert_eq!(wallet_account.lamports, wallet_lamports + withdrawn_amount);
+        assert_eq!(native_sol_account.lamports, 0);
+        assert_eq!(token_amount(&pool_account), 0);
+
+        // lamports sent to the temporary account beforehand do not block it,
+        // they are unwrapped into the wallet with the rest
+        let prefunded_lamports = 5_000;
+        let mut native_sol_account = Account::new(
+            prefunded_lamports,
+            spl_token::state::Account::LEN,
+            &solana_program::system_program::id(),
+        );
+        let wallet_lamports = wallet_account.lamports;
+        let token_b_amount = token_amount(&token_b_account);
+        let amount_in = 100_000;
+        do_process_instruction(
+            native_sol_swap(
+                &accounts,
+                &native_sol_key,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &accounts.fee_a_key,
+                amount_in,
+            ),
+            vec![
+                &mut Account::default(),
+                &mut rent_sysvar_account,
+                &mut Account::default(),
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut wallet_account,
+                &mut native_sol_account,
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut token_b_account,
+                &mut accounts.pool_mint_account,
+                &mut accounts.fixed_fee_account_a,
+                &mut fee_wallet_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.config_account,
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            wallet_account.lamports,
+            wallet_lamports + prefunded_lamports - amount_in
+        );
+        assert_eq!(native_sol_account.lamports, 0);
+        assert!(token_amount(&token_b_account) > token_b_amount);
 
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-            let swap_token_b =
-                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-            let pool_mint =
-                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-            let withdraw_fee = 0;
-            // accounts.fees.owner_withdraw_fee(withdraw_amount).unwrap();
-            let results = accounts
-                .swap_curve
-                .calculator
-                .pool_tokens_to_trading_tokens(
-                    withdraw_amount - withdraw_fee,
-                    pool_mint.supply.try_into().unwrap(),
-                    swap_token_a.amount.try_into().unwrap(),
-                    swap_token_b.amount.try_into().unwrap(),
-                    RoundDirection::Floor,
+        // single token deposits wrap the exact amount deposited
+        let token_a_amount = token_amount(&accounts.token_a_account);
+        let wallet_lamports = wallet_account.lamports;
+        let source_token_amount = 200_000;
+        do_process_instruction(
+            native_sol(
+                deposit_single_token_type_exact_amount_in(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &wallet_key,
+                    &native_sol_key,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &accounts.pool_mint_key,
+                    &pool_key,
+                    DepositSingleTokenTypeExactAmountIn {
+                        source_token_amount,
+                        minimum_pool_token_amount: 1,
+                        deadline: None,
+                    },
                 )
-                .unwrap();
-            assert_eq!(
-                swap_token_a.amount,
-                token_a_amount - to_u64(results.token_a_amount).unwrap()
-            );
-            assert_eq!(
-                swap_token_b.amount,
-                token_b_amount - to_u64(results.token_b_amount).unwrap()
-            );
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(
-                token_a.amount,
-                initial_a + to_u64(results.token_a_amount).unwrap()
-            );
-            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-            assert_eq!(
-                token_b.amount,
-                initial_b + to_u64(results.token_b_amount).unwrap()
-            );
-            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
-            assert_eq!(
-                pool_account.amount,
-                to_u64(initial_pool - withdraw_amount).unwrap()
-            );
-            let fee_account_a =
-                spl_token::state::Account::unpack(&accounts.fixed_fee_account_a.data).unwrap();
-            assert_eq!(
-                fee_account_a.amount,
-                TryInto::<u64>::try_into(withdraw_fee).unwrap()
-            );
-        }
+                .unwrap(),
+            )
+            .unwrap(),
+            vec![
+                &mut Account::default(),
+                &mut rent_sysvar_account,
+                &mut Account::default(),
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut wallet_account,
+                &mut native_sol_account,
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut accounts.pool_mint_account,
+                &mut pool_account,
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(wallet_account.lamports, wallet_lamports - source_token_amount);
+        assert_eq!(native_sol_account.lamports, 0);
+        assert_eq!(
+            token_amount(&accounts.token_a_account),
+            token_a_amount + source_token_amount
+        );
+        let pool_token_amount = token_amount(&pool_account);
+        assert!(pool_token_amount > 0);
 
-        // correct withdrawal from fee account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
+        // single token withdrawals unwrap the exact amount withdrawn
+        let token_a_amount = token_amount(&accounts.token_a_account);
+        let wallet_lamports = wallet_account.lamports;
+        let destination_token_amount = 50_000;
+        do_process_instruction(
+            native_sol(
+                withdraw_single_token_type_exact_amount_out(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &wallet_key,
+                    &accounts.pool_mint_key,
+                    &pool_key,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &native_sol_key,
+                    &accounts.fee_a_key,
+                    &accounts.fee_b_key,
+                    &accounts.config_key,
+                    &accounts.pool_fee_key,
+                    WithdrawSingleTokenTypeExactAmountOut {
+                        destination_token_amount,
+                        maximum_pool_token_amount: pool_token_amount,
+                        deadline: None,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap(),
+            vec![
+                &mut Account::default(),
+                &mut rent_sysvar_account,
+                &mut Account::default(),
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut wallet_account,
+                &mut accounts.pool_mint_account,
+                &mut pool_account,
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut native_sol_account,
+                &mut accounts.fixed_fee_account_a,
+                &mut accounts.fixed_fee_account_b,
+                &mut Account::default(),
+                &mut accounts.config_account,
+                &mut accounts.pool_fee_account,
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            wallet_account.lamports,
+            wallet_lamports + destination_token_amount
+        );
+        assert_eq!(native_sol_account.lamports, 0);
+        assert_eq!(
+            token_amount(&accounts.token_a_account),
+            token_a_amount - destination_token_amount
+        );
+        assert!(token_amount(&pool_account) < pool_token_amount);
+
+        let native_sol_swap_exact_out = |accounts: &SwapAccountInfo,
+                                         source_key: &Pubkey,
+                                         swap_source_key: &Pubkey,
+                                         swap_destination_key: &Pubkey,
+                                         destination_key: &Pubkey,
+                                         fixed_fee_key: &Pubkey,
+                                         amount_out: u64| {
+            native_sol(
+                swap_exact_amount_out(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &wallet_key,
+                    source_key,
+                    swap_source_key,
+                    swap_destination_key,
+                    destination_key,
+                    &accounts.pool_mint_key,
+                    fixed_fee_key,
+                    &accounts.fee_wallet_key,
+                    &accounts.config_key,
+                    SwapExactAmountOutInstruction {
+                        amount_out,
+                        maximum_amount_in: 1_000_000,
+                        deadline: None,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
 
-            let pool_fee_key = accounts.fee_a_key;
-            let mut fixed_fee_account = accounts.fixed_fee_account_a.clone();
-            let fee_account = spl_token::state::Account::unpack(&fixed_fee_account.data).unwrap();
-            let pool_fee_amount = fee_account.amount;
+        // exact out swaps wrap the maximum amount in and refund what is left
+        let token_a_amount = token_amount(&accounts.token_a_account);
+        let token_b_amount = token_amount(&token_b_account);
+        let wallet_lamports = wallet_account.lamports;
+        let fee_wallet_lamports = fee_wallet_account.lamports;
+        let amount_out = 10_000;
+        do_process_instruction(
+            native_sol_swap_exact_out(
+                &accounts,
+                &native_sol_key,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &accounts.fee_a_key,
+                amount_out,
+            ),
+            vec![
+                &mut Account::default(),
+                &mut rent_sysvar_account,
+                &mut Account::default(),
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut wallet_account,
+                &mut native_sol_account,
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut token_b_account,
+                &mut accounts.pool_mint_account,
+                &mut accounts.fixed_fee_account_a,
+                &mut fee_wallet_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.config_account,
+            ],
+        )
+        .unwrap();
+        let amount_in = token_amount(&accounts.token_a_account) - token_a_amount
+            + (fee_wallet_account.lamports - fee_wallet_lamports);
+        assert!(amount_in > 0);
+        assert!(amount_in < 1_000_000);
+        assert_eq!(wallet_account.lamports, wallet_lamports - amount_in);
+        assert_eq!(native_sol_account.lamports, 0);
+        assert_eq!(token_amount(&token_b_account), token_b_amount + amount_out);
+
+        // exact out swaps unwrap the exact amount out
+        let token_a_amount = token_amount(&accounts.token_a_account);
+        let wallet_lamports = wallet_account.lamports;
+        do_process_instruction(
+            native_sol_swap_exact_out(
+                &accounts,
+                &token_b_key,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &native_sol_key,
+                &accounts.fee_b_key,
+                amount_out,
+            ),
+            vec![
+                &mut Account::default(),
+                &mut rent_sysvar_account,
+                &mut Account::default(),
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut wallet_account,
+                &mut token_b_account,
+                &mut accounts.token_b_account,
+                &mut accounts.token_a_account,
+                &mut native_sol_account,
+                &mut accounts.pool_mint_account,
+                &mut accounts.fixed_fee_account_b,
+                &mut fee_wallet_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.config_account,
+            ],
+        )
+        .unwrap();
+        assert_eq!(wallet_account.lamports, wallet_lamports + amount_out);
+        assert_eq!(native_sol_account.lamports, 0);
+        assert_eq!(
+            token_amount(&accounts.token_a_account),
+            token_a_amount - amount_out
+        );
+    }
 
-            accounts
-                .withdraw_all_token_types(
-                    &user_key,
-                    &pool_fee_key,
-                    &mut fixed_fee_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    pool_fee_amount,
-                    0,
-                    0,
-                )
-                .unwrap();
+    // Swap whose token B is a Token-2022 mint with `extension`, initialized
+    // by `init_extension`, holding `token_b_amount` for a classic token A
+    fn token_2022_swap_accounts<F>(
+        user_key: &Pubkey,
+        fees: Fees,
+        swap_curve: SwapCurve,
+        token_b_amount: u64,
+        extension: ExtensionType,
+        init_extension: F,
+    ) -> SwapAccountInfo
+    where
+        F: FnOnce(&Pubkey) -> Instruction,
+    {
+        let mut accounts = SwapAccountInfo::new(user_key, fees, swap_curve, 1_000_000, 0);
+        let (token_b_mint_key, mut token_b_mint_account) =
+            create_token_2022_mint(user_key, extension, init_extension);
+        let (token_b_key, token_b_account) = mint_token_2022(
+            &token_b_mint_key,
+            &mut token_b_mint_account,
+            user_key,
+            &accounts.authority_key,
+            token_b_amount,
+        );
+        let (fee_b_key, fixed_fee_account_b) = mint_token_2022(
+            &token_b_mint_key,
+            &mut token_b_mint_account,
+            user_key,
+            &accounts.fee_wallet_key,
+            0,
+        );
+        accounts.market_account = create_market(
+            &accounts.dex_program_id,
+            &accounts.market_key,
+            &accounts.token_a_mint_key,
+            &token_b_mint_key,
+        );
+        accounts.token_b_mint_key = token_b_mint_key;
+        accounts.token_b_mint_account = token_b_mint_account;
+        accounts.token_b_key = token_b_key;
+        accounts.token_b_account = token_b_account;
+        accounts.fee_b_key = fee_b_key;
+        accounts.fixed_fee_account_b = fixed_fee_account_b;
+        accounts
+    }
 
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-            let swap_token_b =
-                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-            let pool_mint =
-                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-            let results = accounts
-                .swap_curve
-                .calculator
-                .pool_tokens_to_trading_tokens(
-                    pool_fee_amount.try_into().unwrap(),
-                    pool_mint.supply.try_into().unwrap(),
-                    swap_token_a.amount.try_into().unwrap(),
-                    swap_token_b.amount.try_into().unwrap(),
-                    RoundDirection::Floor,
+    fn initialize_token_2022_swap(accounts: &mut SwapAccountInfo) -> ProgramResult {
+        do_process_instruction(
+            with_token_2022(
+                initialize(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &accounts.amm_id_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
+                    &accounts.pool_mint_key,
+                    &accounts.fee_a_key,
+                    &accounts.fee_b_key,
+                    &accounts.pool_token_key,
+                    &accounts.dex_program_id,
+                    &accounts.market_key,
+                    &accounts.open_orders_key,
+                    &accounts.config_key,
+                    accounts.nonce,
+                    accounts.fees.clone(),
+                    accounts.swap_curve.clone(),
                 )
-                .unwrap();
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(
-                token_a.amount,
-                TryInto::<u64>::try_into(results.token_a_amount).unwrap()
-            );
-            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-            assert_eq!(
-                token_b.amount,
-                TryInto::<u64>::try_into(results.token_b_amount).unwrap()
-            );
-        }
+                .unwrap(),
+                &[accounts.token_b_mint_key],
+            ),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut accounts.pool_mint_account,
+                &mut accounts.fixed_fee_account_a,
+                &mut accounts.fixed_fee_account_b,
+                &mut accounts.pool_token_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.market_account,
+                &mut accounts.open_orders_account,
+                &mut accounts.config_account,
+                &mut create_account_for_test(&Rent::free()),
+                &mut Account::default(),
+                &mut accounts.token_b_mint_account,
+            ],
+        )
     }
 
     #[test]
-    fn test_deposit_one_exact_in() {
+    fn test_token_2022_initialize() {
         let user_key = Pubkey::new_unique();
-        let depositor_key = Pubkey::new_unique();
-        
-        let return_fee_numerator = 25;
-        let fixed_fee_numerator = 5;
-        let fee_denominator = 10000;
         let fees = Fees {
-            return_fee_numerator,
-            fixed_fee_numerator,
-            fee_denominator
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
         };
-        
-        let token_a_amount = 1000;
-        let token_b_amount = 9000;
-        let curve_type = CurveType::ConstantProduct;
         let swap_curve = SwapCurve {
-            curve_type,
+            curve_type: CurveType::ConstantProduct,
             calculator: Box::new(ConstantProductCurve {}),
         };
+        let init_transfer_fee = |mint_key: &Pubkey| {
+            initialize_transfer_fee_config(
+                &spl_token_2022::id(),
+                mint_key,
+                None,
+                None,
+                100,
+                u64::MAX,
+            )
+            .unwrap()
+        };
 
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-
-        let deposit_a = token_a_amount / 10;
-        let deposit_b = token_b_amount / 10;
-        let pool_amount = to_u64(INITIAL_SWAP_POOL_AMOUNT / 100).unwrap();
-
-        // swap not initialized
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            assert_eq!(
-                Err(ProgramError::UninitializedAccount),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
-                )
-            );
-        }
-
-        accounts.initialize_swap().unwrap();
+        // the Token-2022 program and mint must follow the listed accounts
+        let mut accounts = token_2022_swap_accounts(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            5_000_000,
+            ExtensionType::TransferFeeConfig,
+            init_transfer_fee,
+        );
+        assert_eq!(
+            Err(AmmError::IncorrectTokenProgramId.into()),
+            accounts.initialize_swap()
+        );
+        initialize_token_2022_swap(&mut accounts).unwrap();
+        let token_swap = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(*token_swap.token_program_id(), spl_token::id());
+        assert_eq!(*token_swap.token_b_mint(), accounts.token_b_mint_key);
 
-        // wrong owner for swap account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let old_swap_account = accounts.swap_account;
-            let mut wrong_swap_account = old_swap_account.clone();
-            wrong_swap_account.owner = spl_token::id();
-            accounts.swap_account = wrong_swap_account;
-            assert_eq!(
-                Err(ProgramError::IncorrectProgramId),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
+        // tokens no one but the swap authority may move
+        let mut accounts = token_2022_swap_accounts(
+            &user_key,
+            fees,
+            swap_curve,
+            5_000_000,
+            ExtensionType::NonTransferable,
+            |mint_key| {
+                spl_token_2022::instruction::initialize_non_transferable_mint(
+                    &spl_token_2022::id(),
+                    mint_key,
                 )
-            );
-            accounts.swap_account = old_swap_account;
-        }
+                .unwrap()
+            },
+        );
+        assert_eq!(
+            Err(AmmError::UnsupportedMintExtension.into()),
+            initialize_token_2022_swap(&mut accounts)
+        );
+    }
 
-        // wrong nonce for authority_key
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &spl_token::id(),
-            );
-            accounts.authority_key = bad_authority_key;
-            assert_eq!(
-                Err(AmmError::InvalidProgramAddress.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
+    #[test]
+    fn test_token_2022_transfer_fees() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        // token B withholds 1% of every transfer
+        let mut accounts = token_2022_swap_accounts(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            5_000_000,
+            ExtensionType::TransferFeeConfig,
+            |mint_key| {
+                initialize_transfer_fee_config(
+                    &spl_token_2022::id(),
+                    mint_key,
+                    None,
+                    None,
+                    100,
+                    u64::MAX,
                 )
-            );
-            accounts.authority_key = old_authority;
-        }
+                .unwrap()
+            },
+        );
+        initialize_token_2022_swap(&mut accounts).unwrap();
+        let transfer_fee = *StateWithExtensions::<spl_token_2022::state::Mint>::unpack(
+            &accounts.token_b_mint_account.data,
+        )
+        .unwrap()
+        .get_extension::<TransferFeeConfig>()
+        .unwrap()
+        .get_epoch_fee(0);
+        let received = |amount: u64| transfer_fee.calculate_post_fee_amount(amount).unwrap();
+        let sent = |amount: u64| transfer_fee.calculate_pre_fee_amount(amount).unwrap();
+
+        let (token_a_key, mut token_a_account) = mint_token(
+            &spl_token::id(),
+            &accounts.token_a_mint_key,
+            &mut accounts.token_a_mint_account,
+            &user_key,
+            &user_key,
+            1_000_000,
+        );
+        let (token_b_key, mut token_b_account) = mint_token_2022(
+            &accounts.token_b_mint_key,
+            &mut accounts.token_b_mint_account,
+            &user_key,
+            &user_key,
+            5_000_000,
+        );
+        let (pool_key, mut pool_account) = mint_token(
+            &spl_token::id(),
+            &accounts.pool_mint_key,
+            &mut accounts.pool_mint_account,
+            &accounts.authority_key,
+            &user_key,
+            0,
+        );
+        let token_b_mint_key = accounts.token_b_mint_key;
+        let swap_instruction = |accounts: &SwapAccountInfo,
+                                source_key: &Pubkey,
+                                swap_source_key: &Pubkey,
+                                swap_destination_key: &Pubkey,
+                                destination_key: &Pubkey,
+                                fixed_fee_key: &Pubkey,
+                                amount_in: u64,
+                                minimum_amount_out: u64| {
+            with_token_2022(
+                swap(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &user_key,
+                    source_key,
+                    swap_source_key,
+                    swap_destination_key,
+                    destination_key,
+                    &accounts.pool_mint_key,
+                    fixed_fee_key,
+                    &accounts.fee_wallet_key,
+                    &accounts.config_key,
+                    SwapInstruction {
+                        amount_in,
+                        minimum_amount_out,
+                        deadline: None,
+                    },
+                )
+                .unwrap(),
+                &[token_b_mint_key],
+            )
+        };
 
-        // not enough token A / B
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &depositor_key,
-                deposit_a / 2,
-                deposit_b / 2,
-                0,
-            );
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
+        // token B out, the minimum applies to the amount received
+        let result = swap_curve
+            .swap(100_000, 1_000_000, 5_000_000, TradeDirection::AtoB, &fees)
+            .unwrap();
+        let owner_fee = result.owner_fee as u64;
+        let amount_out = result.destination_amount_swapped as u64;
+        for minimum_amount_out in [amount_out, received(amount_out)] {
+            let res = do_process_instruction(
+                swap_instruction(
+                    &accounts,
                     &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    0,
-                )
-            );
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
                     &token_b_key,
+                    &accounts.fee_a_key,
+                    100_000,
+                    minimum_amount_out,
+                ),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut token_a_account,
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
                     &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_b,
-                    0,
-                )
+                    &mut accounts.pool_mint_account,
+                    &mut accounts.fixed_fee_account_a,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.config_account,
+                    &mut Account::default(),
+                    &mut accounts.token_b_mint_account,
+                ],
             );
+            if minimum_amount_out == amount_out {
+                assert_eq!(res, Err(AmmError::ExceededSlippage.into()));
+            } else {
+                res.unwrap();
+            }
         }
+        assert_eq!(token_amount(&token_a_account), 900_000);
+        assert_eq!(
+            token_amount(&accounts.token_a_account),
+            1_000_000 + 100_000 - owner_fee
+        );
+        assert_eq!(token_amount(&accounts.fixed_fee_account_a), owner_fee);
+        assert_eq!(token_amount(&accounts.token_b_account), 5_000_000 - amount_out);
+        assert_eq!(token_amount(&token_b_account), 5_000_000 + received(amount_out));
+
+        // token B in, the curve only gets what the pool receives
+        let token_a_amount = token_amount(&accounts.token_a_account);
+        let token_b_amount = token_amount(&accounts.token_b_account);
+        let user_token_b_amount = token_amount(&token_b_account);
+        let result = swap_curve
+            .swap(
+                received(500_000).into(),
+                token_b_amount.into(),
+                token_a_amount.into(),
+                TradeDirection::BtoA,
+                &fees,
+            )
+            .unwrap();
+        let owner_fee = result.owner_fee as u64;
+        let pool_amount_in = result.source_amount_swapped as u64 - owner_fee;
+        let amount_out = result.destination_amount_swapped as u64;
+        do_process_instruction(
+            swap_instruction(
+                &accounts,
+                &token_b_key,
+                &accounts.token_b_key,
+                &accounts.token_a_key,
+                &token_a_key,
+                &accounts.fee_b_key,
+                500_000,
+                amount_out,
+            ),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut token_b_account,
+                &mut accounts.token_b_account,
+                &mut accounts.token_a_account,
+                &mut token_a_account,
+                &mut accounts.pool_mint_account,
+                &mut accounts.fixed_fee_account_b,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.config_account,
+                &mut Account::default(),
+                &mut accounts.token_b_mint_account,
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            token_amount(&accounts.token_b_account),
+            token_b_amount + pool_amount_in
+        );
+        assert_eq!(
+            token_amount(&token_b_account),
+            user_token_b_amount - sent(pool_amount_in) - owner_fee
+        );
+        assert_eq!(
+            token_amount(&accounts.fixed_fee_account_b),
+            received(owner_fee)
+        );
+        assert_eq!(token_amount(&accounts.token_a_account), token_a_amount - amount_out);
+        assert_eq!(token_amount(&token_a_account), 900_000 + amount_out);
 
-        // wrong pool token account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
+        // exact token B out, the pool pays the transfer fee on top of it
+        let token_b_amount = token_amount(&accounts.token_b_account);
+        let user_token_b_amount = token_amount(&token_b_account);
+        do_process_instruction(
+            with_token_2022(
+                swap_exact_amount_out(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &user_key,
                     &token_a_key,
-                    &mut token_a_account,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
                     &token_b_key,
-                    &mut token_b_account,
-                    deposit_a,
-                    pool_amount,
+                    &accounts.pool_mint_key,
+                    &accounts.fee_a_key,
+                    &accounts.fee_wallet_key,
+                    &accounts.config_key,
+                    SwapExactAmountOutInstruction {
+                        amount_out: 10_000,
+                        maximum_amount_in: 10_000,
+                        deadline: None,
+                    },
                 )
-            );
-        }
+                .unwrap(),
+                &[token_b_mint_key],
+            ),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut token_a_account,
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut token_b_account,
+                &mut accounts.pool_mint_account,
+                &mut accounts.fixed_fee_account_a,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.config_account,
+                &mut Account::default(),
+                &mut accounts.token_b_mint_account,
+            ],
+        )
+        .unwrap();
+        assert_eq!(token_amount(&token_b_account), user_token_b_amount + 10_000);
+        assert_eq!(
+            token_amount(&accounts.token_b_account),
+            token_b_amount - sent(10_000)
+        );
 
-        // no approval
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let user_transfer_authority_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    deposit_single_token_type_exact_amount_in(
+        // both tokens in, the maximum of token B covers its transfer fee
+        let token_a_amount = token_amount(&accounts.token_a_account);
+        let token_b_amount = token_amount(&accounts.token_b_account);
+        let user_token_b_amount = token_amount(&token_b_account);
+        let pool_supply = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data)
+            .unwrap()
+            .supply;
+        let results = swap_curve
+            .calculator
+            .pool_tokens_to_trading_tokens(
+                1_000_000,
+                pool_supply.into(),
+                token_a_amount.into(),
+                token_b_amount.into(),
+                RoundDirection::Ceiling,
+            )
+            .unwrap();
+        let deposit_b_amount = results.token_b_amount as u64;
+        for maximum_token_b_amount in [deposit_b_amount, sent(deposit_b_amount)] {
+            let res = do_process_instruction(
+                with_token_2022(
+                    deposit_all_token_types(
                         &SWAP_PROGRAM_ID,
                         &spl_token::id(),
                         &accounts.swap_key,
                         &accounts.authority_key,
-                        &user_transfer_authority_key,
+                        &user_key,
                         &token_a_key,
+                        &token_b_key,
                         &accounts.token_a_key,
                         &accounts.token_b_key,
                         &accounts.pool_mint_key,
                         &pool_key,
-                        DepositSingleTokenTypeExactAmountIn {
-                            source_token_amount: deposit_a,
-                            minimum_pool_token_amount: pool_amount,
+                        DepositInstruction {
+                            pool_token_amount: 1_000_000,
+                            maximum_token_a_amount: u64::MAX,
+                            maximum_token_b_amount,
+                            deadline: None,
                         },
                     )
                     .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut token_a_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut Account::default(),
-                    ],
-                )
+                    &[token_b_mint_key],
+                ),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut token_a_account,
+                    &mut token_b_account,
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    &mut accounts.pool_mint_account,
+                    &mut pool_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.token_b_mint_account,
+                ],
             );
+            if maximum_token_b_amount == deposit_b_amount {
+                assert_eq!(res, Err(AmmError::ExceededSlippage.into()));
+            } else {
+                res.unwrap();
+            }
         }
+        assert_eq!(token_amount(&pool_account), 1_000_000);
+        assert_eq!(
+            token_amount(&accounts.token_b_account),
+            token_b_amount + deposit_b_amount
+        );
+        assert_eq!(
+            token_amount(&token_b_account),
+            user_token_b_amount - sent(deposit_b_amount)
+        );
 
-        // wrong token program id
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let wrong_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(AmmError::IncorrectTokenProgramId.into()),
-                do_process_instruction(
-                    deposit_single_token_type_exact_amount_in(
+        // both tokens out, the minimum of token B applies after its fee
+        let token_a_amount = token_amount(&accounts.token_a_account);
+        let token_b_amount = token_amount(&accounts.token_b_account);
+        let user_token_b_amount = token_amount(&token_b_account);
+        let pool_supply = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data)
+            .unwrap()
+            .supply;
+        let results = swap_curve
+            .calculator
+            .pool_tokens_to_trading_tokens(
+                500_000,
+                pool_supply.into(),
+                token_a_amount.into(),
+                token_b_amount.into(),
+                RoundDirection::Floor,
+            )
+            .unwrap();
+        let withdraw_b_amount = results.token_b_amount as u64;
+        for minimum_token_b_amount in [withdraw_b_amount, received(withdraw_b_amount)] {
+            let res = do_process_instruction(
+                with_token_2022(
+                    withdraw_all_token_types(
                         &SWAP_PROGRAM_ID,
-                        &wrong_key,
+                        &spl_token::id(),
                         &accounts.swap_key,
                         &accounts.authority_key,
-                        &accounts.authority_key,
-                        &token_a_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
+                        &user_key,
                         &accounts.pool_mint_key,
                         &pool_key,
-                        DepositSingleTokenTypeExactAmountIn {
-                            source_token_amount: deposit_a,
-                            minimum_pool_token_amount: pool_amount,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &accounts.config_key,
+                        &accounts.pool_fee_key,
+                        WithdrawInstruction {
+                            pool_token_amount: 500_000,
+                            minimum_token_a_amount: 0,
+                            minimum_token_b_amount,
+                            deadline: None,
                         },
                     )
                     .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut token_a_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut Account::default(),
-                    ],
-                )
-            );
-        }
-
-        // wrong swap token accounts
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-
-            let old_a_key = accounts.token_a_key;
-            let old_a_account = accounts.token_a_account;
-
-            accounts.token_a_key = token_a_key;
-            accounts.token_a_account = token_a_account.clone();
-
-            // wrong swap token a account
-            assert_eq!(
-                Err(AmmError::IncorrectSwapAccount.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
-                )
-            );
-
-            accounts.token_a_key = old_a_key;
-            accounts.token_a_account = old_a_account;
-
-            let old_b_key = accounts.token_b_key;
-            let old_b_account = accounts.token_b_account;
-
-            accounts.token_b_key = token_b_key;
-            accounts.token_b_account = token_b_account;
-
-            // wrong swap token b account
-            assert_eq!(
-                Err(AmmError::IncorrectSwapAccount.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
+                    &[token_b_mint_key],
+                ),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.pool_mint_account,
                     &mut pool_account,
-                    deposit_a,
-                    pool_amount,
-                )
-            );
-
-            accounts.token_b_key = old_b_key;
-            accounts.token_b_account = old_b_account;
-        }
-
-        // wrong mint
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let (pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
-
-            assert_eq!(
-                Err(AmmError::IncorrectPoolMint.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_a_key,
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
                     &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
-                )
+                    &mut token_b_account,
+                    &mut Account::default(),
+                    &mut accounts.config_account,
+                    &mut accounts.pool_fee_account,
+                    &mut Account::default(),
+                    &mut accounts.token_b_mint_account,
+                ],
             );
-
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
+            if minimum_token_b_amount == withdraw_b_amount {
+                assert_eq!(res, Err(AmmError::ExceededSlippage.into()));
+            } else {
+                res.unwrap();
+            }
         }
+        assert_eq!(token_amount(&pool_account), 500_000);
+        assert_eq!(
+            token_amount(&accounts.token_b_account),
+            token_b_amount - withdraw_b_amount
+        );
+        assert_eq!(
+            token_amount(&token_b_account),
+            user_token_b_amount + received(withdraw_b_amount)
+        );
 
-        // slippage exceeded
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            // minimum pool amount too high
-            assert_eq!(
-                Err(AmmError::ExceededSlippage.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a / 10,
-                    pool_amount,
-                )
-            );
-            // minimum pool amount too high
-            assert_eq!(
-                Err(AmmError::ExceededSlippage.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
+        // token B in alone, the pool tokens are worth what the pool receives
+        let token_a_amount = token_amount(&accounts.token_a_account);
+        let token_b_amount = token_amount(&accounts.token_b_account);
+        let pool_supply = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data)
+            .unwrap()
+            .supply;
+        let pool_token_amount = swap_curve
+            .deposit_single_token_type(
+                received(100_000).into(),
+                token_a_amount.into(),
+                token_b_amount.into(),
+                pool_supply.into(),
+                TradeDirection::BtoA,
+                &fees,
+            )
+            .unwrap() as u64;
+        do_process_instruction(
+            with_token_2022(
+                deposit_single_token_type_exact_amount_in(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &user_key,
                     &token_b_key,
-                    &mut token_b_account,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
+                    &accounts.pool_mint_key,
                     &pool_key,
-                    &mut pool_account,
-                    deposit_b / 10,
-                    pool_amount,
+                    DepositSingleTokenTypeExactAmountIn {
+                        source_token_amount: 100_000,
+                        minimum_pool_token_amount: pool_token_amount,
+                        deadline: None,
+                    },
                 )
-            );
-        }
+                .unwrap(),
+                &[token_b_mint_key],
+            ),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut token_b_account,
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut accounts.pool_mint_account,
+                &mut pool_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.token_b_mint_account,
+            ],
+        )
+        .unwrap();
+        assert_eq!(token_amount(&pool_account), 500_000 + pool_token_amount);
+        assert_eq!(
+            token_amount(&accounts.token_b_account),
+            token_b_amount + received(100_000)
+        );
 
-        // invalid input: can't use swap pool tokens as source
-        {
-            let (
-                _token_a_key,
-                _token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let swap_token_a_key = accounts.token_a_key;
-            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
-            let swap_token_b_key = accounts.token_b_key;
-            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
-            let authority_key = accounts.authority_key;
-            assert_eq!(
-                Err(AmmError::InvalidInput.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &authority_key,
-                    &swap_token_a_key,
-                    &mut swap_token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
-                )
-            );
-            assert_eq!(
-                Err(AmmError::InvalidInput.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &authority_key,
-                    &swap_token_b_key,
-                    &mut swap_token_b_account,
+        // exact token B out alone, the pool pays the transfer fee on top of it
+        let token_b_amount = token_amount(&accounts.token_b_account);
+        let user_token_b_amount = token_amount(&token_b_account);
+        do_process_instruction(
+            with_token_2022(
+                withdraw_single_token_type_exact_amount_out(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &user_key,
+                    &accounts.pool_mint_key,
                     &pool_key,
-                    &mut pool_account,
-                    deposit_b,
-                    pool_amount,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
+                    &token_b_key,
+                    &accounts.fee_a_key,
+                    &accounts.fee_b_key,
+                    &accounts.config_key,
+                    &accounts.pool_fee_key,
+                    WithdrawSingleTokenTypeExactAmountOut {
+                        destination_token_amount: 10_000,
+                        maximum_pool_token_amount: u64::MAX,
+                        deadline: None,
+                    },
                 )
-            );
-        }
+                .unwrap(),
+                &[token_b_mint_key],
+            ),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut accounts.pool_mint_account,
+                &mut pool_account,
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut token_b_account,
+                &mut accounts.fixed_fee_account_a,
+                &mut accounts.fixed_fee_account_b,
+                &mut Account::default(),
+                &mut accounts.config_account,
+                &mut accounts.pool_fee_account,
+                &mut Account::default(),
+                &mut accounts.token_b_mint_account,
+            ],
+        )
+        .unwrap();
+        assert_eq!(token_amount(&token_b_account), user_token_b_amount + 10_000);
+        assert_eq!(
+            token_amount(&accounts.token_b_account),
+            token_b_amount - sent(10_000)
+        );
 
-        // correctly deposit
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            accounts
-                .deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
+        // without the Token-2022 accounts the pool cannot move token B
+        assert_eq!(
+            Err(AmmError::IncorrectTokenProgramId.into()),
+            accounts.swap(
+                &user_key,
+                &token_a_key,
+                &mut token_a_account,
+                &accounts.token_a_key.clone(),
+                &accounts.token_b_key.clone(),
+                &token_b_key,
+                &mut token_b_account,
+                1_000,
+                0,
+            )
+        );
+    }
+
+    #[test]
+    fn test_flash_loan() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, 1_000_000, 5_000_000);
+        accounts.initialize_swap().unwrap();
+        let (token_a_key, mut token_a_account, _, _, _, _) =
+            accounts.setup_token_accounts(&user_key, &user_key, 1_000_000, 0, 0);
+
+        let amount = 100_000;
+        let return_fee = 250;
+        let fixed_fee = 50;
+        let flash_loan_with = |accounts: &mut SwapAccountInfo,
+                               token_a_account: &mut Account,
+                               fixed_fee_key: &Pubkey,
+                               repaid_amount: u64| {
+            let mut fixed_fee_account = accounts.fixed_fee_account_a.clone();
+            let result = do_process_instruction(
+                flash_loan(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
                     &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
+                    fixed_fee_key,
+                    &accounts.config_key,
+                    &accounts.open_orders_key,
+                    &FLASH_LOAN_RECEIVER_ID,
+                    &[AccountMeta::new_readonly(user_key, true)],
+                    FlashLoanInstruction {
+                        amount,
+                        deadline: None,
+                        data: repaid_amount.to_le_bytes().to_vec(),
+                    },
                 )
-                .unwrap();
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    token_a_account,
+                    &mut fixed_fee_account,
+                    &mut accounts.config_account,
+                    &mut accounts.open_orders_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            );
+            if result.is_ok() {
+                accounts.fixed_fee_account_a = fixed_fee_account;
+            }
+            result
+        };
 
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
+        // the fee must come back with the loan
+        let fee_a_key = accounts.fee_a_key;
+        assert_eq!(
+            Err(AmmError::FlashLoanNotRepaid.into()),
+            flash_loan_with(
+                &mut accounts,
+                &mut token_a_account,
+                &fee_a_key,
+                amount + return_fee + fixed_fee - 1
+            )
+        );
+        let fee_b_key = accounts.fee_b_key;
+        assert_eq!(
+            Err(AmmError::IncorrectFeeAccount.into()),
+            flash_loan_with(
+                &mut accounts,
+                &mut token_a_account,
+                &fee_b_key,
+                amount + return_fee + fixed_fee
+            )
+        );
 
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, 0);
+        // the return fee stays in the pool, the fixed fee goes to the fixed
+        // fee account
+        take_events();
+        flash_loan_with(
+            &mut accounts,
+            &mut token_a_account,
+            &fee_a_key,
+            amount + return_fee + fixed_fee,
+        )
+        .unwrap();
+        assert_eq!(
+            take_events(),
+            vec![AmmEvent::FlashLoan(FlashLoanEvent {
+                pool: accounts.swap_key,
+                receiver: FLASH_LOAN_RECEIVER_ID,
+                swap_token: accounts.token_a_key,
+                amount,
+                repaid_amount: amount + return_fee + fixed_fee,
+                fixed_fee,
+            })]
+        );
+        assert_eq!(token_amount(&accounts.token_a_account), 1_000_000 + return_fee);
+        assert_eq!(token_amount(&accounts.fixed_fee_account_a), fixed_fee);
+        assert_eq!(
+            token_amount(&token_a_account),
+            1_000_000 - return_fee - fixed_fee
+        );
+        assert!(!SwapVersion::unpack(&accounts.swap_account.data)
+            .unwrap()
+            .locked());
+
+        // nothing moves the tokens of a locked pool
+        let mut swap_version = SwapVersion::unpack_version(&accounts.swap_account.data).unwrap();
+        swap_version.set_locked(true).unwrap();
+        SwapVersion::pack(swap_version, &mut accounts.swap_account.data).unwrap();
+        assert_eq!(
+            Err(AmmError::PoolLocked.into()),
+            flash_loan_with(
+                &mut accounts,
+                &mut token_a_account,
+                &fee_a_key,
+                amount + return_fee + fixed_fee
+            )
+        );
+        let (swap_token_a_key, swap_token_b_key) = (accounts.token_a_key, accounts.token_b_key);
+        let (_, _, token_b_key, mut token_b_account, _, _) =
+            accounts.setup_token_accounts(&user_key, &user_key, 0, 0, 0);
+        assert_eq!(
+            Err(AmmError::PoolLocked.into()),
+            accounts.swap(
+                &user_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                1_000,
+                0,
+            )
+        );
+    }
 
-            accounts
-                .deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
+    #[test]
+    fn test_deadline() {
+        test_syscall_stubs();
+        let now = 1_600_000_000;
+        let slot = 100_000;
+        set_unix_timestamp(now);
+        set_slot(slot);
+        let process = |instruction: AmmInstruction| {
+            Processor::process(&SWAP_PROGRAM_ID, &[], &instruction.pack())
+        };
+        let instructions_with: [fn(Option<Deadline>) -> AmmInstruction; 8] = [
+            |deadline| {
+                AmmInstruction::Swap(SwapInstruction {
+                    amount_in: 1_000,
+                    minimum_amount_out: 0,
+                    deadline,
+                })
+            },
+            |deadline| {
+                AmmInstruction::DepositAllTokenTypes(DepositInstruction {
+                    pool_token_amount: 1_000,
+                    maximum_token_a_amount: u64::MAX,
+                    maximum_token_b_amount: u64::MAX,
+                    deadline,
+                })
+            },
+            |deadline| {
+                AmmInstruction::WithdrawAllTokenTypes(WithdrawInstruction {
+                    pool_token_amount: 1_000,
+                    minimum_token_a_amount: 0,
+                    minimum_token_b_amount: 0,
+                    deadline,
+                })
+            },
+            |deadline| {
+                AmmInstruction::FlashLoan(FlashLoanInstruction {
+                    amount: 1_000,
+                    deadline,
+                    data: vec![],
+                })
+            },
+            |deadline| {
+                AmmInstruction::FlashSwap(FlashSwapInstruction {
+                    amount_out: 1_000,
+                    deadline,
+                    data: vec![],
+                })
+            },
+            |deadline| {
+                AmmInstruction::OpenPosition(OpenPositionInstruction {
+                    tick_lower: -10,
+                    tick_upper: 10,
+                    liquidity: 1_000,
+                    maximum_token_a_amount: u64::MAX,
+                    maximum_token_b_amount: u64::MAX,
+                    deadline,
+                })
+            },
+            |deadline| AmmInstruction::CollectFees(CollectFeesInstruction { deadline }),
+            |deadline| {
+                AmmInstruction::ClosePosition(ClosePositionInstruction {
+                    minimum_token_a_amount: 0,
+                    minimum_token_b_amount: 0,
+                    deadline,
+                })
+            },
+        ];
+
+        // expired instructions fail before their accounts are read
+        for instruction_with in instructions_with.iter() {
+            for deadline in [
+                Deadline::UnixTimestamp(now - 1),
+                Deadline::Slot(slot - 1),
+            ]
+            .iter()
+            {
+                assert_eq!(
+                    Err(AmmError::Expired.into()),
+                    process(instruction_with(Some(*deadline)))
+                );
+            }
+            for deadline in [
+                None,
+                Some(Deadline::UnixTimestamp(now)),
+                Some(Deadline::UnixTimestamp(now + 1)),
+                Some(Deadline::Slot(slot)),
+                Some(Deadline::Slot(slot + 1)),
+            ]
+            .iter()
+            {
+                assert_eq!(
+                    Err(ProgramError::NotEnoughAccountKeys),
+                    process(instruction_with(*deadline))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_flash_swap() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            1_000_000,
+            5_000_000,
+        );
+        accounts.initialize_swap().unwrap();
+        let (token_a_key, mut token_a_account, token_b_key, mut token_b_account, _, _) =
+            accounts.setup_token_accounts(&user_key, &user_key, 1_000_000, 0, 0);
+
+        let amount_out = 100_000;
+        let flash_swap_with = |accounts: &mut SwapAccountInfo,
+                               token_a_account: &mut Account,
+                               token_b_account: &mut Account,
+                               paid_amount: u64| {
+            let mut fixed_fee_account = accounts.fixed_fee_account_a.clone();
+            let result = do_process_instruction(
+                flash_swap(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
                     &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_b,
-                    pool_amount,
+                    &accounts.fee_a_key,
+                    &accounts.config_key,
+                    &accounts.open_orders_key,
+                    &FLASH_LOAN_RECEIVER_ID,
+                    &[
+                        AccountMeta::new_readonly(user_key, true),
+                        AccountMeta::new(token_a_key, false),
+                    ],
+                    FlashSwapInstruction {
+                        amount_out,
+                        deadline: None,
+                        data: paid_amount.to_le_bytes().to_vec(),
+                    },
                 )
-                .unwrap();
-            let swap_token_b =
-                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    token_b_account,
+                    &mut fixed_fee_account,
+                    &mut accounts.config_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    token_a_account,
+                ],
+            );
+            if result.is_ok() {
+                accounts.fixed_fee_account_a = fixed_fee_account;
+            }
+            result
+        };
 
-            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-            assert_eq!(token_b.amount, 0);
+        // about 5 B per A, fees not included
+        assert_eq!(
+            Err(AmmError::FlashSwapNotPaid.into()),
+            flash_swap_with(&mut accounts, &mut token_a_account, &mut token_b_account, 20_000)
+        );
 
-            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
-            let swap_pool_account =
-                spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
-            let pool_mint =
-                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-            assert_eq!(
-                pool_mint.supply,
-                pool_account.amount + swap_pool_account.amount
-            );
-        }
+        let paid_amount = 25_000;
+        let result = swap_curve
+            .swap(
+                paid_amount,
+                1_000_000,
+                5_000_000,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        assert!(result.destination_amount_swapped > u128::from(amount_out));
+        take_events();
+        flash_swap_with(
+            &mut accounts,
+            &mut token_a_account,
+            &mut token_b_account,
+            paid_amount as u64,
+        )
+        .unwrap();
+        let owner_fee = result.owner_fee as u64;
+        assert!(owner_fee > 0);
+        assert_eq!(token_amount(&token_b_account), amount_out);
+        assert_eq!(token_amount(&token_a_account), 1_000_000 - paid_amount as u64);
+        assert_eq!(
+            token_amount(&accounts.token_a_account),
+            1_000_000 + paid_amount as u64 - owner_fee
+        );
+        assert_eq!(token_amount(&accounts.token_b_account), 5_000_000 - amount_out);
+        assert_eq!(token_amount(&accounts.fixed_fee_account_a), owner_fee);
+        let token_swap = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert!(!token_swap.locked());
+        assert_eq!(token_swap.stats().swap_count, 1);
+        assert_eq!(token_swap.stats().volume_a_to_b, paid_amount);
+        assert_eq!(
+            take_events(),
+            vec![AmmEvent::Swap(SwapEvent {
+                pool: accounts.swap_key,
+                user: user_key,
+                trade_direction: TradeDirection::AtoB,
+                amount_in: paid_amount as u64,
+                amount_out,
+                owner_fee,
+                reserve_a: token_amount(&accounts.token_a_account),
+                reserve_b: token_amount(&accounts.token_b_account),
+            })]
+        );
     }
 
     #[test]
-    fn test_withdraw_one_exact_out() {
+    fn test_pool_status() {
         let user_key = Pubkey::new_unique();
-
-        let return_fee_numerator = 25;
-        let fixed_fee_numerator = 5;
-        let fee_denominator = 10000;
         let fees = Fees {
-            return_fee_numerator,
-            fixed_fee_numerator,
-            fee_denominator
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
         };
-
-        let token_a_amount = 100_000;
-        let token_b_amount = 200_000;
-        let curve_type = CurveType::ConstantProduct;
         let swap_curve = SwapCurve {
-            curve_type,
+            curve_type: CurveType::ConstantProduct,
             calculator: Box::new(ConstantProductCurve {}),
         };
-
-        let withdrawer_key = Pubkey::new_unique();
-        let initial_a = token_a_amount / 10;
-        let initial_b = token_b_amount / 10;
-        let initial_pool = swap_curve.calculator.new_pool_supply() / 10;
-        let maximum_pool_token_amount = to_u64(initial_pool / 4).unwrap();
-        let destination_a_amount = initial_a / 40;
-        let destination_b_amount = initial_b / 40;
-
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 5_000_000;
         let mut accounts =
             SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-
-        // swap not initialized
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            assert_eq!(
-                Err(ProgramError::UninitializedAccount),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-        }
-
         accounts.initialize_swap().unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_state.status(), PoolStatus::Active);
 
-        // wrong owner for swap account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            let old_swap_account = accounts.swap_account;
-            let mut wrong_swap_account = old_swap_account.clone();
-            wrong_swap_account.owner = spl_token::id();
-            accounts.swap_account = wrong_swap_account;
-            assert_eq!(
-                Err(ProgramError::IncorrectProgramId),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-            accounts.swap_account = old_swap_account;
-        }
-
-        // wrong nonce for authority_key
-        {
-            let (
-                _token_a_key,
-                _token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _nonce) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &spl_token::id(),
-            );
-            accounts.authority_key = bad_authority_key;
-            assert_eq!(
-                Err(AmmError::InvalidProgramAddress.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-            accounts.authority_key = old_authority;
-        }
+        let admin_key = accounts.admin_key;
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &user_key, 10_000, 10_000, 100_000_000);
 
-        // not enough pool tokens
-        {
-            let (
-                _token_a_key,
-                _token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount / 1000,
-            );
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-        }
+        // signer is not the admin
+        assert_eq!(
+            Err(AmmError::InvalidAdmin.into()),
+            accounts.set_pool_status(&user_key, PoolStatus::Halted)
+        );
 
-        // wrong pool token account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(
+        // withdraw-only mode
+        take_events();
+        accounts
+            .set_pool_status(&admin_key, PoolStatus::WithdrawOnly)
+            .unwrap();
+        assert_eq!(
+            take_events(),
+            vec![AmmEvent::SetPoolStatus(PoolStatusEvent {
+                pool: accounts.swap_key,
+                admin: admin_key,
+                status: PoolStatus::WithdrawOnly,
+            })]
+        );
+        assert_eq!(
+            Err(AmmError::PoolPaused.into()),
+            accounts.swap(
                 &user_key,
-                &withdrawer_key,
-                maximum_pool_token_amount,
-                initial_b,
-                maximum_pool_token_amount,
-            );
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-        }
-
-        // wrong pool fee account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                wrong_pool_key,
-                wrong_pool_account,
-            ) = accounts.setup_token_accounts(
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                1_000,
+                0,
+            )
+        );
+        assert_eq!(
+            Err(AmmError::PoolPaused.into()),
+            accounts.deposit_all_token_types(
                 &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount,
-            );
-            let (
-                _token_a_key,
-                _token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                1_000,
+                10_000,
+                10_000,
+            )
+        );
+        assert_eq!(
+            Err(AmmError::PoolPaused.into()),
+            accounts.deposit_single_token_type_exact_amount_in(
                 &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount,
-            );
-            let old_fixed_fee_account = accounts.fixed_fee_account_a;
-            let old_pool_fee_key = accounts.fee_a_key;
-            accounts.fixed_fee_account_a = wrong_pool_account;
-            accounts.fee_a_key = wrong_pool_key;
-            assert_eq!(
-                Err(AmmError::IncorrectFeeAccount.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-            accounts.fixed_fee_account_a = old_fixed_fee_account;
-            accounts.fee_a_key = old_pool_fee_key;
-        }
-
-        // no approval
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
+                &token_a_key,
+                &mut token_a_account,
+                &pool_key,
+                &mut pool_account,
+                1_000,
+                0,
+            )
+        );
+        accounts
+            .withdraw_all_token_types(
                 &user_key,
-                &withdrawer_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                10_000_000,
                 0,
                 0,
-                maximum_pool_token_amount,
-            );
-            let user_transfer_authority_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    withdraw_single_token_type_exact_amount_out(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &user_transfer_authority_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        WithdrawSingleTokenTypeExactAmountOut {
-                            destination_token_amount: destination_a_amount,
-                            maximum_pool_token_amount,
-                        }
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut accounts.fixed_fee_account_a,
-                        &mut accounts.fixed_fee_account_b,
-                        &mut Account::default(),
-                    ],
-                )
-            );
-        }
+            )
+            .unwrap();
 
-        // wrong token program id
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
+        // halted
+        accounts
+            .set_pool_status(&admin_key, PoolStatus::Halted)
+            .unwrap();
+        assert_eq!(
+            Err(AmmError::PoolPaused.into()),
+            accounts.withdraw_all_token_types(
                 &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount,
-            );
-            let wrong_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(AmmError::IncorrectTokenProgramId.into()),
-                do_process_instruction(
-                    withdraw_single_token_type_exact_amount_out(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_key,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.authority_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        WithdrawSingleTokenTypeExactAmountOut {
-                            destination_token_amount: destination_a_amount,
-                            maximum_pool_token_amount,
-                        }
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut accounts.fixed_fee_account_a,
-                        &mut accounts.fixed_fee_account_b,
-                        &mut Account::default(),
-                    ],
-                )
-            );
-        }
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                10_000_000,
+                0,
+                0,
+            )
+        );
+        assert_eq!(
+            Err(AmmError::PoolPaused.into()),
+            accounts.withdraw_single_token_type_exact_amount_out(
+                &user_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                100,
+                10_000,
+            )
+        );
 
-        // wrong swap token accounts
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
+        // back to active
+        accounts
+            .set_pool_status(&admin_key, PoolStatus::Active)
+            .unwrap();
+        accounts
+            .swap(
                 &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                1_000,
+                0,
+            )
+            .unwrap();
+    }
 
-            let old_a_key = accounts.token_a_key;
-            let old_a_account = accounts.token_a_account;
+    #[test]
+    fn test_pool_status_upgrades_swap_v1() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let mut accounts = SwapAccountInfo::new(&user_key, fees, swap_curve, 1_000_000, 5_000_000);
+        accounts.initialize_swap().unwrap();
+        let admin_key = accounts.admin_key;
 
-            accounts.token_a_key = token_a_key;
-            accounts.token_a_account = token_a_account.clone();
+        // rewrite the swap as created before SwapV2
+        let swap = match SwapVersion::unpack_version(&accounts.swap_account.data).unwrap() {
+            SwapVersion::SwapV3(swap_info) => swap_info.swap,
+            _ => panic!("expected a SwapV3"),
+        };
+        let swap_v1 = SwapV1 {
+            is_initialized: swap.is_initialized,
+            nonce: swap.nonce,
+            amm_id: swap.amm_id,
+            dex_program_id: swap.dex_program_id,
+            market_id: swap.market_id,
+            token_program_id: swap.token_program_id,
+            token_a: swap.token_a,
+            token_b: swap.token_b,
+            pool_mint: swap.pool_mint,
+            token_a_mint: swap.token_a_mint,
+            token_b_mint: swap.token_b_mint,
+            fixed_fee_account_a: swap.fixed_fee_account_a,
+            fixed_fee_account_b: swap.fixed_fee_account_b,
+            fees: swap.fees,
+            swap_curve: swap.swap_curve,
+        };
+        accounts.swap_account.data.iter_mut().for_each(|byte| *byte = 0);
+        SwapVersion::pack(SwapVersion::SwapV1(swap_v1), &mut accounts.swap_account.data).unwrap();
 
-            // wrong swap token a account
-            assert_eq!(
-                Err(AmmError::IncorrectSwapAccount.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
-                )
-            );
+        accounts
+            .set_pool_status(&admin_key, PoolStatus::Halted)
+            .unwrap();
+        match SwapVersion::unpack_version(&accounts.swap_account.data).unwrap() {
+            SwapVersion::SwapV3(swap_info) => {
+                assert_eq!(swap_info.swap.status, PoolStatus::Halted);
+                assert_eq!(swap_info.swap.pool_mint, accounts.pool_mint_key);
+                assert_eq!(swap_info.swap.token_a, accounts.token_a_key);
+                assert_eq!(swap_info.swap.fees, accounts.fees);
+            }
+            _ => panic!("expected the swap to be upgraded to SwapV3"),
+        }
 
-            accounts.token_a_key = old_a_key;
-            accounts.token_a_account = old_a_account;
+        accounts
+            .set_pool_status(&admin_key, PoolStatus::Active)
+            .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_state.status(), PoolStatus::Active);
+    }
 
-            let old_b_key = accounts.token_b_key;
-            let old_b_account = accounts.token_b_account;
+    #[test]
+    fn test_swap_exact_amount_out() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 5_000_000;
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
 
-            accounts.token_b_key = token_b_key;
-            accounts.token_b_account = token_b_account.clone();
+        let initial_a = token_a_amount / 5;
+        let initial_b = token_b_amount / 5;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &user_key, initial_a, initial_b, 0);
 
-            // wrong swap token b account
+        // pool cannot pay out its whole balance
+        {
             assert_eq!(
-                Err(AmmError::IncorrectSwapAccount.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+                Err(AmmError::ZeroTradingTokens.into()),
+                accounts.swap_exact_amount_out(
+                    &user_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount,
+                    token_b_amount,
+                    initial_a,
                 )
             );
-
-            accounts.token_b_key = old_b_key;
-            accounts.token_b_account = old_b_account;
         }
 
-        // wrong mint
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
-            let (pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
+        // A to B, then B to A
+        for (source_key, swap_source_key, swap_destination_key, destination_key, amount_out) in &[
+            (token_a_key, swap_token_a_key, swap_token_b_key, token_b_key, 100_000u64),
+            (token_b_key, swap_token_b_key, swap_token_a_key, token_a_key, 7_777u64),
+        ] {
+            let (mut source_account, mut destination_account) = if *source_key == token_a_key {
+                (token_a_account.clone(), token_b_account.clone())
+            } else {
+                (token_b_account.clone(), token_a_account.clone())
+            };
+            let source_before =
+                spl_token::state::Account::unpack(&source_account.data).unwrap().amount;
+            let destination_before =
+                spl_token::state::Account::unpack(&destination_account.data).unwrap().amount;
+            let swap_source_amount = spl_token::state::Account::unpack(
+                &accounts.get_token_account(swap_source_key).data,
+            )
+            .unwrap()
+            .amount;
+            let swap_destination_amount = spl_token::state::Account::unpack(
+                &accounts.get_token_account(swap_destination_key).data,
+            )
+            .unwrap()
+            .amount;
+            let trade_direction = if *swap_source_key == swap_token_a_key {
+                TradeDirection::AtoB
+            } else {
+                TradeDirection::BtoA
+            };
+            let expected = swap_exact_out(
+                &accounts.swap_curve,
+                u128::from(*amount_out),
+                u128::from(swap_source_amount),
+                u128::from(swap_destination_amount),
+                trade_direction,
+                &accounts.fees,
+            )
+            .unwrap();
+            let amount_in = to_u64(expected.source_amount_swapped).unwrap();
+            let owner_fee = to_u64(expected.owner_fee).unwrap();
 
+            // maximum amount in too low
             assert_eq!(
-                Err(AmmError::IncorrectPoolMint.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
+                Err(AmmError::ExceededSlippage.into()),
+                accounts.swap_exact_amount_out(
+                    &user_key,
+                    source_key,
+                    &mut source_account,
+                    swap_source_key,
+                    swap_destination_key,
+                    destination_key,
+                    &mut destination_account,
+                    *amount_out,
+                    amount_in - 1,
                 )
             );
 
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
+            accounts
+                .swap_exact_amount_out(
+                    &user_key,
+                    source_key,
+                    &mut source_account,
+                    swap_source_key,
+                    swap_destination_key,
+                    destination_key,
+                    &mut destination_account,
+                    *amount_out,
+                    amount_in,
+                )
+                .unwrap();
+
+            let source_after =
+                spl_token::state::Account::unpack(&source_account.data).unwrap().amount;
+            let destination_after =
+                spl_token::state::Account::unpack(&destination_account.data).unwrap().amount;
+            assert_eq!(destination_after - destination_before, *amount_out);
+            assert_eq!(source_before - source_after, amount_in);
+            let swap_source = spl_token::state::Account::unpack(
+                &accounts.get_token_account(swap_source_key).data,
+            )
+            .unwrap();
+            assert_eq!(swap_source.amount, swap_source_amount + amount_in - owner_fee);
+            let swap_destination = spl_token::state::Account::unpack(
+                &accounts.get_token_account(swap_destination_key).data,
+            )
+            .unwrap();
+            assert_eq!(swap_destination.amount, swap_destination_amount - amount_out);
+
+            if *source_key == token_a_key {
+                token_a_account = source_account;
+                token_b_account = destination_account;
+            } else {
+                token_b_account = source_account;
+                token_a_account = destination_account;
+            }
         }
+    }
 
-        // slippage exceeded
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
+    fn create_open_orders(
+        dex_program_id: &Pubkey,
+        market_key: &Pubkey,
+        owner_key: &Pubkey,
+        native_coin_total: u64,
+        native_pc_total: u64,
+    ) -> Account {
+        let mut open_orders_account = Account::new(0, 3228, dex_program_id);
+        let data = &mut open_orders_account.data;
+        data[..5].copy_from_slice(b"serum");
+        data[5..13].copy_from_slice(&5u64.to_le_bytes());
+        data[13..45].copy_from_slice(market_key.as_ref());
+        data[45..77].copy_from_slice(owner_key.as_ref());
+        data[85..93].copy_from_slice(&native_coin_total.to_le_bytes());
+        data[101..109].copy_from_slice(&native_pc_total.to_le_bytes());
+        open_orders_account
+    }
+
+    fn serum_market_accounts(accounts: &SwapAccountInfo) -> SerumMarketAccounts {
+        SerumMarketAccounts {
+            dex_program_id: accounts.dex_program_id,
+            market: accounts.market_key,
+            open_orders: accounts.open_orders_key,
+            request_queue: Pubkey::new_unique(),
+            event_queue: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            vault_signer: Pubkey::new_unique(),
+        }
+    }
+
+    #[test]
+    fn test_initialize_market() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let token_a_amount = 1_000;
+        let token_b_amount = 5_000;
+        let new_accounts = || {
+            SwapAccountInfo::new(
                 &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount,
-            );
+                fees.clone(),
+                swap_curve.clone(),
+                token_a_amount,
+                token_b_amount,
+            )
+        };
 
-            // maximum pool token amount too low
+        // market not owned by the dex program
+        {
+            let mut accounts = new_accounts();
+            accounts.market_account.owner = Pubkey::new_unique();
             assert_eq!(
-                Err(AmmError::ExceededSlippage.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount / 1000,
-                )
+                Err(AmmError::IncorrectMarketOwner.into()),
+                accounts.initialize_swap()
+            );
+        }
+
+        // market trading other tokens
+        {
+            let mut accounts = new_accounts();
+            accounts.market_account = create_market(
+                &accounts.dex_program_id,
+                &accounts.market_key,
+                &accounts.token_b_mint_key,
+                &accounts.token_a_mint_key,
             );
             assert_eq!(
-                Err(AmmError::ExceededSlippage.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount / 1000,
-                )
+                Err(AmmError::IncorrectMarketMint.into()),
+                accounts.initialize_swap()
             );
         }
 
-        // invalid input: can't use swap pool tokens as destination
+        // open orders not owned by the dex program
         {
-            let (
-                _token_a_key,
-                _token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount,
+            let mut accounts = new_accounts();
+            accounts.open_orders_key = Pubkey::new_unique();
+            accounts.open_orders_account = create_open_orders(
+                &accounts.dex_program_id,
+                &accounts.market_key,
+                &accounts.authority_key,
+                0,
+                0,
             );
-            let swap_token_a_key = accounts.token_a_key;
-            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+            accounts.open_orders_account.owner = Pubkey::new_unique();
             assert_eq!(
-                Err(AmmError::InvalidInput.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &swap_token_a_key,
-                    &mut swap_token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
-                )
+                Err(AmmError::IncorrectOpenOrdersAccount.into()),
+                accounts.initialize_swap()
+            );
+        }
+
+        // open orders on another market
+        {
+            let mut accounts = new_accounts();
+            accounts.open_orders_key = Pubkey::new_unique();
+            accounts.open_orders_account = create_open_orders(
+                &accounts.dex_program_id,
+                &Pubkey::new_unique(),
+                &accounts.authority_key,
+                0,
+                0,
             );
-            let swap_token_b_key = accounts.token_b_key;
-            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
             assert_eq!(
-                Err(AmmError::InvalidInput.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &swap_token_b_key,
-                    &mut swap_token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount,
-                )
+                Err(AmmError::IncorrectOpenOrdersAccount.into()),
+                accounts.initialize_swap()
             );
         }
 
-        // correct withdrawal
+        // open orders which the swap authority cannot trade with
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
+            let mut accounts = new_accounts();
+            accounts.open_orders_key = Pubkey::new_unique();
+            accounts.open_orders_account = create_open_orders(
+                &accounts.dex_program_id,
+                &accounts.market_key,
+                &Pubkey::new_unique(),
+                0,
+                0,
             );
-
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-            let swap_token_b =
-                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-            let pool_mint =
-                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-
-            let pool_token_amount = accounts
-                .swap_curve
-                .withdraw_single_token_type_exact_out(
-                    destination_a_amount.try_into().unwrap(),
-                    swap_token_a.amount.try_into().unwrap(),
-                    swap_token_b.amount.try_into().unwrap(),
-                    pool_mint.supply.try_into().unwrap(),
-                    TradeDirection::AtoB,
-                    &accounts.fees,
-                )
-                .unwrap();
-            let withdraw_fee = 0;
-            // accounts.fees.owner_withdraw_fee(pool_token_amount).unwrap();
-
-            accounts
-                .withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
-                )
-                .unwrap();
-
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-
-            assert_eq!(swap_token_a.amount, token_a_amount - destination_a_amount);
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, initial_a + destination_a_amount);
-
-            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
             assert_eq!(
-                pool_account.amount,
-                to_u64(initial_pool - pool_token_amount - withdraw_fee).unwrap()
+                Err(AmmError::IncorrectOpenOrdersOwner.into()),
+                accounts.initialize_swap()
             );
         }
 
-        // correct withdrawal from fee account
+        // open orders the dex has not initialized yet are initialized for the
+        // swap authority
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-
-            let fee_a_amount = 2;
-            let pool_fee_key = accounts.fee_a_key;
-            let mut fixed_fee_account = accounts.fixed_fee_account.clone();
-            let fee_account = spl_token::state::Account::unpack(&fixed_fee_account.data).unwrap();
-            let pool_fee_amount = fee_account.amount;
-
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-
-            let token_a_amount = swap_token_a.amount;
-            accounts
-                .withdraw_single_token_type_exact_amount_out(
-                    &user_key,
-                    &pool_fee_key,
-                    &mut fixed_fee_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    fee_a_amount,
-                    pool_fee_amount,
-                )
-                .unwrap();
-
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            let mut accounts = new_accounts();
+            accounts.open_orders_key = Pubkey::new_unique();
+            accounts.open_orders_account = Account::new(0, 3228, &accounts.dex_program_id);
+            accounts.initialize_swap().unwrap();
+            let open_orders = OpenOrders::unpack(&accounts.open_orders_account.data).unwrap();
+            assert_eq!(open_orders.owner, accounts.authority_key);
+            assert_eq!(open_orders.market, accounts.market_key);
+        }
 
-            assert_eq!(swap_token_a.amount, token_a_amount - fee_a_amount);
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, initial_a + fee_a_amount);
+        // linked to the market
+        {
+            let mut accounts = new_accounts();
+            accounts.open_orders_key = Pubkey::new_unique();
+            accounts.open_orders_account = create_open_orders(
+                &accounts.dex_program_id,
+                &accounts.market_key,
+                &accounts.authority_key,
+                0,
+                0,
+            );
+            accounts.initialize_swap().unwrap();
+            let swap_info = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(*swap_info.open_orders(), accounts.open_orders_key);
         }
     }
 
-    fn check_valid_swap_curve(
-        fees: Fees,
-        curve_type: CurveType,
-        calculator: Box<dyn CurveCalculator>,
-        token_a_amount: u64,
-        token_b_amount: u64,
-    ) {
+    #[test]
+    fn test_serum_open_orders() {
         let user_key = Pubkey::new_unique();
-        let swapper_key = Pubkey::new_unique();
-
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
         let swap_curve = SwapCurve {
-            curve_type,
-            calculator,
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
         };
-
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 5_000_000;
+        let open_orders_coin = 200_000;
+        let open_orders_pc = 1_000_000;
         let mut accounts = SwapAccountInfo::new(
             &user_key,
             fees.clone(),
@@ -5390,13 +15166,19 @@ mod tests {
             token_a_amount,
             token_b_amount,
         );
-        let initial_a = token_a_amount / 5;
-        let initial_b = token_b_amount / 5;
+        accounts.open_orders_key = Pubkey::new_unique();
+        accounts.open_orders_account = create_open_orders(
+            &accounts.dex_program_id,
+            &accounts.market_key,
+            &accounts.authority_key,
+            open_orders_coin,
+            open_orders_pc,
+        );
         accounts.initialize_swap().unwrap();
-
         let swap_token_a_key = accounts.token_a_key;
         let swap_token_b_key = accounts.token_b_key;
 
+        let amount_in = 100_000;
         let (
             token_a_key,
             mut token_a_account,
@@ -5404,369 +15186,645 @@ mod tests {
             mut token_b_account,
             _pool_key,
             _pool_account,
-        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-        // swap one way
-        let a_to_b_amount = initial_a / 10;
-        let minimum_token_b_amount = 0;
-        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-        let initial_supply = pool_mint.supply;
-        accounts
-            .swap(
-                &swapper_key,
-                &token_a_key,
-                &mut token_a_account,
-                &swap_token_a_key,
-                &swap_token_b_key,
-                &token_b_key,
-                &mut token_b_account,
-                a_to_b_amount,
-                minimum_token_b_amount,
-            )
-            .unwrap();
-
-        let results = swap_curve
-            .swap(
-                a_to_b_amount.try_into().unwrap(),
-                token_a_amount.try_into().unwrap(),
-                token_b_amount.try_into().unwrap(),
-                TradeDirection::AtoB,
-                &fees,
-            )
-            .unwrap();
-
-        let swap_token_a =
-            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-        let token_a_amount = swap_token_a.amount;
-        assert_eq!(
-            token_a_amount,
-            TryInto::<u64>::try_into(results.new_swap_source_amount).unwrap()
-        );
-        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-        assert_eq!(token_a.amount, initial_a - a_to_b_amount);
-
-        let swap_token_b =
-            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-        let token_b_amount = swap_token_b.amount;
-        assert_eq!(
-            token_b_amount,
-            TryInto::<u64>::try_into(results.new_swap_destination_amount).unwrap()
-        );
-        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-        assert_eq!(
-            token_b.amount,
-            initial_b + to_u64(results.destination_amount_swapped).unwrap()
-        );
-
-        let first_fee = swap_curve
-            .withdraw_single_token_type_exact_out(
-                results.owner_fee,
-                token_a_amount.try_into().unwrap(),
-                token_b_amount.try_into().unwrap(),
-                initial_supply.try_into().unwrap(),
-                TradeDirection::AtoB,
-                &fees,
-            )
-            .unwrap();
-        // let fee_account =
-        //     spl_token::state::Account::unpack(&accounts.fixed_fee_account.data).unwrap();
-        // assert_eq!(
-        //     fee_account.amount,
-        //     TryInto::<u64>::try_into(first_fee).unwrap()
-        // );
-
-        let first_swap_amount = results.destination_amount_swapped;
-
-        // swap the other way
-        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-        let initial_supply = pool_mint.supply;
-
-        let b_to_a_amount = initial_b / 10;
-        let minimum_a_amount = 0;
-        accounts
-            .swap(
-                &swapper_key,
-                &token_b_key,
-                &mut token_b_account,
-                &swap_token_b_key,
-                &swap_token_a_key,
-                &token_a_key,
-                &mut token_a_account,
-                b_to_a_amount,
-                minimum_a_amount,
-            )
-            .unwrap();
-
-        let results = swap_curve
-            .swap(
-                b_to_a_amount.try_into().unwrap(),
-                token_b_amount.try_into().unwrap(),
-                token_a_amount.try_into().unwrap(),
-                TradeDirection::BtoA,
-                &fees,
-            )
-            .unwrap();
-
-        let swap_token_a =
-            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-        let token_a_amount = swap_token_a.amount;
-        assert_eq!(
-            token_a_amount,
-            TryInto::<u64>::try_into(results.new_swap_destination_amount).unwrap()
-        );
-        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-        assert_eq!(
-            token_a.amount,
-            initial_a - a_to_b_amount + to_u64(results.destination_amount_swapped).unwrap()
-        );
+        ) = accounts.setup_token_accounts(&user_key, &user_key, amount_in, 0, 0);
 
-        let swap_token_b =
-            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-        let token_b_amount = swap_token_b.amount;
-        assert_eq!(
-            token_b_amount,
-            TryInto::<u64>::try_into(results.new_swap_source_amount).unwrap()
-        );
-        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-        assert_eq!(
-            token_b.amount,
-            initial_b + to_u64(first_swap_amount).unwrap()
-                - to_u64(results.source_amount_swapped).unwrap()
-        );
-
-        let second_fee = swap_curve
-            .withdraw_single_token_type_exact_out(
-                results.owner_fee,
-                token_a_amount.try_into().unwrap(),
-                token_b_amount.try_into().unwrap(),
-                initial_supply.try_into().unwrap(),
-                TradeDirection::BtoA,
-                &fees,
-            )
-            .unwrap();
-        // let fee_account =
-        //     spl_token::state::Account::unpack(&accounts.fixed_fee_account.data).unwrap();
-        // assert_eq!(fee_account.amount, to_u64(first_fee + second_fee).unwrap());
-    }
-
-    #[test]
-    fn test_valid_swap_curves_all_fees() {
-        // All fees
-        let return_fee_numerator = 1;
-        let fixed_fee_numerator = 2;
-        let fee_denominator = 10;
-        let fees = Fees {
-            return_fee_numerator,
-            fixed_fee_numerator,
-            fee_denominator,
-        };
+        // the open orders are required once the pool has them
+        {
+            let open_orders_key = accounts.open_orders_key;
+            accounts.open_orders_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(AmmError::IncorrectOpenOrdersAccount.into()),
+                accounts.swap(
+                    &user_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    amount_in,
+                    0,
+                )
+            );
+            accounts.open_orders_key = open_orders_key;
+        }
 
-        let token_a_amount = 10_000_000_000;
-        let token_b_amount = 50_000_000_000;
+        // the open orders must stay those of the market and swap authority
+        {
+            let open_orders_account = accounts.open_orders_account.clone();
+            for (market_key, owner_key, expected) in &[
+                (
+                    accounts.market_key,
+                    Pubkey::new_unique(),
+                    AmmError::IncorrectOpenOrdersOwner,
+                ),
+                (
+                    Pubkey::new_unique(),
+                    accounts.authority_key,
+                    AmmError::IncorrectOpenOrdersAccount,
+                ),
+            ] {
+                accounts.open_orders_account = create_open_orders(
+                    &accounts.dex_program_id,
+                    market_key,
+                    owner_key,
+                    open_orders_coin,
+                    open_orders_pc,
+                );
+                assert_eq!(
+                    Err((*expected).clone().into()),
+                    accounts.swap(
+                        &user_key,
+                        &token_a_key,
+                        &mut token_a_account,
+                        &swap_token_a_key,
+                        &swap_token_b_key,
+                        &token_b_key,
+                        &mut token_b_account,
+                        amount_in,
+                        0,
+                    )
+                );
+            }
+            accounts.open_orders_account = open_orders_account;
+        }
 
-        check_valid_swap_curve(
-            fees.clone(),
-            CurveType::ConstantProduct,
-            Box::new(ConstantProductCurve {}),
-            token_a_amount,
-            token_b_amount,
-        );
-        let token_b_price = 1;
-        check_valid_swap_curve(
-            fees.clone(),
-            CurveType::ConstantPrice,
-            Box::new(ConstantPriceCurve { token_b_price }),
-            token_a_amount,
-            token_b_amount,
-        );
-        let token_b_offset = 10_000_000_000;
-        check_valid_swap_curve(
-            fees,
-            CurveType::Offset,
-            Box::new(OffsetCurve { token_b_offset }),
-            token_a_amount,
-            token_b_amount,
-        );
-    }
+        // swap math includes the funds held on the market
+        {
+            accounts
+                .swap(
+                    &user_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    amount_in,
+                    0,
+                )
+                .unwrap();
+            let results = swap_curve
+                .swap(
+                    u128::from(amount_in),
+                    u128::from(token_a_amount + open_orders_coin),
+                    u128::from(token_b_amount + open_orders_pc),
+                    TradeDirection::AtoB,
+                    &fees,
+                )
+                .unwrap();
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(u128::from(token_b.amount), results.destination_amount_swapped);
+        }
 
-    #[test]
-    fn test_valid_swap_curves_trade_fee_only() {
-        let return_fee_numerator = 25;
-        let fixed_fee_numerator = 5;
-        let fee_denominator = 10000;
-        let fees = Fees {
-            return_fee_numerator,
-            fixed_fee_numerator,
-            fee_denominator
-        };
+        // only the admin trades the pool on the market
+        {
+            let market = serum_market_accounts(&accounts);
+            let wrong_admin_key = Pubkey::new_unique();
+            let mut wrong_market = serum_market_accounts(&accounts);
+            wrong_market.market = Pubkey::new_unique();
+            let mut wrong_open_orders = serum_market_accounts(&accounts);
+            wrong_open_orders.open_orders = Pubkey::new_unique();
+            for (admin_key, market, expected) in &[
+                (&wrong_admin_key, &market, AmmError::InvalidAdmin),
+                (&accounts.admin_key, &wrong_market, AmmError::IncorrectMarketAccount),
+                (
+                    &accounts.admin_key,
+                    &wrong_open_orders,
+                    AmmError::IncorrectOpenOrdersAccount,
+                ),
+            ] {
+                let mut swap_account = accounts.swap_account.clone();
+                let mut config_account = accounts.config_account.clone();
+                let mut token_a_account = accounts.token_a_account.clone();
+                let mut token_b_account = accounts.token_b_account.clone();
+                let mut open_orders_account = accounts.open_orders_account.clone();
+                assert_eq!(
+                    Err((*expected).clone().into()),
+                    do_process_instruction(
+                        place_order(
+                            &SWAP_PROGRAM_ID,
+                            &spl_token::id(),
+                            &accounts.swap_key,
+                            &accounts.authority_key,
+                            &accounts.config_key,
+                            admin_key,
+                            &accounts.token_a_key,
+                            &accounts.token_b_key,
+                            market,
+                            PlaceOrderInstruction {
+                                side: OrderSide::Ask,
+                                coin_lots: 10,
+                                spread_bps: 30,
+                                client_order_id: 1,
+                            },
+                        )
+                        .unwrap(),
+                        vec![
+                            &mut swap_account,
+                            &mut Account::default(),
+                            &mut config_account,
+                            &mut Account::default(),
+                            &mut token_a_account,
+                            &mut token_b_account,
+                            &mut Account::default(),
+                            &mut Account::default(),
+                            &mut open_orders_account,
+                            &mut Account::default(),
+                            &mut Account::default(),
+                            &mut Account::default(),
+                            &mut Account::default(),
+                            &mut Account::default(),
+                            &mut Account::default(),
+                            &mut Account::default(),
+                            &mut Account::default(),
+                        ],
+                    )
+                );
+            }
 
-        let token_a_amount = 10_000_000_000;
-        let token_b_amount = 50_000_000_000;
+            assert_eq!(
+                Err(AmmError::IncorrectMarketAccount.into()),
+                do_process_instruction(
+                    settle_funds(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &wrong_market,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.open_orders_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
 
-        check_valid_swap_curve(
-            fees.clone(),
-            CurveType::ConstantProduct,
-            Box::new(ConstantProductCurve {}),
-            token_a_amount,
-            token_b_amount,
-        );
-        let token_b_price = 10_000;
-        check_valid_swap_curve(
-            fees.clone(),
-            CurveType::ConstantPrice,
-            Box::new(ConstantPriceCurve { token_b_price }),
-            token_a_amount,
-            token_b_amount / token_b_price,
-        );
-        let token_b_offset = 1;
-        check_valid_swap_curve(
-            fees,
-            CurveType::Offset,
-            Box::new(OffsetCurve { token_b_offset }),
-            token_a_amount,
-            token_b_amount,
-        );
+            // nor settles the funds of a pool locked by a flash loan
+            let mut swap_account = accounts.swap_account.clone();
+            let mut swap_version = SwapVersion::unpack_version(&swap_account.data).unwrap();
+            swap_version.set_locked(true).unwrap();
+            SwapVersion::pack(swap_version, &mut swap_account.data).unwrap();
+            assert_eq!(
+                Err(AmmError::PoolLocked.into()),
+                do_process_instruction(
+                    settle_funds(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &market,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut swap_account,
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.open_orders_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
     }
 
     #[test]
-    fn test_valid_swap_with_fee_constraints() {
-        let owner_key = Pubkey::new_unique();
-
-        let return_fee_numerator = 25;
-        let fixed_fee_numerator = 5;
-        let fee_denominator = 10000;
+    fn test_foreign_dex_program() {
+        let user_key = Pubkey::new_unique();
         let fees = Fees {
-            return_fee_numerator,
-            fixed_fee_numerator,
-            fee_denominator
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
         };
-        
-        let token_a_amount = 1_000_000;
-        let token_b_amount = 5_000_000;
-
-
-        let curve = ConstantProductCurve {};
         let swap_curve = SwapCurve {
             curve_type: CurveType::ConstantProduct,
-            calculator: Box::new(curve),
+            calculator: Box::new(ConstantProductCurve {}),
         };
-
-        let owner_key_str = &owner_key.to_string();
-        let valid_curve_types = &[CurveType::ConstantProduct];
-        let constraints = Some(SwapConstraints {
-            owner_key: owner_key_str,
-            valid_curve_types,
-            fees: &fees,
-        });
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 5_000_000;
+        let foreign_dex_program_id = Pubkey::new_unique();
         let mut accounts = SwapAccountInfo::new(
-            &owner_key,
-            fees.clone(),
+            &user_key,
+            fees,
             swap_curve,
             token_a_amount,
             token_b_amount,
         );
+        accounts.open_orders_key = Pubkey::new_unique();
+        accounts.open_orders_account = create_open_orders(
+            &accounts.dex_program_id,
+            &accounts.market_key,
+            &accounts.authority_key,
+            0,
+            0,
+        );
 
-        // initialize swap
-        do_process_instruction_with_fee_constraints(
-            initialize(
-                &SWAP_PROGRAM_ID,
-                &spl_token::id(),
-                &accounts.swap_key,
-                &accounts.authority_key,
-                &accounts.token_a_key,
-                &accounts.token_b_key,
-                &accounts.pool_mint_key,
-                &accounts.fee_a_key,
-                &accounts.fee_b_key,
-                &accounts.pool_token_key,
-                accounts.nonce,
+        // a market and open orders of another program are consistent, but the
+        // swap authority would sign for that program
+        {
+            let mut foreign = SwapAccountInfo::new(
+                &user_key,
                 accounts.fees.clone(),
                 accounts.swap_curve.clone(),
-            )
-            .unwrap(),
-            vec![
-                &mut accounts.swap_account,
-                &mut Account::default(),
-                &mut accounts.token_a_account,
-                &mut accounts.token_b_account,
-                &mut accounts.pool_mint_account,
-                &mut accounts.fixed_fee_account_a,
-                &mut accounts.fixed_fee_account_b,
-                &mut accounts.pool_token_account,
-                &mut Account::default(),
-            ],
-            &constraints,
-        )
-        .unwrap();
+                token_a_amount,
+                token_b_amount,
+            );
+            foreign.dex_program_id = foreign_dex_program_id;
+            foreign.market_account = create_market(
+                &foreign_dex_program_id,
+                &foreign.market_key,
+                &foreign.token_a_mint_key,
+                &foreign.token_b_mint_key,
+            );
+            foreign.open_orders_key = Pubkey::new_unique();
+            foreign.open_orders_account = create_open_orders(
+                &foreign_dex_program_id,
+                &foreign.market_key,
+                &foreign.authority_key,
+                0,
+                0,
+            );
+            assert_eq!(
+                Err(AmmError::IncorrectDexProgram.into()),
+                foreign.initialize_swap()
+            );
+        }
 
-        let authority_key = accounts.authority_key;
+        // a pool saved with another dex program before it was pinned can
+        // neither settle its funds nor count the open orders of that program
+        accounts.initialize_swap().unwrap();
+        let mut swap_version = SwapVersion::unpack_version(&accounts.swap_account.data).unwrap();
+        match swap_version {
+            SwapVersion::SwapV3(ref mut swap) => swap.swap.dex_program_id = foreign_dex_program_id,
+            _ => panic!("pools are created as SwapV3"),
+        }
+        SwapVersion::pack(swap_version, &mut accounts.swap_account.data).unwrap();
+        accounts.dex_program_id = foreign_dex_program_id;
+        accounts.open_orders_account.owner = foreign_dex_program_id;
+        assert_eq!(
+            Err(AmmError::IncorrectDexProgram.into()),
+            do_process_instruction(
+                settle_funds(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
+                    &serum_market_accounts(&accounts),
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.open_orders_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        );
 
+        let amount_in = 100_000;
         let (
             token_a_key,
             mut token_a_account,
             token_b_key,
             mut token_b_account,
-            pool_key,
-            mut pool_account,
-        ) = accounts.setup_token_accounts(
-            &owner_key,
-            &authority_key,
-            token_a_amount,
-            token_b_amount,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &user_key, amount_in, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        assert_eq!(
+            Err(AmmError::IncorrectOpenOrdersAccount.into()),
+            accounts.swap(
+                &user_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                amount_in,
+                0,
+            )
+        );
+    }
+
+    fn route_swap_leg(accounts: &SwapAccountInfo, destination_key: &Pubkey) -> RouteSwapLeg {
+        RouteSwapLeg {
+            swap_pubkey: accounts.swap_key,
+            authority_pubkey: accounts.authority_key,
+            swap_source_pubkey: accounts.token_a_key,
+            swap_destination_pubkey: accounts.token_b_key,
+            destination_pubkey: *destination_key,
+            pool_mint_pubkey: accounts.pool_mint_key,
+            fixed_fee_pubkey: accounts.fee_a_key,
+            open_orders_pubkey: accounts.open_orders_key,
+        }
+    }
+
+    #[test]
+    fn test_route_swap() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Box::new(ConstantProductCurve {}),
+        };
+        let mut first = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            1_000_000,
+            5_000_000,
+        );
+        let mut second =
+            SwapAccountInfo::new(&user_key, fees.clone(), swap_curve.clone(), 5_000_000, 2_000_000);
+
+        // the second pool trades token B of the first pool, with the same
+        // program config and fee wallet
+        second.fee_wallet_key = first.fee_wallet_key;
+        second.config_account = first.config_account.clone();
+        let (token_a_key, token_a_account) = mint_token(
+            &spl_token::id(),
+            &first.token_b_mint_key,
+            &mut first.token_b_mint_account,
+            &user_key,
+            &second.authority_key,
+            5_000_000,
+        );
+        let (fee_a_key, fixed_fee_account_a) = mint_token(
+            &spl_token::id(),
+            &first.token_b_mint_key,
+            &mut first.token_b_mint_account,
+            &user_key,
+            &first.fee_wallet_key,
+            0,
+        );
+        let (fee_b_key, fixed_fee_account_b) = mint_token(
+            &spl_token::id(),
+            &second.token_b_mint_key,
+            &mut second.token_b_mint_account,
+            &user_key,
+            &first.fee_wallet_key,
             0,
         );
+        second.token_a_key = token_a_key;
+        second.token_a_account = token_a_account;
+        second.token_a_mint_key = first.token_b_mint_key;
+        second.token_a_mint_account = first.token_b_mint_account.clone();
+        second.fee_a_key = fee_a_key;
+        second.fixed_fee_account_a = fixed_fee_account_a;
+        second.fee_b_key = fee_b_key;
+        second.fixed_fee_account_b = fixed_fee_account_b;
+        second.market_account = create_market(
+            &second.dex_program_id,
+            &second.market_key,
+            &first.token_b_mint_key,
+            &second.token_b_mint_key,
+        );
 
-        let amount_in = token_a_amount / 2;
-        let minimum_amount_out = 0;
+        first.initialize_swap().unwrap();
+        second.initialize_swap().unwrap();
+
+        let amount_in = 100_000;
+        let (
+            source_key,
+            mut source_account,
+            middle_key,
+            mut middle_account,
+            _pool_key,
+            _pool_account,
+        ) = first.setup_token_accounts(&user_key, &user_key, amount_in, 0, 0);
+        let (
+            _token_a_key,
+            _token_a_account,
+            destination_key,
+            mut destination_account,
+            _pool_key,
+            _pool_account,
+        ) = second.setup_token_accounts(&user_key, &user_key, 0, 0, 0);
+
+        let first_result = swap_curve
+            .swap(
+                u128::from(amount_in),
+                1_000_000,
+                5_000_000,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        let second_result = swap_curve
+            .swap(
+                first_result.destination_amount_swapped,
+                5_000_000,
+                2_000_000,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        let middle_amount = to_u64(first_result.destination_amount_swapped).unwrap();
+        let amount_out = to_u64(second_result.destination_amount_swapped).unwrap();
 
-        // perform the swap
-        do_process_instruction_with_fee_constraints(
-            swap(
-                &SWAP_PROGRAM_ID,
+        let user_transfer_key = Pubkey::new_unique();
+        do_process_instruction(
+            approve(
                 &spl_token::id(),
-                &accounts.swap_key,
-                &accounts.authority_key,
-                &accounts.authority_key,
-                &token_a_key,
-                &accounts.token_a_key,
-                &accounts.token_b_key,
-                &token_b_key,
-                &accounts.pool_mint_key,
-                &accounts.fee_a_key,
-                Some(&pool_key),
-                SwapInstruction {
-                    amount_in,
-                    minimum_amount_out,
-                },
+                &source_key,
+                &user_transfer_key,
+                &user_key,
+                &[],
+                amount_in,
             )
             .unwrap(),
             vec![
-                &mut accounts.swap_account,
+                &mut source_account,
                 &mut Account::default(),
                 &mut Account::default(),
-                &mut token_a_account,
-                &mut accounts.token_a_account,
-                &mut accounts.token_b_account,
-                &mut token_b_account,
-                &mut accounts.pool_mint_account,
-                &mut accounts.fixed_fee_account_a,
-                &mut accounts.fixed_fee_account_b,
+            ],
+        )
+        .unwrap();
+        do_process_instruction(
+            approve(
+                &spl_token::id(),
+                &middle_key,
+                &user_transfer_key,
+                &user_key,
+                &[],
+                middle_amount,
+            )
+            .unwrap(),
+            vec![
+                &mut middle_account,
+                &mut Account::default(),
                 &mut Account::default(),
-                &mut pool_account,
             ],
-            &constraints,
         )
         .unwrap();
 
-        // // check that fees were taken in the host fee account
-        // let host_fee_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
-        // let owner_fee_account =
-        //     spl_token::state::Account::unpack(&accounts.fixed_fee_account.data).unwrap();
-        // let total_fee = owner_fee_account.amount * host_fee_denominator
-        //     / (host_fee_denominator - host_fee_numerator);
-        // assert_eq!(
-        //     total_fee,
-        //     host_fee_account.amount + owner_fee_account.amount
-        // );
+        let legs = [
+            route_swap_leg(&first, &middle_key),
+            route_swap_leg(&second, &destination_key),
+        ];
+
+        // accounts of a leg are missing
+        {
+            let mut instruction = route_swap(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &user_transfer_key,
+                &source_key,
+                &first.fee_wallet_key,
+                &first.config_key,
+                &legs[..1],
+                RouteSwapInstruction {
+                    amount_in,
+                    minimum_amount_out: 0,
+                    deadline: None,
+                },
+            )
+            .unwrap();
+            instruction.accounts.pop();
+            assert_eq!(
+                Err(ProgramError::NotEnoughAccountKeys),
+                do_process_instruction(
+                    instruction,
+                    vec![
+                        &mut Account::default(),
+                        &mut source_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut first.config_account,
+                        &mut first.swap_account,
+                        &mut Account::default(),
+                        &mut first.token_a_account,
+                        &mut first.token_b_account,
+                        &mut middle_account,
+                        &mut first.pool_mint_account,
+                        &mut first.fixed_fee_account_a,
+                    ],
+                )
+            );
+        }
+
+        // slippage is checked on the last leg only
+        for (minimum_amount_out, expected) in &[
+            (amount_out + 1, Err(AmmError::ExceededSlippage.into())),
+            (amount_out, Ok(())),
+        ] {
+            assert_eq!(
+                *expected,
+                do_process_instruction(
+                    route_swap(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &user_transfer_key,
+                        &source_key,
+                        &first.fee_wallet_key,
+                        &first.config_key,
+                        &legs,
+                        RouteSwapInstruction {
+                            amount_in,
+                            minimum_amount_out: *minimum_amount_out,
+                            deadline: None,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut Account::default(),
+                        &mut source_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut first.config_account,
+                        &mut first.swap_account,
+                        &mut Account::default(),
+                        &mut first.token_a_account,
+                        &mut first.token_b_account,
+                        &mut middle_account,
+                        &mut first.pool_mint_account,
+                        &mut first.fixed_fee_account_a,
+                        &mut first.open_orders_account,
+                        &mut second.swap_account,
+                        &mut Account::default(),
+                        &mut second.token_a_account,
+                        &mut second.token_b_account,
+                        &mut destination_account,
+                        &mut second.pool_mint_account,
+                        &mut second.fixed_fee_account_a,
+                        &mut second.open_orders_account,
+                    ],
+                )
+            );
+        }
+
+        let source = spl_token::state::Account::unpack(&source_account.data).unwrap();
+        assert_eq!(source.amount, 0);
+        // the second leg only takes what its rounding allows, the rest stays
+        // with the user
+        let middle = spl_token::state::Account::unpack(&middle_account.data).unwrap();
+        assert_eq!(
+            u128::from(middle.amount),
+            first_result.destination_amount_swapped - second_result.source_amount_swapped
+        );
+        let destination = spl_token::state::Account::unpack(&destination_account.data).unwrap();
+        assert_eq!(destination.amount, amount_out);
+
+        let swap_token_a = spl_token::state::Account::unpack(&first.token_a_account.data).unwrap();
+        assert_eq!(
+            u128::from(swap_token_a.amount),
+            1_000_000 + first_result.source_amount_swapped - first_result.owner_fee
+        );
+        let fee_a = spl_token::state::Account::unpack(&first.fixed_fee_account_a.data).unwrap();
+        assert_eq!(u128::from(fee_a.amount), first_result.owner_fee);
+        let swap_token_b = spl_token::state::Account::unpack(&second.token_a_account.data).unwrap();
+        assert_eq!(
+            u128::from(swap_token_b.amount),
+            5_000_000 + second_result.source_amount_swapped - second_result.owner_fee
+        );
+        let fee_b = spl_token::state::Account::unpack(&second.fixed_fee_account_a.data).unwrap();
+        assert_eq!(u128::from(fee_b.amount), second_result.owner_fee);
+        let swap_token_c = spl_token::state::Account::unpack(&second.token_b_account.data).unwrap();
+        assert_eq!(
+            u128::from(swap_token_c.amount),
+            2_000_000 - second_result.destination_amount_swapped
+        );
     }
 
     #[test]
@@ -5779,7 +15837,9 @@ mod tests {
         let fees = Fees {
             return_fee_numerator,
             fixed_fee_numerator,
-            fee_denominator
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
         };
 
         let token_a_amount = 1000;
@@ -5917,10 +15977,12 @@ mod tests {
                         &token_b_key,
                         &accounts.pool_mint_key,
                         &accounts.fee_a_key,
-                        None,
+                        &accounts.fee_wallet_key,
+                        &accounts.config_key,
                         SwapInstruction {
                             amount_in: initial_a,
                             minimum_amount_out: minimum_token_b_amount,
+                            deadline: None,
                         },
                     )
                     .unwrap(),
@@ -5934,8 +15996,10 @@ mod tests {
                         &mut token_b_account,
                         &mut accounts.pool_mint_account,
                         &mut accounts.fixed_fee_account_a,
-                        &mut accounts.fixed_fee_account_b,
                         &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.config_account,
                     ],
                 ),
             );
@@ -5993,10 +16057,12 @@ mod tests {
                         &token_b_key,
                         &accounts.pool_mint_key,
                         &accounts.fee_a_key,
-                        None,
+                        &accounts.fee_wallet_key,
+                        &accounts.config_key,
                         SwapInstruction {
                             amount_in: initial_a,
                             minimum_amount_out: minimum_token_b_amount,
+                            deadline: None,
                         },
                     )
                     .unwrap(),
@@ -6010,8 +16076,10 @@ mod tests {
                         &mut token_b_account,
                         &mut accounts.pool_mint_account,
                         &mut accounts.fixed_fee_account_a,
-                        &mut accounts.fixed_fee_account_b,
                         &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.config_account,
                     ],
                 ),
             );
@@ -6163,10 +16231,12 @@ mod tests {
                         &token_b_key,
                         &accounts.pool_mint_key,
                         &accounts.fee_a_key,
-                        None,
+                        &accounts.fee_wallet_key,
+                        &accounts.config_key,
                         SwapInstruction {
                             amount_in: initial_a,
                             minimum_amount_out: minimum_token_b_amount,
+                            deadline: None,
                         },
                     )
                     .unwrap(),
@@ -6180,8 +16250,10 @@ mod tests {
                         &mut token_b_account,
                         &mut accounts.pool_mint_account,
                         &mut accounts.fixed_fee_account_a,
-                        &mut accounts.fixed_fee_account_b,
                         &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.config_account,
                     ],
                 ),
             );
@@ -6298,12 +16370,18 @@ mod tests {
             let fees = Fees {
                 return_fee_numerator,
                 fixed_fee_numerator,
-                fee_denominator
+                fee_denominator,
+                owner_withdraw_fee_numerator: 0,
+                owner_withdraw_fee_denominator: 0,
             };
             let constraints = Some(SwapConstraints {
                 owner_key,
                 valid_curve_types: &[],
                 fees: &fees,
+                host_fee: &HostFee {
+                    numerator: 0,
+                    denominator: 0,
+                },
             });
             do_process_instruction_with_fee_constraints(
                 swap(
@@ -6318,10 +16396,12 @@ mod tests {
                     &token_b_key,
                     &accounts.pool_mint_key,
                     &accounts.fee_a_key,
-                    None,
+                    &accounts.fee_wallet_key,
+                    &accounts.config_key,
                     SwapInstruction {
                         amount_in: initial_a,
                         minimum_amount_out: minimum_token_b_amount,
+                        deadline: None,
                     },
                 )
                 .unwrap(),
@@ -6335,8 +16415,10 @@ mod tests {
                     &mut token_b_account,
                     &mut accounts.pool_mint_account,
                     &mut accounts.fixed_fee_account_a,
-                    &mut accounts.fixed_fee_account_b,
                     &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.config_account,
                 ],
                 &constraints,
             )
@@ -6355,10 +16437,10 @@ mod tests {
                 _pool_account,
             ) = accounts.setup_token_accounts(&user_key, &authority_key, initial_a, initial_b, 0);
             let (
-                bad_token_a_key,
-                mut bad_token_a_account,
-                _token_b_key,
-                mut _token_b_account,
+                _token_a_key,
+                _token_a_account,
+                bad_token_b_key,
+                mut bad_token_b_account,
                 _pool_key,
                 _pool_account,
             ) = accounts.setup_token_accounts(&user_key, &authority_key, initial_a, initial_b, 0);
@@ -6367,35 +16449,52 @@ mod tests {
             let fees = Fees {
                 return_fee_numerator,
                 fixed_fee_numerator,
-                fee_denominator
+                fee_denominator,
+                owner_withdraw_fee_numerator: 0,
+                owner_withdraw_fee_denominator: 0,
             };
             let constraints = Some(SwapConstraints {
                 owner_key,
                 valid_curve_types: &[],
                 fees: &fees,
+                host_fee: &HostFee {
+                    numerator: 0,
+                    denominator: 0,
+                },
             });
+            // the host fee is paid in the source token, token B cannot receive
+            // the host share of an A to B swap
+            let mut instruction = swap(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &accounts.authority_key,
+                &token_a_key,
+                &accounts.token_a_key,
+                &accounts.token_b_key,
+                &token_b_key,
+                &accounts.pool_mint_key,
+                &accounts.fee_a_key,
+                &accounts.fee_wallet_key,
+                &accounts.config_key,
+                SwapInstruction {
+                    amount_in: initial_a,
+                    minimum_amount_out: 0,
+                    deadline: None,
+                },
+            )
+            .unwrap();
+            instruction
+                .accounts
+                .push(AccountMeta::new_readonly(accounts.open_orders_key, false));
+            instruction
+                .accounts
+                .push(AccountMeta::new(bad_token_b_key, false));
             assert_eq!(
-                Err(AmmError::IncorrectPoolMint.into()),
+                Err(AmmError::IncorrectFeeAccount.into()),
                 do_process_instruction_with_fee_constraints(
-                    swap(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.authority_key,
-                        &token_a_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_b_key,
-                        &accounts.pool_mint_key,
-                        &accounts.fee_a_key,
-                        Some(&bad_token_a_key),
-                        SwapInstruction {
-                            amount_in: initial_a,
-                            minimum_amount_out: 0,
-                        },
-                    )
-                    .unwrap(),
+                    instruction,
                     vec![
                         &mut accounts.swap_account,
                         &mut Account::default(),
@@ -6406,9 +16505,12 @@ mod tests {
                         &mut token_b_account,
                         &mut accounts.pool_mint_account,
                         &mut accounts.fixed_fee_account_a,
-                        &mut accounts.fixed_fee_account_b,
                         &mut Account::default(),
-                        &mut bad_token_a_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.config_account,
+                        &mut accounts.open_orders_account,
+                        &mut bad_token_b_account,
                     ],
                     &constraints,
                 ),
@@ -6416,6 +16518,172 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_weighted_curve() {
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        // 80/20 weights with reserves in the same proportion price both
+        // tokens the same
+        let token_a_amount = 800_000_000;
+        let token_b_amount = 200_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Weighted,
+            calculator: Box::new(WeightedCurve {
+                token_a_weight: 80,
+                token_b_weight: 20,
+            }),
+        };
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            token_a_amount,
+            token_b_amount,
+        );
+        accounts.initialize_swap().unwrap();
+
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let initial_a = 1_000_000_000;
+        let initial_b = 1_000_000_000;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+
+        // swap a to b at the weighted price
+        let a_to_b_amount = 1_000_000;
+        let result = swap_curve
+            .swap(
+                a_to_b_amount.into(),
+                token_a_amount.into(),
+                token_b_amount.into(),
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                a_to_b_amount,
+                0,
+            )
+            .unwrap();
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(
+            u128::from(token_b.amount),
+            u128::from(initial_b) + result.destination_amount_swapped
+        );
+        // close to one for one, where a constant product pool with the same
+        // reserves would only give a quarter
+        assert!(result.destination_amount_swapped > 990_000);
+        assert!(result.destination_amount_swapped < 1_000_000);
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        assert_eq!(u128::from(swap_token_a.amount), result.new_swap_source_amount);
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(
+            u128::from(swap_token_b.amount),
+            result.new_swap_destination_amount
+        );
+        let fee_account_a =
+            spl_token::state::Account::unpack(&accounts.fixed_fee_account_a.data).unwrap();
+        assert_eq!(u128::from(fee_account_a.amount), result.owner_fee);
+
+        // the weighted power is only computed for swaps adding at most half of
+        // the source reserve
+        assert_eq!(
+            Err(AmmError::ZeroTradingTokens.into()),
+            accounts.swap(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                swap_token_b.amount,
+                0,
+            )
+        );
+
+        // single sided deposit and withdrawal of token a
+        let deposit_amount = 10_000_000;
+        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+        let pool_tokens = swap_curve
+            .deposit_single_token_type(
+                deposit_amount.into(),
+                swap_token_a.amount.into(),
+                swap_token_b.amount.into(),
+                pool_mint.supply.into(),
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        accounts
+            .deposit_single_token_type_exact_amount_in(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &pool_key,
+                &mut pool_account,
+                deposit_amount,
+                0,
+            )
+            .unwrap();
+        let pool = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+        assert_eq!(u128::from(pool.amount), pool_tokens);
+
+        // taking the same tokens back out costs more pool tokens than they
+        // minted
+        assert_eq!(
+            Err(AmmError::ExceededSlippage.into()),
+            accounts.withdraw_single_token_type_exact_amount_out(
+                &swapper_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                deposit_amount,
+                pool.amount,
+            )
+        );
+        accounts
+            .withdraw_single_token_type_exact_amount_out(
+                &swapper_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                deposit_amount / 2,
+                pool.amount,
+            )
+            .unwrap();
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(
+            token_a.amount,
+            initial_a - a_to_b_amount - deposit_amount + deposit_amount / 2
+        );
+    }
+
     #[test]
     fn test_overdraw_offset_curve() {
         let return_fee_numerator = 25;
@@ -6424,7 +16692,9 @@ mod tests {
         let fees = Fees {
             return_fee_numerator,
             fixed_fee_numerator,
-            fee_denominator
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
         };
         let token_a_amount = 1_000_000_000;
         let token_b_amount = 0;
@@ -6487,305 +16757,1559 @@ mod tests {
                 &swap_token_a_key,
                 &token_a_key,
                 &mut token_a_account,
-                b_to_a_amount,
-                minimum_token_a_amount,
+                b_to_a_amount,
+                minimum_token_a_amount,
+            )
+            .unwrap();
+
+        // try a to b again, succeeds due to new liquidity
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                a_to_b_amount,
+                minimum_token_b_amount,
+            )
+            .unwrap();
+
+        // try a to b again, fails due to no more liquidity
+        assert_eq!(
+            Err(AmmError::ZeroTradingTokens.into()),
+            accounts.swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                a_to_b_amount,
+                minimum_token_b_amount,
+            )
+        );
+
+        // Try to deposit, fails because deposits are not allowed for offset
+        // curve swaps
+        {
+            let initial_a = 100;
+            let initial_b = 100;
+            let pool_amount = 100;
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            assert_eq!(
+                Err(AmmError::UnsupportedCurveOperation.into()),
+                accounts.deposit_all_token_types(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount,
+                    initial_a,
+                    initial_b,
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_withdraw_all_offset_curve() {
+        let return_fee_numerator = 25;
+        let fixed_fee_numerator = 5;
+        let fee_denominator = 10000;
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        
+        let token_a_amount = 1_000_000_000;
+        let token_b_amount = 10;
+
+        let token_b_offset = 2_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Offset,
+            calculator: Box::new(OffsetCurve { token_b_offset }),
+        };
+        let total_pool = swap_curve.calculator.new_pool_supply();
+        let user_key = Pubkey::new_unique();
+        let withdrawer_key = Pubkey::new_unique();
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        accounts.initialize_swap().unwrap();
+
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
+
+        let pool_key = accounts.pool_token_key;
+        let mut pool_account = accounts.pool_token_account.clone();
+
+        // WithdrawAllTokenTypes takes all tokens for A and B.
+        // The curve's calculation for token B will say to transfer
+        // `token_b_offset + token_b_amount`, but only `token_b_amount` will be
+        // moved.
+        accounts
+            .withdraw_all_token_types(
+                &user_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                total_pool.try_into().unwrap(),
+                0,
+                0,
+            )
+            .unwrap();
+
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, token_a_amount);
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, token_b_amount);
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        assert_eq!(swap_token_a.amount, 0);
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(swap_token_b.amount, 0);
+    }
+
+    #[test]
+    fn test_withdraw_all_constant_price_curve() {
+
+        let return_fee_numerator = 25;
+        let fixed_fee_numerator = 5;
+        let fee_denominator = 10000;
+        let fees = Fees {
+            return_fee_numerator,
+            fixed_fee_numerator,
+            fee_denominator,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+
+        // initialize "unbalanced", so that withdrawing all will have some issues
+        // A: 1_000_000_000
+        // B: 2_000_000_000 (1_000 * 2_000_000)
+        let swap_token_a_amount = 1_000_000_000;
+        let swap_token_b_amount = 1_000;
+        let token_b_price = 2_000_000;
+
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantPrice,
+            calculator: Box::new(ConstantPriceCurve { token_b_price }),
+        };
+        let total_pool = swap_curve.calculator.new_pool_supply();
+        let user_key = Pubkey::new_unique();
+        let withdrawer_key = Pubkey::new_unique();
+
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees,
+            swap_curve,
+            swap_token_a_amount,
+            swap_token_b_amount,
+        );
+
+        accounts.initialize_swap().unwrap();
+
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
+
+        let pool_key = accounts.pool_token_key;
+        let mut pool_account = accounts.pool_token_account.clone();
+
+        // WithdrawAllTokenTypes will not take all token A and B, since their
+        // ratio is unbalanced.  It will try to take 1_500_000_000 worth of
+        // each token, which means 1_500_000_000 token A, and 750 token B.
+        // With no slippage, this will leave 250 token B in the pool.
+        assert_eq!(
+            Err(AmmError::ExceededSlippage.into()),
+            accounts.withdraw_all_token_types(
+                &user_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                total_pool.try_into().unwrap(),
+                swap_token_a_amount,
+                swap_token_b_amount,
+            )
+        );
+
+        accounts
+            .withdraw_all_token_types(
+                &user_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                total_pool.try_into().unwrap(),
+                0,
+                0,
+            )
+            .unwrap();
+
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, swap_token_a_amount);
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, 750);
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        assert_eq!(swap_token_a.amount, 0);
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(swap_token_b.amount, 250);
+
+        // deposit now, not enough to cover the tokens already in there
+        let token_b_amount = 10;
+        let token_a_amount = token_b_amount * token_b_price;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(
+            &user_key,
+            &withdrawer_key,
+            token_a_amount,
+            token_b_amount,
+            0,
+        );
+
+        assert_eq!(
+            Err(AmmError::ExceededSlippage.into()),
+            accounts.deposit_all_token_types(
+                &withdrawer_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                1, // doesn't matter
+                token_a_amount,
+                token_b_amount,
             )
-            .unwrap();
+        );
+
+        // deposit enough tokens, success!
+        let token_b_amount = 125;
+        let token_a_amount = token_b_amount * token_b_price;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(
+            &user_key,
+            &withdrawer_key,
+            token_a_amount,
+            token_b_amount,
+            0,
+        );
 
-        // try a to b again, succeeds due to new liquidity
         accounts
-            .swap(
-                &swapper_key,
+            .deposit_all_token_types(
+                &withdrawer_key,
                 &token_a_key,
                 &mut token_a_account,
-                &swap_token_a_key,
-                &swap_token_b_key,
                 &token_b_key,
                 &mut token_b_account,
-                a_to_b_amount,
-                minimum_token_b_amount,
+                &pool_key,
+                &mut pool_account,
+                1, // doesn't matter
+                token_a_amount,
+                token_b_amount,
             )
             .unwrap();
+    }
 
-        // try a to b again, fails due to no more liquidity
+    fn check_multi_token_pool(swap_curve: MultiSwapCurve) {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+        };
+        let token_amounts = [1_000_000, 2_000_000, 3_000_000];
+
+        // too few tokens
+        let mut accounts = MultiSwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            &token_amounts[..2],
+        );
         assert_eq!(
-            Err(AmmError::ZeroTradingTokens.into()),
-            accounts.swap(
-                &swapper_key,
-                &token_a_key,
-                &mut token_a_account,
-                &swap_token_a_key,
-                &swap_token_b_key,
-                &token_b_key,
-                &mut token_b_account,
-                a_to_b_amount,
-                minimum_token_b_amount,
-            )
+            Err(AmmError::InvalidTokenCount.into()),
+            accounts.initialize_swap()
         );
 
-        // Try to deposit, fails because deposits are not allowed for offset
-        // curve swaps
+        let start_ts = 1_600_000_000;
+        set_unix_timestamp(start_ts);
+        let mut accounts =
+            MultiSwapAccountInfo::new(&user_key, fees.clone(), swap_curve.clone(), &token_amounts);
+        accounts.initialize_swap().unwrap();
+        assert_eq!(
+            Err(AmmError::AlreadyInUse.into()),
+            accounts.initialize_swap()
+        );
+        let multi_swap_state = MultiSwap::unpack_account(&accounts.swap_account.data).unwrap();
+        assert_eq!(multi_swap_state.price_oracle, MultiPriceOracle::new(start_ts));
+        assert_eq!(multi_swap_state.reserve_accounts(), &accounts.token_keys[..]);
+        assert_eq!(multi_swap_state.mints(), &accounts.token_mint_keys[..]);
+        assert_eq!(multi_swap_state.fee_accounts(), &accounts.fee_keys[..]);
+
+        // a two token pool can not take over the account
         {
-            let initial_a = 100;
-            let initial_b = 100;
-            let pool_amount = 100;
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let mut two_token_accounts = SwapAccountInfo::new(
+                &user_key,
+                fees.clone(),
+                SwapCurve {
+                    curve_type: CurveType::ConstantProduct,
+                    calculator: Box::new(ConstantProductCurve {}),
+                },
+                1_000,
+                1_000,
+            );
+            two_token_accounts.swap_account = accounts.swap_account.clone();
             assert_eq!(
-                Err(AmmError::UnsupportedCurveOperation.into()),
+                Err(AmmError::AlreadyInUse.into()),
+                two_token_accounts.initialize_swap()
+            );
+        }
+
+        let (token_keys, mut token_accounts, pool_key, mut pool_account) =
+            accounts.setup_token_accounts(&user_key, 100_000);
+
+        // swap token 0 for token 2
+        {
+            let amount_in = 10_000;
+            let swap_token_amounts = accounts.swap_token_amounts();
+            let result = swap_curve
+                .swap(amount_in as u128, &swap_token_amounts, 0, 2, &fees)
+                .unwrap();
+            let (source_key, destination_key) = (token_keys[0], token_keys[2]);
+            let (mut source_account, mut destination_account) =
+                (token_accounts[0].clone(), token_accounts[2].clone());
+            take_events();
+            set_unix_timestamp(start_ts + 100);
+            accounts
+                .swap(
+                    &user_key,
+                    &source_key,
+                    &mut source_account,
+                    &destination_key,
+                    &mut destination_account,
+                    0,
+                    amount_in,
+                    0,
+                )
+                .unwrap();
+            token_accounts[0] = source_account;
+            token_accounts[2] = destination_account;
+
+            let swap_token_amounts_after = accounts.swap_token_amounts();
+            assert_eq!(swap_token_amounts_after[0], result.new_swap_source_amount);
+            assert_eq!(swap_token_amounts_after[1], swap_token_amounts[1]);
+            assert_eq!(swap_token_amounts_after[2], result.new_swap_destination_amount);
+            let source = spl_token::state::Account::unpack(&token_accounts[0].data).unwrap();
+            assert_eq!(u128::from(source.amount), 100_000 - result.source_amount_swapped);
+            let destination =
+                spl_token::state::Account::unpack(&token_accounts[2].data).unwrap();
+            assert_eq!(
+                u128::from(destination.amount),
+                100_000 + result.destination_amount_swapped
+            );
+            let fee_account =
+                spl_token::state::Account::unpack(&accounts.fee_accounts[0].data).unwrap();
+            assert_eq!(u128::from(fee_account.amount), result.owner_fee);
+            let stats = MultiSwap::unpack_account(&accounts.swap_account.data)
+                .unwrap()
+                .stats;
+            assert_eq!(stats.volumes[..3], [result.source_amount_swapped, 0, 0]);
+            assert_eq!(stats.fixed_fees[0], result.owner_fee);
+            assert_eq!(stats.return_fees[0], result.trade_fee);
+            assert_eq!(stats.swap_count, 1);
+            // the prices held until the swap are accumulated
+            let price_oracle = MultiSwap::unpack_account(&accounts.swap_account.data)
+                .unwrap()
+                .price_oracle;
+            assert_eq!(price_oracle.last_update_ts, start_ts + 100);
+            assert_eq!(
+                price_oracle.price_cumulatives[..4],
+                [100 << 64, 100 * ((1 << 64) / 2), 100 * ((1 << 64) / 3), 0]
+            );
+            // the user of the event is the delegate approved for the swap
+            let events = take_events();
+            let user = match events[..] {
+                [AmmEvent::MultiSwap(event)] => event.user,
+                _ => panic!("unexpected events {:?}", events),
+            };
+            assert_eq!(
+                events,
+                vec![AmmEvent::MultiSwap(MultiSwapEvent {
+                    pool: accounts.swap_key,
+                    user,
+                    source_index: 0,
+                    destination_index: 2,
+                    amount_in: result.source_amount_swapped as u64,
+                    amount_out: result.destination_amount_swapped as u64,
+                    owner_fee: result.owner_fee as u64,
+                    reserve_in: swap_token_amounts_after[0] as u64,
+                    reserve_out: swap_token_amounts_after[2] as u64,
+                })]
+            );
+        }
+
+        // the fixed fee goes to the account of the source token
+        {
+            let (source_key, destination_key) = (token_keys[0], token_keys[2]);
+            let (mut source_account, mut destination_account) =
+                (token_accounts[0].clone(), token_accounts[2].clone());
+            assert_eq!(
+                Err(AmmError::IncorrectFeeAccount.into()),
+                accounts.swap(
+                    &user_key,
+                    &source_key,
+                    &mut source_account,
+                    &destination_key,
+                    &mut destination_account,
+                    1,
+                    1_000,
+                    0,
+                )
+            );
+        }
+
+        // same token in and out
+        {
+            let (source_key, destination_key) = (token_keys[1], token_keys[1]);
+            let (mut source_account, mut destination_account) =
+                (token_accounts[1].clone(), token_accounts[1].clone());
+            assert_eq!(
+                Err(AmmError::InvalidInput.into()),
+                accounts.swap(
+                    &user_key,
+                    &source_key,
+                    &mut source_account,
+                    &destination_key,
+                    &mut destination_account,
+                    1,
+                    1_000,
+                    0,
+                )
+            );
+        }
+
+        // reserves out of order
+        {
+            accounts.token_keys.swap(0, 1);
+            accounts.token_accounts.swap(0, 1);
+            let (source_key, destination_key) = (token_keys[0], token_keys[2]);
+            let (mut source_account, mut destination_account) =
+                (token_accounts[0].clone(), token_accounts[2].clone());
+            assert_eq!(
+                Err(AmmError::IncorrectSwapAccount.into()),
+                accounts.swap(
+                    &user_key,
+                    &source_key,
+                    &mut source_account,
+                    &destination_key,
+                    &mut destination_account,
+                    0,
+                    1_000,
+                    0,
+                )
+            );
+            accounts.token_keys.swap(0, 1);
+            accounts.token_accounts.swap(0, 1);
+        }
+
+        // deposit all tokens
+        let pool_token_amount = (accounts.pool_supply() / 100) as u64;
+        {
+            assert_eq!(
+                Err(AmmError::InvalidTokenCount.into()),
                 accounts.deposit_all_token_types(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
+                    &user_key,
+                    &token_keys,
+                    &mut token_accounts.clone(),
+                    &pool_key,
+                    &mut pool_account.clone(),
+                    pool_token_amount,
+                    vec![100_000; 2],
+                )
+            );
+
+            let swap_token_amounts = accounts.swap_token_amounts();
+            let user_token_amounts = user_token_amounts(&token_accounts);
+            accounts
+                .deposit_all_token_types(
+                    &user_key,
+                    &token_keys,
+                    &mut token_accounts,
                     &pool_key,
                     &mut pool_account,
-                    pool_amount,
-                    initial_a,
-                    initial_b,
+                    pool_token_amount,
+                    vec![100_000; 3],
+                )
+                .unwrap();
+            let pool = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(pool.amount, pool_token_amount);
+            let deposited = swap_token_amounts
+                .iter()
+                .zip(accounts.swap_token_amounts())
+                .map(|(before, after)| after - before);
+            let paid = user_token_amounts
+                .iter()
+                .zip(self::user_token_amounts(&token_accounts))
+                .map(|(before, after)| before - after);
+            for (deposited, paid) in deposited.zip(paid) {
+                assert!(deposited > 0);
+                assert_eq!(deposited, paid);
+            }
+            let mut token_amounts = [0; MAX_TOKENS];
+            let mut reserves = [0; MAX_TOKENS];
+            for (index, (before, after)) in swap_token_amounts
+                .iter()
+                .zip(accounts.swap_token_amounts())
+                .enumerate()
+            {
+                token_amounts[index] = (after - before) as u64;
+                reserves[index] = after as u64;
+            }
+            let events = take_events();
+            let user = match events[..] {
+                [AmmEvent::MultiDeposit(event)] => event.user,
+                _ => panic!("unexpected events {:?}", events),
+            };
+            assert_eq!(
+                events,
+                vec![AmmEvent::MultiDeposit(MultiLiquidityEvent {
+                    pool: accounts.swap_key,
+                    user,
+                    token_amounts,
+                    pool_token_amount,
+                    reserves,
+                })]
+            );
+        }
+
+        // withdraw half of them
+        {
+            let swap_token_amounts = accounts.swap_token_amounts();
+            let user_token_amounts = user_token_amounts(&token_accounts);
+            accounts
+                .withdraw_all_token_types(
+                    &user_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_keys,
+                    &mut token_accounts,
+                    pool_token_amount / 2,
+                    vec![0; 3],
+                )
+                .unwrap();
+            let pool = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(pool.amount, pool_token_amount - pool_token_amount / 2);
+            let withdrawn = swap_token_amounts
+                .iter()
+                .zip(accounts.swap_token_amounts())
+                .map(|(before, after)| before - after);
+            let received = user_token_amounts
+                .iter()
+                .zip(self::user_token_amounts(&token_accounts))
+                .map(|(before, after)| after - before);
+            for (withdrawn, received) in withdrawn.zip(received) {
+                assert!(withdrawn > 0);
+                assert_eq!(withdrawn, received);
+            }
+            let mut token_amounts = [0; MAX_TOKENS];
+            let mut reserves = [0; MAX_TOKENS];
+            for (index, (before, after)) in swap_token_amounts
+                .iter()
+                .zip(accounts.swap_token_amounts())
+                .enumerate()
+            {
+                token_amounts[index] = (before - after) as u64;
+                reserves[index] = after as u64;
+            }
+            let events = take_events();
+            let user = match events[..] {
+                [AmmEvent::MultiWithdraw(event)] => event.user,
+                _ => panic!("unexpected events {:?}", events),
+            };
+            assert_eq!(
+                events,
+                vec![AmmEvent::MultiWithdraw(MultiLiquidityEvent {
+                    pool: accounts.swap_key,
+                    user,
+                    token_amounts,
+                    pool_token_amount: pool_token_amount / 2,
+                    reserves,
+                })]
+            );
+        }
+
+        // deposit a single token
+        {
+            let source_token_amount = 10_000;
+            let pool_token_amount = swap_curve
+                .deposit_single_token_type(
+                    source_token_amount as u128,
+                    &accounts.swap_token_amounts(),
+                    1,
+                    accounts.pool_supply(),
+                    &fees,
+                )
+                .unwrap();
+            let pool_amount_before =
+                spl_token::state::Account::unpack(&pool_account.data).unwrap().amount;
+            let (source_key, mut source_account) = (token_keys[1], token_accounts[1].clone());
+            accounts
+                .deposit_single_token_type_exact_amount_in(
+                    &user_key,
+                    &source_key,
+                    &mut source_account,
+                    &pool_key,
+                    &mut pool_account,
+                    source_token_amount,
+                    pool_token_amount as u64,
+                )
+                .unwrap();
+            token_accounts[1] = source_account;
+            let pool = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(
+                u128::from(pool.amount),
+                u128::from(pool_amount_before) + pool_token_amount
+            );
+        }
+
+        // withdraw exactly one token
+        {
+            let destination_token_amount = 5_000;
+            let pool_token_amount = swap_curve
+                .withdraw_single_token_type_exact_out(
+                    destination_token_amount as u128,
+                    &accounts.swap_token_amounts(),
+                    2,
+                    accounts.pool_supply(),
+                    &fees,
+                )
+                .unwrap();
+            let pool_amount_before =
+                spl_token::state::Account::unpack(&pool_account.data).unwrap().amount;
+            let (destination_key, mut destination_account) =
+                (token_keys[2], token_accounts[2].clone());
+            let destination_amount_before =
+                spl_token::state::Account::unpack(&destination_account.data)
+                    .unwrap()
+                    .amount;
+            accounts
+                .withdraw_single_token_type_exact_amount_out(
+                    &user_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &destination_key,
+                    &mut destination_account,
+                    destination_token_amount,
+                    pool_amount_before,
+                )
+                .unwrap();
+            let pool = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(
+                u128::from(pool.amount),
+                u128::from(pool_amount_before) - pool_token_amount
+            );
+            let destination = spl_token::state::Account::unpack(&destination_account.data).unwrap();
+            assert_eq!(
+                destination.amount,
+                destination_amount_before + destination_token_amount
+            );
+            token_accounts[2] = destination_account;
+        }
+
+        // halted pool
+        {
+            let admin_key = accounts.admin_key;
+            accounts
+                .set_pool_status(&admin_key, PoolStatus::Halted)
+                .unwrap();
+            let (source_key, destination_key) = (token_keys[0], token_keys[2]);
+            let (mut source_account, mut destination_account) =
+                (token_accounts[0].clone(), token_accounts[2].clone());
+            assert_eq!(
+                Err(AmmError::PoolPaused.into()),
+                accounts.swap(
+                    &user_key,
+                    &source_key,
+                    &mut source_account,
+                    &destination_key,
+                    &mut destination_account,
+                    0,
+                    1_000,
+                    0,
                 )
             );
         }
     }
 
+    fn user_token_amounts(token_accounts: &[Account]) -> Vec<u128> {
+        token_accounts
+            .iter()
+            .map(|account| {
+                let account = spl_token::state::Account::unpack(&account.data).unwrap();
+                u128::from(account.amount)
+            })
+            .collect()
+    }
+
     #[test]
-    fn test_withdraw_all_offset_curve() {
-        let return_fee_numerator = 25;
-        let fixed_fee_numerator = 5;
-        let fee_denominator = 10000;
-        let fees = Fees {
-            return_fee_numerator,
-            fixed_fee_numerator,
-            fee_denominator
-        };
-        
-        let token_a_amount = 1_000_000_000;
-        let token_b_amount = 10;
+    fn test_multi_token_pool() {
+        check_multi_token_pool(MultiSwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Box::new(MultiStableCurve { amp: 100 }),
+        });
+        let mut weights = [0; MAX_TOKENS];
+        weights[..3].copy_from_slice(&[40, 30, 30]);
+        check_multi_token_pool(MultiSwapCurve {
+            curve_type: CurveType::Weighted,
+            calculator: Box::new(MultiWeightedCurve { weights }),
+        });
+    }
 
-        let token_b_offset = 2_000_000;
-        let swap_curve = SwapCurve {
-            curve_type: CurveType::Offset,
-            calculator: Box::new(OffsetCurve { token_b_offset }),
-        };
-        let total_pool = swap_curve.calculator.new_pool_supply();
-        let user_key = Pubkey::new_unique();
-        let withdrawer_key = Pubkey::new_unique();
+    struct ConcentratedAccountInfo {
+        nonce: u8,
+        authority_key: Pubkey,
+        fees: Fees,
+        tick_spacing: u16,
+        sqrt_price: u128,
+        pool_key: Pubkey,
+        pool_account: Account,
+        admin_key: Pubkey,
+        config_key: Pubkey,
+        config_account: Account,
+        fee_wallet_key: Pubkey,
+        token_a_key: Pubkey,
+        token_a_account: Account,
+        token_b_key: Pubkey,
+        token_b_account: Account,
+        token_a_mint_key: Pubkey,
+        token_a_mint_account: Account,
+        token_b_mint_key: Pubkey,
+        token_b_mint_account: Account,
+        fee_a_key: Pubkey,
+        fee_a_account: Account,
+        fee_b_key: Pubkey,
+        fee_b_account: Account,
+    }
 
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+    impl ConcentratedAccountInfo {
+        pub fn new(user_key: &Pubkey, fees: Fees, tick_spacing: u16, sqrt_price: u128) -> Self {
+            let pool_key = Pubkey::new_unique();
+            let pool_account = Account::new(0, ConcentratedPool::ACCOUNT_LEN, &SWAP_PROGRAM_ID);
+            let (authority_key, nonce) =
+                Pubkey::find_program_address(&[&pool_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+            let admin_key = Pubkey::new_unique();
+            let fee_wallet_key = Pubkey::new_unique();
+            let (config_key, config_account) = create_config(&admin_key, &fee_wallet_key);
 
-        accounts.initialize_swap().unwrap();
+            let (token_a_mint_key, mut token_a_mint_account) =
+                create_mint(&spl_token::id(), user_key, None);
+            let (token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &token_a_mint_key,
+                &mut token_a_mint_account,
+                user_key,
+                &authority_key,
+                0,
+            );
+            let (fee_a_key, fee_a_account) = mint_token(
+                &spl_token::id(),
+                &token_a_mint_key,
+                &mut token_a_mint_account,
+                user_key,
+                &fee_wallet_key,
+                0,
+            );
+            let (token_b_mint_key, mut token_b_mint_account) =
+                create_mint(&spl_token::id(), user_key, None);
+            let (token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &token_b_mint_key,
+                &mut token_b_mint_account,
+                user_key,
+                &authority_key,
+                0,
+            );
+            let (fee_b_key, fee_b_account) = mint_token(
+                &spl_token::id(),
+                &token_b_mint_key,
+                &mut token_b_mint_account,
+                user_key,
+                &fee_wallet_key,
+                0,
+            );
 
-        let (
-            token_a_key,
-            mut token_a_account,
-            token_b_key,
-            mut token_b_account,
-            _pool_key,
-            _pool_account,
-        ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
+            ConcentratedAccountInfo {
+                nonce,
+                authority_key,
+                fees,
+                tick_spacing,
+                sqrt_price,
+                pool_key,
+                pool_account,
+                admin_key,
+                config_key,
+                config_account,
+                fee_wallet_key,
+                token_a_key,
+                token_a_account,
+                token_b_key,
+                token_b_account,
+                token_a_mint_key,
+                token_a_mint_account,
+                token_b_mint_key,
+                token_b_mint_account,
+                fee_a_key,
+                fee_a_account,
+                fee_b_key,
+                fee_b_account,
+            }
+        }
 
-        let pool_key = accounts.pool_token_key;
-        let mut pool_account = accounts.pool_token_account.clone();
+        pub fn initialize_pool(&mut self) -> ProgramResult {
+            do_process_instruction(
+                initialize_concentrated(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.pool_key,
+                    &self.authority_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.fee_a_key,
+                    &self.fee_b_key,
+                    &self.config_key,
+                    InitializeConcentratedInstruction {
+                        nonce: self.nonce,
+                        fees: self.fees.clone(),
+                        tick_spacing: self.tick_spacing,
+                        sqrt_price: self.sqrt_price,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.pool_account,
+                    &mut Account::default(),
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.fee_a_account,
+                    &mut self.fee_b_account,
+                    &mut Account::default(),
+                    &mut self.config_account,
+                ],
+            )
+        }
 
-        // WithdrawAllTokenTypes takes all tokens for A and B.
-        // The curve's calculation for token B will say to transfer
-        // `token_b_offset + token_b_amount`, but only `token_b_amount` will be
-        // moved.
-        accounts
-            .withdraw_all_token_types(
-                &user_key,
-                &pool_key,
-                &mut pool_account,
-                &token_a_key,
-                &mut token_a_account,
-                &token_b_key,
-                &mut token_b_account,
-                total_pool.try_into().unwrap(),
-                0,
-                0,
+        pub fn set_pool_status(&mut self, admin_key: &Pubkey, status: PoolStatus) -> ProgramResult {
+            do_process_instruction(
+                set_pool_status(
+                    &SWAP_PROGRAM_ID,
+                    &self.pool_key,
+                    &self.config_key,
+                    admin_key,
+                    status,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.pool_account,
+                    &mut self.config_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        /// Token A and token B accounts of the user holding `amount` each
+        pub fn setup_token_accounts(
+            &mut self,
+            user_key: &Pubkey,
+            amount: u64,
+        ) -> (Pubkey, Account, Pubkey, Account) {
+            let (token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &self.token_a_mint_key,
+                &mut self.token_a_mint_account,
+                user_key,
+                user_key,
+                amount,
+            );
+            let (token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &self.token_b_mint_key,
+                &mut self.token_b_mint_account,
+                user_key,
+                user_key,
+                amount,
+            );
+            (token_a_key, token_a_account, token_b_key, token_b_account)
+        }
+
+        fn pool(&self) -> ConcentratedPool {
+            ConcentratedPool::unpack_account(&self.pool_account.data).unwrap()
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn open_position(
+            &mut self,
+            user_key: &Pubkey,
+            position_key: &Pubkey,
+            position_account: &mut Account,
+            token_a_key: &Pubkey,
+            token_a_account: &mut Account,
+            token_b_key: &Pubkey,
+            token_b_account: &mut Account,
+            instruction: OpenPositionInstruction,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            approve_transfer_to(
+                user_key,
+                &user_transfer_key,
+                token_a_key,
+                token_a_account,
+                instruction.maximum_token_a_amount,
+            );
+            approve_transfer_to(
+                user_key,
+                &user_transfer_key,
+                token_b_key,
+                token_b_account,
+                instruction.maximum_token_b_amount,
+            );
+            do_process_instruction(
+                open_position(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.pool_key,
+                    &self.authority_key,
+                    position_key,
+                    user_key,
+                    &user_transfer_key,
+                    token_a_key,
+                    token_b_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    instruction,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.pool_account,
+                    &mut Account::default(),
+                    position_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    token_a_account,
+                    token_b_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn swap(
+            &mut self,
+            user_key: &Pubkey,
+            source_key: &Pubkey,
+            source_account: &mut Account,
+            destination_key: &Pubkey,
+            destination_account: &mut Account,
+            a_to_b: bool,
+            amount_in: u64,
+            minimum_amount_out: u64,
+        ) -> ProgramResult {
+            let user_transfer_key =
+                approve_transfer(user_key, source_key, source_account, amount_in);
+            let (swap_source_key, swap_destination_key, fee_key) = if a_to_b {
+                (self.token_a_key, self.token_b_key, self.fee_a_key)
+            } else {
+                (self.token_b_key, self.token_a_key, self.fee_b_key)
+            };
+            let (swap_source_account, swap_destination_account, fee_account) = if a_to_b {
+                (
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.fee_a_account,
+                )
+            } else {
+                (
+                    &mut self.token_b_account,
+                    &mut self.token_a_account,
+                    &mut self.fee_b_account,
+                )
+            };
+            do_process_instruction(
+                concentrated_swap(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.pool_key,
+                    &self.authority_key,
+                    &user_transfer_key,
+                    source_key,
+                    &swap_source_key,
+                    &swap_destination_key,
+                    destination_key,
+                    &fee_key,
+                    &self.fee_wallet_key,
+                    &self.config_key,
+                    SwapInstruction {
+                        amount_in,
+                        minimum_amount_out,
+                        deadline: None,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.pool_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    source_account,
+                    swap_source_account,
+                    swap_destination_account,
+                    destination_account,
+                    fee_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.config_account,
+                ],
+            )
+        }
+
+        /// Collects the fees of the position, or closes it if `close` is set
+        #[allow(clippy::too_many_arguments)]
+        pub fn position_withdraw(
+            &mut self,
+            owner_key: &Pubkey,
+            owner_account: &mut Account,
+            position_key: &Pubkey,
+            position_account: &mut Account,
+            token_a_key: &Pubkey,
+            token_a_account: &mut Account,
+            token_b_key: &Pubkey,
+            token_b_account: &mut Account,
+            close: Option<ClosePositionInstruction>,
+        ) -> ProgramResult {
+            let instruction = match close {
+                Some(close) => close_position(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.pool_key,
+                    &self.authority_key,
+                    position_key,
+                    owner_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    token_a_key,
+                    token_b_key,
+                    close,
+                ),
+                None => collect_fees(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.pool_key,
+                    &self.authority_key,
+                    position_key,
+                    owner_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    token_a_key,
+                    token_b_key,
+                    CollectFeesInstruction { deadline: None },
+                ),
+            };
+            do_process_instruction(
+                instruction.unwrap(),
+                vec![
+                    &mut self.pool_account,
+                    &mut Account::default(),
+                    position_account,
+                    owner_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    token_a_account,
+                    token_b_account,
+                    &mut Account::default(),
+                ],
             )
-            .unwrap();
+        }
+    }
 
-        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-        assert_eq!(token_a.amount, token_a_amount);
-        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-        assert_eq!(token_b.amount, token_b_amount);
-        let swap_token_a =
-            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-        assert_eq!(swap_token_a.amount, 0);
-        let swap_token_b =
-            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-        assert_eq!(swap_token_b.amount, 0);
+    fn token_amount(account: &Account) -> u64 {
+        // Token-2022 accounts start with the spl_token layout
+        spl_token::state::Account::unpack(&account.data[..spl_token::state::Account::LEN])
+            .unwrap()
+            .amount
     }
 
     #[test]
-    fn test_withdraw_all_constant_price_curve() {
-
-        let return_fee_numerator = 25;
-        let fixed_fee_numerator = 5;
-        let fee_denominator = 10000;
+    fn test_concentrated_pool() {
+        let user_key = Pubkey::new_unique();
         let fees = Fees {
-            return_fee_numerator,
-            fixed_fee_numerator,
-            fee_denominator
+            return_fee_numerator: 25,
+            fixed_fee_numerator: 5,
+            fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
         };
+        // price of 1, at tick 0
+        let sqrt_price = 1u128 << 64;
+        let mut accounts = ConcentratedAccountInfo::new(&user_key, fees.clone(), 10, sqrt_price);
 
-        // initialize "unbalanced", so that withdrawing all will have some issues
-        // A: 1_000_000_000
-        // B: 2_000_000_000 (1_000 * 2_000_000)
-        let swap_token_a_amount = 1_000_000_000;
-        let swap_token_b_amount = 1_000;
-        let token_b_price = 2_000_000;
+        // tick spacing out of bounds
+        accounts.tick_spacing = 0;
+        assert_eq!(
+            Err(AmmError::InvalidCurve.into()),
+            accounts.initialize_pool()
+        );
+        accounts.tick_spacing = 10;
 
-        let swap_curve = SwapCurve {
-            curve_type: CurveType::ConstantPrice,
-            calculator: Box::new(ConstantPriceCurve { token_b_price }),
+        let start_ts = 1_600_000_000;
+        set_unix_timestamp(start_ts);
+        accounts.initialize_pool().unwrap();
+        assert_eq!(
+            Err(AmmError::AlreadyInUse.into()),
+            accounts.initialize_pool()
+        );
+        let pool = accounts.pool();
+        assert_eq!(pool.tick_current, 0);
+        assert_eq!(pool.liquidity, 0);
+        assert_eq!(pool.price_oracle, PriceOracle::new(start_ts));
+
+        let (token_a_key, mut token_a_account, token_b_key, mut token_b_account) =
+            accounts.setup_token_accounts(&user_key, 10_000_000);
+        let position_lamports = Rent::default().minimum_balance(Position::ACCOUNT_LEN);
+        let wide_key = Pubkey::new_unique();
+        let mut wide_account =
+            Account::new(position_lamports, Position::ACCOUNT_LEN, &SWAP_PROGRAM_ID);
+        let narrow_key = Pubkey::new_unique();
+        let mut narrow_account =
+            Account::new(position_lamports, Position::ACCOUNT_LEN, &SWAP_PROGRAM_ID);
+        let open = |tick_lower, tick_upper, liquidity| OpenPositionInstruction {
+            tick_lower,
+            tick_upper,
+            liquidity,
+            maximum_token_a_amount: 1_000_000,
+            maximum_token_b_amount: 1_000_000,
+            deadline: None,
         };
-        let total_pool = swap_curve.calculator.new_pool_supply();
-        let user_key = Pubkey::new_unique();
-        let withdrawer_key = Pubkey::new_unique();
 
-        let mut accounts = SwapAccountInfo::new(
-            &user_key,
-            fees,
-            swap_curve,
-            swap_token_a_amount,
-            swap_token_b_amount,
+        // ticks off the spacing
+        assert_eq!(
+            Err(AmmError::InvalidTickRange.into()),
+            accounts.open_position(
+                &user_key,
+                &wide_key,
+                &mut wide_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                open(-1_005, 1_000, 10_000_000),
+            )
         );
-
-        accounts.initialize_swap().unwrap();
-
-        let (
-            token_a_key,
-            mut token_a_account,
-            token_b_key,
-            mut token_b_account,
-            _pool_key,
-            _pool_account,
-        ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
-
-        let pool_key = accounts.pool_token_key;
-        let mut pool_account = accounts.pool_token_account.clone();
-
-        // WithdrawAllTokenTypes will not take all token A and B, since their
-        // ratio is unbalanced.  It will try to take 1_500_000_000 worth of
-        // each token, which means 1_500_000_000 token A, and 750 token B.
-        // With no slippage, this will leave 250 token B in the pool.
+        // empty range
+        assert_eq!(
+            Err(AmmError::InvalidTickRange.into()),
+            accounts.open_position(
+                &user_key,
+                &wide_key,
+                &mut wide_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                open(1_000, 1_000, 10_000_000),
+            )
+        );
+        // more tokens than allowed
         assert_eq!(
             Err(AmmError::ExceededSlippage.into()),
-            accounts.withdraw_all_token_types(
+            accounts.open_position(
                 &user_key,
-                &pool_key,
-                &mut pool_account,
+                &wide_key,
+                &mut wide_account,
                 &token_a_key,
                 &mut token_a_account,
                 &token_b_key,
                 &mut token_b_account,
-                total_pool.try_into().unwrap(),
-                swap_token_a_amount,
-                swap_token_b_amount,
+                open(-1_000, 1_000, 100_000_000),
             )
         );
 
+        take_events();
         accounts
-            .withdraw_all_token_types(
+            .open_position(
                 &user_key,
-                &pool_key,
-                &mut pool_account,
+                &wide_key,
+                &mut wide_account,
                 &token_a_key,
                 &mut token_a_account,
                 &token_b_key,
                 &mut token_b_account,
-                total_pool.try_into().unwrap(),
-                0,
-                0,
+                open(-1_000, 1_000, 10_000_000),
             )
             .unwrap();
-
-        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-        assert_eq!(token_a.amount, swap_token_a_amount);
-        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-        assert_eq!(token_b.amount, 750);
-        let swap_token_a =
-            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-        assert_eq!(swap_token_a.amount, 0);
-        let swap_token_b =
-            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-        assert_eq!(swap_token_b.amount, 250);
-
-        // deposit now, not enough to cover the tokens already in there
-        let token_b_amount = 10;
-        let token_a_amount = token_b_amount * token_b_price;
-        let (
-            token_a_key,
-            mut token_a_account,
-            token_b_key,
-            mut token_b_account,
-            pool_key,
-            mut pool_account,
-        ) = accounts.setup_token_accounts(
-            &user_key,
-            &withdrawer_key,
-            token_a_amount,
-            token_b_amount,
-            0,
+        let (wide_a, wide_b) =
+            token_amounts(sqrt_price, -1_000, 1_000, 10_000_000, true).unwrap();
+        assert_eq!(u128::from(token_amount(&accounts.token_a_account)), wide_a);
+        assert_eq!(u128::from(token_amount(&accounts.token_b_account)), wide_b);
+        assert_eq!(
+            take_events(),
+            vec![AmmEvent::OpenPosition(PositionEvent {
+                pool: accounts.pool_key,
+                position: wide_key,
+                owner: user_key,
+                tick_lower: -1_000,
+                tick_upper: 1_000,
+                liquidity: 10_000_000,
+                token_a_amount: wide_a as u64,
+                token_b_amount: wide_b as u64,
+            })]
+        );
+        assert_eq!(
+            Err(AmmError::AlreadyInUse.into()),
+            accounts.open_position(
+                &user_key,
+                &wide_key,
+                &mut wide_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                open(-1_000, 1_000, 10_000_000),
+            )
+        );
+        accounts
+            .open_position(
+                &user_key,
+                &narrow_key,
+                &mut narrow_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                open(-100, 100, 10_000_000),
+            )
+            .unwrap();
+        let pool = accounts.pool();
+        assert_eq!(pool.liquidity, 20_000_000);
+        assert_eq!(pool.initialized_ticks().len(), 4);
+        let position = Position::unpack_account(&narrow_account.data).unwrap();
+        assert_eq!(position.pool, accounts.pool_key);
+        assert_eq!(position.owner, user_key);
+        assert_eq!(position.liquidity, 10_000_000);
+
+        // more than the whole range holds
+        assert_eq!(
+            Err(AmmError::InsufficientLiquidity.into()),
+            accounts.swap(
+                &user_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                true,
+                5_000_000,
+                0,
+            )
         );
 
+        // swap A for B, moving the price out of the narrow position
+        let amount_in = 200_000;
+        let expected = accounts.pool().swap(amount_in, true).unwrap();
+        let user_b_before = token_amount(&token_b_account);
         assert_eq!(
             Err(AmmError::ExceededSlippage.into()),
-            accounts.deposit_all_token_types(
-                &withdrawer_key,
+            accounts.swap(
+                &user_key,
                 &token_a_key,
                 &mut token_a_account,
                 &token_b_key,
                 &mut token_b_account,
-                &pool_key,
-                &mut pool_account,
-                1, // doesn't matter
-                token_a_amount,
-                token_b_amount,
+                true,
+                amount_in as u64,
+                expected.destination_amount_swapped as u64 + 1,
+            )
+        );
+        take_events();
+        set_unix_timestamp(start_ts + 100);
+        accounts
+            .swap(
+                &user_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                true,
+                amount_in as u64,
+                expected.destination_amount_swapped as u64,
             )
+            .unwrap();
+        assert_eq!(
+            u128::from(token_amount(&token_b_account) - user_b_before),
+            expected.destination_amount_swapped
+        );
+        // the user of the event is the delegate approved for the swap
+        let events = take_events();
+        let user = match events[..] {
+            [AmmEvent::Swap(event)] => event.user,
+            _ => panic!("unexpected events {:?}", events),
+        };
+        assert_eq!(
+            events,
+            vec![AmmEvent::Swap(SwapEvent {
+                pool: accounts.pool_key,
+                user,
+                trade_direction: TradeDirection::AtoB,
+                amount_in: amount_in as u64,
+                amount_out: expected.destination_amount_swapped as u64,
+                owner_fee: expected.owner_fee as u64,
+                reserve_a: token_amount(&accounts.token_a_account),
+                reserve_b: token_amount(&accounts.token_b_account),
+            })]
+        );
+        assert_eq!(
+            u128::from(token_amount(&accounts.fee_a_account)),
+            expected.owner_fee
+        );
+        let pool = accounts.pool();
+        assert!(pool.tick_current < -100);
+        assert_eq!(pool.liquidity, 10_000_000);
+        // the price of 1 held until the swap is accumulated
+        assert_eq!(pool.price_oracle.last_update_ts, start_ts + 100);
+        assert_eq!(pool.price_oracle.price_a_cumulative, 100 << 64);
+        assert_eq!(pool.price_oracle.price_b_cumulative, 100 << 64);
+        assert_eq!(
+            pool.stats,
+            PoolStats {
+                volume_a_to_b: expected.source_amount_swapped,
+                fixed_fees_a: expected.owner_fee,
+                return_fees_a: expected.trade_fee,
+                swap_count: 1,
+                deposit_count: 2,
+                ..PoolStats::default()
+            }
         );
 
-        // deposit enough tokens, success!
-        let token_b_amount = 125;
-        let token_a_amount = token_b_amount * token_b_price;
-        let (
-            token_a_key,
-            mut token_a_account,
-            token_b_key,
-            mut token_b_account,
-            pool_key,
-            mut pool_account,
-        ) = accounts.setup_token_accounts(
-            &user_key,
-            &withdrawer_key,
-            token_a_amount,
-            token_b_amount,
-            0,
+        // only the owner collects the fees
+        let mut owner_account = Account::default();
+        let other_key = Pubkey::new_unique();
+        let (fees_a_key, mut fees_a_account, fees_b_key, mut fees_b_account) =
+            accounts.setup_token_accounts(&user_key, 0);
+        assert_eq!(
+            Err(AmmError::IncorrectPositionAccount.into()),
+            accounts.position_withdraw(
+                &other_key,
+                &mut owner_account,
+                &narrow_key,
+                &mut narrow_account,
+                &fees_a_key,
+                &mut fees_a_account,
+                &fees_b_key,
+                &mut fees_b_account,
+                None,
+            )
+        );
+        accounts
+            .position_withdraw(
+                &user_key,
+                &mut owner_account,
+                &narrow_key,
+                &mut narrow_account,
+                &fees_a_key,
+                &mut fees_a_account,
+                &fees_b_key,
+                &mut fees_b_account,
+                None,
+            )
+            .unwrap();
+        let narrow_fees = token_amount(&fees_a_account);
+        assert!(narrow_fees > 0);
+        let collect_event = PositionEvent {
+            pool: accounts.pool_key,
+            position: narrow_key,
+            owner: user_key,
+            tick_lower: -100,
+            tick_upper: 100,
+            liquidity: 0,
+            token_a_amount: narrow_fees,
+            token_b_amount: 0,
+        };
+        assert_eq!(take_events(), vec![AmmEvent::CollectFees(collect_event)]);
+        assert!(u128::from(narrow_fees) < expected.trade_fee);
+        assert_eq!(token_amount(&fees_b_account), 0);
+        let position = Position::unpack_account(&narrow_account.data).unwrap();
+        assert_eq!(position.fees_owed_a, 0);
+        assert_eq!(position.liquidity, 10_000_000);
+
+        // nothing more to collect
+        accounts
+            .position_withdraw(
+                &user_key,
+                &mut owner_account,
+                &narrow_key,
+                &mut narrow_account,
+                &fees_a_key,
+                &mut fees_a_account,
+                &fees_b_key,
+                &mut fees_b_account,
+                None,
+            )
+            .unwrap();
+        assert_eq!(token_amount(&fees_a_account), narrow_fees);
+
+        // the narrow position is out of range, all in token A
+        let (narrow_a, narrow_b) =
+            token_amounts(pool.sqrt_price, -100, 100, 10_000_000, false).unwrap();
+        assert_eq!(narrow_b, 0);
+        let close = ClosePositionInstruction {
+            minimum_token_a_amount: narrow_a as u64,
+            minimum_token_b_amount: 0,
+            deadline: None,
+        };
+        assert_eq!(
+            Err(AmmError::ExceededSlippage.into()),
+            accounts.position_withdraw(
+                &user_key,
+                &mut owner_account,
+                &narrow_key,
+                &mut narrow_account,
+                &fees_a_key,
+                &mut fees_a_account,
+                &fees_b_key,
+                &mut fees_b_account,
+                Some(ClosePositionInstruction {
+                    minimum_token_a_amount: narrow_a as u64 + 1,
+                    minimum_token_b_amount: 0,
+                    deadline: None,
+                }),
+            )
+        );
+        accounts
+            .position_withdraw(
+                &user_key,
+                &mut owner_account,
+                &narrow_key,
+                &mut narrow_account,
+                &fees_a_key,
+                &mut fees_a_account,
+                &fees_b_key,
+                &mut fees_b_account,
+                Some(close),
+            )
+            .unwrap();
+        assert_eq!(
+            u128::from(token_amount(&fees_a_account)),
+            narrow_a + u128::from(narrow_fees)
+        );
+        assert_eq!(
+            take_events().last(),
+            Some(&AmmEvent::ClosePosition(PositionEvent {
+                liquidity: 10_000_000,
+                token_a_amount: narrow_a as u64,
+                ..collect_event
+            }))
+        );
+        assert_eq!(owner_account.lamports, position_lamports);
+        assert_eq!(narrow_account.lamports, 0);
+        assert!(narrow_account.data.iter().all(|byte| *byte == 0));
+        let pool = accounts.pool();
+        assert_eq!(pool.liquidity, 10_000_000);
+        assert_eq!(pool.initialized_ticks().len(), 2);
+        assert_eq!(pool.stats.withdraw_count, 1);
+
+        // swapping back crosses into the wide position only
+        let amount_in = 100_000;
+        let expected = accounts.pool().swap(amount_in, false).unwrap();
+        let user_a_before = token_amount(&token_a_account);
+        accounts
+            .swap(
+                &user_key,
+                &token_b_key,
+                &mut token_b_account,
+                &token_a_key,
+                &mut token_a_account,
+                false,
+                amount_in as u64,
+                0,
+            )
+            .unwrap();
+        assert_eq!(
+            u128::from(token_amount(&token_a_account) - user_a_before),
+            expected.destination_amount_swapped
         );
 
+        // a halted pool does not swap
+        let admin_key = accounts.admin_key;
         accounts
-            .deposit_all_token_types(
-                &withdrawer_key,
+            .set_pool_status(&admin_key, PoolStatus::Halted)
+            .unwrap();
+        assert_eq!(accounts.pool().status, PoolStatus::Halted);
+        assert_eq!(
+            Err(AmmError::PoolPaused.into()),
+            accounts.swap(
+                &user_key,
                 &token_a_key,
                 &mut token_a_account,
                 &token_b_key,
                 &mut token_b_account,
-                &pool_key,
-                &mut pool_account,
-                1, // doesn't matter
-                token_a_amount,
-                token_b_amount,
+                true,
+                1_000,
+                0,
             )
-            .unwrap();
+        );
     }
 }